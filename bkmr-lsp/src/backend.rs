@@ -1,29 +1,205 @@
 // File: bkmr-lsp/src/backend.rs - Word-based completion with manual triggering
 
 use anyhow::{Result, anyhow};
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use std::collections::HashMap;
 use std::path::Path;
-use tower_lsp::{Client, LanguageServer, jsonrpc::Result as LspResult, lsp_types::*};
+use tower_lsp::{
+    Client, LanguageServer,
+    jsonrpc::{self, Result as LspResult},
+    lsp_types::*,
+};
 use tracing::{debug, error, info, instrument, warn};
 
-use crate::domain::CompletionContext;
-use crate::repositories::{BkmrRepository, RepositoryConfig};
-use crate::services::CompletionService;
+use crate::domain::{
+    AliasPattern, CaseMode, CompletionContext, ContentLimit, DEFAULT_ESCAPE_ALL_DOLLARS_FOR,
+    DEFAULT_IMPORT_MARKER, DEFAULT_MAX_SNIPPET_BYTES, DEFAULT_TRANSLATE_TAG,
+    DEFAULT_URL_COMPLETION_LANGUAGES, FolderRepository, InlineCompletionList,
+    InlineCompletionParams, ItemKind, LanguageDefinitions, LanguageMap, LanguageOverrides,
+    LanguageRegistry, LargeSnippetMode, PositionEncoding, RankingStrategy, ReplaceMode,
+    SnippetSources, TagCount, TagFilters, TierOrder, TranslationPolicy, default_snippet_tags,
+    parse_language_overrides,
+};
+use crate::metrics::{DEFAULT_METRICS_SUMMARY_EVERY, Metrics, Stage};
+use crate::repositories::{
+    BKMR_DB_URL, BkmrBackend, BkmrDaemonRepository, BkmrRepository, CacheConfig, CacheStats,
+    CachingSnippetRepository, CompositeRepository, DEFAULT_ID_CACHE_CAPACITY, FileSystemRepository,
+    QueryStrategy, RepositoryConfig, RepositoryRouter, SnippetRepository,
+};
+use crate::services::{
+    BkmrCommand, ClientCompletionSupport, CommandService, CompletionService,
+    DEFAULT_DESCRIPTION_MAX_LENGTH, DEFAULT_DOC_CACHE_BYTES, DEFAULT_MIN_QUERY_INTERVAL_MS,
+    DEFAULT_WORD_CHARS, DocumentService, FilepathComment, HealthSummary, HoverService,
+    IGNORE_FILE_NAME, IgnoreService, InvalidArgument, LinkService, PrefetchService,
+    ProgressService, SymbolService, hoist_item_defaults,
+};
+use crate::sync;
+use crate::trace::{CompletionTrace, TraceReporter};
+
+/// Custom request returning server diagnostics, see [`BkmrLspBackend::status`]
+pub const STATUS_METHOD: &str = "bkmr/status";
+/// Custom request listing bkmr tags with counts, see [`BkmrLspBackend::list_tags`]
+pub const LIST_TAGS_METHOD: &str = "bkmr/listTags";
+/// LSP 3.18 request for ghost text, see [`BkmrLspBackend::inline_completion`]
+pub const INLINE_COMPLETION_METHOD: &str = "textDocument/inlineCompletion";
+/// Server capabilities unknown to lsp-types 0.94, sent under experimental and moved up
+const PROMOTED_CAPABILITIES: [&str; 1] = ["inlineCompletionProvider"];
+
+/// JSON-RPC error code LSP reserves for requests that were valid but failed
+const REQUEST_FAILED: i64 = -32803;
+
+/// How failing completions and commands are reported to the client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorMode {
+    /// Empty result plus an error message in the client's log
+    #[default]
+    Silent,
+    /// Empty result plus a warning, which clients do not pop up
+    Log,
+    /// JSON-RPC RequestFailed error carrying bkmr's message
+    Respond,
+}
 
-/// Language-specific information for Rust pattern translation
-#[derive(Debug, Clone)]
-pub struct LanguageInfo {
-    pub line_comment: Option<String>,
-    pub block_comment: Option<(String, String)>,
-    pub indent_char: String,
+impl std::str::FromStr for ErrorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "silent" => Ok(Self::Silent),
+            "log" => Ok(Self::Log),
+            "respond" => Ok(Self::Respond),
+            other => Err(format!(
+                "unknown error mode '{}', expected silent, log or respond",
+                other
+            )),
+        }
+    }
 }
 
 /// Configuration for the bkmr-lsp server
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct BkmrConfig {
     pub bkmr_binary: String,
     pub max_completions: usize,
     pub enable_interpolation: bool,
+    /// Seconds a bkmr invocation may take before it is abandoned
+    pub bkmr_timeout_seconds: u64,
+    /// Lifetime of cached bkmr search results in seconds, 0 disables caching
+    pub cache_ttl_seconds: u64,
+    /// Maximum number of distinct queries kept in the result cache
+    pub cache_max_entries: usize,
+    /// Snippets kept by id after a search for commands like insertSnippetById, 0 disables
+    pub id_cache_size: usize,
+    /// Milliseconds between two repository searches for one document, 0 searches for every request
+    pub min_query_interval_ms: u64,
+    /// Open snippets with `bkmr edit` instead of `bkmr open`
+    pub open_with_edit: bool,
+    /// bkmr database to use instead of bkmr's default
+    pub db_path: Option<String>,
+    /// Ordering of completion results
+    pub ranking: RankingStrategy,
+    /// Character that triggers completion automatically, manual completion only when unset
+    pub trigger_char: Option<char>,
+    /// Warm the result cache in the background when a document is opened
+    pub prefetch: bool,
+    /// Convert bkmr placeholders like `{{ cursor }}` to snippet tabstops
+    pub convert_placeholders: bool,
+    /// Maximum number of completion items returned per response
+    pub page_size: usize,
+    /// Extra language ID to snippet tag mappings on top of the built-in aliases
+    pub language_map: LanguageMap,
+    /// Limit and ranking of completions per lowercase language ID
+    pub per_language: HashMap<String, LanguageOverrides>,
+    /// Tags marking bkmr bookmarks as snippets, any of them matches, none means all bookmarks
+    pub snippet_tags: Vec<String>,
+    /// Report inserted snippets back to bkmr to raise their access count
+    pub track_usage: bool,
+    /// bkmr subcommand recording usage, e.g. "touch" or "update --access"
+    pub usage_command: String,
+    /// Languages whose snippets keep `$1` and `$(...)` as literal text
+    pub escape_all_dollars_for: Vec<String>,
+    /// Characters of a snippet title shown as completion label before it is cut off, 0 for all
+    pub label_max_length: usize,
+    /// Characters of a snippet description shown next to the completion label, 0 for none
+    pub description_max_length: usize,
+    /// Let clients match typed characters against snippet descriptions as well as titles
+    pub filter_on_description: bool,
+    /// Search language-specific and universal snippets with one bkmr query or two
+    pub query_strategy: QueryStrategy,
+    /// Tags whose snippets never show up in completions
+    pub excluded_tags: Vec<String>,
+    /// Directories of `<language_id>/*.snippet` files offered alongside bkmr snippets
+    pub snippet_dirs: Vec<String>,
+    /// bkmr database and binary of documents in these folders, the global ones elsewhere
+    pub folder_repositories: Vec<FolderRepository>,
+    /// Characters besides alphanumerics, '_' and '-' that belong to a completion query
+    pub word_chars: Vec<char>,
+    /// Drop snippets with broken snippet syntax instead of inserting them as plain text
+    pub strict_snippets: bool,
+    /// Render relative paths in filepath comments with the platform's separator instead of '/'
+    pub native_path_separators: bool,
+    /// Insert filepath comments into unsaved buffers, naming them like `untitled-1.py`
+    pub untitled_filepath_comment: bool,
+    /// Retries of a bkmr search failing because the database is locked or busy
+    pub bkmr_retries: u32,
+    /// Answer textDocument/inlineCompletion with the best snippet as ghost text
+    pub inline_completions: bool,
+    /// Name substituted for `{{ author }}`, git's user.name when unset
+    pub author: Option<String>,
+    /// Language ids of documents that never get snippet completions
+    pub disable_for: Vec<String>,
+    /// How failing completions and commands are reported
+    pub errors: ErrorMode,
+    /// Tags of bookmarks bkmr runs rather than inserts, besides `_shell_`
+    pub action_tags: Vec<String>,
+    /// Offer action snippets as plain text events instead of leaving them out
+    pub include_action_snippets: bool,
+    /// Run bkmr per search or keep a bkmr daemon running
+    pub backend: BkmrBackend,
+    /// Also search descriptions for the typed prefix, finding snippets by alias
+    pub alias_search: bool,
+    /// Regex finding aliases in descriptions instead of an "alias:" line
+    pub alias_pattern: Option<AliasPattern>,
+    /// Largest snippet content in bytes offered in completions, 0 for no limit
+    pub max_snippet_bytes: usize,
+    /// Whether larger snippets are left out or offered as a stub
+    pub large_snippet_mode: LargeSnippetMode,
+    /// Escape snippets completed in strings and continue comments they are completed in
+    pub context_aware_insertion: bool,
+    /// Total bytes of open document texts kept in memory, 0 for no limit
+    pub doc_cache_bytes: usize,
+    /// Language ids of documents where bookmark URLs are completed besides snippets
+    pub url_completion_languages: Vec<String>,
+    /// File of language definitions merged over the built-in ones, the config directory's by default
+    pub languages_file: Option<String>,
+    /// Completions between two latency summaries in the log, 0 for none
+    pub metrics_summary_every: u64,
+    /// Start of snippet header lines declaring imports, "" to insert them verbatim
+    pub import_marker: String,
+    /// Offer the most used snippets of the language when nothing matches the query
+    pub fallback_completions: bool,
+    /// Show environment variables with their current values in documentation
+    pub resolve_env_in_docs: bool,
+    /// Tag a universal snippet needs to be translated from Rust style
+    pub translate_tag: String,
+    /// Translate every universal snippet, regardless of translate_tag and target
+    pub legacy_universal_translation: bool,
+    /// Offer universal snippets besides the language's own, false for language-tagged snippets only
+    pub universal_snippets: bool,
+    /// Completion item kind of snippets inserted as snippets, None keeps "snippet"
+    pub item_kind: Option<ItemKind>,
+    /// Completion item detail with {tags}, {id}, {access_count} and {source}, None for "bkmr snippet"
+    pub detail_template: Option<String>,
+    /// Text put in front of every snippet and bookmark label
+    pub label_prefix: String,
+    /// Order of completion tiers: exact title matches, language, universal and fallback snippets
+    pub tier_order: TierOrder,
+    /// Range of plain text edits when the word continues after the cursor
+    pub replace_mode: ReplaceMode,
+    /// Whether the case of typed query words matters when searching
+    pub case_mode: CaseMode,
 }
 
 impl Default for BkmrConfig {
@@ -32,406 +208,1566 @@ impl Default for BkmrConfig {
             bkmr_binary: "bkmr".to_string(),
             max_completions: 50,
             enable_interpolation: true,
+            bkmr_timeout_seconds: 10,
+            cache_ttl_seconds: 30,
+            cache_max_entries: 100,
+            id_cache_size: DEFAULT_ID_CACHE_CAPACITY,
+            min_query_interval_ms: DEFAULT_MIN_QUERY_INTERVAL_MS,
+            open_with_edit: false,
+            db_path: None,
+            ranking: RankingStrategy::default(),
+            trigger_char: None,
+            prefetch: true,
+            convert_placeholders: true,
+            page_size: 50,
+            language_map: LanguageMap::default(),
+            per_language: HashMap::new(),
+            snippet_tags: default_snippet_tags(),
+            track_usage: false,
+            usage_command: "touch".to_string(),
+            escape_all_dollars_for: DEFAULT_ESCAPE_ALL_DOLLARS_FOR
+                .iter()
+                .map(|language| language.to_string())
+                .collect(),
+            label_max_length: 60,
+            description_max_length: DEFAULT_DESCRIPTION_MAX_LENGTH,
+            filter_on_description: false,
+            query_strategy: QueryStrategy::default(),
+            excluded_tags: Vec::new(),
+            snippet_dirs: Vec::new(),
+            folder_repositories: Vec::new(),
+            word_chars: DEFAULT_WORD_CHARS.to_vec(),
+            strict_snippets: false,
+            native_path_separators: false,
+            untitled_filepath_comment: true,
+            bkmr_retries: 2,
+            inline_completions: false,
+            author: None,
+            disable_for: Vec::new(),
+            errors: ErrorMode::default(),
+            action_tags: Vec::new(),
+            include_action_snippets: false,
+            backend: BkmrBackend::default(),
+            alias_search: false,
+            alias_pattern: None,
+            max_snippet_bytes: DEFAULT_MAX_SNIPPET_BYTES,
+            large_snippet_mode: LargeSnippetMode::default(),
+            context_aware_insertion: false,
+            doc_cache_bytes: DEFAULT_DOC_CACHE_BYTES,
+            url_completion_languages: DEFAULT_URL_COMPLETION_LANGUAGES
+                .iter()
+                .map(|language| language.to_string())
+                .collect(),
+            languages_file: None,
+            metrics_summary_every: DEFAULT_METRICS_SUMMARY_EVERY,
+            import_marker: DEFAULT_IMPORT_MARKER.to_string(),
+            fallback_completions: true,
+            resolve_env_in_docs: false,
+            translate_tag: DEFAULT_TRANSLATE_TAG.to_string(),
+            legacy_universal_translation: false,
+            universal_snippets: true,
+            item_kind: None,
+            detail_template: None,
+            label_prefix: String::new(),
+            tier_order: TierOrder::default(),
+            replace_mode: ReplaceMode::default(),
+            case_mode: CaseMode::default(),
         }
     }
 }
 
-/// Represents a bkmr snippet from JSON output
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
-pub struct BkmrSnippet {
-    pub id: i32,
-    pub title: String,
-    pub url: String, // This contains the actual snippet content
-    pub description: String,
-    pub tags: Vec<String>,
-    #[serde(default)]
-    pub access_count: i32,
+impl BkmrConfig {
+    /// Which universal snippets are translated to the target language
+    pub fn translation_policy(&self) -> TranslationPolicy {
+        TranslationPolicy {
+            translate_tag: self.translate_tag.clone(),
+            legacy: self.legacy_universal_translation,
+            universal: self.universal_snippets,
+        }
+    }
+
+    /// Build the repository configuration for the bkmr CLI
+    pub fn repository_config(&self) -> RepositoryConfig {
+        RepositoryConfig {
+            binary_path: self.bkmr_binary.clone(),
+            max_results: self.max_completions,
+            timeout_seconds: self.bkmr_timeout_seconds,
+            enable_interpolation: self.enable_interpolation,
+            open_with_edit: self.open_with_edit,
+            db_path: self.db_path.clone(),
+            snippet_tags: self.snippet_tags.clone(),
+            usage_command: self
+                .usage_command
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            query_strategy: self.query_strategy,
+            retries: self.bkmr_retries,
+            alias_search: self.alias_search,
+            content_limit: ContentLimit {
+                max_bytes: self.max_snippet_bytes,
+                mode: self.large_snippet_mode,
+            },
+            id_cache_capacity: self.id_cache_size,
+            id_cache_ttl: std::time::Duration::from_secs(self.cache_ttl_seconds),
+            ..Default::default()
+        }
+    }
+
+    /// Languages file to read, the one in the config directory when unset
+    pub fn languages_path(&self) -> Option<&Path> {
+        self.languages_file.as_deref().map(Path::new)
+    }
+
+    /// Languages of the configured language map and the definitions read from the languages file
+    pub fn language_registry(&self, definitions: LanguageDefinitions) -> LanguageRegistry {
+        LanguageRegistry::new(self.language_map.clone(), definitions)
+    }
+
+    /// Repository running bkmr the configured way, sending user notices to the channel if given
+    ///
+    /// Spawned bkmr processes are counted in the metrics if given.
+    pub fn bkmr_repository(
+        &self,
+        language_registry: std::sync::Arc<LanguageRegistry>,
+        notices: Option<tokio::sync::mpsc::UnboundedSender<String>>,
+        metrics: Option<std::sync::Arc<Metrics>>,
+    ) -> std::sync::Arc<dyn SnippetRepository> {
+        let config = RepositoryConfig {
+            language_registry,
+            ..self.repository_config()
+        };
+        match self.backend {
+            BkmrBackend::Cli => {
+                let mut repository = BkmrRepository::new(config);
+                if let Some(notices) = notices {
+                    repository = repository.with_notices(notices);
+                }
+                if let Some(metrics) = metrics {
+                    repository = repository.with_metrics(metrics);
+                }
+                std::sync::Arc::new(repository)
+            }
+            BkmrBackend::Daemon => {
+                let mut repository = BkmrDaemonRepository::new(config);
+                if let Some(notices) = notices {
+                    repository = repository.with_notices(notices);
+                }
+                if let Some(metrics) = metrics {
+                    repository = repository.with_metrics(metrics);
+                }
+                std::sync::Arc::new(repository)
+            }
+        }
+    }
+
+    /// Add the settings files of workspace folders, replacing those read before
+    ///
+    /// Folders configured in initializationOptions keep their settings.
+    pub fn with_folder_files(mut self, files: Vec<FolderRepository>) -> Self {
+        self.folder_repositories
+            .retain(|repository| !repository.from_file);
+        for file in files {
+            if !self
+                .folder_repositories
+                .iter()
+                .any(|repository| repository.path() == file.path())
+            {
+                self.folder_repositories.push(file);
+            }
+        }
+        self
+    }
+
+    /// Configuration of the repository serving a folder, its settings over the global ones
+    pub fn for_folder(&self, folder: &FolderRepository) -> Self {
+        let mut config = self.clone();
+        if let Some(db_path) = &folder.db_path {
+            config.db_path = Some(db_path.clone());
+        }
+        if let Some(bkmr_binary) = &folder.bkmr_binary {
+            config.bkmr_binary = bkmr_binary.clone();
+        }
+        config
+    }
+
+    /// Merge snippet files into a repository's results if snippet directories are configured
+    pub fn with_snippet_dirs(
+        &self,
+        repository: std::sync::Arc<dyn SnippetRepository>,
+        language_registry: &std::sync::Arc<LanguageRegistry>,
+    ) -> std::sync::Arc<dyn SnippetRepository> {
+        if self.snippet_dirs.is_empty() {
+            return repository;
+        }
+        let files = FileSystemRepository::new(
+            self.snippet_dirs
+                .iter()
+                .map(std::path::PathBuf::from)
+                .collect(),
+        )
+        .with_language_registry(language_registry.clone());
+        std::sync::Arc::new(CompositeRepository::new(
+            repository,
+            std::sync::Arc::new(files),
+        ))
+    }
+
+    /// bkmr database and snippet directories to watch, the database from --db-path or BKMR_DB_URL
+    pub fn snippet_sources(&self) -> SnippetSources {
+        let database = self
+            .db_path
+            .clone()
+            .or_else(|| std::env::var(BKMR_DB_URL).ok());
+        SnippetSources::new(database.as_deref(), &self.snippet_dirs)
+    }
+
+    /// Fall back to git's user.name for `{{ author }}` when no author is configured
+    ///
+    /// Run once at startup, rebuilt services keep the resolved author.
+    pub async fn with_resolved_author(mut self) -> Self {
+        if self.author.is_none() {
+            self.author = git_author().await;
+        }
+        self
+    }
+
+    /// Check that an absolute bkmr binary path points to an executable file
+    ///
+    /// Relative names like "bkmr" are resolved via PATH when spawning and are
+    /// not checked here.
+    pub fn validate_binary(&self) -> Result<()> {
+        let path = Path::new(&self.bkmr_binary);
+        if !path.is_absolute() {
+            return Ok(());
+        }
+
+        let metadata = std::fs::metadata(path)
+            .map_err(|e| anyhow!("bkmr binary '{}' not found: {}", self.bkmr_binary, e))?;
+        if !metadata.is_file() {
+            return Err(anyhow!("bkmr binary '{}' is not a file", self.bkmr_binary));
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if metadata.permissions().mode() & 0o111 == 0 {
+                return Err(anyhow!(
+                    "bkmr binary '{}' is not executable",
+                    self.bkmr_binary
+                ));
+            }
+        }
+
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
-pub struct BkmrLspBackend {
-    client: Client,
-    config: BkmrConfig,
-    completion_service: CompletionService,
-    /// Cache of document contents to extract prefixes
-    document_cache: std::sync::Arc<std::sync::RwLock<std::collections::HashMap<String, String>>>,
-    /// Cache of document language IDs for filetype-based filtering
-    language_cache: std::sync::Arc<std::sync::RwLock<std::collections::HashMap<String, String>>>,
+/// Settings a client can pass via initialize's initializationOptions
+///
+/// Each key is parsed on its own: a value of the wrong type is ignored with a
+/// warning and leaves that setting at its command line value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InitializationOptions {
+    pub bkmr_binary: Option<String>,
+    pub db_path: Option<String>,
+    pub max_completions: Option<usize>,
+    pub enable_interpolation: Option<bool>,
+    pub bkmr_timeout_seconds: Option<u64>,
+    pub cache_ttl_seconds: Option<u64>,
+    pub cache_max_entries: Option<usize>,
+    pub id_cache_size: Option<usize>,
+    pub min_query_interval_ms: Option<u64>,
+    pub open_with_edit: Option<bool>,
+    pub ranking: Option<RankingStrategy>,
+    pub trigger_char: Option<char>,
+    pub prefetch: Option<bool>,
+    pub convert_placeholders: Option<bool>,
+    pub page_size: Option<usize>,
+    pub language_map: Option<LanguageMap>,
+    pub per_language: Option<HashMap<String, LanguageOverrides>>,
+    pub track_usage: Option<bool>,
+    pub usage_command: Option<String>,
+    pub escape_all_dollars_for: Option<Vec<String>>,
+    pub label_max_length: Option<usize>,
+    pub description_max_length: Option<usize>,
+    pub filter_on_description: Option<bool>,
+    pub query_strategy: Option<QueryStrategy>,
+    pub excluded_tags: Option<Vec<String>>,
+    pub snippet_dirs: Option<Vec<String>>,
+    pub folder_repositories: Option<Vec<FolderRepository>>,
+    pub word_chars: Option<Vec<char>>,
+    pub strict_snippets: Option<bool>,
+    pub native_path_separators: Option<bool>,
+    pub untitled_filepath_comment: Option<bool>,
+    pub bkmr_retries: Option<u32>,
+    pub inline_completions: Option<bool>,
+    pub author: Option<String>,
+    pub disable_for: Option<Vec<String>>,
+    pub errors: Option<ErrorMode>,
+    pub action_tags: Option<Vec<String>>,
+    pub include_action_snippets: Option<bool>,
+    pub backend: Option<BkmrBackend>,
+    pub alias_search: Option<bool>,
+    pub alias_pattern: Option<AliasPattern>,
+    pub max_snippet_bytes: Option<usize>,
+    pub large_snippet_mode: Option<LargeSnippetMode>,
+    pub context_aware_insertion: Option<bool>,
+    pub doc_cache_bytes: Option<usize>,
+    pub url_completion_languages: Option<Vec<String>>,
+    pub languages_file: Option<String>,
+    pub metrics_summary_every: Option<u64>,
+    pub import_marker: Option<String>,
+    pub fallback_completions: Option<bool>,
+    pub resolve_env_in_docs: Option<bool>,
+    pub translate_tag: Option<String>,
+    pub legacy_universal_translation: Option<bool>,
+    pub universal_snippets: Option<bool>,
+    pub item_kind: Option<ItemKind>,
+    pub detail_template: Option<String>,
+    pub label_prefix: Option<String>,
+    pub tier_order: Option<TierOrder>,
+    pub replace_mode: Option<ReplaceMode>,
+    pub case_mode: Option<CaseMode>,
+    /// Keys that are not recognized and were ignored
+    pub unknown_keys: Vec<String>,
 }
 
-impl BkmrLspBackend {
-    pub fn new(client: Client) -> Self {
-        Self::with_config(client, BkmrConfig::default())
+impl InitializationOptions {
+    /// Parse the initializationOptions JSON sent by the client
+    pub fn from_value(value: &serde_json::Value) -> Self {
+        let mut options = Self::default();
+        let Some(object) = value.as_object() else {
+            if !value.is_null() {
+                warn!(
+                    "Ignoring initializationOptions, expected an object: {}",
+                    value
+                );
+            }
+            return options;
+        };
+
+        for (key, value) in object {
+            match key.as_str() {
+                "bkmrBinary" => options.bkmr_binary = Self::field(key, value),
+                "dbPath" => options.db_path = Self::field(key, value),
+                "maxCompletions" => options.max_completions = Self::field(key, value),
+                "enableInterpolation" => options.enable_interpolation = Self::field(key, value),
+                "bkmrTimeoutSeconds" => options.bkmr_timeout_seconds = Self::field(key, value),
+                "bkmrRetries" => options.bkmr_retries = Self::field(key, value),
+                "inlineCompletions" => options.inline_completions = Self::field(key, value),
+                "cacheTtlSeconds" => options.cache_ttl_seconds = Self::field(key, value),
+                "cacheMaxEntries" => options.cache_max_entries = Self::field(key, value),
+                "idCacheSize" => options.id_cache_size = Self::field(key, value),
+                "minQueryIntervalMs" => options.min_query_interval_ms = Self::field(key, value),
+                "openWithEdit" => options.open_with_edit = Self::field(key, value),
+                "triggerChar" => options.trigger_char = Self::field(key, value),
+                "prefetch" => options.prefetch = Self::field(key, value),
+                "convertPlaceholders" => options.convert_placeholders = Self::field(key, value),
+                "pageSize" => options.page_size = Self::field(key, value),
+                "trackUsage" => options.track_usage = Self::field(key, value),
+                "usageCommand" => options.usage_command = Self::field(key, value),
+                "escapeAllDollarsFor" => options.escape_all_dollars_for = Self::field(key, value),
+                "labelMaxLength" => options.label_max_length = Self::field(key, value),
+                "descriptionMaxLength" => options.description_max_length = Self::field(key, value),
+                "filterOnDescription" => options.filter_on_description = Self::field(key, value),
+                "excludeTags" => options.excluded_tags = Self::field(key, value),
+                "snippetDirs" => options.snippet_dirs = Self::field(key, value),
+                "folderRepositories" => options.folder_repositories = Self::field(key, value),
+                "wordChars" => options.word_chars = Self::field(key, value),
+                "strictSnippets" => options.strict_snippets = Self::field(key, value),
+                "nativePathSeparators" => options.native_path_separators = Self::field(key, value),
+                "untitledFilepathComment" => {
+                    options.untitled_filepath_comment = Self::field(key, value)
+                }
+                "author" => options.author = Self::field(key, value),
+                "disableFor" => options.disable_for = Self::field(key, value),
+                "actionTags" => options.action_tags = Self::field(key, value),
+                "includeActionSnippets" => {
+                    options.include_action_snippets = Self::field(key, value)
+                }
+                "ranking" => {
+                    options.ranking = Self::field::<String>(key, value)
+                        .and_then(|ranking| Self::parsed(key, &ranking))
+                }
+                "queryStrategy" => {
+                    options.query_strategy = Self::field::<String>(key, value)
+                        .and_then(|strategy| Self::parsed(key, &strategy))
+                }
+                "aliasSearch" => options.alias_search = Self::field(key, value),
+                "aliasPattern" => {
+                    options.alias_pattern = Self::field::<String>(key, value)
+                        .and_then(|pattern| Self::parsed(key, &pattern))
+                }
+                "maxSnippetBytes" => options.max_snippet_bytes = Self::field(key, value),
+                "largeSnippetMode" => {
+                    options.large_snippet_mode =
+                        Self::field::<String>(key, value).and_then(|mode| Self::parsed(key, &mode))
+                }
+                "docCacheBytes" => options.doc_cache_bytes = Self::field(key, value),
+                "languagesFile" => options.languages_file = Self::field(key, value),
+                "metricsSummaryEvery" => options.metrics_summary_every = Self::field(key, value),
+                "importMarker" => options.import_marker = Self::field(key, value),
+                "fallbackCompletions" => options.fallback_completions = Self::field(key, value),
+                "resolveEnvInDocs" => options.resolve_env_in_docs = Self::field(key, value),
+                "translateTag" => options.translate_tag = Self::field(key, value),
+                "legacyUniversalTranslation" => {
+                    options.legacy_universal_translation = Self::field(key, value)
+                }
+                "universalSnippets" => options.universal_snippets = Self::field(key, value),
+                "itemKind" => {
+                    options.item_kind =
+                        Self::field::<String>(key, value).and_then(|kind| Self::parsed(key, &kind))
+                }
+                "detailTemplate" => options.detail_template = Self::field(key, value),
+                "labelPrefix" => options.label_prefix = Self::field(key, value),
+                "tierOrder" => {
+                    options.tier_order = Self::field::<String>(key, value)
+                        .and_then(|order| Self::parsed(key, &order))
+                }
+                "replaceMode" => {
+                    options.replace_mode =
+                        Self::field::<String>(key, value).and_then(|mode| Self::parsed(key, &mode))
+                }
+                "caseMode" => {
+                    options.case_mode =
+                        Self::field::<String>(key, value).and_then(|mode| Self::parsed(key, &mode))
+                }
+                "urlCompletionLanguages" => {
+                    options.url_completion_languages = Self::field(key, value)
+                }
+                "contextAwareInsertion" => {
+                    options.context_aware_insertion = Self::field(key, value)
+                }
+                "backend" => {
+                    options.backend = Self::field::<String>(key, value)
+                        .and_then(|backend| Self::parsed(key, &backend))
+                }
+                "errors" => {
+                    options.errors =
+                        Self::field::<String>(key, value).and_then(|mode| Self::parsed(key, &mode))
+                }
+                "languageMap" => options.language_map = Self::language_map_field(key, value),
+                "perLanguage" => options.per_language = Self::per_language_field(key, value),
+                _ => {
+                    warn!("Ignoring unknown initialization option '{}'", key);
+                    options.unknown_keys.push(key.clone());
+                }
+            }
+        }
+        options
     }
 
-    pub fn with_config(client: Client, config: BkmrConfig) -> Self {
-        debug!("Creating BkmrLspBackend with config: {:?}", config);
-        
-        // Create repository with configuration from BkmrConfig
-        let repo_config = RepositoryConfig {
-            binary_path: config.bkmr_binary.clone(),
-            max_results: config.max_completions,
-            timeout_seconds: 10,
-            enable_interpolation: config.enable_interpolation,
+    /// Deserialize one option, None with a warning when the type does not match
+    fn field<T: serde::de::DeserializeOwned>(key: &str, value: &serde_json::Value) -> Option<T> {
+        if value.is_null() {
+            return None;
+        }
+        serde_json::from_value(value.clone())
+            .map_err(|e| {
+                warn!(
+                    "Ignoring initialization option '{}' = {}: {}",
+                    key, value, e
+                )
+            })
+            .ok()
+    }
+
+    /// Parse a string option, None with a warning when it is invalid
+    fn parsed<T: std::str::FromStr<Err = String>>(key: &str, value: &str) -> Option<T> {
+        value
+            .parse()
+            .map_err(|e| warn!("Ignoring initialization option '{}': {}", key, e))
+            .ok()
+    }
+
+    /// Accept a language map as "from=to,..." or as an object of tags
+    fn language_map_field(key: &str, value: &serde_json::Value) -> Option<LanguageMap> {
+        let entries = match value {
+            serde_json::Value::String(map) => map.clone(),
+            serde_json::Value::Object(object) => {
+                let mut entries = Vec::new();
+                for (from, to) in object {
+                    match to {
+                        serde_json::Value::String(tag) => entries.push(format!("{}={}", from, tag)),
+                        serde_json::Value::Array(tags) => {
+                            for tag in tags {
+                                let Some(tag) = tag.as_str() else {
+                                    warn!(
+                                        "Ignoring initialization option '{}': tags must be strings",
+                                        key
+                                    );
+                                    return None;
+                                };
+                                entries.push(format!("{}={}", from, tag));
+                            }
+                        }
+                        other => {
+                            warn!(
+                                "Ignoring initialization option '{}' = {}: expected tag strings",
+                                key, other
+                            );
+                            return None;
+                        }
+                    }
+                }
+                entries.join(",")
+            }
+            other => {
+                warn!(
+                    "Ignoring initialization option '{}' = {}: expected a string or object",
+                    key, other
+                );
+                return None;
+            }
+        };
+        Self::parsed(key, &entries)
+    }
+
+    /// Accept language overrides as "language:option=value,..." or as an object of option objects
+    fn per_language_field(
+        key: &str,
+        value: &serde_json::Value,
+    ) -> Option<HashMap<String, LanguageOverrides>> {
+        let entries = match value {
+            serde_json::Value::String(overrides) => overrides.clone(),
+            serde_json::Value::Object(object) => {
+                let mut entries = Vec::new();
+                for (language, options) in object {
+                    let Some(options) = options.as_object() else {
+                        warn!(
+                            "Ignoring initialization option '{}' = {}: expected an object per language",
+                            key, value
+                        );
+                        return None;
+                    };
+                    for (name, option) in options {
+                        let option = match option {
+                            serde_json::Value::String(option) => option.clone(),
+                            other => other.to_string(),
+                        };
+                        entries.push(format!("{}:{}={}", language, name, option));
+                    }
+                }
+                entries.join(",")
+            }
+            other => {
+                warn!(
+                    "Ignoring initialization option '{}' = {}: expected a string or object",
+                    key, other
+                );
+                return None;
+            }
+        };
+        parse_language_overrides(&entries)
+            .map_err(|e| warn!("Ignoring initialization option '{}': {}", key, e))
+            .ok()
+    }
+
+    /// Override the given configuration, logging where each setting came from
+    pub fn merge_into(self, mut config: BkmrConfig) -> BkmrConfig {
+        Self::merge("bkmrBinary", self.bkmr_binary, &mut config.bkmr_binary);
+        Self::merge("dbPath", self.db_path.map(Some), &mut config.db_path);
+        Self::merge(
+            "maxCompletions",
+            self.max_completions,
+            &mut config.max_completions,
+        );
+        Self::merge(
+            "enableInterpolation",
+            self.enable_interpolation,
+            &mut config.enable_interpolation,
+        );
+        Self::merge(
+            "bkmrTimeoutSeconds",
+            self.bkmr_timeout_seconds,
+            &mut config.bkmr_timeout_seconds,
+        );
+        Self::merge("bkmrRetries", self.bkmr_retries, &mut config.bkmr_retries);
+        Self::merge(
+            "cacheTtlSeconds",
+            self.cache_ttl_seconds,
+            &mut config.cache_ttl_seconds,
+        );
+        Self::merge(
+            "cacheMaxEntries",
+            self.cache_max_entries,
+            &mut config.cache_max_entries,
+        );
+        Self::merge("idCacheSize", self.id_cache_size, &mut config.id_cache_size);
+        Self::merge(
+            "minQueryIntervalMs",
+            self.min_query_interval_ms,
+            &mut config.min_query_interval_ms,
+        );
+        Self::merge(
+            "openWithEdit",
+            self.open_with_edit,
+            &mut config.open_with_edit,
+        );
+        Self::merge("ranking", self.ranking, &mut config.ranking);
+        Self::merge(
+            "triggerChar",
+            self.trigger_char.map(Some),
+            &mut config.trigger_char,
+        );
+        Self::merge("prefetch", self.prefetch, &mut config.prefetch);
+        Self::merge(
+            "convertPlaceholders",
+            self.convert_placeholders,
+            &mut config.convert_placeholders,
+        );
+        Self::merge("pageSize", self.page_size, &mut config.page_size);
+        Self::merge("trackUsage", self.track_usage, &mut config.track_usage);
+        Self::merge(
+            "usageCommand",
+            self.usage_command,
+            &mut config.usage_command,
+        );
+        Self::merge(
+            "escapeAllDollarsFor",
+            self.escape_all_dollars_for,
+            &mut config.escape_all_dollars_for,
+        );
+        Self::merge(
+            "labelMaxLength",
+            self.label_max_length,
+            &mut config.label_max_length,
+        );
+        Self::merge(
+            "descriptionMaxLength",
+            self.description_max_length,
+            &mut config.description_max_length,
+        );
+        Self::merge(
+            "filterOnDescription",
+            self.filter_on_description,
+            &mut config.filter_on_description,
+        );
+        Self::merge(
+            "queryStrategy",
+            self.query_strategy,
+            &mut config.query_strategy,
+        );
+        Self::merge("excludeTags", self.excluded_tags, &mut config.excluded_tags);
+        Self::merge("snippetDirs", self.snippet_dirs, &mut config.snippet_dirs);
+        Self::merge(
+            "folderRepositories",
+            self.folder_repositories,
+            &mut config.folder_repositories,
+        );
+        Self::merge("wordChars", self.word_chars, &mut config.word_chars);
+        Self::merge(
+            "strictSnippets",
+            self.strict_snippets,
+            &mut config.strict_snippets,
+        );
+        Self::merge(
+            "nativePathSeparators",
+            self.native_path_separators,
+            &mut config.native_path_separators,
+        );
+        Self::merge(
+            "untitledFilepathComment",
+            self.untitled_filepath_comment,
+            &mut config.untitled_filepath_comment,
+        );
+        Self::merge(
+            "inlineCompletions",
+            self.inline_completions,
+            &mut config.inline_completions,
+        );
+        Self::merge("author", self.author.map(Some), &mut config.author);
+        Self::merge("disableFor", self.disable_for, &mut config.disable_for);
+        Self::merge("errors", self.errors, &mut config.errors);
+        Self::merge("actionTags", self.action_tags, &mut config.action_tags);
+        Self::merge(
+            "includeActionSnippets",
+            self.include_action_snippets,
+            &mut config.include_action_snippets,
+        );
+        Self::merge("backend", self.backend, &mut config.backend);
+        Self::merge("aliasSearch", self.alias_search, &mut config.alias_search);
+        Self::merge(
+            "maxSnippetBytes",
+            self.max_snippet_bytes,
+            &mut config.max_snippet_bytes,
+        );
+        Self::merge(
+            "largeSnippetMode",
+            self.large_snippet_mode,
+            &mut config.large_snippet_mode,
+        );
+        Self::merge(
+            "docCacheBytes",
+            self.doc_cache_bytes,
+            &mut config.doc_cache_bytes,
+        );
+        Self::merge(
+            "languagesFile",
+            self.languages_file.map(Some),
+            &mut config.languages_file,
+        );
+        Self::merge(
+            "metricsSummaryEvery",
+            self.metrics_summary_every,
+            &mut config.metrics_summary_every,
+        );
+        Self::merge(
+            "fallbackCompletions",
+            self.fallback_completions,
+            &mut config.fallback_completions,
+        );
+        Self::merge(
+            "resolveEnvInDocs",
+            self.resolve_env_in_docs,
+            &mut config.resolve_env_in_docs,
+        );
+        Self::merge(
+            "translateTag",
+            self.translate_tag,
+            &mut config.translate_tag,
+        );
+        Self::merge(
+            "legacyUniversalTranslation",
+            self.legacy_universal_translation,
+            &mut config.legacy_universal_translation,
+        );
+        Self::merge(
+            "universalSnippets",
+            self.universal_snippets,
+            &mut config.universal_snippets,
+        );
+        Self::merge("itemKind", self.item_kind.map(Some), &mut config.item_kind);
+        Self::merge(
+            "detailTemplate",
+            self.detail_template.map(Some),
+            &mut config.detail_template,
+        );
+        Self::merge("labelPrefix", self.label_prefix, &mut config.label_prefix);
+        Self::merge("tierOrder", self.tier_order, &mut config.tier_order);
+        Self::merge("replaceMode", self.replace_mode, &mut config.replace_mode);
+        Self::merge("caseMode", self.case_mode, &mut config.case_mode);
+        Self::merge(
+            "importMarker",
+            self.import_marker,
+            &mut config.import_marker,
+        );
+        Self::merge(
+            "urlCompletionLanguages",
+            self.url_completion_languages,
+            &mut config.url_completion_languages,
+        );
+        Self::merge(
+            "contextAwareInsertion",
+            self.context_aware_insertion,
+            &mut config.context_aware_insertion,
+        );
+        Self::merge(
+            "aliasPattern",
+            self.alias_pattern.map(Some),
+            &mut config.alias_pattern,
+        );
+        Self::merge("languageMap", self.language_map, &mut config.language_map);
+        Self::merge("perLanguage", self.per_language, &mut config.per_language);
+        config
+    }
+
+    fn merge<T: std::fmt::Debug>(key: &str, option: Option<T>, target: &mut T) {
+        match option {
+            Some(value) => {
+                info!("{} = {:?} (from initializationOptions)", key, value);
+                *target = value;
+            }
+            None => info!("{} = {:?} (from command line)", key, target),
+        }
+    }
+}
+
+/// bkmr's JSON snippet, re-exported for library users and tests
+pub use crate::domain::BkmrSnippet;
+
+/// Configuration and the services built from it
+struct BackendState {
+    config: BkmrConfig,
+    /// Languages of the configuration, its definitions read from the languages file
+    language_registry: std::sync::Arc<LanguageRegistry>,
+    /// Repository shared by the services, including the cache if enabled
+    repository: std::sync::Arc<dyn SnippetRepository>,
+    completion_service: CompletionService,
+    command_service: CommandService,
+    hover_service: HoverService,
+    symbol_service: SymbolService,
+    link_service: LinkService,
+    /// Background cache warming, None when disabled or caching is off
+    prefetch_service: Option<PrefetchService>,
+}
+
+impl BackendState {
+    /// Services around the default repository and those of configured folders
+    fn new(
+        config: BkmrConfig,
+        language_registry: std::sync::Arc<LanguageRegistry>,
+        mut repository: std::sync::Arc<dyn SnippetRepository>,
+        folder_repositories: Vec<(FolderRepository, std::sync::Arc<dyn SnippetRepository>)>,
+        document_service: &std::sync::Arc<DocumentService>,
+        metrics: &std::sync::Arc<Metrics>,
+    ) -> Self {
+        metrics.set_summary_every(config.metrics_summary_every);
+        document_service.set_language_registry(language_registry.clone());
+        document_service.set_trigger_char(config.trigger_char);
+        document_service.set_word_chars(config.word_chars.clone());
+        document_service.set_cache_bytes(config.doc_cache_bytes);
+
+        // Cache search results unless disabled with a zero TTL, one cache per repository
+        let cache_config = CacheConfig {
+            ttl: std::time::Duration::from_secs(config.cache_ttl_seconds),
+            max_entries: config.cache_max_entries,
         };
-        let repository = std::sync::Arc::new(BkmrRepository::new(repo_config));
-        
+        let cached = |repository: std::sync::Arc<dyn SnippetRepository>| {
+            std::sync::Arc::new(CachingSnippetRepository::new(
+                repository,
+                cache_config.clone(),
+            ))
+        };
+        let mut prefetch_service = None;
+        if config.cache_ttl_seconds > 0 {
+            let cache = cached(repository);
+            if config.prefetch {
+                prefetch_service = Some(PrefetchService::new(cache.clone()));
+            }
+            repository = cache;
+        }
+        if !folder_repositories.is_empty() {
+            let folders = folder_repositories
+                .into_iter()
+                .map(|(folder, folder_repository)| {
+                    let folder_repository: std::sync::Arc<dyn SnippetRepository> =
+                        if config.cache_ttl_seconds > 0 {
+                            cached(folder_repository)
+                        } else {
+                            folder_repository
+                        };
+                    (folder.folder.clone(), folder.path(), folder_repository)
+                })
+                .collect();
+            repository = std::sync::Arc::new(RepositoryRouter::new(repository, folders));
+        }
+
         // Create completion service with repository and configuration
-        let completion_service = CompletionService::with_config(repository, config.clone());
-        
+        let completion_service = CompletionService::with_config(repository.clone(), config.clone())
+            .with_language_registry(language_registry.clone())
+            .with_metrics(metrics.clone());
+        let hover_service = HoverService::new(repository.clone())
+            .with_translation(config.translation_policy())
+            .with_language_registry(language_registry.clone());
+        let symbol_service = SymbolService::new(repository.clone(), config.max_completions);
+        let link_service = LinkService::new(repository.clone(), document_service.clone());
+        let command_service = CommandService::new(repository.clone(), document_service.clone())
+            .with_snippet_tags(config.snippet_tags.clone())
+            .with_track_usage(config.track_usage)
+            .with_escape_all_dollars_for(config.escape_all_dollars_for.clone())
+            .with_native_path_separators(config.native_path_separators)
+            .with_untitled_filepath_comment(config.untitled_filepath_comment)
+            .with_author(config.author.clone())
+            .with_translation(config.translation_policy())
+            .with_language_registry(language_registry.clone());
+
         Self {
-            client,
             config,
+            language_registry,
+            repository,
             completion_service,
-            document_cache: std::sync::Arc::new(std::sync::RwLock::new(
-                std::collections::HashMap::new(),
-            )),
-            language_cache: std::sync::Arc::new(std::sync::RwLock::new(
-                std::collections::HashMap::new(),
-            )),
+            command_service,
+            hover_service,
+            symbol_service,
+            link_service,
+            prefetch_service,
         }
     }
+}
 
-    /// Extract word backwards from cursor position and return both query and range
-    #[instrument(skip(self))]
-    fn extract_snippet_query(&self, uri: &Url, position: Position) -> Option<(String, Range)> {
-        let cache = self.document_cache.read().ok()?;
-        let content = cache.get(&uri.to_string())?;
+/// git's user.name, None if git or the setting is missing
+async fn git_author() -> Option<String> {
+    let output = tokio::process::Command::new("git")
+        .args(["config", "user.name"])
+        .output()
+        .await
+        .ok()?;
+    let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !name.is_empty()).then_some(name)
+}
 
-        let lines: Vec<&str> = content.lines().collect();
-        if position.line as usize >= lines.len() {
-            return None;
-        }
+impl std::fmt::Debug for BackendState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BackendState")
+            .field("config", &self.config)
+            .field("language_registry", &self.language_registry)
+            .field("repository", &"<SnippetRepository>")
+            .field("completion_service", &self.completion_service)
+            .field("command_service", &self.command_service)
+            .field("hover_service", &self.hover_service)
+            .field("symbol_service", &self.symbol_service)
+            .field("link_service", &self.link_service)
+            .field("prefetch_service", &self.prefetch_service)
+            .finish()
+    }
+}
 
-        let line = lines[position.line as usize];
-        let char_pos = position.character as usize;
+/// Progress of the shutdown sequence, deciding the process exit code
+#[derive(Debug, Default)]
+pub struct Lifecycle {
+    shutdown: std::sync::atomic::AtomicBool,
+    exit: tokio::sync::watch::Sender<bool>,
+}
 
-        if char_pos > line.len() {
-            return None;
-        }
+impl Lifecycle {
+    fn record_shutdown(&self) {
+        self.shutdown
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+    }
 
-        let before_cursor = &line[..char_pos];
-        debug!(
-            "Extracting from line: '{}', char_pos: {}, before_cursor: '{}'",
-            line, char_pos, before_cursor
-        );
-
-        // Extract word backwards from cursor - find where the word starts
-        let word_start = before_cursor
-            .char_indices()
-            .rev()
-            .take_while(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == '-')
-            .last()
-            .map(|(i, _)| i)
-            .unwrap_or(char_pos);
-
-        debug!("Word boundaries: start={}, end={}", word_start, char_pos);
-
-        if word_start < char_pos {
-            let word = &before_cursor[word_start..];
-            if !word.is_empty() && word.chars().any(|c| c.is_alphanumeric()) {
-                debug!("Extracted word: '{}' from position {}", word, char_pos);
-
-                // Create range for the word to be replaced
-                let range = Range {
-                    start: Position {
-                        line: position.line,
-                        character: word_start as u32,
-                    },
-                    end: Position {
-                        line: position.line,
-                        character: char_pos as u32,
-                    },
-                };
+    fn record_exit(&self) {
+        self.exit.send_replace(true);
+    }
 
-                return Some((word.to_string(), range));
-            }
-        }
+    pub fn shutdown_received(&self) -> bool {
+        self.shutdown.load(std::sync::atomic::Ordering::SeqCst)
+    }
 
-        debug!("No valid word found at position {}", char_pos);
-        None
+    pub fn exit_received(&self) -> bool {
+        *self.exit.borrow()
     }
 
-    /// Get the language ID for a document URI
-    fn get_language_id(&self, uri: &Url) -> Option<String> {
-        let cache = self.language_cache.read().ok()?;
-        cache.get(&uri.to_string()).cloned()
+    /// Wait for the exit notification
+    pub async fn exited(&self) {
+        // The sender lives as long as self, waiting cannot fail
+        let _ = self.exit.subscribe().wait_for(|exit| *exit).await;
     }
 
+    /// 0 when shutdown came before the server stopped, 1 otherwise, as the spec asks of exit
+    pub fn exit_code(&self) -> i32 {
+        if self.shutdown_received() { 0 } else { 1 }
+    }
+}
 
+pub struct BkmrLspBackend {
+    client: Client,
+    /// Replaced in initialize when the client sends initialization options, and by bkmr.reloadConfig
+    state: std::sync::RwLock<std::sync::Arc<BackendState>>,
+    /// Initialization options sent by the client, applied again by bkmr.reloadConfig
+    initialization_options: std::sync::RwLock<Option<serde_json::Value>>,
+    /// Repository injected in place of the bkmr CLI, kept when the state is rebuilt
+    custom_repository: Option<std::sync::Arc<dyn SnippetRepository>>,
+    /// Document contents and language IDs used to extract completion queries
+    document_service: std::sync::Arc<DocumentService>,
+    /// Workspace folders opened by the client, base for relative file paths
+    workspace_folders: std::sync::RwLock<Vec<Url>>,
+    /// Progress shown while bkmr is slow to answer
+    progress_service: ProgressService,
+    /// Tags completions are restricted to, set with bkmr.setTagFilter
+    tag_filters: std::sync::RwLock<TagFilters>,
+    /// Documents without completions, by language id or .bkmrlspignore
+    ignore_service: IgnoreService,
+    /// Whether the client lets us register file watchers, set at initialize
+    watch_files: std::sync::atomic::AtomicBool,
+    /// Messages from the bkmr repository, forwarded to the client as window/logMessage
+    notices: tokio::sync::mpsc::UnboundedSender<String>,
+    /// Receiving end of the notices, taken when forwarding starts in initialized
+    notice_receiver: std::sync::Mutex<Option<tokio::sync::mpsc::UnboundedReceiver<String>>>,
+    /// Completion latencies and bkmr spawns, kept when the state is rebuilt
+    metrics: std::sync::Arc<Metrics>,
+    /// `$/logTrace` reports at the level set with `$/setTrace`
+    trace: std::sync::Arc<TraceReporter>,
+    /// Receiving end of the traces, taken when forwarding starts in initialized
+    trace_receiver: std::sync::Mutex<Option<tokio::sync::mpsc::UnboundedReceiver<LogTraceParams>>>,
+    /// Background tasks like usage tracking, cancelled at shutdown
+    tasks: std::sync::Mutex<tokio::task::JoinSet<()>>,
+    /// Whether shutdown and exit were received, deciding the exit code
+    lifecycle: std::sync::Arc<Lifecycle>,
+}
 
+impl std::fmt::Debug for BkmrLspBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BkmrLspBackend")
+            .field("state", &self.state)
+            .field("document_service", &self.document_service)
+            .finish()
+    }
+}
 
+impl BkmrLspBackend {
+    pub fn new(client: Client) -> Self {
+        Self::with_config(client, BkmrConfig::default())
+    }
 
+    pub fn with_config(client: Client, config: BkmrConfig) -> Self {
+        debug!("Creating BkmrLspBackend with config: {:?}", config);
+        Self::build(client, config, None)
+    }
 
+    /// Create a backend serving snippets from the given repository
+    pub fn with_repository(
+        client: Client,
+        config: BkmrConfig,
+        repository: std::sync::Arc<dyn SnippetRepository>,
+    ) -> Self {
+        Self::build(client, config, Some(repository))
+    }
 
-    /// Check if bkmr binary is available
+    fn build(
+        client: Client,
+        config: BkmrConfig,
+        custom_repository: Option<std::sync::Arc<dyn SnippetRepository>>,
+    ) -> Self {
+        let document_service = std::sync::Arc::new(DocumentService::new());
+        let (notices, notice_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let metrics = std::sync::Arc::new(Metrics::default());
+        let (trace, trace_receiver) = TraceReporter::new();
+        // Read once at startup, rebuilt services read the languages file with tokio::fs
+        let definitions = LanguageDefinitions::load(config.languages_path());
+        let state = Self::build_state(
+            config,
+            definitions,
+            custom_repository.clone(),
+            &document_service,
+            &notices,
+            &metrics,
+            Vec::new(),
+        );
+
+        Self {
+            progress_service: ProgressService::new(client.clone()),
+            client,
+            state: std::sync::RwLock::new(std::sync::Arc::new(state)),
+            initialization_options: std::sync::RwLock::new(None),
+            custom_repository,
+            document_service,
+            workspace_folders: std::sync::RwLock::new(Vec::new()),
+            tag_filters: std::sync::RwLock::new(TagFilters::default()),
+            ignore_service: IgnoreService::new(),
+            watch_files: std::sync::atomic::AtomicBool::new(false),
+            notices,
+            notice_receiver: std::sync::Mutex::new(Some(notice_receiver)),
+            metrics,
+            trace: std::sync::Arc::new(trace),
+            trace_receiver: std::sync::Mutex::new(Some(trace_receiver)),
+            tasks: std::sync::Mutex::new(tokio::task::JoinSet::new()),
+            lifecycle: std::sync::Arc::new(Lifecycle::default()),
+        }
+    }
+
+    fn build_state(
+        config: BkmrConfig,
+        definitions: LanguageDefinitions,
+        custom_repository: Option<std::sync::Arc<dyn SnippetRepository>>,
+        document_service: &std::sync::Arc<DocumentService>,
+        notices: &tokio::sync::mpsc::UnboundedSender<String>,
+        metrics: &std::sync::Arc<Metrics>,
+        folder_files: Vec<FolderRepository>,
+    ) -> BackendState {
+        let config = config.with_folder_files(folder_files);
+        let language_registry = std::sync::Arc::new(config.language_registry(definitions));
+
+        // Create repository with configuration from BkmrConfig
+        let folder_repositories = match &custom_repository {
+            Some(_) => Vec::new(),
+            None => config
+                .folder_repositories
+                .iter()
+                .map(|folder| {
+                    let repository = config.for_folder(folder).bkmr_repository(
+                        language_registry.clone(),
+                        Some(notices.clone()),
+                        Some(metrics.clone()),
+                    );
+                    (
+                        folder.clone(),
+                        config.with_snippet_dirs(repository, &language_registry),
+                    )
+                })
+                .collect(),
+        };
+        let repository = custom_repository.unwrap_or_else(|| {
+            config.bkmr_repository(
+                language_registry.clone(),
+                Some(notices.clone()),
+                Some(metrics.clone()),
+            )
+        });
+        let repository = config.with_snippet_dirs(repository, &language_registry);
+        BackendState::new(
+            config,
+            language_registry,
+            repository,
+            folder_repositories,
+            document_service,
+            metrics,
+        )
+    }
+
+    /// Run a task in the background until it finishes or the server shuts down
+    fn spawn(&self, task: impl std::future::Future<Output = ()> + Send + 'static) {
+        let mut tasks = sync::lock(&self.tasks, "background tasks");
+        // Reap finished tasks so the set does not grow with every insertion
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(task);
+    }
+
+    /// Shutdown and exit notifications received so far
+    pub fn lifecycle(&self) -> std::sync::Arc<Lifecycle> {
+        self.lifecycle.clone()
+    }
+
+    /// Current configuration and services
+    fn state(&self) -> std::sync::Arc<BackendState> {
+        sync::read(&self.state, "backend state").clone()
+    }
+
+    /// Completion features the client advertised at initialize
+    pub fn client_completion_support(&self) -> ClientCompletionSupport {
+        self.state().completion_service.client_support()
+    }
+
+    /// Handle bkmr/status: bkmr version, last search, open documents and configuration
+    ///
+    /// Meant for finding out why completions come back empty.
     #[instrument(skip(self))]
-    async fn verify_bkmr_availability(&self) -> Result<()> {
-        debug!("Verifying bkmr availability");
+    pub async fn status(&self) -> LspResult<serde_json::Value> {
+        let state = self.state();
+        let version = state.repository.version().await.unwrap_or_else(|e| {
+            debug!("Failed to get bkmr version: {:#}", e);
+            None
+        });
+        let stats = state.repository.stats();
+        let documents = self.document_service.document_languages().await;
+        let document_cache = self.document_service.cache_stats().await;
+
+        Ok(serde_json::json!({
+            "serverVersion": env!("CARGO_PKG_VERSION"),
+            "bkmr": {
+                "binary": state.config.bkmr_binary,
+                "version": version,
+                "capabilities": state.repository.capabilities(),
+            },
+            "lastQuery": stats.last_query,
+            "lastFetchCount": stats.last_fetch_count,
+            "lastCompletionCount": state.completion_service.last_completion_count(),
+            "latency": self.metrics.snapshot(),
+            "documents": {
+                "count": documents.len(),
+                "languages": documents,
+                "bytes": document_cache.bytes,
+                "evicted": document_cache.evicted,
+            },
+            "cache": stats.cache,
+            "tagFilters": *sync::read(&self.tag_filters, "tag filters"),
+            "config": state.config,
+        }))
+    }
 
-        let command_future = tokio::process::Command::new(&self.config.bkmr_binary)
-            .args(["--help"])
-            .output();
+    /// Handle $/setTrace: the level of the `$/logTrace` reports, off until the client sets it
+    #[instrument(skip(self))]
+    pub async fn set_trace(&self, params: SetTraceParams) {
+        self.trace.set_level(params.value);
+    }
 
-        let output =
-            match tokio::time::timeout(std::time::Duration::from_secs(5), command_future).await {
-                Ok(Ok(output)) => output,
-                Ok(Err(e)) => {
-                    return Err(anyhow!("bkmr binary not found: {}", e));
-                }
-                Err(_) => {
-                    return Err(anyhow!("bkmr --help command timed out"));
+    /// Report a finished completion, with the search and cache activity at verbose level
+    ///
+    /// The cache counters are shared, hits of completions running at the
+    /// same time are counted with this one.
+    fn trace_completion(
+        &self,
+        state: &BackendState,
+        context: &CompletionContext,
+        items: usize,
+        start: std::time::Instant,
+        cache_before: Option<CacheStats>,
+    ) {
+        let mut trace = CompletionTrace {
+            query: context.get_query_text().unwrap_or("").to_string(),
+            language_id: context.language_id.clone(),
+            items,
+            elapsed: start.elapsed(),
+            ..Default::default()
+        };
+        if self.trace.verbose() {
+            let stats = state.repository.stats();
+            trace.fts_query = stats.last_query;
+            trace.cache = stats.cache.map(|after| {
+                let before = cache_before.unwrap_or_default();
+                CacheStats {
+                    hits: after.hits.saturating_sub(before.hits),
+                    misses: after.misses.saturating_sub(before.misses),
                 }
-            };
+            });
+        }
+        self.trace.completion(&trace);
+    }
 
-        if !output.status.success() {
-            return Err(anyhow!("bkmr binary is not working properly"));
+    /// Handle bkmr/listTags: tags with the number of bookmarks carrying them, most used first
+    ///
+    /// Snippet marker tags like `_snip_` are left out, every snippet has them.
+    #[instrument(skip(self))]
+    pub async fn list_tags(&self) -> LspResult<Vec<TagCount>> {
+        let state = self.state();
+        let tags = state.repository.list_tags().await.map_err(|e| {
+            error!("Failed to list tags: {:#}", e);
+            internal_error(format!("Failed to list tags: {:#}", e))
+        })?;
+        Ok(tags
+            .into_iter()
+            .filter(|count| {
+                !state
+                    .config
+                    .snippet_tags
+                    .iter()
+                    .any(|tag| tag.eq_ignore_ascii_case(&count.tag))
+            })
+            .collect())
+    }
+
+    /// Handle textDocument/inlineCompletion: the best snippet for the word before the cursor
+    ///
+    /// Returns nothing unless enabled with --inline-completions. Errors are
+    /// only logged, the request is sent automatically while typing.
+    #[instrument(skip(self, params))]
+    pub async fn inline_completion(
+        &self,
+        params: InlineCompletionParams,
+    ) -> LspResult<Option<InlineCompletionList>> {
+        let state = self.state();
+        if !state.config.inline_completions {
+            return Ok(None);
         }
 
-        info!("bkmr binary verified successfully");
-        Ok(())
+        let uri = &params.text_document.uri;
+        let context = match self
+            .document_service
+            .extract_completion_context(uri, params.position)
+            .await
+        {
+            Ok(context) => context,
+            Err(e) => {
+                debug!("No document context for inline completion: {}", e);
+                return Ok(None);
+            }
+        };
+        if self
+            .completion_disabled(uri, context.language_id.as_deref())
+            .await
+        {
+            return Ok(None);
+        }
+        let context = match self.tag_filter(uri.as_str()) {
+            Some(tag) => context.with_tag_filter(tag),
+            None => context,
+        };
+
+        match state.completion_service.inline_completion(&context).await {
+            Ok(item) => Ok(Some(InlineCompletionList {
+                items: item.into_iter().collect(),
+            })),
+            Err(e) => {
+                warn!("Failed to get inline completion: {:#}", e);
+                Ok(None)
+            }
+        }
     }
 
-    /// Get language-specific information for Rust pattern translation
-    pub fn get_language_info(&self, language_id: &str) -> LanguageInfo {
-        match language_id.to_lowercase().as_str() {
-            "rust" => LanguageInfo {
-                line_comment: Some("//".to_string()),
-                block_comment: Some(("/*".to_string(), "*/".to_string())),
-                indent_char: "    ".to_string(),
-            },
-            "javascript" | "js" => LanguageInfo {
-                line_comment: Some("//".to_string()),
-                block_comment: Some(("/*".to_string(), "*/".to_string())),
-                indent_char: "  ".to_string(),
-            },
-            "typescript" | "ts" => LanguageInfo {
-                line_comment: Some("//".to_string()),
-                block_comment: Some(("/*".to_string(), "*/".to_string())),
-                indent_char: "  ".to_string(),
-            },
-            "python" => LanguageInfo {
-                line_comment: Some("#".to_string()),
-                block_comment: Some(("\"\"\"".to_string(), "\"\"\"".to_string())),
-                indent_char: "    ".to_string(),
-            },
-            "go" => LanguageInfo {
-                line_comment: Some("//".to_string()),
-                block_comment: Some(("/*".to_string(), "*/".to_string())),
-                indent_char: "\t".to_string(),
-            },
-            "java" => LanguageInfo {
-                line_comment: Some("//".to_string()),
-                block_comment: Some(("/*".to_string(), "*/".to_string())),
-                indent_char: "    ".to_string(),
-            },
-            "c" => LanguageInfo {
-                line_comment: Some("//".to_string()),
-                block_comment: Some(("/*".to_string(), "*/".to_string())),
-                indent_char: "    ".to_string(),
-            },
-            "cpp" | "c++" => LanguageInfo {
-                line_comment: Some("//".to_string()),
-                block_comment: Some(("/*".to_string(), "*/".to_string())),
-                indent_char: "    ".to_string(),
-            },
-            "html" => LanguageInfo {
-                line_comment: None,
-                block_comment: Some(("<!--".to_string(), "-->".to_string())),
-                indent_char: "  ".to_string(),
-            },
-            "css" => LanguageInfo {
-                line_comment: None,
-                block_comment: Some(("/*".to_string(), "*/".to_string())),
-                indent_char: "  ".to_string(),
-            },
-            "scss" => LanguageInfo {
-                line_comment: Some("//".to_string()),
-                block_comment: Some(("/*".to_string(), "*/".to_string())),
-                indent_char: "  ".to_string(),
-            },
-            "ruby" => LanguageInfo {
-                line_comment: Some("#".to_string()),
-                block_comment: Some(("=begin".to_string(), "=end".to_string())),
-                indent_char: "  ".to_string(),
-            },
-            "php" => LanguageInfo {
-                line_comment: Some("//".to_string()),
-                block_comment: Some(("/*".to_string(), "*/".to_string())),
-                indent_char: "    ".to_string(),
-            },
-            "swift" => LanguageInfo {
-                line_comment: Some("//".to_string()),
-                block_comment: Some(("/*".to_string(), "*/".to_string())),
-                indent_char: "    ".to_string(),
-            },
-            "kotlin" => LanguageInfo {
-                line_comment: Some("//".to_string()),
-                block_comment: Some(("/*".to_string(), "*/".to_string())),
-                indent_char: "    ".to_string(),
-            },
-            "shell" | "bash" | "sh" => LanguageInfo {
-                line_comment: Some("#".to_string()),
-                block_comment: None,
-                indent_char: "    ".to_string(),
-            },
-            "yaml" | "yml" => LanguageInfo {
-                line_comment: Some("#".to_string()),
-                block_comment: None,
-                indent_char: "  ".to_string(),
-            },
-            "json" => LanguageInfo {
-                line_comment: None,
-                block_comment: None,
-                indent_char: "  ".to_string(),
-            },
-            "markdown" | "md" => LanguageInfo {
-                line_comment: None,
-                block_comment: Some(("<!--".to_string(), "-->".to_string())),
-                indent_char: "  ".to_string(),
-            },
-            "xml" => LanguageInfo {
-                line_comment: None,
-                block_comment: Some(("<!--".to_string(), "-->".to_string())),
-                indent_char: "  ".to_string(),
-            },
-            "vim" | "viml" => LanguageInfo {
-                line_comment: Some("\"".to_string()),
-                block_comment: None,
-                indent_char: "  ".to_string(),
-            },
-            // Default fallback for unknown languages
-            _ => LanguageInfo {
-                line_comment: Some("#".to_string()),
-                block_comment: None,
-                indent_char: "    ".to_string(),
-            },
+    /// Whether the document's language or a .bkmrlspignore pattern disables completions
+    async fn completion_disabled(&self, uri: &Url, language_id: Option<&str>) -> bool {
+        let workspace_folders = sync::read(&self.workspace_folders, "workspace folders").clone();
+        self.ignore_service
+            .is_ignored(uri, language_id, &workspace_folders)
+            .await
+    }
+
+    /// Tag filter applying to completions in a document
+    fn tag_filter(&self, uri: &str) -> Option<String> {
+        sync::read(&self.tag_filters, "tag filters")
+            .for_document(uri)
+            .map(str::to_string)
+    }
+
+    /// Rebuild configuration and services from the client's initialization options
+    ///
+    /// The language definitions are kept unless the options name another languages file.
+    async fn apply_initialization_options(&self, value: &serde_json::Value) {
+        let previous = self.state();
+        let options = InitializationOptions::from_value(value);
+        let config = options.merge_into(previous.config.clone());
+        let definitions = if config.languages_file == previous.config.languages_file {
+            previous.language_registry.definitions().clone()
+        } else {
+            LanguageDefinitions::load_async(config.languages_path()).await
+        };
+        self.replace_state(config, definitions).await;
+    }
+
+    /// Build new services from a configuration, stopping the previous prefetching
+    ///
+    /// The settings files of the workspace folders are read again, the
+    /// client's completion support carries over.
+    async fn replace_state(&self, config: BkmrConfig, definitions: LanguageDefinitions) {
+        let folders = sync::read(&self.workspace_folders, "workspace folders").clone();
+        let folder_files = FolderRepository::load_all(&folders).await;
+        let state = Self::build_state(
+            config,
+            definitions,
+            self.custom_repository.clone(),
+            &self.document_service,
+            &self.notices,
+            &self.metrics,
+            folder_files,
+        );
+        state.completion_service.set_workspace_folders(folders);
+        state
+            .completion_service
+            .set_client_support(self.state().completion_service.client_support());
+        let previous = std::mem::replace(
+            &mut *sync::write(&self.state, "backend state"),
+            std::sync::Arc::new(state),
+        );
+        if let Some(prefetch_service) = &previous.prefetch_service {
+            prefetch_service.shutdown();
         }
     }
 
+    /// Handle bkmr.reloadConfig: rebuild the services, re-read ignore files and check bkmr again
+    ///
+    /// The rebuilt services start with empty caches and keep the client's
+    /// completion support and workspace folders.
+    async fn reload_config(&self) -> HealthSummary {
+        let previous = self.state();
+        let options = sync::read(&self.initialization_options, "initialization options").clone();
+        let config = match &options {
+            Some(options) => {
+                InitializationOptions::from_value(options).merge_into(previous.config.clone())
+            }
+            None => previous.config.clone(),
+        };
+        let definitions = LanguageDefinitions::load_async(config.languages_path()).await;
+        self.replace_state(config, definitions).await;
+
+        let state = self.state();
+        let folders = sync::read(&self.workspace_folders, "workspace folders").clone();
+        state
+            .completion_service
+            .set_workspace_folders(folders.clone());
+        self.ignore_service
+            .set_disabled_languages(state.config.disable_for.clone());
+        self.ignore_service.load(&folders).await;
+
+        if let Err(e) = state.repository.start().await {
+            warn!("Failed to start snippet repository: {:#}", e);
+        }
+        let summary = state.command_service.health_summary().await;
+        info!("Reloaded configuration: {:?}", summary);
+        summary
+    }
+
+    /// Remember the workspace folders, falling back to the root URI for older clients
+    async fn set_workspace_folders(&self, params: &InitializeParams) {
+        #[allow(deprecated)] // root_uri is the only root some clients send
+        let folders: Vec<Url> = match &params.workspace_folders {
+            Some(folders) => folders.iter().map(|folder| folder.uri.clone()).collect(),
+            None => params.root_uri.iter().cloned().collect(),
+        };
+        debug!("Workspace folders: {:?}", folders);
+        self.state()
+            .completion_service
+            .set_workspace_folders(folders.clone());
+        self.ignore_service.load(&folders).await;
+        *sync::write(&self.workspace_folders, "workspace folders") = folders;
+    }
+
+    /// Insert a snippet at each target via workspace edit, reporting failures to the client
+    async fn insert_snippet_by_id(
+        &self,
+        id: i32,
+        folder: Option<&str>,
+        uri: &Url,
+        targets: &[Range],
+    ) -> LspResult<bool> {
+        let state = self.state();
+        let workspace_folders = sync::read(&self.workspace_folders, "workspace folders").clone();
+        let edit = state
+            .command_service
+            .insert_snippet_by_id(id, folder, uri, targets, &workspace_folders)
+            .await;
+        self.apply_snippet_edit(id, uri, targets.len(), edit).await
+    }
 
+    /// Insert the uninterpolated template of a snippet, reporting failures to the client
+    async fn insert_raw(
+        &self,
+        id: i32,
+        folder: Option<&str>,
+        uri: &Url,
+        position: Position,
+    ) -> LspResult<bool> {
+        let edit = self
+            .state()
+            .command_service
+            .insert_raw(id, folder, uri, position)
+            .await;
+        self.apply_snippet_edit(id, uri, 1, edit).await
+    }
 
-    /// Legacy method for backward compatibility - uses new language info system
-    fn get_comment_syntax(&self, file_path: &str) -> &'static str {
-        let path = Path::new(file_path);
-        let extension = path.extension().and_then(|s| s.to_str()).unwrap_or("");
-
-        // Map file extension to language ID for language info lookup
-        let language_id = match extension {
-            "rs" => "rust",
-            "js" | "mjs" => "javascript",
-            "ts" | "tsx" => "typescript",
-            "py" | "pyw" => "python",
-            "go" => "go",
-            "java" => "java",
-            "c" | "h" => "c",
-            "cpp" | "cc" | "cxx" | "hpp" => "cpp",
-            "html" | "htm" => "html",
-            "css" => "css",
-            "scss" => "scss",
-            "rb" => "ruby",
-            "php" => "php",
-            "swift" => "swift",
-            "kt" | "kts" => "kotlin",
-            "sh" | "bash" | "zsh" => "shell",
-            "yaml" | "yml" => "yaml",
-            "json" => "json",
-            "md" | "markdown" => "markdown",
-            "xml" => "xml",
-            "vim" => "vim",
-            _ => "unknown",
+    /// Ask the client to apply an edit inserting a snippet, whether it was applied
+    async fn apply_snippet_edit(
+        &self,
+        id: i32,
+        uri: &Url,
+        targets: usize,
+        edit: Result<WorkspaceEdit>,
+    ) -> LspResult<bool> {
+        let result = match edit {
+            Ok(edit) => self
+                .client
+                .apply_edit(edit)
+                .await
+                .map_err(|e| anyhow!("apply workspace edit: {}", e))
+                .and_then(|response| {
+                    if response.applied {
+                        Ok(())
+                    } else {
+                        Err(anyhow!(
+                            "client rejected edit: {}",
+                            response.failure_reason.unwrap_or_default()
+                        ))
+                    }
+                }),
+            Err(e) => Err(e),
         };
 
-        let lang_info = self.get_language_info(language_id);
-        // Return line comment or block comment start, fallback to #
-        if let Some(_line_comment) = &lang_info.line_comment {
-            // This is a bit of a hack since we need to return &'static str
-            // but the LanguageInfo returns String. For the legacy method,
-            // we'll use a simple lookup.
-            match language_id {
-                "rust" | "javascript" | "typescript" | "go" | "java" | "c" | "cpp" | "swift"
-                | "kotlin" | "scss" | "php" => "//",
-                "python" | "shell" | "yaml" => "#",
-                "html" | "markdown" | "xml" => "<!--",
-                "css" => "/*",
-                "vim" => "\"",
-                _ => "#",
+        match result {
+            Ok(()) => {
+                info!(
+                    "Inserted snippet {} into {} at {} targets",
+                    id, uri, targets
+                );
+                Ok(true)
             }
-        } else {
-            "#"
+            Err(e) => {
+                error!("Failed to insert snippet {}: {:#}", id, e);
+                self.report_failure(format!("Failed to insert snippet {}: {:#}", id, e))
+                    .await
+                    .map_or(Ok(false), Err)
+            }
+        }
+    }
+
+    /// Log a rejected command argument, the InvalidParams error naming its field
+    async fn invalid_argument(&self, command: &str, e: InvalidArgument) -> jsonrpc::Error {
+        error!("Invalid arguments for {}: {}", command, e);
+        self.client
+            .log_message(
+                MessageType::ERROR,
+                &format!("Invalid arguments for {}: {}", command, e),
+            )
+            .await;
+        jsonrpc::Error {
+            code: jsonrpc::ErrorCode::InvalidParams,
+            message: format!("Invalid {} argument of {}: {}", e.field, command, e.message).into(),
+            data: Some(serde_json::json!({ "field": e.field })),
         }
     }
 
-    /// Get the relative path from project root
-    fn get_relative_path(&self, file_uri: &str) -> String {
-        let url = match Url::parse(file_uri) {
-            Ok(u) => u,
-            Err(_) => return file_uri.to_string(),
+    /// Report a failed request as the error mode asks, the error to respond with in respond mode
+    async fn report_failure(&self, message: String) -> Option<jsonrpc::Error> {
+        let message_type = match self.state().config.errors {
+            ErrorMode::Silent => MessageType::ERROR,
+            ErrorMode::Log => MessageType::WARNING,
+            ErrorMode::Respond => return Some(request_failed(message)),
         };
+        self.client.log_message(message_type, message).await;
+        None
+    }
 
-        let file_path = match url.to_file_path() {
-            Ok(p) => p,
-            Err(_) => return file_uri.to_string(),
+    /// Ask the client to report changes to the bkmr database and snippet directories
+    async fn watch_snippet_sources(&self) {
+        let sources = self.state().config.snippet_sources();
+        if sources.is_empty() {
+            debug!("No bkmr database path or snippet directory to watch");
+            return;
+        }
+        let registration = Registration {
+            id: "bkmr-lsp-snippet-sources".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
+                watchers: sources.watchers(),
+            })
+            .ok(),
         };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            warn!("Failed to watch snippet sources: {}", e);
+        }
+    }
 
-        // Try to find a project root by looking for common indicators
-        let mut current = file_path.as_path();
-        while let Some(parent) = current.parent() {
-            // Check for common project root indicators
-            if parent.join("Cargo.toml").exists()
-                || parent.join("package.json").exists()
-                || parent.join("pom.xml").exists()
-                || parent.join("build.gradle").exists()
-                || parent.join("build.gradle.kts").exists()
-                || parent.join("Makefile").exists()
-                || parent.join(".git").exists()
-            {
-                // Found project root, return relative path
-                if let Ok(rel_path) = file_path.strip_prefix(parent) {
-                    return rel_path.to_string_lossy().to_string();
-                }
-                break;
+    /// Drop cached results after snippets changed outside the editor and warm the open languages again
+    async fn refresh_snippets(&self) {
+        let state = self.state();
+        let evicted = state.command_service.clear_cache(&state.completion_service);
+        info!(
+            "Snippet sources changed, cleared {} cached results",
+            evicted
+        );
+        let documents = self.document_service.document_languages().await;
+        for (uri, language_id) in documents {
+            if let (Ok(uri), Some(language_id)) = (Url::parse(&uri), language_id) {
+                self.prefetch(&uri, &language_id);
             }
-            current = parent;
         }
+    }
 
-        // Fall back to just the filename if no project root found
-        file_path
-            .file_name()
-            .map(|n| n.to_string_lossy().to_string())
-            .unwrap_or_else(|| file_uri.to_string())
+    /// Warm the cache for empty-query completions in a document's language, if enabled
+    fn prefetch(&self, uri: &Url, language_id: &str) {
+        let state = self.state();
+        if let Some(prefetch_service) = &state.prefetch_service
+            && !language_id.is_empty()
+        {
+            prefetch_service.prefetch(state.completion_service.language_filter(
+                uri,
+                language_id,
+                self.tag_filter(uri.as_str()),
+            ));
+        }
     }
 
-    /// Insert filepath comment at the beginning of the file
+    /// Check if bkmr binary is available
     #[instrument(skip(self))]
-    async fn insert_filepath_comment(&self, file_uri: &str) -> Result<Vec<TextEdit>> {
-        let relative_path = self.get_relative_path(file_uri);
-        let comment_syntax = self.get_comment_syntax(file_uri);
-
-        let comment_text = match comment_syntax {
-            "<!--" => format!("<!-- {} -->\n", relative_path),
-            "/*" => format!("/* {} */\n", relative_path),
-            _ => format!("{} {}\n", comment_syntax, relative_path),
-        };
+    async fn verify_bkmr_availability(&self) -> Result<()> {
+        let state = self.state();
+        debug!("Verifying bkmr availability");
 
-        debug!("Inserting filepath comment: {}", comment_text.trim());
-
-        // Create a text edit to insert at the beginning of the file
-        let edit = TextEdit {
-            range: Range {
-                start: Position {
-                    line: 0,
-                    character: 0,
-                },
-                end: Position {
-                    line: 0,
-                    character: 0,
-                },
-            },
-            new_text: comment_text,
+        let command_future = tokio::process::Command::new(&state.config.bkmr_binary)
+            .args(["--help"])
+            .kill_on_drop(true)
+            .output();
+        let timeout = std::time::Duration::from_secs(state.config.bkmr_timeout_seconds);
+
+        let output = match tokio::time::timeout(timeout, command_future).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => {
+                return Err(anyhow!(
+                    "bkmr binary '{}' not found: {}",
+                    state.config.bkmr_binary,
+                    e
+                ));
+            }
+            Err(_) => {
+                return Err(anyhow!("bkmr --help command timed out"));
+            }
         };
 
-        Ok(vec![edit])
+        if !output.status.success() {
+            return Err(anyhow!("bkmr binary is not working properly"));
+        }
+
+        info!("bkmr binary verified successfully");
+        Ok(())
     }
 }
 
@@ -444,6 +1780,44 @@ impl LanguageServer for BkmrLspBackend {
             params.client_info
         );
 
+        if let Some(level) = params.trace {
+            self.trace.set_level(level);
+        }
+
+        // Folders first, building the services reads their settings files
+        self.set_workspace_folders(&params).await;
+
+        // Settings from the client take precedence over command line flags
+        if let Some(options) = &params.initialization_options {
+            self.apply_initialization_options(options).await;
+        } else {
+            let folders = sync::read(&self.workspace_folders, "workspace folders").clone();
+            if FolderRepository::any_file(&folders).await {
+                let state = self.state();
+                self.replace_state(
+                    state.config.clone(),
+                    state.language_registry.definitions().clone(),
+                )
+                .await;
+            }
+        }
+        *sync::write(&self.initialization_options, "initialization options") =
+            params.initialization_options.clone();
+        let state = self.state();
+        self.ignore_service
+            .set_disabled_languages(state.config.disable_for.clone());
+
+        // Ignore files and snippet sources are watched when the client can do it for us
+        let watch_files = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+            .and_then(|watched_files| watched_files.dynamic_registration)
+            .unwrap_or(false);
+        self.watch_files
+            .store(watch_files, std::sync::atomic::Ordering::Relaxed);
+
         // Verify bkmr is available
         if let Err(e) = self.verify_bkmr_availability().await {
             error!("bkmr verification failed: {}", e);
@@ -455,17 +1829,52 @@ impl LanguageServer for BkmrLspBackend {
                 .await;
         }
 
-        // Check if client supports snippets
-        let snippet_support = params
+        // Each folder's repository logs its own result
+        if !state.config.folder_repositories.is_empty()
+            && let Err(e) = state.repository.health_check().await
+        {
+            warn!("Default bkmr repository is unavailable: {:#}", e);
+        }
+
+        // Spawn long-running helpers like the bkmr daemon before the first completion
+        if let Err(e) = state.repository.start().await {
+            warn!("Failed to start snippet repository: {:#}", e);
+        }
+
+        // Adapt completion items to what the client can handle
+        let completion_support = ClientCompletionSupport::from_capabilities(
+            params
+                .capabilities
+                .text_document
+                .as_ref()
+                .and_then(|td| td.completion.as_ref()),
+        );
+        let snippet_support = completion_support.snippets;
+        info!("Client snippet support: {}", snippet_support);
+        state
+            .completion_service
+            .set_client_support(completion_support);
+
+        // Progress notifications are only sent to clients that can show them
+        let work_done_progress = params
             .capabilities
-            .text_document
+            .window
             .as_ref()
-            .and_then(|td| td.completion.as_ref())
-            .and_then(|comp| comp.completion_item.as_ref())
-            .and_then(|item| item.snippet_support)
+            .and_then(|window| window.work_done_progress)
             .unwrap_or(false);
-
-        info!("Client snippet support: {}", snippet_support);
+        self.progress_service.set_enabled(work_done_progress);
+
+        // Agree on how Position.character is counted, UTF-16 unless the client offers UTF-8
+        let position_encoding = PositionEncoding::negotiate(
+            params
+                .capabilities
+                .general
+                .as_ref()
+                .and_then(|general| general.position_encodings.as_deref()),
+        );
+        info!("Negotiated position encoding: {:?}", position_encoding);
+        self.document_service
+            .set_position_encoding(position_encoding);
 
         if !snippet_support {
             warn!("Client does not support snippets");
@@ -479,26 +1888,69 @@ impl LanguageServer for BkmrLspBackend {
 
         let result = InitializeResult {
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                position_encoding: Some(position_encoding.to_kind()),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::INCREMENTAL),
+                        save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                            include_text: Some(true),
+                        })),
+                        ..Default::default()
+                    },
                 )),
                 completion_provider: Some(CompletionOptions {
-                    resolve_provider: Some(false),
-                    trigger_characters: None, // No automatic triggers - manual completion only
+                    resolve_provider: Some(true),
+                    // Manual completion only, unless a trigger character is configured
+                    trigger_characters: state.config.trigger_char.map(|c| vec![c.to_string()]),
                     all_commit_characters: None,
                     work_done_progress_options: WorkDoneProgressOptions::default(),
                     completion_item: None,
                 }),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        code_action_kinds: Some(vec![
+                            CodeActionKind::SOURCE,
+                            CodeActionKind::EMPTY,
+                        ]),
+                        work_done_progress_options: WorkDoneProgressOptions::default(),
+                        resolve_provider: None,
+                    },
+                )),
+                workspace: Some(WorkspaceServerCapabilities {
+                    workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                        supported: Some(true),
+                        change_notifications: Some(OneOf::Left(true)),
+                    }),
+                    file_operations: None,
+                }),
                 execute_command_provider: Some(ExecuteCommandOptions {
-                    commands: vec!["bkmr.insertFilepathComment".to_string()],
+                    commands: state.command_service.command_names(),
                     work_done_progress_options: WorkDoneProgressOptions::default(),
                 }),
+                // Moved to the top level by BkmrLspService, lsp-types lacks the field
+                experimental: state
+                    .config
+                    .inline_completions
+                    .then(|| serde_json::json!({ "inlineCompletionProvider": {} })),
                 ..Default::default()
             },
             ..Default::default()
         };
 
-        info!("Initialize complete - manual completion only (no trigger characters)");
+        match state.config.trigger_char {
+            Some(trigger) => info!(
+                "Initialize complete - completion triggered by '{}'",
+                trigger
+            ),
+            None => info!("Initialize complete - manual completion only (no trigger characters)"),
+        }
         Ok(result)
     }
 
@@ -506,34 +1958,153 @@ impl LanguageServer for BkmrLspBackend {
     async fn initialized(&self, _: InitializedParams) {
         info!("Server initialized successfully");
 
+        // Repository notices like paused searches are shown in the client's log
+        let receiver = sync::lock(&self.notice_receiver, "notice receiver").take();
+        if let Some(mut receiver) = receiver {
+            let client = self.client.clone();
+            self.spawn(async move {
+                while let Some(notice) = receiver.recv().await {
+                    client.log_message(MessageType::WARNING, notice).await;
+                }
+            });
+        }
+        let receiver = sync::lock(&self.trace_receiver, "trace receiver").take();
+        if let Some(mut receiver) = receiver {
+            let client = self.client.clone();
+            self.spawn(async move {
+                while let Some(trace) = receiver.recv().await {
+                    client
+                        .send_notification::<notification::LogTrace>(trace)
+                        .await;
+                }
+            });
+        }
+
+        if self.watch_files.load(std::sync::atomic::Ordering::Relaxed) {
+            let registration = Registration {
+                id: "bkmr-lsp-ignore-files".to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+                register_options: serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
+                    watchers: vec![FileSystemWatcher {
+                        glob_pattern: GlobPattern::String(format!("**/{}", IGNORE_FILE_NAME)),
+                        kind: None,
+                    }],
+                })
+                .ok(),
+            };
+            if let Err(e) = self.client.register_capability(vec![registration]).await {
+                warn!("Failed to watch {} files: {}", IGNORE_FILE_NAME, e);
+            }
+            self.watch_snippet_sources().await;
+        } else {
+            debug!("Client cannot register file watchers, changes to snippet sources go unnoticed");
+        }
+
         self.client
             .log_message(MessageType::INFO, "bkmr-lsp server ready")
             .await;
     }
 
     #[instrument(skip(self))]
+    /// Stop background work and bkmr processes, later requests fail with InvalidRequest
+    ///
+    /// The exit notification that follows ends the server with exit code 0.
     async fn shutdown(&self) -> LspResult<()> {
+        let state = self.state();
         info!("Shutdown request received");
+        self.lifecycle.record_shutdown();
         self.client
             .log_message(MessageType::INFO, "Shutting down bkmr-lsp server")
             .await;
+        if let Some(prefetch_service) = &state.prefetch_service {
+            prefetch_service.shutdown();
+        }
+        sync::lock(&self.tasks, "background tasks").abort_all();
+        state.repository.shutdown().await;
         Ok(())
     }
 
+    #[instrument(skip(self, params))]
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        let state = self.state();
+        // Folder settings files choose repositories, those of removed folders go with them
+        let removed_settings = params.event.removed.iter().any(|folder| {
+            folder.uri.to_file_path().is_ok_and(|path| {
+                state
+                    .config
+                    .folder_repositories
+                    .iter()
+                    .any(|repository| repository.from_file && repository.path() == path)
+            })
+        });
+        let (added, folders) = {
+            let mut folders = sync::write(&self.workspace_folders, "workspace folders");
+            folders.retain(|uri| !params.event.removed.iter().any(|folder| folder.uri == *uri));
+            let added: Vec<Url> = params
+                .event
+                .added
+                .into_iter()
+                .map(|folder| folder.uri)
+                .filter(|uri| !folders.contains(uri))
+                .collect();
+            folders.extend(added.iter().cloned());
+            (added, folders.clone())
+        };
+        self.ignore_service.load(&added).await;
+        debug!("Workspace folders changed: {:?}", folders);
+
+        if removed_settings || FolderRepository::any_file(&added).await {
+            info!("Folder repositories changed with the workspace folders, rebuilding services");
+            self.replace_state(
+                state.config.clone(),
+                state.language_registry.definitions().clone(),
+            )
+            .await;
+            if let Err(e) = self.state().repository.start().await {
+                warn!("Failed to start snippet repository: {:#}", e);
+            }
+        } else {
+            state.completion_service.set_workspace_folders(folders);
+        }
+    }
+
+    #[instrument(skip(self, params))]
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let sources = self.state().config.snippet_sources();
+        let mut snippets_changed = false;
+        for change in params.changes {
+            if self.ignore_service.reload(&change.uri).await.is_some() {
+                debug!("Reloaded {}", change.uri);
+            }
+            snippets_changed |= sources.contains(&change.uri);
+        }
+        if snippets_changed {
+            self.refresh_snippets().await;
+        }
+    }
+
     #[instrument(skip(self, params))]
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
         let uri = params.text_document.uri.to_string();
         let content = params.text_document.text;
         let language_id = params.text_document.language_id;
+        let version = params.text_document.version;
 
         debug!("Document opened: {} (language: {})", uri, language_id);
 
-        if let Ok(mut cache) = self.document_cache.write() {
-            cache.insert(uri.clone(), content);
+        if !self
+            .completion_disabled(&params.text_document.uri, Some(&language_id))
+            .await
+        {
+            self.prefetch(&params.text_document.uri, &language_id);
         }
 
-        if let Ok(mut lang_cache) = self.language_cache.write() {
-            lang_cache.insert(uri, language_id);
+        if let Err(e) = self
+            .document_service
+            .open_versioned_document(uri, language_id, content, version)
+            .await
+        {
+            error!("Failed to open document: {}", e);
         }
     }
 
@@ -543,39 +2114,49 @@ impl LanguageServer for BkmrLspBackend {
 
         debug!("Document changed: {}", uri);
 
-        if let Ok(mut cache) = self.document_cache.write() {
-            for change in params.content_changes {
-                if let Some(content) = cache.get_mut(&uri) {
-                    // For FULL sync, replace entire content
-                    if change.range.is_none() {
-                        *content = change.text;
-                    } else {
-                        // For incremental sync, would need more complex logic
-                        // For now, just replace entirely
-                        *content = change.text;
-                    }
-                }
-            }
+        if let Err(e) = self
+            .document_service
+            .change_document(uri, params.text_document.version, params.content_changes)
+            .await
+        {
+            warn!("Failed to apply document changes: {}", e);
         }
     }
 
     #[instrument(skip(self, params))]
-    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
         let uri = params.text_document.uri.to_string();
 
-        debug!("Document closed: {}", uri);
+        debug!(
+            "Document saved: {} (text included: {})",
+            uri,
+            params.text.is_some()
+        );
 
-        if let Ok(mut cache) = self.document_cache.write() {
-            cache.remove(&uri);
+        if let Err(e) = self.document_service.save_document(&uri, params.text).await {
+            warn!("Failed to handle document save: {}", e);
         }
+    }
+
+    #[instrument(skip(self, params))]
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri.to_string();
+
+        debug!("Document closed: {}", uri);
 
-        if let Ok(mut lang_cache) = self.language_cache.write() {
-            lang_cache.remove(&uri);
+        sync::write(&self.tag_filters, "tag filters").set(Some(&uri), None);
+        let state = self.state();
+        state.link_service.forget(&uri);
+        state.completion_service.close_document(&uri);
+        if let Err(e) = self.document_service.close_document(uri).await {
+            error!("Failed to close document: {}", e);
         }
     }
 
     #[instrument(skip(self, params))]
     async fn completion(&self, params: CompletionParams) -> LspResult<Option<CompletionResponse>> {
+        let start = std::time::Instant::now();
+        let state = self.state();
         let uri = &params.text_document_position.text_document.uri;
         let position = params.text_document_position.position;
 
@@ -594,6 +2175,13 @@ impl LanguageServer for BkmrLspBackend {
                 CompletionTriggerKind::TRIGGER_FOR_INCOMPLETE_COMPLETIONS => {
                     debug!("Completion for incomplete results - proceeding");
                 }
+                CompletionTriggerKind::TRIGGER_CHARACTER
+                    if state.config.trigger_char.is_some_and(|trigger| {
+                        context.trigger_character.as_deref() == Some(trigger.to_string().as_str())
+                    }) =>
+                {
+                    debug!("Trigger character completion - proceeding");
+                }
                 _ => {
                     debug!("Ignoring automatic trigger - only manual completion supported");
                     return Ok(Some(CompletionResponse::Array(vec![])));
@@ -605,31 +2193,90 @@ impl LanguageServer for BkmrLspBackend {
         }
 
         // Extract the query after trigger and get replacement range
-        let query_info = self.extract_snippet_query(uri, position);
-        debug!("Extracted snippet query info: {:?}", query_info);
-
-        // Get the language ID for filetype-based filtering
-        let language_id = self.get_language_id(uri);
-        debug!("Document language ID: {:?}", language_id);
-
-        // Create completion context for the service
-        let mut context = CompletionContext::new(
-            uri.clone(),
-            position,
-            language_id
-        );
-        
-        // Add query information if extracted
-        if let Some((query, range)) = query_info {
-            debug!("Query: '{}', Range: {:?}", query, range);
-            context = context.with_query(crate::domain::CompletionQuery::new(query, range));
-        } else {
-            debug!("No query extracted, using empty query");
+        let context = match self
+            .metrics
+            .time(
+                Stage::QueryExtraction,
+                self.document_service
+                    .extract_completion_context(uri, position),
+            )
+            .await
+        {
+            Ok(context) => context,
+            Err(e) => {
+                debug!("No document context available, using empty query: {}", e);
+                let language_id = self.document_service.get_language_id(uri.as_str()).await;
+                CompletionContext::new(uri.clone(), position, language_id)
+            }
+        };
+        if self
+            .completion_disabled(uri, context.language_id.as_deref())
+            .await
+        {
+            return Ok(Some(CompletionResponse::Array(vec![])));
+        }
+        // The tags line of a markdown frontmatter completes tags instead of snippets
+        if let Some((prefix, range)) = self
+            .document_service
+            .get_frontmatter_tag_query(uri.as_str(), position)
+            .await
+        {
+            match state
+                .completion_service
+                .tag_completions(&prefix, range)
+                .await
+            {
+                Ok(items) => {
+                    info!("Returning {} tag completions for {:?}", items.len(), prefix);
+                    return Ok(Some(CompletionResponse::List(CompletionList {
+                        is_incomplete: false,
+                        items,
+                    })));
+                }
+                Err(e) => debug!("Listing tags failed, completing snippets instead: {:#}", e),
+            }
         }
+        let context = match self.tag_filter(uri.as_str()) {
+            Some(tag) => context.with_tag_filter(tag),
+            None => context,
+        };
+        debug!(
+            "Document language ID: {:?}, query: {:?}, tag filter: {:?}",
+            context.language_id, context.query, context.tag_filter
+        );
+
+        // Re-triggered incomplete completions can narrow the previous result
+        let narrow = params.context.as_ref().is_some_and(|context| {
+            context.trigger_kind == CompletionTriggerKind::TRIGGER_FOR_INCOMPLETE_COMPLETIONS
+        });
+
+        // Cache counters before the search, for the verbose trace and the refresh below
+        let cache_before = state.repository.stats().cache;
 
         // Use CompletionService to get completion items
-        match self.completion_service.get_completions(&context).await {
-            Ok(completion_items) => {
+        let completion_list = self.progress_service.run(
+            "Querying bkmr…",
+            params.work_done_progress_params.work_done_token.clone(),
+            state
+                .completion_service
+                .get_latest_completion_list(&context, narrow),
+        );
+        let completion_list = completion_list.await;
+        self.metrics.finish_completion(start.elapsed());
+        match completion_list {
+            Ok(CompletionList {
+                is_incomplete,
+                items: completion_items,
+            }) => {
+                if self.trace.enabled() {
+                    self.trace_completion(
+                        &state,
+                        &context,
+                        completion_items.len(),
+                        start,
+                        cache_before,
+                    );
+                }
                 info!(
                     "Returning {} completion items for query: {:?}",
                     completion_items.len(),
@@ -647,20 +2294,154 @@ impl LanguageServer for BkmrLspBackend {
                     debug!("... and {} more items", completion_items.len() - 3);
                 }
 
+                // Empty-query results served by the warm cache, refresh it for next time
+                let cache_hit = cache_before.is_some_and(|before| {
+                    state
+                        .repository
+                        .stats()
+                        .cache
+                        .is_some_and(|after| after.hits > before.hits)
+                });
+                if cache_hit
+                    && context.get_query_text().unwrap_or("").is_empty()
+                    && let Some(language_id) = &context.language_id
+                {
+                    self.prefetch(&context.uri, language_id);
+                }
+
                 Ok(Some(CompletionResponse::List(CompletionList {
-                    is_incomplete: true,
+                    is_incomplete,
                     items: completion_items,
                 })))
             }
             Err(e) => {
                 error!("Failed to get completions: {}", e);
+                match self
+                    .report_failure(format!("Failed to get completions: {:#}", e))
+                    .await
+                {
+                    Some(error) => Err(error),
+                    None => Ok(Some(CompletionResponse::Array(vec![]))),
+                }
+            }
+        }
+    }
+
+    #[instrument(skip(self, params))]
+    async fn hover(&self, params: HoverParams) -> LspResult<Option<Hover>> {
+        let state = self.state();
+        let uri = &params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+        debug!(
+            "Hover request for {}:{},{}",
+            uri, position.line, position.character
+        );
+
+        let Some((word, range)) = self
+            .document_service
+            .get_word_at_position(uri.as_str(), position)
+            .await
+        else {
+            return Ok(None);
+        };
+        let language_id = self.document_service.get_language_id(uri.as_str()).await;
+
+        match state
+            .hover_service
+            .hover_for_word(&word, range, language_id.as_deref(), uri)
+            .await
+        {
+            Ok(hover) => Ok(hover),
+            Err(e) => {
+                error!("Failed to get hover: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    #[instrument(skip(self, params))]
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> LspResult<Option<Vec<SymbolInformation>>> {
+        let state = self.state();
+        debug!("Workspace symbol request: {:?}", params.query);
+
+        match state.symbol_service.workspace_symbols(&params.query).await {
+            Ok(symbols) => Ok(Some(symbols)),
+            Err(e) => {
+                error!("Failed to search workspace symbols: {:#}", e);
                 self.client
                     .log_message(
                         MessageType::ERROR,
-                        &format!("Failed to get completions: {}", e),
+                        &format!("Failed to search snippets: {:#}", e),
                     )
                     .await;
-                Ok(Some(CompletionResponse::Array(vec![])))
+                Ok(Some(vec![]))
+            }
+        }
+    }
+
+    #[instrument(skip(self, params))]
+    async fn document_link(
+        &self,
+        params: DocumentLinkParams,
+    ) -> LspResult<Option<Vec<DocumentLink>>> {
+        let state = self.state();
+        debug!("Document link request for {}", params.text_document.uri);
+
+        Ok(Some(
+            state
+                .link_service
+                .document_links(&params.text_document.uri)
+                .await,
+        ))
+    }
+
+    #[instrument(skip(self, params))]
+    async fn document_link_resolve(&self, params: DocumentLink) -> LspResult<DocumentLink> {
+        let state = self.state();
+        match state.link_service.resolve(params.clone()).await {
+            Ok(link) => Ok(link),
+            Err(e) => {
+                warn!("Failed to resolve document link: {:#}", e);
+                Ok(params)
+            }
+        }
+    }
+
+    #[instrument(skip(self, params))]
+    async fn code_action(&self, params: CodeActionParams) -> LspResult<Option<CodeActionResponse>> {
+        let state = self.state();
+        debug!(
+            "Code action request for {} at {:?}",
+            params.text_document.uri, params.range
+        );
+
+        let actions = state
+            .command_service
+            .code_actions(
+                &params.text_document.uri,
+                params.range,
+                params.context.only.as_deref(),
+            )
+            .await;
+        Ok(Some(actions))
+    }
+
+    #[instrument(skip(self, item), fields(label = %item.label))]
+    async fn completion_resolve(&self, item: CompletionItem) -> LspResult<CompletionItem> {
+        let state = self.state();
+        debug!("Completion resolve request");
+
+        match state
+            .completion_service
+            .resolve_completion_item(item.clone())
+        {
+            Ok(resolved) => Ok(resolved),
+            Err(e) => {
+                error!("Failed to resolve completion item: {}", e);
+                Ok(item)
             }
         }
     }
@@ -670,108 +2451,398 @@ impl LanguageServer for BkmrLspBackend {
         &self,
         params: ExecuteCommandParams,
     ) -> LspResult<Option<serde_json::Value>> {
+        let state = self.state();
         debug!("Execute command request: {}", params.command);
 
-        match params.command.as_str() {
-            "bkmr.insertFilepathComment" => {
-                // Extract file URI from arguments
-                if !params.arguments.is_empty() {
-                    if let Some(first_arg) = params.arguments.first() {
-                        if let Ok(uri_str) = serde_json::from_value::<String>(first_arg.clone()) {
-                            match self.insert_filepath_comment(&uri_str).await {
-                                Ok(edits) => {
-                                    // Apply the text edits to the document
-                                    let workspace_edit = WorkspaceEdit {
-                                        changes: Some({
-                                            let mut changes = std::collections::HashMap::new();
-                                            if let Ok(uri) = Url::parse(&uri_str) {
-                                                changes.insert(uri, edits);
-                                            }
-                                            changes
-                                        }),
-                                        document_changes: None,
-                                        change_annotations: None,
-                                    };
-
-                                    // Request client to apply the edit
-                                    match self.client.apply_edit(workspace_edit).await {
-                                        Ok(response) => {
-                                            if response.applied {
-                                                info!("Successfully inserted filepath comment");
-                                                self.client
-                                                    .log_message(
-                                                        MessageType::INFO,
-                                                        "Filepath comment inserted successfully",
-                                                    )
-                                                    .await;
-                                            } else {
-                                                warn!("Client rejected the edit");
-                                                self.client
-                                                    .log_message(
-                                                        MessageType::WARNING,
-                                                        "Failed to apply filepath comment edit",
-                                                    )
-                                                    .await;
-                                            }
-                                        }
-                                        Err(e) => {
-                                            error!("Failed to apply edit: {}", e);
-                                            self.client
-                                                .log_message(
-                                                    MessageType::ERROR,
-                                                    &format!("Failed to apply edit: {}", e),
-                                                )
-                                                .await;
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Failed to create filepath comment: {}", e);
+        let command = match CommandService::parse_command(&params.command, &params.arguments) {
+            Ok(Some(command)) => command,
+            Ok(None) => {
+                error!("Unknown command: {}", params.command);
+                return self
+                    .report_failure(format!("Unknown command: {}", params.command))
+                    .await
+                    .map_or(Ok(None), Err);
+            }
+            Err(e) => return Err(self.invalid_argument(&params.command, e).await),
+        };
+        if let Err(e) = state.command_service.validate_positions(&command).await {
+            return Err(self.invalid_argument(&params.command, e).await);
+        }
+
+        match command {
+            BkmrCommand::InsertFilepathComment { uri, .. } => {
+                let uri_str = uri.as_str();
+                let workspace_folders =
+                    sync::read(&self.workspace_folders, "workspace folders").clone();
+                match state
+                    .command_service
+                    .insert_filepath_comment(uri_str, &workspace_folders)
+                    .await
+                {
+                    Ok(FilepathComment::AlreadyPresent) => {
+                        info!("Filepath comment already present in {}", uri_str);
+                        self.client
+                            .log_message(MessageType::INFO, "Filepath comment already present")
+                            .await;
+                        return Ok(Some(serde_json::json!({ "alreadyPresent": true })));
+                    }
+                    Ok(FilepathComment::NotSaved) => {
+                        info!("Not inserting a filepath comment into unsaved {}", uri_str);
+                        self.client
+                            .log_message(MessageType::INFO, "Document is not a saved file")
+                            .await;
+                        return Ok(Some(serde_json::json!({ "notSaved": true })));
+                    }
+                    Ok(FilepathComment::Insert(workspace_edit)) => {
+                        // Request client to apply the edit
+                        match self.client.apply_edit(workspace_edit).await {
+                            Ok(response) => {
+                                if response.applied {
+                                    info!("Successfully inserted filepath comment");
+                                    self.client
+                                        .log_message(
+                                            MessageType::INFO,
+                                            "Filepath comment inserted successfully",
+                                        )
+                                        .await;
+                                } else {
+                                    warn!("Client rejected the edit");
                                     self.client
                                         .log_message(
-                                            MessageType::ERROR,
-                                            &format!("Failed to create filepath comment: {}", e),
+                                            MessageType::WARNING,
+                                            "Failed to apply filepath comment edit",
                                         )
                                         .await;
                                 }
+                                Ok(None)
+                            }
+                            Err(e) => {
+                                error!("Failed to apply edit: {}", e);
+                                self.report_failure(format!("Failed to apply edit: {}", e))
+                                    .await
+                                    .map_or(Ok(None), Err)
                             }
-                        } else {
-                            error!("Invalid argument format for insertFilepathComment");
                         }
-                    } else {
-                        error!("No arguments provided for insertFilepathComment command");
                     }
-                } else {
-                    error!("No arguments provided for insertFilepathComment command");
+                    Err(e) => {
+                        error!("Failed to create filepath comment: {}", e);
+                        self.report_failure(format!("Failed to create filepath comment: {}", e))
+                            .await
+                            .map_or(Ok(None), Err)
+                    }
                 }
             }
-            _ => {
-                error!("Unknown command: {}", params.command);
-                self.client
-                    .log_message(
-                        MessageType::ERROR,
-                        &format!("Unknown command: {}", params.command),
-                    )
-                    .await;
+            BkmrCommand::OpenSnippet { id, folder } => match state
+                .command_service
+                .open_snippet(id, folder.as_deref())
+                .await
+            {
+                Ok(id) => {
+                    info!("Opened snippet {} in bkmr", id);
+                    self.client
+                        .log_message(MessageType::INFO, &format!("Opened snippet {} in bkmr", id))
+                        .await;
+                    Ok(Some(serde_json::json!(id)))
+                }
+                Err(e) => {
+                    error!("Failed to open snippet {}: {}", id, e);
+                    let error = self.report_failure(e.to_string()).await;
+                    Err(error.unwrap_or_else(|| internal_error(e.to_string())))
+                }
+            },
+            BkmrCommand::AddSnippet {
+                uri,
+                range,
+                title,
+                tags,
+            } => match self
+                .progress_service
+                .run(
+                    "Adding snippet to bkmr…",
+                    params.work_done_progress_params.work_done_token.clone(),
+                    state.command_service.add_snippet(&uri, range, &title, tags),
+                )
+                .await
+            {
+                Ok(id) => {
+                    info!("Added snippet {} '{}' to bkmr", id, title);
+                    self.client
+                        .log_message(
+                            MessageType::INFO,
+                            &format!("Added snippet {} '{}' to bkmr", id, title),
+                        )
+                        .await;
+                    Ok(Some(serde_json::json!(id)))
+                }
+                Err(e) => {
+                    error!("Failed to add snippet '{}': {:#}", title, e);
+                    let error = self
+                        .report_failure(format!("Failed to add snippet '{}': {:#}", title, e))
+                        .await;
+                    Err(error.unwrap_or_else(|| internal_error(format!("{:#}", e))))
+                }
+            },
+            BkmrCommand::InsertSnippetById {
+                id,
+                folder,
+                uri,
+                targets,
+            } => {
+                let applied = self
+                    .insert_snippet_by_id(id, folder.as_deref(), &uri, &targets)
+                    .await?;
+                Ok(Some(serde_json::json!({ "applied": applied })))
+            }
+            BkmrCommand::InsertRaw {
+                id,
+                folder,
+                uri,
+                position,
+            } => {
+                let applied = self
+                    .insert_raw(id, folder.as_deref(), &uri, position)
+                    .await?;
+                Ok(Some(serde_json::json!({ "applied": applied })))
+            }
+            BkmrCommand::SetTagFilter { tag, uri } => {
+                let mut tag_filters = sync::write(&self.tag_filters, "tag filters");
+                tag_filters.set(uri.as_deref(), tag);
+                let active = match &uri {
+                    Some(uri) => tag_filters.for_document(uri),
+                    None => tag_filters.global.as_deref(),
+                };
+                info!(
+                    "Tag filter for {}: {:?}",
+                    uri.as_deref().unwrap_or("all documents"),
+                    active
+                );
+                Ok(Some(serde_json::json!({ "tagFilter": active })))
+            }
+            BkmrCommand::RecordUsage { id, folder } => {
+                // Runs in the background so that inserting a completion never waits on bkmr
+                if let Some(task) = state.command_service.record_usage(id, folder.as_deref()) {
+                    self.spawn(task);
+                }
+                Ok(None)
             }
+            BkmrCommand::ClearCache => {
+                let evicted = state.command_service.clear_cache(&state.completion_service);
+                info!("Cleared {} cached results", evicted);
+                Ok(Some(serde_json::json!({ "evicted": evicted })))
+            }
+            BkmrCommand::ReloadConfig => {
+                let summary = self.reload_config().await;
+                serde_json::to_value(summary)
+                    .map(Some)
+                    .map_err(|e| internal_error(format!("Failed to serialize summary: {}", e)))
+            }
+            BkmrCommand::ExportSnippets {
+                language,
+                format,
+                path,
+                overwrite,
+            } => {
+                let workspace_folders =
+                    sync::read(&self.workspace_folders, "workspace folders").clone();
+                match state
+                    .command_service
+                    .export_snippets(&language, format, &path, overwrite, &workspace_folders)
+                    .await
+                {
+                    Ok(summary) => {
+                        info!(
+                            "Exported {} {} snippets to {}",
+                            summary.count, language, summary.path
+                        );
+                        serde_json::to_value(summary).map(Some).map_err(|e| {
+                            internal_error(format!("Failed to serialize summary: {}", e))
+                        })
+                    }
+                    Err(e) => {
+                        error!("Failed to export {} snippets: {:#}", language, e);
+                        let error = self
+                            .report_failure(format!(
+                                "Failed to export {} snippets: {:#}",
+                                language, e
+                            ))
+                            .await;
+                        Err(error.unwrap_or_else(|| internal_error(format!("{:#}", e))))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Build the LSP service with the custom bkmr requests registered
+pub fn lsp_service(
+    init: impl FnOnce(Client) -> BkmrLspBackend,
+) -> (BkmrLspService, tower_lsp::ClientSocket) {
+    let (inner, socket) = tower_lsp::LspService::build(init)
+        .custom_method(STATUS_METHOD, BkmrLspBackend::status)
+        .custom_method(LIST_TAGS_METHOD, BkmrLspBackend::list_tags)
+        .custom_method(INLINE_COMPLETION_METHOD, BkmrLspBackend::inline_completion)
+        .custom_method(
+            <notification::SetTrace as notification::Notification>::METHOD,
+            BkmrLspBackend::set_trace,
+        )
+        .finish();
+    let lifecycle = inner.inner().lifecycle();
+    (BkmrLspService { inner, lifecycle }, socket)
+}
+
+/// LSP service of the backend, filling in protocol parts lsp-types 0.94 lacks
+///
+/// CompletionList.itemDefaults are hoisted out of serialized completion
+/// responses for clients that advertised support for them, and capabilities
+/// like inlineCompletionProvider are moved out of `experimental`.
+pub struct BkmrLspService {
+    inner: tower_lsp::LspService<BkmrLspBackend>,
+    lifecycle: std::sync::Arc<Lifecycle>,
+}
+
+impl BkmrLspService {
+    /// Shutdown and exit received, still readable after the server stopped serving
+    pub fn lifecycle(&self) -> std::sync::Arc<Lifecycle> {
+        self.lifecycle.clone()
+    }
+}
+
+impl std::fmt::Debug for BkmrLspService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BkmrLspService")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl tower_service::Service<jsonrpc::Request> for BkmrLspService {
+    type Response = Option<jsonrpc::Response>;
+    type Error = tower_lsp::ExitedError;
+    type Future = std::pin::Pin<
+        Box<
+            dyn std::future::Future<Output = std::result::Result<Self::Response, Self::Error>>
+                + Send,
+        >,
+    >;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::result::Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: jsonrpc::Request) -> Self::Future {
+        let support = (request.method() == "textDocument/completion")
+            .then(|| self.inner.inner().client_completion_support())
+            .filter(|support| !support.item_defaults.is_empty());
+        let initialize = request.method() == "initialize";
+        if request.method() == "exit" {
+            info!(
+                "Exit notification received, exit code {}",
+                self.lifecycle.exit_code()
+            );
+            self.lifecycle.record_exit();
         }
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = response.await?;
+            Ok(match (response, support) {
+                (Some(response), Some(support)) => {
+                    let (id, body) = response.into_parts();
+                    let body = body.map(|mut list| {
+                        hoist_item_defaults(&mut list, &support);
+                        list
+                    });
+                    Some(jsonrpc::Response::from_parts(id, body))
+                }
+                (Some(response), None) if initialize => {
+                    let (id, body) = response.into_parts();
+                    let body = body.map(|mut result| {
+                        promote_experimental_capabilities(&mut result);
+                        result
+                    });
+                    Some(jsonrpc::Response::from_parts(id, body))
+                }
+                (response, _) => response,
+            })
+        })
+    }
+}
 
-        Ok(None)
+/// Move capabilities from `capabilities.experimental` of an InitializeResult to the top level
+fn promote_experimental_capabilities(result: &mut serde_json::Value) {
+    let Some(capabilities) = result
+        .get_mut("capabilities")
+        .and_then(serde_json::Value::as_object_mut)
+    else {
+        return;
+    };
+    let Some(serde_json::Value::Object(mut experimental)) = capabilities.remove("experimental")
+    else {
+        return;
+    };
+    for name in PROMOTED_CAPABILITIES {
+        if let Some(capability) = experimental.remove(name) {
+            capabilities.insert(name.to_string(), capability);
+        }
+    }
+    if !experimental.is_empty() {
+        capabilities.insert(
+            "experimental".to_string(),
+            serde_json::Value::Object(experimental),
+        );
     }
 }
 
 /// Start a bkmr-lsp server with given input/output streams
 /// This function is used by tests to spawn a real LSP server for testing
-pub async fn start_server<I, O>(read: I, write: O)
+pub async fn start_server<I, O>(read: I, write: O) -> i32
+where
+    I: tokio::io::AsyncRead + Unpin,
+    O: tokio::io::AsyncWrite,
+{
+    crate::transport::serve_connection(read, write, BkmrConfig::default()).await
+}
+
+/// Serve until the exit notification or the end of input, returning the process exit code
+///
+/// tower-lsp only stops reading when the next message arrives after exit, so
+/// the exit notification itself ends serving here.
+pub async fn serve<I, O>(
+    read: I,
+    write: O,
+    service: BkmrLspService,
+    socket: tower_lsp::ClientSocket,
+) -> i32
 where
     I: tokio::io::AsyncRead + Unpin,
     O: tokio::io::AsyncWrite,
 {
-    use tower_lsp::{LspService, Server};
+    let lifecycle = service.lifecycle();
+    tokio::select! {
+        _ = tower_lsp::Server::new(read, write, socket).serve(service) => {}
+        _ = lifecycle.exited() => {}
+    }
+    lifecycle.exit_code()
+}
 
-    // Create the LSP service
-    let (service, socket) = LspService::new(BkmrLspBackend::new);
+/// Build a JSON-RPC internal error carrying the given message
+fn internal_error(message: String) -> jsonrpc::Error {
+    jsonrpc::Error {
+        code: jsonrpc::ErrorCode::InternalError,
+        message: message.into(),
+        data: None,
+    }
+}
 
-    // Start the server with the provided streams
-    Server::new(read, write, socket).serve(service).await;
+/// Build a JSON-RPC RequestFailed error carrying the given message
+fn request_failed(message: String) -> jsonrpc::Error {
+    jsonrpc::Error {
+        code: jsonrpc::ErrorCode::ServerError(REQUEST_FAILED),
+        message: message.into(),
+        data: None,
+    }
 }
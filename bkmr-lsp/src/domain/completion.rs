@@ -1,15 +1,199 @@
-use tower_lsp::lsp_types::{Position, Range, Url};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use tower_lsp::lsp_types::{CompletionItemKind, Position, Range, Url};
+
+use crate::domain::{
+    CompletionTier, DocumentImports, IndentUnit, LanguageMap, LanguageRegistry, fts_term,
+};
+
+/// Tag marking bkmr bookmarks as snippets unless configured otherwise
+pub const DEFAULT_SNIPPET_TAG: &str = "_snip_";
+
+/// Default snippet marker tags, see [`DEFAULT_SNIPPET_TAG`]
+pub fn default_snippet_tags() -> Vec<String> {
+    vec![DEFAULT_SNIPPET_TAG.to_string()]
+}
+
+/// Tag of snippets offered only with nothing but whitespace before the query
+pub const LINE_START_TAG: &str = "_line_start_";
+/// Tag of snippets offered only after other text on the line
+pub const INLINE_TAG: &str = "_inline_";
+
+/// Completion item kind reported for snippets, clients show it as an icon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemKind {
+    Snippet,
+    Text,
+    Reference,
+    Module,
+    Keyword,
+    Function,
+    Value,
+    Constant,
+    File,
+    Event,
+}
+
+impl ItemKind {
+    pub fn completion_item_kind(self) -> CompletionItemKind {
+        match self {
+            Self::Snippet => CompletionItemKind::SNIPPET,
+            Self::Text => CompletionItemKind::TEXT,
+            Self::Reference => CompletionItemKind::REFERENCE,
+            Self::Module => CompletionItemKind::MODULE,
+            Self::Keyword => CompletionItemKind::KEYWORD,
+            Self::Function => CompletionItemKind::FUNCTION,
+            Self::Value => CompletionItemKind::VALUE,
+            Self::Constant => CompletionItemKind::CONSTANT,
+            Self::File => CompletionItemKind::FILE,
+            Self::Event => CompletionItemKind::EVENT,
+        }
+    }
+}
+
+impl FromStr for ItemKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "snippet" => Ok(Self::Snippet),
+            "text" => Ok(Self::Text),
+            "reference" => Ok(Self::Reference),
+            "module" => Ok(Self::Module),
+            "keyword" => Ok(Self::Keyword),
+            "function" => Ok(Self::Function),
+            "value" => Ok(Self::Value),
+            "constant" => Ok(Self::Constant),
+            "file" => Ok(Self::File),
+            "event" => Ok(Self::Event),
+            other => Err(format!(
+                "unknown item kind '{}', expected snippet, text, reference, module, keyword, function, value, constant, file or event",
+                other
+            )),
+        }
+    }
+}
+
+/// Range a plain text edit replaces when the word continues after the cursor
+///
+/// Clients supporting insert-and-replace edits get both ranges and choose
+/// themselves.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplaceMode {
+    /// Word start up to the cursor, the rest of the word stays
+    Insert,
+    /// The whole word the cursor is in
+    #[default]
+    Replace,
+}
+
+impl ReplaceMode {
+    /// Range of a plain text edit given the insert range and the whole word's range
+    pub fn edit_range(self, insert: Range, word: Option<Range>) -> Range {
+        match self {
+            Self::Insert => insert,
+            Self::Replace => word.unwrap_or(insert),
+        }
+    }
+}
+
+impl FromStr for ReplaceMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "insert" => Ok(Self::Insert),
+            "replace" => Ok(Self::Replace),
+            other => Err(format!(
+                "unknown replace mode '{}', expected insert or replace",
+                other
+            )),
+        }
+    }
+}
+
+/// How the case of typed query words is matched against titles
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaseMode {
+    /// Ignore case unless the query has an uppercase character, like ripgrep
+    #[default]
+    Smart,
+    /// Always ignore case
+    Insensitive,
+    /// Always match case
+    Sensitive,
+}
+
+impl CaseMode {
+    /// Whether a search for the query matches case
+    pub fn is_case_sensitive(self, query: &str) -> bool {
+        match self {
+            Self::Smart => query.chars().any(char::is_uppercase),
+            Self::Insensitive => false,
+            Self::Sensitive => true,
+        }
+    }
+
+    /// Query as searched for, lowercased when its case is ignored
+    pub fn search_query(self, query: &str) -> String {
+        if self.is_case_sensitive(query) {
+            query.to_string()
+        } else {
+            query.to_lowercase()
+        }
+    }
+
+    /// Whether the text contains the query under this mode
+    pub fn contains(self, text: &str, query: &str) -> bool {
+        if self.is_case_sensitive(query) {
+            text.contains(query)
+        } else {
+            text.to_lowercase().contains(&query.to_lowercase())
+        }
+    }
+}
+
+impl FromStr for CaseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "smart" => Ok(Self::Smart),
+            "insensitive" => Ok(Self::Insensitive),
+            "sensitive" => Ok(Self::Sensitive),
+            other => Err(format!(
+                "unknown case mode '{}', expected smart, insensitive or sensitive",
+                other
+            )),
+        }
+    }
+}
 
 /// Represents a completion query extracted from the document
 #[derive(Debug, Clone, PartialEq)]
 pub struct CompletionQuery {
     pub text: String,
+    /// Word start up to the cursor, replaced by inserted snippets
     pub range: Range,
+    /// End of the word the cursor is in, past `range` when the word continues after the cursor
+    pub word_end: Position,
 }
 
 impl CompletionQuery {
     pub fn new(text: String, range: Range) -> Self {
-        Self { text, range }
+        Self {
+            text,
+            range,
+            word_end: range.end,
+        }
+    }
+
+    pub fn with_word_end(mut self, word_end: Position) -> Self {
+        self.word_end = word_end.max(self.range.end);
+        self
     }
 
     pub fn is_empty(&self) -> bool {
@@ -17,6 +201,60 @@ impl CompletionQuery {
     }
 }
 
+/// The line a completion is requested on, seen from where the query starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineContext {
+    /// Full text of the line
+    pub text: String,
+    /// Whether only whitespace precedes the query
+    pub at_line_start: bool,
+    /// Character right before the query, None at the beginning of the line
+    pub preceding_char: Option<char>,
+}
+
+impl LineContext {
+    /// Describe a line whose query starts at the given byte offset
+    pub fn new(text: &str, query_start: usize) -> Self {
+        let before = text.get(..query_start).unwrap_or(text);
+        Self {
+            text: text.to_string(),
+            at_line_start: before.trim().is_empty(),
+            preceding_char: before.chars().next_back(),
+        }
+    }
+
+    /// Check a snippet's positional tags, snippets without them are allowed everywhere
+    ///
+    /// A snippet tagged both [`LINE_START_TAG`] and [`INLINE_TAG`] fits anywhere.
+    pub fn allows_tags(&self, tags: &[String]) -> bool {
+        let has = |name: &str| tags.iter().any(|tag| tag.eq_ignore_ascii_case(name));
+        let (line_start, inline) = (has(LINE_START_TAG), has(INLINE_TAG));
+        if self.at_line_start {
+            line_start || !inline
+        } else {
+            inline || !line_start
+        }
+    }
+}
+
+/// What the text at a completion position is, for adapting the inserted snippet
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum InsertionKind {
+    #[default]
+    Code,
+    /// Inside a string literal opened with `quote`
+    String { quote: char, multiline: bool },
+    /// Inside a comment whose continuation lines start with `leader`, e.g. "// "
+    Comment { leader: String },
+}
+
+impl InsertionKind {
+    pub fn is_code(&self) -> bool {
+        *self == Self::Code
+    }
+}
+
 /// Context for completion requests
 #[derive(Debug, Clone)]
 pub struct CompletionContext {
@@ -24,6 +262,20 @@ pub struct CompletionContext {
     pub position: Position,
     pub language_id: Option<String>,
     pub query: Option<CompletionQuery>,
+    /// Leading whitespace of the line the completion is inserted on
+    pub indentation: Option<String>,
+    /// Unit the document indents by, None to use the language's
+    pub indent_unit: Option<IndentUnit>,
+    /// Tag the snippets must carry, set with the bkmr.setTagFilter command
+    pub tag_filter: Option<String>,
+    /// Text of the line around the query, for snippets restricted by position
+    pub line: Option<LineContext>,
+    /// Whether the query is in code, a string or a comment
+    pub insertion_kind: InsertionKind,
+    /// Document text for adding the imports snippets declare
+    pub document_imports: Option<DocumentImports>,
+    /// Version of the document text the context was extracted from
+    pub version: Option<i32>,
 }
 
 impl CompletionContext {
@@ -33,14 +285,56 @@ impl CompletionContext {
             position,
             language_id,
             query: None,
+            indentation: None,
+            indent_unit: None,
+            tag_filter: None,
+            line: None,
+            insertion_kind: InsertionKind::Code,
+            document_imports: None,
+            version: None,
         }
     }
 
+    pub fn with_document_imports(mut self, document_imports: DocumentImports) -> Self {
+        self.document_imports = Some(document_imports);
+        self
+    }
+
+    pub fn with_insertion_kind(mut self, insertion_kind: InsertionKind) -> Self {
+        self.insertion_kind = insertion_kind;
+        self
+    }
+
+    pub fn with_line(mut self, line: LineContext) -> Self {
+        self.line = Some(line);
+        self
+    }
+
+    pub fn with_tag_filter(mut self, tag: String) -> Self {
+        self.tag_filter = Some(tag);
+        self
+    }
+
+    pub fn with_indentation(mut self, indentation: String) -> Self {
+        self.indentation = Some(indentation);
+        self
+    }
+
+    pub fn with_indent_unit(mut self, indent_unit: IndentUnit) -> Self {
+        self.indent_unit = Some(indent_unit);
+        self
+    }
+
     pub fn with_query(mut self, query: CompletionQuery) -> Self {
         self.query = Some(query);
         self
     }
 
+    pub fn with_version(mut self, version: i32) -> Self {
+        self.version = Some(version);
+        self
+    }
+
     pub fn has_query(&self) -> bool {
         self.query.is_some()
     }
@@ -52,14 +346,105 @@ impl CompletionContext {
     pub fn get_replacement_range(&self) -> Option<Range> {
         self.query.as_ref().map(|q| q.range)
     }
+
+    /// Whether a snippet's positional tags allow it at the completion position
+    pub fn allows_position_tags(&self, tags: &[String]) -> bool {
+        self.line.as_ref().is_none_or(|line| line.allows_tags(tags))
+    }
+
+    /// Word start up to the end of the word, replaced by clients choosing replace over insert
+    pub fn get_word_range(&self) -> Option<Range> {
+        self.query
+            .as_ref()
+            .map(|q| Range::new(q.range.start, q.word_end))
+    }
+}
+
+/// Payload attached to completion items so they can be resolved later
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionItemData {
+    pub id: i32,
+    pub language_id: Option<String>,
+    pub uri: Url,
+    pub range: Option<Range>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indentation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indent_unit: Option<IndentUnit>,
+    /// Replace range of an InsertReplaceEdit, when the word continues after the cursor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub word_range: Option<Range>,
+    #[serde(default, skip_serializing_if = "InsertionKind::is_code")]
+    pub insertion_kind: InsertionKind,
+    /// Whether bkmr interpolated the content, client plugins offer a raw re-insert when set
+    #[serde(default)]
+    pub interpolated: bool,
+    /// Group of the item for client-side grouping, see [`CompletionTier`]
+    #[serde(default)]
+    pub tier: CompletionTier,
+    /// Folder whose repository the snippet came from, see [`SnippetFilter::folder`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+}
+
+impl CompletionItemData {
+    pub fn new(id: i32, context: &CompletionContext) -> Self {
+        Self {
+            id,
+            language_id: context.language_id.clone(),
+            uri: context.uri.clone(),
+            range: context.get_replacement_range(),
+            indentation: context.indentation.clone(),
+            indent_unit: context.indent_unit,
+            word_range: context
+                .get_word_range()
+                .filter(|range| Some(*range) != context.get_replacement_range()),
+            insertion_kind: context.insertion_kind.clone(),
+            interpolated: false,
+            tier: CompletionTier::default(),
+            folder: None,
+        }
+    }
+
+    pub fn with_interpolated(mut self, interpolated: bool) -> Self {
+        self.interpolated = interpolated;
+        self
+    }
+
+    pub fn with_tier(mut self, tier: CompletionTier) -> Self {
+        self.tier = tier;
+        self
+    }
+
+    pub fn with_folder(mut self, folder: Option<String>) -> Self {
+        self.folder = folder;
+        self
+    }
 }
 
 /// Configuration for snippet filtering
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct SnippetFilter {
     pub language_id: Option<String>,
     pub query_prefix: Option<String>,
     pub max_results: usize,
+    /// Only return snippets whose title equals this, ignoring case
+    pub title_exact: Option<String>,
+    /// Never return snippets carrying any of these tags, lowercase
+    pub excluded_tags: Vec<String>,
+    /// Only return snippets carrying all of these tags, lowercase
+    pub required_tags: Vec<String>,
+    /// Search bookmarks, entries without a snippet tag, instead of snippets
+    pub bookmarks: bool,
+    /// Also return universal snippets besides the language's own
+    pub universal: bool,
+    /// Folder whose repository is searched, see [`FolderRepository`], the default one if None
+    ///
+    /// [`FolderRepository`]: crate::domain::FolderRepository
+    pub folder: Option<String>,
+    /// How the case of the query prefix is matched
+    pub case_mode: CaseMode,
 }
 
 impl SnippetFilter {
@@ -72,22 +457,238 @@ impl SnippetFilter {
             language_id,
             query_prefix,
             max_results,
+            title_exact: None,
+            excluded_tags: Vec::new(),
+            required_tags: Vec::new(),
+            bookmarks: false,
+            universal: true,
+            folder: None,
+            case_mode: CaseMode::default(),
         }
     }
 
+    /// Bookmarks whose title starts with the query, e.g. to complete their URLs
+    pub fn bookmarks(query: String, max_results: usize) -> Self {
+        Self {
+            bookmarks: true,
+            ..Self::new(None, Some(query), max_results)
+        }
+    }
+
+    /// Restrict results to snippets with exactly this title
+    pub fn with_title_exact(mut self, title: String) -> Self {
+        self.title_exact = Some(title);
+        self
+    }
+
+    /// Include universal snippets, or only snippets of the filter's language
+    pub fn with_universal(mut self, universal: bool) -> Self {
+        self.universal = universal;
+        self
+    }
+
+    /// Search the repository of a folder instead of the default one
+    pub fn with_folder(mut self, folder: Option<String>) -> Self {
+        self.folder = folder;
+        self
+    }
+
+    /// Match the query prefix's case as the mode says
+    pub fn with_case_mode(mut self, case_mode: CaseMode) -> Self {
+        self.case_mode = case_mode;
+        self
+    }
+
+    /// Leave out snippets with any of these tags, compared ignoring case like bkmr does
+    pub fn with_excluded_tags(mut self, tags: Vec<String>) -> Self {
+        self.excluded_tags = tags
+            .iter()
+            .map(|tag| tag.trim().to_lowercase())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        self
+    }
+
+    /// Only return snippets with all of these tags, e.g. a tag picked in the editor
+    pub fn with_required_tags(mut self, tags: Vec<String>) -> Self {
+        self.required_tags = tags
+            .iter()
+            .map(|tag| tag.trim().to_lowercase())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        self
+    }
+
+    /// Check whether none of a snippet's tags is excluded and all required ones are present
+    pub fn allows_tags(&self, tags: &[String]) -> bool {
+        let tags: Vec<String> = tags.iter().map(|tag| tag.to_lowercase()).collect();
+        !tags.iter().any(|tag| self.excluded_tags.contains(tag))
+            && self.required_tags.iter().all(|tag| tags.contains(tag))
+    }
+
+    /// FTS clause requiring the filter's tags, e.g. `tags:"sql"`
+    pub fn requirement_query(&self) -> Option<String> {
+        (!self.required_tags.is_empty()).then(|| {
+            self.required_tags
+                .iter()
+                .map(|tag| format!(r#"tags:"{}""#, tag.replace('"', "")))
+                .collect::<Vec<_>>()
+                .join(" AND ")
+        })
+    }
+
+    /// FTS clauses removing excluded snippets from a query, e.g. `NOT tags:"deprecated"`
+    ///
+    /// FTS5 NOT is binary, so the clauses must follow another query.
+    pub fn exclusion_query(&self) -> Option<String> {
+        (!self.excluded_tags.is_empty()).then(|| {
+            self.excluded_tags
+                .iter()
+                .map(|tag| format!(r#"NOT tags:"{}""#, tag.replace('"', "")))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+    }
+
+    /// FTS clauses leaving snippets out of a bookmark search, e.g. `NOT tags:"_snip_"`
+    ///
+    /// None for snippet searches, which require the snippet tags instead.
+    pub fn snippet_exclusion_query(&self, snippet_tags: &[String]) -> Option<String> {
+        let clauses: Vec<String> = snippet_tags
+            .iter()
+            .filter(|_| self.bookmarks)
+            .map(|tag| tag.trim().replace('"', ""))
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| format!(r#"NOT tags:"{}""#, tag))
+            .collect();
+        (!clauses.is_empty()).then(|| clauses.join(" "))
+    }
+
+    /// Check whether a snippet title satisfies the exact-title restriction
+    pub fn matches_title(&self, title: &str) -> bool {
+        self.title_exact
+            .as_ref()
+            .is_none_or(|exact| exact.eq_ignore_ascii_case(title))
+    }
+
     /// Build FTS query for snippets that includes both language-specific and universal snippets
-    pub fn build_fts_query(&self) -> Option<String> {
-        if let Some(ref lang) = self.language_id {
-            if !lang.trim().is_empty() {
-                // Query for either (language AND _snip_) OR (universal AND _snip_)
-                return Some(format!(
-                    r#"(tags:{} AND tags:"_snip_") OR (tags:universal AND tags:"_snip_")"#,
-                    lang
-                ));
+    ///
+    /// Snippets must carry any of the snippet tags; an empty list drops that
+    /// restriction, leaving no query at all without a language.
+    pub fn build_fts_query(
+        &self,
+        registry: &LanguageRegistry,
+        snippet_tags: &[String],
+    ) -> Option<String> {
+        self.build_fts_query_for(
+            |language_id| registry.language_tags(language_id),
+            snippet_tags,
+        )
+    }
+
+    /// Build the FTS query resolving language tags with the given language map
+    pub fn build_fts_query_with(
+        &self,
+        language_map: &LanguageMap,
+        snippet_tags: &[String],
+    ) -> Option<String> {
+        self.build_fts_query_for(|language_id| language_map.tags(language_id), snippet_tags)
+    }
+
+    /// Build the language-specific and the universal snippet query, run as two searches
+    ///
+    /// Joined with OR they give the combined query; None without a language,
+    /// for bookmark searches and without universal snippets.
+    pub fn build_split_fts_queries(
+        &self,
+        registry: &LanguageRegistry,
+        snippet_tags: &[String],
+    ) -> Option<[String; 2]> {
+        self.split_fts_queries_for(
+            |language_id| registry.language_tags(language_id),
+            snippet_tags,
+        )
+    }
+
+    fn build_fts_query_for(
+        &self,
+        language_tags: impl Fn(&str) -> Vec<String>,
+        snippet_tags: &[String],
+    ) -> Option<String> {
+        if self.bookmarks {
+            return None;
+        }
+        if !self.universal {
+            return self
+                .language_fts_queries_for(language_tags, snippet_tags)
+                .map(|[language_query, _]| language_query)
+                // Fallback: just get all snippets with a snippet tag
+                .or_else(|| Self::snippet_tag_query(snippet_tags));
+        }
+        match self.split_fts_queries_for(language_tags, snippet_tags) {
+            Some([language_query, universal_query]) => {
+                Some(format!("{} OR {}", language_query, universal_query))
             }
+            // Fallback: just get all snippets with a snippet tag
+            None => Self::snippet_tag_query(snippet_tags),
+        }
+    }
+
+    fn split_fts_queries_for(
+        &self,
+        language_tags: impl Fn(&str) -> Vec<String>,
+        snippet_tags: &[String],
+    ) -> Option<[String; 2]> {
+        if !self.universal {
+            return None;
+        }
+        self.language_fts_queries_for(language_tags, snippet_tags)
+    }
+
+    /// Language-specific and universal snippet query, None without a language
+    fn language_fts_queries_for(
+        &self,
+        language_tags: impl Fn(&str) -> Vec<String>,
+        snippet_tags: &[String],
+    ) -> Option<[String; 2]> {
+        let lang = self
+            .language_id
+            .as_deref()
+            .filter(|lang| !lang.trim().is_empty() && !self.bookmarks)?;
+        let tags = language_tags(lang);
+        let language_query = match tags.as_slice() {
+            [tag] => format!("tags:{}", fts_term(tag)),
+            tags => format!(
+                "({})",
+                tags.iter()
+                    .map(|tag| format!("tags:{}", fts_term(tag)))
+                    .collect::<Vec<_>>()
+                    .join(" OR ")
+            ),
+        };
+        // Either (language AND snippet tag) or (universal AND snippet tag)
+        Some(match Self::snippet_tag_query(snippet_tags) {
+            Some(snippet_query) => [
+                format!("({} AND {})", language_query, snippet_query),
+                format!("(tags:universal AND {})", snippet_query),
+            ],
+            None => [language_query, "tags:universal".to_string()],
+        })
+    }
+
+    /// Query matching any of the snippet tags, None for an empty list
+    fn snippet_tag_query(snippet_tags: &[String]) -> Option<String> {
+        let tags: Vec<String> = snippet_tags
+            .iter()
+            .map(|tag| tag.trim().replace('"', ""))
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| format!(r#"tags:"{}""#, tag))
+            .collect();
+        match tags.as_slice() {
+            [] => None,
+            [tag] => Some(tag.clone()),
+            tags => Some(format!("({})", tags.join(" OR "))),
         }
-        // Fallback: just get all snippets with _snip_ tag
-        Some(r#"tags:"_snip_""#.to_string())
     }
 }
 
@@ -97,6 +698,13 @@ impl Default for SnippetFilter {
             language_id: None,
             query_prefix: None,
             max_results: 50,
+            title_exact: None,
+            excluded_tags: Vec::new(),
+            required_tags: Vec::new(),
+            bookmarks: false,
+            universal: true,
+            folder: None,
+            case_mode: CaseMode::default(),
         }
     }
 }
@@ -194,7 +802,7 @@ mod tests {
         let filter = SnippetFilter::new(Some("rust".to_string()), None, 50);
 
         // Act
-        let query = filter.build_fts_query();
+        let query = filter.build_fts_query(&LanguageRegistry::default(), &default_snippet_tags());
 
         // Assert
         assert_eq!(
@@ -212,7 +820,7 @@ mod tests {
         let filter = SnippetFilter::new(None, None, 50);
 
         // Act
-        let query = filter.build_fts_query();
+        let query = filter.build_fts_query(&LanguageRegistry::default(), &default_snippet_tags());
 
         // Assert
         assert_eq!(query, Some(r#"tags:"_snip_""#.to_string()));
@@ -224,9 +832,239 @@ mod tests {
         let filter = SnippetFilter::new(Some("".to_string()), None, 50);
 
         // Act
-        let query = filter.build_fts_query();
+        let query = filter.build_fts_query(&LanguageRegistry::default(), &default_snippet_tags());
 
         // Assert
         assert_eq!(query, Some(r#"tags:"_snip_""#.to_string()));
     }
+
+    #[test]
+    fn given_aliased_language_id_when_building_fts_query_then_ors_all_tags() {
+        // Arrange
+        let filter = SnippetFilter::new(Some("typescriptreact".to_string()), None, 50);
+
+        // Act
+        let query = filter.build_fts_query_with(&LanguageMap::default(), &default_snippet_tags());
+
+        // Assert
+        assert_eq!(
+            query,
+            Some(
+                r#"((tags:typescript OR tags:typescriptreact) AND tags:"_snip_") OR (tags:universal AND tags:"_snip_")"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn given_user_language_map_when_building_fts_query_then_uses_mapped_tags() {
+        // Arrange
+        let language_map: LanguageMap = "markdown=md".parse().expect("valid language map");
+        let filter = SnippetFilter::new(Some("markdown".to_string()), None, 50);
+
+        // Act
+        let query = filter.build_fts_query_with(&language_map, &default_snippet_tags());
+
+        // Assert
+        assert_eq!(
+            query,
+            Some(
+                r#"((tags:md OR tags:markdown) AND tags:"_snip_") OR (tags:universal AND tags:"_snip_")"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn given_two_snippet_tags_when_building_fts_query_then_ors_them_for_language_and_universal() {
+        // Arrange
+        let filter = SnippetFilter::new(Some("rust".to_string()), None, 50);
+        let snippet_tags = vec!["_snip_".to_string(), "snippet".to_string()];
+
+        // Act
+        let query = filter.build_fts_query_with(&LanguageMap::default(), &snippet_tags);
+
+        // Assert
+        assert_eq!(
+            query,
+            Some(
+                r#"(tags:rust AND (tags:"_snip_" OR tags:"snippet")) OR (tags:universal AND (tags:"_snip_" OR tags:"snippet"))"#
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn given_single_custom_snippet_tag_when_building_fts_query_then_replaces_default() {
+        // Arrange
+        let filter = SnippetFilter::new(None, None, 50);
+
+        // Act
+        let query = filter.build_fts_query_with(&LanguageMap::default(), &["snippet".to_string()]);
+
+        // Assert
+        assert_eq!(query, Some(r#"tags:"snippet""#.to_string()));
+    }
+
+    #[test]
+    fn given_no_snippet_tags_when_building_fts_query_then_only_filters_by_language() {
+        // Arrange
+        let rust = SnippetFilter::new(Some("rust".to_string()), None, 50);
+        let any = SnippetFilter::new(None, None, 50);
+
+        // Act
+        let rust_query = rust.build_fts_query_with(&LanguageMap::default(), &[]);
+        let any_query = any.build_fts_query_with(&LanguageMap::default(), &[]);
+
+        // Assert
+        assert_eq!(rust_query, Some("tags:rust OR tags:universal".to_string()));
+        assert_eq!(any_query, None);
+    }
+
+    #[test]
+    fn given_language_id_when_building_split_queries_then_joined_they_equal_combined_query() {
+        // Arrange
+        let filter = SnippetFilter::new(Some("rust".to_string()), None, 50);
+        let tags = default_snippet_tags();
+
+        // Act
+        let queries = filter
+            .build_split_fts_queries(&LanguageRegistry::default(), &tags)
+            .expect("split queries");
+
+        // Assert
+        assert_eq!(
+            queries,
+            [
+                r#"(tags:rust AND tags:"_snip_")"#.to_string(),
+                r#"(tags:universal AND tags:"_snip_")"#.to_string(),
+            ]
+        );
+        assert_eq!(
+            Some(queries.join(" OR ")),
+            filter.build_fts_query(&LanguageRegistry::default(), &tags)
+        );
+        assert_eq!(
+            SnippetFilter::default().build_split_fts_queries(&LanguageRegistry::default(), &tags),
+            None
+        );
+    }
+
+    #[test]
+    fn given_universal_disabled_when_building_queries_then_asks_for_the_language_only() {
+        // Arrange
+        let rust = SnippetFilter::new(Some("rust".to_string()), None, 50).with_universal(false);
+        let unknown = SnippetFilter::new(None, None, 50).with_universal(false);
+        let tags = default_snippet_tags();
+
+        // Act
+        let rust_query = rust.build_fts_query(&LanguageRegistry::default(), &tags);
+        let split = rust.build_split_fts_queries(&LanguageRegistry::default(), &tags);
+        let unknown_query = unknown.build_fts_query(&LanguageRegistry::default(), &tags);
+        let untagged = rust.build_fts_query_with(&LanguageMap::default(), &[]);
+
+        // Assert
+        assert_eq!(
+            rust_query,
+            Some(r#"(tags:rust AND tags:"_snip_")"#.to_string())
+        );
+        assert_eq!(split, None);
+        assert_eq!(unknown_query, Some(r#"tags:"_snip_""#.to_string()));
+        assert_eq!(untagged, Some("tags:rust".to_string()));
+    }
+
+    #[test]
+    fn given_replace_modes_when_picking_edit_range_then_word_range_only_replaces() {
+        // Arrange
+        let insert = Range::new(Position::new(0, 0), Position::new(0, 5));
+        let word = Range::new(Position::new(0, 0), Position::new(0, 8));
+
+        // Act & Assert
+        assert_eq!(ReplaceMode::Replace.edit_range(insert, Some(word)), word);
+        assert_eq!(ReplaceMode::Replace.edit_range(insert, None), insert);
+        assert_eq!(ReplaceMode::Insert.edit_range(insert, Some(word)), insert);
+        assert_eq!("INSERT".parse::<ReplaceMode>(), Ok(ReplaceMode::Insert));
+        assert!(
+            "both"
+                .parse::<ReplaceMode>()
+                .expect_err("unknown mode")
+                .contains("expected insert or replace")
+        );
+    }
+
+    #[test]
+    fn given_case_modes_when_matching_queries_then_uppercase_turns_smart_mode_sensitive() {
+        // Act & Assert
+        assert!(CaseMode::Smart.contains("Docker build", "docker"));
+        assert!(!CaseMode::Smart.contains("docker compose up", "Docker"));
+        assert!(CaseMode::Insensitive.contains("docker compose up", "DOCKER"));
+        assert!(!CaseMode::Sensitive.contains("Docker build", "docker"));
+        assert_eq!(CaseMode::Smart.search_query("Docker"), "Docker");
+        assert_eq!(CaseMode::Insensitive.search_query("Docker"), "docker");
+        assert_eq!("SMART".parse::<CaseMode>(), Ok(CaseMode::Smart));
+        assert!(
+            "ignore"
+                .parse::<CaseMode>()
+                .expect_err("unknown mode")
+                .contains("expected smart, insensitive or sensitive")
+        );
+    }
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn given_bookmark_filter_when_building_queries_then_excludes_snippet_tags_instead_of_requiring_them()
+     {
+        // Arrange
+        let filter = SnippetFilter::bookmarks("rust".to_string(), 20);
+        let snippet_tags = vec!["_snip_".to_string(), "_tpl_".to_string()];
+
+        // Act
+        let snippet_query = filter.build_fts_query(&LanguageRegistry::default(), &snippet_tags);
+        let exclusion = filter.snippet_exclusion_query(&snippet_tags);
+
+        // Assert
+        assert!(filter.bookmarks);
+        assert_eq!(filter.language_id, None);
+        assert_eq!(filter.query_prefix.as_deref(), Some("rust"));
+        assert_eq!(filter.max_results, 20);
+        assert_eq!(snippet_query, None);
+        assert_eq!(
+            exclusion.as_deref(),
+            Some(r#"NOT tags:"_snip_" NOT tags:"_tpl_""#)
+        );
+        assert_eq!(
+            SnippetFilter::new(None, Some("rust".to_string()), 20)
+                .snippet_exclusion_query(&snippet_tags),
+            None
+        );
+    }
+
+    #[test]
+    fn given_query_after_indentation_when_describing_line_then_is_at_line_start() {
+        // Act
+        let line = LineContext::new("    derive", 4);
+
+        // Assert
+        assert!(line.at_line_start);
+        assert_eq!(line.preceding_char, Some(' '));
+        assert!(line.allows_tags(&tags(&["rust", LINE_START_TAG])));
+        assert!(!line.allows_tags(&tags(&["rust", INLINE_TAG])));
+    }
+
+    #[test]
+    fn given_query_after_code_when_describing_line_then_is_inline() {
+        // Act
+        let line = LineContext::new("let x = 1; todo", 11);
+
+        // Assert
+        assert!(!line.at_line_start);
+        assert_eq!(line.preceding_char, Some(' '));
+        assert!(!line.allows_tags(&tags(&["_LINE_START_"])));
+        assert!(line.allows_tags(&tags(&[INLINE_TAG])));
+        assert!(line.allows_tags(&tags(&[LINE_START_TAG, INLINE_TAG])));
+        assert!(line.allows_tags(&tags(&["rust"])));
+    }
 }
@@ -0,0 +1,200 @@
+use serde::Serialize;
+use std::str::FromStr;
+use tracing::warn;
+
+use crate::domain::{BkmrSnippet, Snippet};
+
+/// Default largest snippet content offered in completions
+pub const DEFAULT_MAX_SNIPPET_BYTES: usize = 64 * 1024;
+
+/// Start of the note in stubs of oversized snippets
+const STUB_NOTE: &str = "content too large";
+
+/// What happens to snippets whose content is over the size limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LargeSnippetMode {
+    /// Leave them out of the results
+    #[default]
+    Skip,
+    /// Offer them with a placeholder body and a note on how to insert them
+    Stub,
+}
+
+impl FromStr for LargeSnippetMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(Self::Skip),
+            "stub" => Ok(Self::Stub),
+            other => Err(format!(
+                "unknown large snippet mode '{}', expected skip or stub",
+                other
+            )),
+        }
+    }
+}
+
+/// Size guard applied when bkmr results become snippets, before any translation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentLimit {
+    /// Largest content in bytes, 0 for no limit
+    pub max_bytes: usize,
+    pub mode: LargeSnippetMode,
+}
+
+impl Default for ContentLimit {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_SNIPPET_BYTES,
+            mode: LargeSnippetMode::default(),
+        }
+    }
+}
+
+impl ContentLimit {
+    /// Convert bkmr snippets, dropping or stubbing those over the limit
+    pub fn apply(&self, snippets: Vec<BkmrSnippet>) -> Vec<Snippet> {
+        let mut oversized = 0;
+        let snippets: Vec<Snippet> = snippets
+            .into_iter()
+            .filter_map(|snippet| {
+                if self.max_bytes == 0 || snippet.url.len() <= self.max_bytes {
+                    return Some(Snippet::from(snippet));
+                }
+                oversized += 1;
+                match self.mode {
+                    LargeSnippetMode::Skip => None,
+                    LargeSnippetMode::Stub => Some(Self::stub(snippet)),
+                }
+            })
+            .collect();
+
+        if oversized > 0 {
+            let action = match self.mode {
+                LargeSnippetMode::Skip => "Skipped",
+                LargeSnippetMode::Stub => "Stubbed",
+            };
+            warn!(
+                "{} {} snippets larger than {}",
+                action,
+                oversized,
+                format_size(self.max_bytes)
+            );
+        }
+        snippets
+    }
+
+    /// Whether the snippet is a placeholder for one over the limit
+    pub fn is_stub(snippet: &Snippet) -> bool {
+        snippet
+            .content
+            .strip_prefix(&format!("[bkmr snippet {}: ", snippet.id))
+            .is_some_and(|note| note.starts_with(STUB_NOTE))
+    }
+
+    /// Plain placeholder whose description tells the size and how to insert the real content
+    fn stub(snippet: BkmrSnippet) -> Snippet {
+        let note = format!(
+            "{} ({}), use bkmr.insertSnippetById to insert",
+            STUB_NOTE,
+            format_size(snippet.url.len())
+        );
+        let mut stub = Snippet::from(BkmrSnippet {
+            url: format!("[bkmr snippet {}: {}]", snippet.id, note),
+            description: note,
+            ..snippet
+        });
+        if !stub.is_plain() {
+            stub.tags.push("plain".to_string());
+        }
+        stub
+    }
+}
+
+/// Byte count with a decimal unit, e.g. "2.1 MB" or "512 B"
+pub fn format_size(bytes: usize) -> String {
+    match bytes {
+        0..1_000 => format!("{} B", bytes),
+        1_000..1_000_000 => format!("{:.1} KB", bytes as f64 / 1e3),
+        _ => format!("{:.1} MB", bytes as f64 / 1e6),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bkmr_snippet(id: i32, bytes: usize) -> BkmrSnippet {
+        BkmrSnippet {
+            id,
+            title: format!("snippet {}", id),
+            url: "x".repeat(bytes),
+            description: "fixture".to_string(),
+            tags: vec!["json".to_string(), "_snip_".to_string()],
+            access_count: 0,
+        }
+    }
+
+    #[test]
+    fn given_content_at_and_over_the_limit_when_applying_then_keeps_only_the_one_at_it() {
+        // Arrange
+        let limit = ContentLimit {
+            max_bytes: 100,
+            mode: LargeSnippetMode::Skip,
+        };
+
+        // Act
+        let snippets = limit.apply(vec![bkmr_snippet(1, 100), bkmr_snippet(2, 101)]);
+        let unlimited = ContentLimit {
+            max_bytes: 0,
+            ..limit
+        }
+        .apply(vec![bkmr_snippet(2, 101)]);
+
+        // Assert
+        assert_eq!(
+            snippets
+                .iter()
+                .map(|snippet| snippet.id)
+                .collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert_eq!(unlimited.len(), 1);
+    }
+
+    #[test]
+    fn given_stub_mode_when_applying_then_replaces_oversized_content_with_a_plain_note() {
+        // Arrange
+        let limit = ContentLimit {
+            max_bytes: DEFAULT_MAX_SNIPPET_BYTES,
+            mode: LargeSnippetMode::Stub,
+        };
+
+        // Act
+        let snippets = limit.apply(vec![bkmr_snippet(7, 2_100_000)]);
+
+        // Assert
+        let stub = &snippets[0];
+        assert_eq!(stub.id, 7);
+        assert_eq!(stub.title, "snippet 7");
+        assert_eq!(
+            stub.description,
+            "content too large (2.1 MB), use bkmr.insertSnippetById to insert"
+        );
+        assert_eq!(
+            stub.content,
+            "[bkmr snippet 7: content too large (2.1 MB), use bkmr.insertSnippetById to insert]"
+        );
+        assert_eq!(stub.tags, vec!["json", "_snip_", "plain"]);
+    }
+
+    #[test]
+    fn given_byte_counts_when_formatting_then_uses_decimal_units() {
+        // Act & Assert
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(65_536), "65.5 KB");
+        assert_eq!(format_size(2_097_152), "2.1 MB");
+    }
+}
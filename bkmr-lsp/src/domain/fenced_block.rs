@@ -0,0 +1,221 @@
+use crate::domain::LanguageRegistry;
+
+/// Language IDs of documents whose fenced code blocks complete in the fence language
+pub const FENCED_LANGUAGES: &[&str] = &["markdown"];
+
+/// A fenced code block of a markdown document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FencedBlock {
+    /// Line of the opening fence
+    pub start: u32,
+    /// Line of the closing fence, None while the block runs to the end of the document
+    pub end: Option<u32>,
+    /// Language ID named by the info string, None without one
+    pub language_id: Option<String>,
+}
+
+impl FencedBlock {
+    /// Whether a line is between the fences, the fence lines themselves are outside
+    pub fn contains(&self, line: u32) -> bool {
+        line > self.start && self.end.is_none_or(|end| line < end)
+    }
+}
+
+/// Opening fence of the block being scanned
+#[derive(Debug, Clone)]
+struct OpenFence {
+    marker: char,
+    length: usize,
+    block: FencedBlock,
+}
+
+/// Fenced code blocks of a document, scanned only as far as lines were asked about
+///
+/// Backtick and tilde fences of any indentation are recognized. A block is
+/// closed by a fence of the same character at least as long as its opening
+/// one, so shorter fences inside it, like those of a nested example, stay
+/// content. A scan only stays valid for the text it was started on.
+#[derive(Debug, Clone, Default)]
+pub struct FenceScan {
+    /// Closed blocks in document order
+    blocks: Vec<FencedBlock>,
+    open: Option<OpenFence>,
+    /// Lines scanned so far
+    lines: u32,
+    /// Byte offset of the first line not scanned yet
+    offset: usize,
+}
+
+impl FenceScan {
+    /// Language of the fenced block containing a line, scanning the text up to it
+    ///
+    /// Returns None outside blocks and in blocks without info string.
+    pub fn language_at(
+        &mut self,
+        text: &str,
+        line: u32,
+        registry: &LanguageRegistry,
+    ) -> Option<&str> {
+        self.scan_to(text, line, registry);
+        if let Some(open) = &self.open
+            && open.block.contains(line)
+        {
+            return open.block.language_id.as_deref();
+        }
+        let index = self.blocks.partition_point(|block| block.start < line);
+        index
+            .checked_sub(1)
+            .map(|index| &self.blocks[index])
+            .filter(|block| block.contains(line))
+            .and_then(|block| block.language_id.as_deref())
+    }
+
+    /// Scan the lines up to and including `line`, continuing where the last scan stopped
+    fn scan_to(&mut self, text: &str, line: u32, registry: &LanguageRegistry) {
+        while self.lines <= line && self.offset < text.len() {
+            let rest = &text[self.offset..];
+            let length = rest.find('\n').map_or(rest.len(), |end| end + 1);
+            let current = rest[..length].trim_end_matches(['\n', '\r']);
+            self.scan_line(current, registry);
+            self.offset += length;
+            self.lines += 1;
+        }
+    }
+
+    fn scan_line(&mut self, line: &str, registry: &LanguageRegistry) {
+        let Some((marker, length, info)) = Self::fence(line) else {
+            return;
+        };
+        match self.open.take() {
+            Some(open) if marker == open.marker && length >= open.length && info.is_empty() => {
+                self.blocks.push(FencedBlock {
+                    end: Some(self.lines),
+                    ..open.block
+                });
+            }
+            Some(open) => self.open = Some(open),
+            // Backticks in the info string make it inline code, not a fence
+            None if marker == '`' && info.contains('`') => {}
+            None => {
+                self.open = Some(OpenFence {
+                    marker,
+                    length,
+                    block: FencedBlock {
+                        start: self.lines,
+                        end: None,
+                        language_id: Self::info_language(info, registry),
+                    },
+                });
+            }
+        }
+    }
+
+    /// Fence character, its count and the info string of a fence line
+    fn fence(line: &str) -> Option<(char, usize, &str)> {
+        let line = line.trim_start();
+        let marker = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+        let length = line.chars().take_while(|c| *c == marker).count();
+        if length < 3 {
+            return None;
+        }
+        Some((marker, length, line[length..].trim()))
+    }
+
+    /// Language of an info string like `py`, `{.python}` or `rust,ignore`
+    fn info_language(info: &str, registry: &LanguageRegistry) -> Option<String> {
+        let word = info
+            .split(|c: char| c.is_whitespace() || c == ',' || c == '}')
+            .next()?
+            .trim_start_matches(['{', '.']);
+        (!word.is_empty()).then(|| registry.language_id_for(word))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn language_at(text: &str, line: u32) -> Option<String> {
+        FenceScan::default()
+            .language_at(text, line, &LanguageRegistry::default())
+            .map(str::to_string)
+    }
+
+    #[test]
+    fn given_cursor_inside_and_outside_fences_when_scanning_then_only_inside_has_language() {
+        // Arrange
+        let text = "# Notes\n```python\nimport os\n```\ntext\n";
+
+        // Act & Assert
+        assert_eq!(language_at(text, 0), None);
+        assert_eq!(language_at(text, 1), None);
+        assert_eq!(language_at(text, 2).as_deref(), Some("python"));
+        assert_eq!(language_at(text, 3), None);
+        assert_eq!(language_at(text, 4), None);
+    }
+
+    #[test]
+    fn given_tilde_and_indented_fences_when_scanning_then_recognizes_them() {
+        // Arrange
+        let text = "~~~go\n```\nnot closed by backticks\n~~~\n- item\n    ```rust\n    let x = 1;\n    ```\n";
+
+        // Act & Assert
+        assert_eq!(language_at(text, 2).as_deref(), Some("go"));
+        assert_eq!(language_at(text, 4), None);
+        assert_eq!(language_at(text, 6).as_deref(), Some("rust"));
+    }
+
+    #[test]
+    fn given_nested_shorter_fence_when_scanning_then_it_stays_content() {
+        // Arrange
+        let text = "````markdown\n```python\nx = 1\n```\n````\nafter\n";
+
+        // Act & Assert
+        assert_eq!(language_at(text, 2).as_deref(), Some("markdown"));
+        assert_eq!(language_at(text, 3).as_deref(), Some("markdown"));
+        assert_eq!(language_at(text, 5), None);
+    }
+
+    #[test]
+    fn given_fence_without_info_string_when_scanning_then_has_no_language() {
+        // Act & Assert
+        assert_eq!(language_at("```\nplain\n```\n", 1), None);
+    }
+
+    #[test]
+    fn given_unterminated_fence_when_scanning_then_inside_until_end_of_document() {
+        // Arrange
+        let text = "intro\n```sh\necho hi\n\nmore";
+
+        // Act & Assert
+        assert_eq!(language_at(text, 2).as_deref(), Some("shell"));
+        assert_eq!(language_at(text, 4).as_deref(), Some("shell"));
+        assert_eq!(language_at(text, 9).as_deref(), Some("shell"));
+    }
+
+    #[test]
+    fn given_alias_info_strings_when_scanning_then_maps_to_language_ids() {
+        // Arrange
+        let text = "```py\n1\n```\n```{.js}\n2\n```\n```Rust,ignore\n3\n```\n```foo\n4\n```\n";
+        let mut scan = FenceScan::default();
+        let registry = LanguageRegistry::default();
+
+        // Act
+        let languages: Vec<Option<String>> = [1, 4, 7, 10]
+            .into_iter()
+            .map(|line| scan.language_at(text, line, &registry).map(str::to_string))
+            .collect();
+
+        // Assert
+        assert_eq!(
+            languages,
+            vec![
+                Some("python".to_string()),
+                Some("javascript".to_string()),
+                Some("rust".to_string()),
+                Some("foo".to_string()),
+            ]
+        );
+        assert_eq!(scan.language_at(text, 1, &registry), Some("python"));
+    }
+}
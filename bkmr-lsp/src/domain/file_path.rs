@@ -0,0 +1,255 @@
+use percent_encoding::percent_decode_str;
+use std::path::{Component, Path, PathBuf};
+use tower_lsp::lsp_types::Url;
+
+use crate::domain::LanguageRegistry;
+
+/// Scheme of buffers that were never saved, like untitled:Untitled-1 in VS Code
+pub const UNTITLED_SCHEME: &str = "untitled";
+
+/// Separator used when rendering relative paths for display
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathSeparator {
+    /// Forward slashes on every platform
+    #[default]
+    Slash,
+    /// The platform's own separator, a backslash on Windows
+    Native,
+}
+
+impl PathSeparator {
+    pub fn from_native(native: bool) -> Self {
+        if native { Self::Native } else { Self::Slash }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Slash => "/",
+            Self::Native => std::path::MAIN_SEPARATOR_STR,
+        }
+    }
+}
+
+/// Name of the file a URI points to
+///
+/// File URIs are converted to a path first, so percent-encoded characters
+/// and drive letters come out right. Other URIs, like untitled:Untitled-1,
+/// use their decoded last path segment.
+pub fn uri_file_name(uri: &Url) -> Option<String> {
+    if let Ok(path) = uri.to_file_path() {
+        return path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+    }
+    uri.path()
+        .rsplit('/')
+        .next()
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().to_string())
+}
+
+/// File name standing in for an unsaved buffer, None for URIs of other schemes
+///
+/// The lowercased buffer name gets the extension of the document language
+/// unless it has one: untitled:Untitled-1 in Python becomes "untitled-1.py".
+pub fn untitled_file_name(
+    uri: &Url,
+    language_id: Option<&str>,
+    registry: &LanguageRegistry,
+) -> Option<String> {
+    if uri.scheme() != UNTITLED_SCHEME {
+        return None;
+    }
+    let name = uri_file_name(uri)
+        .map(|name| name.to_lowercase().replace(char::is_whitespace, "-"))
+        .unwrap_or_else(|| UNTITLED_SCHEME.to_string());
+    if Path::new(&name).extension().is_some() {
+        return Some(name);
+    }
+    Some(
+        match language_id.and_then(|language_id| registry.extension_for(language_id)) {
+            Some(extension) => format!("{}.{}", name, extension),
+            None => name,
+        },
+    )
+}
+
+/// Path components of a file URI, a leading drive letter lowercased
+///
+/// Clients differ in how they spell drive letters, `C:` or `c%3A`, so
+/// lowercasing makes paths of the same drive comparable.
+pub fn uri_path_components(uri: &Url) -> Option<Vec<String>> {
+    let path = uri.to_file_path().ok()?;
+    let mut components = path_components(&path);
+    if let Some(first) = components.first_mut()
+        && first.len() == 2
+        && first.ends_with(':')
+        && first.starts_with(|c: char| c.is_ascii_alphabetic())
+    {
+        *first = first.to_ascii_lowercase();
+    }
+    Some(components)
+}
+
+/// Render a relative path with the given separator
+pub fn render_relative_path(path: &Path, separator: PathSeparator) -> String {
+    join_path_components(&path_components(path), separator)
+}
+
+/// Join path components with the given separator
+pub fn join_path_components(components: &[String], separator: PathSeparator) -> String {
+    components.join(separator.as_str())
+}
+
+/// Path with `.` and `..` components resolved without touching the file system
+///
+/// `..` never climbs above the root, so `/a/../../b` becomes `/b`.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() && !normalized.has_root() {
+                    normalized.push(component);
+                }
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Named components of a path, with a Windows prefix like `C:` kept as its own component
+fn path_components(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Prefix(prefix) => Some(prefix.as_os_str().to_string_lossy().to_string()),
+            Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(uri: &str) -> Url {
+        Url::parse(uri).expect("parse URI")
+    }
+
+    #[test]
+    fn given_percent_encoded_uri_when_getting_file_name_then_decodes_it() {
+        // Act & Assert
+        assert_eq!(
+            uri_file_name(&url("file:///home/me/my%20notes/read%20me.md")).as_deref(),
+            Some("read me.md")
+        );
+        assert_eq!(
+            uri_file_name(&url("file:///C:/Users/John%20Doe/src/main.rs")).as_deref(),
+            Some("main.rs")
+        );
+        assert_eq!(
+            uri_file_name(&url("untitled:Untitled%201")).as_deref(),
+            Some("Untitled 1")
+        );
+    }
+
+    #[test]
+    fn given_untitled_uri_when_synthesizing_file_name_then_adds_language_extension() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+
+        // Act & Assert
+        assert_eq!(
+            untitled_file_name(&url("untitled:Untitled-1"), Some("python"), &registry).as_deref(),
+            Some("untitled-1.py")
+        );
+        assert_eq!(
+            untitled_file_name(&url("untitled:Untitled%202"), Some("rust"), &registry).as_deref(),
+            Some("untitled-2.rs")
+        );
+        assert_eq!(
+            untitled_file_name(&url("untitled:Untitled-1"), Some("unknownlang"), &registry)
+                .as_deref(),
+            Some("untitled-1")
+        );
+        assert_eq!(
+            untitled_file_name(&url("untitled:/tmp/notes.md"), Some("python"), &registry)
+                .as_deref(),
+            Some("notes.md")
+        );
+        assert_eq!(
+            untitled_file_name(&url("file:///tmp/main.py"), Some("python"), &registry),
+            None
+        );
+    }
+
+    #[test]
+    fn given_drive_letter_spellings_when_splitting_uri_then_components_match() {
+        // Act
+        let upper = uri_path_components(&url("file:///C:/work/my%20repo"));
+        let encoded = uri_path_components(&url("file:///c%3A/work/my%20repo"));
+
+        // Assert
+        assert_eq!(upper, encoded);
+        assert_eq!(
+            upper.expect("file URI").last().map(String::as_str),
+            Some("my repo")
+        );
+    }
+
+    #[test]
+    fn given_relative_path_when_rendering_then_uses_chosen_separator() {
+        // Arrange
+        let path = Path::new("src").join("services").join("main.rs");
+
+        // Act
+        let slash = render_relative_path(&path, PathSeparator::Slash);
+        let native = render_relative_path(&path, PathSeparator::Native);
+
+        // Assert
+        assert_eq!(slash, "src/services/main.rs");
+        assert_eq!(
+            native,
+            ["src", "services", "main.rs"].join(std::path::MAIN_SEPARATOR_STR)
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn given_windows_file_uri_when_converting_then_path_has_no_leading_slash() {
+        // Arrange
+        let uri = url("file:///C:/Users/John%20Doe/src/main.rs");
+
+        // Act
+        let components = uri_path_components(&uri).expect("file URI");
+        let name = uri_file_name(&uri);
+
+        // Assert
+        assert_eq!(components[0], "c:");
+        assert_eq!(
+            join_path_components(&components[1..], PathSeparator::Native),
+            "Users\\John Doe\\src\\main.rs"
+        );
+        assert_eq!(name.as_deref(), Some("main.rs"));
+    }
+
+    #[test]
+    fn given_dot_components_when_normalizing_path_then_resolves_them_lexically() {
+        // Act & Assert
+        assert_eq!(
+            normalize_path(Path::new("/work/app/./docs/../snippets.json")),
+            PathBuf::from("/work/app/snippets.json")
+        );
+        assert_eq!(
+            normalize_path(Path::new("/work/../../etc/passwd")),
+            PathBuf::from("/etc/passwd")
+        );
+        assert_eq!(
+            normalize_path(Path::new("../out.json")),
+            PathBuf::from("../out.json")
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::Url;
+use tracing::{error, info, warn};
+
+use crate::domain::LanguageDefinitions;
+
+/// File at a workspace folder root choosing the bkmr database of its documents
+pub const FOLDER_CONFIG_FILE: &str = ".bkmr-lsp.toml";
+
+/// bkmr database and binary serving the documents in one folder
+///
+/// Set in initializationOptions or in the folder's `.bkmr-lsp.toml`, unset
+/// keys keep the global setting.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderRepository {
+    /// Absolute folder path, or a file:// URI
+    pub folder: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub db_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bkmr_binary: Option<String>,
+    /// Read from the folder's `.bkmr-lsp.toml`, replaced whenever the files are read again
+    #[serde(skip)]
+    pub from_file: bool,
+}
+
+impl FolderRepository {
+    /// Settings of a `.bkmr-lsp.toml`, a relative `db_path` is relative to the folder
+    ///
+    /// Only `key = "value"` lines are understood, unknown keys are ignored.
+    pub fn parse(folder: &Path, text: &str) -> Result<Self, String> {
+        let mut repository = Self {
+            folder: folder.display().to_string(),
+            from_file: true,
+            ..Self::default()
+        };
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let at = |e: String| format!("line {}: {}", index + 1, e);
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| at("expected key = value".to_string()))?;
+            let value = LanguageDefinitions::string_value(value).map_err(at)?;
+            match key.trim() {
+                "db_path" => repository.db_path = Some(folder.join(value).display().to_string()),
+                "bkmr_binary" => repository.bkmr_binary = Some(value),
+                other => warn!(
+                    "Ignoring unknown key '{}' of {} on line {}",
+                    other,
+                    FOLDER_CONFIG_FILE,
+                    index + 1
+                ),
+            }
+        }
+        Ok(repository)
+    }
+
+    /// Settings of the `.bkmr-lsp.toml` at a folder root, None if there is none
+    ///
+    /// A file that cannot be read or parsed is logged and ignored.
+    pub async fn load(folder: &Path) -> Option<Self> {
+        let path = folder.join(FOLDER_CONFIG_FILE);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(text) => match Self::parse(folder, &text) {
+                Ok(repository) => {
+                    info!("Read folder repository settings from {}", path.display());
+                    Some(repository)
+                }
+                Err(e) => {
+                    error!("Ignoring malformed {}, {}", path.display(), e);
+                    None
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                error!("Failed to read {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Settings files of the workspace folders having one
+    pub async fn load_all(workspace_folders: &[Url]) -> Vec<Self> {
+        let mut repositories = Vec::new();
+        for folder in workspace_folders
+            .iter()
+            .filter_map(|folder| folder.to_file_path().ok())
+        {
+            repositories.extend(Self::load(&folder).await);
+        }
+        repositories
+    }
+
+    /// Whether one of the folders has a settings file
+    pub async fn any_file(workspace_folders: &[Url]) -> bool {
+        for folder in workspace_folders
+            .iter()
+            .filter_map(|folder| folder.to_file_path().ok())
+        {
+            if tokio::fs::metadata(folder.join(FOLDER_CONFIG_FILE))
+                .await
+                .is_ok_and(|metadata| metadata.is_file())
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Folder as a path, converted from a file:// URI if given as one
+    pub fn path(&self) -> PathBuf {
+        match Url::parse(&self.folder) {
+            Ok(url) if url.scheme() == "file" => url
+                .to_file_path()
+                .unwrap_or_else(|_| PathBuf::from(&self.folder)),
+            _ => PathBuf::from(&self.folder),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_folder_settings_file_when_parsing_then_resolves_database_against_folder() {
+        // Arrange
+        let folder = Path::new("/work/clientA");
+        let text = "# client A\ndb_path = \"bkmr/bkmr.db\"\nbkmr_binary = '/opt/bkmr' # pinned\ncolor = \"red\"\n";
+
+        // Act
+        let repository = FolderRepository::parse(folder, text).expect("valid settings");
+        let absolute =
+            FolderRepository::parse(folder, "db_path = \"/dbs/a.db\"").expect("absolute database");
+
+        // Assert
+        assert_eq!(
+            repository,
+            FolderRepository {
+                folder: "/work/clientA".to_string(),
+                db_path: Some("/work/clientA/bkmr/bkmr.db".to_string()),
+                bkmr_binary: Some("/opt/bkmr".to_string()),
+                from_file: true,
+            }
+        );
+        assert_eq!(absolute.db_path.as_deref(), Some("/dbs/a.db"));
+    }
+
+    #[test]
+    fn given_malformed_folder_settings_when_parsing_then_names_the_line() {
+        // Act
+        let no_value = FolderRepository::parse(Path::new("/work"), "db_path\n");
+        let unquoted = FolderRepository::parse(Path::new("/work"), "\ndb_path = a.db");
+
+        // Assert
+        assert_eq!(no_value, Err("line 1: expected key = value".to_string()));
+        assert!(unquoted.expect_err("unquoted").starts_with("line 2:"));
+    }
+
+    #[tokio::test]
+    async fn given_workspace_folders_when_loading_then_reads_only_existing_settings_files() {
+        // Arrange
+        let root = std::env::temp_dir().join(format!("bkmr-lsp-folders-{}", std::process::id()));
+        let (with_file, without_file) = (root.join("clientA"), root.join("clientB"));
+        std::fs::create_dir_all(&with_file).expect("create folder");
+        std::fs::create_dir_all(&without_file).expect("create folder");
+        std::fs::write(with_file.join(FOLDER_CONFIG_FILE), "db_path = \"a.db\"\n")
+            .expect("write settings file");
+        let folder = |path: &Path| Url::from_directory_path(path).expect("folder URI");
+
+        // Act
+        let repositories =
+            FolderRepository::load_all(&[folder(&with_file), folder(&without_file)]).await;
+        let any_file = FolderRepository::any_file(&[folder(&with_file)]).await;
+        let no_file = FolderRepository::any_file(&[folder(&without_file)]).await;
+
+        // Assert
+        assert_eq!(repositories.len(), 1);
+        assert_eq!(repositories[0].path(), with_file);
+        assert!(any_file);
+        assert!(!no_file);
+        std::fs::remove_dir_all(root).expect("remove folders");
+    }
+
+    #[test]
+    fn given_folder_option_when_deserializing_then_accepts_paths_and_uris() {
+        // Arrange
+        let value = serde_json::json!([
+            { "folder": "file:///work/clientA", "dbPath": "/dbs/a.db" },
+            { "folder": "/work/clientB", "bkmrBinary": "bkmr-b" }
+        ]);
+
+        // Act
+        let repositories: Vec<FolderRepository> =
+            serde_json::from_value(value).expect("valid option");
+
+        // Assert
+        assert_eq!(repositories[0].path(), PathBuf::from("/work/clientA"));
+        assert_eq!(repositories[0].db_path.as_deref(), Some("/dbs/a.db"));
+        assert_eq!(repositories[1].path(), PathBuf::from("/work/clientB"));
+        assert_eq!(repositories[1].bkmr_binary.as_deref(), Some("bkmr-b"));
+        assert!(!repositories[1].from_file);
+    }
+}
@@ -0,0 +1,130 @@
+/// Line opening and closing a bkmr frontmatter block
+const FRONTMATTER_DELIMITER: &str = "---";
+/// Key of the frontmatter line listing the snippet's tags
+const TAGS_KEY: &str = "tags:";
+
+/// Partial tag typed on the `tags:` line of a leading frontmatter block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrontmatterTagQuery {
+    /// Tag characters between the previous separator and the cursor
+    pub prefix: String,
+    /// Byte offset of the prefix in the line
+    pub start: usize,
+}
+
+impl FrontmatterTagQuery {
+    /// Find the tag being typed at a byte offset of a line, None outside frontmatter tags lines
+    ///
+    /// The block has to start on the first line and be closed by another
+    /// `---` below the cursor; a `---` further down the file is a rule.
+    pub fn at(text: &str, line: u32, cursor: usize) -> Option<Self> {
+        let lines: Vec<&str> = text
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .collect();
+        if lines.first()?.trim_end() != FRONTMATTER_DELIMITER {
+            return None;
+        }
+        let close = (1..lines.len()).find(|&i| lines[i].trim_end() == FRONTMATTER_DELIMITER)?;
+        let line = line as usize;
+        if line == 0 || line >= close {
+            return None;
+        }
+        let content = lines[line];
+        let value = content.strip_prefix(TAGS_KEY)?;
+        let value_start = TAGS_KEY.len();
+        let before = value.get(..cursor.checked_sub(value_start)?)?;
+        let prefix_len = before
+            .chars()
+            .rev()
+            .take_while(|c| !Self::is_separator(*c))
+            .map(char::len_utf8)
+            .sum::<usize>();
+        Some(Self {
+            prefix: before[before.len() - prefix_len..].to_string(),
+            start: cursor - prefix_len,
+        })
+    }
+
+    /// Characters between tags, as in `tags: a, b` or `tags: [a, "b"]`
+    fn is_separator(c: char) -> bool {
+        c.is_whitespace() || matches!(c, ',' | '[' | ']' | '"' | '\'')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_tags_line_in_leading_frontmatter_when_finding_query_then_returns_partial_tag() {
+        // Arrange
+        let text = "---\ntitle: Deploy\ntags: rust, kub\n---\nkubectl apply\n";
+
+        // Act
+        let query = FrontmatterTagQuery::at(text, 2, "tags: rust, kub".len());
+
+        // Assert
+        assert_eq!(
+            query,
+            Some(FrontmatterTagQuery {
+                prefix: "kub".to_string(),
+                start: "tags: rust, ".len(),
+            })
+        );
+    }
+
+    #[test]
+    fn given_list_syntax_and_empty_word_when_finding_query_then_stops_at_separators() {
+        // Arrange
+        let text = "---\r\ntags: [\"sn\r\n---\r\n";
+
+        // Act
+        let quoted = FrontmatterTagQuery::at(text, 1, "tags: [\"sn".len());
+        let empty = FrontmatterTagQuery::at("---\ntags: \n---\n", 1, "tags: ".len());
+
+        // Assert
+        assert_eq!(quoted.map(|query| query.prefix), Some("sn".to_string()));
+        assert_eq!(
+            empty,
+            Some(FrontmatterTagQuery {
+                prefix: String::new(),
+                start: "tags: ".len(),
+            })
+        );
+    }
+
+    #[test]
+    fn given_no_frontmatter_when_finding_query_then_returns_none() {
+        // Act & Assert
+        assert_eq!(FrontmatterTagQuery::at("tags: rust\n", 0, 8), None);
+        assert_eq!(
+            FrontmatterTagQuery::at("# Notes\ntags: rust\n---\n", 1, 8),
+            None
+        );
+        assert_eq!(FrontmatterTagQuery::at("---\ntags: rust\n", 1, 8), None);
+    }
+
+    #[test]
+    fn given_mid_file_delimiters_when_finding_query_then_ignores_them() {
+        // Arrange
+        let text = "---\ntitle: x\n---\nSome text\n---\ntags: ru\n---\n";
+
+        // Act
+        let query = FrontmatterTagQuery::at(text, 5, "tags: ru".len());
+
+        // Assert
+        assert_eq!(query, None);
+    }
+
+    #[test]
+    fn given_other_line_or_cursor_in_key_when_finding_query_then_returns_none() {
+        // Arrange
+        let text = "---\ntitle: ru\ntags: rust\n---\n";
+
+        // Act & Assert
+        assert_eq!(FrontmatterTagQuery::at(text, 1, "title: ru".len()), None);
+        assert_eq!(FrontmatterTagQuery::at(text, 2, 3), None);
+        assert_eq!(FrontmatterTagQuery::at(text, 0, 3), None);
+    }
+}
@@ -0,0 +1,123 @@
+/// Longest typed prefix searched for, longer input is cut off
+pub const MAX_FTS_PREFIX_CHARS: usize = 64;
+
+/// Words FTS5 reads as operators when they appear as barewords
+const FTS_OPERATORS: [&str; 4] = ["AND", "OR", "NOT", "NEAR"];
+
+/// Term as FTS bareword, quoted when it contains characters like '.', ':' or '('
+///
+/// Embedded double quotes are doubled, operator words are lowercased so they
+/// match as plain words, FTS matching ignores case anyway.
+pub fn fts_term(term: &str) -> String {
+    if FTS_OPERATORS.contains(&term) {
+        term.to_lowercase()
+    } else if !term.is_empty() && term.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        term.to_string()
+    } else {
+        format!("\"{}\"", term.replace('"', "\"\""))
+    }
+}
+
+/// Typed prefix as FTS term for a prefix search, None when nothing searchable remains
+///
+/// Control characters are dropped, whitespace is collapsed and the input is
+/// cut to [`MAX_FTS_PREFIX_CHARS`] characters.
+pub fn fts_prefix_term(prefix: &str) -> Option<String> {
+    let prefix: String = prefix
+        .chars()
+        .filter(|c| !c.is_control() || c.is_whitespace())
+        .take(MAX_FTS_PREFIX_CHARS)
+        .collect();
+    let words: Vec<String> = prefix
+        .split_whitespace()
+        .map(|word| {
+            if FTS_OPERATORS.contains(&word) {
+                word.to_lowercase()
+            } else {
+                word.to_string()
+            }
+        })
+        .collect();
+    // Punctuation alone yields no tokens, it would only make an invalid query
+    if !words
+        .iter()
+        .any(|word| word.chars().any(char::is_alphanumeric))
+    {
+        return None;
+    }
+    Some(fts_term(&words.join(" ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_plain_words_when_building_terms_then_keeps_them_bare() {
+        // Act & Assert
+        assert_eq!(fts_term("deploy"), "deploy");
+        assert_eq!(fts_term("snake_case"), "snake_case");
+        assert_eq!(fts_term("größe"), "größe");
+        assert_eq!(fts_prefix_term("dep"), Some("dep".to_string()));
+    }
+
+    #[test]
+    fn given_quotes_colons_and_parentheses_when_building_prefix_terms_then_quotes_them() {
+        // Act & Assert
+        assert_eq!(fts_prefix_term("say\"hi"), Some(r#""say""hi""#.to_string()));
+        assert_eq!(fts_prefix_term("tags:"), Some(r#""tags:""#.to_string()));
+        assert_eq!(fts_prefix_term("f(x)"), Some(r#""f(x)""#.to_string()));
+        assert_eq!(
+            fts_prefix_term(r#"a" OR tags:x OR ""#),
+            Some(r#""a"" or tags:x or """"#.to_string())
+        );
+        assert_eq!(fts_term("c++"), r#""c++""#);
+    }
+
+    #[test]
+    fn given_operator_words_when_building_prefix_terms_then_lowercases_them() {
+        // Act & Assert
+        assert_eq!(fts_prefix_term("AND"), Some("and".to_string()));
+        assert_eq!(fts_prefix_term("NEAR"), Some("near".to_string()));
+        assert_eq!(
+            fts_prefix_term("git  OR\tNOT rm"),
+            Some(r#""git or not rm""#.to_string())
+        );
+        assert_eq!(fts_prefix_term("Order"), Some("Order".to_string()));
+        assert_eq!(fts_term("OR"), "or");
+    }
+
+    #[test]
+    fn given_unicode_and_control_characters_when_building_prefix_terms_then_keeps_only_text() {
+        // Act & Assert
+        assert_eq!(fts_prefix_term("café"), Some("café".to_string()));
+        assert_eq!(fts_prefix_term("日本"), Some("日本".to_string()));
+        assert_eq!(fts_prefix_term("ab\u{0}c\u{1b}"), Some("abc".to_string()));
+        assert_eq!(
+            fts_prefix_term("naïve–dash"),
+            Some("\"naïve–dash\"".to_string())
+        );
+    }
+
+    #[test]
+    fn given_nothing_searchable_when_building_prefix_terms_then_returns_none() {
+        // Act & Assert
+        assert_eq!(fts_prefix_term(""), None);
+        assert_eq!(fts_prefix_term("   "), None);
+        assert_eq!(fts_prefix_term("\"\""), None);
+        assert_eq!(fts_prefix_term("(*:)"), None);
+    }
+
+    #[test]
+    fn given_overlong_prefix_when_building_prefix_terms_then_cuts_it_at_the_limit() {
+        // Arrange
+        let prefix = "é".repeat(MAX_FTS_PREFIX_CHARS + 10);
+
+        // Act
+        let term = fts_prefix_term(&prefix).expect("searchable prefix");
+
+        // Assert
+        assert_eq!(term.chars().count(), MAX_FTS_PREFIX_CHARS);
+        assert_eq!(term, "é".repeat(MAX_FTS_PREFIX_CHARS));
+    }
+}
@@ -0,0 +1,147 @@
+use regex::Regex;
+use tracing::warn;
+
+/// Patterns of a .bkmrlspignore file, in gitignore syntax
+///
+/// Patterns without a slash match at any depth, a leading or inner slash
+/// anchors them at the root and a trailing slash matches directories only.
+/// `*` and `?` stay within a path component, `**` spans components and a
+/// leading `!` re-includes paths. The last matching pattern wins.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    regex: Regex,
+    negated: bool,
+}
+
+impl IgnoreRules {
+    /// Parse the text of an ignore file, skipping blank lines, comments and invalid patterns
+    pub fn parse(text: &str) -> Self {
+        let rules = text
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim_end();
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+                let (negated, pattern) = match line.strip_prefix('!') {
+                    Some(pattern) => (true, pattern),
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+                Regex::new(&Self::pattern_regex(pattern))
+                    .map_err(|e| warn!("Ignoring .bkmrlspignore pattern '{}': {}", line, e))
+                    .ok()
+                    .map(|regex| IgnoreRule { regex, negated })
+            })
+            .collect();
+        Self { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether a file path, relative to the root and separated by '/', is ignored
+    pub fn is_ignored(&self, relative_path: &str) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.regex.is_match(relative_path))
+            .is_some_and(|rule| !rule.negated)
+    }
+
+    /// Regex matching the files a pattern ignores: the path itself or anything below it
+    fn pattern_regex(pattern: &str) -> String {
+        let directory_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/');
+
+        let mut regex = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    i += 1;
+                    if chars.get(i + 1) == Some(&'/') {
+                        regex.push_str("(?:.*/)?");
+                        i += 1;
+                    } else {
+                        regex.push_str(".*");
+                    }
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                '[' => match chars[i + 1..].iter().position(|&c| c == ']') {
+                    Some(len) => {
+                        let class: String = chars[i + 1..i + 1 + len].iter().collect();
+                        let class = match class.strip_prefix('!') {
+                            Some(rest) => format!("^{}", rest),
+                            None => class,
+                        };
+                        regex.push('[');
+                        regex.push_str(&class.replace('\\', "\\\\"));
+                        regex.push(']');
+                        i += len + 1;
+                    }
+                    None => regex.push_str(r"\["),
+                },
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+            i += 1;
+        }
+        regex.push_str(if directory_only { "/.*$" } else { "(?:/.*)?$" });
+        regex
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_unanchored_patterns_when_matching_then_apply_at_any_depth() {
+        // Arrange
+        let rules = IgnoreRules::parse("# generated code\n*.gen.rs\n\nvendor/\nCOMMIT_EDITMSG\n");
+
+        // Act & Assert
+        assert!(rules.is_ignored("src/api/types.gen.rs"));
+        assert!(rules.is_ignored("types.gen.rs"));
+        assert!(rules.is_ignored("vendor/lib.rs"));
+        assert!(rules.is_ignored("crates/app/vendor/serde/src/lib.rs"));
+        assert!(rules.is_ignored(".git/COMMIT_EDITMSG"));
+        assert!(!rules.is_ignored("src/vendor.rs"));
+        assert!(!rules.is_ignored("src/types.rs"));
+    }
+
+    #[test]
+    fn given_anchored_and_double_star_patterns_when_matching_then_respect_root_and_depth() {
+        // Arrange
+        let rules = IgnoreRules::parse("/build\ndocs/**/draft-?.md\nassets/[!a]*.svg\n");
+
+        // Act & Assert
+        assert!(rules.is_ignored("build/out/main.js"));
+        assert!(!rules.is_ignored("src/build/main.rs"));
+        assert!(rules.is_ignored("docs/draft-1.md"));
+        assert!(rules.is_ignored("docs/guide/intro/draft-2.md"));
+        assert!(!rules.is_ignored("docs/guide/draft-10.md"));
+        assert!(rules.is_ignored("assets/logo.svg"));
+        assert!(!rules.is_ignored("assets/arrow.svg"));
+    }
+
+    #[test]
+    fn given_negated_pattern_when_matching_then_last_match_wins() {
+        // Arrange
+        let rules = IgnoreRules::parse("vendor/\n!vendor/patches/**\n");
+
+        // Act & Assert
+        assert!(rules.is_ignored("vendor/serde/lib.rs"));
+        assert!(!rules.is_ignored("vendor/patches/fix.rs"));
+        assert!(IgnoreRules::parse("\n# only comments\n").is_empty());
+    }
+}
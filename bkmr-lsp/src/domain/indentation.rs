@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::FormattingOptions;
+
+/// Lines at the start of a document inspected to detect its indentation
+pub const INDENT_DETECTION_LINES: usize = 200;
+
+/// Indented lines needed before a detected indentation is trusted
+const MIN_INDENTED_LINES: usize = 3;
+
+/// Space widths a document is detected to indent by
+const SPACE_WIDTHS: [u8; 3] = [2, 4, 8];
+
+/// Unit one indentation level of a document is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IndentUnit {
+    Tabs,
+    Spaces(u8),
+}
+
+impl IndentUnit {
+    /// Indentation the editor formats a document with, e.g. sent with a formatting request
+    pub fn from_formatting_options(options: &FormattingOptions) -> Self {
+        if options.insert_spaces {
+            Self::Spaces(options.tab_size.clamp(1, u8::MAX.into()) as u8)
+        } else {
+            Self::Tabs
+        }
+    }
+
+    /// Dominant indentation of the first lines of a document
+    ///
+    /// Tabs win when tab-indented lines outnumber space-indented ones at least
+    /// two to one, and vice versa. The space width is the most frequent step
+    /// by which a line is indented deeper than the line before it. Returns
+    /// None for documents with few indented lines, and for ones mixing tabs
+    /// and spaces or widths so evenly that neither dominates.
+    pub fn detect(text: &str) -> Option<Self> {
+        let mut tab_lines = 0;
+        let mut space_lines = 0;
+        let mut steps = [0usize; SPACE_WIDTHS.len()];
+        let mut previous_width = 0;
+        for line in text.lines().take(INDENT_DETECTION_LINES) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let indentation = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+            if indentation.starts_with('\t') {
+                tab_lines += 1;
+                continue;
+            }
+            // Spaces followed by tabs align rather than indent
+            if indentation.contains('\t') {
+                continue;
+            }
+            let width = indentation.len();
+            if width > 0 {
+                space_lines += 1;
+            }
+            if let Some(index) = SPACE_WIDTHS
+                .iter()
+                .position(|step| width == previous_width + usize::from(*step))
+            {
+                steps[index] += 1;
+            }
+            previous_width = width;
+        }
+
+        if tab_lines + space_lines < MIN_INDENTED_LINES {
+            return None;
+        }
+        if tab_lines >= 2 * space_lines {
+            return Some(Self::Tabs);
+        }
+        if space_lines < 2 * tab_lines {
+            return None;
+        }
+        let most = *steps.iter().max()?;
+        let mut winners = SPACE_WIDTHS
+            .iter()
+            .zip(steps)
+            .filter(|(_, count)| *count == most);
+        match (winners.next(), winners.next()) {
+            (Some((width, _)), None) if most > 0 => Some(Self::Spaces(*width)),
+            _ => None,
+        }
+    }
+
+    /// Whitespace of one indentation level
+    pub fn text(&self) -> String {
+        match self {
+            Self::Tabs => "\t".to_string(),
+            Self::Spaces(width) => " ".repeat(usize::from(*width)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Python-like lines nested to the given levels, indented by `unit`
+    fn nested(unit: &str, levels: &[usize]) -> String {
+        levels
+            .iter()
+            .map(|level| format!("{}line", unit.repeat(*level)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn given_tab_indented_file_when_detecting_then_returns_tabs() {
+        // Arrange
+        let text = "func main() {\n\tif x {\n\t\treturn\n\t}\n}\n";
+
+        // Act & Assert
+        assert_eq!(IndentUnit::detect(text), Some(IndentUnit::Tabs));
+    }
+
+    #[test]
+    fn given_space_indented_files_when_detecting_then_returns_their_width() {
+        // Arrange
+        let levels = [0, 1, 2, 2, 1, 0, 1, 2, 3];
+
+        // Act & Assert
+        assert_eq!(
+            IndentUnit::detect(&nested("  ", &levels)),
+            Some(IndentUnit::Spaces(2))
+        );
+        assert_eq!(
+            IndentUnit::detect(&nested("    ", &levels)),
+            Some(IndentUnit::Spaces(4))
+        );
+        assert_eq!(
+            IndentUnit::detect(&nested("        ", &levels)),
+            Some(IndentUnit::Spaces(8))
+        );
+    }
+
+    #[test]
+    fn given_mixed_file_when_detecting_then_dominant_indentation_wins_or_none() {
+        // Arrange: stray tabs in a 4-space file, then as many tab as space lines
+        let mostly_spaces = format!("{}\n\tstray\n", nested("    ", &[0, 1, 2, 1, 0, 1, 2]));
+        let even = "a\n\tb\n    c\n\td\n    e\n";
+
+        // Act & Assert
+        assert_eq!(
+            IndentUnit::detect(&mostly_spaces),
+            Some(IndentUnit::Spaces(4))
+        );
+        assert_eq!(IndentUnit::detect(even), None);
+    }
+
+    #[test]
+    fn given_small_or_ambiguous_file_when_detecting_then_returns_none() {
+        // Arrange: a single indented line, then as many 2- as 4-space steps
+        let small = "def f():\n    pass\n";
+        let ambiguous = "a\n  b\n  c\nd\n    e\n    f\n";
+        let unindented = "one\ntwo\nthree\nfour\n";
+
+        // Act & Assert
+        assert_eq!(IndentUnit::detect(small), None);
+        assert_eq!(IndentUnit::detect(ambiguous), None);
+        assert_eq!(IndentUnit::detect(unindented), None);
+    }
+
+    #[test]
+    fn given_formatting_options_when_converting_then_follows_insert_spaces() {
+        // Arrange
+        let options = |insert_spaces: bool| FormattingOptions {
+            tab_size: 3,
+            insert_spaces,
+            ..Default::default()
+        };
+
+        // Act & Assert
+        assert_eq!(
+            IndentUnit::from_formatting_options(&options(true)),
+            IndentUnit::Spaces(3)
+        );
+        assert_eq!(
+            IndentUnit::from_formatting_options(&options(false)),
+            IndentUnit::Tabs
+        );
+        assert_eq!(IndentUnit::Spaces(3).text(), "   ");
+        assert_eq!(IndentUnit::Tabs.text(), "\t");
+    }
+}
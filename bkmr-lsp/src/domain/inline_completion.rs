@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{Command, Position, Range, TextDocumentIdentifier};
+
+/// Parameters of textDocument/inlineCompletion (LSP 3.18), missing from lsp-types 0.94
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+    /// Trigger kind and selected completion, not used for snippet previews
+    #[serde(default)]
+    pub context: Option<serde_json::Value>,
+}
+
+/// Text of an inline completion, plain or in snippet syntax
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InlineInsertText {
+    Plain(String),
+    /// LSP StringValue, `{"kind": "snippet", "value": ...}`
+    Snippet {
+        kind: String,
+        value: String,
+    },
+}
+
+impl InlineInsertText {
+    pub fn snippet(value: String) -> Self {
+        Self::Snippet {
+            kind: "snippet".to_string(),
+            value,
+        }
+    }
+}
+
+/// A ghost text suggestion replacing the range with its insert text
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionItem {
+    pub insert_text: InlineInsertText,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter_text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<Command>,
+}
+
+/// Result of textDocument/inlineCompletion
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InlineCompletionList {
+    pub items: Vec<InlineCompletionItem>,
+}
@@ -1,3 +1,15 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::domain::{LanguageDefinition, LanguageDefinitions};
+
+/// Languages where every `$1` or `$(...)` is literal text, unless configured otherwise
+pub const DEFAULT_ESCAPE_ALL_DOLLARS_FOR: &[&str] = &["make", "makefile", "awk", "perl", "bash"];
+
+/// Languages where bookmark URLs are completed besides snippets, unless configured otherwise
+pub const DEFAULT_URL_COMPLETION_LANGUAGES: &[&str] = &["markdown"];
+
 /// Language-specific information for code pattern translation
 #[derive(Debug, Clone, PartialEq)]
 pub struct LanguageInfo {
@@ -20,156 +32,253 @@ impl LanguageInfo {
     }
 }
 
+/// Quote characters of a language's string literals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StringSyntax {
+    /// Characters opening and closing a string literal
+    pub quotes: &'static [char],
+    /// Quotes whose strings may span lines, e.g. '`' in JavaScript
+    pub multiline_quotes: &'static [char],
+}
+
+/// Editor language IDs whose snippets are tagged differently in bkmr
+fn builtin_aliases(language_id: &str) -> &'static [&'static str] {
+    match language_id {
+        "typescriptreact" => &["typescript"],
+        "javascriptreact" => &["javascript"],
+        "shellscript" => &["sh", "bash", "shell"],
+        "shell" => &["sh", "bash"],
+        _ => &[],
+    }
+}
+
+/// Mapping from editor language IDs to the bkmr tags of their snippets
+///
+/// User entries replace the built-in aliases of the same language ID.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct LanguageMap {
+    overrides: HashMap<String, Vec<String>>,
+}
+
+impl LanguageMap {
+    /// Tags matching snippets for a language ID, mapped tags first, the ID itself last
+    pub fn tags(&self, language_id: &str) -> Vec<String> {
+        let key = language_id.to_lowercase();
+        let mut tags: Vec<String> = match self.overrides.get(&key) {
+            Some(tags) => tags.clone(),
+            None => builtin_aliases(&key)
+                .iter()
+                .map(|tag| tag.to_string())
+                .collect(),
+        };
+        if !tags.iter().any(|tag| tag == language_id) {
+            tags.push(language_id.to_string());
+        }
+        tags
+    }
+}
+
+impl FromStr for LanguageMap {
+    type Err = String;
+
+    /// Parse "from=to,from2=to2", repeating a language ID maps it to several tags
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut overrides: HashMap<String, Vec<String>> = HashMap::new();
+        for entry in s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let (from, to) = entry
+                .split_once('=')
+                .map(|(from, to)| (from.trim(), to.trim()))
+                .filter(|(from, to)| !from.is_empty() && !to.is_empty() && !to.contains('='))
+                .ok_or_else(|| format!("invalid language mapping '{}', expected from=to", entry))?;
+            let tags = overrides.entry(from.to_lowercase()).or_default();
+            if !tags.iter().any(|tag| tag == to) {
+                tags.push(to.to_string());
+            }
+        }
+        Ok(Self { overrides })
+    }
+}
+
 /// Language registry for mapping language IDs to language information
-pub struct LanguageRegistry;
+///
+/// Built from the configured language map and definitions, the default one
+/// knows the built-in aliases and languages only.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageRegistry {
+    language_map: LanguageMap,
+    /// Comment syntax and indentation of languages, built-in or loaded from the user's file
+    definitions: LanguageDefinitions,
+}
+
+impl Default for LanguageRegistry {
+    fn default() -> Self {
+        Self::new(LanguageMap::default(), LanguageDefinitions::builtin())
+    }
+}
 
 impl LanguageRegistry {
-    /// Get language information for a specific language ID
-    pub fn get_language_info(language_id: &str) -> LanguageInfo {
-        match language_id.to_lowercase().as_str() {
-            "rust" => LanguageInfo::new(
-                Some("//".to_string()),
-                Some(("/*".to_string(), "*/".to_string())),
-                "    ".to_string(),
-            ),
-            "javascript" | "js" => LanguageInfo::new(
-                Some("//".to_string()),
-                Some(("/*".to_string(), "*/".to_string())),
-                "  ".to_string(),
-            ),
-            "typescript" | "ts" => LanguageInfo::new(
-                Some("//".to_string()),
-                Some(("/*".to_string(), "*/".to_string())),
-                "  ".to_string(),
-            ),
-            "python" => LanguageInfo::new(
-                Some("#".to_string()),
-                Some(("\"\"\"".to_string(), "\"\"\"".to_string())),
-                "    ".to_string(),
-            ),
-            "go" => LanguageInfo::new(
-                Some("//".to_string()),
-                Some(("/*".to_string(), "*/".to_string())),
-                "\t".to_string(),
-            ),
-            "java" => LanguageInfo::new(
-                Some("//".to_string()),
-                Some(("/*".to_string(), "*/".to_string())),
-                "    ".to_string(),
-            ),
-            "c" => LanguageInfo::new(
-                Some("//".to_string()),
-                Some(("/*".to_string(), "*/".to_string())),
-                "    ".to_string(),
-            ),
-            "cpp" | "c++" => LanguageInfo::new(
-                Some("//".to_string()),
-                Some(("/*".to_string(), "*/".to_string())),
-                "    ".to_string(),
-            ),
-            "html" => LanguageInfo::new(
-                None,
-                Some(("<!--".to_string(), "-->".to_string())),
-                "  ".to_string(),
-            ),
-            "css" => LanguageInfo::new(
-                None,
-                Some(("/*".to_string(), "*/".to_string())),
-                "  ".to_string(),
-            ),
-            "scss" => LanguageInfo::new(
-                Some("//".to_string()),
-                Some(("/*".to_string(), "*/".to_string())),
-                "  ".to_string(),
-            ),
-            "ruby" => LanguageInfo::new(
-                Some("#".to_string()),
-                Some(("=begin".to_string(), "=end".to_string())),
-                "  ".to_string(),
-            ),
-            "php" => LanguageInfo::new(
-                Some("//".to_string()),
-                Some(("/*".to_string(), "*/".to_string())),
-                "    ".to_string(),
-            ),
-            "swift" => LanguageInfo::new(
-                Some("//".to_string()),
-                Some(("/*".to_string(), "*/".to_string())),
-                "    ".to_string(),
-            ),
-            "kotlin" => LanguageInfo::new(
-                Some("//".to_string()),
-                Some(("/*".to_string(), "*/".to_string())),
-                "    ".to_string(),
-            ),
-            "shell" | "bash" | "sh" => {
-                LanguageInfo::new(Some("#".to_string()), None, "    ".to_string())
-            }
-            "yaml" | "yml" => LanguageInfo::new(Some("#".to_string()), None, "  ".to_string()),
-            "json" => LanguageInfo::new(None, None, "  ".to_string()),
-            "markdown" | "md" => LanguageInfo::new(
-                None,
-                Some(("<!--".to_string(), "-->".to_string())),
-                "  ".to_string(),
-            ),
-            "xml" => LanguageInfo::new(
-                None,
-                Some(("<!--".to_string(), "-->".to_string())),
-                "  ".to_string(),
-            ),
-            "vim" | "viml" => LanguageInfo::new(Some("\"".to_string()), None, "  ".to_string()),
-            // Default fallback for unknown languages
-            _ => LanguageInfo::new(Some("#".to_string()), None, "    ".to_string()),
+    pub fn new(language_map: LanguageMap, definitions: LanguageDefinitions) -> Self {
+        Self {
+            language_map,
+            definitions,
         }
     }
 
-    /// Get legacy comment syntax for backward compatibility
-    pub fn get_comment_syntax(file_path: &str) -> &'static str {
+    /// Comment syntax and indentation of the known languages
+    pub fn definitions(&self) -> &LanguageDefinitions {
+        &self.definitions
+    }
+
+    /// Tags matching snippets for a language ID under the configured language map
+    pub fn language_tags(&self, language_id: &str) -> Vec<String> {
+        self.language_map.tags(language_id)
+    }
+
+    /// Language ID named by a file extension or alias, e.g. `py` for python, lowercased if unknown
+    pub fn language_id_for(&self, name: &str) -> String {
+        self.definitions
+            .language_id(name)
+            .map_or_else(|| name.to_lowercase(), str::to_string)
+    }
+
+    /// Whether `$1` and `$(...)` are the language's own syntax rather than tabstops
+    ///
+    /// True when the language ID or one of its tags is in the given list, so
+    /// that e.g. `shellscript` follows `bash`.
+    pub fn escapes_all_dollars(&self, language_id: &str, languages: &[String]) -> bool {
+        self.language_tags(language_id)
+            .iter()
+            .chain(std::iter::once(&language_id.to_string()))
+            .any(|tag| {
+                languages
+                    .iter()
+                    .any(|language| language.eq_ignore_ascii_case(tag))
+            })
+    }
+
+    /// Get language information for a specific language ID, following its aliases
+    pub fn get_language_info(&self, language_id: &str) -> LanguageInfo {
+        self.language_tags(language_id)
+            .iter()
+            .find_map(|tag| self.defined_language_info(tag))
+            // Default fallback for unknown languages
+            .unwrap_or_else(|| LanguageInfo::new(Some("#".to_string()), None, "    ".to_string()))
+    }
+
+    /// String literal syntax for a language ID, following its aliases
+    ///
+    /// Unknown languages get double-quoted strings that end with the line.
+    pub fn string_syntax(&self, language_id: &str) -> StringSyntax {
+        self.language_tags(language_id)
+            .iter()
+            .find_map(|tag| Self::builtin_string_syntax(tag))
+            .unwrap_or(StringSyntax {
+                quotes: &['"'],
+                multiline_quotes: &[],
+            })
+    }
+
+    /// String literal syntax of languages known by their own ID
+    fn builtin_string_syntax(language_id: &str) -> Option<StringSyntax> {
+        let (quotes, multiline_quotes): (&'static [char], &'static [char]) =
+            match language_id.to_lowercase().as_str() {
+                // Single quotes are char literals and lifetimes
+                "rust" => (&['"'], &['"']),
+                "javascript" | "js" | "typescript" | "ts" => (&['"', '\'', '`'], &['`']),
+                "go" => (&['"', '`'], &['`']),
+                "python" | "css" | "scss" => (&['"', '\''], &[]),
+                "java" | "c" | "cpp" | "c++" | "swift" | "kotlin" | "json" => (&['"'], &[]),
+                "ruby" | "php" | "shell" | "bash" | "sh" | "yaml" | "yml" | "html" | "xml" => {
+                    (&['"', '\''], &['"', '\''])
+                }
+                // '"' starts a comment in vim script
+                "vim" | "viml" => (&['\''], &[]),
+                // Apostrophes in prose are no quotes
+                "markdown" | "md" => (&[], &[]),
+                _ => return None,
+            };
+        Some(StringSyntax {
+            quotes,
+            multiline_quotes,
+        })
+    }
+
+    /// Language information of a language ID or alias defined in the registry
+    fn defined_language_info(&self, language_id: &str) -> Option<LanguageInfo> {
+        self.definitions
+            .get(language_id)
+            .map(LanguageDefinition::info)
+    }
+
+    /// Language ID a file's extension belongs to, None without a known extension
+    pub fn language_for_file(&self, file_path: &str) -> Option<String> {
         let extension = std::path::Path::new(file_path)
             .extension()
-            .and_then(|s| s.to_str())
-            .unwrap_or("");
-
-        let language_id = Self::extension_to_language_id(extension);
-
-        match language_id {
-            "rust" | "javascript" | "typescript" | "go" | "java" | "c" | "cpp" | "swift"
-            | "kotlin" | "scss" | "php" => "//",
-            "python" | "shell" | "yaml" => "#",
-            "html" | "markdown" | "xml" => "<!--",
-            "css" => "/*",
-            "vim" => "\"",
-            _ => "#",
-        }
+            .and_then(|s| s.to_str())?;
+        self.definitions
+            .language_for_extension(extension)
+            .map(str::to_string)
+    }
+
+    /// Language information of the language a file extension belongs to
+    fn extension_language_info(&self, file_path: &str) -> Option<LanguageInfo> {
+        self.language_for_file(file_path)
+            .and_then(|language_id| self.defined_language_info(&language_id))
+    }
+
+    /// File extension of documents in a language, following its aliases, None if unknown
+    pub fn extension_for(&self, language_id: &str) -> Option<String> {
+        let tags = self.language_tags(language_id);
+        let definitions = &self.definitions;
+        tags.iter().find_map(|tag| {
+            definitions
+                .get(tag)
+                .and_then(LanguageDefinition::extension)
+                .map(str::to_string)
+        })
     }
 
-    /// Map file extension to language ID
-    fn extension_to_language_id(extension: &str) -> &str {
-        match extension {
-            "rs" => "rust",
-            "js" | "mjs" => "javascript",
-            "ts" | "tsx" => "typescript",
-            "py" | "pyw" => "python",
-            "go" => "go",
-            "java" => "java",
-            "c" | "h" => "c",
-            "cpp" | "cc" | "cxx" | "hpp" => "cpp",
-            "html" | "htm" => "html",
-            "css" => "css",
-            "scss" => "scss",
-            "rb" => "ruby",
-            "php" => "php",
-            "swift" => "swift",
-            "kt" | "kts" => "kotlin",
-            "sh" | "bash" | "zsh" => "shell",
-            "yaml" | "yml" => "yaml",
-            "json" => "json",
-            "md" | "markdown" => "markdown",
-            "xml" => "xml",
-            "vim" => "vim",
-            _ => "unknown",
+    /// Comment delimiters for a document, by its language ID if known, else by file extension
+    ///
+    /// Returns the comment start and, for block comments, the end.
+    pub fn comment_delimiters(
+        &self,
+        language_id: Option<&str>,
+        file_path: &str,
+    ) -> (String, Option<String>) {
+        let info = language_id
+            .and_then(|language_id| {
+                self.language_tags(language_id)
+                    .iter()
+                    .find_map(|tag| self.defined_language_info(tag))
+            })
+            .filter(|info| info.line_comment.is_some() || info.block_comment.is_some())
+            .or_else(|| self.extension_language_info(file_path));
+        match info {
+            Some(LanguageInfo {
+                line_comment: Some(line_comment),
+                ..
+            }) => (line_comment, None),
+            Some(LanguageInfo {
+                block_comment: Some((start, end)),
+                ..
+            }) => (start, Some(end)),
+            _ => ("#".to_string(), None),
         }
     }
+
+    /// Get legacy comment syntax for backward compatibility
+    ///
+    /// The line comment of the language the file extension belongs to, else
+    /// its block comment start, '#' for unknown extensions.
+    pub fn get_comment_syntax(&self, file_path: &str) -> String {
+        self.comment_delimiters(None, file_path).0
+    }
 }
 
 #[cfg(test)]
@@ -182,7 +291,7 @@ mod tests {
         let language_id = "rust";
 
         // Act
-        let language_info = LanguageRegistry::get_language_info(language_id);
+        let language_info = LanguageRegistry::default().get_language_info(language_id);
 
         // Assert
         assert_eq!(language_info.line_comment, Some("//".to_string()));
@@ -199,7 +308,7 @@ mod tests {
         let language_id = "python";
 
         // Act
-        let language_info = LanguageRegistry::get_language_info(language_id);
+        let language_info = LanguageRegistry::default().get_language_info(language_id);
 
         // Assert
         assert_eq!(language_info.line_comment, Some("#".to_string()));
@@ -210,13 +319,50 @@ mod tests {
         assert_eq!(language_info.indent_char, "    ");
     }
 
+    #[test]
+    fn given_language_ids_when_getting_extension_then_follows_aliases() {
+        // Act & Assert
+        assert_eq!(
+            LanguageRegistry::default()
+                .extension_for("python")
+                .as_deref(),
+            Some("py")
+        );
+        assert_eq!(
+            LanguageRegistry::default().extension_for("rust").as_deref(),
+            Some("rs")
+        );
+        assert_eq!(
+            LanguageRegistry::default()
+                .extension_for("Markdown")
+                .as_deref(),
+            Some("md")
+        );
+        assert_eq!(
+            LanguageRegistry::default()
+                .extension_for("typescriptreact")
+                .as_deref(),
+            Some("ts")
+        );
+        assert_eq!(
+            LanguageRegistry::default()
+                .extension_for("shellscript")
+                .as_deref(),
+            Some("sh")
+        );
+        assert_eq!(
+            LanguageRegistry::default().extension_for("unknownlang"),
+            None
+        );
+    }
+
     #[test]
     fn given_unknown_language_when_getting_info_then_returns_default_settings() {
         // Arrange
         let language_id = "unknownlang";
 
         // Act
-        let language_info = LanguageRegistry::get_language_info(language_id);
+        let language_info = LanguageRegistry::default().get_language_info(language_id);
 
         // Assert
         assert_eq!(language_info.line_comment, Some("#".to_string()));
@@ -224,27 +370,140 @@ mod tests {
         assert_eq!(language_info.indent_char, "    ");
     }
 
+    #[test]
+    fn given_languages_when_getting_string_syntax_then_returns_their_quotes() {
+        // Act
+        let rust = LanguageRegistry::default().string_syntax("rust");
+        let typescript = LanguageRegistry::default().string_syntax("typescriptreact");
+        let unknown = LanguageRegistry::default().string_syntax("unknownlang");
+
+        // Assert
+        assert_eq!(rust.quotes, &['"']);
+        assert_eq!(typescript.quotes, &['"', '\'', '`']);
+        assert_eq!(typescript.multiline_quotes, &['`']);
+        assert_eq!(unknown.multiline_quotes, &[] as &[char]);
+    }
+
+    #[test]
+    fn given_aliased_language_when_getting_info_then_uses_target_language() {
+        // Act
+        let language_info = LanguageRegistry::default().get_language_info("typescriptreact");
+
+        // Assert
+        assert_eq!(language_info.line_comment, Some("//".to_string()));
+        assert_eq!(language_info.indent_char, "  ");
+    }
+
+    #[test]
+    fn given_language_map_when_getting_tags_then_overrides_builtin_aliases() {
+        // Arrange
+        let language_map: LanguageMap = "markdown=md, shellscript=zsh,shellscript=sh"
+            .parse()
+            .expect("valid language map");
+
+        // Act & Assert
+        assert_eq!(language_map.tags("markdown"), vec!["md", "markdown"]);
+        assert_eq!(
+            language_map.tags("shellscript"),
+            vec!["zsh", "sh", "shellscript"]
+        );
+        assert_eq!(
+            LanguageMap::default().tags("typescriptreact"),
+            vec!["typescript", "typescriptreact"]
+        );
+        assert_eq!(LanguageMap::default().tags("rust"), vec!["rust"]);
+    }
+
+    #[test]
+    fn given_registries_with_different_maps_when_getting_tags_then_each_keeps_its_own() {
+        // Arrange
+        let configured = LanguageRegistry::new(
+            "markdown=md".parse().expect("valid language map"),
+            LanguageDefinitions::builtin(),
+        );
+        let builtin = LanguageRegistry::default();
+
+        // Act
+        let configured_tags = configured.language_tags("markdown");
+        let builtin_tags = builtin.language_tags("markdown");
+
+        // Assert
+        assert_eq!(configured_tags, vec!["md", "markdown"]);
+        assert_eq!(builtin_tags, vec!["markdown"]);
+    }
+
+    #[test]
+    fn given_malformed_language_map_when_parsing_then_returns_error() {
+        // Act & Assert
+        for malformed in ["markdown", "=md", "markdown=", "a=b=c", "md=markdown,rust"] {
+            let error = malformed
+                .parse::<LanguageMap>()
+                .expect_err("malformed language map");
+            assert!(
+                error.contains("expected from=to"),
+                "{}: {}",
+                malformed,
+                error
+            );
+        }
+        assert_eq!("".parse::<LanguageMap>(), Ok(LanguageMap::default()));
+    }
+
     #[test]
     fn given_rust_file_when_getting_comment_syntax_then_returns_double_slash() {
         // Arrange
         let file_path = "test.rs";
 
         // Act
-        let comment_syntax = LanguageRegistry::get_comment_syntax(file_path);
+        let comment_syntax = LanguageRegistry::default().get_comment_syntax(file_path);
 
         // Assert
         assert_eq!(comment_syntax, "//");
     }
 
+    #[test]
+    fn given_language_id_when_getting_comment_delimiters_then_prefers_it_over_extension() {
+        // Act & Assert
+        assert_eq!(
+            LanguageRegistry::default().comment_delimiters(Some("shellscript"), "/tmp/run.txt"),
+            ("#".to_string(), None)
+        );
+        assert_eq!(
+            LanguageRegistry::default().comment_delimiters(Some("css"), "/tmp/site.txt"),
+            ("/*".to_string(), Some("*/".to_string()))
+        );
+        assert_eq!(
+            LanguageRegistry::default().comment_delimiters(Some("plaintext"), "/tmp/index.html"),
+            ("<!--".to_string(), Some("-->".to_string()))
+        );
+    }
+
     #[test]
     fn given_python_file_when_getting_comment_syntax_then_returns_hash() {
         // Arrange
         let file_path = "test.py";
 
         // Act
-        let comment_syntax = LanguageRegistry::get_comment_syntax(file_path);
+        let comment_syntax = LanguageRegistry::default().get_comment_syntax(file_path);
 
         // Assert
         assert_eq!(comment_syntax, "#");
     }
+
+    #[test]
+    fn given_language_list_when_deciding_dollar_escaping_then_follows_aliases() {
+        // Arrange
+        let defaults: Vec<String> = DEFAULT_ESCAPE_ALL_DOLLARS_FOR
+            .iter()
+            .map(|language| language.to_string())
+            .collect();
+
+        // Act & Assert
+        assert!(LanguageRegistry::default().escapes_all_dollars("makefile", &defaults));
+        assert!(LanguageRegistry::default().escapes_all_dollars("awk", &defaults));
+        assert!(LanguageRegistry::default().escapes_all_dollars("shellscript", &defaults));
+        assert!(!LanguageRegistry::default().escapes_all_dollars("rust", &defaults));
+        assert!(!LanguageRegistry::default().escapes_all_dollars("makefile", &[]));
+        assert!(LanguageRegistry::default().escapes_all_dollars("Python", &["python".to_string()]));
+    }
 }
@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+
+use crate::domain::LanguageInfo;
+
+/// Language definitions compiled into the server
+const BUILTIN_LANGUAGES: &str = include_str!("languages.toml");
+
+/// User language definitions, relative to the config directory
+const LANGUAGES_FILE: &str = "bkmr-lsp/languages.toml";
+
+/// Indentation of languages whose definition sets none
+const DEFAULT_INDENT: &str = "    ";
+
+/// Keys of a language table
+const KEYS: &[&str] = &[
+    "line_comment",
+    "block_comment",
+    "indent",
+    "extensions",
+    "aliases",
+];
+
+/// Value of a key in a languages file
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DefinitionValue {
+    String(String),
+    Array(Vec<String>),
+}
+
+/// Comment syntax, indentation and file extensions of one language
+///
+/// Unset keys keep the value they are merged over; an empty line comment or
+/// block comment removes it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LanguageDefinition {
+    line_comment: Option<String>,
+    /// Start and end, empty for none
+    block_comment: Option<Vec<String>>,
+    indent: Option<String>,
+    /// Lowercase file extensions without the dot
+    extensions: Vec<String>,
+    /// Other lowercase language IDs sharing the definition
+    aliases: Vec<String>,
+}
+
+impl LanguageDefinition {
+    /// Comment syntax and indentation used for snippet translation
+    pub fn info(&self) -> LanguageInfo {
+        LanguageInfo::new(
+            self.line_comment
+                .clone()
+                .filter(|line_comment| !line_comment.is_empty()),
+            match self.block_comment.as_deref() {
+                Some([start, end]) => Some((start.clone(), end.clone())),
+                _ => None,
+            },
+            self.indent
+                .clone()
+                .unwrap_or_else(|| DEFAULT_INDENT.to_string()),
+        )
+    }
+
+    /// File extension of documents in this language, the first one listed
+    pub fn extension(&self) -> Option<&str> {
+        self.extensions.first().map(String::as_str)
+    }
+
+    /// Set one known key, failing for values of the wrong type
+    fn set(&mut self, key: &str, value: DefinitionValue) -> Result<(), String> {
+        match (key, value) {
+            ("line_comment", DefinitionValue::String(line_comment)) => {
+                self.line_comment = Some(line_comment)
+            }
+            ("block_comment", DefinitionValue::Array(delimiters))
+                if delimiters.is_empty() || delimiters.len() == 2 =>
+            {
+                self.block_comment = Some(delimiters)
+            }
+            ("block_comment", _) => {
+                return Err("block_comment must be [start, end], or [] for none".to_string());
+            }
+            ("indent", DefinitionValue::String(indent)) => self.indent = Some(indent),
+            ("extensions", DefinitionValue::Array(extensions)) => {
+                self.extensions = extensions
+                    .iter()
+                    .map(|extension| extension.trim_start_matches('.').to_lowercase())
+                    .collect()
+            }
+            ("aliases", DefinitionValue::Array(aliases)) => {
+                self.aliases = aliases.iter().map(|alias| alias.to_lowercase()).collect()
+            }
+            ("extensions" | "aliases", _) => {
+                return Err(format!("{} must be an array of strings", key));
+            }
+            (key, _) => return Err(format!("{} must be a string", key)),
+        }
+        Ok(())
+    }
+
+    /// Take every key the other definition sets
+    fn merge(&mut self, other: LanguageDefinition) {
+        if other.line_comment.is_some() {
+            self.line_comment = other.line_comment;
+        }
+        if other.block_comment.is_some() {
+            self.block_comment = other.block_comment;
+        }
+        if other.indent.is_some() {
+            self.indent = other.indent;
+        }
+        if !other.extensions.is_empty() {
+            self.extensions = other.extensions;
+        }
+        if !other.aliases.is_empty() {
+            self.aliases = other.aliases;
+        }
+    }
+}
+
+/// Language definitions by lowercase language ID, read from a languages file
+///
+/// The built-in definitions are compiled in from `languages.toml`; a user
+/// file is merged over them. The file format is line based and borrows its
+/// syntax from TOML without being TOML: `[language]` headers, `key = value`
+/// lines whose value is a "basic" or 'literal' string or an array of them
+/// on one line, and `#` comments. Multi-line arrays and strings, dotted
+/// table names and `\u` escapes are rejected with the line they are on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LanguageDefinitions {
+    languages: HashMap<String, LanguageDefinition>,
+}
+
+impl LanguageDefinitions {
+    /// The definitions compiled into the server
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN_LANGUAGES).expect("built-in language definitions are valid")
+    }
+
+    /// Built-in definitions with the user's file merged over them
+    ///
+    /// Without a path the file in the config directory is used if it exists.
+    /// A file that cannot be read or parsed is logged and ignored.
+    pub fn load(path: Option<&Path>) -> Self {
+        match Self::file_path(path) {
+            Some((path, explicit)) => {
+                let text = std::fs::read_to_string(&path);
+                Self::with_file(&path, explicit, text)
+            }
+            None => Self::builtin(),
+        }
+    }
+
+    /// Like [`LanguageDefinitions::load`], reading the file without blocking the runtime
+    pub async fn load_async(path: Option<&Path>) -> Self {
+        match Self::file_path(path) {
+            Some((path, explicit)) => {
+                let text = tokio::fs::read_to_string(&path).await;
+                Self::with_file(&path, explicit, text)
+            }
+            None => Self::builtin(),
+        }
+    }
+
+    /// File to load and whether it was given explicitly, None without a config directory
+    fn file_path(path: Option<&Path>) -> Option<(PathBuf, bool)> {
+        match path {
+            Some(path) => Some((path.to_path_buf(), true)),
+            None => Self::default_path().map(|path| (path, false)),
+        }
+    }
+
+    /// Built-in definitions with the text read from a file merged over them
+    ///
+    /// A missing file is only an error when it was given explicitly.
+    fn with_file(path: &Path, explicit: bool, text: std::io::Result<String>) -> Self {
+        let mut definitions = Self::builtin();
+        match text {
+            Ok(text) => match Self::parse(&text) {
+                Ok(user) => {
+                    info!("Read language definitions from {}", path.display());
+                    definitions.merge(user);
+                }
+                Err(e) => error!(
+                    "Ignoring malformed language definitions in {}, {}",
+                    path.display(),
+                    e
+                ),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => {}
+            Err(e) => error!(
+                "Failed to read language definitions {}: {}",
+                path.display(),
+                e
+            ),
+        }
+        definitions
+    }
+
+    /// `$XDG_CONFIG_HOME/bkmr-lsp/languages.toml`, below `~/.config` when the variable is unset
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join(LANGUAGES_FILE))
+    }
+
+    /// Parse a languages file, failing on syntax errors and values of the wrong type
+    ///
+    /// Errors name the line; unknown keys are logged and ignored.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut languages: HashMap<String, LanguageDefinition> = HashMap::new();
+        let mut current: Option<String> = None;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let at = |e: String| format!("line {}: {}", index + 1, e);
+
+            if let Some(header) = line.strip_prefix('[') {
+                let name = Self::table_name(header).map_err(at)?;
+                if languages.contains_key(&name) {
+                    return Err(at(format!("language '{}' defined twice", name)));
+                }
+                languages.insert(name.clone(), LanguageDefinition::default());
+                current = Some(name);
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| at("expected key = value".to_string()))?;
+            let key = key.trim();
+            let value = Self::value(value.trim()).map_err(at)?;
+            let Some(name) = &current else {
+                return Err(at(format!("key '{}' outside a language table", key)));
+            };
+            if !KEYS.contains(&key) {
+                warn!(
+                    "Ignoring unknown key '{}' of language '{}' on line {}",
+                    key,
+                    name,
+                    index + 1
+                );
+                continue;
+            }
+            languages
+                .get_mut(name)
+                .expect("current language is defined")
+                .set(key, value)
+                .map_err(at)?;
+        }
+        Ok(Self { languages })
+    }
+
+    /// Definition of a language by its ID or one of its aliases, ignoring case
+    pub fn get(&self, language_id: &str) -> Option<&LanguageDefinition> {
+        let language_id = language_id.to_lowercase();
+        self.languages.get(&language_id).or_else(|| {
+            self.languages
+                .values()
+                .find(|definition| definition.aliases.contains(&language_id))
+        })
+    }
+
+    /// Language defined under a name, alias or file extension, ignoring case
+    pub fn language_id(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.languages
+            .get_key_value(&name)
+            .or_else(|| {
+                self.languages
+                    .iter()
+                    .find(|(_, definition)| definition.aliases.contains(&name))
+            })
+            .map(|(id, _)| id.as_str())
+            .or_else(|| self.language_for_extension(&name))
+    }
+
+    /// Language whose definition lists a file extension, ignoring case
+    pub fn language_for_extension(&self, extension: &str) -> Option<&str> {
+        let extension = extension.to_lowercase();
+        self.languages
+            .iter()
+            .find(|(_, definition)| definition.extensions.contains(&extension))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Merge definitions over these key by key, adding languages that are new
+    ///
+    /// Extensions listed by a merged language are taken from all others.
+    pub fn merge(&mut self, overrides: LanguageDefinitions) {
+        for (name, definition) in overrides.languages {
+            for other in self.languages.values_mut() {
+                other
+                    .extensions
+                    .retain(|extension| !definition.extensions.contains(extension));
+            }
+            self.languages.entry(name).or_default().merge(definition);
+        }
+    }
+
+    /// Lowercase name of a `[language]` header, given the text after '['
+    fn table_name(header: &str) -> Result<String, String> {
+        let (name, rest) = header
+            .split_once(']')
+            .ok_or_else(|| "unterminated table header".to_string())?;
+        Self::ensure_line_end(rest)?;
+        let name = name.trim();
+        let name = match name
+            .strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+        {
+            Some(quoted) => quoted,
+            None if name.contains('.') => {
+                return Err(format!(
+                    "unsupported table name '{}', dotted table names are not supported",
+                    name
+                ));
+            }
+            None if name.contains('"') => {
+                return Err(format!("unsupported table name '{}'", name));
+            }
+            None => name,
+        };
+        if name.is_empty() {
+            return Err("empty table name".to_string());
+        }
+        Ok(name.to_lowercase())
+    }
+
+    /// A string or a single-line array of strings, optionally followed by a comment
+    fn value(text: &str) -> Result<DefinitionValue, String> {
+        let (value, rest) = match text.strip_prefix('[') {
+            Some(items) => {
+                let (items, rest) = Self::array(items)?;
+                (DefinitionValue::Array(items), rest)
+            }
+            None => {
+                let (string, rest) = Self::string(text)?;
+                (DefinitionValue::String(string), rest)
+            }
+        };
+        Self::ensure_line_end(rest)?;
+        Ok(value)
+    }
+
+    /// Strings of an array up to its ']', and the text after it
+    fn array(mut text: &str) -> Result<(Vec<String>, &str), String> {
+        let mut items = Vec::new();
+        loop {
+            text = text.trim_start();
+            if let Some(rest) = text.strip_prefix(']') {
+                return Ok((items, rest));
+            }
+            if text.is_empty() {
+                return Err("unterminated array, arrays must fit on one line".to_string());
+            }
+            let (item, rest) = Self::string(text)?;
+            items.push(item);
+            text = rest.trim_start();
+            match text.strip_prefix(',') {
+                Some(rest) => text = rest,
+                None if text.starts_with(']') => {}
+                None => return Err("expected ',' or ']' in array".to_string()),
+            }
+        }
+    }
+
+    /// A basic "..." or literal '...' string at the start of the text, and the text after it
+    fn string(text: &str) -> Result<(String, &str), String> {
+        let mut chars = text.char_indices();
+        let quote = match chars.next() {
+            Some((_, quote @ ('"' | '\''))) if text[1..].starts_with([quote, quote]) => {
+                return Err("multi-line strings are not supported".to_string());
+            }
+            Some((_, quote @ ('"' | '\''))) => quote,
+            _ => {
+                return Err(format!(
+                    "unsupported value '{}', expected a string or an array of strings",
+                    text
+                ));
+            }
+        };
+        let mut string = String::new();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                c if c == quote => return Ok((string, &text[index + 1..])),
+                '\\' if quote == '"' => match chars.next().map(|(_, escaped)| escaped) {
+                    Some('\\') => string.push('\\'),
+                    Some('"') => string.push('"'),
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('r') => string.push('\r'),
+                    Some(other) => return Err(format!("unsupported escape '\\{}'", other)),
+                    None => break,
+                },
+                c => string.push(c),
+            }
+        }
+        Err("unterminated string".to_string())
+    }
+
+    /// A string value of a `key = "value"` line, optionally followed by a comment
+    pub(crate) fn string_value(text: &str) -> Result<String, String> {
+        let (string, rest) = Self::string(text.trim())?;
+        Self::ensure_line_end(rest)?;
+        Ok(string)
+    }
+
+    /// Fail unless only whitespace or a comment follows
+    fn ensure_line_end(rest: &str) -> Result<(), String> {
+        let rest = rest.trim_start();
+        if rest.is_empty() || rest.starts_with('#') {
+            Ok(())
+        } else {
+            Err(format!("unexpected '{}'", rest))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_builtin_definitions_when_parsing_then_covers_languages_aliases_and_extensions() {
+        // Act
+        let definitions = LanguageDefinitions::builtin();
+
+        // Assert
+        let rust = definitions.get("Rust").expect("rust definition").info();
+        assert_eq!(rust.line_comment.as_deref(), Some("//"));
+        assert_eq!(
+            rust.block_comment,
+            Some(("/*".to_string(), "*/".to_string()))
+        );
+        assert_eq!(rust.indent_char, "    ");
+        assert_eq!(
+            definitions
+                .get("python")
+                .expect("python")
+                .info()
+                .block_comment,
+            Some(("\"\"\"".to_string(), "\"\"\"".to_string()))
+        );
+        assert_eq!(definitions.get("go").expect("go").info().indent_char, "\t");
+        let html = definitions.get("html").expect("html").info();
+        assert_eq!(html.line_comment, None);
+        assert_eq!(definitions.get("c++"), definitions.get("cpp"));
+        assert_eq!(
+            definitions
+                .get("viml")
+                .expect("vim alias")
+                .info()
+                .line_comment,
+            Some("\"".to_string())
+        );
+        assert_eq!(definitions.get("zig"), None);
+    }
+
+    #[test]
+    fn given_extensions_when_looking_up_then_returns_their_language() {
+        // Arrange
+        let definitions = LanguageDefinitions::builtin();
+
+        // Act & Assert
+        assert_eq!(definitions.language_for_extension("rs"), Some("rust"));
+        assert_eq!(
+            definitions.language_for_extension("TSX"),
+            Some("typescript")
+        );
+        assert_eq!(definitions.language_for_extension("zsh"), Some("shell"));
+        assert_eq!(definitions.language_for_extension("txt"), None);
+    }
+
+    #[test]
+    fn given_user_definitions_when_merging_then_overrides_keys_and_keeps_the_rest() {
+        // Arrange
+        let mut definitions = LanguageDefinitions::builtin();
+        let user = LanguageDefinitions::parse(
+            r#"
+# Two spaces for Rust, and .rs.in files are Rust too
+[rust]
+indent = "  "      # narrower
+extensions = ["rs", "rs.in"]
+
+[python]
+block_comment = []
+tabs = "never"
+
+[c]
+extensions = [".H"]
+"#,
+        )
+        .expect("valid user definitions");
+
+        // Act
+        definitions.merge(user);
+
+        // Assert
+        let rust = definitions.get("rust").expect("rust").info();
+        assert_eq!(rust.indent_char, "  ");
+        assert_eq!(rust.line_comment.as_deref(), Some("//"));
+        assert_eq!(definitions.language_for_extension("rs.in"), Some("rust"));
+        let python = definitions.get("python").expect("python").info();
+        assert_eq!(python.block_comment, None);
+        assert_eq!(python.line_comment.as_deref(), Some("#"));
+        assert_eq!(definitions.language_for_extension("h"), Some("c"));
+        assert_eq!(definitions.language_for_extension("c"), None);
+    }
+
+    #[test]
+    fn given_language_only_in_user_file_when_merging_then_adds_it_with_default_indent() {
+        // Arrange
+        let mut definitions = LanguageDefinitions::builtin();
+        let user = LanguageDefinitions::parse(
+            "[zig]\nline_comment = \"//\"\nextensions = [\"zig\", 'zon']\n\n[Lua]\nline_comment = '--'\nblock_comment = [\"--[[\", \"]]\"]\n",
+        )
+        .expect("valid user definitions");
+
+        // Act
+        definitions.merge(user);
+
+        // Assert
+        let zig = definitions.get("zig").expect("zig").info();
+        assert_eq!(zig.line_comment.as_deref(), Some("//"));
+        assert_eq!(zig.block_comment, None);
+        assert_eq!(zig.indent_char, "    ");
+        assert_eq!(definitions.language_for_extension("zon"), Some("zig"));
+        assert_eq!(
+            definitions.get("lua").expect("lua").info().block_comment,
+            Some(("--[[".to_string(), "]]".to_string()))
+        );
+    }
+
+    #[test]
+    fn given_malformed_files_when_parsing_then_reports_the_line() {
+        // Act & Assert
+        for (malformed, reason) in [
+            (
+                "indent = \"  \"",
+                "line 1: key 'indent' outside a language table",
+            ),
+            ("[rust]\nindent = 2", "line 2: unsupported value '2'"),
+            ("[rust]\nindent = \"  ", "line 2: unterminated string"),
+            (
+                "[rust]\nextensions = [\"rs\",",
+                "line 2: unterminated array",
+            ),
+            (
+                "[rust]\nblock_comment = [\"/*\"]",
+                "line 2: block_comment must be",
+            ),
+            (
+                "[rust]\nline_comment = [\"//\"]",
+                "line 2: line_comment must be a string",
+            ),
+            ("[rust]\n[rust]", "line 2: language 'rust' defined twice"),
+            ("[rust.x]", "line 1: unsupported table name"),
+            (
+                "[languages.zig]",
+                "line 1: unsupported table name 'languages.zig', dotted table names",
+            ),
+            (
+                "[rust]\nindent = \"\"\"\n    \"\"\"",
+                "line 2: multi-line strings are not supported",
+            ),
+            (
+                "[rust]\nline_comment = \"\\u002F\"",
+                "line 2: unsupported escape '\\u'",
+            ),
+            ("[rust]\nindent", "line 2: expected key = value"),
+        ] {
+            let error = LanguageDefinitions::parse(malformed).expect_err("malformed file");
+            assert!(error.starts_with(reason), "{}: {}", malformed, error);
+        }
+    }
+
+    #[test]
+    fn given_missing_or_malformed_user_file_when_loading_then_falls_back_to_builtin() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!(
+            "bkmr-lsp-languages-{}-{:?}.toml",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, "[zig]\nline_comment = //\n").expect("write languages file");
+
+        // Act
+        let malformed = LanguageDefinitions::load(Some(&path));
+        let missing = LanguageDefinitions::load(Some(Path::new("/nonexistent/languages.toml")));
+
+        // Assert
+        assert_eq!(malformed, LanguageDefinitions::builtin());
+        assert_eq!(missing, LanguageDefinitions::builtin());
+        std::fs::remove_file(path).expect("remove languages file");
+    }
+
+    #[tokio::test]
+    async fn given_user_file_when_loading_async_then_matches_blocking_load() {
+        // Arrange
+        let path = std::env::temp_dir().join(format!(
+            "bkmr-lsp-languages-async-{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(&path, "[zig]\nline_comment = \"//\"\n").expect("write languages file");
+
+        // Act
+        let loaded = LanguageDefinitions::load_async(Some(&path)).await;
+
+        // Assert
+        assert_eq!(loaded, LanguageDefinitions::load(Some(&path)));
+        assert_ne!(loaded, LanguageDefinitions::builtin());
+        std::fs::remove_file(path).expect("remove languages file");
+    }
+}
@@ -0,0 +1,148 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::domain::RankingStrategy;
+
+/// Settings of one language that replace the global ones
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LanguageOverrides {
+    /// Maximum number of completion items, `limit=N`
+    #[serde(rename = "limit", skip_serializing_if = "Option::is_none")]
+    pub max_results: Option<usize>,
+    /// Ordering of completion results, `ranking=usage`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ranking: Option<RankingStrategy>,
+}
+
+impl LanguageOverrides {
+    /// Set one `name=value` option, failing for unknown names, bad values and repeats
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "limit" => {
+                let limit = value
+                    .parse()
+                    .ok()
+                    .filter(|limit| *limit > 0)
+                    .ok_or_else(|| format!("limit must be a positive number, got '{}'", value))?;
+                Self::set_once(&mut self.max_results, limit, name)
+            }
+            "ranking" => Self::set_once(&mut self.ranking, value.parse()?, name),
+            other => Err(format!(
+                "unknown option '{}', expected limit or ranking",
+                other
+            )),
+        }
+    }
+
+    fn set_once<T>(slot: &mut Option<T>, value: T, name: &str) -> Result<(), String> {
+        if slot.is_some() {
+            return Err(format!("{} given twice", name));
+        }
+        *slot = Some(value);
+        Ok(())
+    }
+}
+
+/// Parse "python:limit=20,shell:limit=100" into overrides by lowercase language ID
+///
+/// Repeating a language adds options to it, e.g. "sh:limit=50,sh:ranking=usage".
+/// Errors name the offending entry.
+pub fn parse_language_overrides(s: &str) -> Result<HashMap<String, LanguageOverrides>, String> {
+    let mut overrides: HashMap<String, LanguageOverrides> = HashMap::new();
+    for entry in s
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (language, name, value) = entry
+            .split_once(':')
+            .and_then(|(language, option)| {
+                let (name, value) = option.split_once('=')?;
+                Some((language.trim(), name.trim(), value.trim()))
+            })
+            .filter(|(language, name, value)| {
+                !language.is_empty() && !name.is_empty() && !value.is_empty()
+            })
+            .ok_or_else(|| {
+                format!(
+                    "invalid language override '{}', expected language:option=value",
+                    entry
+                )
+            })?;
+        overrides
+            .entry(language.to_lowercase())
+            .or_default()
+            .set(name, value)
+            .map_err(|e| format!("invalid language override '{}': {}", entry, e))?;
+    }
+    Ok(overrides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_valid_overrides_when_parsing_then_merges_options_per_language() {
+        // Act
+        let overrides =
+            parse_language_overrides("python:limit=20, Shell:limit=100,shell:ranking=usage,")
+                .expect("valid overrides");
+
+        // Assert
+        assert_eq!(overrides.len(), 2);
+        assert_eq!(
+            overrides["python"],
+            LanguageOverrides {
+                max_results: Some(20),
+                ranking: None,
+            }
+        );
+        assert_eq!(
+            overrides["shell"],
+            LanguageOverrides {
+                max_results: Some(100),
+                ranking: Some(RankingStrategy::Usage),
+            }
+        );
+        assert_eq!(parse_language_overrides(""), Ok(HashMap::new()));
+    }
+
+    #[test]
+    fn given_repeated_option_for_a_language_when_parsing_then_reports_the_fragment() {
+        // Act
+        let error = parse_language_overrides("python:limit=20,PYTHON:limit=30")
+            .expect_err("repeated limit");
+
+        // Assert
+        assert_eq!(
+            error,
+            "invalid language override 'PYTHON:limit=30': limit given twice"
+        );
+    }
+
+    #[test]
+    fn given_malformed_or_unknown_options_when_parsing_then_reports_the_fragment() {
+        // Act & Assert
+        for (malformed, reason) in [
+            ("python", "expected language:option=value"),
+            ("python:limit", "expected language:option=value"),
+            (":limit=20", "expected language:option=value"),
+            (
+                "python:color=red",
+                "unknown option 'color', expected limit or ranking",
+            ),
+            ("python:limit=0", "limit must be a positive number, got '0'"),
+            ("python:ranking=random", "unknown ranking 'random'"),
+        ] {
+            let error = parse_language_overrides(&format!("rust:limit=5,{}", malformed))
+                .expect_err("malformed override");
+            assert!(
+                error.contains(&format!("'{}'", malformed)) && error.contains(reason),
+                "{}: {}",
+                malformed,
+                error
+            );
+        }
+    }
+}
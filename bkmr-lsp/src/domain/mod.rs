@@ -1,7 +1,45 @@
 pub mod completion;
+pub mod content_limit;
+pub mod fenced_block;
+pub mod file_path;
+pub mod folder_repository;
+pub mod frontmatter;
+pub mod fts;
+pub mod ignore;
+pub mod indentation;
+pub mod inline_completion;
 pub mod language;
+pub mod language_definitions;
+pub mod language_overrides;
+pub mod placeholder;
+pub mod position;
+pub mod ranking;
 pub mod snippet;
+pub mod snippet_export;
+pub mod snippet_imports;
+pub mod snippet_sources;
+pub mod snippet_syntax;
+pub mod tags;
 
 pub use completion::*;
+pub use content_limit::*;
+pub use fenced_block::*;
+pub use file_path::*;
+pub use folder_repository::*;
+pub use frontmatter::*;
+pub use fts::*;
+pub use ignore::*;
+pub use indentation::*;
+pub use inline_completion::*;
 pub use language::*;
+pub use language_definitions::*;
+pub use language_overrides::*;
+pub use placeholder::*;
+pub use position::*;
+pub use ranking::*;
 pub use snippet::*;
+pub use snippet_export::*;
+pub use snippet_imports::*;
+pub use snippet_sources::*;
+pub use snippet_syntax::*;
+pub use tags::*;
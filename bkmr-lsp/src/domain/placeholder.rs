@@ -0,0 +1,135 @@
+/// Reduce LSP snippet syntax to plain text for insertion without a snippet engine
+///
+/// Tabstops are removed (`$1`, `${2}`), placeholders keep their default
+/// value (`${1:foo}` becomes `foo`, nested ones included) and choices keep
+/// their first option. Escaped `\$`, `\}` and `\\` become literal. Variables
+/// such as `$HOME` or `${USER}` are left untouched.
+pub fn strip_placeholders(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut stripped = String::with_capacity(text.len());
+    let mut pos = 0;
+    strip_into(&chars, &mut pos, &mut stripped, false);
+    stripped
+}
+
+/// Copy plain text to `out` until the end, or the closing brace of a nested placeholder
+fn strip_into(chars: &[char], pos: &mut usize, out: &mut String, nested: bool) {
+    while let Some(&c) = chars.get(*pos) {
+        match c {
+            '\\' if matches!(chars.get(*pos + 1), Some('$' | '}' | '\\')) => {
+                out.push(chars[*pos + 1]);
+                *pos += 2;
+            }
+            '}' if nested => return,
+            '$' => {
+                if !strip_tabstop(chars, pos, out) {
+                    out.push('$');
+                    *pos += 1;
+                }
+            }
+            _ => {
+                out.push(c);
+                *pos += 1;
+            }
+        }
+    }
+}
+
+/// Strip the tabstop, placeholder or choice starting at `pos`, false if there is none
+fn strip_tabstop(chars: &[char], pos: &mut usize, out: &mut String) -> bool {
+    let digits_end = |start: usize| {
+        let end = (start..chars.len())
+            .find(|&i| !chars[i].is_ascii_digit())
+            .unwrap_or(chars.len());
+        (end > start).then_some(end)
+    };
+
+    // $1
+    if let Some(end) = digits_end(*pos + 1) {
+        *pos = end;
+        return true;
+    }
+    if chars.get(*pos + 1) != Some(&'{') {
+        return false;
+    }
+    let Some(end) = digits_end(*pos + 2) else {
+        return false;
+    };
+
+    match chars.get(end) {
+        // ${1}
+        Some('}') => {
+            *pos = end + 1;
+            true
+        }
+        // ${1:default}
+        Some(':') => {
+            *pos = end + 1;
+            strip_into(chars, pos, out, true);
+            if chars.get(*pos) == Some(&'}') {
+                *pos += 1;
+            }
+            true
+        }
+        // ${1|first,second|}
+        Some('|') => {
+            let Some(close) = (end + 1..chars.len().saturating_sub(1))
+                .find(|&i| chars[i] == '|' && chars[i + 1] == '}' && chars[i - 1] != '\\')
+            else {
+                return false;
+            };
+            let mut i = end + 1;
+            while i < close && chars[i] != ',' {
+                if chars[i] == '\\' && matches!(chars.get(i + 1), Some(',' | '|' | '\\')) {
+                    i += 1;
+                }
+                out.push(chars[i]);
+                i += 1;
+            }
+            *pos = close + 2;
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_tabstops_and_placeholders_when_stripping_then_keeps_default_values() {
+        // Arrange
+        let snippet = "fn ${1:name}(${2}) {\n    $0\n}";
+
+        // Act
+        let stripped = strip_placeholders(snippet);
+
+        // Assert
+        assert_eq!(stripped, "fn name() {\n    \n}");
+    }
+
+    #[test]
+    fn given_nested_placeholders_and_choices_when_stripping_then_keeps_inner_defaults() {
+        // Arrange
+        let snippet = "${1:let ${2:x} = ${3:0};} ${4|debug,info|} $12done";
+
+        // Act
+        let stripped = strip_placeholders(snippet);
+
+        // Assert
+        assert_eq!(stripped, "let x = 0; debug done");
+    }
+
+    #[test]
+    fn given_escapes_and_variables_when_stripping_then_keeps_literal_text() {
+        // Arrange
+        let snippet = r"echo \$1 $HOME ${USER} cost: 5$ \} ${1:a\}b}";
+
+        // Act
+        let stripped = strip_placeholders(snippet);
+
+        // Assert
+        assert_eq!(stripped, "echo $1 $HOME ${USER} cost: 5$ } a}b");
+    }
+}
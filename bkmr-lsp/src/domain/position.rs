@@ -0,0 +1,133 @@
+use tower_lsp::lsp_types::PositionEncodingKind;
+
+/// Unit in which `Position.character` counts columns, negotiated at initialize
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    /// UTF-16 code units, the LSP default
+    #[default]
+    Utf16,
+    /// UTF-8 bytes
+    Utf8,
+}
+
+impl PositionEncoding {
+    /// Pick the first encoding offered by the client that we support, UTF-16 otherwise
+    pub fn negotiate(client_encodings: Option<&[PositionEncodingKind]>) -> Self {
+        client_encodings
+            .unwrap_or_default()
+            .iter()
+            .find_map(Self::from_kind)
+            .unwrap_or_default()
+    }
+
+    /// Map an LSP encoding kind, returning None for unsupported kinds
+    pub fn from_kind(kind: &PositionEncodingKind) -> Option<Self> {
+        if *kind == PositionEncodingKind::UTF16 {
+            Some(Self::Utf16)
+        } else if *kind == PositionEncodingKind::UTF8 {
+            Some(Self::Utf8)
+        } else {
+            None
+        }
+    }
+
+    /// LSP encoding kind to advertise in the server capabilities
+    pub fn to_kind(self) -> PositionEncodingKind {
+        match self {
+            Self::Utf16 => PositionEncodingKind::UTF16,
+            Self::Utf8 => PositionEncodingKind::UTF8,
+        }
+    }
+
+    /// Width of a character in this encoding's units
+    fn char_width(self, c: char) -> usize {
+        match self {
+            Self::Utf16 => c.len_utf16(),
+            Self::Utf8 => c.len_utf8(),
+        }
+    }
+
+    /// Convert a column in this encoding to a byte offset within the line
+    ///
+    /// Returns None when the column lies past the end of the line. Columns
+    /// pointing into the middle of a character round down to its start.
+    pub fn byte_offset(self, line: &str, character: u32) -> Option<usize> {
+        let target = character as usize;
+        let mut units = 0;
+        for (byte_idx, c) in line.char_indices() {
+            let width = self.char_width(c);
+            if units + width > target {
+                return Some(byte_idx);
+            }
+            units += width;
+        }
+        (units >= target).then_some(line.len())
+    }
+
+    /// Convert a byte offset within the line to a column in this encoding
+    pub fn character(self, line: &str, byte_offset: usize) -> u32 {
+        line[..byte_offset]
+            .chars()
+            .map(|c| self.char_width(c))
+            .sum::<usize>() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_client_encodings_when_negotiating_then_picks_first_supported() {
+        // Arrange
+        let utf8_first = [PositionEncodingKind::UTF32, PositionEncodingKind::UTF8];
+        let unsupported = [PositionEncodingKind::UTF32];
+
+        // Act
+        let preferred = PositionEncoding::negotiate(Some(&utf8_first));
+        let fallback = PositionEncoding::negotiate(Some(&unsupported));
+        let missing = PositionEncoding::negotiate(None);
+
+        // Assert
+        assert_eq!(preferred, PositionEncoding::Utf8);
+        assert_eq!(fallback, PositionEncoding::Utf16);
+        assert_eq!(missing, PositionEncoding::Utf16);
+    }
+
+    #[test]
+    fn given_multibyte_line_when_converting_columns_then_round_trips_in_both_encodings() {
+        // Arrange
+        let line = "日本語 héllo🚀 word";
+        let byte_offset = line.find(" word").expect("find word");
+
+        // Act
+        let utf16_column = PositionEncoding::Utf16.character(line, byte_offset);
+        let utf8_column = PositionEncoding::Utf8.character(line, byte_offset);
+
+        // Assert
+        assert_eq!(utf16_column, 11);
+        assert_eq!(utf8_column, byte_offset as u32);
+        assert_eq!(
+            PositionEncoding::Utf16.byte_offset(line, utf16_column),
+            Some(byte_offset)
+        );
+        assert_eq!(
+            PositionEncoding::Utf8.byte_offset(line, utf8_column),
+            Some(byte_offset)
+        );
+    }
+
+    #[test]
+    fn given_column_past_line_end_when_converting_then_returns_none() {
+        // Arrange
+        let line = "héllo";
+
+        // Act
+        let end = PositionEncoding::Utf16.byte_offset(line, 5);
+        let past_end = PositionEncoding::Utf16.byte_offset(line, 6);
+
+        // Assert
+        assert_eq!(end, Some(line.len()));
+        assert_eq!(past_end, None);
+    }
+}
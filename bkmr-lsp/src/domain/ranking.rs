@@ -0,0 +1,416 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use crate::domain::Snippet;
+
+/// Score for a title starting with the query
+const PREFIX_SCORE: u32 = 1000;
+/// Score for the query starting a word inside the title
+const WORD_BOUNDARY_SCORE: u32 = 800;
+/// Score for the query appearing anywhere in the title
+const SUBSTRING_SCORE: u32 = 600;
+/// Best score for the query characters appearing in order in the title
+const FUZZY_SCORE: u32 = 400;
+/// Score for the query appearing only in the description
+const DESCRIPTION_SCORE: u32 = 100;
+/// Largest usage boost, kept below the gap between match kinds
+const MAX_USAGE_BOOST: u32 = 99;
+
+/// How completion results are ordered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RankingStrategy {
+    /// Best match for the query first, boosted by usage
+    #[default]
+    Relevance,
+    /// Matching snippets by title
+    Alphabetical,
+    /// Most used matching snippets first
+    Usage,
+}
+
+impl FromStr for RankingStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "relevance" => Ok(Self::Relevance),
+            "alphabetical" => Ok(Self::Alphabetical),
+            "usage" => Ok(Self::Usage),
+            other => Err(format!(
+                "unknown ranking '{}', expected relevance, alphabetical or usage",
+                other
+            )),
+        }
+    }
+}
+
+/// Group of a completion item, items of one tier are listed before the next
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompletionTier {
+    /// Title equal to the query, ignoring case
+    Exact,
+    /// Snippet of the document language
+    #[default]
+    Language,
+    /// Universal snippet translated to the document language
+    Universal,
+    /// Bookmark or fallback snippet shown while the query matches nothing
+    Fallback,
+}
+
+impl CompletionTier {
+    const ALL: [Self; 4] = [Self::Exact, Self::Language, Self::Universal, Self::Fallback];
+
+    /// Tier of a snippet completed for the query
+    pub fn of(snippet: &Snippet, query: &str) -> Self {
+        let query = query.trim();
+        if !query.is_empty() && snippet.title.trim().to_lowercase() == query.to_lowercase() {
+            Self::Exact
+        } else if snippet.is_universal() {
+            Self::Universal
+        } else {
+            Self::Language
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Exact => "exact",
+            Self::Language => "language",
+            Self::Universal => "universal",
+            Self::Fallback => "fallback",
+        }
+    }
+}
+
+impl FromStr for CompletionTier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|tier| tier.name() == s.trim().to_lowercase())
+            .ok_or_else(|| {
+                format!(
+                    "unknown tier '{}', expected exact, language, universal or fallback",
+                    s.trim()
+                )
+            })
+    }
+}
+
+/// Order in which completion tiers are listed, e.g. "universal,exact,language,fallback"
+///
+/// Tiers left out follow the given ones in their default order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TierOrder(Vec<CompletionTier>);
+
+impl Default for TierOrder {
+    fn default() -> Self {
+        Self(CompletionTier::ALL.to_vec())
+    }
+}
+
+impl TierOrder {
+    /// Leading sort text character of a tier, so that tiers never interleave
+    pub fn sort_prefix(&self, tier: CompletionTier) -> char {
+        let position = self
+            .0
+            .iter()
+            .position(|ordered| *ordered == tier)
+            .unwrap_or(self.0.len());
+        char::from(b'0' + position as u8)
+    }
+}
+
+impl FromStr for TierOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tiers = Vec::new();
+        for name in s.split(',').filter(|name| !name.trim().is_empty()) {
+            let tier: CompletionTier = name.parse()?;
+            if tiers.contains(&tier) {
+                return Err(format!("tier '{}' is listed twice", tier.name()));
+            }
+            tiers.push(tier);
+        }
+        for tier in CompletionTier::ALL {
+            if !tiers.contains(&tier) {
+                tiers.push(tier);
+            }
+        }
+        Ok(Self(tiers))
+    }
+}
+
+impl Serialize for TierOrder {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let names: Vec<&str> = self.0.iter().map(|tier| tier.name()).collect();
+        serializer.serialize_str(&names.join(","))
+    }
+}
+
+/// Score how well a snippet matches the query, 0 meaning no match at all
+///
+/// Title matches rank prefix > word boundary > substring > fuzzy subsequence,
+/// followed by matches in the description only. Matching snippets get a small
+/// boost from their access count that never lifts them into a better kind.
+pub fn match_score(snippet: &Snippet, query: &str) -> u32 {
+    let query = query.trim().to_lowercase();
+    let usage_boost = (snippet.access_count.max(0) as u32).min(MAX_USAGE_BOOST);
+    if query.is_empty() {
+        return 1 + usage_boost;
+    }
+
+    let title = snippet.title.to_lowercase();
+    let base = if title.starts_with(&query) {
+        PREFIX_SCORE
+    } else if word_starts(&title).any(|word| word.starts_with(&query)) {
+        WORD_BOUNDARY_SCORE
+    } else if title.contains(&query) {
+        SUBSTRING_SCORE
+    } else if let Some(gaps) = subsequence_gaps(&title, &query) {
+        FUZZY_SCORE - gaps.min(FUZZY_SCORE - DESCRIPTION_SCORE - MAX_USAGE_BOOST - 1)
+    } else if snippet.description.to_lowercase().contains(&query) {
+        DESCRIPTION_SCORE
+    } else {
+        return 0;
+    };
+
+    base + usage_boost
+}
+
+/// Order snippets for completion according to the strategy
+///
+/// Snippets that do not match the query are kept but placed last.
+pub fn rank_snippets(
+    snippets: Vec<Snippet>,
+    query: Option<&str>,
+    strategy: RankingStrategy,
+) -> Vec<Snippet> {
+    let query = query.unwrap_or("");
+    let mut scored: Vec<(u32, Snippet)> = snippets
+        .into_iter()
+        .map(|snippet| (match_score(&snippet, query), snippet))
+        .collect();
+
+    scored.sort_by(|(a_score, a), (b_score, b)| {
+        let matching = (*b_score > 0).cmp(&(*a_score > 0));
+        let by_strategy = match strategy {
+            RankingStrategy::Relevance => b_score.cmp(a_score),
+            RankingStrategy::Alphabetical => Ordering::Equal,
+            RankingStrategy::Usage => b
+                .access_count
+                .cmp(&a.access_count)
+                .then(b_score.cmp(a_score)),
+        };
+        matching
+            .then(by_strategy)
+            .then_with(|| a.title.to_lowercase().cmp(&b.title.to_lowercase()))
+    });
+
+    scored.into_iter().map(|(_, snippet)| snippet).collect()
+}
+
+/// Iterate over the title suffixes starting at each word
+fn word_starts(title: &str) -> impl Iterator<Item = &str> {
+    title
+        .char_indices()
+        .filter(move |(i, _)| {
+            *i > 0
+                && title[..*i]
+                    .chars()
+                    .next_back()
+                    .is_some_and(|prev| !prev.is_alphanumeric())
+        })
+        .map(move |(i, _)| &title[i..])
+}
+
+/// Count skipped characters if the query is a subsequence of the title
+fn subsequence_gaps(title: &str, query: &str) -> Option<u32> {
+    let mut query_chars = query.chars().peekable();
+    let mut gaps = 0;
+    let mut started = false;
+    for c in title.chars() {
+        match query_chars.peek() {
+            Some(&q) if q == c => {
+                query_chars.next();
+                started = true;
+            }
+            Some(_) if started => gaps += 1,
+            Some(_) => {}
+            None => break,
+        }
+    }
+    query_chars.peek().is_none().then_some(gaps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippet(id: i32, title: &str, description: &str, access_count: i32) -> Snippet {
+        let mut snippet = Snippet::new(
+            id,
+            title.to_string(),
+            "content".to_string(),
+            description.to_string(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        );
+        snippet.access_count = access_count;
+        snippet
+    }
+
+    fn fixture() -> Vec<Snippet> {
+        vec![
+            snippet(1, "Serialize JSON", "uses serde somewhere", 50),
+            snippet(2, "Derive with serde", "", 0),
+            snippet(3, "Serde derive skeleton", "", 0),
+            snippet(4, "Hello world", "", 10),
+            snippet(5, "Some erde value", "", 0),
+            snippet(6, "Deserializer", "", 0),
+        ]
+    }
+
+    fn ranked_ids(query: &str, strategy: RankingStrategy) -> Vec<i32> {
+        rank_snippets(fixture(), Some(query), strategy)
+            .iter()
+            .map(|s| s.id)
+            .collect()
+    }
+
+    #[test]
+    fn given_serde_query_when_ranking_by_relevance_then_orders_by_match_kind() {
+        // Act
+        let ids = ranked_ids("serde", RankingStrategy::Relevance);
+
+        // Assert: prefix, word boundary, fuzzy, description only, then non-matching
+        assert_eq!(ids, vec![3, 2, 5, 1, 6, 4]);
+    }
+
+    #[test]
+    fn given_fuzzy_query_when_ranking_by_relevance_then_subsequence_matches_rank_before_misses() {
+        // Act
+        let ids = ranked_ids("hlwd", RankingStrategy::Relevance);
+
+        // Assert
+        assert_eq!(ids[0], 4);
+        assert!(match_score(&fixture()[3], "hlwd") > 0);
+        assert_eq!(match_score(&fixture()[2], "hlwd"), 0);
+    }
+
+    #[test]
+    fn given_usage_counts_when_ranking_by_relevance_then_boost_only_breaks_ties_within_kind() {
+        // Arrange
+        let snippets = vec![
+            snippet(1, "Test alpha", "", 0),
+            snippet(2, "Test beta", "", 80),
+            snippet(3, "A test", "", 1000),
+        ];
+
+        // Act
+        let ids: Vec<i32> = rank_snippets(snippets, Some("test"), RankingStrategy::Relevance)
+            .iter()
+            .map(|s| s.id)
+            .collect();
+
+        // Assert: both prefix matches beat the heavily used word-boundary match
+        assert_eq!(ids, vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn given_query_when_ranking_alphabetically_then_sorts_matches_by_title() {
+        // Act
+        let ids = ranked_ids("de", RankingStrategy::Alphabetical);
+
+        // Assert: all matches by title, "Hello world" does not match and comes last
+        assert_eq!(ids, vec![2, 6, 3, 1, 5, 4]);
+    }
+
+    #[test]
+    fn given_query_when_ranking_by_usage_then_most_used_matches_come_first() {
+        // Act
+        let ids = ranked_ids("serde", RankingStrategy::Usage);
+
+        // Assert
+        assert_eq!(ids, vec![1, 3, 2, 5, 4, 6]);
+    }
+
+    #[test]
+    fn given_empty_query_when_ranking_by_relevance_then_orders_by_usage_and_title() {
+        // Act
+        let ids = ranked_ids("", RankingStrategy::Relevance);
+
+        // Assert
+        assert_eq!(ids, vec![1, 4, 2, 6, 3, 5]);
+    }
+
+    #[test]
+    fn given_strategy_names_when_parsing_then_accepts_known_values() {
+        // Assert
+        assert_eq!(
+            "relevance".parse::<RankingStrategy>(),
+            Ok(RankingStrategy::Relevance)
+        );
+        assert_eq!(
+            "Usage".parse::<RankingStrategy>(),
+            Ok(RankingStrategy::Usage)
+        );
+        assert!("random".parse::<RankingStrategy>().is_err());
+    }
+
+    #[test]
+    fn given_snippets_when_assigning_tiers_then_exact_title_beats_language_and_universal() {
+        // Arrange
+        let language = snippet(1, "Serde derive", "", 0);
+        let mut universal = snippet(2, "Serde derive skeleton", "", 0);
+        universal.tags.push("universal".to_string());
+
+        // Assert
+        assert_eq!(
+            CompletionTier::of(&language, " serde DERIVE "),
+            CompletionTier::Exact
+        );
+        assert_eq!(
+            CompletionTier::of(&language, "serde"),
+            CompletionTier::Language
+        );
+        assert_eq!(CompletionTier::of(&language, ""), CompletionTier::Language);
+        assert_eq!(
+            CompletionTier::of(&universal, "serde"),
+            CompletionTier::Universal
+        );
+        assert_eq!(
+            CompletionTier::of(&universal, "serde derive skeleton"),
+            CompletionTier::Exact
+        );
+    }
+
+    #[test]
+    fn given_tier_order_when_parsing_then_fills_in_missing_tiers_and_rejects_invalid_names() {
+        // Act
+        let custom = "Universal, exact".parse::<TierOrder>().expect("tier order");
+        let unknown = "exact,bookmarks".parse::<TierOrder>();
+        let duplicate = "exact,language,exact".parse::<TierOrder>();
+
+        // Assert
+        assert_eq!(custom.sort_prefix(CompletionTier::Universal), '0');
+        assert_eq!(custom.sort_prefix(CompletionTier::Exact), '1');
+        assert_eq!(custom.sort_prefix(CompletionTier::Language), '2');
+        assert_eq!(custom.sort_prefix(CompletionTier::Fallback), '3');
+        assert_eq!(
+            serde_json::to_value(&custom).expect("serialize"),
+            serde_json::json!("universal,exact,language,fallback")
+        );
+        assert_eq!(
+            "exact,language,universal,fallback".parse::<TierOrder>(),
+            Ok(TierOrder::default())
+        );
+        assert!(unknown.unwrap_err().contains("unknown tier 'bookmarks'"));
+        assert!(duplicate.unwrap_err().contains("listed twice"));
+    }
+}
@@ -1,4 +1,74 @@
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::domain::LanguageRegistry;
+
+/// bkmr tags of bookmarks that are run rather than inserted
+pub const DEFAULT_ACTION_TAGS: &[&str] = &["_shell_"];
+/// Tag opting a universal snippet into translation from Rust style
+pub const DEFAULT_TRANSLATE_TAG: &str = "rust-style";
+
+/// Which universal snippets are translated from Rust style to the target language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranslationPolicy {
+    /// Tag a universal snippet needs besides "universal"
+    pub translate_tag: String,
+    /// Translate every universal snippet for every language, as before the tag existed
+    pub legacy: bool,
+    /// Whether universal snippets are offered at all, none is translated otherwise
+    pub universal: bool,
+}
+
+impl Default for TranslationPolicy {
+    fn default() -> Self {
+        Self {
+            translate_tag: DEFAULT_TRANSLATE_TAG.to_string(),
+            legacy: false,
+            universal: true,
+        }
+    }
+}
+
+/// Start of a tag restricting a snippet to the listed languages, e.g. "only:python,go"
+pub const ONLY_TAG_PREFIX: &str = "only:";
+/// Start of a tag keeping a snippet out of the listed languages, e.g. "not:go"
+pub const NOT_TAG_PREFIX: &str = "not:";
+
+/// Start of the description line listing a snippet's aliases, e.g. "alias:kdep,kd"
+pub const ALIAS_PREFIX: &str = "alias:";
+
+/// Regex finding alias lists in descriptions, the first group holds the list if it has one
+#[derive(Debug, Clone)]
+pub struct AliasPattern(Regex);
+
+impl AliasPattern {
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl FromStr for AliasPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Regex::new(s)
+            .map(Self)
+            .map_err(|e| format!("invalid alias pattern '{}': {}", s, e))
+    }
+}
+
+impl PartialEq for AliasPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Serialize for AliasPattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
 
 /// Core snippet domain model representing a bkmr snippet
 #[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
@@ -36,6 +106,38 @@ impl Snippet {
         self.tags.contains(&"universal".to_string())
     }
 
+    /// Whether the content is translated from Rust style to the target language
+    ///
+    /// Universal snippets opt in with the policy's tag. Rust targets and
+    /// languages without any comments, like json, are left alone.
+    pub fn needs_translation(
+        &self,
+        target_lang: &str,
+        policy: &TranslationPolicy,
+        registry: &LanguageRegistry,
+    ) -> bool {
+        if !self.is_universal() || !policy.universal {
+            return false;
+        }
+        if policy.legacy {
+            return true;
+        }
+        if !self.tags.contains(&policy.translate_tag) {
+            return false;
+        }
+        let info = registry.get_language_info(target_lang);
+        let rust = registry
+            .language_tags(target_lang)
+            .iter()
+            .any(|tag| tag == "rust");
+        !rust && (info.line_comment.is_some() || info.block_comment.is_some())
+    }
+
+    /// Where the snippet comes from, "file" for snippet files and "bkmr" otherwise
+    pub fn source(&self) -> &'static str {
+        if self.id < 0 { "file" } else { "bkmr" }
+    }
+
     /// Check if this snippet is marked as a snippet
     pub fn is_snippet(&self) -> bool {
         self.tags.contains(&"_snip_".to_string())
@@ -51,21 +153,188 @@ impl Snippet {
         self.tags.contains(&"plain".to_string())
     }
 
+    /// Action bkmr runs for this bookmark instead of inserting it, e.g. "shell" for `_shell_`
+    ///
+    /// Checks the default action tags and the given additional ones, ignoring case.
+    pub fn action_kind(&self, action_tags: &[String]) -> Option<String> {
+        self.tags
+            .iter()
+            .find(|tag| {
+                DEFAULT_ACTION_TAGS
+                    .iter()
+                    .copied()
+                    .chain(action_tags.iter().map(String::as_str))
+                    .any(|action| action.eq_ignore_ascii_case(tag))
+            })
+            .map(|tag| tag.trim_matches('_').to_lowercase())
+    }
+
+    /// Description without its "alias:" line, lines joined by single spaces
+    pub fn description_text(&self) -> String {
+        self.description
+            .lines()
+            .map(str::trim)
+            .filter(|line| {
+                !line.is_empty()
+                    && !line
+                        .get(..ALIAS_PREFIX.len())
+                        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(ALIAS_PREFIX))
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Short trigger words stored in the description, e.g. "kdep" and "kd" for "alias:kdep,kd"
+    ///
+    /// Read from the first description line starting with "alias:", or from
+    /// every match of the given pattern instead. Aliases are separated by
+    /// commas, entries containing whitespace are skipped, repeats dropped.
+    pub fn aliases(&self, pattern: Option<&AliasPattern>) -> Vec<String> {
+        let lists: Vec<&str> = match pattern {
+            Some(pattern) => pattern
+                .0
+                .captures_iter(&self.description)
+                .filter_map(|captures| captures.get(1).or_else(|| captures.get(0)))
+                .map(|list| list.as_str())
+                .collect(),
+            None => self
+                .description
+                .lines()
+                .map(str::trim_start)
+                .find_map(|line| {
+                    line.get(..ALIAS_PREFIX.len())
+                        .filter(|prefix| prefix.eq_ignore_ascii_case(ALIAS_PREFIX))
+                        .map(|_| &line[ALIAS_PREFIX.len()..])
+                })
+                .into_iter()
+                .collect(),
+        };
+
+        let mut aliases: Vec<String> = Vec::new();
+        for alias in lists.iter().flat_map(|list| list.split(',')).map(str::trim) {
+            if alias.is_empty()
+                || alias.contains(char::is_whitespace)
+                || aliases
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(alias))
+            {
+                continue;
+            }
+            aliases.push(alias.to_string());
+        }
+        aliases
+    }
+
+    /// Language IDs of the snippet's "only:" tags, empty if it is not restricted to any
+    pub fn allowed_languages(&self, registry: &LanguageRegistry) -> Vec<String> {
+        self.restriction_languages(ONLY_TAG_PREFIX, registry)
+    }
+
+    /// Language IDs of the snippet's "not:" tags
+    pub fn denied_languages(&self, registry: &LanguageRegistry) -> Vec<String> {
+        self.restriction_languages(NOT_TAG_PREFIX, registry)
+    }
+
+    /// Whether the restriction tags let the snippet complete in a document language
+    ///
+    /// The language matches a restriction by its ID or one of its tags, so
+    /// "only:sh" covers shellscript documents. Snippets restricted with
+    /// "only:" are not offered where the language is unknown.
+    pub fn allows_language(&self, language_id: Option<&str>, registry: &LanguageRegistry) -> bool {
+        let allowed = self.allowed_languages(registry);
+        let denied = self.denied_languages(registry);
+        if allowed.is_empty() && denied.is_empty() {
+            return true;
+        }
+        let names: Vec<String> = language_id
+            .filter(|language_id| !language_id.is_empty() && *language_id != "unknown")
+            .map(|language_id| {
+                std::iter::once(language_id.to_string())
+                    .chain(registry.language_tags(language_id))
+                    .map(|name| registry.language_id_for(&name))
+                    .collect()
+            })
+            .unwrap_or_default();
+        (allowed.is_empty() || names.iter().any(|name| allowed.contains(name)))
+            && !names.iter().any(|name| denied.contains(name))
+    }
+
+    /// Languages listed after the prefix by any tag, comma separated, as language IDs
+    fn restriction_languages(&self, prefix: &str, registry: &LanguageRegistry) -> Vec<String> {
+        let mut languages: Vec<String> = Vec::new();
+        for name in self
+            .tags
+            .iter()
+            .filter_map(|tag| restriction(tag, prefix))
+            .flat_map(|list| list.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let language = registry.language_id_for(name);
+            if !languages.contains(&language) {
+                languages.push(language);
+            }
+        }
+        languages
+    }
+
+    /// Get the first tag that is not a bkmr meta tag, typically the snippet's language
+    pub fn language_tag(&self) -> Option<&str> {
+        self.content_tags().next()
+    }
+
+    /// Iterate over the tags that describe the snippet content rather than bkmr metadata
+    pub fn content_tags(&self) -> impl Iterator<Item = &str> {
+        self.tags
+            .iter()
+            .map(String::as_str)
+            .filter(|tag| !is_meta_tag(tag))
+    }
+
     /// Get the snippet content (content field contains actual snippet data)
     pub fn get_content(&self) -> &str {
         &self.content
     }
 }
 
+/// Snippet to be created in bkmr, before it has been assigned an id
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewSnippet {
+    pub title: String,
+    pub content: String,
+    pub tags: Vec<String>,
+}
+
+/// Check if a tag carries bkmr metadata rather than describing the snippet content
+fn is_meta_tag(tag: &str) -> bool {
+    matches!(tag, "universal" | "plain" | DEFAULT_TRANSLATE_TAG)
+        || (tag.starts_with('_') && tag.ends_with('_'))
+        || restriction(tag, ONLY_TAG_PREFIX).is_some()
+        || restriction(tag, NOT_TAG_PREFIX).is_some()
+}
+
+/// Language list of a restriction tag with the given prefix, ignoring the prefix's case
+fn restriction<'a>(tag: &'a str, prefix: &str) -> Option<&'a str> {
+    tag.get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .map(|_| &tag[prefix.len()..])
+}
+
 /// Compatibility type for existing BkmrSnippet usage
 /// This maintains backwards compatibility with existing JSON deserialization
+///
+/// Field aliases and defaults keep older and newer bkmr JSON schemas parseable.
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct BkmrSnippet {
     pub id: i32,
+    #[serde(alias = "metadata")]
     pub title: String,
     /// Note: In the bkmr CLI output, "url" field contains the actual snippet content
+    #[serde(alias = "content", alias = "URL")]
     pub url: String,
+    #[serde(default)]
     pub description: String,
+    #[serde(default)]
     pub tags: Vec<String>,
     #[serde(default)]
     pub access_count: i32,
@@ -137,6 +406,105 @@ mod tests {
         assert!(!is_universal);
     }
 
+    fn tagged(tags: &[&str]) -> Snippet {
+        Snippet::new(
+            1,
+            "Test".to_string(),
+            "// content".to_string(),
+            "desc".to_string(),
+            tags.iter().map(|tag| tag.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn given_tag_combinations_when_checking_needs_translation_then_requires_universal_and_translate_tag()
+     {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let policy = TranslationPolicy::default();
+        let custom = TranslationPolicy {
+            translate_tag: "from-rust".to_string(),
+            ..TranslationPolicy::default()
+        };
+
+        // Act & Assert
+        assert!(
+            tagged(&["universal", "rust-style", "_snip_"])
+                .needs_translation("python", &policy, &registry)
+        );
+        assert!(!tagged(&["universal", "_snip_"]).needs_translation("python", &policy, &registry));
+        assert!(!tagged(&["rust-style", "_snip_"]).needs_translation("python", &policy, &registry));
+        assert!(
+            !tagged(&["universal", "rust-style"]).needs_translation("python", &custom, &registry)
+        );
+        assert!(
+            tagged(&["universal", "from-rust"]).needs_translation("python", &custom, &registry)
+        );
+    }
+
+    #[test]
+    fn given_json_or_rust_target_when_checking_needs_translation_then_skips_translation() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let snippet = tagged(&["universal", "rust-style", "_snip_"]);
+        let policy = TranslationPolicy::default();
+
+        // Act & Assert
+        assert!(!snippet.needs_translation("json", &policy, &registry));
+        assert!(!snippet.needs_translation("rust", &policy, &registry));
+        assert!(snippet.needs_translation("go", &policy, &registry));
+    }
+
+    #[test]
+    fn given_legacy_policy_when_checking_needs_translation_then_translates_every_universal_snippet()
+    {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let policy = TranslationPolicy {
+            legacy: true,
+            ..TranslationPolicy::default()
+        };
+
+        // Act & Assert
+        assert!(tagged(&["universal", "_snip_"]).needs_translation("python", &policy, &registry));
+        assert!(tagged(&["universal", "_snip_"]).needs_translation("json", &policy, &registry));
+        assert!(tagged(&["universal", "_snip_"]).needs_translation("rust", &policy, &registry));
+        assert!(!tagged(&["rust", "_snip_"]).needs_translation("python", &policy, &registry));
+    }
+
+    #[test]
+    fn given_universal_snippets_disabled_when_checking_needs_translation_then_translates_none() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let policy = TranslationPolicy {
+            universal: false,
+            ..TranslationPolicy::default()
+        };
+        let legacy = TranslationPolicy {
+            legacy: true,
+            ..policy.clone()
+        };
+
+        // Act & Assert
+        assert!(
+            !tagged(&["universal", "rust-style", "_snip_"])
+                .needs_translation("python", &policy, &registry)
+        );
+        assert!(!tagged(&["universal", "_snip_"]).needs_translation("python", &legacy, &registry));
+    }
+
+    #[test]
+    fn given_translate_tag_when_getting_content_tags_then_skips_it() {
+        // Arrange
+        let snippet = tagged(&["python", "universal", "rust-style", "_snip_"]);
+
+        // Act
+        let tags: Vec<&str> = snippet.content_tags().collect();
+
+        // Assert
+        assert_eq!(tags, vec!["python"]);
+    }
+
     #[test]
     fn given_bkmr_snippet_when_converting_to_domain_then_maps_correctly() {
         // Arrange
@@ -184,6 +552,98 @@ mod tests {
         assert_eq!(bkmr_snippet.access_count, 0); // default for new snippet
     }
 
+    #[test]
+    fn given_action_tags_when_getting_action_kind_then_names_the_action() {
+        // Arrange
+        let snippet = |tags: &[&str]| {
+            Snippet::new(
+                1,
+                "Deploy".to_string(),
+                "kubectl apply -f .".to_string(),
+                String::new(),
+                tags.iter().map(|tag| tag.to_string()).collect(),
+            )
+        };
+        let extra = vec!["_md_".to_string()];
+
+        // Act & Assert
+        assert_eq!(
+            snippet(&["k8s", "_shell_"]).action_kind(&[]),
+            Some("shell".to_string())
+        );
+        assert_eq!(
+            snippet(&["notes", "_MD_"]).action_kind(&extra),
+            Some("md".to_string())
+        );
+        assert_eq!(snippet(&["notes", "_md_"]).action_kind(&[]), None);
+        assert_eq!(snippet(&["rust", "_snip_"]).action_kind(&extra), None);
+    }
+
+    fn described(description: &str) -> Snippet {
+        Snippet::new(
+            1,
+            "Kubernetes deployment with resource limits".to_string(),
+            "apiVersion: apps/v1".to_string(),
+            description.to_string(),
+            vec!["yaml".to_string(), "_snip_".to_string()],
+        )
+    }
+
+    #[test]
+    fn given_alias_line_when_getting_aliases_then_splits_and_trims_the_list() {
+        // Act & Assert
+        assert_eq!(described("alias:kdep,kd").aliases(None), vec!["kdep", "kd"]);
+        assert_eq!(
+            described("Deployment manifest\n  Alias: kdep , KD,kd,\nmore text").aliases(None),
+            vec!["kdep", "KD"]
+        );
+        assert!(described("").aliases(None).is_empty());
+        assert!(
+            described("Uses an alias: see docs")
+                .aliases(None)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn given_description_with_alias_line_when_getting_text_then_leaves_it_out() {
+        // Act & Assert
+        assert_eq!(
+            described("Deployment manifest\n  Alias: kdep\n\n  with limits ").description_text(),
+            "Deployment manifest with limits"
+        );
+        assert_eq!(described("alias:kdep").description_text(), "");
+    }
+
+    #[test]
+    fn given_malformed_alias_lists_when_getting_aliases_then_skips_bad_entries() {
+        // Act & Assert
+        assert!(described("alias:").aliases(None).is_empty());
+        assert!(described("alias: , ,").aliases(None).is_empty());
+        assert_eq!(
+            described("alias:k dep,kd\talt,kdep").aliases(None),
+            vec!["kdep"]
+        );
+        assert!(described("aliases:kdep").aliases(None).is_empty());
+    }
+
+    #[test]
+    fn given_alias_pattern_when_getting_aliases_then_reads_every_match() {
+        // Arrange
+        let pattern: AliasPattern = r"\[aka ([^\]]+)\]".parse().expect("valid pattern");
+        let whole_match: AliasPattern = r"#\w+".parse().expect("valid pattern");
+
+        // Act
+        let aliases = described("[aka kdep,kd] deploy [aka kube-deploy]").aliases(Some(&pattern));
+        let hashtags = described("#kdep and #kd").aliases(Some(&whole_match));
+
+        // Assert
+        assert_eq!(aliases, vec!["kdep", "kd", "kube-deploy"]);
+        assert_eq!(hashtags, vec!["#kdep", "#kd"]);
+        assert!(described("alias:kdep").aliases(Some(&pattern)).is_empty());
+        assert!("[unclosed".parse::<AliasPattern>().is_err());
+    }
+
     #[test]
     fn given_plain_tag_when_checking_is_plain_then_returns_true() {
         // Arrange
@@ -219,4 +679,56 @@ mod tests {
         // Assert
         assert!(!is_plain);
     }
+
+    fn tagged_snippet(tags: &[&str]) -> Snippet {
+        Snippet::new(
+            1,
+            "Context manager".to_string(),
+            "with ${1:resource}:".to_string(),
+            String::new(),
+            tags.iter().map(|tag| tag.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn given_restriction_tags_when_parsing_then_returns_language_ids() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let snippet = tagged_snippet(&[
+            "universal",
+            "_snip_",
+            "only:python,go",
+            "ONLY:py",
+            "not:js, ts",
+            "not:",
+        ]);
+
+        // Act
+        let allowed = snippet.allowed_languages(&registry);
+        let denied = snippet.denied_languages(&registry);
+
+        // Assert
+        assert_eq!(allowed, vec!["python", "go"]);
+        assert_eq!(denied, vec!["javascript", "typescript"]);
+        assert_eq!(snippet.content_tags().count(), 0);
+    }
+
+    #[test]
+    fn given_restriction_tags_when_checking_languages_then_only_and_not_apply() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let only = tagged_snippet(&["universal", "only:python", "only:go"]);
+        let not = tagged_snippet(&["universal", "not:go"]);
+        let plain = tagged_snippet(&["universal"]);
+
+        // Act & Assert
+        assert!(only.allows_language(Some("python"), &registry));
+        assert!(only.allows_language(Some("go"), &registry));
+        assert!(!only.allows_language(Some("rust"), &registry));
+        assert!(!only.allows_language(None, &registry));
+        assert!(!not.allows_language(Some("go"), &registry));
+        assert!(not.allows_language(Some("python"), &registry));
+        assert!(not.allows_language(None, &registry));
+        assert!(plain.allows_language(Some("go"), &registry));
+    }
 }
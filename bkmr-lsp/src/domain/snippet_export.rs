@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::domain::Snippet;
+
+/// File format written by the exportSnippets command
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// Array of `{id, title, tags, content}` objects
+    #[default]
+    Json,
+    /// VS Code snippets file keyed by title, bodies split into lines
+    Vscode,
+}
+
+/// One snippet of a JSON export
+#[derive(Debug, Serialize)]
+struct ExportedSnippet<'a> {
+    id: i32,
+    title: &'a str,
+    tags: &'a [String],
+    content: &'a str,
+}
+
+/// One snippet of a VS Code snippets file
+#[derive(Debug, Serialize)]
+struct VscodeSnippet<'a> {
+    prefix: Vec<&'a str>,
+    body: Vec<&'a str>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    description: &'a str,
+}
+
+/// Render snippets, their content already translated, in the export format
+///
+/// In VS Code files a title used twice gets the snippet id appended, keys
+/// must be unique.
+pub fn render_snippet_export(
+    format: ExportFormat,
+    snippets: &[Snippet],
+) -> serde_json::Result<String> {
+    match format {
+        ExportFormat::Json => serde_json::to_string_pretty(
+            &snippets
+                .iter()
+                .map(|snippet| ExportedSnippet {
+                    id: snippet.id,
+                    title: &snippet.title,
+                    tags: &snippet.tags,
+                    content: &snippet.content,
+                })
+                .collect::<Vec<_>>(),
+        ),
+        ExportFormat::Vscode => {
+            let mut entries = BTreeMap::new();
+            for snippet in snippets {
+                let mut key = snippet.title.clone();
+                if entries.contains_key(&key) {
+                    key = format!("{} ({})", snippet.title, snippet.id);
+                }
+                entries.insert(
+                    key,
+                    VscodeSnippet {
+                        prefix: vec![&snippet.title],
+                        body: snippet.content.split('\n').collect(),
+                        description: &snippet.description,
+                    },
+                );
+            }
+            serde_json::to_string_pretty(&entries)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippet(id: i32, title: &str, content: &str, description: &str) -> Snippet {
+        Snippet::new(
+            id,
+            title.to_string(),
+            content.to_string(),
+            description.to_string(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        )
+    }
+
+    #[test]
+    fn given_snippets_when_rendering_json_export_then_writes_id_title_tags_and_content() {
+        // Arrange
+        let snippets = [snippet(
+            3,
+            "Print",
+            "println!(\"{}\", $1);",
+            "Print a value",
+        )];
+
+        // Act
+        let rendered = render_snippet_export(ExportFormat::Json, &snippets).expect("render");
+
+        // Assert
+        assert_eq!(
+            rendered,
+            r#"[
+  {
+    "id": 3,
+    "title": "Print",
+    "tags": [
+      "rust",
+      "_snip_"
+    ],
+    "content": "println!(\"{}\", $1);"
+  }
+]"#
+        );
+    }
+
+    #[test]
+    fn given_snippets_when_rendering_vscode_export_then_keys_them_by_title() {
+        // Arrange
+        let snippets = [
+            snippet(3, "Print", "println!($1);", "Print a value"),
+            snippet(4, "Main", "fn main() {}", ""),
+        ];
+
+        // Act
+        let rendered = render_snippet_export(ExportFormat::Vscode, &snippets).expect("render");
+
+        // Assert
+        assert_eq!(
+            rendered,
+            r#"{
+  "Main": {
+    "prefix": [
+      "Main"
+    ],
+    "body": [
+      "fn main() {}"
+    ]
+  },
+  "Print": {
+    "prefix": [
+      "Print"
+    ],
+    "body": [
+      "println!($1);"
+    ],
+    "description": "Print a value"
+  }
+}"#
+        );
+    }
+
+    #[test]
+    fn given_multi_line_snippet_when_rendering_vscode_export_then_splits_body_on_newlines() {
+        // Arrange
+        let snippets = [
+            snippet(1, "Test", "#[test]\nfn ${1:name}() {\n    $0\n}\n", ""),
+            snippet(2, "Test", "#[tokio::test]", ""),
+        ];
+
+        // Act
+        let rendered = render_snippet_export(ExportFormat::Vscode, &snippets).expect("render");
+
+        // Assert
+        let parsed: serde_json::Value = serde_json::from_str(&rendered).expect("valid JSON");
+        assert_eq!(
+            parsed["Test"]["body"],
+            serde_json::json!(["#[test]", "fn ${1:name}() {", "    $0", "}", ""])
+        );
+        assert_eq!(
+            parsed["Test (2)"]["body"],
+            serde_json::json!(["#[tokio::test]"])
+        );
+    }
+}
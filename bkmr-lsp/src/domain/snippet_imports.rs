@@ -0,0 +1,212 @@
+use std::sync::Arc;
+use tower_lsp::lsp_types::{Position, Range, TextEdit};
+
+/// Default start of snippet header lines naming an import the snippet needs
+pub const DEFAULT_IMPORT_MARKER: &str = "#import:";
+
+/// Imports declared by a snippet's leading marker lines and the content after them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnippetImports<'a> {
+    pub imports: Vec<&'a str>,
+    pub body: &'a str,
+}
+
+impl<'a> SnippetImports<'a> {
+    /// Split leading lines like "#import: from dataclasses import dataclass" off the content
+    ///
+    /// An empty marker disables imports, the whole content is the body.
+    pub fn parse(content: &'a str, marker: &str) -> Self {
+        let mut imports = Vec::new();
+        let mut body = content;
+        if !marker.is_empty() {
+            while let Some(rest) = body.trim_start_matches([' ', '\t']).strip_prefix(marker) {
+                let (line, next) = rest.split_once('\n').unwrap_or((rest, ""));
+                let import = line.trim();
+                if !import.is_empty() && !imports.contains(&import) {
+                    imports.push(import);
+                }
+                body = next;
+            }
+        }
+        Self { imports, body }
+    }
+}
+
+/// Document text needed to place snippet imports, shared cheaply between completion contexts
+#[derive(Clone)]
+pub struct DocumentImports {
+    text: Arc<str>,
+    /// Where imports go: after a shebang and a module docstring
+    insert_at: Position,
+    /// Whether the text ends before the insert line, so an inserted import starts a new line
+    past_end: bool,
+}
+
+impl std::fmt::Debug for DocumentImports {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DocumentImports")
+            .field("bytes", &self.text.len())
+            .field("insert_at", &self.insert_at)
+            .finish()
+    }
+}
+
+impl DocumentImports {
+    pub fn new(text: &str) -> Self {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut line = 0;
+        if lines.first().is_some_and(|first| first.starts_with("#!")) {
+            line = 1;
+        }
+        if let Some(end) = Self::docstring_end(&lines[line.min(lines.len())..]) {
+            line += end + 1;
+        }
+
+        let past_end = line >= lines.len() && !text.is_empty() && !text.ends_with('\n');
+        let insert_at = if past_end {
+            // Past the line length positions default back to the end of the line
+            Position::new(line.saturating_sub(1) as u32, u32::MAX)
+        } else {
+            Position::new(line as u32, 0)
+        };
+        Self {
+            text: text.into(),
+            insert_at,
+            past_end,
+        }
+    }
+
+    /// Where imports are inserted
+    pub fn insert_at(&self) -> Position {
+        self.insert_at
+    }
+
+    /// Whether the document already has the import as a line of its own
+    pub fn contains(&self, import: &str) -> bool {
+        self.text.lines().any(|line| line.trim() == import)
+    }
+
+    /// Edits inserting the imports the document lacks, one per import
+    pub fn edits(&self, imports: &[&str]) -> Vec<TextEdit> {
+        let range = Range::new(self.insert_at, self.insert_at);
+        imports
+            .iter()
+            .filter(|import| !self.contains(import))
+            .map(|import| TextEdit {
+                range,
+                new_text: if self.past_end {
+                    format!("\n{}", import)
+                } else {
+                    format!("{}\n", import)
+                },
+            })
+            .collect()
+    }
+
+    /// Index of the last line of a docstring opening the lines, None without one
+    fn docstring_end(lines: &[&str]) -> Option<usize> {
+        let first = lines.first()?.trim_start();
+        let delimiter = ["\"\"\"", "'''"]
+            .into_iter()
+            .find(|delimiter| first.starts_with(delimiter))?;
+        if first[delimiter.len()..].contains(delimiter) {
+            return Some(0);
+        }
+        lines[1..]
+            .iter()
+            .position(|line| line.contains(delimiter))
+            .map(|index| index + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_import_headers_when_parsing_then_strips_them_from_the_body() {
+        // Arrange
+        let content = "#import: from dataclasses import dataclass\n\
+                       #import: from typing import Optional\n\
+                       @dataclass\nclass $1:\n    #import: kept in the body\n";
+
+        // Act
+        let parsed = SnippetImports::parse(content, DEFAULT_IMPORT_MARKER);
+
+        // Assert
+        assert_eq!(
+            parsed.imports,
+            vec![
+                "from dataclasses import dataclass",
+                "from typing import Optional"
+            ]
+        );
+        assert_eq!(
+            parsed.body,
+            "@dataclass\nclass $1:\n    #import: kept in the body\n"
+        );
+    }
+
+    #[test]
+    fn given_content_without_marker_when_parsing_then_leaves_it_untouched() {
+        // Arrange
+        let content = "# import: not the marker\nprint($1)";
+
+        // Act
+        let parsed = SnippetImports::parse(content, DEFAULT_IMPORT_MARKER);
+        let disabled = SnippetImports::parse("#import: os\nos.getcwd()", "");
+
+        // Assert
+        assert!(parsed.imports.is_empty());
+        assert_eq!(parsed.body, content);
+        assert!(disabled.imports.is_empty());
+        assert_eq!(disabled.body, "#import: os\nos.getcwd()");
+    }
+
+    #[test]
+    fn given_existing_import_when_building_edits_then_skips_it() {
+        // Arrange
+        let document = DocumentImports::new("import os\n\nprint(os.getcwd())\n");
+
+        // Act
+        let edits = document.edits(&["import os", "import sys"]);
+
+        // Assert
+        assert_eq!(
+            edits,
+            vec![TextEdit {
+                range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+                new_text: "import sys\n".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn given_shebang_and_docstring_when_placing_imports_then_inserts_after_them() {
+        // Act
+        let shebang = DocumentImports::new("#!/usr/bin/env python3\nprint(1)\n");
+        let docstring =
+            DocumentImports::new("#!/usr/bin/env python3\n\"\"\"Tool.\n\nUsage.\n\"\"\"\nx = 1\n");
+        let one_line = DocumentImports::new("'''Tool.'''\nx = 1\n");
+        let empty = DocumentImports::new("");
+
+        // Assert
+        assert_eq!(shebang.insert_at(), Position::new(1, 0));
+        assert_eq!(docstring.insert_at(), Position::new(5, 0));
+        assert_eq!(one_line.insert_at(), Position::new(1, 0));
+        assert_eq!(empty.insert_at(), Position::new(0, 0));
+    }
+
+    #[test]
+    fn given_only_a_shebang_without_newline_when_building_edits_then_starts_a_new_line() {
+        // Arrange
+        let document = DocumentImports::new("#!/bin/sh");
+
+        // Act
+        let edits = document.edits(&["set -e"]);
+
+        // Assert
+        assert_eq!(edits[0].range.start, Position::new(0, u32::MAX));
+        assert_eq!(edits[0].new_text, "\nset -e");
+    }
+}
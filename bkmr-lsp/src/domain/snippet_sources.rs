@@ -0,0 +1,115 @@
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::{FileSystemWatcher, GlobPattern, Url};
+
+use crate::domain::normalize_path;
+
+/// Files whose changes make cached snippets stale: the bkmr database and snippet directories
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnippetSources {
+    /// bkmr's SQLite database, None when its location is unknown
+    pub database: Option<PathBuf>,
+    /// Directories of `*.snippet` files
+    pub snippet_dirs: Vec<PathBuf>,
+}
+
+impl SnippetSources {
+    pub fn new(database: Option<&str>, snippet_dirs: &[String]) -> Self {
+        Self {
+            database: database
+                .filter(|path| !path.is_empty())
+                .map(|path| normalize_path(Path::new(path))),
+            snippet_dirs: snippet_dirs
+                .iter()
+                .map(|dir| normalize_path(Path::new(dir)))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.database.is_none() && self.snippet_dirs.is_empty()
+    }
+
+    /// Watchers for the client, the database pattern also covers SQLite's -wal and -journal files
+    pub fn watchers(&self) -> Vec<FileSystemWatcher> {
+        let watcher = |pattern: String| FileSystemWatcher {
+            glob_pattern: GlobPattern::String(pattern),
+            kind: None,
+        };
+        self.database
+            .iter()
+            .map(|database| watcher(format!("{}*", database.display())))
+            .chain(
+                self.snippet_dirs
+                    .iter()
+                    .map(|dir| watcher(format!("{}/**", dir.display()))),
+            )
+            .collect()
+    }
+
+    /// Whether a changed file is the database, one of its SQLite companions, or in a snippet directory
+    pub fn contains(&self, uri: &Url) -> bool {
+        let Ok(path) = uri.to_file_path() else {
+            return false;
+        };
+        let path = normalize_path(&path);
+        let is_database = self.database.as_ref().is_some_and(|database| {
+            path.parent() == database.parent()
+                && path
+                    .file_name()
+                    .zip(database.file_name())
+                    .is_some_and(|(name, database)| {
+                        let (name, database) = (name.to_string_lossy(), database.to_string_lossy());
+                        name == database || name.starts_with(&format!("{}-", database))
+                    })
+        });
+        is_database || self.snippet_dirs.iter().any(|dir| path.starts_with(dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_database_and_snippet_dirs_when_building_watchers_then_watches_both() {
+        // Arrange
+        let sources = SnippetSources::new(
+            Some("/home/me/.config/bkmr/../bkmr/bkmr.db"),
+            &["/home/me/snippets/".to_string()],
+        );
+
+        // Act
+        let patterns: Vec<String> = sources
+            .watchers()
+            .into_iter()
+            .map(|watcher| match watcher.glob_pattern {
+                GlobPattern::String(pattern) => pattern,
+                other => panic!("unexpected pattern {:?}", other),
+            })
+            .collect();
+
+        // Assert
+        assert_eq!(
+            patterns,
+            vec!["/home/me/.config/bkmr/bkmr.db*", "/home/me/snippets/**"]
+        );
+        assert!(SnippetSources::new(Some(""), &[]).is_empty());
+    }
+
+    #[test]
+    fn given_changed_files_when_checking_sources_then_matches_database_companions_and_dirs() {
+        // Arrange
+        let sources =
+            SnippetSources::new(Some("/data/bkmr.db"), &["/home/me/snippets".to_string()]);
+        let uri = |path: &str| Url::from_file_path(path).expect("file URI");
+
+        // Act & Assert
+        assert!(sources.contains(&uri("/data/bkmr.db")));
+        assert!(sources.contains(&uri("/data/bkmr.db-wal")));
+        assert!(sources.contains(&uri("/home/me/snippets/rust/test.snippet")));
+        assert!(!sources.contains(&uri("/data/bkmr.dbx")));
+        assert!(!sources.contains(&uri("/data/other.db")));
+        assert!(!sources.contains(&uri("/home/me/snippets-old/a.snippet")));
+        assert!(!sources.contains(&Url::parse("untitled:Untitled-1").expect("parse URI")));
+    }
+}
@@ -0,0 +1,258 @@
+/// Kind of LSP snippet grammar error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnippetSyntaxErrorKind {
+    /// `${1:...` or `${VAR:...` without its closing brace
+    UnterminatedPlaceholder,
+    /// `${1|a,b` without the closing `|}`
+    UnterminatedChoice,
+    /// `${1/regex/format/` without its closing brace
+    UnterminatedTransform,
+}
+
+/// Malformed snippet syntax, located by the character where the construct starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnippetSyntaxError {
+    pub kind: SnippetSyntaxErrorKind,
+    /// Character offset of the `$` opening the construct
+    pub position: usize,
+}
+
+impl std::fmt::Display for SnippetSyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let what = match self.kind {
+            SnippetSyntaxErrorKind::UnterminatedPlaceholder => "placeholder without closing '}'",
+            SnippetSyntaxErrorKind::UnterminatedChoice => "choice without closing '|}'",
+            SnippetSyntaxErrorKind::UnterminatedTransform => "transform without closing '}'",
+        };
+        write!(f, "{} at character {}", what, self.position)
+    }
+}
+
+impl std::error::Error for SnippetSyntaxError {}
+
+/// Check text against the LSP snippet grammar
+///
+/// A `$` that starts no tabstop, placeholder, choice or variable is literal
+/// text, as editors treat it. Only constructs that are opened but never
+/// closed are errors, since editors insert those incorrectly or not at all.
+pub fn validate_snippet_syntax(text: &str) -> Result<(), SnippetSyntaxError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut pos = 0;
+    scan_text(&chars, &mut pos, None)
+}
+
+/// Scan text to the end, or past the closing brace of the placeholder opened at `open`
+fn scan_text(
+    chars: &[char],
+    pos: &mut usize,
+    open: Option<usize>,
+) -> Result<(), SnippetSyntaxError> {
+    while let Some(&c) = chars.get(*pos) {
+        match c {
+            '\\' if matches!(chars.get(*pos + 1), Some('$' | '}' | '\\')) => *pos += 2,
+            '}' if open.is_some() => {
+                *pos += 1;
+                return Ok(());
+            }
+            '$' => scan_dollar(chars, pos)?,
+            _ => *pos += 1,
+        }
+    }
+
+    match open {
+        Some(position) => Err(SnippetSyntaxError {
+            kind: SnippetSyntaxErrorKind::UnterminatedPlaceholder,
+            position,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Scan the tabstop, placeholder, choice or variable starting with the `$` at `pos`
+fn scan_dollar(chars: &[char], pos: &mut usize) -> Result<(), SnippetSyntaxError> {
+    let start = *pos;
+    if chars.get(start + 1) != Some(&'{') {
+        // $1, $VAR or a literal $
+        *pos = name_end(chars, start + 1).unwrap_or(start + 1);
+        return Ok(());
+    }
+
+    let Some(end) = name_end(chars, start + 2) else {
+        // "${" not followed by a number or name is literal text
+        *pos = start + 1;
+        return Ok(());
+    };
+    let is_tabstop = chars[start + 2].is_ascii_digit();
+    let error = |kind| SnippetSyntaxError {
+        kind,
+        position: start,
+    };
+
+    match chars.get(end) {
+        Some('}') => {
+            *pos = end + 1;
+            Ok(())
+        }
+        Some(':') => {
+            *pos = end + 1;
+            scan_text(chars, pos, Some(start))
+        }
+        Some('|') if is_tabstop => {
+            *pos = scan_choice(chars, end + 1)
+                .ok_or_else(|| error(SnippetSyntaxErrorKind::UnterminatedChoice))?;
+            Ok(())
+        }
+        Some('/') => {
+            *pos = scan_transform(chars, end + 1)
+                .ok_or_else(|| error(SnippetSyntaxErrorKind::UnterminatedTransform))?;
+            Ok(())
+        }
+        None => Err(error(SnippetSyntaxErrorKind::UnterminatedPlaceholder)),
+        // Anything else after "${1" or "${VAR" makes the "$" literal text
+        Some(_) => {
+            *pos = start + 1;
+            Ok(())
+        }
+    }
+}
+
+/// End of the tabstop number or variable name starting at `start`, None if there is none
+fn name_end(chars: &[char], start: usize) -> Option<usize> {
+    let first = *chars.get(start)?;
+    let end = if first.is_ascii_digit() {
+        (start..chars.len()).find(|&i| !chars[i].is_ascii_digit())
+    } else if first.is_ascii_alphabetic() || first == '_' {
+        (start..chars.len()).find(|&i| !(chars[i].is_ascii_alphanumeric() || chars[i] == '_'))
+    } else {
+        return None;
+    };
+    Some(end.unwrap_or(chars.len()))
+}
+
+/// Position after the `|}` closing the choice options starting at `pos`
+fn scan_choice(chars: &[char], mut pos: usize) -> Option<usize> {
+    while let Some(&c) = chars.get(pos) {
+        match c {
+            '\\' => pos += 2,
+            '|' if chars.get(pos + 1) == Some(&'}') => return Some(pos + 2),
+            _ => pos += 1,
+        }
+    }
+    None
+}
+
+/// Position after the `}` closing the transform `regex/format/options` starting at `pos`
+fn scan_transform(chars: &[char], mut pos: usize) -> Option<usize> {
+    let mut slashes = 1;
+    while let Some(&c) = chars.get(pos) {
+        match c {
+            '\\' => pos += 2,
+            // Format groups like ${1:/upcase} may contain slashes
+            '$' if slashes == 2 && chars.get(pos + 1) == Some(&'{') => {
+                pos = (pos..chars.len()).find(|&i| chars[i] == '}')? + 1;
+            }
+            '/' if slashes < 3 => {
+                slashes += 1;
+                pos += 1;
+            }
+            '}' if slashes == 3 => return Some(pos + 1),
+            _ => pos += 1,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_kind(text: &str) -> Option<SnippetSyntaxErrorKind> {
+        validate_snippet_syntax(text).err().map(|error| error.kind)
+    }
+
+    #[test]
+    fn given_tabstops_when_validating_then_accepts_them() {
+        // Assert
+        assert_eq!(validate_snippet_syntax("fn $1() {\n    $0\n}"), Ok(()));
+        assert_eq!(validate_snippet_syntax("${1} and ${12}"), Ok(()));
+    }
+
+    #[test]
+    fn given_nested_placeholders_when_validating_then_accepts_them() {
+        // Assert
+        assert_eq!(
+            validate_snippet_syntax("${1:outer ${2:inner} text} $0"),
+            Ok(())
+        );
+        assert_eq!(validate_snippet_syntax("${TM_FILENAME:untitled}"), Ok(()));
+    }
+
+    #[test]
+    fn given_choices_and_transforms_when_validating_then_accepts_them() {
+        // Assert
+        assert_eq!(
+            validate_snippet_syntax("${1|one,two\\,three,four|}"),
+            Ok(())
+        );
+        assert_eq!(
+            validate_snippet_syntax("${TM_FILENAME/(.*)\\..+$/${1:/upcase}/g}"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn given_escaped_and_literal_dollars_when_validating_then_accepts_them() {
+        // Assert
+        assert_eq!(validate_snippet_syntax("echo \\${HOME} costs 5$"), Ok(()));
+        assert_eq!(validate_snippet_syntax("${1:a \\} brace}"), Ok(()));
+        assert_eq!(validate_snippet_syntax("${ not a placeholder"), Ok(()));
+        assert_eq!(validate_snippet_syntax("} stray brace"), Ok(()));
+    }
+
+    #[test]
+    fn given_unterminated_placeholder_when_validating_then_reports_its_start() {
+        // Act
+        let result = validate_snippet_syntax("let x = ${1:value;");
+
+        // Assert
+        let error = result.expect_err("unterminated placeholder");
+        assert_eq!(error.kind, SnippetSyntaxErrorKind::UnterminatedPlaceholder);
+        assert_eq!(error.position, 8);
+        assert_eq!(
+            error.to_string(),
+            "placeholder without closing '}' at character 8"
+        );
+    }
+
+    #[test]
+    fn given_nested_placeholder_missing_outer_brace_when_validating_then_reports_outer() {
+        // Act
+        let result = validate_snippet_syntax("${1:outer ${2:inner}");
+
+        // Assert
+        assert_eq!(
+            result,
+            Err(SnippetSyntaxError {
+                kind: SnippetSyntaxErrorKind::UnterminatedPlaceholder,
+                position: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn given_malformed_choice_or_transform_when_validating_then_reports_them() {
+        // Assert
+        assert_eq!(
+            error_kind("${1|yes,no}"),
+            Some(SnippetSyntaxErrorKind::UnterminatedChoice)
+        );
+        assert_eq!(
+            error_kind("${1/(a)/b"),
+            Some(SnippetSyntaxErrorKind::UnterminatedTransform)
+        );
+        assert_eq!(
+            error_kind("text ${2"),
+            Some(SnippetSyntaxErrorKind::UnterminatedPlaceholder)
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// A bkmr tag with the number of bookmarks carrying it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+impl TagCount {
+    pub fn new(tag: impl Into<String>, count: usize) -> Self {
+        Self {
+            tag: tag.into(),
+            count,
+        }
+    }
+
+    /// Count how often each tag occurs, most frequent first
+    pub fn count<'a>(tags: impl IntoIterator<Item = &'a String>) -> Vec<TagCount> {
+        Self::merge(tags.into_iter().map(|tag| TagCount::new(tag.clone(), 1)))
+    }
+
+    /// Add up counts of the same tag, ignoring case, most frequent first
+    ///
+    /// The spelling seen first is kept; ties are ordered by tag.
+    pub fn merge(counts: impl IntoIterator<Item = TagCount>) -> Vec<TagCount> {
+        let mut merged: Vec<TagCount> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        for TagCount { tag, count } in counts {
+            let tag = tag.trim();
+            if tag.is_empty() {
+                continue;
+            }
+            match index.get(&tag.to_lowercase()) {
+                Some(&i) => merged[i].count += count,
+                None => {
+                    index.insert(tag.to_lowercase(), merged.len());
+                    merged.push(TagCount::new(tag, count));
+                }
+            }
+        }
+        merged.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+        merged
+    }
+}
+
+/// Tags completions are restricted to, for all documents or a single one
+///
+/// A document's own filter takes precedence over the global one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagFilters {
+    pub global: Option<String>,
+    /// Filters by document URI
+    pub documents: BTreeMap<String, String>,
+}
+
+impl TagFilters {
+    /// Set or, with None or a blank tag, clear the filter of a document or the global one
+    pub fn set(&mut self, uri: Option<&str>, tag: Option<String>) {
+        let tag = tag
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty());
+        match (uri, tag) {
+            (Some(uri), Some(tag)) => {
+                self.documents.insert(uri.to_string(), tag);
+            }
+            (Some(uri), None) => {
+                self.documents.remove(uri);
+            }
+            (None, tag) => self.global = tag,
+        }
+    }
+
+    /// Filter applying to completions in a document
+    pub fn for_document(&self, uri: &str) -> Option<&str> {
+        self.documents
+            .get(uri)
+            .or(self.global.as_ref())
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_tags_of_several_snippets_when_counting_then_merges_case_and_sorts_by_count() {
+        // Arrange
+        let tags: Vec<String> = ["sql", "regex", "SQL", "kubernetes", "sql", "regex", " "]
+            .iter()
+            .map(|tag| tag.to_string())
+            .collect();
+
+        // Act
+        let counts = TagCount::count(&tags);
+
+        // Assert
+        assert_eq!(
+            counts,
+            vec![
+                TagCount::new("sql", 3),
+                TagCount::new("regex", 2),
+                TagCount::new("kubernetes", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn given_global_and_document_filters_when_looking_up_then_document_filter_wins() {
+        // Arrange
+        let mut filters = TagFilters::default();
+        filters.set(None, Some("sql".to_string()));
+        filters.set(Some("file:///a.rs"), Some(" regex ".to_string()));
+
+        // Act & Assert
+        assert_eq!(filters.for_document("file:///a.rs"), Some("regex"));
+        assert_eq!(filters.for_document("file:///b.rs"), Some("sql"));
+
+        filters.set(Some("file:///a.rs"), None);
+        filters.set(None, Some("  ".to_string()));
+        assert_eq!(filters, TagFilters::default());
+    }
+}
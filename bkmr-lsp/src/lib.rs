@@ -1,14 +1,19 @@
 pub mod backend;
 pub mod domain;
+pub mod logging;
+pub mod metrics;
 pub mod repositories;
 pub mod services;
+mod sync;
+pub mod trace;
+pub mod transport;
 
 pub use backend::*;
 
 #[cfg(test)]
 mod tests {
-    use crate::domain::{BkmrSnippet, LanguageRegistry, SnippetFilter};
-    use crate::services::LanguageTranslator;
+    use crate::domain::{BkmrSnippet, LanguageRegistry, SnippetFilter, default_snippet_tags};
+    use crate::services::{ContextVariables, LanguageTranslator};
     use tower_lsp::lsp_types::Url;
 
     #[test]
@@ -39,15 +44,15 @@ mod tests {
 
     #[test]
     fn given_rust_line_comments_when_translating_to_python_then_converts_to_hash_comments() {
+        let registry = LanguageRegistry::default();
         // Arrange
-        let uri = Url::parse("file:///test/example.py").expect("valid test URI");
         let rust_content = r#"// This is a line comment
     // Indented comment
 let x = 5; // End of line comment"#;
 
         // Act
         let python_result =
-            LanguageTranslator::translate_rust_patterns(rust_content, "python", &uri)
+            LanguageTranslator::translate_rust_patterns(rust_content, "python", &registry)
                 .expect("translation should succeed");
 
         // Assert
@@ -56,8 +61,9 @@ let x = 5; // End of line comment"#;
         assert!(python_result.contains("let x = 5; # End of line comment"));
 
         // Test with HTML (no line comments)
-        let html_result = LanguageTranslator::translate_rust_patterns(rust_content, "html", &uri)
-            .expect("translation should succeed");
+        let html_result =
+            LanguageTranslator::translate_rust_patterns(rust_content, "html", &registry)
+                .expect("translation should succeed");
         assert!(html_result.contains("<!-- This is a line comment -->"));
         assert!(html_result.contains("  <!-- Indented comment -->")); // HTML uses 2 spaces
         assert!(html_result.contains("let x = 5; <!-- End of line comment -->"));
@@ -65,8 +71,7 @@ let x = 5; // End of line comment"#;
 
     #[test]
     fn test_rust_block_comment_translation() {
-        let uri = Url::parse("file:///test/example.py").expect("valid test URI");
-
+        let registry = LanguageRegistry::default();
         let rust_content = r#"/* This is a block comment */
 /*
 Multi-line
@@ -74,21 +79,21 @@ block comment
 */"#;
 
         let python_result =
-            LanguageTranslator::translate_rust_patterns(rust_content, "python", &uri)
+            LanguageTranslator::translate_rust_patterns(rust_content, "python", &registry)
                 .expect("translation should succeed");
         assert!(python_result.contains("\"\"\" This is a block comment \"\"\""));
         assert!(python_result.contains("\"\"\"\nMulti-line\nblock comment\n\"\"\""));
 
-        let html_result = LanguageTranslator::translate_rust_patterns(rust_content, "html", &uri)
-            .expect("translation should succeed");
+        let html_result =
+            LanguageTranslator::translate_rust_patterns(rust_content, "html", &registry)
+                .expect("translation should succeed");
         assert!(html_result.contains("<!-- This is a block comment -->"));
         assert!(html_result.contains("<!--\nMulti-line\nblock comment\n-->"));
     }
 
     #[test]
     fn test_rust_indentation_translation() {
-        let uri = Url::parse("file:///test/example.go").expect("valid Go test URI");
-
+        let registry = LanguageRegistry::default();
         let rust_content = r#"fn example() {
     let x = 5;
         let y = 10;
@@ -96,7 +101,7 @@ block comment
 }"#;
 
         // Go uses tabs
-        let go_result = LanguageTranslator::translate_rust_patterns(rust_content, "go", &uri)
+        let go_result = LanguageTranslator::translate_rust_patterns(rust_content, "go", &registry)
             .expect("translation should succeed");
         assert!(go_result.contains("fn example() {"));
         assert!(go_result.contains("\tlet x = 5;"));
@@ -105,7 +110,7 @@ block comment
 
         // JavaScript uses 2 spaces
         let js_result =
-            LanguageTranslator::translate_rust_patterns(rust_content, "javascript", &uri)
+            LanguageTranslator::translate_rust_patterns(rust_content, "javascript", &registry)
                 .expect("translation should succeed");
         assert!(js_result.contains("  let x = 5;"));
         assert!(js_result.contains("    let y = 10;"));
@@ -114,18 +119,22 @@ block comment
 
     #[test]
     fn test_filename_replacement() {
+        let registry = LanguageRegistry::default();
         let uri = Url::parse("file:///path/to/example.rs").expect("valid Rust test URI");
-
         let content = "// File: {{ filename }}";
-        let result = LanguageTranslator::translate_rust_patterns(content, "rust", &uri)
+        let result = LanguageTranslator::translate_rust_patterns(content, "rust", &registry)
             .expect("translation should succeed");
+        let result = LanguageTranslator::expand_context_variables(
+            &result,
+            &ContextVariables::new(&uri, "rust", &registry),
+            false,
+        );
         assert!(result.contains("// File: example.rs"));
     }
 
     #[test]
     fn test_mixed_pattern_translation() {
-        let uri = Url::parse("file:///test/example.py").expect("valid test URI");
-
+        let registry = LanguageRegistry::default();
         let rust_content = r#"// Function: {{ function_name }}
 // File: {{ filename }}
 fn {{ function_name }}() {
@@ -134,9 +143,15 @@ fn {{ function_name }}() {
         let value = "hello";
 }"#;
 
+        let uri = Url::parse("file:///test/example.py").expect("valid test URI");
         let python_result =
-            LanguageTranslator::translate_rust_patterns(rust_content, "python", &uri)
+            LanguageTranslator::translate_rust_patterns(rust_content, "python", &registry)
                 .expect("translation should succeed");
+        let python_result = LanguageTranslator::expand_context_variables(
+            &python_result,
+            &ContextVariables::new(&uri, "python", &registry),
+            false,
+        );
 
         // Check comment translation
         assert!(python_result.contains("# Function: {{ function_name }}"));
@@ -153,7 +168,8 @@ fn {{ function_name }}() {
 
     #[test]
     fn test_language_info_retrieval() {
-        let rust_info = LanguageRegistry::get_language_info("rust");
+        let registry = LanguageRegistry::default();
+        let rust_info = registry.get_language_info("rust");
         assert_eq!(rust_info.line_comment, Some("//".to_string()));
         assert_eq!(
             rust_info.block_comment,
@@ -161,7 +177,7 @@ fn {{ function_name }}() {
         );
         assert_eq!(rust_info.indent_char, "    ");
 
-        let python_info = LanguageRegistry::get_language_info("python");
+        let python_info = registry.get_language_info("python");
         assert_eq!(python_info.line_comment, Some("#".to_string()));
         assert_eq!(
             python_info.block_comment,
@@ -169,11 +185,11 @@ fn {{ function_name }}() {
         );
         assert_eq!(python_info.indent_char, "    ");
 
-        let go_info = LanguageRegistry::get_language_info("go");
+        let go_info = registry.get_language_info("go");
         assert_eq!(go_info.line_comment, Some("//".to_string()));
         assert_eq!(go_info.indent_char, "\t");
 
-        let html_info = LanguageRegistry::get_language_info("html");
+        let html_info = registry.get_language_info("html");
         assert_eq!(html_info.line_comment, None);
         assert_eq!(
             html_info.block_comment,
@@ -184,29 +200,29 @@ fn {{ function_name }}() {
 
     #[test]
     fn test_edge_cases() {
-        let uri = Url::parse("file:///test/example.py").expect("valid test URI");
-
+        let registry = LanguageRegistry::default();
         // Empty content
-        let result = LanguageTranslator::translate_rust_patterns("", "python", &uri)
+        let result = LanguageTranslator::translate_rust_patterns("", "python", &registry)
             .expect("translation should succeed");
         assert_eq!(result, "");
 
         // No Rust patterns
         let no_patterns = "Just plain text here";
-        let result = LanguageTranslator::translate_rust_patterns(no_patterns, "python", &uri)
+        let result = LanguageTranslator::translate_rust_patterns(no_patterns, "python", &registry)
             .expect("translation should succeed");
         assert_eq!(result, no_patterns);
 
         // Comments in strings (should not be translated)
         let string_comments = r#"let url = "https://example.com"; // Real comment"#;
-        let result = LanguageTranslator::translate_rust_patterns(string_comments, "python", &uri)
-            .expect("translation should succeed");
+        let result =
+            LanguageTranslator::translate_rust_patterns(string_comments, "python", &registry)
+                .expect("translation should succeed");
         assert!(result.contains("\"https://example.com\""));
         assert!(result.contains("# Real comment"));
 
         // Multiple line patterns
         let multi_line = "//Comment1\n//Comment2\n    //Comment3";
-        let result = LanguageTranslator::translate_rust_patterns(multi_line, "python", &uri)
+        let result = LanguageTranslator::translate_rust_patterns(multi_line, "python", &registry)
             .expect("translation should succeed");
         assert!(result.contains("# Comment1"));
         assert!(result.contains("# Comment2"));
@@ -217,7 +233,7 @@ fn {{ function_name }}() {
     fn test_fts_query_builder() {
         // Test with specific language
         let filter = SnippetFilter::new(Some("markdown".to_string()), None, 50);
-        let query = filter.build_fts_query();
+        let query = filter.build_fts_query(&LanguageRegistry::default(), &default_snippet_tags());
         assert_eq!(
             query,
             Some(
@@ -228,7 +244,7 @@ fn {{ function_name }}() {
 
         // Test with rust language
         let filter = SnippetFilter::new(Some("rust".to_string()), None, 50);
-        let query = filter.build_fts_query();
+        let query = filter.build_fts_query(&LanguageRegistry::default(), &default_snippet_tags());
         assert_eq!(
             query,
             Some(
@@ -239,22 +255,22 @@ fn {{ function_name }}() {
 
         // Test with empty language
         let filter = SnippetFilter::new(Some("".to_string()), None, 50);
-        let query = filter.build_fts_query();
+        let query = filter.build_fts_query(&LanguageRegistry::default(), &default_snippet_tags());
         assert_eq!(query, Some(r#"tags:"_snip_""#.to_string()));
 
         // Test with whitespace-only language
         let filter = SnippetFilter::new(Some("   ".to_string()), None, 50);
-        let query = filter.build_fts_query();
+        let query = filter.build_fts_query(&LanguageRegistry::default(), &default_snippet_tags());
         assert_eq!(query, Some(r#"tags:"_snip_""#.to_string()));
 
         // Test with None language
         let filter = SnippetFilter::new(None, None, 50);
-        let query = filter.build_fts_query();
+        let query = filter.build_fts_query(&LanguageRegistry::default(), &default_snippet_tags());
         assert_eq!(query, Some(r#"tags:"_snip_""#.to_string()));
 
         // Test with complex language names
         let filter = SnippetFilter::new(Some("typescript".to_string()), None, 50);
-        let query = filter.build_fts_query();
+        let query = filter.build_fts_query(&LanguageRegistry::default(), &default_snippet_tags());
         assert_eq!(
             query,
             Some(
@@ -266,8 +282,8 @@ fn {{ function_name }}() {
 
     #[test]
     fn test_multiline_universal_snippet_processing() {
+        let registry = LanguageRegistry::default();
         // Test the exact content from the failing snippet
-        let uri = Url::parse("file:///test/example.py").expect("valid test URI");
         let multiline_content = "{% raw %}\n// Fold description {{{ //\n\nContent\n// }}} Fold description //\n$0\n{% endraw %}";
 
         println!("Input content: {:?}", multiline_content);
@@ -276,8 +292,9 @@ fn {{ function_name }}() {
             multiline_content.split('\n').collect::<Vec<_>>()
         );
 
-        let result = LanguageTranslator::translate_rust_patterns(multiline_content, "python", &uri)
-            .expect("translation should succeed");
+        let result =
+            LanguageTranslator::translate_rust_patterns(multiline_content, "python", &registry)
+                .expect("translation should succeed");
 
         println!("Output content: {:?}", result);
         println!("Output lines: {:?}", result.split('\n').collect::<Vec<_>>());
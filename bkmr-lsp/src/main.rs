@@ -1,23 +1,652 @@
-use bkmr_lsp::backend::{BkmrLspBackend, BkmrConfig};
-use clap::Parser;
+use bkmr_lsp::backend::{BkmrConfig, ErrorMode};
+use bkmr_lsp::domain::{
+    AliasPattern, CaseMode, DEFAULT_ESCAPE_ALL_DOLLARS_FOR, DEFAULT_IMPORT_MARKER,
+    DEFAULT_MAX_SNIPPET_BYTES, DEFAULT_TRANSLATE_TAG, DEFAULT_URL_COMPLETION_LANGUAGES, ItemKind,
+    LanguageDefinitions, LanguageMap, LanguageOverrides, LargeSnippetMode, RankingStrategy,
+    ReplaceMode, TierOrder, parse_language_overrides,
+};
+use bkmr_lsp::logging::{LogConfig, LogContent, init_logging};
+use bkmr_lsp::metrics::DEFAULT_METRICS_SUMMARY_EVERY;
+use bkmr_lsp::repositories::{BkmrBackend, DEFAULT_ID_CACHE_CAPACITY, QueryStrategy};
+use bkmr_lsp::services::{
+    DEFAULT_DESCRIPTION_MAX_LENGTH, DEFAULT_DOC_CACHE_BYTES, DEFAULT_MIN_QUERY_INTERVAL_MS,
+    QueryService,
+};
+use bkmr_lsp::transport::{DEFAULT_TRANSPORT, Transport, run_server};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use std::collections::HashMap;
+use std::io::Write;
 use std::panic;
-use tower_lsp::{LspService, Server};
-use tracing_subscriber::EnvFilter;
+use std::path::PathBuf;
+use tower_lsp::lsp_types::Url;
 
 #[derive(Parser)]
 #[command(name = "bkmr-lsp")]
 #[command(about = "Language Server Protocol implementation for bkmr snippet manager")]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 struct Args {
+    /// Packaging helper or one-shot query to run instead of the server
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Disable bkmr template interpolation
-    #[arg(long, help = "Disable bkmr template interpolation (serve raw templates instead of processed content)")]
+    #[arg(
+        long,
+        help = "Disable bkmr template interpolation (serve raw templates instead of processed content)"
+    )]
     no_interpolation: bool,
+
+    /// Lifetime of cached bkmr search results in seconds
+    #[arg(
+        long,
+        default_value_t = 30,
+        help = "Cache bkmr search results for this many seconds (0 disables caching)"
+    )]
+    cache_ttl: u64,
+
+    /// Seconds a bkmr invocation may take
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 10,
+        help = "Abandon bkmr searches and checks taking longer than this many seconds"
+    )]
+    bkmr_timeout: u64,
+
+    /// Retries of bkmr searches failing on a locked database
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 2,
+        help = "Retry bkmr searches failing because the database is locked or busy up to N times"
+    )]
+    bkmr_retries: u32,
+
+    /// Maximum number of cached queries
+    #[arg(
+        long,
+        default_value_t = 100,
+        help = "Maximum number of distinct queries kept in the result cache"
+    )]
+    cache_max_entries: usize,
+
+    /// Maximum number of snippets cached by id
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_ID_CACHE_CAPACITY,
+        help = "Keep up to N searched snippets by id so that commands like insertSnippetById need no bkmr query, 0 disables"
+    )]
+    id_cache_size: usize,
+
+    /// Minimum time between two bkmr searches for one document
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = DEFAULT_MIN_QUERY_INTERVAL_MS,
+        help = "Answer completions requested within MS milliseconds of the last search for the document by narrowing its result, 0 searches every time"
+    )]
+    min_query_interval_ms: u64,
+
+    /// Path or name of the bkmr binary
+    #[arg(
+        long,
+        value_name = "PATH",
+        default_value = "bkmr",
+        help = "bkmr binary to run, looked up in PATH unless absolute"
+    )]
+    bkmr_binary: String,
+
+    /// bkmr database to use
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "bkmr database to use, passed to bkmr via BKMR_DB_URL"
+    )]
+    db_path: Option<String>,
+
+    /// Ordering of completion results
+    #[arg(
+        long,
+        value_name = "STRATEGY",
+        default_value = "relevance",
+        help = "Order completions by relevance, alphabetical or usage"
+    )]
+    ranking: RankingStrategy,
+
+    /// How language-specific and universal snippets are searched
+    #[arg(
+        long,
+        value_name = "STRATEGY",
+        default_value = "combined",
+        help = "Search language and universal snippets with one bkmr query (combined) or two concurrent ones (split)"
+    )]
+    query_strategy: QueryStrategy,
+
+    /// How bkmr is run for searches
+    #[arg(
+        long,
+        value_name = "BACKEND",
+        default_value = "cli",
+        help = "Run bkmr once per search (cli) or keep `bkmr serve --stdio` running, falling back to cli when bkmr has no daemon mode (daemon)"
+    )]
+    backend: BkmrBackend,
+
+    /// Maximum number of completion items per response
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 50,
+        help = "Return at most this many completion items per response, more on further typing"
+    )]
+    page_size: usize,
+
+    /// Maximum completion label length
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 60,
+        help = "Cut snippet titles longer than this many characters in completion labels, 0 disables"
+    )]
+    label_max_length: usize,
+
+    /// Maximum description length next to completion labels
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_DESCRIPTION_MAX_LENGTH,
+        help = "Show up to this many characters of a snippet's description next to its completion label, 0 shows only the id"
+    )]
+    description_max_length: usize,
+
+    /// Match typed characters against descriptions too
+    #[arg(
+        long,
+        help = "Add snippet descriptions to the filter text so typing a word of the description finds the snippet"
+    )]
+    filter_on_description: bool,
+
+    /// Completion item kind of snippets
+    #[arg(
+        long,
+        value_name = "KIND",
+        help = "Report snippets as this completion item kind for client icons: snippet, text, reference, module, keyword, function, value, constant, file or event"
+    )]
+    item_kind: Option<ItemKind>,
+
+    /// Completion item detail
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        help = "Detail shown next to snippets, with {tags}, {id}, {access_count} and {source} replaced, e.g. 'bkmr {source} #{id}' (default: \"bkmr snippet\")"
+    )]
+    detail_template: Option<String>,
+
+    /// Prefix of completion labels
+    #[arg(
+        long,
+        value_name = "TEXT",
+        default_value = "",
+        help = "Put this marker in front of snippet and bookmark labels, e.g. '󰆐 '"
+    )]
+    label_prefix: String,
+
+    /// Order of completion tiers
+    #[arg(
+        long,
+        value_name = "TIERS",
+        default_value = "exact,language,universal,fallback",
+        help = "List completion tiers in this order, e.g. 'universal,exact,language,fallback'; tiers left out follow in the default order"
+    )]
+    tier_order: TierOrder,
+
+    /// Range replaced inside a word by clients without insert-and-replace edits
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "replace",
+        help = "With the cursor inside a word, replace the whole word (replace) or only the part before the cursor (insert); clients supporting insert-and-replace edits choose themselves"
+    )]
+    replace_mode: ReplaceMode,
+
+    /// Whether typed query words match titles' case
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "smart",
+        help = "Ignore case in queries unless they have an uppercase character (smart), always (insensitive) or never (sensitive)"
+    )]
+    case_mode: CaseMode,
+
+    /// Character that triggers snippet completion
+    #[arg(
+        long,
+        value_name = "CHAR",
+        help = "Trigger snippet completion automatically after this character, e.g. ':'"
+    )]
+    trigger_char: Option<char>,
+
+    /// Additional language ID to snippet tag mappings
+    #[arg(
+        long,
+        value_name = "MAP",
+        help = "Map editor language IDs to bkmr tags, e.g. 'markdown=md,typescriptreact=tsx'"
+    )]
+    language_map: Option<LanguageMap>,
+
+    /// Completion settings replacing the global ones in some languages
+    #[arg(
+        long,
+        value_name = "OVERRIDES",
+        value_parser = parse_language_overrides,
+        help = "Per-language limit and ranking, e.g. 'markdown:limit=20,shellscript:limit=100,sh:ranking=usage'"
+    )]
+    per_language: Option<HashMap<String, LanguageOverrides>>,
+
+    /// Tags marking bkmr bookmarks as snippets
+    #[arg(
+        long = "snippet-tag",
+        value_name = "TAG",
+        default_value = "_snip_",
+        help = "Treat bookmarks with this tag as snippets, repeatable; '' disables the tag restriction"
+    )]
+    snippet_tags: Vec<String>,
+
+    /// Tags hiding snippets from completions
+    #[arg(
+        long = "exclude-tag",
+        value_name = "TAG",
+        help = "Never offer snippets with this tag, e.g. 'deprecated', repeatable"
+    )]
+    excluded_tags: Vec<String>,
+
+    /// Tags of bookmarks bkmr runs instead of inserting
+    #[arg(
+        long = "action-tags",
+        value_name = "TAGS",
+        value_delimiter = ',',
+        help = "Treat bookmarks with these tags like '_shell_' ones, comma separated or repeatable"
+    )]
+    action_tags: Vec<String>,
+
+    /// Offer action snippets instead of leaving them out
+    #[arg(
+        long,
+        help = "Offer '_shell_' and other action snippets as plain text marked [shell] instead of hiding them"
+    )]
+    include_action_snippets: bool,
+
+    /// Directories of plain snippet files
+    #[arg(
+        long = "snippet-dir",
+        value_name = "PATH",
+        help = "Also offer <PATH>/<language_id>/*.snippet and <PATH>/universal/*.snippet files, repeatable"
+    )]
+    snippet_dirs: Vec<String>,
+
+    /// Language definitions of the user
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "File of language comment syntax, indentation and extensions merged over the built-in definitions [default: $XDG_CONFIG_HOME/bkmr-lsp/languages.toml]"
+    )]
+    languages_file: Option<String>,
+
+    /// Interval of the latency summary in the log
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_METRICS_SUMMARY_EVERY,
+        help = "Log p50/p95 latencies of the completion stages and the bkmr spawn count every N completions, 0 for never"
+    )]
+    metrics_summary_every: u64,
+
+    /// Start of snippet lines declaring imports
+    #[arg(
+        long,
+        value_name = "MARKER",
+        default_value = DEFAULT_IMPORT_MARKER,
+        help = "Snippet header lines starting with this declare an import, added at the top of the file unless present, '' to insert them verbatim"
+    )]
+    import_marker: String,
+
+    /// Tag opting universal snippets into Rust-style translation
+    #[arg(
+        long,
+        value_name = "TAG",
+        default_value = DEFAULT_TRANSLATE_TAG,
+        help = "Translate universal snippets from Rust style (comments, indentation) only when they carry this tag as well"
+    )]
+    translate_tag: String,
+
+    /// Translate all universal snippets as before the translate tag existed
+    #[arg(
+        long,
+        help = "Translate every universal snippet from Rust style for every language, ignoring --translate-tag"
+    )]
+    legacy_universal_translation: bool,
+
+    /// Offer language-tagged snippets only
+    #[arg(
+        long,
+        help = "Leave universal snippets out of completions, only snippets tagged with the document's language are offered"
+    )]
+    no_universal: bool,
+
+    /// Extra characters of completion queries
+    #[arg(
+        long,
+        value_name = "CHARS",
+        value_delimiter = ',',
+        default_values = [".", "/"],
+        help = "Also treat these characters as part of the completion query, e.g. 'docker.compose', '' for none"
+    )]
+    word_chars: Vec<String>,
+
+    /// Languages whose `$1` and `$(...)` are literal text
+    #[arg(
+        long,
+        value_name = "LANGS",
+        value_delimiter = ',',
+        default_values = DEFAULT_ESCAPE_ALL_DOLLARS_FOR,
+        help = "Keep $1 and $(...) literal instead of tabstops in these languages, '' for none"
+    )]
+    escape_all_dollars_for: Vec<String>,
+
+    /// Convert bkmr editor placeholders to snippet tabstops
+    #[arg(
+        long,
+        value_name = "BOOL",
+        default_value_t = true,
+        num_args = 0..=1,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set,
+        help = "Convert {{ cursor }}, {{ tabstopN }} and {{ selection }} to snippet tabstops"
+    )]
+    convert_placeholders: bool,
+
+    /// Drop snippets with broken snippet syntax
+    #[arg(
+        long,
+        help = "Leave out snippets with malformed placeholders instead of inserting them as plain text"
+    )]
+    strict_snippets: bool,
+
+    /// Offer the best snippet match as ghost text
+    #[arg(
+        long,
+        help = "Answer textDocument/inlineCompletion with the best matching snippet as ghost text"
+    )]
+    inline_completions: bool,
+
+    /// Author of inserted snippets
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Name inserted for {{ author }} in snippets, git's user.name when not given"
+    )]
+    author: Option<String>,
+
+    /// Languages without snippet completions
+    #[arg(
+        long = "disable-for",
+        value_name = "LANGUAGE",
+        help = "Never offer completions in documents of this language id, e.g. 'gitcommit', repeatable"
+    )]
+    disable_for: Vec<String>,
+
+    /// Reporting of failing completions and commands
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "silent",
+        help = "Report bkmr failures as an error message with an empty result (silent), as a warning (log) or as a JSON-RPC error response (respond)"
+    )]
+    errors: ErrorMode,
+
+    /// Find snippets by the aliases in their description
+    #[arg(
+        long,
+        help = "Also match the typed prefix against snippet descriptions, where aliases like 'alias:kdep,kd' are stored"
+    )]
+    alias_search: bool,
+
+    /// Regex locating aliases in descriptions
+    #[arg(
+        long,
+        value_name = "REGEX",
+        help = "Read comma-separated aliases from the first group (or whole match) of this regex instead of an 'alias:' line"
+    )]
+    alias_pattern: Option<AliasPattern>,
+
+    /// Largest snippet content offered in completions
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = DEFAULT_MAX_SNIPPET_BYTES,
+        help = "Snippets with more content bytes are left out of completions or stubbed, 0 for no limit"
+    )]
+    max_snippet_bytes: usize,
+
+    /// Handling of snippets over --max-snippet-bytes
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "skip",
+        help = "Leave oversized snippets out (skip) or offer a placeholder to insert with bkmr.insertSnippetById (stub)"
+    )]
+    large_snippet_mode: LargeSnippetMode,
+
+    /// Adapt snippets to strings and comments they are completed in
+    #[arg(
+        long,
+        help = "Escape quotes, backslashes and newlines of snippets completed inside strings, and start continuation lines of snippets completed in comments with the comment leader"
+    )]
+    context_aware_insertion: bool,
+
+    /// Memory cap of the open document cache
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = DEFAULT_DOC_CACHE_BYTES,
+        help = "Keep at most this many bytes of open document texts, evicting the least recently used ones, 0 for no limit"
+    )]
+    doc_cache_bytes: usize,
+
+    /// Languages whose documents also complete bookmark URLs
+    #[arg(
+        long,
+        value_name = "LANGS",
+        value_delimiter = ',',
+        default_values = DEFAULT_URL_COMPLETION_LANGUAGES,
+        help = "Also complete bkmr bookmarks as links by title in these languages, '' for none"
+    )]
+    url_completion_languages: Vec<String>,
+
+    /// Use the platform's path separator in filepath comments
+    #[arg(
+        long,
+        help = "Write relative paths in filepath comments with backslashes on Windows instead of forward slashes"
+    )]
+    native_path_separators: bool,
+
+    /// Leave unsaved buffers without filepath comment
+    #[arg(
+        long,
+        help = "Make bkmr.insertFilepathComment report unsaved buffers instead of naming them like untitled-1.py"
+    )]
+    no_untitled_filepath_comment: bool,
+
+    /// Disable background snippet prefetching
+    #[arg(
+        long,
+        help = "Do not warm the snippet cache in the background when a document is opened"
+    )]
+    no_prefetch: bool,
+
+    /// Disable the most used snippets shown when nothing matches
+    #[arg(
+        long,
+        help = "Do not offer the most used snippets of the language when no snippet matches the query"
+    )]
+    no_fallback_completions: bool,
+
+    /// Resolve environment variables in documentation previews
+    #[arg(
+        long,
+        help = "Show $VAR and ${VAR} with their current values in completion documentation, never in inserted text"
+    )]
+    resolve_env_in_docs: bool,
+
+    /// Report inserted snippets back to bkmr
+    #[arg(
+        long,
+        help = "Record inserted completions in bkmr so that usage ranking learns from the editor"
+    )]
+    track_usage: bool,
+
+    /// bkmr subcommand recording snippet usage
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        default_value = "touch",
+        help = "bkmr subcommand run with the snippet id to record usage, e.g. 'update --access'"
+    )]
+    usage_command: String,
+
+    /// Open snippets with `bkmr edit` instead of `bkmr open`
+    #[arg(
+        long,
+        help = "Make the bkmr.openSnippet command run `bkmr edit` instead of `bkmr open`"
+    )]
+    open_with_edit: bool,
+
+    /// File to write logs to instead of stderr
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "BKMR_LSP_LOG",
+        help = "Write logs to this file, rotated daily, instead of stderr"
+    )]
+    log_file: Option<PathBuf>,
+
+    /// Number of rotated log files to keep
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 7,
+        help = "Keep this many daily log files when logging to --log-file"
+    )]
+    log_keep: usize,
+
+    /// Log filter overriding RUST_LOG
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        help = "Log level or filter, e.g. 'debug' or 'bkmr_lsp=trace' (overrides RUST_LOG)"
+    )]
+    log_level: Option<String>,
+
+    /// How much snippet content log lines show
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "preview",
+        help = "Log snippet bodies and bkmr output not at all (none), truncated with secrets masked (preview) or in full (full)"
+    )]
+    log_content: LogContent,
+
+    /// Transport to serve the LSP over
+    #[arg(
+        long,
+        value_name = "URI",
+        default_value = DEFAULT_TRANSPORT,
+        help = "Serve over stdin/stdout (stdio) or accept one client at a time on a TCP socket, e.g. tcp://127.0.0.1:9257"
+    )]
+    listen: Transport,
+
+    /// Accept a new TCP client after a disconnect
+    #[arg(
+        long,
+        help = "With --listen tcp://..., await the next client when one disconnects instead of exiting"
+    )]
+    keep_listening: bool,
+}
+
+/// Subcommands run instead of the server, which runs without one
+#[derive(Subcommand, Debug, PartialEq)]
+enum Command {
+    /// Print the completions the server would offer for a prefix as JSON
+    Query(QueryArgs),
+    /// Write a shell completion script to stdout
+    Completions {
+        /// Shell to generate the script for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Write a roff man page to stdout
+    Man,
+}
+
+/// Completion request of `bkmr-lsp query`, answered with the server's flags
+#[derive(clap::Args, Debug, PartialEq)]
+struct QueryArgs {
+    /// Language id of the document, as an editor sends it
+    #[arg(long, value_name = "ID")]
+    language: String,
+
+    /// Text before the cursor, whose last word is the query
+    #[arg(long, value_name = "TEXT", default_value = "")]
+    prefix: String,
+
+    /// URI of the document, for {{ filename }} and similar variables
+    #[arg(long, value_name = "URI")]
+    uri: Option<Url>,
+}
+
+/// Write the completion script for a shell
+fn write_completions(shell: Shell, out: &mut dyn Write) {
+    let mut command = Args::command();
+    clap_complete::generate(shell, &mut command, "bkmr-lsp", out);
+}
+
+/// Write the man page in roff format
+fn write_man_page(out: &mut dyn Write) -> std::io::Result<()> {
+    clap_mangen::Man::new(Args::command()).render(out)
+}
+
+/// Run a packaging subcommand, before any logging or LSP setup
+///
+/// Output is buffered because clap_complete panics on write errors like a closed pipe.
+fn run_command(command: Command) -> std::io::Result<()> {
+    let mut out = Vec::new();
+    match command {
+        Command::Completions { shell } => write_completions(shell, &mut out),
+        Command::Man => write_man_page(&mut out)?,
+        Command::Query(_) => unreachable!("queries run with the server's configuration"),
+    }
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&out)?;
+    stdout.flush()
 }
 
 #[tokio::main]
 async fn main() {
     // Parse command line arguments
-    let args = Args::parse();
+    let mut args = Args::parse();
+    let query = match args.command.take() {
+        Some(Command::Query(query)) => Some(query),
+        Some(command) => {
+            if let Err(e) = run_command(command) {
+                eprintln!("Failed to write output: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => None,
+    };
+
     // Set up panic hook to log panics instead of just exiting
     panic::set_hook(Box::new(|panic_info| {
         eprintln!("PANIC in bkmr-lsp: {}", panic_info);
@@ -41,66 +670,163 @@ async fn main() {
         std::process::exit(1);
     }));
 
-    // Initialize logging with fallback if it fails
-    let result = init_logging();
-    if let Err(e) = result {
+    // Initialize logging with fallback if it fails; the guard flushes the log file on exit
+    let log_config = LogConfig {
+        file: args.log_file,
+        keep: args.log_keep,
+        level: args.log_level,
+        content: args.log_content,
+    };
+    let log_guard = init_logging(&log_config).unwrap_or_else(|e| {
         eprintln!(
-            "Failed to initialize logging: {}, continuing without structured logging",
+            "Failed to initialize logging: {:#}, continuing without structured logging",
             e
         );
-    }
+        None
+    });
 
     tracing::info!("Starting bkmr-lsp server v{}", env!("CARGO_PKG_VERSION"));
 
     // Create configuration from CLI args
     let config = BkmrConfig {
+        bkmr_binary: args.bkmr_binary,
+        db_path: args.db_path,
+        ranking: args.ranking,
+        query_strategy: args.query_strategy,
+        backend: args.backend,
+        alias_search: args.alias_search,
+        alias_pattern: args.alias_pattern,
+        max_snippet_bytes: args.max_snippet_bytes,
+        large_snippet_mode: args.large_snippet_mode,
+        context_aware_insertion: args.context_aware_insertion,
+        doc_cache_bytes: args.doc_cache_bytes,
+        trigger_char: args.trigger_char,
         enable_interpolation: !args.no_interpolation,
+        cache_ttl_seconds: args.cache_ttl,
+        bkmr_timeout_seconds: args.bkmr_timeout,
+        bkmr_retries: args.bkmr_retries,
+        cache_max_entries: args.cache_max_entries,
+        id_cache_size: args.id_cache_size,
+        min_query_interval_ms: args.min_query_interval_ms,
+        open_with_edit: args.open_with_edit,
+        prefetch: !args.no_prefetch,
+        fallback_completions: !args.no_fallback_completions,
+        resolve_env_in_docs: args.resolve_env_in_docs,
+        convert_placeholders: args.convert_placeholders,
+        strict_snippets: args.strict_snippets,
+        native_path_separators: args.native_path_separators,
+        untitled_filepath_comment: !args.no_untitled_filepath_comment,
+        inline_completions: args.inline_completions,
+        author: args.author,
+        disable_for: args.disable_for,
+        errors: args.errors,
+        page_size: args.page_size,
+        label_max_length: args.label_max_length,
+        description_max_length: args.description_max_length,
+        filter_on_description: args.filter_on_description,
+        item_kind: args.item_kind,
+        detail_template: args.detail_template,
+        label_prefix: args.label_prefix,
+        tier_order: args.tier_order,
+        replace_mode: args.replace_mode,
+        case_mode: args.case_mode,
+        language_map: args.language_map.unwrap_or_default(),
+        per_language: args.per_language.unwrap_or_default(),
+        snippet_tags: args
+            .snippet_tags
+            .into_iter()
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect(),
+        excluded_tags: args.excluded_tags,
+        action_tags: args.action_tags,
+        include_action_snippets: args.include_action_snippets,
+        snippet_dirs: args.snippet_dirs,
+        languages_file: args.languages_file,
+        metrics_summary_every: args.metrics_summary_every,
+        import_marker: args.import_marker,
+        translate_tag: args.translate_tag,
+        legacy_universal_translation: args.legacy_universal_translation,
+        universal_snippets: !args.no_universal,
+        word_chars: args
+            .word_chars
+            .iter()
+            .flat_map(|chars| chars.trim().chars())
+            .collect(),
+        escape_all_dollars_for: args
+            .escape_all_dollars_for
+            .into_iter()
+            .map(|language| language.trim().to_string())
+            .filter(|language| !language.is_empty())
+            .collect(),
+        url_completion_languages: args
+            .url_completion_languages
+            .into_iter()
+            .map(|language| language.trim().to_string())
+            .filter(|language| !language.is_empty())
+            .collect(),
+        track_usage: args.track_usage,
+        usage_command: args.usage_command,
         ..Default::default()
     };
 
     tracing::info!("Configuration: {:?}", config);
 
-    // Validate environment before starting
-    if let Err(e) = validate_environment().await {
-        tracing::error!("Environment validation failed: {}", e);
-        eprintln!("Environment validation failed: {}", e);
+    if let Err(e) = config.validate_binary() {
+        tracing::error!("Invalid --bkmr-binary: {}", e);
+        eprintln!("Invalid --bkmr-binary: {}", e);
         std::process::exit(1);
     }
+    let config = config.with_resolved_author().await;
 
-    // Set up the LSP service with error handling
-    let (service, socket) = LspService::new(move |client| {
-        tracing::debug!("Creating new LSP backend instance");
-        BkmrLspBackend::with_config(client, config.clone())
-    });
-
-    tracing::info!("LSP service created, starting server on stdin/stdout");
+    if let Some(query) = query {
+        if let Err(e) = run_query(config, query).await {
+            eprintln!("Query failed: {:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    // Create server with stdin/stdout
-    let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
+    // Validate environment before starting, a network client needs no stdin/stdout
+    let transport = args.listen.with_keep_listening(args.keep_listening);
+    if transport == Transport::Stdio
+        && let Err(e) = validate_environment().await
+    {
+        tracing::error!("Environment validation failed: {}", e);
+        eprintln!("Environment validation failed: {}", e);
+        std::process::exit(1);
+    }
 
-    // Start the server - this method returns () and only exits on error via panic
+    // Serve until the exit notification or the end of input
     tracing::info!("Starting LSP server loop");
-    Server::new(stdin, stdout, socket).serve(service).await;
+    let exit_code = match run_server(transport, config).await {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            tracing::error!("Failed to start server: {:#}", e);
+            eprintln!("Failed to start server: {:#}", e);
+            1
+        }
+    };
 
-    // If we reach here, the server has shut down gracefully
-    tracing::info!("Server shutdown gracefully");
+    // Exit code 0 only after a shutdown request, a crashed client leaves us with 1
+    tracing::info!("Server stopped, exiting with code {}", exit_code);
+    // process::exit skips destructors, flush the log file first
+    drop(log_guard);
+    std::process::exit(exit_code);
 }
 
-/// Initialize logging with fallback options
-fn init_logging() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Try different logging configurations in order of preference
-    let filter = EnvFilter::try_from_default_env()
-        .or_else(|_| EnvFilter::try_new("bkmr_lsp=info"))
-        .unwrap_or_else(|_| EnvFilter::new("info"));
-
-    tracing_subscriber::fmt()
-        .with_writer(std::io::stderr)
-        .with_ansi(false) // Disable color codes for LSP compatibility
-        .with_target(false) // Reduce noise in LSP logs
-        .with_env_filter(filter)
-        .try_init()?;
-
+/// Answer a `bkmr-lsp query` through the real bkmr binary, printing the items as JSON
+async fn run_query(config: BkmrConfig, query: QueryArgs) -> anyhow::Result<()> {
+    let definitions = LanguageDefinitions::load_async(config.languages_path()).await;
+    let language_registry = std::sync::Arc::new(config.language_registry(definitions));
+    let repository = config.with_snippet_dirs(
+        config.bkmr_repository(language_registry.clone(), None, None),
+        &language_registry,
+    );
+    let items = QueryService::new(repository, config, language_registry)
+        .query(&query.language, &query.prefix, query.uri)
+        .await?;
+    println!("{}", serde_json::to_string_pretty(&items)?);
     Ok(())
 }
 
@@ -121,3 +847,164 @@ async fn validate_environment() -> Result<(), Box<dyn std::error::Error + Send +
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_no_subcommand_when_parsing_then_runs_server_with_flags() {
+        // Act
+        let args = Args::try_parse_from(["bkmr-lsp", "--no-interpolation", "--page-size", "20"])
+            .expect("valid arguments");
+
+        // Assert
+        assert_eq!(args.command, None);
+        assert!(args.no_interpolation);
+        assert_eq!(args.page_size, 20);
+        assert_eq!(args.query_strategy, QueryStrategy::Combined);
+    }
+
+    #[test]
+    fn given_query_strategy_flag_when_parsing_then_accepts_split_only_by_name() {
+        // Act
+        let args = Args::try_parse_from(["bkmr-lsp", "--query-strategy", "split"]);
+
+        // Assert
+        assert_eq!(
+            args.expect("valid arguments").query_strategy,
+            QueryStrategy::Split
+        );
+        assert!(Args::try_parse_from(["bkmr-lsp", "--query-strategy", "parallel"]).is_err());
+    }
+
+    #[test]
+    fn given_backend_flag_when_parsing_then_accepts_cli_and_daemon() {
+        // Act
+        let default = Args::try_parse_from(["bkmr-lsp"]).expect("valid arguments");
+        let daemon = Args::try_parse_from(["bkmr-lsp", "--backend", "daemon"]);
+
+        // Assert
+        assert_eq!(default.backend, BkmrBackend::Cli);
+        assert_eq!(
+            daemon.expect("valid arguments").backend,
+            BkmrBackend::Daemon
+        );
+        assert!(Args::try_parse_from(["bkmr-lsp", "--backend", "server"]).is_err());
+    }
+
+    #[test]
+    fn given_listen_flag_when_parsing_then_accepts_stdio_and_tcp_uris() {
+        // Act
+        let default = Args::try_parse_from(["bkmr-lsp"]).expect("valid arguments");
+        let tcp = Args::try_parse_from([
+            "bkmr-lsp",
+            "--listen",
+            "tcp://127.0.0.1:9257",
+            "--keep-listening",
+        ])
+        .expect("valid arguments");
+
+        // Assert
+        assert_eq!(default.listen, Transport::Stdio);
+        assert_eq!(
+            tcp.listen.with_keep_listening(tcp.keep_listening),
+            Transport::Tcp {
+                address: "127.0.0.1:9257".to_string(),
+                keep_listening: true,
+            }
+        );
+        assert!(Args::try_parse_from(["bkmr-lsp", "--listen", "ws://127.0.0.1:9257"]).is_err());
+        assert!(Args::try_parse_from(["bkmr-lsp", "--listen", "tcp://127.0.0.1"]).is_err());
+    }
+
+    #[test]
+    fn given_word_chars_flag_when_parsing_then_splits_on_commas() {
+        // Act
+        let default = Args::try_parse_from(["bkmr-lsp"]).expect("valid arguments");
+        let custom =
+            Args::try_parse_from(["bkmr-lsp", "--word-chars", ".,/,:"]).expect("valid arguments");
+
+        // Assert
+        assert_eq!(default.word_chars, vec![".", "/"]);
+        assert_eq!(custom.word_chars, vec![".", "/", ":"]);
+    }
+
+    #[test]
+    fn given_completions_subcommand_when_generating_then_script_lists_flags() {
+        // Arrange
+        let args = Args::try_parse_from(["bkmr-lsp", "completions", "bash"]).expect("valid");
+        let Some(Command::Completions { shell }) = args.command else {
+            panic!("expected completions subcommand");
+        };
+        let mut out = Vec::new();
+
+        // Act
+        write_completions(shell, &mut out);
+
+        // Assert
+        let script = String::from_utf8(out).expect("utf-8 script");
+        assert!(script.contains("--no-interpolation"));
+        assert!(script.contains("--snippet-tag"));
+        assert!(script.contains("--log-file"));
+    }
+
+    #[test]
+    fn given_each_supported_shell_when_parsing_then_accepts_it() {
+        // Assert
+        for shell in ["bash", "zsh", "fish", "powershell"] {
+            assert!(Args::try_parse_from(["bkmr-lsp", "completions", shell]).is_ok());
+        }
+        assert!(Args::try_parse_from(["bkmr-lsp", "completions", "cmd"]).is_err());
+    }
+
+    #[test]
+    fn given_query_subcommand_when_parsing_then_keeps_server_flags_and_query() {
+        // Act
+        let args = Args::try_parse_from([
+            "bkmr-lsp",
+            "--no-interpolation",
+            "query",
+            "--language",
+            "rust",
+            "--prefix",
+            "    hel",
+            "--uri",
+            "file:///src/main.rs",
+        ])
+        .expect("valid arguments");
+
+        // Assert
+        assert!(args.no_interpolation);
+        assert_eq!(
+            args.command,
+            Some(Command::Query(QueryArgs {
+                language: "rust".to_string(),
+                prefix: "    hel".to_string(),
+                uri: Some(Url::parse("file:///src/main.rs").expect("parse URI")),
+            }))
+        );
+        assert!(Args::try_parse_from(["bkmr-lsp", "query", "--prefix", "hel"]).is_err());
+        assert!(
+            Args::try_parse_from(["bkmr-lsp", "query", "--language", "rust", "--uri", "a b"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn given_man_subcommand_when_rendering_then_page_documents_flags() {
+        // Arrange
+        let args = Args::try_parse_from(["bkmr-lsp", "man"]).expect("valid");
+        assert_eq!(args.command, Some(Command::Man));
+        let mut out = Vec::new();
+
+        // Act
+        write_man_page(&mut out).expect("render man page");
+
+        // Assert
+        let page = String::from_utf8(out).expect("utf-8 man page");
+        assert!(page.contains(".TH bkmr-lsp"));
+        assert!(page.contains("no\\-interpolation"));
+        assert!(page.contains("snippet\\-tag"));
+    }
+}
@@ -0,0 +1,349 @@
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{Instrument, debug_span, info};
+
+/// Default number of completions between two latency summaries in the log
+pub const DEFAULT_METRICS_SUMMARY_EVERY: u64 = 50;
+
+/// Upper bounds of the histogram buckets in microseconds, a last bucket holds slower ones
+const BUCKET_BOUNDS_MICROS: [u64; 16] = [
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 2_500_000, 5_000_000, 10_000_000,
+];
+
+/// Stages of a completion request whose latency is recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading the query and its range from the document
+    QueryExtraction,
+    /// Searching the repository, bkmr or its cache
+    Fetch,
+    /// Ranking, translating and building the completion items
+    Items,
+    /// The whole textDocument/completion request
+    Total,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [Self::QueryExtraction, Self::Fetch, Self::Items, Self::Total];
+
+    /// Short name used in spans, the summary line and bkmr/status
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::QueryExtraction => "query",
+            Self::Fetch => "fetch",
+            Self::Items => "items",
+            Self::Total => "total",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Latency counts in fixed buckets, recorded without locks or allocation
+#[derive(Debug, Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKET_BOUNDS_MICROS.len() + 1],
+}
+
+impl Histogram {
+    pub fn record(&self, duration: Duration) {
+        self.buckets[Self::bucket(duration)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of recorded durations
+    pub fn count(&self) -> u64 {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Upper bound of the bucket the given fraction of durations falls into, None when empty
+    ///
+    /// Durations beyond the last bound count as the last bound.
+    pub fn percentile(&self, fraction: f64) -> Option<Duration> {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((fraction * total as f64).ceil() as u64).clamp(1, total);
+        let mut seen = 0;
+        let index = counts
+            .iter()
+            .position(|count| {
+                seen += count;
+                seen >= rank
+            })
+            .expect("rank lies within the total");
+        let bound = BUCKET_BOUNDS_MICROS[index.min(BUCKET_BOUNDS_MICROS.len() - 1)];
+        Some(Duration::from_micros(bound))
+    }
+
+    /// Index of the first bucket whose bound is at least the duration
+    fn bucket(duration: Duration) -> usize {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        BUCKET_BOUNDS_MICROS.partition_point(|bound| *bound < micros)
+    }
+}
+
+/// Latency of completion stages and bkmr process spawns, shared by the services
+///
+/// Every `summary_every` completions a summary line with the p50 and p95 of
+/// each stage is logged at info level.
+#[derive(Debug)]
+pub struct Metrics {
+    histograms: [Histogram; Stage::ALL.len()],
+    /// Microseconds of each stage in the most recent completion
+    last_micros: [AtomicU64; Stage::ALL.len()],
+    completions: AtomicU64,
+    bkmr_spawns: AtomicU64,
+    /// Completions between two summaries, 0 for none
+    summary_every: AtomicU64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new(DEFAULT_METRICS_SUMMARY_EVERY)
+    }
+}
+
+/// Latency numbers reported by the bkmr/status request
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsSnapshot {
+    pub completions: u64,
+    pub bkmr_spawns: u64,
+    /// Microseconds per stage of the most recent completion
+    pub last_micros: StageMicros,
+    pub p50_micros: StageMicros,
+    pub p95_micros: StageMicros,
+}
+
+/// One number per stage, in microseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StageMicros {
+    pub query: u64,
+    pub fetch: u64,
+    pub items: u64,
+    pub total: u64,
+}
+
+impl Metrics {
+    pub fn new(summary_every: u64) -> Self {
+        Self {
+            histograms: Default::default(),
+            last_micros: Default::default(),
+            completions: AtomicU64::new(0),
+            bkmr_spawns: AtomicU64::new(0),
+            summary_every: AtomicU64::new(summary_every),
+        }
+    }
+
+    /// Set the number of completions between two summaries, 0 for none
+    pub fn set_summary_every(&self, summary_every: u64) {
+        self.summary_every.store(summary_every, Ordering::Relaxed);
+    }
+
+    /// Record the latency of one stage
+    pub fn record(&self, stage: Stage, duration: Duration) {
+        self.histograms[stage.index()].record(duration);
+        self.last_micros[stage.index()].store(
+            u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Run a stage in a span of its name, recording how long it took
+    pub async fn time<F: Future>(&self, stage: Stage, future: F) -> F::Output {
+        let start = Instant::now();
+        let output = future
+            .instrument(debug_span!("stage", name = stage.name()))
+            .await;
+        self.record(stage, start.elapsed());
+        output
+    }
+
+    /// Run a synchronous stage in a span of its name, recording how long it took
+    pub fn measure<T>(&self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let output = debug_span!("stage", name = stage.name()).in_scope(f);
+        self.record(stage, start.elapsed());
+        output
+    }
+
+    /// Record a finished completion request, logging the summary every `summary_every` requests
+    pub fn finish_completion(&self, total: Duration) {
+        self.record(Stage::Total, total);
+        let completions = self.completions.fetch_add(1, Ordering::Relaxed) + 1;
+        let summary_every = self.summary_every.load(Ordering::Relaxed);
+        if summary_every > 0 && completions.is_multiple_of(summary_every) {
+            info!("{}", self.summary());
+        }
+    }
+
+    /// Count a bkmr process about to be spawned
+    pub fn record_spawn(&self) {
+        self.bkmr_spawns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of recorded latencies of a stage
+    pub fn count(&self, stage: Stage) -> u64 {
+        self.histograms[stage.index()].count()
+    }
+
+    /// Compact line with p50/p95 per stage and the spawn count
+    ///
+    /// E.g. "Latency of 50 completions: query p50 100µs p95 250µs, ..., 12 bkmr spawns".
+    pub fn summary(&self) -> String {
+        let stages: Vec<String> = Stage::ALL
+            .iter()
+            .map(|stage| {
+                let histogram = &self.histograms[stage.index()];
+                let percentile = |fraction| {
+                    histogram
+                        .percentile(fraction)
+                        .map_or_else(|| "-".to_string(), format_latency)
+                };
+                format!(
+                    "{} p50 {} p95 {}",
+                    stage.name(),
+                    percentile(0.5),
+                    percentile(0.95)
+                )
+            })
+            .collect();
+        format!(
+            "Latency of {} completions: {}, {} bkmr spawns",
+            self.completions.load(Ordering::Relaxed),
+            stages.join(", "),
+            self.bkmr_spawns.load(Ordering::Relaxed)
+        )
+    }
+
+    /// Counters and latencies for bkmr/status
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let micros = |value: &dyn Fn(Stage) -> u64| StageMicros {
+            query: value(Stage::QueryExtraction),
+            fetch: value(Stage::Fetch),
+            items: value(Stage::Items),
+            total: value(Stage::Total),
+        };
+        let percentile = |fraction: f64| {
+            micros(&|stage| {
+                self.histograms[stage.index()]
+                    .percentile(fraction)
+                    .map_or(0, |duration| duration.as_micros() as u64)
+            })
+        };
+        MetricsSnapshot {
+            completions: self.completions.load(Ordering::Relaxed),
+            bkmr_spawns: self.bkmr_spawns.load(Ordering::Relaxed),
+            last_micros: micros(&|stage| self.last_micros[stage.index()].load(Ordering::Relaxed)),
+            p50_micros: percentile(0.5),
+            p95_micros: percentile(0.95),
+        }
+    }
+}
+
+/// Duration with a unit fitting its size, e.g. "250µs", "2.5ms" or "1.2s"
+pub fn format_latency(duration: Duration) -> String {
+    let micros = duration.as_micros();
+    match micros {
+        0..1_000 => format!("{}µs", micros),
+        1_000..1_000_000 => format!("{}ms", trim_decimal(micros as f64 / 1e3)),
+        _ => format!("{}s", trim_decimal(micros as f64 / 1e6)),
+    }
+}
+
+/// One decimal, dropped when it is zero
+fn trim_decimal(value: f64) -> String {
+    let formatted = format!("{:.1}", value);
+    formatted
+        .strip_suffix(".0")
+        .map(str::to_string)
+        .unwrap_or(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_durations_when_bucketing_then_uses_first_bound_not_below_them() {
+        // Act & Assert
+        assert_eq!(Histogram::bucket(Duration::ZERO), 0);
+        assert_eq!(Histogram::bucket(Duration::from_micros(100)), 0);
+        assert_eq!(Histogram::bucket(Duration::from_micros(101)), 1);
+        assert_eq!(Histogram::bucket(Duration::from_millis(3)), 5);
+        assert_eq!(
+            Histogram::bucket(Duration::from_secs(60)),
+            BUCKET_BOUNDS_MICROS.len()
+        );
+    }
+
+    #[test]
+    fn given_recorded_durations_when_taking_percentiles_then_returns_bucket_bounds() {
+        // Arrange
+        let histogram = Histogram::default();
+        for _ in 0..9 {
+            histogram.record(Duration::from_micros(80));
+        }
+        histogram.record(Duration::from_millis(40));
+
+        // Act
+        let p50 = histogram.percentile(0.5);
+        let p95 = histogram.percentile(0.95);
+
+        // Assert
+        assert_eq!(histogram.count(), 10);
+        assert_eq!(p50, Some(Duration::from_micros(100)));
+        assert_eq!(p95, Some(Duration::from_millis(50)));
+        assert_eq!(Histogram::default().percentile(0.5), None);
+    }
+
+    #[test]
+    fn given_recorded_stages_when_summarizing_then_formats_one_compact_line() {
+        // Arrange
+        let metrics = Metrics::new(0);
+        metrics.record(Stage::QueryExtraction, Duration::from_micros(30));
+        metrics.record(Stage::Fetch, Duration::from_millis(20));
+        metrics.record(Stage::Items, Duration::from_micros(700));
+        metrics.finish_completion(Duration::from_millis(30));
+        metrics.record_spawn();
+
+        // Act
+        let summary = metrics.summary();
+
+        // Assert
+        assert_eq!(
+            summary,
+            "Latency of 1 completions: query p50 100µs p95 100µs, fetch p50 25ms p95 25ms, \
+             items p50 1ms p95 1ms, total p50 50ms p95 50ms, 1 bkmr spawns"
+        );
+        assert_eq!(
+            Metrics::new(0).summary(),
+            "Latency of 0 completions: query p50 - p95 -, fetch p50 - p95 -, \
+             items p50 - p95 -, total p50 - p95 -, 0 bkmr spawns"
+        );
+        assert_eq!(metrics.snapshot().last_micros.fetch, 20_000);
+    }
+
+    #[test]
+    fn given_durations_when_formatting_then_picks_unit() {
+        // Act & Assert
+        assert_eq!(format_latency(Duration::from_micros(250)), "250µs");
+        assert_eq!(format_latency(Duration::from_micros(2_500)), "2.5ms");
+        assert_eq!(format_latency(Duration::from_millis(25)), "25ms");
+        assert_eq!(format_latency(Duration::from_millis(1_200)), "1.2s");
+    }
+}
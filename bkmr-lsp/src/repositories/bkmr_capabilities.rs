@@ -0,0 +1,169 @@
+use serde::Serialize;
+
+use crate::repositories::BkmrRepository;
+
+/// Optional `bkmr search` flags the installed bkmr understands, and its version
+///
+/// Until bkmr has been probed every flag is assumed to be supported, as by
+/// current bkmr releases. Searches leave out the flags an older bkmr lacks
+/// instead of failing on them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BkmrCapabilities {
+    /// Number from `bkmr --version`, None before probing or if it printed none
+    pub version: Option<String>,
+    /// `--json` output, which searches are parsed from
+    pub json: bool,
+    /// `--limit`, emulated by cutting the results when missing
+    pub limit: bool,
+    /// `--interpolate`, interpolation is off when missing
+    pub interpolate: bool,
+    /// `--Ntags` excluding bookmarks with any of the tags
+    pub ntags: bool,
+    /// Whether the values come from the installed bkmr rather than assumed
+    pub probed: bool,
+}
+
+impl Default for BkmrCapabilities {
+    fn default() -> Self {
+        Self {
+            version: None,
+            json: true,
+            limit: true,
+            interpolate: true,
+            ntags: true,
+            probed: false,
+        }
+    }
+}
+
+impl BkmrCapabilities {
+    /// Capabilities from `bkmr --version` and `bkmr search --help` output
+    pub fn from_output(version: &str, search_help: &str) -> Self {
+        let has_flag = |flag: &str| Self::has_flag(search_help, flag);
+        Self {
+            version: BkmrRepository::parse_version(version),
+            json: has_flag("--json"),
+            limit: has_flag("--limit"),
+            interpolate: has_flag("--interpolate"),
+            ntags: has_flag("--Ntags"),
+            probed: true,
+        }
+    }
+
+    /// Whether help output lists a flag as a whole word, `--Ntags` is not `--ntags`
+    fn has_flag(help: &str, flag: &str) -> bool {
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        help.match_indices(flag).any(|(start, _)| {
+            let before = help[..start].chars().next_back();
+            let after = help[start + flag.len()..].chars().next();
+            !before.is_some_and(is_word) && !after.is_some_and(is_word)
+        })
+    }
+
+    /// Supported and missing flags for the log, e.g. "--json --limit supported, missing --Ntags"
+    pub fn describe(&self) -> String {
+        let flags = [
+            ("--json", self.json),
+            ("--limit", self.limit),
+            ("--interpolate", self.interpolate),
+            ("--Ntags", self.ntags),
+        ];
+        let names = |supported: bool| -> Vec<&str> {
+            flags
+                .iter()
+                .filter(|(_, has)| *has == supported)
+                .map(|(flag, _)| *flag)
+                .collect()
+        };
+        let (present, missing) = (names(true), names(false));
+        match (present.is_empty(), missing.is_empty()) {
+            (_, true) => format!("{} supported", present.join(" ")),
+            (true, false) => format!("missing {}", missing.join(" ")),
+            (false, false) => format!(
+                "{} supported, missing {}",
+                present.join(" "),
+                missing.join(" ")
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURRENT_HELP: &str = "\
+Searches bookmarks
+
+Usage: bkmr search [OPTIONS] [FTS_QUERY]
+
+Options:
+  -e, --exact <TAGS_EXACT>    match exact, comma-separated tag list
+  -t, --tags <TAGS_ALL>       match all, comma-separated tag list
+  -n, --ntags <TAGS_ANY>      match any, comma-separated tag list
+  -N, --Ntags <TAGS_ANY_NOT>  match any, comma-separated tag list
+  -l, --limit <LIMIT>
+      --json                  non-interactive mode, output as json
+      --interpolate           Render templates in URLs
+  -h, --help                  Print help
+";
+
+    #[test]
+    fn given_current_search_help_when_probing_then_every_flag_is_supported() {
+        // Act
+        let capabilities = BkmrCapabilities::from_output("bkmr 4.24.0\n", CURRENT_HELP);
+
+        // Assert
+        assert_eq!(
+            capabilities,
+            BkmrCapabilities {
+                version: Some("4.24.0".to_string()),
+                probed: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            capabilities.describe(),
+            "--json --limit --interpolate --Ntags supported"
+        );
+    }
+
+    #[test]
+    fn given_older_search_help_when_probing_then_missing_flags_are_unsupported() {
+        // Arrange: before --interpolate and --limit, only the lowercase ntags
+        let help = "Usage: bkmr search [OPTIONS] [FTS_QUERY]\n\n  -n, --ntags <TAGS_ANY>\n      --json\n      --np   no prompt\n";
+
+        // Act
+        let capabilities = BkmrCapabilities::from_output("bkmr 1.2.0", help);
+
+        // Assert
+        assert!(capabilities.json);
+        assert!(!capabilities.limit);
+        assert!(!capabilities.interpolate);
+        assert!(!capabilities.ntags);
+        assert_eq!(
+            capabilities.describe(),
+            "--json supported, missing --limit --interpolate --Ntags"
+        );
+    }
+
+    #[test]
+    fn given_flags_only_as_parts_of_words_when_probing_then_they_do_not_count() {
+        // Arrange
+        let help = "  --jsonl        json lines\n  --limit-rate   throttle\n  --no-interpolate\n";
+
+        // Act
+        let capabilities = BkmrCapabilities::from_output("", help);
+
+        // Assert
+        assert_eq!(capabilities.version, None);
+        assert!(!capabilities.json);
+        assert!(!capabilities.limit);
+        assert!(!capabilities.interpolate);
+        assert_eq!(
+            capabilities.describe(),
+            "missing --json --limit --interpolate --Ntags"
+        );
+    }
+}
@@ -0,0 +1,670 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::oneshot;
+use tracing::{debug, info, instrument, warn};
+
+use crate::domain::{BkmrSnippet, NewSnippet, Snippet, SnippetFilter, TagCount};
+use crate::logging::preview;
+use crate::metrics::Metrics;
+use crate::repositories::{
+    BkmrCapabilities, BkmrEntries, BkmrRepository, QueryStrategy, RepositoryConfig,
+    RepositoryStats, SnippetIdCache, SnippetRepository,
+};
+use crate::sync;
+
+/// Arguments starting bkmr's JSON-lines server on stdin and stdout
+const SERVE_ARGS: [&str; 2] = ["serve", "--stdio"];
+/// Longest wait before restarting a daemon that keeps dying
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(5);
+
+/// One search, written to the daemon as a JSON line
+#[derive(Debug, Serialize)]
+struct DaemonRequest<'a> {
+    id: u64,
+    query: Option<&'a str>,
+    limit: usize,
+    interpolate: bool,
+}
+
+/// The daemon's answer to the request with the same id
+#[derive(Debug, Deserialize)]
+struct DaemonResponse {
+    id: u64,
+    /// An array of entries, or an object wrapping them like bkmr's CLI output may
+    #[serde(default)]
+    snippets: Option<serde_json::Value>,
+    error: Option<String>,
+}
+
+type Reply = std::result::Result<serde_json::Value, String>;
+
+/// A running `bkmr serve --stdio` process
+struct Daemon {
+    /// Killed when the daemon is replaced, the repository dropped or the server shut down
+    child: Mutex<tokio::process::Child>,
+    stdin: tokio::sync::Mutex<tokio::process::ChildStdin>,
+    /// Requests waiting for their response, by request id
+    pending: Arc<Mutex<HashMap<u64, oneshot::Sender<Reply>>>>,
+    /// Cleared once the daemon closed its output or its input broke
+    alive: Arc<AtomicBool>,
+}
+
+impl Daemon {
+    /// Spawn the daemon and the tasks reading its output
+    fn spawn(mut command: tokio::process::Command) -> Result<Self> {
+        let mut child = command
+            .args(SERVE_ARGS)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("spawn bkmr daemon")?;
+        let stdin = child.stdin.take().context("open bkmr daemon stdin")?;
+        let stdout = child.stdout.take().context("open bkmr daemon stdout")?;
+        let stderr = child.stderr.take().context("open bkmr daemon stderr")?;
+
+        let pending: Arc<Mutex<HashMap<u64, oneshot::Sender<Reply>>>> = Arc::default();
+        let alive = Arc::new(AtomicBool::new(true));
+        tokio::spawn(Self::read_responses(stdout, pending.clone(), alive.clone()));
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                debug!("bkmr daemon: {}", line);
+            }
+        });
+
+        Ok(Self {
+            child: Mutex::new(child),
+            stdin: tokio::sync::Mutex::new(stdin),
+            pending,
+            alive,
+        })
+    }
+
+    /// Hand each response line to the request waiting for it until the daemon exits
+    ///
+    /// Requests still waiting then fail, as their senders are dropped.
+    async fn read_responses(
+        stdout: tokio::process::ChildStdout,
+        pending: Arc<Mutex<HashMap<u64, oneshot::Sender<Reply>>>>,
+        alive: Arc<AtomicBool>,
+    ) {
+        let mut lines = BufReader::new(stdout).lines();
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Failed to read bkmr daemon output: {}", e);
+                    break;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<DaemonResponse>(&line) {
+                Ok(response) => response,
+                Err(e) => {
+                    warn!(
+                        "Ignoring malformed bkmr daemon response {}: {}",
+                        preview(&line),
+                        e
+                    );
+                    continue;
+                }
+            };
+            let sender = sync::lock(&pending, "pending").remove(&response.id);
+            let reply = match response.error {
+                Some(error) => Err(error),
+                None => Ok(response
+                    .snippets
+                    .unwrap_or_else(|| serde_json::Value::Array(Vec::new()))),
+            };
+            match sender {
+                Some(sender) => {
+                    // The request may have timed out or been cancelled meanwhile
+                    let _ = sender.send(reply);
+                }
+                None => debug!(
+                    "No request waiting for bkmr daemon response {}",
+                    response.id
+                ),
+            }
+        }
+        alive.store(false, Ordering::SeqCst);
+        sync::lock(&pending, "pending").clear();
+        info!("bkmr daemon exited");
+    }
+
+    fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+
+    /// Kill the process, failing the requests waiting for it
+    fn kill(&self) {
+        self.alive.store(false, Ordering::SeqCst);
+        if let Err(e) = sync::lock(&self.child, "daemon child").start_kill() {
+            debug!("Failed to kill bkmr daemon: {}", e);
+        }
+        sync::lock(&self.pending, "pending").clear();
+    }
+
+    /// Send a request and wait for its response, the snippets as JSON entries
+    async fn request(&self, request: &DaemonRequest<'_>, timeout: Duration) -> Result<Reply> {
+        let mut line = serde_json::to_string(request).context("serialize bkmr daemon request")?;
+        line.push('\n');
+
+        let (sender, receiver) = oneshot::channel();
+        sync::lock(&self.pending, "pending").insert(request.id, sender);
+        // Checked after registering, the reader clears the pending requests after marking the daemon dead
+        if !self.is_alive() {
+            self.forget(request.id);
+            return Err(anyhow::anyhow!("bkmr daemon is not running"));
+        }
+
+        let written = async {
+            let mut stdin = self.stdin.lock().await;
+            stdin.write_all(line.as_bytes()).await?;
+            stdin.flush().await
+        }
+        .await;
+        if let Err(e) = written {
+            // A broken pipe means the daemon is gone
+            self.alive.store(false, Ordering::SeqCst);
+            self.forget(request.id);
+            return Err(e).context("write request to bkmr daemon");
+        }
+
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(_)) => Err(anyhow::anyhow!("bkmr daemon exited before answering")),
+            Err(_) => {
+                self.forget(request.id);
+                Err(anyhow::anyhow!(
+                    "bkmr daemon did not answer within {:?}",
+                    timeout
+                ))
+            }
+        }
+    }
+
+    fn forget(&self, id: u64) {
+        sync::lock(&self.pending, "pending").remove(&id);
+    }
+}
+
+enum DaemonState {
+    /// Not spawned yet, the first start probes whether bkmr has a daemon mode
+    NotStarted,
+    Running(Arc<Daemon>),
+    /// bkmr has no daemon mode, searches run one bkmr process each
+    Unsupported,
+    /// Killed at server shutdown, searches fail
+    Stopped,
+}
+
+/// Repository searching a long-running `bkmr serve --stdio` process
+///
+/// Saves bkmr's startup time on every completion. Requests are JSON lines
+/// `{id, query, limit, interpolate}` answered by `{id, snippets}` or
+/// `{id, error}` lines, in any order. A daemon that dies is restarted with
+/// backoff; when bkmr has no daemon mode, searches fall back to the CLI.
+/// Everything but searching is done by the CLI.
+pub struct BkmrDaemonRepository {
+    cli: BkmrRepository,
+    config: RepositoryConfig,
+    state: tokio::sync::Mutex<DaemonState>,
+    /// Restarts since the daemon last answered, doubling the wait before the next one
+    restarts: AtomicU32,
+    next_id: AtomicU64,
+    /// Set once searches fell back to the CLI
+    fell_back: AtomicBool,
+    /// Query and result size of the last search
+    stats: Mutex<RepositoryStats>,
+    /// Filter of the last search whose response had a total, and whether it found every match
+    last_complete: Mutex<Option<(SnippetFilter, bool)>>,
+}
+
+impl BkmrDaemonRepository {
+    pub fn new(config: RepositoryConfig) -> Self {
+        Self {
+            cli: BkmrRepository::new(config.clone()),
+            config,
+            state: tokio::sync::Mutex::new(DaemonState::NotStarted),
+            restarts: AtomicU32::new(0),
+            next_id: AtomicU64::new(1),
+            fell_back: AtomicBool::new(false),
+            stats: Mutex::new(RepositoryStats::default()),
+            last_complete: Mutex::new(None),
+        }
+    }
+
+    /// Send messages meant for the user, like paused searches, to the channel
+    pub fn with_notices(mut self, notices: tokio::sync::mpsc::UnboundedSender<String>) -> Self {
+        self.cli = self.cli.with_notices(notices);
+        self
+    }
+
+    /// Count spawned bkmr processes in the shared metrics
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.cli = self.cli.with_metrics(metrics);
+        self
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.config.timeout_seconds)
+    }
+
+    /// Start the daemon unless done before, false when bkmr has no daemon mode
+    pub async fn uses_daemon(&self) -> bool {
+        let mut state = self.state.lock().await;
+        if let DaemonState::NotStarted = *state {
+            *state = match self.start_daemon().await {
+                Ok(daemon) => {
+                    info!("Started bkmr daemon");
+                    DaemonState::Running(daemon)
+                }
+                Err(e) => {
+                    warn!(
+                        "bkmr daemon mode unavailable, running bkmr per search: {:#}",
+                        e
+                    );
+                    self.fell_back.store(true, Ordering::Relaxed);
+                    DaemonState::Unsupported
+                }
+            };
+        }
+        !matches!(*state, DaemonState::Unsupported)
+    }
+
+    /// Spawn the daemon and check that it answers a request
+    async fn start_daemon(&self) -> Result<Arc<Daemon>> {
+        let daemon = Daemon::spawn(self.cli.command())?;
+        let probe = DaemonRequest {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            query: None,
+            limit: 1,
+            interpolate: false,
+        };
+        // An error answer still shows that bkmr speaks the protocol
+        if let Err(message) = daemon
+            .request(&probe, self.timeout())
+            .await
+            .context("probe bkmr daemon")?
+        {
+            debug!("bkmr daemon answered the probe with an error: {}", message);
+        }
+        Ok(Arc::new(daemon))
+    }
+
+    /// The running daemon, restarted with backoff if it died
+    async fn running_daemon(&self) -> Result<Arc<Daemon>> {
+        let mut state = self.state.lock().await;
+        match &*state {
+            DaemonState::Running(daemon) if daemon.is_alive() => return Ok(daemon.clone()),
+            DaemonState::Stopped => return Err(anyhow::anyhow!("bkmr-lsp is shutting down")),
+            _ => {}
+        }
+
+        let restarts = self.restarts.fetch_add(1, Ordering::Relaxed);
+        let backoff = self
+            .config
+            .retry_backoff
+            .saturating_mul(2u32.saturating_pow(restarts))
+            .min(MAX_RESTART_BACKOFF);
+        warn!("Restarting bkmr daemon in {:?}", backoff);
+        tokio::time::sleep(backoff).await;
+        let daemon = self.start_daemon().await.context("restart bkmr daemon")?;
+        *state = DaemonState::Running(daemon.clone());
+        Ok(daemon)
+    }
+
+    /// Search the daemon, restarting it once if it died while answering
+    ///
+    /// Also tells whether the search found every match, if the daemon reported a total.
+    #[instrument(skip(self))]
+    async fn search(
+        &self,
+        query: Option<&str>,
+        limit: usize,
+    ) -> Result<(Vec<BkmrSnippet>, Option<bool>)> {
+        let mut restarted = false;
+        loop {
+            let daemon = self.running_daemon().await?;
+            let request = DaemonRequest {
+                id: self.next_id.fetch_add(1, Ordering::Relaxed),
+                query,
+                limit,
+                interpolate: self.cli.interpolates(),
+            };
+            match daemon.request(&request, self.timeout()).await {
+                Ok(reply) => {
+                    self.restarts.store(0, Ordering::Relaxed);
+                    let value = reply.map_err(|message| {
+                        anyhow::anyhow!("bkmr daemon search failed: {}", message)
+                    })?;
+                    let entries =
+                        BkmrEntries::from_value(value).context("parse bkmr daemon response")?;
+                    let complete = entries.complete();
+                    if let Some(total) = entries.total {
+                        debug!(
+                            "bkmr daemon reported {} matches, returned {}",
+                            total,
+                            entries.entries.len()
+                        );
+                    }
+                    let (snippets, skipped) = BkmrRepository::parse_entries(entries.entries);
+                    BkmrRepository::log_skipped(snippets.len(), skipped);
+                    return Ok((snippets, complete));
+                }
+                Err(e) if !restarted && !daemon.is_alive() => {
+                    warn!("bkmr daemon died during a search: {:#}", e);
+                    restarted = true;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Search language-specific and universal snippets concurrently and merge the results
+    async fn search_split(
+        &self,
+        limit: usize,
+        [language_query, universal_query]: [String; 2],
+    ) -> Result<Vec<BkmrSnippet>> {
+        let (language, universal) = tokio::join!(
+            self.search(Some(&language_query), limit),
+            self.search(Some(&universal_query), limit)
+        );
+        let (language, universal) = match (
+            language.map(|(snippets, _)| snippets),
+            universal.map(|(snippets, _)| snippets),
+        ) {
+            (Ok(language), Ok(universal)) => (language, universal),
+            (Ok(language), Err(e)) => {
+                warn!(
+                    "Universal snippet search failed, using language snippets only: {:#}",
+                    e
+                );
+                (language, Vec::new())
+            }
+            (Err(e), Ok(universal)) => {
+                warn!(
+                    "Language snippet search failed, using universal snippets only: {:#}",
+                    e
+                );
+                (Vec::new(), universal)
+            }
+            (Err(e), Err(_)) => return Err(e).context("search language and universal snippets"),
+        };
+        Ok(BkmrRepository::merge_split_results(
+            language, universal, limit,
+        ))
+    }
+}
+
+#[async_trait]
+impl SnippetRepository for BkmrDaemonRepository {
+    #[instrument(skip(self))]
+    async fn fetch_snippets(&self, filter: &SnippetFilter) -> Result<Vec<Snippet>> {
+        if !self.uses_daemon().await {
+            return self.cli.fetch_snippets(filter).await;
+        }
+
+        let split_queries = match self.config.query_strategy {
+            QueryStrategy::Combined => None,
+            QueryStrategy::Split => self.cli.build_split_fts_queries(filter),
+        };
+        let query = match &split_queries {
+            Some(_) => None,
+            None => self.cli.build_fts_query(filter),
+        };
+        {
+            let mut stats = sync::lock(&self.stats, "stats");
+            stats.last_query = match &split_queries {
+                Some(queries) => Some(queries.join(" | ")),
+                None => query.clone(),
+            };
+            stats.last_fetch_count = None;
+        }
+        let (bkmr_snippets, complete) = match split_queries {
+            Some(queries) => self
+                .search_split(filter.max_results, queries)
+                .await
+                .map(|snippets| (snippets, None)),
+            None => self.search(query.as_deref(), filter.max_results).await,
+        }
+        .context("fetch snippets from bkmr daemon")?;
+        *sync::lock(&self.last_complete, "last complete") =
+            complete.map(|complete| (filter.clone(), complete));
+
+        let snippets: Vec<Snippet> = self
+            .config
+            .content_limit
+            .apply(bkmr_snippets)
+            .into_iter()
+            .filter(|snippet| {
+                filter.matches_title(&snippet.title) && filter.allows_tags(&snippet.tags)
+            })
+            .collect();
+        sync::lock(&self.stats, "stats").last_fetch_count = Some(snippets.len());
+        if let Some(ids) = self.cli.id_cache() {
+            ids.insert_all(&snippets, std::time::Instant::now());
+        }
+
+        Ok(snippets)
+    }
+
+    async fn query_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.cli.query_snippet_by_id(id).await
+    }
+
+    fn id_cache(&self) -> Option<Arc<SnippetIdCache>> {
+        self.cli.id_cache()
+    }
+
+    async fn fetch_raw_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.cli.fetch_raw_snippet_by_id(id).await
+    }
+
+    async fn create_snippet(&self, snippet: &NewSnippet) -> Result<i32> {
+        self.cli.create_snippet(snippet).await
+    }
+
+    async fn open_snippet(&self, id: i32) -> Result<()> {
+        self.cli.open_snippet(id).await
+    }
+
+    async fn record_usage(&self, id: i32) -> Result<()> {
+        self.cli.record_usage(id).await
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.cli.health_check().await
+    }
+
+    /// Probe bkmr's flags and start the daemon ahead of the first search
+    async fn start(&self) -> Result<()> {
+        self.cli.start().await?;
+        self.uses_daemon().await;
+        Ok(())
+    }
+
+    async fn shutdown(&self) {
+        let previous = std::mem::replace(&mut *self.state.lock().await, DaemonState::Stopped);
+        if let DaemonState::Running(daemon) = previous {
+            info!("Stopping bkmr daemon");
+            daemon.kill();
+        }
+        self.cli.shutdown().await;
+    }
+
+    async fn list_tags(&self) -> Result<Vec<TagCount>> {
+        self.cli.list_tags().await
+    }
+
+    async fn version(&self) -> Result<Option<String>> {
+        self.cli.version().await
+    }
+
+    fn capabilities(&self) -> Option<BkmrCapabilities> {
+        self.cli.capabilities()
+    }
+
+    fn clear_cache(&self) -> usize {
+        self.cli.clear_cache()
+    }
+
+    fn search_complete(&self, filter: &SnippetFilter) -> Option<bool> {
+        if self.fell_back.load(Ordering::Relaxed) {
+            return self.cli.search_complete(filter);
+        }
+        match &*sync::lock(&self.last_complete, "last complete") {
+            Some((last, complete)) if last == filter => Some(*complete),
+            _ => None,
+        }
+    }
+
+    fn stats(&self) -> RepositoryStats {
+        if self.fell_back.load(Ordering::Relaxed) {
+            return self.cli.stats();
+        }
+        sync::lock(&self.stats, "stats").clone()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// Shell function answering a request line with a snippet titled by its prefix search term
+    const REPLY: &str = r#"reply() {
+  id=$(printf '%s' "$1" | sed 's/^{"id":\([0-9]*\).*/\1/')
+  word=$(printf '%s' "$1" | sed -n 's/.*metadata:\([a-z]*\).*/\1/p')
+  printf '{"id":%s,"snippets":[{"id":%s,"title":"%s","url":"echo %s","tags":["_snip_"]}]}\n' "$id" "$id" "$word" "$word"
+}"#;
+
+    /// Write an executable shell script standing in for the bkmr binary, in its own directory
+    fn fake_bkmr(name: &str, script: &str) -> std::path::PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir =
+            std::env::temp_dir().join(format!("bkmr-lsp-daemon-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create temp dir");
+        let path = dir.join("bkmr");
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n{}\n", REPLY, script))
+            .expect("write fake bkmr");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .expect("make fake bkmr executable");
+        path
+    }
+
+    fn repository(binary: &std::path::Path) -> BkmrDaemonRepository {
+        BkmrDaemonRepository::new(RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            timeout_seconds: 5,
+            ..Default::default()
+        })
+    }
+
+    fn prefix_filter(prefix: &str) -> SnippetFilter {
+        SnippetFilter::new(None, Some(prefix.to_string()), 10)
+    }
+
+    fn titles(snippets: Vec<Snippet>) -> Vec<String> {
+        snippets.into_iter().map(|snippet| snippet.title).collect()
+    }
+
+    #[tokio::test]
+    async fn given_concurrent_searches_when_daemon_answers_out_of_order_then_each_gets_its_reply() {
+        // Arrange: after the probe the daemon waits for two requests and answers the second first
+        let binary = fake_bkmr(
+            "multiplex",
+            r#"[ "$1" = "serve" ] || exit 2
+read -r probe; reply "$probe"
+read -r first; read -r second
+reply "$second"; reply "$first"
+while read -r line; do reply "$line"; done"#,
+        );
+        let repository = repository(&binary);
+        let (alpha_filter, beta_filter) = (prefix_filter("alpha"), prefix_filter("beta"));
+        assert!(repository.uses_daemon().await);
+
+        // Act
+        let (alpha, beta) = tokio::join!(
+            repository.fetch_snippets(&alpha_filter),
+            repository.fetch_snippets(&beta_filter)
+        );
+
+        // Assert
+        assert_eq!(titles(alpha.expect("alpha snippets")), vec!["alpha"]);
+        assert_eq!(titles(beta.expect("beta snippets")), vec!["beta"]);
+    }
+
+    #[tokio::test]
+    async fn given_killed_daemon_when_searching_then_restarts_it() {
+        // Arrange: every daemon started appends its pid
+        let binary = fake_bkmr(
+            "restart",
+            r#"[ "$1" = "serve" ] || exit 2
+echo $$ >> "$(dirname "$0")/pids"
+while read -r line; do reply "$line"; done"#,
+        );
+        let pids = binary.with_file_name("pids");
+        let _ = std::fs::remove_file(&pids);
+        let repository = repository(&binary);
+        let before = repository
+            .fetch_snippets(&prefix_filter("alpha"))
+            .await
+            .expect("snippets before kill");
+        let pid = std::fs::read_to_string(&pids).expect("read pids");
+        let killed = std::process::Command::new("kill")
+            .args(["-9", pid.trim()])
+            .status()
+            .expect("run kill");
+
+        // Act
+        let after = repository.fetch_snippets(&prefix_filter("beta")).await;
+
+        // Assert
+        assert!(killed.success());
+        assert_eq!(titles(before), vec!["alpha"]);
+        assert_eq!(titles(after.expect("snippets after restart")), vec!["beta"]);
+        assert_eq!(
+            std::fs::read_to_string(&pids)
+                .expect("read pids")
+                .lines()
+                .count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn given_bkmr_without_daemon_mode_when_searching_then_falls_back_to_cli() {
+        // Arrange: `serve` is unknown, searches print one snippet
+        let binary = fake_bkmr(
+            "fallback",
+            r#"if [ "$1" = "serve" ]; then echo "error: unrecognized subcommand 'serve'" >&2; exit 2; fi
+printf '[{"id": 7, "title": "from cli", "url": "ls", "tags": ["_snip_"]}]'"#,
+        );
+        let repository = repository(&binary);
+
+        // Act
+        let uses_daemon = repository.uses_daemon().await;
+        let snippets = repository
+            .fetch_snippets(&prefix_filter("from"))
+            .await
+            .expect("snippets from cli");
+
+        // Assert
+        assert!(!uses_daemon);
+        assert_eq!(titles(snippets), vec!["from cli"]);
+    }
+}
@@ -1,106 +1,856 @@
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use tracing::{debug, error, info, instrument};
+use std::collections::HashSet;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, error, info, instrument, warn};
 
-use crate::domain::{BkmrSnippet, Snippet, SnippetFilter};
-use crate::repositories::{RepositoryConfig, SnippetRepository};
+use crate::domain::{BkmrSnippet, NewSnippet, Snippet, SnippetFilter, TagCount, fts_prefix_term};
+use crate::logging::preview;
+use crate::metrics::Metrics;
+use crate::repositories::{
+    BkmrCapabilities, CircuitBreaker, QueryStrategy, RepositoryConfig, RepositoryStats,
+    SnippetIdCache, SnippetRepository, TAG_SEARCH_LIMIT,
+};
+use crate::sync;
+
+/// Environment variable selecting the bkmr database
+pub const BKMR_DB_URL: &str = "BKMR_DB_URL";
+/// Seconds recording snippet usage may take, it runs in the background
+const USAGE_TIMEOUT_SECONDS: u64 = 2;
+/// Words in bkmr's error output telling that another process holds the database
+const TRANSIENT_ERROR_MARKERS: [&str; 2] = ["locked", "busy"];
+
+/// Parse the JSON document in bkmr's stdout, tolerating text around it
+///
+/// Warnings printed before the JSON are skipped up to the first `[` or `{`
+/// that starts a valid document, anything after the document is ignored.
+/// Empty output is an empty array.
+pub fn parse_bkmr_output(stdout: &str) -> Result<serde_json::Value> {
+    if stdout.trim().is_empty() {
+        return Ok(serde_json::Value::Array(Vec::new()));
+    }
+
+    for (start, _) in stdout.match_indices(['[', '{']) {
+        let mut documents =
+            serde_json::Deserializer::from_str(&stdout[start..]).into_iter::<serde_json::Value>();
+        let Some(Ok(value)) = documents.next() else {
+            continue;
+        };
+
+        let prefix = stdout[..start].trim();
+        if !prefix.is_empty() {
+            warn!("Skipped text before bkmr JSON output: {}", preview(prefix));
+        }
+        let trailing = stdout[start + documents.byte_offset()..].trim();
+        if !trailing.is_empty() {
+            warn!("Ignored text after bkmr JSON output: {}", preview(trailing));
+        }
+        return Ok(value);
+    }
+
+    Err(anyhow::anyhow!(
+        "No JSON document in bkmr output: {}",
+        preview(stdout)
+    ))
+}
+
+/// Keys of the object some bkmr versions wrap the result array in, the first one present is used
+pub const RESULT_ARRAY_KEYS: [&str; 2] = ["items", "results"];
+
+/// Entries of a bkmr search and the number of matches bkmr reported, if it did
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BkmrEntries {
+    pub entries: Vec<serde_json::Value>,
+    pub total: Option<usize>,
+}
+
+impl BkmrEntries {
+    /// Entries of a bare array, or of an object wrapper like `{"items": [..], "total": N}`
+    ///
+    /// Unknown fields of the wrapper are ignored, so are totals that are not
+    /// a number.
+    pub fn from_value(value: serde_json::Value) -> Result<Self> {
+        use serde_json::Value;
+
+        let mut object = match value {
+            Value::Array(entries) => {
+                debug!("bkmr output is an array of {} entries", entries.len());
+                return Ok(Self {
+                    entries,
+                    total: None,
+                });
+            }
+            Value::Object(object) => object,
+            other => {
+                return Err(anyhow::anyhow!(
+                    "expected a JSON array or object in bkmr output, got {}",
+                    json_type(&other)
+                ));
+            }
+        };
+
+        let Some((key, entries)) =
+            RESULT_ARRAY_KEYS
+                .into_iter()
+                .find_map(|key| match object.get_mut(key) {
+                    Some(Value::Array(entries)) => Some((key, std::mem::take(entries))),
+                    _ => None,
+                })
+        else {
+            let keys: Vec<&str> = object.keys().map(String::as_str).collect();
+            return Err(anyhow::anyhow!(
+                "bkmr output object has no {} array, found keys: {}",
+                RESULT_ARRAY_KEYS.join(" or "),
+                if keys.is_empty() {
+                    "none".to_string()
+                } else {
+                    keys.join(", ")
+                }
+            ));
+        };
+        let total = object
+            .get("total")
+            .and_then(Value::as_u64)
+            .map(|total| total as usize);
+        debug!(
+            "bkmr output wraps {} entries in an object under '{}', total {:?}",
+            entries.len(),
+            key,
+            total
+        );
+        Ok(Self { entries, total })
+    }
+
+    /// Whether the entries are every match, None when bkmr reported no total
+    pub fn complete(&self) -> Option<bool> {
+        self.total.map(|total| total <= self.entries.len())
+    }
+}
+
+/// Name of a JSON value's type for error messages
+fn json_type(value: &serde_json::Value) -> &'static str {
+    use serde_json::Value;
+
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Parse the entries of bkmr search output, see [`parse_bkmr_output`] and [`BkmrEntries::from_value`]
+pub fn parse_bkmr_entries(stdout: &str) -> Result<BkmrEntries> {
+    BkmrEntries::from_value(parse_bkmr_output(stdout)?)
+        .with_context(|| format!("parse bkmr output as JSON array: {}", preview(stdout)))
+}
+
+/// A failed bkmr run, transient when running it again may succeed
+#[derive(Debug)]
+struct BkmrFailure {
+    error: anyhow::Error,
+    transient: bool,
+}
+
+impl BkmrFailure {
+    fn hard(error: anyhow::Error) -> Self {
+        Self {
+            error,
+            transient: false,
+        }
+    }
+
+    fn transient(error: anyhow::Error) -> Self {
+        Self {
+            error,
+            transient: true,
+        }
+    }
+}
 
 /// Repository implementation that uses the bkmr CLI for snippet retrieval
 pub struct BkmrRepository {
     config: RepositoryConfig,
+    /// Query and result size of the last search
+    stats: Mutex<RepositoryStats>,
+    /// Pauses searches while bkmr keeps failing
+    breaker: CircuitBreaker,
+    /// Messages for the user, e.g. that searches are paused
+    notices: Option<tokio::sync::mpsc::UnboundedSender<String>>,
+    /// Counts the bkmr processes spawned
+    metrics: Option<Arc<Metrics>>,
+    /// Set at server shutdown, killing running bkmr processes and refusing new ones
+    shut_down: tokio::sync::watch::Sender<bool>,
+    /// Snippets of recent searches by id
+    ids: Arc<SnippetIdCache>,
+    /// Filter of the last search whose output had a total, and whether it found every match
+    last_complete: Mutex<Option<(SnippetFilter, bool)>>,
+    /// Flags of the installed bkmr, the configured ones until probed at start
+    capabilities: Mutex<BkmrCapabilities>,
 }
 
 impl BkmrRepository {
     pub fn new(config: RepositoryConfig) -> Self {
-        Self { config }
+        Self {
+            breaker: CircuitBreaker::new(config.breaker_threshold, config.breaker_cooldown),
+            ids: Arc::new(SnippetIdCache::new(
+                config.id_cache_capacity,
+                config.id_cache_ttl,
+            )),
+            stats: Mutex::new(RepositoryStats::default()),
+            notices: None,
+            metrics: None,
+            shut_down: tokio::sync::watch::Sender::new(false),
+            last_complete: Mutex::new(None),
+            capabilities: Mutex::new(config.capabilities.clone()),
+            config,
+        }
+    }
+
+    /// Send messages meant for the user, like paused searches, to the channel
+    pub fn with_notices(mut self, notices: tokio::sync::mpsc::UnboundedSender<String>) -> Self {
+        self.notices = Some(notices);
+        self
+    }
+
+    /// Count spawned bkmr processes in the shared metrics
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    fn notify(&self, message: String) {
+        if let Some(notices) = &self.notices
+            && notices.send(message).is_err()
+        {
+            debug!("Notice receiver is gone");
+        }
+    }
+
+    /// Extract the version number from `bkmr --version` output, e.g. "bkmr 4.24.0"
+    pub fn parse_version(stdout: &str) -> Option<String> {
+        stdout
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .and_then(|line| line.split_whitespace().next_back())
+            .map(str::to_string)
+    }
+
+    /// bkmr flags in use, probed at start or those configured
+    pub fn bkmr_capabilities(&self) -> BkmrCapabilities {
+        sync::lock(&self.capabilities, "capabilities").clone()
+    }
+
+    /// Whether searches interpolate templates, configured and supported by bkmr
+    pub(crate) fn interpolates(&self) -> bool {
+        self.config.enable_interpolation && self.bkmr_capabilities().interpolate
+    }
+
+    /// Run `bkmr --version` and `bkmr search --help` together and read which flags bkmr has
+    #[instrument(skip(self))]
+    pub async fn probe_capabilities(&self) -> Result<BkmrCapabilities> {
+        let (version, help) = tokio::join!(
+            self.run_probe(&["--version"]),
+            self.run_probe(&["search", "--help"])
+        );
+        Ok(BkmrCapabilities::from_output(&version?, &help?))
+    }
+
+    /// Probe bkmr once and adapt searches to it, keeping the assumed flags if that fails
+    ///
+    /// Missing flags are left out of the commands. Interpolation is turned
+    /// off with a notice when it is enabled but bkmr cannot do it.
+    async fn probe_once(&self) {
+        if self.bkmr_capabilities().probed {
+            return;
+        }
+        let capabilities = match self.probe_capabilities().await {
+            Ok(capabilities) => capabilities,
+            Err(e) => {
+                warn!(
+                    "Probing bkmr flags failed, assuming all are supported: {:#}",
+                    e
+                );
+                return;
+            }
+        };
+        info!(
+            "bkmr {}: {}",
+            capabilities
+                .version
+                .as_deref()
+                .unwrap_or("of unknown version"),
+            capabilities.describe()
+        );
+        if !capabilities.json {
+            error!(
+                "bkmr search has no --json flag, its output cannot be read, please upgrade bkmr"
+            );
+        }
+        if self.config.enable_interpolation && !capabilities.interpolate {
+            let message = "bkmr search has no --interpolate flag, snippet templates are inserted uninterpolated";
+            warn!("{}", message);
+            self.notify(message.to_string());
+        }
+        *sync::lock(&self.capabilities, "capabilities") = capabilities;
+    }
+
+    /// Stdout of a short bkmr run like `bkmr --version`, an error if it fails
+    async fn run_probe(&self, args: &[&str]) -> Result<String> {
+        let output = tokio::time::timeout(
+            std::time::Duration::from_secs(self.config.timeout_seconds),
+            self.until_shutdown(self.command().args(args).output()),
+        )
+        .await
+        .with_context(|| format!("execute bkmr {} within timeout", args.join(" ")))?
+        .with_context(|| format!("spawn bkmr binary '{}'", self.config.binary_path))?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "bkmr {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Create a bkmr command targeting the configured binary and database
+    ///
+    /// The process is killed when its future is dropped, e.g. because the
+    /// client cancelled the request or a newer completion superseded it.
+    pub(crate) fn command(&self) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new(&self.config.binary_path);
+        command.kill_on_drop(true);
+        if let Some(metrics) = &self.metrics {
+            metrics.record_spawn();
+        }
+        if let Some(ref db_path) = self.config.db_path {
+            command.env(BKMR_DB_URL, db_path);
+        }
+        command
+    }
+
+    /// Await a bkmr process unless the server shuts down first
+    ///
+    /// On shutdown the process future is dropped, which kills the process, and
+    /// processes are no longer started.
+    async fn until_shutdown<T>(
+        &self,
+        process: impl Future<Output = std::io::Result<T>>,
+    ) -> std::io::Result<T> {
+        let mut shut_down = self.shut_down.subscribe();
+        tokio::select! {
+            biased;
+            _ = shut_down.wait_for(|shut_down| *shut_down) => Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "bkmr-lsp is shutting down",
+            )),
+            output = process => output,
+        }
     }
 
     /// Build command arguments for bkmr CLI
     fn build_command_args(&self, filter: &SnippetFilter) -> Vec<String> {
-        let mut args = vec![
-            "search".to_string(),
-            "--json".to_string(),
-            "--limit".to_string(),
-            filter.max_results.to_string(),
-        ];
+        self.build_search_args(filter.max_results, self.build_fts_query(filter))
+    }
+
+    /// Build search arguments for one FTS query
+    fn build_search_args(&self, limit: usize, fts_query: Option<String>) -> Vec<String> {
+        let mut args = self.search_flags(limit);
 
         // Conditionally add interpolation flag
-        if self.config.enable_interpolation {
+        if self.interpolates() {
             args.push("--interpolate".to_string());
         }
 
-        // Build FTS query that combines language-specific and universal snippets
+        if let Some(fts_query) = fts_query {
+            debug!("Final FTS query: {}", fts_query);
+            args.push(fts_query);
+        }
+
+        args
+    }
+
+    /// Build the FTS query that combines language-specific and universal snippets
+    pub(crate) fn build_fts_query(&self, filter: &SnippetFilter) -> Option<String> {
         let mut fts_parts = Vec::new();
 
         // Add language + universal snippet query
-        if let Some(snippet_query) = filter.build_fts_query() {
+        if let Some(snippet_query) =
+            filter.build_fts_query(&self.config.language_registry, &self.config.snippet_tags)
+        {
             fts_parts.push(format!("({})", snippet_query));
             debug!("Using snippet query: {}", snippet_query);
         }
+        fts_parts.extend(self.metadata_queries(filter));
+        fts_parts.extend(filter.requirement_query());
+
+        // Combine all FTS parts with AND logic
+        (!fts_parts.is_empty()).then(|| self.exclude_tags(fts_parts.join(" AND "), filter))
+    }
+
+    /// Build the language-specific and the universal FTS query of the split strategy
+    ///
+    /// None without a language, which needs a single query only.
+    pub(crate) fn build_split_fts_queries(&self, filter: &SnippetFilter) -> Option<[String; 2]> {
+        let mut metadata_queries = self.metadata_queries(filter);
+        metadata_queries.extend(filter.requirement_query());
+        filter
+            .build_split_fts_queries(&self.config.language_registry, &self.config.snippet_tags)
+            .map(|queries| {
+                queries.map(|query| {
+                    let query = std::iter::once(query)
+                        .chain(metadata_queries.iter().cloned())
+                        .collect::<Vec<_>>()
+                        .join(" AND ");
+                    self.exclude_tags(query, filter)
+                })
+            })
+    }
+
+    /// Append the filter's tag exclusions to a query, and the snippet tags' for bookmarks
+    ///
+    /// Without any query to exclude from, excluded snippets are only dropped
+    /// from the results.
+    fn exclude_tags(&self, query: String, filter: &SnippetFilter) -> String {
+        std::iter::once(query)
+            .chain(filter.snippet_exclusion_query(&self.config.snippet_tags))
+            .chain(filter.exclusion_query())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Title restrictions of the filter as FTS query parts
+    ///
+    /// With alias search the prefix may match a description word instead. The
+    /// prefix is lowercased unless the filter's case mode keeps its case.
+    fn metadata_queries(&self, filter: &SnippetFilter) -> Vec<String> {
+        let mut fts_parts = Vec::new();
 
         // Add search term if prefix is provided and not empty
-        if let Some(ref prefix) = filter.query_prefix {
-            if !prefix.trim().is_empty() {
-                // Use title prefix search for better snippet matching
-                fts_parts.push(format!("metadata:{}*", prefix));
-                debug!("Using search prefix: {}", prefix);
-            }
+        if let Some(ref prefix) = filter.query_prefix
+            && let Some(term) = fts_prefix_term(&filter.case_mode.search_query(prefix))
+        {
+            // Use title prefix search for better snippet matching
+            fts_parts.push(if self.config.alias_search && !filter.bookmarks {
+                format!("(metadata:{}* OR desc:{}*)", term, term)
+            } else {
+                format!("metadata:{}*", term)
+            });
+            debug!("Using search prefix: {}", prefix);
         }
 
-        // Combine all FTS parts with AND logic
-        if !fts_parts.is_empty() {
-            let fts_query = if fts_parts.len() == 1 {
-                fts_parts.into_iter().next().expect("single FTS part")
-            } else {
-                fts_parts.join(" AND ")
-            };
-            args.push(fts_query);
-            debug!("Final FTS query: {}", args.last().expect("FTS query"));
+        // Narrow down exact title lookups, the exact match is checked on the results
+        if let Some(ref title) = filter.title_exact {
+            fts_parts.push(format!("metadata:\"{}\"", title.replace('"', "")));
+            debug!("Using exact title: {}", title);
         }
 
+        fts_parts
+    }
+
+    /// Build command arguments for looking up a single snippet by id
+    ///
+    /// `interpolate` overrides the configured interpolation for this lookup only.
+    fn build_id_args(&self, id: i32, interpolate: bool) -> Vec<String> {
+        let mut args = self.search_flags(self.config.max_results);
+        if interpolate && self.bkmr_capabilities().interpolate {
+            args.push("--interpolate".to_string());
+        }
+        args.push(format!("id:{}", id));
         args
     }
 
-    /// Execute bkmr command and parse output
-    #[instrument(skip(self))]
-    async fn execute_bkmr_command(&self, args: &[String]) -> Result<Vec<BkmrSnippet>> {
-        debug!("Executing bkmr with args: {:?}", args);
+    /// `search` with the output and limit flags bkmr supports
+    fn search_flags(&self, limit: usize) -> Vec<String> {
+        let capabilities = self.bkmr_capabilities();
+        let mut args = vec!["search".to_string()];
+        if capabilities.json {
+            args.push("--json".to_string());
+        }
+        if capabilities.limit {
+            args.extend(["--limit".to_string(), limit.to_string()]);
+        }
+        args
+    }
 
-        // Add timeout to prevent hanging
-        let command_future = tokio::process::Command::new(&self.config.binary_path)
+    /// Look up a single snippet by id, interpolated or raw
+    async fn fetch_by_id(&self, id: i32, interpolate: bool) -> Result<Option<Snippet>> {
+        let args = self.build_id_args(id, interpolate);
+        let bkmr_snippets = self
+            .execute_bkmr_command(&args)
+            .await
+            .context("fetch snippet by id from bkmr CLI")?;
+
+        Ok(bkmr_snippets
+            .into_iter()
+            .map(Snippet::from)
+            .find(|snippet| snippet.id == id))
+    }
+
+    /// Build command arguments for adding a snippet whose content is read from stdin
+    fn build_add_args(&self, snippet: &NewSnippet) -> Vec<String> {
+        vec![
+            "add".to_string(),
+            "--stdin".to_string(),
+            "--type".to_string(),
+            "snip".to_string(),
+            "--no-web".to_string(),
+            "--title".to_string(),
+            snippet.title.clone(),
+            snippet.tags.join(","),
+        ]
+    }
+
+    /// Build command arguments for recording usage of a snippet, e.g. `touch 42`
+    fn build_usage_args(&self, id: i32) -> Vec<String> {
+        let mut args = self.config.usage_command.clone();
+        args.push(id.to_string());
+        args
+    }
+
+    /// Extract the id of a newly added snippet from bkmr output
+    fn parse_created_id(stdout: &str) -> Result<i32> {
+        stdout
+            .split(|c: char| !c.is_ascii_digit())
+            .find(|token| !token.is_empty())
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("No snippet id in bkmr output: {:?}", stdout.trim()))
+    }
+
+    /// Execute bkmr command with the given input piped to stdin, returning stdout
+    #[instrument(skip(self, input))]
+    async fn execute_bkmr_with_stdin(&self, args: &[String], input: &str) -> Result<String> {
+        debug!(
+            "Executing bkmr with args: {:?} and {} bytes on stdin",
+            args,
+            input.len()
+        );
+
+        let mut child = self
+            .command()
             .args(args)
-            .output();
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("spawn bkmr process")?;
+
+        let mut stdin = child.stdin.take().context("open bkmr stdin")?;
+        stdin
+            .write_all(input.as_bytes())
+            .await
+            .context("write snippet content to bkmr stdin")?;
+        drop(stdin);
 
         let output = tokio::time::timeout(
             std::time::Duration::from_secs(self.config.timeout_seconds),
-            command_future,
+            self.until_shutdown(child.wait_with_output()),
         )
         .await
         .context("execute bkmr command within timeout")?
-        .context("spawn bkmr process")?;
+        .context("wait for bkmr process")?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
             error!("bkmr command failed with stderr: {}", stderr);
-            return Err(anyhow::anyhow!("bkmr command failed: {}", stderr))
-                .context("execute bkmr command successfully");
+            return Err(anyhow::anyhow!("bkmr command failed: {}", stderr.trim()));
         }
 
-        let stdout_str = String::from_utf8_lossy(&output.stdout);
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Execute bkmr command and parse output
+    ///
+    /// Transient failures are retried with backoff. While the circuit breaker
+    /// is open bkmr is not run and no snippets are returned, the user is told
+    /// once when it opens.
+    async fn execute_bkmr_command(&self, args: &[String]) -> Result<Vec<BkmrSnippet>> {
+        Ok(self.execute_bkmr_search(args).await?.0)
+    }
 
-        if stdout_str.trim().is_empty() {
-            debug!("Empty output from bkmr");
-            return Ok(Vec::new());
+    /// Execute a bkmr search, also telling whether it found every match if bkmr reported a total
+    #[instrument(skip(self))]
+    async fn execute_bkmr_search(
+        &self,
+        args: &[String],
+    ) -> Result<(Vec<BkmrSnippet>, Option<bool>)> {
+        if self.breaker.is_open() {
+            debug!("Circuit breaker open, skipping bkmr search");
+            return Ok((Vec::new(), None));
         }
 
-        let snippets: Vec<BkmrSnippet> =
-            serde_json::from_str(&stdout_str).context("parse bkmr JSON output")?;
+        let stdout = match self.run_with_retries(args).await {
+            Ok(stdout) => {
+                self.breaker.record_success();
+                stdout
+            }
+            Err(e) => {
+                if self.breaker.record_failure() {
+                    self.notify(format!(
+                        "bkmr failed repeatedly, pausing snippet searches for {}s: {:#}",
+                        self.config.breaker_cooldown.as_secs(),
+                        e
+                    ));
+                }
+                return Err(e).context("execute bkmr command successfully");
+            }
+        };
+        let entries = parse_bkmr_entries(&stdout).context("parse bkmr JSON output")?;
+        let complete = entries.complete();
+        if let Some(total) = entries.total {
+            debug!(
+                "bkmr reported {} matches, returned {}",
+                total,
+                entries.entries.len()
+            );
+        }
+        let (snippets, skipped) = Self::parse_entries(entries.entries);
+        Self::log_skipped(snippets.len(), skipped);
 
         info!(
             "Successfully fetched {} interpolated snippets",
             snippets.len()
         );
-        Ok(snippets)
+        Ok((snippets, complete))
+    }
+
+    /// Run bkmr, retrying transient failures with exponential backoff
+    async fn run_with_retries(&self, args: &[String]) -> Result<String> {
+        let mut backoff = self.config.retry_backoff;
+        let mut attempt = 0;
+        loop {
+            match self.run_once(args).await {
+                Ok(stdout) => return Ok(stdout),
+                Err(failure) if failure.transient && attempt < self.config.retries => {
+                    attempt += 1;
+                    warn!(
+                        "bkmr failed transiently, retry {} of {} in {:?}: {:#}",
+                        attempt, self.config.retries, backoff, failure.error
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(failure) => return Err(failure.error),
+            }
+        }
+    }
+
+    /// Run bkmr once, returning stdout
+    ///
+    /// Spawn errors and errors about a locked or busy database are transient,
+    /// timeouts and other errors are not.
+    async fn run_once(&self, args: &[String]) -> Result<String, BkmrFailure> {
+        debug!("Executing bkmr with args: {:?}", args);
+
+        // Add timeout to prevent hanging
+        let output = tokio::time::timeout(
+            std::time::Duration::from_secs(self.config.timeout_seconds),
+            self.until_shutdown(self.command().args(args).output()),
+        )
+        .await
+        .context("execute bkmr command within timeout")
+        .map_err(BkmrFailure::hard)?
+        .map_err(|e| {
+            let interrupted = e.kind() == std::io::ErrorKind::Interrupted;
+            let error = anyhow::Error::new(e).context("spawn bkmr process");
+            // Retrying is pointless once shutting down
+            if interrupted {
+                BkmrFailure::hard(error)
+            } else {
+                BkmrFailure::transient(error)
+            }
+        })?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!("bkmr command failed with stderr: {}", stderr);
+            let lowercase = stderr.to_lowercase();
+            let error = anyhow::anyhow!("bkmr command failed: {}", stderr.trim());
+            return Err(
+                if TRANSIENT_ERROR_MARKERS
+                    .iter()
+                    .any(|marker| lowercase.contains(marker))
+                {
+                    BkmrFailure::transient(error)
+                } else {
+                    BkmrFailure::hard(error)
+                },
+            );
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Tags with counts as listed by `bkmr tags --json`
+    async fn execute_bkmr_tags(&self) -> Result<Vec<TagCount>> {
+        let output = tokio::time::timeout(
+            std::time::Duration::from_secs(self.config.timeout_seconds),
+            self.until_shutdown(self.command().args(["tags", "--json"]).output()),
+        )
+        .await
+        .context("execute bkmr tags within timeout")?
+        .context("spawn bkmr process")?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "bkmr tags --json failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Self::parse_tag_counts(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// Parse tag counts given as `[{"tag": .., "count": ..}]`, `{"tag": count}` or `["tag"]`
+    fn parse_tag_counts(stdout: &str) -> Result<Vec<TagCount>> {
+        use serde_json::Value;
+
+        let count = |value: Option<&Value>| {
+            value
+                .and_then(Value::as_u64)
+                .map_or(1, |count| count as usize)
+        };
+        let counts: Vec<TagCount> = match parse_bkmr_output(stdout)? {
+            Value::Array(entries) => entries
+                .iter()
+                .filter_map(|entry| match entry {
+                    Value::String(tag) => Some(TagCount::new(tag.as_str(), 1)),
+                    Value::Object(object) => {
+                        let tag = object.get("tag").or_else(|| object.get("name"))?;
+                        Some(TagCount::new(
+                            tag.as_str()?,
+                            count(object.get("count").or_else(|| object.get("n"))),
+                        ))
+                    }
+                    _ => None,
+                })
+                .collect(),
+            Value::Object(object) => object
+                .iter()
+                .map(|(tag, value)| TagCount::new(tag.as_str(), count(Some(value))))
+                .collect(),
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Expected tag list in bkmr tags output, got: {}",
+                    other
+                ));
+            }
+        };
+        Ok(TagCount::merge(counts))
+    }
+
+    /// Search language-specific and universal snippets concurrently and merge the results
+    ///
+    /// If only one of the searches fails, the other one's snippets are used.
+    async fn fetch_split(
+        &self,
+        limit: usize,
+        [language_query, universal_query]: [String; 2],
+    ) -> Result<Vec<BkmrSnippet>> {
+        let language_args = self.build_search_args(limit, Some(language_query));
+        let universal_args = self.build_search_args(limit, Some(universal_query));
+        let (language, universal) = tokio::join!(
+            self.execute_bkmr_command(&language_args),
+            self.execute_bkmr_command(&universal_args)
+        );
+
+        let (language, universal) = match (language, universal) {
+            (Ok(language), Ok(universal)) => (language, universal),
+            (Ok(language), Err(e)) => {
+                warn!(
+                    "Universal snippet search failed, using language snippets only: {:#}",
+                    e
+                );
+                (language, Vec::new())
+            }
+            (Err(e), Ok(universal)) => {
+                warn!(
+                    "Language snippet search failed, using universal snippets only: {:#}",
+                    e
+                );
+                (Vec::new(), universal)
+            }
+            (Err(e), Err(_)) => return Err(e).context("search language and universal snippets"),
+        };
+        Ok(Self::merge_split_results(language, universal, limit))
+    }
+
+    /// Cut the results to the limit when bkmr has no --limit flag and returned every match
+    ///
+    /// bkmr's results are then complete exactly when they fit the limit. Split
+    /// searches are cut when merged and keep guessing completeness by size.
+    pub(crate) fn emulate_limit(
+        &self,
+        mut snippets: Vec<BkmrSnippet>,
+        complete: Option<bool>,
+        limit: usize,
+    ) -> (Vec<BkmrSnippet>, Option<bool>) {
+        if self.bkmr_capabilities().limit {
+            return (snippets, complete);
+        }
+        let complete = snippets.len() <= limit;
+        if !complete {
+            debug!(
+                "bkmr returned {} snippets without --limit, keeping {}",
+                snippets.len(),
+                limit
+            );
+            snippets.truncate(limit);
+        }
+        (snippets, Some(complete))
+    }
+
+    /// Language-specific snippets first, then universal ones not already included
+    pub(crate) fn merge_split_results(
+        language: Vec<BkmrSnippet>,
+        universal: Vec<BkmrSnippet>,
+        limit: usize,
+    ) -> Vec<BkmrSnippet> {
+        let mut seen = HashSet::new();
+        language
+            .into_iter()
+            .chain(universal)
+            .filter(|snippet| seen.insert(snippet.id))
+            .take(limit)
+            .collect()
+    }
+
+    /// Parse bkmr JSON entries, returning the snippets and the number of malformed entries
+    pub(crate) fn parse_entries(entries: Vec<serde_json::Value>) -> (Vec<BkmrSnippet>, usize) {
+        let mut snippets = Vec::with_capacity(entries.len());
+        let mut skipped = 0;
+        for (index, entry) in entries.into_iter().enumerate() {
+            match serde_json::from_value::<BkmrSnippet>(entry) {
+                Ok(snippet) => snippets.push(snippet),
+                Err(e) => {
+                    warn!("Skipping malformed bkmr entry {}: {}", index, e);
+                    skipped += 1;
+                }
+            }
+        }
+        (snippets, skipped)
+    }
+
+    /// Log skipped entries, as an error when most were skipped
+    pub(crate) fn log_skipped(parsed: usize, skipped: usize) {
+        if skipped == 0 {
+            return;
+        }
+        let total = parsed + skipped;
+        if skipped * 2 > total {
+            error!(
+                "Skipped {} of {} bkmr entries, the bkmr JSON schema may have changed",
+                skipped, total
+            );
+        } else {
+            warn!("Skipped {} of {} malformed bkmr entries", skipped, total);
+        }
     }
 }
 
@@ -108,59 +858,495 @@ impl BkmrRepository {
 impl SnippetRepository for BkmrRepository {
     #[instrument(skip(self))]
     async fn fetch_snippets(&self, filter: &SnippetFilter) -> Result<Vec<Snippet>> {
-        let args = self.build_command_args(filter);
-        let bkmr_snippets = self
-            .execute_bkmr_command(&args)
-            .await
-            .context("fetch snippets from bkmr CLI")?;
+        let split_queries = match self.config.query_strategy {
+            QueryStrategy::Combined => None,
+            QueryStrategy::Split => self.build_split_fts_queries(filter),
+        };
+        {
+            let mut stats = sync::lock(&self.stats, "stats");
+            stats.last_query = match &split_queries {
+                Some(queries) => Some(queries.join(" | ")),
+                None => self.build_fts_query(filter),
+            };
+            stats.last_fetch_count = None;
+        }
+        let (bkmr_snippets, complete) = match split_queries {
+            Some(queries) => self
+                .fetch_split(filter.max_results, queries)
+                .await
+                .map(|snippets| (snippets, None)),
+            None => self
+                .execute_bkmr_search(&self.build_command_args(filter))
+                .await
+                .map(|(snippets, complete)| {
+                    self.emulate_limit(snippets, complete, filter.max_results)
+                }),
+        }
+        .context("fetch snippets from bkmr CLI")?;
+        *sync::lock(&self.last_complete, "last complete") =
+            complete.map(|complete| (filter.clone(), complete));
 
-        // Convert BkmrSnippet to domain Snippet
-        let snippets: Vec<Snippet> = bkmr_snippets
+        // Convert BkmrSnippet to domain Snippet, guarding against huge content
+        let snippets: Vec<Snippet> = self
+            .config
+            .content_limit
+            .apply(bkmr_snippets)
             .into_iter()
-            .map(|bkmr_snippet| bkmr_snippet.into())
+            .filter(|snippet| {
+                filter.matches_title(&snippet.title) && filter.allows_tags(&snippet.tags)
+            })
             .collect();
+        sync::lock(&self.stats, "stats").last_fetch_count = Some(snippets.len());
+        self.ids.insert_all(&snippets, std::time::Instant::now());
 
         Ok(snippets)
     }
 
     #[instrument(skip(self))]
-    async fn health_check(&self) -> Result<()> {
-        debug!("Verifying bkmr availability");
+    async fn query_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.fetch_by_id(id, self.interpolates()).await
+    }
+
+    fn id_cache(&self) -> Option<Arc<SnippetIdCache>> {
+        Some(self.ids.clone())
+    }
+
+    fn clear_cache(&self) -> usize {
+        self.ids.clear()
+    }
+
+    fn search_complete(&self, filter: &SnippetFilter) -> Option<bool> {
+        match &*sync::lock(&self.last_complete, "last complete") {
+            Some((last, complete)) if last == filter => Some(*complete),
+            _ => None,
+        }
+    }
 
-        let command_future = tokio::process::Command::new(&self.config.binary_path)
-            .args(["--help"])
-            .output();
+    #[instrument(skip(self))]
+    async fn fetch_raw_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.fetch_by_id(id, false).await
+    }
 
-        let output = tokio::time::timeout(std::time::Duration::from_secs(5), command_future)
+    #[instrument(skip(self, snippet), fields(title = %snippet.title))]
+    async fn create_snippet(&self, snippet: &NewSnippet) -> Result<i32> {
+        let args = self.build_add_args(snippet);
+        let stdout = self
+            .execute_bkmr_with_stdin(&args, &snippet.content)
             .await
-            .context("execute bkmr health check within timeout")?
-            .context("spawn bkmr process for health check")?;
+            .context("add snippet with bkmr CLI")?;
+
+        let id = Self::parse_created_id(&stdout).context("parse id of added snippet")?;
+        info!("Created snippet {} with bkmr", id);
+        Ok(id)
+    }
+
+    #[instrument(skip(self))]
+    async fn open_snippet(&self, id: i32) -> Result<()> {
+        let subcommand = if self.config.open_with_edit {
+            "edit"
+        } else {
+            "open"
+        };
+        debug!("Executing bkmr {} {}", subcommand, id);
+
+        let command_future = self.command().args([subcommand, &id.to_string()]).output();
+
+        let output = tokio::time::timeout(
+            std::time::Duration::from_secs(self.config.timeout_seconds),
+            self.until_shutdown(command_future),
+        )
+        .await
+        .context("execute bkmr open within timeout")?
+        .context("spawn bkmr process")?;
 
         if !output.status.success() {
-            return Err(anyhow::anyhow!("bkmr binary is not working properly"))
-                .context("verify bkmr binary functionality");
+            // Surface bkmr's own message, e.g. for unknown ids
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!("bkmr {} {} failed with stderr: {}", subcommand, id, stderr);
+            return Err(anyhow::anyhow!(
+                "bkmr {} {} failed: {}",
+                subcommand,
+                id,
+                stderr.trim()
+            ));
         }
 
-        info!("bkmr binary verified successfully");
+        info!("Opened snippet {} with bkmr {}", id, subcommand);
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[instrument(skip(self))]
+    async fn record_usage(&self, id: i32) -> Result<()> {
+        let args = self.build_usage_args(id);
+        debug!("Executing bkmr {}", args.join(" "));
 
-    #[test]
-    fn given_basic_filter_when_building_args_then_includes_required_args() {
-        // Arrange
-        let config = RepositoryConfig::default();
-        let repository = BkmrRepository::new(config);
-        let filter = SnippetFilter::new(Some("rust".to_string()), None, 25);
+        let output = tokio::time::timeout(
+            std::time::Duration::from_secs(USAGE_TIMEOUT_SECONDS),
+            self.until_shutdown(self.command().args(&args).output()),
+        )
+        .await
+        .context("record usage within timeout")?
+        .context("spawn bkmr process")?;
 
-        // Act
-        let args = repository.build_command_args(&filter);
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow::anyhow!(
+                "bkmr {} failed: {}",
+                args.join(" "),
+                stderr.trim()
+            ));
+        }
 
-        // Assert
+        debug!("Recorded usage of snippet {}", id);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn health_check(&self) -> Result<()> {
+        debug!("Verifying bkmr availability");
+
+        let command_future = self.command().args(["--help"]).output();
+
+        let output = tokio::time::timeout(
+            std::time::Duration::from_secs(self.config.timeout_seconds),
+            self.until_shutdown(command_future),
+        )
+        .await
+        .context("execute bkmr health check within timeout")?
+        .with_context(|| format!("spawn bkmr binary '{}'", self.config.binary_path))?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!("bkmr binary is not working properly"))
+                .context("verify bkmr binary functionality");
+        }
+
+        info!("bkmr binary verified successfully");
+        Ok(())
+    }
+
+    /// Tags from `bkmr tags --json`, counted from a broad search if that fails
+    #[instrument(skip(self))]
+    async fn list_tags(&self) -> Result<Vec<TagCount>> {
+        match self.execute_bkmr_tags().await {
+            Ok(tags) => Ok(tags),
+            Err(e) => {
+                warn!(
+                    "Listing tags with bkmr tags failed, counting them instead: {:#}",
+                    e
+                );
+                let snippets = self
+                    .fetch_snippets(&SnippetFilter::new(None, None, TAG_SEARCH_LIMIT))
+                    .await
+                    .context("search snippets to count their tags")?;
+                Ok(TagCount::count(
+                    snippets.iter().flat_map(|snippet| snippet.tags.iter()),
+                ))
+            }
+        }
+    }
+
+    /// The version probed at start, asked from bkmr before that
+    #[instrument(skip(self))]
+    async fn version(&self) -> Result<Option<String>> {
+        let capabilities = self.bkmr_capabilities();
+        if capabilities.probed {
+            return Ok(capabilities.version);
+        }
+        let stdout = self.run_probe(&["--version"]).await?;
+        Ok(Self::parse_version(&stdout))
+    }
+
+    /// Probe which optional flags the installed bkmr supports
+    async fn start(&self) -> Result<()> {
+        self.probe_once().await;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Option<BkmrCapabilities> {
+        Some(self.bkmr_capabilities())
+    }
+
+    async fn shutdown(&self) {
+        if !self.shut_down.send_replace(true) {
+            info!("Stopping bkmr processes");
+        }
+    }
+
+    fn stats(&self) -> RepositoryStats {
+        sync::lock(&self.stats, "stats").clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::CaseMode;
+
+    /// Write an executable shell script standing in for the bkmr binary
+    #[cfg(unix)]
+    fn fake_bkmr(name: &str, script: &str) -> std::path::PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("bkmr-lsp-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create temp dir");
+        let path = dir.join("bkmr");
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).expect("write fake bkmr");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .expect("make fake bkmr executable");
+        path
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_db_path_when_fetching_snippets_then_passes_database_via_env() {
+        // Arrange: the fake bkmr echoes the database it was given as snippet content
+        let binary = fake_bkmr(
+            "fetch-env",
+            r#"printf '[{"id": 1, "title": "db", "url": "%s", "description": "", "tags": []}]' "$BKMR_DB_URL""#,
+        );
+        let repository = BkmrRepository::new(RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            db_path: Some("/tmp/work.db".to_string()),
+            ..Default::default()
+        });
+
+        // Act
+        let snippets = repository
+            .fetch_snippets(&SnippetFilter::default())
+            .await
+            .expect("fetch snippets");
+
+        // Assert
+        assert_eq!(snippets.len(), 1);
+        assert_eq!(snippets[0].content, "/tmp/work.db");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_db_path_when_checking_health_then_passes_database_via_env() {
+        // Arrange: the fake bkmr only succeeds when pointed at the expected database
+        let binary = fake_bkmr(
+            "health-env",
+            r#"[ "$BKMR_DB_URL" = "/tmp/personal.db" ] || exit 1"#,
+        );
+        let config = |db_path: &str| RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            db_path: Some(db_path.to_string()),
+            ..Default::default()
+        };
+
+        // Act
+        let matching = BkmrRepository::new(config("/tmp/personal.db"))
+            .health_check()
+            .await;
+        let other = BkmrRepository::new(config("/tmp/work.db"))
+            .health_check()
+            .await;
+
+        // Assert
+        assert!(matching.is_ok());
+        assert!(other.is_err());
+    }
+
+    /// Fake bkmr failing with the message for its first `failures` runs, counting runs in a file
+    #[cfg(unix)]
+    fn flaky_bkmr(
+        name: &str,
+        failures: u32,
+        message: &str,
+    ) -> (std::path::PathBuf, std::path::PathBuf) {
+        let binary = fake_bkmr(
+            name,
+            &format!(
+                r#"count="$(dirname "$0")/count"
+n=$(( $(cat "$count" 2>/dev/null || echo 0) + 1 ))
+echo "$n" > "$count"
+[ "$1" = "--help" ] && exit 0
+if [ "$n" -le {} ]; then echo '{}' >&2; exit 1; fi
+echo '[{{"id": 1, "title": "Hello", "url": "hi", "description": "", "tags": ["_snip_"]}}]'"#,
+                failures, message
+            ),
+        );
+        let count = binary.with_file_name("count");
+        let _ = std::fs::remove_file(&count);
+        (binary, count)
+    }
+
+    #[cfg(unix)]
+    fn runs(count: &std::path::Path) -> u32 {
+        std::fs::read_to_string(count)
+            .map(|n| n.trim().parse().expect("run count"))
+            .unwrap_or(0)
+    }
+
+    #[cfg(unix)]
+    fn resilient_config(binary: &std::path::Path) -> RepositoryConfig {
+        RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            retry_backoff: std::time::Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_searched_snippet_when_fetching_by_id_then_does_not_run_bkmr_again() {
+        // Arrange
+        let (binary, count) = flaky_bkmr("id-cache", 0, "");
+        let repository = BkmrRepository::new(resilient_config(&binary));
+        repository
+            .fetch_snippets(&SnippetFilter::default())
+            .await
+            .expect("search");
+
+        // Act
+        let cached = repository
+            .fetch_snippet_by_id(1)
+            .await
+            .expect("cached fetch");
+        let cleared = repository.clear_cache();
+        let queried = repository
+            .fetch_snippet_by_id(1)
+            .await
+            .expect("queried fetch");
+
+        // Assert
+        assert_eq!(
+            cached.map(|snippet| snippet.title),
+            Some("Hello".to_string())
+        );
+        assert_eq!(cleared, 1);
+        assert!(queried.is_some());
+        assert_eq!(runs(&count), 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_locked_database_when_fetching_then_retries_until_success() {
+        // Arrange
+        let (binary, count) = flaky_bkmr("retry-locked", 2, "Error: database is locked");
+        let repository = BkmrRepository::new(resilient_config(&binary));
+
+        // Act
+        let snippets = repository
+            .fetch_snippets(&SnippetFilter::default())
+            .await
+            .expect("fetch snippets after retries");
+
+        // Assert
+        assert_eq!(snippets.len(), 1);
+        assert_eq!(runs(&count), 3);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_persistent_or_hard_failure_when_fetching_then_stops_retrying() {
+        // Arrange
+        let (busy, busy_count) = flaky_bkmr("retry-busy", 10, "database busy");
+        let (broken, broken_count) = flaky_bkmr("retry-hard", 10, "no such table");
+
+        // Act
+        let busy_result = BkmrRepository::new(resilient_config(&busy))
+            .fetch_snippets(&SnippetFilter::default())
+            .await;
+        let broken_result = BkmrRepository::new(resilient_config(&broken))
+            .fetch_snippets(&SnippetFilter::default())
+            .await;
+
+        // Assert
+        assert!(format!("{:#}", busy_result.unwrap_err()).contains("database busy"));
+        assert_eq!(runs(&busy_count), 3);
+        assert!(broken_result.is_err());
+        assert_eq!(runs(&broken_count), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_repeated_failures_when_fetching_then_breaker_opens_and_notifies_once() {
+        // Arrange
+        let (binary, count) = flaky_bkmr("breaker", 10, "no such table");
+        let (notices, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let repository = BkmrRepository::new(RepositoryConfig {
+            breaker_threshold: 2,
+            ..resilient_config(&binary)
+        })
+        .with_notices(notices);
+        let filter = SnippetFilter::default();
+
+        // Act
+        let first = repository.fetch_snippets(&filter).await;
+        let second = repository.fetch_snippets(&filter).await;
+        let paused = repository.fetch_snippets(&filter).await;
+        let paused_again = repository.fetch_snippets(&filter).await;
+        let runs_while_open = runs(&count);
+        let health = repository.health_check().await;
+
+        // Assert
+        assert!(first.is_err());
+        assert!(second.is_err());
+        assert!(paused.expect("empty result while open").is_empty());
+        assert!(paused_again.expect("empty result while open").is_empty());
+        assert_eq!(runs_while_open, 2);
+        assert!(health.is_ok());
+        assert_eq!(runs(&count), 3);
+        let notice = received.try_recv().expect("breaker notice");
+        assert!(notice.contains("pausing snippet searches for 30s"));
+        assert!(received.try_recv().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_open_breaker_when_cooldown_passes_then_next_success_closes_it() {
+        // Arrange
+        let (binary, count) = flaky_bkmr("breaker-cooldown", 1, "no such table");
+        let repository = BkmrRepository::new(RepositoryConfig {
+            breaker_threshold: 1,
+            breaker_cooldown: std::time::Duration::from_millis(20),
+            ..resilient_config(&binary)
+        });
+        let filter = SnippetFilter::default();
+
+        // Act
+        let failed = repository.fetch_snippets(&filter).await;
+        let paused = repository.fetch_snippets(&filter).await;
+        tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+        let recovered = repository.fetch_snippets(&filter).await;
+
+        // Assert
+        assert!(failed.is_err());
+        assert!(paused.expect("empty result while open").is_empty());
+        assert_eq!(recovered.expect("fetch after cooldown").len(), 1);
+        assert_eq!(runs(&count), 2);
+        assert!(!repository.breaker.is_open());
+    }
+
+    #[tokio::test]
+    async fn given_missing_binary_when_checking_health_then_error_names_path() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig {
+            binary_path: "/nonexistent/bkmr".to_string(),
+            ..Default::default()
+        });
+
+        // Act
+        let result = repository.health_check().await;
+
+        // Assert
+        let error_message = format!("{:#}", result.unwrap_err());
+        assert!(error_message.contains("/nonexistent/bkmr"));
+    }
+
+    #[test]
+    fn given_basic_filter_when_building_args_then_includes_required_args() {
+        // Arrange
+        let config = RepositoryConfig::default();
+        let repository = BkmrRepository::new(config);
+        let filter = SnippetFilter::new(Some("rust".to_string()), None, 25);
+
+        // Act
+        let args = repository.build_command_args(&filter);
+
+        // Assert
         assert!(args.contains(&"search".to_string()));
         assert!(args.contains(&"--json".to_string()));
         assert!(args.contains(&"--interpolate".to_string()));
@@ -168,6 +1354,181 @@ mod tests {
         assert!(args.contains(&"25".to_string()));
     }
 
+    fn repository_with(
+        capabilities: BkmrCapabilities,
+        enable_interpolation: bool,
+    ) -> BkmrRepository {
+        BkmrRepository::new(RepositoryConfig {
+            enable_interpolation,
+            capabilities,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn given_capability_combinations_when_building_args_then_leaves_out_unsupported_flags() {
+        // Arrange
+        let filter = SnippetFilter::new(None, None, 25);
+        let all = BkmrCapabilities::default();
+        let cases = [
+            (
+                all.clone(),
+                true,
+                vec!["search", "--json", "--limit", "25", "--interpolate"],
+            ),
+            (
+                all.clone(),
+                false,
+                vec!["search", "--json", "--limit", "25"],
+            ),
+            (
+                BkmrCapabilities {
+                    limit: false,
+                    ..all.clone()
+                },
+                true,
+                vec!["search", "--json", "--interpolate"],
+            ),
+            (
+                BkmrCapabilities {
+                    interpolate: false,
+                    ..all.clone()
+                },
+                true,
+                vec!["search", "--json", "--limit", "25"],
+            ),
+            (
+                BkmrCapabilities {
+                    json: false,
+                    limit: false,
+                    interpolate: false,
+                    ..all.clone()
+                },
+                true,
+                vec!["search"],
+            ),
+        ];
+
+        for (capabilities, enable_interpolation, expected) in cases {
+            // Act
+            let repository = repository_with(capabilities.clone(), enable_interpolation);
+            let args = repository.build_command_args(&filter);
+
+            // Assert: flags first, the FTS query last
+            assert_eq!(args[..args.len() - 1], expected, "{:?}", capabilities);
+            assert_eq!(args.last().map(String::as_str), Some(r#"(tags:"_snip_")"#));
+            assert_eq!(
+                repository.interpolates(),
+                enable_interpolation && capabilities.interpolate
+            );
+        }
+        let old_bkmr = repository_with(
+            BkmrCapabilities {
+                limit: false,
+                interpolate: false,
+                ..all
+            },
+            true,
+        );
+        assert_eq!(
+            old_bkmr.build_id_args(7, true),
+            vec!["search", "--json", "id:7"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_bkmr_without_limit_flag_when_fetching_then_cuts_results_to_the_limit() {
+        // Arrange: bkmr ignoring the limit returns all three matches
+        let binary = fake_bkmr(
+            "no-limit",
+            r#"echo '[{"id": 1, "title": "a", "url": "1", "description": "", "tags": []}, {"id": 2, "title": "b", "url": "2", "description": "", "tags": []}, {"id": 3, "title": "c", "url": "3", "description": "", "tags": []}]'"#,
+        );
+        let repository = BkmrRepository::new(RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            capabilities: BkmrCapabilities {
+                limit: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let two = SnippetFilter::new(None, None, 2);
+        let three = SnippetFilter::new(None, None, 3);
+
+        // Act
+        let cut = repository.fetch_snippets(&two).await.expect("fetch");
+        let cut_complete = repository.search_complete(&two);
+        let all = repository.fetch_snippets(&three).await.expect("fetch");
+        let all_complete = repository.search_complete(&three);
+
+        // Assert
+        assert_eq!(
+            cut.iter().map(|snippet| snippet.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(cut_complete, Some(false));
+        assert_eq!(all.len(), 3);
+        assert_eq!(all_complete, Some(true));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_older_bkmr_when_starting_then_probes_flags_once_and_adapts_searches() {
+        // Arrange: an old bkmr failing on flags it does not know, counting its runs
+        let dir = std::env::temp_dir().join(format!("bkmr-lsp-probe-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create temp dir");
+        let count = dir.join("runs");
+        let _ = std::fs::remove_file(&count);
+        let binary = fake_bkmr(
+            "probe",
+            &format!(
+                r#"echo run >> {}
+[ "$1" = "--version" ] && echo "bkmr 1.9.0" && exit 0
+[ "$2" = "--help" ] && printf 'Usage: bkmr search [OPTIONS]
+      --json
+  -n, --ntags <TAGS>
+' && exit 0
+for arg in "$@"; do case "$arg" in --limit|--interpolate) echo "error: unexpected argument '$arg'" >&2; exit 2;; esac; done
+echo '[{{"id": 1, "title": "a", "url": "1", "description": "", "tags": []}}]'"#,
+                count.display()
+            ),
+        );
+        let (notices, mut notice_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let repository = BkmrRepository::new(RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .with_notices(notices);
+
+        // Act
+        repository.start().await.expect("start");
+        repository.start().await.expect("start again");
+        let version = repository.version().await.expect("version");
+        let snippets = repository
+            .fetch_snippets(&SnippetFilter::default())
+            .await
+            .expect("fetch without unsupported flags");
+
+        // Assert
+        let capabilities = repository.capabilities().expect("bkmr capabilities");
+        assert!(capabilities.probed);
+        assert!(capabilities.json && !capabilities.limit);
+        assert!(!capabilities.interpolate && !capabilities.ntags);
+        assert_eq!(version.as_deref(), Some("1.9.0"));
+        assert_eq!(snippets.len(), 1);
+        let runs = std::fs::read_to_string(&count)
+            .expect("run log")
+            .lines()
+            .count();
+        assert_eq!(runs, 3);
+        assert!(
+            notice_receiver
+                .try_recv()
+                .expect("interpolation notice")
+                .contains("--interpolate")
+        );
+    }
+
     #[test]
     fn given_filter_with_prefix_when_building_args_then_includes_metadata_search() {
         // Arrange
@@ -182,6 +1543,705 @@ mod tests {
         assert!(args.iter().any(|arg| arg.contains("metadata:hello*")));
     }
 
+    #[test]
+    fn given_case_modes_when_building_args_then_metadata_search_case_follows_mode() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig::default());
+        let metadata = |mode: CaseMode, prefix: &str| {
+            let filter =
+                SnippetFilter::new(None, Some(prefix.to_string()), 50).with_case_mode(mode);
+            repository
+                .build_command_args(&filter)
+                .into_iter()
+                .find_map(|arg| {
+                    arg.find("metadata:")
+                        .map(|start| arg[start..].split(' ').next().unwrap_or("").to_string())
+                })
+                .expect("metadata search")
+        };
+
+        // Act & Assert
+        assert_eq!(metadata(CaseMode::Smart, "docker"), "metadata:docker*");
+        assert_eq!(metadata(CaseMode::Smart, "Docker"), "metadata:Docker*");
+        assert_eq!(
+            metadata(CaseMode::Insensitive, "DOCKER"),
+            "metadata:docker*"
+        );
+        assert_eq!(metadata(CaseMode::Sensitive, "Docker"), "metadata:Docker*");
+        assert_eq!(metadata(CaseMode::Sensitive, "docker"), "metadata:docker*");
+    }
+
+    #[test]
+    fn given_prefix_with_separators_when_building_args_then_quotes_metadata_search() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig::default());
+        let dotted = SnippetFilter::new(None, Some("docker.compose".to_string()), 50);
+        let quoted = SnippetFilter::new(None, Some("say\"hi".to_string()), 50);
+
+        // Act
+        let dotted_args = repository.build_command_args(&dotted);
+        let quoted_args = repository.build_command_args(&quoted);
+
+        // Assert
+        assert!(
+            dotted_args
+                .iter()
+                .any(|arg| arg.ends_with(r#"metadata:"docker.compose"*"#))
+        );
+        assert!(
+            quoted_args
+                .iter()
+                .any(|arg| arg.ends_with(r#"metadata:"say""hi"*"#))
+        );
+    }
+
+    #[test]
+    fn given_nasty_prefixes_when_building_args_then_final_argument_stays_one_title_search() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig::default());
+        let final_argument = |prefix: &str| {
+            let filter = SnippetFilter::new(None, Some(prefix.to_string()), 50);
+            repository
+                .build_command_args(&filter)
+                .pop()
+                .expect("FTS query argument")
+        };
+
+        // Act & Assert
+        assert_eq!(
+            final_argument(r#"x" OR tags:secret"#),
+            r#"(tags:"_snip_") AND metadata:"x"" or tags:secret"*"#
+        );
+        assert_eq!(
+            final_argument("NEAR"),
+            r#"(tags:"_snip_") AND metadata:near*"#
+        );
+        assert_eq!(
+            final_argument("(a OR b)"),
+            r#"(tags:"_snip_") AND metadata:"(a or b)"*"#
+        );
+        assert_eq!(
+            final_argument(&"x".repeat(200)),
+            format!(r#"(tags:"_snip_") AND metadata:{}*"#, "x".repeat(64))
+        );
+        assert_eq!(final_argument("\"()\""), r#"(tags:"_snip_")"#);
+    }
+
+    #[test]
+    fn given_excluded_tags_when_building_fts_query_then_appends_one_not_clause_each() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig::default());
+        let filter = SnippetFilter::new(Some("rust".to_string()), Some("dep".to_string()), 50);
+        let base = r#"((tags:rust AND tags:"_snip_") OR (tags:universal AND tags:"_snip_")) AND metadata:dep*"#;
+
+        // Act
+        let none = repository.build_fts_query(&filter);
+        let one = repository.build_fts_query(
+            &filter
+                .clone()
+                .with_excluded_tags(vec!["Deprecated".to_string()]),
+        );
+        let several = repository.build_fts_query(&filter.with_excluded_tags(vec![
+            "deprecated".to_string(),
+            " private ".to_string(),
+            String::new(),
+        ]));
+
+        // Assert
+        assert_eq!(none.as_deref(), Some(base));
+        assert_eq!(one, Some(format!(r#"{} NOT tags:"deprecated""#, base)));
+        assert_eq!(
+            several,
+            Some(format!(
+                r#"{} NOT tags:"deprecated" NOT tags:"private""#,
+                base
+            ))
+        );
+    }
+
+    #[test]
+    fn given_alias_search_when_building_fts_query_then_ors_a_description_prefix_clause() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig {
+            alias_search: true,
+            ..Default::default()
+        });
+        let filter = |prefix: &str| {
+            SnippetFilter::new(Some("yaml".to_string()), Some(prefix.to_string()), 50)
+        };
+        let snippets = r#"((tags:yaml AND tags:"_snip_") OR (tags:universal AND tags:"_snip_"))"#;
+
+        // Act
+        let alias = repository.build_fts_query(&filter("kdep"));
+        let quoted = repository.build_fts_query(&filter("k8s.dep"));
+        let without =
+            BkmrRepository::new(RepositoryConfig::default()).build_fts_query(&filter("kdep"));
+
+        // Assert
+        assert_eq!(
+            alias,
+            Some(format!("{} AND (metadata:kdep* OR desc:kdep*)", snippets))
+        );
+        assert_eq!(
+            quoted,
+            Some(format!(
+                r#"{} AND (metadata:"k8s.dep"* OR desc:"k8s.dep"*)"#,
+                snippets
+            ))
+        );
+        assert_eq!(without, Some(format!("{} AND metadata:kdep*", snippets)));
+    }
+
+    #[test]
+    fn given_bookmark_filter_when_building_fts_query_then_searches_titles_without_snippet_tags() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig {
+            alias_search: true,
+            ..RepositoryConfig::default()
+        });
+
+        // Act
+        let query = repository.build_fts_query(&SnippetFilter::bookmarks("rust".to_string(), 10));
+        let split =
+            repository.build_split_fts_queries(&SnippetFilter::bookmarks("rust".to_string(), 10));
+
+        // Assert
+        assert_eq!(
+            query.as_deref(),
+            Some(r#"metadata:rust* NOT tags:"_snip_""#)
+        );
+        assert_eq!(split, None);
+    }
+
+    #[test]
+    fn given_default_config_when_building_completion_fts_query_then_excludes_action_snippets() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig::default());
+        let excluding = crate::services::CompletionService::with_config(
+            std::sync::Arc::new(crate::repositories::MockSnippetRepository::new()),
+            crate::BkmrConfig {
+                action_tags: vec!["_md_".to_string()],
+                ..Default::default()
+            },
+        );
+        let including = crate::services::CompletionService::with_config(
+            std::sync::Arc::new(crate::repositories::MockSnippetRepository::new()),
+            crate::BkmrConfig {
+                include_action_snippets: true,
+                ..Default::default()
+            },
+        );
+
+        let uri = tower_lsp::lsp_types::Url::parse("file:///main.rs").expect("parse URI");
+
+        // Act
+        let excluded = repository.build_fts_query(&excluding.language_filter(&uri, "rust", None));
+        let included = repository.build_fts_query(&including.language_filter(&uri, "rust", None));
+
+        // Assert
+        assert!(
+            excluded
+                .as_deref()
+                .is_some_and(|query| query.ends_with(r#" NOT tags:"_shell_" NOT tags:"_md_""#)),
+            "{:?}",
+            excluded
+        );
+        assert!(included.is_some_and(|query| !query.contains("NOT")));
+    }
+
+    #[test]
+    fn given_excluded_tags_and_split_strategy_when_building_queries_then_both_exclude() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig {
+            query_strategy: QueryStrategy::Split,
+            ..Default::default()
+        });
+        let filter = SnippetFilter::new(Some("rust".to_string()), None, 50)
+            .with_excluded_tags(vec!["private".to_string()]);
+
+        // Act
+        let queries = repository
+            .build_split_fts_queries(&filter)
+            .expect("split queries");
+
+        // Assert
+        assert!(
+            queries
+                .iter()
+                .all(|query| query.ends_with(r#" NOT tags:"private""#))
+        );
+    }
+
+    #[test]
+    fn given_snippet_id_when_building_id_args_then_queries_id_column() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig::default());
+
+        // Act
+        let args = repository.build_id_args(42, true);
+
+        // Assert
+        assert_eq!(args[0], "search");
+        assert!(args.contains(&"--json".to_string()));
+        assert_eq!(args.last().map(String::as_str), Some("id:42"));
+    }
+
+    #[test]
+    fn given_interpolation_override_when_building_id_args_then_only_interpolating_lookup_asks_for_it()
+     {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig {
+            enable_interpolation: true,
+            ..Default::default()
+        });
+        let interpolate = "--interpolate".to_string();
+
+        // Act
+        let interpolated = repository.build_id_args(42, true);
+        let raw = repository.build_id_args(42, false);
+
+        // Assert
+        assert!(interpolated.contains(&interpolate));
+        assert!(!raw.contains(&interpolate));
+        assert_eq!(raw.last().map(String::as_str), Some("id:42"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_interpolation_enabled_when_fetching_raw_snippet_then_template_is_kept() {
+        // Arrange: the fake bkmr interpolates only when asked to
+        let binary = fake_bkmr(
+            "fetch-raw",
+            r#"content='{{ env(\"USER\") }}'
+for arg in "$@"; do [ "$arg" = --interpolate ] && content=alice; done
+printf '[{"id": 7, "title": "user", "url": "%s", "description": "", "tags": ["_snip_"]}]' "$content""#,
+        );
+        let repository = BkmrRepository::new(RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            enable_interpolation: true,
+            ..Default::default()
+        });
+
+        // Act
+        let interpolated = repository
+            .fetch_snippet_by_id(7)
+            .await
+            .expect("fetch snippet")
+            .expect("snippet 7");
+        let raw = repository
+            .fetch_raw_snippet_by_id(7)
+            .await
+            .expect("fetch raw snippet")
+            .expect("snippet 7");
+
+        // Assert
+        assert_eq!(interpolated.content, "alice");
+        assert_eq!(raw.content, r#"{{ env("USER") }}"#);
+    }
+
+    #[test]
+    fn given_version_output_when_parsing_then_returns_version_number() {
+        // Assert
+        assert_eq!(
+            BkmrRepository::parse_version("bkmr 4.24.0\n"),
+            Some("4.24.0".to_string())
+        );
+        assert_eq!(BkmrRepository::parse_version("  \n"), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_search_when_fetching_then_records_query_and_result_count() {
+        // Arrange
+        let binary = fake_bkmr(
+            "stats",
+            r#"echo '[{"id": 1, "title": "Hello", "url": "hi", "description": "", "tags": ["rust", "_snip_"]}]'"#,
+        );
+        let repository = BkmrRepository::new(RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            ..Default::default()
+        });
+        let filter = SnippetFilter::new(Some("rust".to_string()), None, 50);
+
+        // Act
+        repository.fetch_snippets(&filter).await.expect("fetch");
+        let stats = repository.stats();
+
+        // Assert
+        assert_eq!(
+            stats.last_query.as_deref(),
+            Some(r#"((tags:rust AND tags:"_snip_") OR (tags:universal AND tags:"_snip_"))"#)
+        );
+        assert_eq!(stats.last_fetch_count, Some(1));
+        assert_eq!(stats.cache, None);
+    }
+
+    #[test]
+    fn given_required_tag_when_building_fts_queries_then_ands_it_with_both_strategies() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig::default());
+        let filter = SnippetFilter::new(Some("sh".to_string()), Some("get".to_string()), 50)
+            .with_required_tags(vec!["Kubernetes".to_string()]);
+
+        // Act
+        let combined = repository.build_fts_query(&filter);
+        let split = repository.build_split_fts_queries(&filter);
+
+        // Assert
+        assert_eq!(
+            combined.as_deref(),
+            Some(
+                r#"((tags:sh AND tags:"_snip_") OR (tags:universal AND tags:"_snip_")) AND metadata:get* AND tags:"kubernetes""#
+            )
+        );
+        let [language, universal] = split.expect("split queries");
+        assert!(language.ends_with(r#"AND metadata:get* AND tags:"kubernetes""#));
+        assert!(universal.ends_with(r#"AND metadata:get* AND tags:"kubernetes""#));
+    }
+
+    #[test]
+    fn given_tag_output_shapes_when_parsing_tag_counts_then_reads_each() {
+        // Act
+        let objects = BkmrRepository::parse_tag_counts(
+            r#"[{"tag": "sql", "count": 2}, {"name": "regex", "n": 5}]"#,
+        )
+        .expect("objects");
+        let map = BkmrRepository::parse_tag_counts(r#"{"sql": 2, "SQL": 1}"#).expect("map");
+        let names = BkmrRepository::parse_tag_counts(r#"["sql"]"#).expect("names");
+
+        // Assert
+        assert_eq!(
+            objects,
+            vec![TagCount::new("regex", 5), TagCount::new("sql", 2)]
+        );
+        assert_eq!(map, vec![TagCount::new("SQL", 3)]);
+        assert_eq!(names, vec![TagCount::new("sql", 1)]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_bkmr_without_tags_command_when_listing_tags_then_counts_search_results() {
+        // Arrange
+        let binary = fake_bkmr(
+            "tags-fallback",
+            r#"if [ "$1" = tags ]; then echo 'unknown subcommand' >&2; exit 2; fi
+echo '[{"id": 1, "title": "a", "url": "x", "description": "", "tags": ["sql", "_snip_"]},
+       {"id": 2, "title": "b", "url": "y", "description": "", "tags": ["sql", "regex", "_snip_"]}]'"#,
+        );
+        let repository = BkmrRepository::new(RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            ..Default::default()
+        });
+
+        // Act
+        let tags = repository.list_tags().await.expect("tags");
+
+        // Assert
+        assert_eq!(
+            tags,
+            vec![
+                TagCount::new("_snip_", 2),
+                TagCount::new("sql", 2),
+                TagCount::new("regex", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn given_usage_command_when_building_usage_args_then_appends_snippet_id() {
+        // Arrange
+        let default = BkmrRepository::new(RepositoryConfig::default());
+        let configured = BkmrRepository::new(RepositoryConfig {
+            usage_command: vec!["update".to_string(), "--access".to_string()],
+            ..Default::default()
+        });
+
+        // Act & Assert
+        assert_eq!(default.build_usage_args(42), vec!["touch", "42"]);
+        assert_eq!(
+            configured.build_usage_args(42),
+            vec!["update", "--access", "42"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_running_bkmr_when_shutting_down_then_kills_it_and_refuses_new_ones() {
+        // Arrange
+        let binary = fake_bkmr("shutdown", "sleep 30");
+        let repository = BkmrRepository::new(RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            ..Default::default()
+        });
+        let filter = SnippetFilter::default();
+
+        // Act
+        let (running, ()) = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            tokio::join!(repository.fetch_snippets(&filter), async {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                repository.shutdown().await;
+            })
+        })
+        .await
+        .expect("shutdown does not wait for bkmr to finish");
+        let later = repository.record_usage(7).await;
+
+        // Assert
+        assert!(format!("{:#}", running.unwrap_err()).contains("shutting down"));
+        assert!(format!("{:#}", later.unwrap_err()).contains("shutting down"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_failing_bkmr_when_recording_usage_then_returns_error_with_stderr() {
+        // Arrange
+        let binary = fake_bkmr("usage-fail", "echo 'unknown command' >&2; exit 2");
+        let repository = BkmrRepository::new(RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            ..Default::default()
+        });
+
+        // Act
+        let result = repository.record_usage(7).await;
+
+        // Assert
+        let error_message = format!("{:#}", result.unwrap_err());
+        assert!(error_message.contains("bkmr touch 7 failed: unknown command"));
+    }
+
+    #[test]
+    fn given_new_snippet_when_building_add_args_then_includes_title_and_tags() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig::default());
+        let snippet = NewSnippet {
+            title: "Hello".to_string(),
+            content: "fn main() {}".to_string(),
+            tags: vec!["rust".to_string(), "_snip_".to_string()],
+        };
+
+        // Act
+        let args = repository.build_add_args(&snippet);
+
+        // Assert
+        assert_eq!(args[0], "add");
+        assert!(args.contains(&"--stdin".to_string()));
+        assert!(args.contains(&"Hello".to_string()));
+        assert_eq!(args.last().map(String::as_str), Some("rust,_snip_"));
+        assert!(!args.iter().any(|arg| arg.contains("fn main")));
+    }
+
+    #[test]
+    fn given_bkmr_add_output_when_parsing_created_id_then_returns_first_number() {
+        // Arrange
+        let output = "Added bookmark with id 42\n";
+
+        // Act
+        let id = BkmrRepository::parse_created_id(output);
+        let missing = BkmrRepository::parse_created_id("done");
+
+        // Assert
+        assert_eq!(id.expect("parsed id"), 42);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn given_mixed_valid_and_invalid_entries_when_parsing_then_keeps_valid_ones() {
+        // Arrange
+        let output = r#"[
+            {"id": 1, "title": "Valid", "url": "content one", "description": "d", "tags": ["rust"]},
+            {"id": "not-a-number", "title": "Broken", "url": "x"},
+            {"title": "Missing id", "url": "y"},
+            {"id": 4, "title": "Also valid", "url": "content four", "description": "", "tags": []}
+        ]"#;
+
+        // Act
+        let (snippets, skipped) = parse_snippets(output).expect("parsed output");
+
+        // Assert
+        assert_eq!(skipped, 2);
+        let ids: Vec<i32> = snippets.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![1, 4]);
+        assert_eq!(snippets[1].url, "content four");
+    }
+
+    #[test]
+    fn given_aliased_and_missing_fields_when_parsing_then_maps_and_defaults() {
+        // Arrange
+        let output = r#"[
+            {"id": 1, "metadata": "Renamed title", "content": "renamed content"},
+            {"id": 2, "title": "Upper", "URL": "upper content", "tags": ["_snip_"]}
+        ]"#;
+
+        // Act
+        let (snippets, skipped) = parse_snippets(output).expect("parsed output");
+
+        // Assert
+        assert_eq!(skipped, 0);
+        assert_eq!(snippets[0].title, "Renamed title");
+        assert_eq!(snippets[0].url, "renamed content");
+        assert!(snippets[0].description.is_empty());
+        assert!(snippets[0].tags.is_empty());
+        assert_eq!(snippets[1].url, "upper content");
+        assert_eq!(snippets[1].tags, vec!["_snip_".to_string()]);
+    }
+
+    #[test]
+    fn given_non_array_output_when_parsing_then_returns_error() {
+        // Arrange
+        let output = r#"{"error": "database locked"}"#;
+
+        // Act
+        let result = parse_snippets(output);
+
+        // Assert
+        assert!(result.is_err());
+    }
+
+    /// Parse bkmr output like a search does: the entries, then the snippets among them
+    fn parse_snippets(output: &str) -> Result<(Vec<BkmrSnippet>, usize)> {
+        parse_bkmr_entries(output).map(|entries| BkmrRepository::parse_entries(entries.entries))
+    }
+
+    #[test]
+    fn given_bare_array_when_parsing_entries_then_has_no_total() {
+        // Act
+        let entries = parse_bkmr_entries(r#"[{"id": 1}, {"id": 2}]"#).expect("parsed output");
+
+        // Assert
+        assert_eq!(entries.entries.len(), 2);
+        assert_eq!(entries.total, None);
+        assert_eq!(entries.complete(), None);
+    }
+
+    #[test]
+    fn given_object_wrapping_items_when_parsing_entries_then_takes_items_and_total() {
+        // Act
+        let entries =
+            parse_bkmr_entries(r#"{"items": [{"id": 1, "title": "A", "url": "a"}], "total": 12}"#)
+                .expect("parsed output");
+        let (snippets, skipped) = BkmrRepository::parse_entries(entries.entries.clone());
+
+        // Assert
+        assert_eq!(entries.total, Some(12));
+        assert_eq!(entries.complete(), Some(false));
+        assert_eq!(snippets[0].id, 1);
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn given_object_wrapping_results_when_parsing_entries_then_takes_results() {
+        // Act
+        let entries = parse_bkmr_entries(r#"{"results": [{"id": 1}, {"id": 2}], "total": 2}"#)
+            .expect("parsed output");
+
+        // Assert
+        assert_eq!(entries.entries.len(), 2);
+        assert_eq!(entries.complete(), Some(true));
+    }
+
+    #[test]
+    fn given_wrapper_with_unknown_fields_when_parsing_entries_then_ignores_them() {
+        // Act
+        let entries = parse_bkmr_entries(
+            r#"{"version": 2, "query": "metadata:a*", "items": [{"id": 1}], "total": "many", "page": {"next": null}}"#,
+        )
+        .expect("parsed output");
+
+        // Assert
+        assert_eq!(entries.entries.len(), 1);
+        assert_eq!(entries.total, None);
+    }
+
+    #[test]
+    fn given_object_without_array_when_parsing_entries_then_error_names_its_keys() {
+        // Act
+        let error = parse_bkmr_entries(r#"{"total": 3, "items": "none", "took_ms": 5}"#)
+            .expect_err("no result array");
+        let empty = parse_bkmr_entries("{}").expect_err("empty object");
+
+        // Assert
+        let message = format!("{:#}", error);
+        assert!(message.contains("no items or results array, found keys: items, took_ms, total"));
+        assert!(format!("{:#}", empty).contains("found keys: none"));
+    }
+
+    #[test]
+    fn given_warning_before_json_when_parsing_output_then_skips_prefix() {
+        // Arrange
+        let output =
+            "WARNING: plugin [fzf] misconfigured\n[{\"id\": 1, \"title\": \"A\", \"url\": \"a\"}]";
+
+        // Act
+        let (snippets, skipped) = parse_snippets(output).expect("parsed output");
+
+        // Assert
+        assert_eq!(skipped, 0);
+        assert_eq!(snippets[0].id, 1);
+    }
+
+    #[test]
+    fn given_trailing_text_after_json_when_parsing_output_then_ignores_it() {
+        // Arrange
+        let output = "[{\"id\": 2, \"title\": \"B\", \"url\": \"b\"}]\n\nDone in 3ms\n";
+
+        // Act
+        let (snippets, _) = parse_snippets(output).expect("parsed output");
+
+        // Assert
+        assert_eq!(snippets.len(), 1);
+        assert_eq!(
+            parse_bkmr_output("[]\n\n").expect("parsed"),
+            serde_json::json!([])
+        );
+    }
+
+    #[test]
+    fn given_empty_output_when_parsing_output_then_returns_no_snippets() {
+        // Act
+        let (snippets, skipped) = parse_snippets("  \n").expect("parsed output");
+
+        // Assert
+        assert!(snippets.is_empty());
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn given_garbage_output_when_parsing_output_then_error_quotes_output() {
+        // Arrange
+        let output = format!("Error: database [main] is locked {}", "x".repeat(300));
+
+        // Act
+        let error = parse_bkmr_output(&output).expect_err("garbage output");
+
+        // Assert
+        let message = error.to_string();
+        assert!(message.contains("database [main] is locked"));
+        assert!(message.len() < 300);
+    }
+
+    #[test]
+    fn given_secret_in_unparseable_output_when_parsing_then_error_masks_it() {
+        // Arrange
+        let garbage = "Error: cannot decode snippet token=s3cr3t-value".to_string();
+        let object = format!(
+            r#"{{"url": "curl -H 'Authorization: Bearer abc.def.ghi' {}"}}"#,
+            "x".repeat(300)
+        );
+
+        // Act
+        let garbage_error = parse_bkmr_output(&garbage).expect_err("garbage output");
+        let object_error = parse_snippets(&object).expect_err("non-array output");
+
+        // Assert
+        let garbage_message = garbage_error.to_string();
+        assert!(garbage_message.contains("token=[REDACTED]"));
+        assert!(!garbage_message.contains("s3cr3t-value"));
+        let object_message = format!("{:#}", object_error);
+        assert!(object_message.contains("Bearer [REDACTED]"));
+        assert!(!object_message.contains("abc.def.ghi"));
+        assert!(!object_message.contains(&object));
+    }
+
     #[test]
     fn given_filter_with_language_when_building_args_then_includes_language_query() {
         // Arrange
@@ -198,4 +2258,147 @@ mod tests {
                 .any(|arg| arg.contains("tags:python") && arg.contains("universal"))
         );
     }
+
+    fn split_repository(binary: &std::path::Path) -> BkmrRepository {
+        BkmrRepository::new(RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            query_strategy: QueryStrategy::Split,
+            ..Default::default()
+        })
+    }
+
+    /// Fake bkmr answering universal searches and language searches differently
+    #[cfg(unix)]
+    fn fake_split_bkmr(
+        name: &str,
+        language_output: &str,
+        universal_output: &str,
+    ) -> std::path::PathBuf {
+        fake_bkmr(
+            name,
+            &format!(
+                "case \"$*\" in\n*tags:universal*) {} ;;\n*) {} ;;\nesac",
+                universal_output, language_output
+            ),
+        )
+    }
+
+    #[test]
+    fn given_split_strategy_with_prefix_when_building_queries_then_both_filter_by_title() {
+        // Arrange
+        let repository = BkmrRepository::new(RepositoryConfig {
+            query_strategy: QueryStrategy::Split,
+            ..Default::default()
+        });
+        let filter = SnippetFilter::new(Some("rust".to_string()), Some("dep".to_string()), 50);
+
+        // Act
+        let queries = repository
+            .build_split_fts_queries(&filter)
+            .expect("split queries");
+        let no_language = repository.build_split_fts_queries(&SnippetFilter::default());
+
+        // Assert
+        assert_eq!(
+            queries,
+            [
+                r#"(tags:rust AND tags:"_snip_") AND metadata:dep*"#.to_string(),
+                r#"(tags:universal AND tags:"_snip_") AND metadata:dep*"#.to_string(),
+            ]
+        );
+        assert_eq!(no_language, None);
+    }
+
+    #[test]
+    fn given_overlapping_results_when_merging_split_searches_then_language_copy_wins_up_to_limit() {
+        // Arrange
+        let snippet = |id: i32, title: &str| BkmrSnippet {
+            id,
+            title: title.to_string(),
+            url: String::new(),
+            description: String::new(),
+            tags: vec![],
+            access_count: 0,
+        };
+        let language = vec![snippet(1, "rust one"), snippet(2, "rust copy")];
+        let universal = vec![
+            snippet(2, "universal copy"),
+            snippet(3, "universal"),
+            snippet(4, "extra"),
+        ];
+
+        // Act
+        let merged = BkmrRepository::merge_split_results(language, universal, 3);
+
+        // Assert
+        let titles: Vec<&str> = merged.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, vec!["rust one", "rust copy", "universal"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_split_strategy_when_fetching_then_merges_both_searches() {
+        // Arrange
+        let binary = fake_split_bkmr(
+            "split-merge",
+            r#"echo '[{"id": 1, "title": "Lang", "url": "l", "tags": ["rust", "_snip_"]}, {"id": 2, "title": "Both", "url": "rust", "tags": ["rust", "universal", "_snip_"]}]'"#,
+            r#"echo '[{"id": 2, "title": "Both", "url": "universal", "tags": ["rust", "universal", "_snip_"]}, {"id": 3, "title": "Uni", "url": "u", "tags": ["universal", "_snip_"]}]'"#,
+        );
+        let repository = split_repository(&binary);
+        let filter = SnippetFilter::new(Some("rust".to_string()), None, 50);
+
+        // Act
+        let snippets = repository.fetch_snippets(&filter).await.expect("fetch");
+
+        // Assert
+        let ids: Vec<i32> = snippets.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(snippets[1].content, "rust");
+        assert!(
+            repository
+                .stats()
+                .last_query
+                .is_some_and(|query| query.contains(" | "))
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_failing_universal_search_when_fetching_split_then_returns_language_snippets() {
+        // Arrange
+        let binary = fake_split_bkmr(
+            "split-partial",
+            r#"echo '[{"id": 1, "title": "Lang", "url": "l", "tags": ["rust", "_snip_"]}]'"#,
+            "echo 'database locked' >&2; exit 1",
+        );
+        let repository = split_repository(&binary);
+        let filter = SnippetFilter::new(Some("rust".to_string()), None, 50);
+
+        // Act
+        let snippets = repository.fetch_snippets(&filter).await.expect("fetch");
+
+        // Assert
+        assert_eq!(snippets.len(), 1);
+        assert_eq!(snippets[0].id, 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_both_split_searches_failing_when_fetching_then_returns_error() {
+        // Arrange
+        let binary = fake_split_bkmr(
+            "split-failing",
+            "echo 'database locked' >&2; exit 1",
+            "echo 'database locked' >&2; exit 1",
+        );
+        let repository = split_repository(&binary);
+        let filter = SnippetFilter::new(Some("rust".to_string()), None, 50);
+
+        // Act
+        let result = repository.fetch_snippets(&filter).await;
+
+        // Assert
+        let message = format!("{:#}", result.expect_err("both searches fail"));
+        assert!(message.contains("database locked"));
+    }
 }
@@ -0,0 +1,575 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::{debug, instrument};
+
+use crate::domain::{NewSnippet, Snippet, SnippetFilter, TagCount};
+use crate::repositories::{
+    BkmrCapabilities, CacheStats, RepositoryStats, SnippetIdCache, SnippetRepository,
+};
+use crate::sync;
+
+/// Configuration for the snippet result cache
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub ttl: Duration,
+    pub max_entries: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(30),
+            max_entries: 100,
+        }
+    }
+}
+
+/// Cached result for one filter, shared by all requests waiting on it
+type CacheSlot = Arc<OnceCell<(Instant, Vec<Snippet>)>>;
+
+/// Repository decorator caching fetch results per filter with a TTL
+///
+/// Concurrent requests for the same filter share a single in-flight fetch
+/// of the inner repository.
+pub struct CachingSnippetRepository {
+    inner: Arc<dyn SnippetRepository>,
+    config: CacheConfig,
+    entries: Mutex<HashMap<SnippetFilter, CacheSlot>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CachingSnippetRepository {
+    pub fn new(inner: Arc<dyn SnippetRepository>, config: CacheConfig) -> Self {
+        Self {
+            inner,
+            config,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the slot for a filter, replacing it when its result has expired
+    fn slot_for(&self, filter: &SnippetFilter) -> CacheSlot {
+        let mut entries = sync::lock(&self.entries, "cache");
+
+        if let Some(slot) = entries.get(filter) {
+            match slot.get() {
+                Some((fetched_at, _)) if fetched_at.elapsed() >= self.config.ttl => {
+                    debug!("Cache entry expired for filter: {:?}", filter);
+                }
+                _ => return Arc::clone(slot),
+            }
+        }
+
+        if entries.len() >= self.config.max_entries && !entries.contains_key(filter) {
+            self.evict(&mut entries);
+        }
+
+        let slot: CacheSlot = Arc::new(OnceCell::new());
+        entries.insert(filter.clone(), Arc::clone(&slot));
+        slot
+    }
+
+    /// Fetch fresh results for a filter while requests keep getting the cached ones
+    ///
+    /// Filters without a cached result are fetched normally, so that a request
+    /// arriving meanwhile shares the fetch instead of starting its own.
+    pub async fn refresh(&self, filter: &SnippetFilter) -> Result<()> {
+        let cached = sync::lock(&self.entries, "cache")
+            .get(filter)
+            .is_some_and(|slot| slot.get().is_some());
+        if !cached {
+            self.fetch_snippets(filter).await?;
+            return Ok(());
+        }
+
+        debug!("Refreshing cache entry for filter: {:?}", filter);
+        let snippets = self.inner.fetch_snippets(filter).await?;
+        let slot: CacheSlot = Arc::new(OnceCell::new_with(Some((Instant::now(), snippets))));
+        let mut entries = sync::lock(&self.entries, "cache");
+        if entries.len() >= self.config.max_entries && !entries.contains_key(filter) {
+            self.evict(&mut entries);
+        }
+        entries.insert(filter.clone(), slot);
+        Ok(())
+    }
+
+    /// Make room for a new entry: drop expired entries, then the oldest one
+    fn evict(&self, entries: &mut HashMap<SnippetFilter, CacheSlot>) {
+        let ttl = self.config.ttl;
+        entries.retain(|_, slot| {
+            slot.get()
+                .is_none_or(|(fetched_at, _)| fetched_at.elapsed() < ttl)
+        });
+
+        if entries.len() >= self.config.max_entries {
+            let oldest = entries
+                .iter()
+                .filter_map(|(filter, slot)| slot.get().map(|(at, _)| (filter.clone(), *at)))
+                .min_by_key(|(_, at)| *at)
+                .map(|(filter, _)| filter);
+            if let Some(filter) = oldest {
+                debug!("Evicting oldest cache entry: {:?}", filter);
+                entries.remove(&filter);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SnippetRepository for CachingSnippetRepository {
+    #[instrument(skip(self))]
+    async fn fetch_snippets(&self, filter: &SnippetFilter) -> Result<Vec<Snippet>> {
+        let slot = self.slot_for(filter);
+
+        if let Some((fetched_at, snippets)) = slot.get() {
+            debug!("Cache hit: {} snippets", snippets.len());
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            // Offered again, so keep them by id for as long as the search result lives
+            if let Some(ids) = self.inner.id_cache() {
+                ids.insert_all(snippets, *fetched_at);
+            }
+            return Ok(snippets.clone());
+        }
+
+        let (_, snippets) = slot
+            .get_or_try_init(|| async {
+                debug!("Cache miss, fetching from inner repository");
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                let snippets = self.inner.fetch_snippets(filter).await?;
+                Ok::<_, anyhow::Error>((Instant::now(), snippets))
+            })
+            .await?;
+
+        Ok(snippets.clone())
+    }
+
+    async fn query_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.inner.query_snippet_by_id(id).await
+    }
+
+    fn id_cache(&self) -> Option<Arc<SnippetIdCache>> {
+        self.inner.id_cache()
+    }
+
+    /// Never cached, raw templates stay out of the interpolated search results
+    async fn fetch_raw_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.inner.fetch_raw_snippet_by_id(id).await
+    }
+
+    async fn create_snippet(&self, snippet: &NewSnippet) -> Result<i32> {
+        let id = self.inner.create_snippet(snippet).await?;
+        // Cached results no longer include every matching snippet
+        sync::lock(&self.entries, "cache").clear();
+        Ok(id)
+    }
+
+    async fn open_snippet(&self, id: i32) -> Result<()> {
+        self.inner.open_snippet(id).await
+    }
+
+    async fn record_usage(&self, id: i32) -> Result<()> {
+        self.inner.record_usage(id).await
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+
+    async fn start(&self) -> Result<()> {
+        self.inner.start().await
+    }
+
+    async fn shutdown(&self) {
+        self.inner.shutdown().await
+    }
+
+    async fn list_tags(&self) -> Result<Vec<TagCount>> {
+        self.inner.list_tags().await
+    }
+
+    async fn version(&self) -> Result<Option<String>> {
+        self.inner.version().await
+    }
+
+    fn capabilities(&self) -> Option<BkmrCapabilities> {
+        self.inner.capabilities()
+    }
+
+    /// The inner repository's answer, it was asked when the filter was last fetched
+    fn search_complete(&self, filter: &SnippetFilter) -> Option<bool> {
+        self.inner.search_complete(filter)
+    }
+
+    fn clear_cache(&self) -> usize {
+        let evicted = std::mem::take(&mut *sync::lock(&self.entries, "cache")).len();
+        debug!("Cleared {} cache entries", evicted);
+        evicted + self.inner.clear_cache()
+    }
+
+    fn stats(&self) -> RepositoryStats {
+        RepositoryStats {
+            cache: Some(CacheStats {
+                hits: self.hits.load(Ordering::Relaxed),
+                misses: self.misses.load(Ordering::Relaxed),
+            }),
+            ..self.inner.stats()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::MockSnippetRepository;
+
+    fn rust_snippet() -> Snippet {
+        Snippet::new(
+            1,
+            "Hello World".to_string(),
+            "println!(\"Hello, World!\");".to_string(),
+            "Hello world example".to_string(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        )
+    }
+
+    #[tokio::test]
+    async fn given_repeated_query_within_ttl_when_fetching_then_calls_inner_once() {
+        // Arrange
+        let inner = Arc::new(MockSnippetRepository::new().with_snippets(vec![rust_snippet()]));
+        let repository = CachingSnippetRepository::new(inner.clone(), CacheConfig::default());
+        let filter = SnippetFilter::new(Some("rust".to_string()), Some("hel".to_string()), 50);
+
+        // Act
+        let first = repository
+            .fetch_snippets(&filter)
+            .await
+            .expect("first fetch");
+        let second = repository
+            .fetch_snippets(&filter)
+            .await
+            .expect("second fetch");
+
+        // Assert
+        assert_eq!(first, second);
+        assert_eq!(first.len(), 1);
+        assert_eq!(inner.fetch_count(), 1);
+        assert_eq!(
+            repository.stats().cache,
+            Some(CacheStats { hits: 1, misses: 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn given_cached_results_when_fetching_raw_snippet_then_cache_keeps_interpolated_content()
+    {
+        // Arrange
+        let mut raw = rust_snippet();
+        raw.content = r#"println!("Hello, {{ env("USER") }}!");"#.to_string();
+        let inner = Arc::new(
+            MockSnippetRepository::new()
+                .with_snippets(vec![rust_snippet()])
+                .with_raw_snippets(vec![raw.clone()]),
+        );
+        let repository = CachingSnippetRepository::new(inner.clone(), CacheConfig::default());
+        let filter = SnippetFilter::new(Some("rust".to_string()), Some("hel".to_string()), 50);
+        repository.fetch_snippets(&filter).await.expect("fetch");
+
+        // Act
+        let fetched_raw = repository
+            .fetch_raw_snippet_by_id(1)
+            .await
+            .expect("raw fetch");
+        let fetched_again = repository
+            .fetch_raw_snippet_by_id(1)
+            .await
+            .expect("second raw fetch");
+        let cached = repository
+            .fetch_snippets(&filter)
+            .await
+            .expect("cached fetch");
+
+        // Assert
+        assert_eq!(fetched_raw, Some(raw.clone()));
+        assert_eq!(fetched_again, Some(raw));
+        assert_eq!(cached, vec![rust_snippet()]);
+        assert_eq!(inner.fetch_count(), 3);
+        assert_eq!(
+            repository.stats().cache,
+            Some(CacheStats { hits: 1, misses: 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn given_cached_results_when_clearing_then_counts_them_and_refetches() {
+        // Arrange
+        let inner = Arc::new(MockSnippetRepository::new().with_snippets(vec![rust_snippet()]));
+        let repository = CachingSnippetRepository::new(inner.clone(), CacheConfig::default());
+        let filters = [
+            SnippetFilter::new(Some("rust".to_string()), Some("he".to_string()), 50),
+            SnippetFilter::new(Some("rust".to_string()), Some("hel".to_string()), 50),
+        ];
+        for filter in &filters {
+            repository.fetch_snippets(filter).await.expect("fetch");
+        }
+
+        // Act
+        let evicted = repository.clear_cache();
+        repository
+            .fetch_snippets(&filters[0])
+            .await
+            .expect("fetch after clearing");
+
+        // Assert
+        assert_eq!(evicted, 2);
+        assert_eq!(repository.clear_cache(), 1);
+        assert_eq!(inner.fetch_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn given_different_filters_when_fetching_then_caches_separately() {
+        // Arrange
+        let inner = Arc::new(MockSnippetRepository::new().with_snippets(vec![rust_snippet()]));
+        let repository = CachingSnippetRepository::new(inner.clone(), CacheConfig::default());
+
+        // Act
+        for prefix in ["he", "hel", "he"] {
+            let filter = SnippetFilter::new(Some("rust".to_string()), Some(prefix.to_string()), 50);
+            repository.fetch_snippets(&filter).await.expect("fetch");
+        }
+
+        // Assert
+        assert_eq!(inner.fetch_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn given_expired_entry_when_fetching_then_refetches() {
+        // Arrange
+        let inner = Arc::new(MockSnippetRepository::new().with_snippets(vec![rust_snippet()]));
+        let config = CacheConfig {
+            ttl: Duration::from_millis(20),
+            max_entries: 10,
+        };
+        let repository = CachingSnippetRepository::new(inner.clone(), config);
+        let filter = SnippetFilter::default();
+
+        // Act
+        repository
+            .fetch_snippets(&filter)
+            .await
+            .expect("first fetch");
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        repository
+            .fetch_snippets(&filter)
+            .await
+            .expect("second fetch");
+
+        // Assert
+        assert_eq!(inner.fetch_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn given_concurrent_identical_queries_when_fetching_then_coalesces_inner_calls() {
+        // Arrange
+        let inner = Arc::new(
+            MockSnippetRepository::new()
+                .with_snippets(vec![rust_snippet()])
+                .with_delay(Duration::from_millis(50)),
+        );
+        let repository = Arc::new(CachingSnippetRepository::new(
+            inner.clone(),
+            CacheConfig::default(),
+        ));
+        let filter = SnippetFilter::new(Some("rust".to_string()), None, 50);
+
+        // Act
+        let handles: Vec<_> = (0..5)
+            .map(|_| {
+                let repository = Arc::clone(&repository);
+                let filter = filter.clone();
+                tokio::spawn(async move { repository.fetch_snippets(&filter).await })
+            })
+            .collect();
+        for handle in handles {
+            let snippets = handle.await.expect("join task").expect("fetch");
+            assert_eq!(snippets.len(), 1);
+        }
+
+        // Assert
+        assert_eq!(inner.fetch_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn given_full_cache_when_adding_entry_then_evicts_oldest() {
+        // Arrange
+        let inner = Arc::new(MockSnippetRepository::new().with_snippets(vec![rust_snippet()]));
+        let config = CacheConfig {
+            ttl: Duration::from_secs(30),
+            max_entries: 2,
+        };
+        let repository = CachingSnippetRepository::new(inner.clone(), config);
+        let filter = |prefix: &str| SnippetFilter::new(None, Some(prefix.to_string()), 50);
+
+        // Act
+        repository
+            .fetch_snippets(&filter("a"))
+            .await
+            .expect("fetch a");
+        repository
+            .fetch_snippets(&filter("b"))
+            .await
+            .expect("fetch b");
+        repository
+            .fetch_snippets(&filter("c"))
+            .await
+            .expect("fetch c");
+        repository
+            .fetch_snippets(&filter("c"))
+            .await
+            .expect("fetch c again");
+        repository
+            .fetch_snippets(&filter("a"))
+            .await
+            .expect("fetch a again");
+
+        // Assert: "a" was evicted when "c" arrived, so it is fetched twice
+        assert_eq!(inner.fetch_count(), 4);
+        assert!(repository.entries.lock().expect("cache lock").len() <= 2);
+    }
+
+    #[tokio::test]
+    async fn given_cached_results_when_creating_snippet_then_invalidates_cache() {
+        // Arrange
+        let inner = Arc::new(MockSnippetRepository::new().with_snippets(vec![rust_snippet()]));
+        let repository = CachingSnippetRepository::new(inner.clone(), CacheConfig::default());
+        let filter = SnippetFilter::default();
+        repository.fetch_snippets(&filter).await.expect("fetch");
+        let new_snippet = NewSnippet {
+            title: "New".to_string(),
+            content: "new content".to_string(),
+            tags: vec!["rust".to_string(), "_snip_".to_string()],
+        };
+
+        // Act
+        repository
+            .create_snippet(&new_snippet)
+            .await
+            .expect("create snippet");
+        repository.fetch_snippets(&filter).await.expect("refetch");
+
+        // Assert
+        assert_eq!(inner.fetch_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn given_warm_entry_when_refreshing_then_replaces_result_without_extra_fetch() {
+        // Arrange
+        let inner = Arc::new(MockSnippetRepository::new().with_snippets(vec![rust_snippet()]));
+        let repository = CachingSnippetRepository::new(inner.clone(), CacheConfig::default());
+        let filter = SnippetFilter::new(Some("rust".to_string()), None, 50);
+
+        // Act
+        repository.refresh(&filter).await.expect("cold refresh");
+        repository.refresh(&filter).await.expect("warm refresh");
+        let snippets = repository.fetch_snippets(&filter).await.expect("fetch");
+
+        // Assert
+        assert_eq!(snippets.len(), 1);
+        assert_eq!(inner.fetch_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn given_failing_inner_repository_when_fetching_then_does_not_cache_error() {
+        // Arrange
+        let inner = Arc::new(MockSnippetRepository::new().with_fetch_error("bkmr failed"));
+        let repository = CachingSnippetRepository::new(inner.clone(), CacheConfig::default());
+        let filter = SnippetFilter::default();
+
+        // Act
+        let first = repository.fetch_snippets(&filter).await;
+        let second = repository.fetch_snippets(&filter).await;
+
+        // Assert
+        assert!(first.is_err());
+        assert!(second.is_err());
+        assert_eq!(inner.fetch_count(), 2);
+    }
+
+    fn id_cache() -> Arc<SnippetIdCache> {
+        Arc::new(SnippetIdCache::new(10, Duration::from_secs(30)))
+    }
+
+    #[tokio::test]
+    async fn given_cached_search_when_fetching_by_id_then_skips_inner_query() {
+        // Arrange
+        let ids = id_cache();
+        let inner = Arc::new(
+            MockSnippetRepository::new()
+                .with_snippets(vec![rust_snippet()])
+                .with_id_cache(ids.clone()),
+        );
+        let repository = CachingSnippetRepository::new(inner.clone(), CacheConfig::default());
+        let filter = SnippetFilter::new(Some("rust".to_string()), None, 50);
+        repository.fetch_snippets(&filter).await.expect("search");
+        ids.clear();
+
+        // Act
+        repository
+            .fetch_snippets(&filter)
+            .await
+            .expect("cached search");
+        let snippet = repository
+            .fetch_snippet_by_id(1)
+            .await
+            .expect("fetch by id");
+
+        // Assert
+        assert_eq!(
+            snippet.map(|snippet| snippet.title),
+            Some("Hello World".to_string())
+        );
+        assert_eq!(inner.fetch_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn given_unknown_or_stale_id_when_fetching_by_id_then_queries_and_caches_it() {
+        // Arrange
+        let ids = id_cache();
+        let mut stale = rust_snippet();
+        stale.content = "outdated".to_string();
+        ids.insert_all([&stale], Instant::now() - Duration::from_secs(60));
+        let inner = Arc::new(
+            MockSnippetRepository::new()
+                .with_snippets(vec![rust_snippet()])
+                .with_id_cache(ids.clone()),
+        );
+        let repository = CachingSnippetRepository::new(inner.clone(), CacheConfig::default());
+
+        // Act
+        let first = repository
+            .fetch_snippet_by_id(1)
+            .await
+            .expect("first fetch");
+        let second = repository
+            .fetch_snippet_by_id(1)
+            .await
+            .expect("second fetch");
+        let missing = repository
+            .fetch_snippet_by_id(2)
+            .await
+            .expect("missing fetch");
+
+        // Assert
+        assert_eq!(first, Some(rust_snippet()));
+        assert_eq!(second, first);
+        assert!(missing.is_none());
+        assert_eq!(inner.fetch_count(), 2);
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::sync;
+
+/// Stops calling a failing command after consecutive failures, for a cooldown
+///
+/// Once the cooldown has passed the next call goes through again: success
+/// closes the breaker, another failure opens it for a new cooldown, so a
+/// still failing command is tried once per cooldown. A threshold of 0 never
+/// opens it.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// Whether calls are rejected because the breaker is open
+    pub fn is_open(&self) -> bool {
+        let state = sync::lock(&self.state, "breaker");
+        state.open_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Close the breaker after a successful call
+    pub fn record_success(&self) {
+        let mut state = sync::lock(&self.state, "breaker");
+        if state.open_until.is_some() {
+            info!("bkmr answered again, closing circuit breaker");
+        }
+        *state = BreakerState::default();
+    }
+
+    /// Count a failed call, returning true if it opened the breaker
+    pub fn record_failure(&self) -> bool {
+        let mut state = sync::lock(&self.state, "breaker");
+        state.consecutive_failures += 1;
+        if self.threshold == 0 || state.consecutive_failures < self.threshold {
+            return false;
+        }
+
+        warn!(
+            "bkmr failed {} times in a row, pausing calls for {:?}",
+            state.consecutive_failures, self.cooldown
+        );
+        state.open_until = Some(Instant::now() + self.cooldown);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_consecutive_failures_when_reaching_threshold_then_opens() {
+        // Arrange
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+
+        // Act
+        let first_failure_opened = breaker.record_failure();
+        let second_failure_opened = breaker.record_failure();
+
+        // Assert
+        assert!(!first_failure_opened);
+        assert!(second_failure_opened);
+        assert!(breaker.is_open());
+    }
+
+    #[test]
+    fn given_open_breaker_when_cooldown_passes_or_call_succeeds_then_closes() {
+        // Arrange
+        let expiring = CircuitBreaker::new(1, Duration::ZERO);
+        let succeeding = CircuitBreaker::new(2, Duration::from_secs(60));
+        expiring.record_failure();
+        succeeding.record_failure();
+
+        // Act
+        succeeding.record_success();
+        let reopened = succeeding.record_failure();
+
+        // Assert
+        assert!(!expiring.is_open());
+        assert!(!reopened);
+        assert!(!succeeding.is_open());
+    }
+
+    #[test]
+    fn given_zero_threshold_when_failing_then_never_opens() {
+        // Arrange
+        let breaker = CircuitBreaker::new(0, Duration::from_secs(60));
+
+        // Act
+        let opened = (0..5).any(|_| breaker.record_failure());
+
+        // Assert
+        assert!(!opened);
+        assert!(!breaker.is_open());
+    }
+}
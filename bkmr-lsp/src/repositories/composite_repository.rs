@@ -0,0 +1,238 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::{instrument, warn};
+
+use crate::domain::{NewSnippet, Snippet, SnippetFilter, TagCount};
+use crate::repositories::{BkmrCapabilities, RepositoryStats, SnippetIdCache, SnippetRepository};
+
+/// Repository merging bkmr snippets with those of a secondary source, e.g. snippet files
+///
+/// Both are searched concurrently and bkmr results come first, so they win
+/// ties in ranking. Snippets with negative ids belong to the secondary
+/// repository, all others to bkmr.
+pub struct CompositeRepository {
+    bkmr: Arc<dyn SnippetRepository>,
+    secondary: Arc<dyn SnippetRepository>,
+}
+
+impl CompositeRepository {
+    pub fn new(bkmr: Arc<dyn SnippetRepository>, secondary: Arc<dyn SnippetRepository>) -> Self {
+        Self { bkmr, secondary }
+    }
+
+    /// Repository a snippet id belongs to
+    fn owner(&self, id: i32) -> &Arc<dyn SnippetRepository> {
+        if id < 0 { &self.secondary } else { &self.bkmr }
+    }
+}
+
+#[async_trait]
+impl SnippetRepository for CompositeRepository {
+    #[instrument(skip(self))]
+    async fn fetch_snippets(&self, filter: &SnippetFilter) -> Result<Vec<Snippet>> {
+        let (bkmr, secondary) = tokio::join!(
+            self.bkmr.fetch_snippets(filter),
+            self.secondary.fetch_snippets(filter)
+        );
+
+        match (bkmr, secondary) {
+            (Ok(mut bkmr), Ok(secondary)) => {
+                bkmr.extend(secondary);
+                Ok(bkmr)
+            }
+            (Ok(bkmr), Err(e)) => {
+                warn!(
+                    "Snippet file search failed, using bkmr snippets only: {:#}",
+                    e
+                );
+                Ok(bkmr)
+            }
+            (Err(e), Ok(secondary)) => {
+                warn!("bkmr search failed, using snippet files only: {:#}", e);
+                Ok(secondary)
+            }
+            (Err(e), Err(_)) => Err(e).context("search bkmr and snippet files"),
+        }
+    }
+
+    async fn query_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.owner(id).query_snippet_by_id(id).await
+    }
+
+    /// bkmr's id cache, which also takes the snippet files found alongside bkmr snippets
+    fn id_cache(&self) -> Option<Arc<SnippetIdCache>> {
+        self.bkmr.id_cache()
+    }
+
+    async fn fetch_raw_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.owner(id).fetch_raw_snippet_by_id(id).await
+    }
+
+    async fn create_snippet(&self, snippet: &NewSnippet) -> Result<i32> {
+        self.bkmr.create_snippet(snippet).await
+    }
+
+    async fn open_snippet(&self, id: i32) -> Result<()> {
+        self.owner(id).open_snippet(id).await
+    }
+
+    async fn record_usage(&self, id: i32) -> Result<()> {
+        self.owner(id).record_usage(id).await
+    }
+
+    /// Tags of both repositories, counts of shared tags added up
+    async fn list_tags(&self) -> Result<Vec<TagCount>> {
+        let (bkmr, secondary) = tokio::join!(self.bkmr.list_tags(), self.secondary.list_tags());
+        match (bkmr, secondary) {
+            (Ok(bkmr), Ok(secondary)) => Ok(TagCount::merge(bkmr.into_iter().chain(secondary))),
+            (Ok(tags), Err(e)) | (Err(e), Ok(tags)) => {
+                warn!("Listing tags failed for one snippet source: {:#}", e);
+                Ok(tags)
+            }
+            (Err(e), Err(_)) => Err(e).context("list tags of bkmr and snippet files"),
+        }
+    }
+
+    /// Healthy as long as one of the repositories is
+    async fn health_check(&self) -> Result<()> {
+        let (bkmr, secondary) =
+            tokio::join!(self.bkmr.health_check(), self.secondary.health_check());
+        match (bkmr, secondary) {
+            (Err(e), Err(secondary)) => {
+                Err(e).context(format!("snippet files unavailable too: {:#}", secondary))
+            }
+            (Err(e), Ok(())) => {
+                warn!("bkmr unavailable, serving snippet files only: {:#}", e);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn start(&self) -> Result<()> {
+        let (bkmr, secondary) = tokio::join!(self.bkmr.start(), self.secondary.start());
+        bkmr.and(secondary)
+    }
+
+    async fn shutdown(&self) {
+        tokio::join!(self.bkmr.shutdown(), self.secondary.shutdown());
+    }
+
+    async fn version(&self) -> Result<Option<String>> {
+        self.bkmr.version().await
+    }
+
+    fn capabilities(&self) -> Option<BkmrCapabilities> {
+        self.bkmr.capabilities()
+    }
+
+    fn clear_cache(&self) -> usize {
+        self.bkmr.clear_cache() + self.secondary.clear_cache()
+    }
+
+    fn stats(&self) -> RepositoryStats {
+        self.bkmr.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{RankingStrategy, rank_snippets};
+    use crate::repositories::MockSnippetRepository;
+
+    fn snippet(id: i32, title: &str) -> Snippet {
+        Snippet::new(
+            id,
+            title.to_string(),
+            format!("content {}", id),
+            String::new(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        )
+    }
+
+    fn composite(bkmr: MockSnippetRepository, files: MockSnippetRepository) -> CompositeRepository {
+        CompositeRepository::new(Arc::new(bkmr), Arc::new(files))
+    }
+
+    #[tokio::test]
+    async fn given_both_sources_when_fetching_then_bkmr_results_come_first_on_ties() {
+        // Arrange
+        let repository = composite(
+            MockSnippetRepository::new().with_snippets(vec![snippet(1, "hello")]),
+            MockSnippetRepository::new()
+                .with_snippets(vec![snippet(-7, "hello"), snippet(-8, "fmt")]),
+        );
+
+        // Act
+        let snippets = repository
+            .fetch_snippets(&SnippetFilter::default())
+            .await
+            .expect("fetch snippets");
+        let ranked = rank_snippets(snippets, Some("hello"), RankingStrategy::Relevance);
+
+        // Assert
+        let ids: Vec<i32> = ranked.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![1, -7, -8]);
+    }
+
+    #[tokio::test]
+    async fn given_failing_bkmr_when_fetching_then_returns_file_snippets() {
+        // Arrange
+        let repository = composite(
+            MockSnippetRepository::new().with_fetch_error("bkmr not found"),
+            MockSnippetRepository::new().with_snippets(vec![snippet(-7, "hello")]),
+        );
+
+        // Act
+        let snippets = repository
+            .fetch_snippets(&SnippetFilter::default())
+            .await
+            .expect("fetch snippets");
+
+        // Assert
+        assert_eq!(snippets.len(), 1);
+        assert_eq!(snippets[0].id, -7);
+    }
+
+    #[tokio::test]
+    async fn given_negative_id_when_fetching_by_id_then_asks_secondary_repository() {
+        // Arrange
+        let repository = composite(
+            MockSnippetRepository::new().with_snippets(vec![snippet(1, "bkmr")]),
+            MockSnippetRepository::new().with_snippets(vec![snippet(-7, "file")]),
+        );
+
+        // Act
+        let file = repository
+            .fetch_snippet_by_id(-7)
+            .await
+            .expect("file snippet");
+        let bkmr = repository
+            .fetch_snippet_by_id(1)
+            .await
+            .expect("bkmr snippet");
+
+        // Assert
+        assert_eq!(file.map(|s| s.title), Some("file".to_string()));
+        assert_eq!(bkmr.map(|s| s.title), Some("bkmr".to_string()));
+    }
+
+    #[tokio::test]
+    async fn given_one_healthy_repository_when_checking_health_then_passes() {
+        // Arrange
+        let healthy = composite(
+            MockSnippetRepository::new().with_health_check_error(anyhow::anyhow!("no bkmr")),
+            MockSnippetRepository::new(),
+        );
+        let unhealthy = composite(
+            MockSnippetRepository::new().with_health_check_error(anyhow::anyhow!("no bkmr")),
+            MockSnippetRepository::new().with_health_check_error(anyhow::anyhow!("no dirs")),
+        );
+
+        // Act & Assert
+        assert!(healthy.health_check().await.is_ok());
+        assert!(unhealthy.health_check().await.is_err());
+    }
+}
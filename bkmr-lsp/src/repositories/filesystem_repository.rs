@@ -0,0 +1,484 @@
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::{debug, instrument, warn};
+
+use crate::domain::{
+    CaseMode, DEFAULT_SNIPPET_TAG, LanguageRegistry, NewSnippet, Snippet, SnippetFilter,
+};
+use crate::repositories::SnippetRepository;
+use crate::sync;
+
+/// Directory holding the snippets offered for every language
+pub const UNIVERSAL_SNIPPET_DIR: &str = "universal";
+/// Extension of snippet files, the file name stem is the title
+pub const SNIPPET_FILE_EXTENSION: &str = "snippet";
+
+/// Content of a snippet file, valid while its modification time is unchanged
+#[derive(Debug, Clone)]
+struct CachedFile {
+    modified: SystemTime,
+    content: String,
+}
+
+/// Snippet file contents by path
+type FileCache = Mutex<HashMap<PathBuf, CachedFile>>;
+
+/// Repository reading plain snippet files from per-language directories
+///
+/// Each snippet directory contains `<language_id>/*.snippet` and
+/// `universal/*.snippet`. File snippets get negative ids so they never
+/// collide with bkmr ids.
+pub struct FileSystemRepository {
+    directories: Arc<[PathBuf]>,
+    cache: Arc<FileCache>,
+    file_reads: Arc<AtomicU64>,
+    /// Languages whose tags name further snippet directories
+    language_registry: Arc<LanguageRegistry>,
+}
+
+impl std::fmt::Debug for FileSystemRepository {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileSystemRepository")
+            .field("directories", &self.directories)
+            .finish()
+    }
+}
+
+impl FileSystemRepository {
+    pub fn new(directories: Vec<PathBuf>) -> Self {
+        Self {
+            directories: directories.into(),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            file_reads: Arc::new(AtomicU64::new(0)),
+            language_registry: Arc::new(LanguageRegistry::default()),
+        }
+    }
+
+    /// Search the directories of a language's tags under the given registry
+    pub fn with_language_registry(mut self, language_registry: Arc<LanguageRegistry>) -> Self {
+        self.language_registry = language_registry;
+        self
+    }
+
+    /// Number of snippet files read from disk rather than the cache
+    pub fn file_reads(&self) -> u64 {
+        self.file_reads.load(Ordering::Relaxed)
+    }
+
+    /// Stable id of the snippet in a file, always negative
+    pub fn snippet_id(path: &Path) -> i32 {
+        // FNV-1a, stable across runs unlike the std hasher
+        let hash = path
+            .to_string_lossy()
+            .bytes()
+            .fold(0x811c_9dc5_u32, |hash, byte| {
+                (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+            });
+        -((hash & 0x7fff_ffff) as i32) - 1
+    }
+
+    /// Snippets of the language and universal directories, all of them without a language
+    async fn load_snippets(&self, language_id: Option<&str>) -> Result<Vec<Snippet>> {
+        let directories = Arc::clone(&self.directories);
+        let cache = Arc::clone(&self.cache);
+        let file_reads = Arc::clone(&self.file_reads);
+        let language_registry = Arc::clone(&self.language_registry);
+        let language_id = language_id.map(str::to_string);
+
+        tokio::task::spawn_blocking(move || {
+            let mut snippets = Vec::new();
+            for root in directories.iter() {
+                let names = match &language_id {
+                    Some(language_id) => Self::language_dir_names(language_id, &language_registry),
+                    None => Self::all_dir_names(root),
+                };
+                for name in names {
+                    let dir = root.join(&name);
+                    if dir.is_dir() {
+                        snippets.extend(Self::read_dir_snippets(&dir, &name, &cache, &file_reads)?);
+                    }
+                }
+            }
+            Ok(snippets)
+        })
+        .await
+        .context("join snippet directory scan")?
+    }
+
+    /// Directories searched for a language: its ID, its tags and the universal one
+    fn language_dir_names(language_id: &str, registry: &LanguageRegistry) -> Vec<String> {
+        let mut names = vec![language_id.to_string()];
+        for tag in registry.language_tags(language_id) {
+            if !names.contains(&tag) {
+                names.push(tag);
+            }
+        }
+        names.push(UNIVERSAL_SNIPPET_DIR.to_string());
+        names
+    }
+
+    /// Every subdirectory of a snippet directory, sorted by name
+    fn all_dir_names(root: &Path) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(root) else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Snippets of the `.snippet` files in one directory, sorted by file name
+    fn read_dir_snippets(
+        dir: &Path,
+        tag: &str,
+        cache: &FileCache,
+        file_reads: &AtomicU64,
+    ) -> Result<Vec<Snippet>> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+            .with_context(|| format!("read snippet directory {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path.extension().and_then(|ext| ext.to_str()) == Some(SNIPPET_FILE_EXTENSION)
+            })
+            .collect();
+        paths.sort();
+
+        let mut snippets = Vec::new();
+        for path in paths {
+            let Some(title) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            match Self::read_cached(&path, cache, file_reads) {
+                Ok(content) => snippets.push(Snippet::new(
+                    Self::snippet_id(&path),
+                    title.to_string(),
+                    content,
+                    path.display().to_string(),
+                    vec![tag.to_string(), DEFAULT_SNIPPET_TAG.to_string()],
+                )),
+                Err(e) => warn!("Skipping snippet file: {:#}", e),
+            }
+        }
+        Ok(snippets)
+    }
+
+    /// File content from the cache unless the file was modified since it was read
+    fn read_cached(path: &Path, cache: &FileCache, file_reads: &AtomicU64) -> Result<String> {
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| format!("stat snippet file {}", path.display()))?;
+        if let Some(cached) = sync::lock(cache, "file cache").get(path)
+            && cached.modified == modified
+        {
+            return Ok(cached.content.clone());
+        }
+
+        debug!("Reading snippet file {}", path.display());
+        file_reads.fetch_add(1, Ordering::Relaxed);
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("read snippet file {}", path.display()))?;
+        sync::lock(cache, "file cache").insert(
+            path.to_path_buf(),
+            CachedFile {
+                modified,
+                content: content.clone(),
+            },
+        );
+        Ok(content)
+    }
+
+    /// Check whether a word of the title starts with the prefix, like bkmr's title search
+    fn matches_prefix(title: &str, prefix: &str, case_mode: CaseMode) -> bool {
+        let prefix = case_mode.search_query(prefix.trim());
+        let title = if case_mode.is_case_sensitive(&prefix) {
+            title.to_string()
+        } else {
+            title.to_lowercase()
+        };
+        prefix.is_empty()
+            || title
+                .split(|c: char| !c.is_alphanumeric())
+                .any(|word| word.starts_with(&prefix))
+    }
+}
+
+#[async_trait]
+impl SnippetRepository for FileSystemRepository {
+    #[instrument(skip(self))]
+    async fn fetch_snippets(&self, filter: &SnippetFilter) -> Result<Vec<Snippet>> {
+        // Snippet files hold no bookmarks
+        if filter.bookmarks {
+            return Ok(Vec::new());
+        }
+        let language_id = filter
+            .language_id
+            .as_deref()
+            .filter(|language_id| !language_id.trim().is_empty());
+        let mut snippets = self.load_snippets(language_id).await?;
+
+        snippets.retain(|snippet| {
+            filter
+                .query_prefix
+                .as_deref()
+                .is_none_or(|prefix| Self::matches_prefix(&snippet.title, prefix, filter.case_mode))
+                && filter.matches_title(&snippet.title)
+                && filter.allows_tags(&snippet.tags)
+                // Only languages fetch the universal directory, leaving it out on request
+                && (filter.universal || language_id.is_none() || !snippet.is_universal())
+        });
+        snippets.truncate(filter.max_results);
+
+        debug!("Found {} file snippets", snippets.len());
+        Ok(snippets)
+    }
+
+    async fn query_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        Ok(self
+            .load_snippets(None)
+            .await?
+            .into_iter()
+            .find(|snippet| snippet.id == id))
+    }
+
+    async fn create_snippet(&self, _snippet: &NewSnippet) -> Result<i32> {
+        Err(anyhow!(
+            "snippets are created in bkmr, not in snippet directories"
+        ))
+    }
+
+    async fn open_snippet(&self, id: i32) -> Result<()> {
+        match self.fetch_snippet_by_id(id).await? {
+            Some(snippet) => Err(anyhow!(
+                "file snippet '{}' is edited in {}",
+                snippet.title,
+                snippet.description
+            )),
+            None => Err(anyhow!("no file snippet with id {}", id)),
+        }
+    }
+
+    async fn record_usage(&self, _id: i32) -> Result<()> {
+        // Snippet files have no access count
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        if self.directories.iter().any(|dir| dir.is_dir()) {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "no snippet directory found: {}",
+            self.directories
+                .iter()
+                .map(|dir| dir.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Snippet directory in the temp dir, removed when dropped
+    struct SnippetDir {
+        root: PathBuf,
+    }
+
+    impl SnippetDir {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "bkmr-lsp-snippets-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(&root).expect("create snippet dir");
+            Self { root }
+        }
+
+        fn write(&self, relative: &str, content: &str) -> PathBuf {
+            let path = self.root.join(relative);
+            std::fs::create_dir_all(path.parent().expect("parent dir")).expect("create dir");
+            std::fs::write(&path, content).expect("write snippet file");
+            path
+        }
+
+        fn repository(&self) -> FileSystemRepository {
+            FileSystemRepository::new(vec![self.root.clone()])
+        }
+    }
+
+    impl Drop for SnippetDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn fixture(name: &str) -> SnippetDir {
+        let dir = SnippetDir::new(name);
+        dir.write("rust/hello-world.snippet", "println!(\"Hello\");");
+        dir.write("rust/test-module.snippet", "#[cfg(test)]\nmod tests {}");
+        dir.write("python/main-guard.snippet", "if __name__ == \"__main__\":");
+        dir.write(
+            "universal/license-header.snippet",
+            "SPDX-License-Identifier: MIT",
+        );
+        dir.write("rust/notes.txt", "not a snippet");
+        dir
+    }
+
+    fn titles(snippets: &[Snippet]) -> Vec<&str> {
+        snippets.iter().map(|s| s.title.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn given_language_when_fetching_then_returns_language_and_universal_snippets() {
+        // Arrange
+        let dir = fixture("language");
+        let filter = SnippetFilter::new(Some("rust".to_string()), None, 50);
+
+        // Act
+        let snippets = dir
+            .repository()
+            .fetch_snippets(&filter)
+            .await
+            .expect("fetch snippets");
+
+        // Assert
+        assert_eq!(
+            titles(&snippets),
+            vec!["hello-world", "test-module", "license-header"]
+        );
+        assert_eq!(snippets[0].content, "println!(\"Hello\");");
+        assert_eq!(snippets[0].tags, vec!["rust", "_snip_"]);
+        assert_eq!(snippets[2].tags, vec!["universal", "_snip_"]);
+        assert!(snippets[0].id < 0);
+    }
+
+    #[tokio::test]
+    async fn given_no_language_when_fetching_then_returns_snippets_of_all_directories() {
+        // Arrange
+        let dir = fixture("all");
+
+        // Act
+        let snippets = dir
+            .repository()
+            .fetch_snippets(&SnippetFilter::default())
+            .await
+            .expect("fetch snippets");
+
+        // Assert
+        assert_eq!(
+            titles(&snippets),
+            vec!["main-guard", "hello-world", "test-module", "license-header"]
+        );
+    }
+
+    #[tokio::test]
+    async fn given_prefix_when_fetching_then_matches_words_of_file_names() {
+        // Arrange
+        let dir = fixture("prefix");
+        let filter = SnippetFilter::new(Some("rust".to_string()), Some("Mod".to_string()), 50)
+            .with_case_mode(CaseMode::Insensitive);
+
+        // Act
+        let snippets = dir
+            .repository()
+            .fetch_snippets(&filter)
+            .await
+            .expect("fetch snippets");
+
+        // Assert
+        assert_eq!(titles(&snippets), vec!["test-module"]);
+    }
+
+    #[tokio::test]
+    async fn given_unchanged_files_when_fetching_again_then_serves_contents_from_cache() {
+        // Arrange
+        let dir = fixture("cache");
+        let repository = dir.repository();
+        let filter = SnippetFilter::new(Some("python".to_string()), None, 50);
+        repository
+            .fetch_snippets(&filter)
+            .await
+            .expect("first fetch");
+        let reads = repository.file_reads();
+
+        // Act
+        repository
+            .fetch_snippets(&filter)
+            .await
+            .expect("second fetch");
+
+        // Assert
+        assert_eq!(reads, 2);
+        assert_eq!(repository.file_reads(), reads);
+    }
+
+    #[tokio::test]
+    async fn given_touched_file_when_fetching_then_rereads_it() {
+        // Arrange
+        let dir = fixture("touch");
+        let repository = dir.repository();
+        let filter = SnippetFilter::new(Some("python".to_string()), Some("main".to_string()), 50);
+        repository
+            .fetch_snippets(&filter)
+            .await
+            .expect("first fetch");
+        let path = dir.write("python/main-guard.snippet", "def main():");
+        let later = SystemTime::now() + std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(later))
+            .expect("touch snippet file");
+
+        // Act
+        let snippets = repository
+            .fetch_snippets(&filter)
+            .await
+            .expect("second fetch");
+
+        // Assert
+        assert_eq!(snippets[0].content, "def main():");
+        assert_eq!(repository.file_reads(), 3);
+    }
+
+    #[tokio::test]
+    async fn given_snippet_id_when_fetching_by_id_then_finds_file_snippet() {
+        // Arrange
+        let dir = fixture("by-id");
+        let id = FileSystemRepository::snippet_id(&dir.root.join("python/main-guard.snippet"));
+
+        // Act
+        let snippet = dir
+            .repository()
+            .fetch_snippet_by_id(id)
+            .await
+            .expect("fetch by id");
+
+        // Assert
+        assert_eq!(snippet.map(|s| s.title), Some("main-guard".to_string()));
+    }
+
+    #[tokio::test]
+    async fn given_missing_directories_when_checking_health_then_fails() {
+        // Arrange
+        let repository = FileSystemRepository::new(vec![PathBuf::from("/nonexistent/snippets")]);
+
+        // Act & Assert
+        assert!(repository.health_check().await.is_err());
+    }
+}
@@ -1,20 +1,56 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::domain::{Snippet, SnippetFilter};
-use crate::repositories::SnippetRepository;
+use crate::domain::{LanguageRegistry, NewSnippet, Snippet, SnippetFilter, default_snippet_tags};
+use crate::repositories::{SnippetIdCache, SnippetRepository};
+use crate::sync;
 
 /// Mock repository implementation for testing
 pub struct MockSnippetRepository {
     pub snippets: Vec<Snippet>,
+    /// Uninterpolated versions of snippets, returned by raw fetches
+    pub raw_snippets: Vec<Snippet>,
     pub health_check_result: Result<(), anyhow::Error>,
+    /// Error message returned by every fetch, if set
+    pub fetch_error: Option<String>,
+    /// Artificial latency applied to every fetch
+    pub delay: Option<Duration>,
+    /// Error message returned by every record_usage call, if set
+    pub usage_error: Option<String>,
+    /// Tags marking snippets, any of them matches, none means all snippets
+    pub snippet_tags: Vec<String>,
+    /// Languages whose tags the language filter matches
+    pub language_registry: LanguageRegistry,
+    /// Filled with the results of every search, if set
+    pub id_cache: Option<Arc<SnippetIdCache>>,
+    /// Whether searches found every match, as a repository knowing the total reports it
+    pub search_complete: Option<bool>,
+    fetch_count: AtomicUsize,
+    opened_ids: Mutex<Vec<i32>>,
+    used_ids: Mutex<Vec<i32>>,
+    created_snippets: Mutex<Vec<NewSnippet>>,
 }
 
 impl MockSnippetRepository {
     pub fn new() -> Self {
         Self {
             snippets: Vec::new(),
+            raw_snippets: Vec::new(),
             health_check_result: Ok(()),
+            fetch_error: None,
+            delay: None,
+            usage_error: None,
+            snippet_tags: default_snippet_tags(),
+            language_registry: LanguageRegistry::default(),
+            id_cache: None,
+            search_complete: None,
+            fetch_count: AtomicUsize::new(0),
+            opened_ids: Mutex::new(Vec::new()),
+            used_ids: Mutex::new(Vec::new()),
+            created_snippets: Mutex::new(Vec::new()),
         }
     }
 
@@ -23,10 +59,65 @@ impl MockSnippetRepository {
         self
     }
 
+    pub fn with_raw_snippets(mut self, raw_snippets: Vec<Snippet>) -> Self {
+        self.raw_snippets = raw_snippets;
+        self
+    }
+
     pub fn with_health_check_error(mut self, error: anyhow::Error) -> Self {
         self.health_check_result = Err(error);
         self
     }
+
+    pub fn with_fetch_error(mut self, message: &str) -> Self {
+        self.fetch_error = Some(message.to_string());
+        self
+    }
+
+    pub fn with_usage_error(mut self, message: &str) -> Self {
+        self.usage_error = Some(message.to_string());
+        self
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    pub fn with_snippet_tags(mut self, snippet_tags: Vec<String>) -> Self {
+        self.snippet_tags = snippet_tags;
+        self
+    }
+
+    pub fn with_id_cache(mut self, id_cache: Arc<SnippetIdCache>) -> Self {
+        self.id_cache = Some(id_cache);
+        self
+    }
+
+    pub fn with_search_complete(mut self, complete: bool) -> Self {
+        self.search_complete = Some(complete);
+        self
+    }
+
+    /// Number of times fetch_snippets has been called
+    pub fn fetch_count(&self) -> usize {
+        self.fetch_count.load(Ordering::SeqCst)
+    }
+
+    /// Snippets passed to create_snippet so far
+    pub fn created_snippets(&self) -> Vec<NewSnippet> {
+        sync::lock(&self.created_snippets, "created snippets").clone()
+    }
+
+    /// Ids passed to open_snippet so far
+    pub fn opened_ids(&self) -> Vec<i32> {
+        sync::lock(&self.opened_ids, "opened ids").clone()
+    }
+
+    /// Ids passed to record_usage so far
+    pub fn used_ids(&self) -> Vec<i32> {
+        sync::lock(&self.used_ids, "used ids").clone()
+    }
 }
 
 impl Default for MockSnippetRepository {
@@ -38,40 +129,125 @@ impl Default for MockSnippetRepository {
 #[async_trait]
 impl SnippetRepository for MockSnippetRepository {
     async fn fetch_snippets(&self, filter: &SnippetFilter) -> Result<Vec<Snippet>> {
+        self.fetch_count.fetch_add(1, Ordering::SeqCst);
+
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
+
+        if let Some(ref message) = self.fetch_error {
+            return Err(anyhow::anyhow!("{}", message));
+        }
+
         let mut filtered_snippets = self.snippets.clone();
 
+        // Apply snippet tag filter, bookmarks are the entries without snippet tags
+        if !self.snippet_tags.is_empty() {
+            filtered_snippets.retain(|snippet| {
+                self.snippet_tags
+                    .iter()
+                    .any(|tag| snippet.tags.contains(tag))
+                    != filter.bookmarks
+            });
+        }
+
         // Apply language filter
         if let Some(ref language) = filter.language_id {
-            filtered_snippets
-                .retain(|snippet| snippet.has_language(language) || snippet.is_universal());
+            let tags = self.language_registry.language_tags(language);
+            filtered_snippets.retain(|snippet| {
+                tags.iter().any(|tag| snippet.has_language(tag))
+                    || (filter.universal && snippet.is_universal())
+            });
         }
 
         // Apply prefix filter
         if let Some(ref prefix) = filter.query_prefix {
-            let prefix_lower = prefix.to_lowercase();
+            let case_mode = filter.case_mode;
             filtered_snippets.retain(|snippet| {
-                snippet.title.to_lowercase().contains(&prefix_lower)
-                    || snippet.description.to_lowercase().contains(&prefix_lower)
+                case_mode.contains(&snippet.title, prefix)
+                    || case_mode.contains(&snippet.description, prefix)
             });
         }
 
+        filtered_snippets.retain(|snippet| filter.matches_title(&snippet.title));
+        filtered_snippets.retain(|snippet| filter.allows_tags(&snippet.tags));
+
         // Apply limit
         filtered_snippets.truncate(filter.max_results);
 
+        if let Some(id_cache) = &self.id_cache {
+            id_cache.insert_all(&filtered_snippets, Instant::now());
+        }
         Ok(filtered_snippets)
     }
 
+    async fn query_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.fetch_count.fetch_add(1, Ordering::SeqCst);
+        Ok(self
+            .snippets
+            .iter()
+            .find(|snippet| snippet.id == id)
+            .cloned())
+    }
+
+    fn id_cache(&self) -> Option<Arc<SnippetIdCache>> {
+        self.id_cache.clone()
+    }
+
+    async fn fetch_raw_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        match self.raw_snippets.iter().find(|snippet| snippet.id == id) {
+            Some(snippet) => {
+                self.fetch_count.fetch_add(1, Ordering::SeqCst);
+                Ok(Some(snippet.clone()))
+            }
+            None => self.fetch_snippet_by_id(id).await,
+        }
+    }
+
+    async fn create_snippet(&self, snippet: &NewSnippet) -> Result<i32> {
+        let mut created = sync::lock(&self.created_snippets, "created snippets");
+        created.push(snippet.clone());
+
+        // Hand out ids after the ones already known to the mock
+        let max_id = self.snippets.iter().map(|s| s.id).max().unwrap_or(0);
+        Ok(max_id + created.len() as i32)
+    }
+
+    async fn open_snippet(&self, id: i32) -> Result<()> {
+        if !self.snippets.iter().any(|snippet| snippet.id == id) {
+            return Err(anyhow::anyhow!(
+                "bkmr open {} failed: No bookmark found",
+                id
+            ));
+        }
+        sync::lock(&self.opened_ids, "opened ids").push(id);
+        Ok(())
+    }
+
+    async fn record_usage(&self, id: i32) -> Result<()> {
+        sync::lock(&self.used_ids, "used ids").push(id);
+        match &self.usage_error {
+            Some(message) => Err(anyhow::anyhow!("{}", message)),
+            None => Ok(()),
+        }
+    }
+
     async fn health_check(&self) -> Result<()> {
         match &self.health_check_result {
             Ok(()) => Ok(()),
             Err(e) => Err(anyhow::anyhow!("{}", e)),
         }
     }
+
+    fn search_complete(&self, _filter: &SnippetFilter) -> Option<bool> {
+        self.search_complete
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::CaseMode;
 
     #[tokio::test]
     async fn given_empty_repository_when_fetching_snippets_then_returns_empty_list() {
@@ -87,6 +263,153 @@ mod tests {
         assert!(result.expect("empty snippet list").is_empty());
     }
 
+    #[tokio::test]
+    async fn given_excluded_tag_when_fetching_then_skips_matching_snippets_of_any_case() {
+        // Arrange
+        let snippet = |id: i32, tags: &[&str]| {
+            Snippet::new(
+                id,
+                format!("Deploy {}", id),
+                "kubectl apply".to_string(),
+                String::new(),
+                tags.iter().map(|tag| tag.to_string()).collect(),
+            )
+        };
+        let repository = MockSnippetRepository::new().with_snippets(vec![
+            snippet(1, &["sh", "_snip_"]),
+            snippet(2, &["sh", "_snip_", "Private"]),
+            snippet(3, &["universal", "_snip_", "deprecated"]),
+        ]);
+        let filter = SnippetFilter::new(Some("sh".to_string()), Some("deploy".to_string()), 50)
+            .with_excluded_tags(vec!["private".to_string(), "DEPRECATED".to_string()]);
+
+        // Act
+        let snippets = repository.fetch_snippets(&filter).await.expect("snippets");
+
+        // Assert
+        let ids: Vec<i32> = snippets.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn given_case_modes_when_fetching_by_prefix_then_matches_titles_by_mode() {
+        // Arrange
+        let repository = MockSnippetRepository::new().with_snippets(
+            ["docker compose up", "Docker build", "DOCKER prune"]
+                .iter()
+                .enumerate()
+                .map(|(id, title)| {
+                    Snippet::new(
+                        id as i32,
+                        title.to_string(),
+                        "docker".to_string(),
+                        String::new(),
+                        vec!["sh".to_string(), "_snip_".to_string()],
+                    )
+                })
+                .collect(),
+        );
+        let titles = |mode: CaseMode, query: &str| {
+            let filter = SnippetFilter::new(None, Some(query.to_string()), 50).with_case_mode(mode);
+            let repository = &repository;
+            async move {
+                repository
+                    .fetch_snippets(&filter)
+                    .await
+                    .expect("snippets")
+                    .into_iter()
+                    .map(|snippet| snippet.title)
+                    .collect::<Vec<_>>()
+            }
+        };
+        let all = vec!["docker compose up", "Docker build", "DOCKER prune"];
+
+        // Act & Assert
+        assert_eq!(titles(CaseMode::Smart, "docker").await, all);
+        assert_eq!(
+            titles(CaseMode::Smart, "Docker").await,
+            vec!["Docker build"]
+        );
+        assert_eq!(
+            titles(CaseMode::Smart, "DOCKER").await,
+            vec!["DOCKER prune"]
+        );
+        for query in ["docker", "Docker", "DOCKER"] {
+            assert_eq!(titles(CaseMode::Insensitive, query).await, all);
+        }
+        assert_eq!(
+            titles(CaseMode::Sensitive, "docker").await,
+            vec!["docker compose up"]
+        );
+        assert_eq!(
+            titles(CaseMode::Sensitive, "Docker").await,
+            vec!["Docker build"]
+        );
+        assert_eq!(
+            titles(CaseMode::Sensitive, "DOCKER").await,
+            vec!["DOCKER prune"]
+        );
+    }
+
+    #[tokio::test]
+    async fn given_universal_disabled_when_fetching_then_returns_language_snippets_only() {
+        // Arrange
+        let snippet = |id: i32, tags: &[&str]| {
+            Snippet::new(
+                id,
+                format!("Comment {}", id),
+                "// note".to_string(),
+                String::new(),
+                tags.iter().map(|tag| tag.to_string()).collect(),
+            )
+        };
+        let repository = MockSnippetRepository::new().with_snippets(vec![
+            snippet(1, &["sh", "_snip_"]),
+            snippet(2, &["universal", "_snip_"]),
+        ]);
+        let filter = SnippetFilter::new(Some("sh".to_string()), None, 50);
+
+        // Act
+        let with_universal = repository.fetch_snippets(&filter).await.expect("snippets");
+        let without_universal = repository
+            .fetch_snippets(&filter.clone().with_universal(false))
+            .await
+            .expect("snippets");
+
+        // Assert
+        let ids = |snippets: &[Snippet]| snippets.iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(ids(&with_universal), vec![1, 2]);
+        assert_eq!(ids(&without_universal), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn given_required_tag_when_fetching_then_returns_only_snippets_with_that_tag() {
+        // Arrange
+        let snippet = |id: i32, tags: &[&str]| {
+            Snippet::new(
+                id,
+                format!("Query {}", id),
+                "select 1".to_string(),
+                String::new(),
+                tags.iter().map(|tag| tag.to_string()).collect(),
+            )
+        };
+        let repository = MockSnippetRepository::new().with_snippets(vec![
+            snippet(1, &["sh", "_snip_", "SQL"]),
+            snippet(2, &["sh", "_snip_"]),
+            snippet(3, &["universal", "_snip_", "sql"]),
+        ]);
+        let filter = SnippetFilter::new(Some("sh".to_string()), None, 50)
+            .with_required_tags(vec!["sql".to_string()]);
+
+        // Act
+        let snippets = repository.fetch_snippets(&filter).await.expect("snippets");
+
+        // Assert
+        let ids: Vec<i32> = snippets.iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+    }
+
     #[tokio::test]
     async fn given_snippets_with_language_filter_when_fetching_then_returns_matching_snippets() {
         // Arrange
@@ -167,6 +490,45 @@ mod tests {
         assert_eq!(snippets[0].id, hello_snippet.id);
     }
 
+    #[tokio::test]
+    async fn given_custom_snippet_tags_when_fetching_then_returns_snippets_with_any_of_them() {
+        // Arrange
+        let snippet = |id: i32, tag: &str| {
+            Snippet::new(
+                id,
+                format!("Snippet {}", id),
+                "content".to_string(),
+                String::new(),
+                vec!["rust".to_string(), tag.to_string()],
+            )
+        };
+        let snippets = vec![
+            snippet(1, "_snip_"),
+            snippet(2, "snippet"),
+            snippet(3, "note"),
+        ];
+        let tagged = MockSnippetRepository::new()
+            .with_snippets(snippets.clone())
+            .with_snippet_tags(vec!["snippet".to_string(), "note".to_string()]);
+        let untagged = MockSnippetRepository::new()
+            .with_snippets(snippets)
+            .with_snippet_tags(Vec::new());
+
+        // Act
+        let tagged = tagged
+            .fetch_snippets(&SnippetFilter::default())
+            .await
+            .expect("tagged snippets");
+        let untagged = untagged
+            .fetch_snippets(&SnippetFilter::default())
+            .await
+            .expect("all snippets");
+
+        // Assert
+        assert_eq!(tagged.iter().map(|s| s.id).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(untagged.len(), 3);
+    }
+
     #[tokio::test]
     async fn given_healthy_repository_when_health_check_then_returns_ok() {
         // Arrange
@@ -1,10 +1,26 @@
+pub mod bkmr_capabilities;
+pub mod bkmr_daemon_repository;
 pub mod bkmr_repository;
+pub mod caching_repository;
+pub mod circuit_breaker;
+pub mod composite_repository;
+pub mod filesystem_repository;
+pub mod repository_router;
+pub mod snippet_id_cache;
 pub mod snippet_repository;
 
 #[cfg(test)]
 pub mod mock_repository;
 
+pub use bkmr_capabilities::*;
+pub use bkmr_daemon_repository::*;
 pub use bkmr_repository::*;
+pub use caching_repository::*;
+pub use circuit_breaker::*;
+pub use composite_repository::*;
+pub use filesystem_repository::*;
+pub use repository_router::*;
+pub use snippet_id_cache::*;
 pub use snippet_repository::*;
 
 #[cfg(test)]
@@ -0,0 +1,320 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tower_lsp::lsp_types::Url;
+use tracing::{debug, info, instrument, warn};
+
+use crate::domain::{NewSnippet, Snippet, SnippetFilter, TagCount};
+use crate::repositories::{BkmrCapabilities, RepositoryStats, SnippetRepository};
+
+/// Repository of one folder, documents in it or below it are served by it
+struct FolderRoute {
+    /// Folder as configured, the value of [`SnippetFilter::folder`]
+    folder: String,
+    path: PathBuf,
+    repository: Arc<dyn SnippetRepository>,
+}
+
+/// Repository sending each search to the bkmr database of the document's folder
+///
+/// Documents are routed to the longest configured folder containing them,
+/// documents outside all folders to the default repository. Requests by id
+/// reach a folder's database through [`SnippetRepository::folder_repository`]
+/// with the folder the snippet was searched in, on the router itself they go
+/// to the default repository.
+pub struct RepositoryRouter {
+    default: Arc<dyn SnippetRepository>,
+    /// Longest folder first
+    routes: Vec<FolderRoute>,
+}
+
+impl RepositoryRouter {
+    pub fn new(
+        default: Arc<dyn SnippetRepository>,
+        folders: Vec<(String, PathBuf, Arc<dyn SnippetRepository>)>,
+    ) -> Self {
+        let mut routes: Vec<FolderRoute> = folders
+            .into_iter()
+            .map(|(folder, path, repository)| FolderRoute {
+                folder,
+                path,
+                repository,
+            })
+            .collect();
+        routes.sort_by_key(|route| std::cmp::Reverse(route.path.components().count()));
+        Self { default, routes }
+    }
+
+    /// Index of the route serving a search, None for the default repository
+    fn route_index(&self, filter: &SnippetFilter) -> Option<usize> {
+        let folder = filter.folder.as_deref()?;
+        self.routes.iter().position(|route| route.folder == folder)
+    }
+
+    fn repository(&self, index: Option<usize>) -> &Arc<dyn SnippetRepository> {
+        index.map_or(&self.default, |index| &self.routes[index].repository)
+    }
+
+    /// Every repository, the default one first
+    fn all(&self) -> impl Iterator<Item = &Arc<dyn SnippetRepository>> {
+        std::iter::once(&self.default).chain(self.routes.iter().map(|route| &route.repository))
+    }
+}
+
+#[async_trait]
+impl SnippetRepository for RepositoryRouter {
+    #[instrument(skip(self))]
+    async fn fetch_snippets(&self, filter: &SnippetFilter) -> Result<Vec<Snippet>> {
+        self.repository(self.route_index(filter))
+            .fetch_snippets(filter)
+            .await
+    }
+
+    async fn query_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.default.query_snippet_by_id(id).await
+    }
+
+    async fn fetch_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.default.fetch_snippet_by_id(id).await
+    }
+
+    async fn fetch_raw_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.default.fetch_raw_snippet_by_id(id).await
+    }
+
+    async fn create_snippet(&self, snippet: &NewSnippet) -> Result<i32> {
+        self.default.create_snippet(snippet).await
+    }
+
+    async fn open_snippet(&self, id: i32) -> Result<()> {
+        self.default.open_snippet(id).await
+    }
+
+    async fn record_usage(&self, id: i32) -> Result<()> {
+        self.default.record_usage(id).await
+    }
+
+    /// Check every repository, logging each folder's result, healthy if the default one is
+    async fn health_check(&self) -> Result<()> {
+        for route in &self.routes {
+            match route.repository.health_check().await {
+                Ok(()) => info!("bkmr repository of {} is healthy", route.folder),
+                Err(e) => warn!(
+                    "bkmr repository of {} is unavailable: {:#}",
+                    route.folder, e
+                ),
+            }
+        }
+        self.default
+            .health_check()
+            .await
+            .context("check default repository")
+    }
+
+    async fn list_tags(&self) -> Result<Vec<TagCount>> {
+        self.default.list_tags().await
+    }
+
+    async fn version(&self) -> Result<Option<String>> {
+        self.default.version().await
+    }
+
+    fn capabilities(&self) -> Option<BkmrCapabilities> {
+        self.default.capabilities()
+    }
+
+    async fn start(&self) -> Result<()> {
+        let mut result = Ok(());
+        for repository in self.all() {
+            if let Err(e) = repository.start().await {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    async fn shutdown(&self) {
+        for repository in self.all() {
+            repository.shutdown().await;
+        }
+    }
+
+    fn clear_cache(&self) -> usize {
+        self.all().map(|repository| repository.clear_cache()).sum()
+    }
+
+    fn search_complete(&self, filter: &SnippetFilter) -> Option<bool> {
+        self.repository(self.route_index(filter))
+            .search_complete(filter)
+    }
+
+    fn stats(&self) -> RepositoryStats {
+        self.default.stats()
+    }
+
+    fn route(&self, uri: &Url) -> Option<String> {
+        let path = uri.to_file_path().ok()?;
+        let route = self
+            .routes
+            .iter()
+            .find(|route| path.starts_with(&route.path))?;
+        debug!("Routing {} to the repository of {}", uri, route.folder);
+        Some(route.folder.clone())
+    }
+
+    fn folder_repository(&self, folder: &str) -> Option<Arc<dyn SnippetRepository>> {
+        let route = self.routes.iter().find(|route| route.folder == folder);
+        if route.is_none() {
+            warn!(
+                "No repository configured for folder {}, using the default one",
+                folder
+            );
+        }
+        route.map(|route| route.repository.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::{CacheConfig, CachingSnippetRepository, MockSnippetRepository};
+
+    fn snippet(id: i32, title: &str) -> Snippet {
+        Snippet::new(
+            id,
+            title.to_string(),
+            format!("content of {}", title),
+            String::new(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        )
+    }
+
+    fn router(
+        default: Arc<dyn SnippetRepository>,
+        folders: Vec<(&str, Arc<dyn SnippetRepository>)>,
+    ) -> RepositoryRouter {
+        RepositoryRouter::new(
+            default,
+            folders
+                .into_iter()
+                .map(|(folder, repository)| (folder.to_string(), PathBuf::from(folder), repository))
+                .collect(),
+        )
+    }
+
+    async fn titles(router: &RepositoryRouter, uri: &str) -> Vec<String> {
+        let uri = Url::parse(uri).expect("parse URI");
+        let filter = SnippetFilter::default().with_folder(router.route(&uri));
+        router
+            .fetch_snippets(&filter)
+            .await
+            .expect("fetch snippets")
+            .into_iter()
+            .map(|snippet| snippet.title)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn given_nested_folders_when_routing_then_longest_containing_folder_wins() {
+        // Arrange
+        let router = router(
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet(1, "default")])),
+            vec![
+                (
+                    "/work",
+                    Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet(1, "work")])),
+                ),
+                (
+                    "/work/clientA",
+                    Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet(1, "a")])),
+                ),
+            ],
+        );
+
+        // Act
+        let client_a = titles(&router, "file:///work/clientA/src/main.rs").await;
+        let work = titles(&router, "file:///work/clientAB/main.rs").await;
+
+        // Assert
+        assert_eq!(client_a, vec!["a"]);
+        assert_eq!(work, vec!["work"]);
+    }
+
+    #[tokio::test]
+    async fn given_document_outside_all_folders_when_routing_then_uses_default() {
+        // Arrange
+        let router = router(
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet(1, "default")])),
+            vec![(
+                "/work/clientA",
+                Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet(1, "a")])),
+            )],
+        );
+
+        // Act
+        let outside = titles(&router, "file:///home/me/notes.md").await;
+        let untitled = titles(&router, "untitled:Untitled-1").await;
+
+        // Assert
+        assert_eq!(outside, vec!["default"]);
+        assert_eq!(untitled, vec!["default"]);
+    }
+
+    #[tokio::test]
+    async fn given_id_in_two_folders_when_recording_usage_then_reaches_the_named_folder() {
+        // Arrange
+        let default = Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet(5, "d")]));
+        let client_a = Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet(5, "a")]));
+        let client_b = Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet(5, "b")]));
+        let router = router(
+            default.clone(),
+            vec![
+                ("/work/clientA", client_a.clone()),
+                ("/work/clientB", client_b.clone()),
+            ],
+        );
+        // The latest search of id 5 was client B's
+        titles(&router, "file:///work/clientA/main.rs").await;
+        titles(&router, "file:///work/clientB/main.rs").await;
+
+        // Act
+        router
+            .folder_repository("/work/clientA")
+            .expect("client A repository")
+            .record_usage(5)
+            .await
+            .expect("record usage");
+        router.record_usage(5).await.expect("record usage");
+        let unknown = router.folder_repository("/work/clientC");
+
+        // Assert
+        assert_eq!(client_a.used_ids(), vec![5]);
+        assert_eq!(client_b.used_ids(), Vec::<i32>::new());
+        assert_eq!(default.used_ids(), vec![5]);
+        assert!(unknown.is_none());
+    }
+
+    #[tokio::test]
+    async fn given_cached_repository_per_folder_when_searching_then_caches_stay_separate() {
+        // Arrange
+        let cache = |title: &str| -> Arc<dyn SnippetRepository> {
+            Arc::new(CachingSnippetRepository::new(
+                Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet(1, title)])),
+                CacheConfig::default(),
+            ))
+        };
+        let router = router(cache("default"), vec![("/work/clientA", cache("a"))]);
+
+        // Act
+        let first_a = titles(&router, "file:///work/clientA/main.rs").await;
+        let default = titles(&router, "file:///work/clientB/main.rs").await;
+        let second_a = titles(&router, "file:///work/clientA/lib.rs").await;
+
+        // Assert
+        assert_eq!(first_a, vec!["a"]);
+        assert_eq!(default, vec!["default"]);
+        assert_eq!(second_a, vec!["a"]);
+        assert_eq!(router.clear_cache(), 2);
+    }
+}
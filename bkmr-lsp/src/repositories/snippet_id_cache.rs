@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::domain::{ContentLimit, Snippet};
+use crate::sync;
+
+/// Default number of snippets kept by id
+pub const DEFAULT_ID_CACHE_CAPACITY: usize = 200;
+
+/// A snippet as last returned by the repository
+#[derive(Debug, Clone)]
+struct IdCacheEntry {
+    snippet: Snippet,
+    fetched_at: Instant,
+    /// Value of the use counter when the entry was last read or written
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct IdCacheState {
+    entries: HashMap<i32, IdCacheEntry>,
+    /// Increases with every read and write, orders entries by recency
+    uses: u64,
+}
+
+/// Least recently used snippets by id, filled from search results
+///
+/// Commands acting on a completion shortly after it was offered, like
+/// inserting a snippet by id, find it here instead of querying bkmr again.
+/// Entries older than the TTL are stale and fetched again.
+#[derive(Debug)]
+pub struct SnippetIdCache {
+    capacity: usize,
+    ttl: Duration,
+    state: Mutex<IdCacheState>,
+}
+
+impl SnippetIdCache {
+    /// Cache keeping up to `capacity` snippets for `ttl`, 0 for either disables it
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            state: Mutex::new(IdCacheState::default()),
+        }
+    }
+
+    /// Fresh snippet with the id, None if unknown or stale
+    pub fn get(&self, id: i32) -> Option<Snippet> {
+        let mut state = sync::lock(&self.state, "id cache");
+        state.uses += 1;
+        let uses = state.uses;
+        let entry = state.entries.get_mut(&id)?;
+        if entry.fetched_at.elapsed() >= self.ttl {
+            debug!("Cached snippet {} is stale", id);
+            state.entries.remove(&id);
+            return None;
+        }
+        entry.last_used = uses;
+        Some(entry.snippet.clone())
+    }
+
+    /// Remember snippets fetched at the given time, evicting the least recently used
+    ///
+    /// Stubs of oversized snippets are left out, their real content has to be fetched.
+    pub fn insert_all<'a>(
+        &self,
+        snippets: impl IntoIterator<Item = &'a Snippet>,
+        fetched_at: Instant,
+    ) {
+        if self.capacity == 0 || self.ttl.is_zero() {
+            return;
+        }
+        let mut state = sync::lock(&self.state, "id cache");
+        for snippet in snippets {
+            if ContentLimit::is_stub(snippet) {
+                continue;
+            }
+            state.uses += 1;
+            let entry = IdCacheEntry {
+                snippet: snippet.clone(),
+                fetched_at,
+                last_used: state.uses,
+            };
+            state.entries.insert(snippet.id, entry);
+            if state.entries.len() > self.capacity {
+                Self::evict_least_recently_used(&mut state);
+            }
+        }
+    }
+
+    /// Remember one snippet fetched just now
+    pub fn insert(&self, snippet: &Snippet) {
+        self.insert_all([snippet], Instant::now());
+    }
+
+    /// Forget all snippets, returning how many were cached
+    pub fn clear(&self) -> usize {
+        std::mem::take(&mut sync::lock(&self.state, "id cache").entries).len()
+    }
+
+    /// Number of cached snippets, stale ones included
+    pub fn len(&self) -> usize {
+        sync::lock(&self.state, "id cache").entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn evict_least_recently_used(state: &mut IdCacheState) {
+        let oldest = state
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(id, _)| *id);
+        if let Some(id) = oldest {
+            state.entries.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn snippet(id: i32) -> Snippet {
+        Snippet::new(
+            id,
+            format!("snippet {}", id),
+            format!("content {}", id),
+            String::new(),
+            vec!["_snip_".to_string()],
+        )
+    }
+
+    #[test]
+    fn given_inserted_snippets_when_getting_then_hits_known_ids_only() {
+        // Arrange
+        let cache = SnippetIdCache::new(10, Duration::from_secs(30));
+        cache.insert_all(&[snippet(1), snippet(2)], Instant::now());
+
+        // Act
+        let hit = cache.get(2);
+        let miss = cache.get(3);
+
+        // Assert
+        assert_eq!(
+            hit.map(|snippet| snippet.content),
+            Some("content 2".to_string())
+        );
+        assert!(miss.is_none());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn given_entry_older_than_ttl_when_getting_then_drops_it() {
+        // Arrange
+        let cache = SnippetIdCache::new(10, Duration::from_secs(30));
+        let long_ago = Instant::now() - Duration::from_secs(31);
+        cache.insert_all(&[snippet(1)], long_ago);
+        cache.insert_all(&[snippet(2)], Instant::now());
+
+        // Act
+        let stale = cache.get(1);
+        let fresh = cache.get(2);
+
+        // Assert
+        assert!(stale.is_none());
+        assert!(fresh.is_some());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn given_full_cache_when_inserting_then_evicts_least_recently_used() {
+        // Arrange
+        let cache = SnippetIdCache::new(2, Duration::from_secs(30));
+        cache.insert_all(&[snippet(1), snippet(2)], Instant::now());
+        cache.get(1);
+
+        // Act
+        cache.insert(&snippet(3));
+
+        // Assert
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(3).is_some());
+    }
+
+    #[test]
+    fn given_stub_or_disabled_cache_when_inserting_then_keeps_nothing() {
+        // Arrange
+        let cache = SnippetIdCache::new(10, Duration::from_secs(30));
+        let disabled = SnippetIdCache::new(0, Duration::from_secs(30));
+        let mut stub = snippet(4);
+        stub.content =
+            "[bkmr snippet 4: content too large (2.0 MB), use bkmr.insertSnippetById to insert]"
+                .to_string();
+
+        // Act
+        cache.insert(&stub);
+        disabled.insert(&snippet(1));
+
+        // Assert
+        assert!(cache.is_empty());
+        assert!(disabled.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn given_two_tasks_when_sharing_cache_then_both_see_all_snippets() {
+        // Arrange
+        let cache = Arc::new(SnippetIdCache::new(100, Duration::from_secs(30)));
+        let tasks: Vec<_> = [0, 50]
+            .into_iter()
+            .map(|first| {
+                let cache = cache.clone();
+                tokio::spawn(async move {
+                    for id in first..first + 50 {
+                        cache.insert(&snippet(id));
+                        tokio::task::yield_now().await;
+                        assert!(cache.get(id).is_some());
+                    }
+                })
+            })
+            .collect();
+
+        // Act
+        for task in tasks {
+            task.await.expect("cache task");
+        }
+
+        // Assert
+        assert_eq!(cache.len(), 100);
+        assert!((0..100).all(|id| cache.get(id).is_some()));
+    }
+}
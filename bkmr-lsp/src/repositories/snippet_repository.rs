@@ -1,7 +1,19 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
+use serde::Serialize;
+use std::str::FromStr;
+use std::sync::Arc;
+use tower_lsp::lsp_types::Url;
+use tracing::debug;
 
-use crate::domain::{Snippet, SnippetFilter};
+use crate::domain::{
+    ContentLimit, LanguageRegistry, NewSnippet, Snippet, SnippetFilter, TagCount,
+    default_snippet_tags,
+};
+use crate::repositories::{BkmrCapabilities, DEFAULT_ID_CACHE_CAPACITY, SnippetIdCache};
+
+/// Number of snippets searched to count tags when the source cannot list them itself
+pub const TAG_SEARCH_LIMIT: usize = 1000;
 
 /// Repository trait for snippet retrieval operations
 #[async_trait]
@@ -9,8 +21,184 @@ pub trait SnippetRepository: Send + Sync {
     /// Fetch snippets based on the provided filter
     async fn fetch_snippets(&self, filter: &SnippetFilter) -> Result<Vec<Snippet>>;
 
+    /// Query the source for a single snippet by its bkmr id, None if it does not exist
+    async fn query_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>>;
+
+    /// Fetch a single snippet by its bkmr id, None if it does not exist
+    ///
+    /// Snippets returned by a recent search come from the id cache, others
+    /// are queried and cached.
+    async fn fetch_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        let cache = self.id_cache();
+        if let Some(snippet) = cache.as_ref().and_then(|cache| cache.get(id)) {
+            debug!("Snippet {} found in id cache", id);
+            return Ok(Some(snippet));
+        }
+        let snippet = self.query_snippet_by_id(id).await?;
+        if let (Some(cache), Some(snippet)) = (cache, &snippet) {
+            cache.insert(snippet);
+        }
+        Ok(snippet)
+    }
+
+    /// Recently returned snippets by id, shared with decorators of this repository
+    fn id_cache(&self) -> Option<Arc<SnippetIdCache>> {
+        None
+    }
+
+    /// Fetch a single snippet with its template left as stored, even with interpolation enabled
+    ///
+    /// Sources that never interpolate return the same as `fetch_snippet_by_id`.
+    async fn fetch_raw_snippet_by_id(&self, id: i32) -> Result<Option<Snippet>> {
+        self.fetch_snippet_by_id(id).await
+    }
+
+    /// Store a new snippet and return the id assigned to it
+    async fn create_snippet(&self, snippet: &NewSnippet) -> Result<i32>;
+
+    /// Open a snippet in bkmr for viewing or editing
+    async fn open_snippet(&self, id: i32) -> Result<()>;
+
+    /// Record that a snippet was inserted, raising its access count in bkmr
+    async fn record_usage(&self, id: i32) -> Result<()>;
+
     /// Check if the repository is available and properly configured
     async fn health_check(&self) -> Result<()>;
+
+    /// Tags with the number of snippets carrying them, most used first
+    ///
+    /// Counted from a broad search unless the source can list its tags.
+    async fn list_tags(&self) -> Result<Vec<TagCount>> {
+        let snippets = self
+            .fetch_snippets(&SnippetFilter::new(None, None, TAG_SEARCH_LIMIT))
+            .await
+            .context("search snippets to count their tags")?;
+        Ok(TagCount::count(
+            snippets.iter().flat_map(|snippet| snippet.tags.iter()),
+        ))
+    }
+
+    /// Version of the snippet source, None when it has no version
+    async fn version(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Start long-running helpers ahead of the first search, like the bkmr daemon
+    async fn start(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Kill bkmr processes still running and refuse to start new ones, at server shutdown
+    async fn shutdown(&self) {}
+
+    /// Drop cached search results, returning how many were evicted
+    fn clear_cache(&self) -> usize {
+        0
+    }
+
+    /// Whether the last search with the filter found every match, None if the repository cannot tell
+    ///
+    /// Without it a search returning as many snippets as requested is taken
+    /// to have more matches.
+    fn search_complete(&self, _filter: &SnippetFilter) -> Option<bool> {
+        None
+    }
+
+    /// Flags and version of the bkmr searched, None for other snippet sources
+    fn capabilities(&self) -> Option<BkmrCapabilities> {
+        None
+    }
+
+    /// Diagnostics about recent fetches, reported by the bkmr/status request
+    fn stats(&self) -> RepositoryStats {
+        RepositoryStats::default()
+    }
+
+    /// Folder whose own repository serves a document, None for this repository
+    ///
+    /// Searches for the document set it as [`SnippetFilter::folder`].
+    fn route(&self, _uri: &Url) -> Option<String> {
+        None
+    }
+
+    /// Repository of a folder [`SnippetRepository::route`] returned, None for this repository
+    ///
+    /// Requests by id take the folder their snippet was searched in, ids of
+    /// different databases overlap.
+    fn folder_repository(&self, _folder: &str) -> Option<Arc<dyn SnippetRepository>> {
+        None
+    }
+}
+
+/// Recent repository activity, for diagnosing empty completions
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepositoryStats {
+    /// FTS query of the last search sent to bkmr
+    pub last_query: Option<String>,
+    /// Number of snippets the last search returned
+    pub last_fetch_count: Option<usize>,
+    /// Result cache counters, None without caching
+    pub cache: Option<CacheStats>,
+}
+
+/// Hit and miss counters of the result cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// How bkmr is searched for the snippets of a language
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryStrategy {
+    /// One search for language-specific OR universal snippets
+    #[default]
+    Combined,
+    /// Concurrent searches for language-specific and universal snippets, merged
+    Split,
+}
+
+impl FromStr for QueryStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "combined" => Ok(Self::Combined),
+            "split" => Ok(Self::Split),
+            other => Err(format!(
+                "unknown query strategy '{}', expected combined or split",
+                other
+            )),
+        }
+    }
+}
+
+/// How bkmr is run for searches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BkmrBackend {
+    /// One bkmr process per search
+    #[default]
+    Cli,
+    /// A long-running `bkmr serve --stdio`, the CLI when bkmr has no daemon mode
+    Daemon,
+}
+
+impl FromStr for BkmrBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "cli" => Ok(Self::Cli),
+            "daemon" => Ok(Self::Daemon),
+            other => Err(format!(
+                "unknown backend '{}', expected cli or daemon",
+                other
+            )),
+        }
+    }
 }
 
 /// Configuration for snippet repositories
@@ -20,6 +208,36 @@ pub struct RepositoryConfig {
     pub max_results: usize,
     pub timeout_seconds: u64,
     pub enable_interpolation: bool,
+    /// Open snippets with `bkmr edit` instead of `bkmr open`
+    pub open_with_edit: bool,
+    /// bkmr database passed via BKMR_DB_URL, bkmr's default when unset
+    pub db_path: Option<String>,
+    /// Tags marking bookmarks as snippets, any of them matches, none means all bookmarks
+    pub snippet_tags: Vec<String>,
+    /// bkmr subcommand recording snippet usage, the snippet id is appended
+    pub usage_command: Vec<String>,
+    /// Whether language-specific and universal snippets are searched together or apart
+    pub query_strategy: QueryStrategy,
+    /// Retries of a search failing transiently, e.g. on a locked database
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each further one
+    pub retry_backoff: std::time::Duration,
+    /// Consecutive failed searches after which bkmr is not called for a cooldown, 0 never
+    pub breaker_threshold: u32,
+    /// How long searches return no snippets once the breaker opened
+    pub breaker_cooldown: std::time::Duration,
+    /// Match the typed prefix against descriptions too, where aliases are stored
+    pub alias_search: bool,
+    /// Searched snippets with larger content are dropped or stubbed
+    pub content_limit: ContentLimit,
+    /// Snippets kept by id after a search, 0 disables the id cache
+    pub id_cache_capacity: usize,
+    /// How long a snippet kept by id is used before it is queried again
+    pub id_cache_ttl: std::time::Duration,
+    /// bkmr flags assumed until the installed bkmr has been probed
+    pub capabilities: BkmrCapabilities,
+    /// Languages whose tags the searches match
+    pub language_registry: Arc<LanguageRegistry>,
 }
 
 impl Default for RepositoryConfig {
@@ -29,6 +247,21 @@ impl Default for RepositoryConfig {
             max_results: 50,
             timeout_seconds: 10,
             enable_interpolation: true,
+            open_with_edit: false,
+            db_path: None,
+            snippet_tags: default_snippet_tags(),
+            usage_command: vec!["touch".to_string()],
+            query_strategy: QueryStrategy::default(),
+            retries: 2,
+            retry_backoff: std::time::Duration::from_millis(50),
+            breaker_threshold: 3,
+            breaker_cooldown: std::time::Duration::from_secs(30),
+            alias_search: false,
+            content_limit: ContentLimit::default(),
+            id_cache_capacity: DEFAULT_ID_CACHE_CAPACITY,
+            id_cache_ttl: std::time::Duration::from_secs(30),
+            capabilities: BkmrCapabilities::default(),
+            language_registry: Arc::new(LanguageRegistry::default()),
         }
     }
 }
@@ -1,136 +1,2478 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
-use tower_lsp::lsp_types::{Position, Range, TextEdit, Url, WorkspaceEdit};
-use tracing::{debug, instrument};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tower_lsp::lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, Command, Position, Range, TextEdit, Url,
+    WorkspaceEdit,
+};
+use tracing::{debug, instrument, warn};
 
-use crate::domain::LanguageRegistry;
+use crate::backend::BkmrConfig;
+use crate::domain::{
+    ExportFormat, LanguageRegistry, NewSnippet, PathSeparator, PositionEncoding, Snippet,
+    SnippetFilter, TranslationPolicy, UNTITLED_SCHEME, default_snippet_tags, join_path_components,
+    normalize_path, render_relative_path, render_snippet_export, strip_placeholders,
+    untitled_file_name, uri_path_components,
+};
+use crate::repositories::{BkmrCapabilities, SnippetRepository};
+use crate::services::{
+    CompletionService, ContextVariables, DocumentService, LanguageTranslator, SnippetPresenter,
+};
+
+/// Command inserting a comment with the file's relative path
+pub const INSERT_FILEPATH_COMMENT: &str = "bkmr.insertFilepathComment";
+/// Command opening the source snippet of a completion in bkmr
+pub const OPEN_SNIPPET: &str = "bkmr.openSnippet";
+/// Command saving the current selection as a new bkmr snippet
+pub const ADD_SNIPPET: &str = "bkmr.addSnippet";
+/// Command inserting a snippet by id, meant for keybindings
+pub const INSERT_SNIPPET_BY_ID: &str = "bkmr.insertSnippetById";
+/// Command inserting the uninterpolated template of a snippet verbatim
+pub const INSERT_RAW: &str = "bkmr.insertRaw";
+/// Command recording that a completed snippet was inserted, available with --track-usage
+pub const RECORD_USAGE: &str = "bkmr.recordUsage";
+/// Command restricting completions to snippets with a tag, null clears the restriction
+pub const SET_TAG_FILTER: &str = "bkmr.setTagFilter";
+/// Command dropping cached search results so the next completion asks bkmr again
+pub const CLEAR_CACHE: &str = "bkmr.clearCache";
+/// Command re-applying the initialization options and checking bkmr again
+pub const RELOAD_CONFIG: &str = "bkmr.reloadConfig";
+/// Command writing the snippets of a language to a JSON or VS Code snippets file
+pub const EXPORT_SNIPPETS: &str = "bkmr.exportSnippets";
+
+/// Most snippets fetched for an export, far beyond any completion limit
+const EXPORT_MAX_SNIPPETS: usize = 10_000;
+
+/// Longest title proposed for a snippet saved from a selection
+const MAX_SELECTION_TITLE_CHARS: usize = 60;
+/// Leading lines searched for an existing filepath comment
+const FILEPATH_COMMENT_SEARCH_LINES: usize = 3;
+/// Byte order mark some editors keep at the start of a file
+const BYTE_ORDER_MARK: char = '\u{feff}';
+
+/// Supported LSP commands with their parsed arguments
+#[derive(Debug, Clone, PartialEq)]
+pub enum BkmrCommand {
+    InsertFilepathComment {
+        uri: Url,
+        position: Option<Position>,
+    },
+    /// Snippet ids name the folder whose repository they came from, None for the default one
+    OpenSnippet {
+        id: i32,
+        folder: Option<String>,
+    },
+    AddSnippet {
+        uri: Url,
+        range: Range,
+        title: String,
+        tags: Vec<String>,
+    },
+    /// Insert at each target, empty ranges for cursors and ranges for selections
+    InsertSnippetById {
+        id: i32,
+        folder: Option<String>,
+        uri: Url,
+        targets: Vec<Range>,
+    },
+    InsertRaw {
+        id: i32,
+        folder: Option<String>,
+        uri: Url,
+        position: Position,
+    },
+    RecordUsage {
+        id: i32,
+        folder: Option<String>,
+    },
+    /// Tag filter for one document, or for all documents without a URI
+    SetTagFilter {
+        tag: Option<String>,
+        uri: Option<String>,
+    },
+    ClearCache,
+    ReloadConfig,
+    ExportSnippets {
+        language: String,
+        format: ExportFormat,
+        path: PathBuf,
+        overwrite: bool,
+    },
+}
+
+/// Outcome of the reloadConfig command
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthSummary {
+    pub healthy: bool,
+    /// None when bkmr is unavailable or reports no version
+    pub bkmr_version: Option<String>,
+    /// Flags of the installed bkmr, left out for other snippet sources
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bkmr_capabilities: Option<BkmrCapabilities>,
+}
+
+/// Outcome of the exportSnippets command
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportSummary {
+    pub count: usize,
+    pub path: String,
+}
+
+/// Outcome of the insertFilepathComment command
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilepathComment {
+    /// Edit inserting the comment
+    Insert(WorkspaceEdit),
+    /// One of the first lines already names the file's path, nothing to insert
+    AlreadyPresent,
+    /// The document was never saved and untitled buffers are left without comment
+    NotSaved,
+}
+
+/// Command argument in a form the command does not accept
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidArgument {
+    /// Argument field at fault, `arguments` when the whole argument is
+    pub field: String,
+    pub message: String,
+}
+
+impl InvalidArgument {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for InvalidArgument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for InvalidArgument {}
+
+/// Fields of a command's argument object, each deserialized on its own so errors name it
+struct ArgumentObject(serde_json::Map<String, Value>);
+
+impl ArgumentObject {
+    /// The first argument of a command, which must be an object shaped like `expected`
+    fn first(command: &str, arguments: &[Value], expected: &str) -> Result<Self, InvalidArgument> {
+        match arguments.first() {
+            Some(Value::Object(fields)) => Ok(Self(fields.clone())),
+            _ => Err(InvalidArgument::new(
+                "arguments",
+                format!("{} expects a {} argument", command, expected),
+            )),
+        }
+    }
+
+    fn required<T: DeserializeOwned>(&self, name: &str) -> Result<T, InvalidArgument> {
+        self.optional(name)?
+            .ok_or_else(|| InvalidArgument::new(name, "missing"))
+    }
+
+    /// Missing fields and null both count as absent
+    fn optional<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, InvalidArgument> {
+        match self.0.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => T::deserialize(value)
+                .map(Some)
+                .map_err(|e| InvalidArgument::new(name, e.to_string())),
+        }
+    }
+
+    fn snippet_id(&self) -> Result<i32, InvalidArgument> {
+        let id = self
+            .0
+            .get("id")
+            .ok_or_else(|| InvalidArgument::new("id", "missing"))?;
+        CommandService::parse_snippet_id(id).map_err(|e| InvalidArgument::new("id", e.to_string()))
+    }
+
+    /// Folder of the snippet, given as `folder` or inside the completion item data passed as `id`
+    fn snippet_folder(&self) -> Result<Option<String>, InvalidArgument> {
+        match self.optional("folder")? {
+            Some(folder) => Ok(Some(folder)),
+            None => match self.0.get("id") {
+                Some(Value::Object(data)) => ArgumentObject(data.clone()).optional("folder"),
+                _ => Ok(None),
+            },
+        }
+    }
+}
+
+/// Arguments of the insertFilepathComment command
+///
+/// Either the bare document URI or an object `{uri, position?}`.
+#[derive(Debug, Clone, PartialEq)]
+struct InsertFilepathCommentArguments {
+    uri: Url,
+    position: Option<Position>,
+}
+
+impl InsertFilepathCommentArguments {
+    fn parse(command: &str, arguments: &[Value]) -> Result<Self, InvalidArgument> {
+        match arguments.first() {
+            Some(Value::String(uri)) => Ok(Self {
+                uri: Url::parse(uri).map_err(|e| InvalidArgument::new("uri", e.to_string()))?,
+                position: None,
+            }),
+            _ => {
+                let object =
+                    ArgumentObject::first(command, arguments, "file URI or {uri, position}")?;
+                Ok(Self {
+                    uri: object.required("uri")?,
+                    position: object.optional("position")?,
+                })
+            }
+        }
+    }
+}
+
+/// Arguments of the addSnippet command
+#[derive(Debug, Clone, PartialEq)]
+struct AddSnippetArguments {
+    uri: Url,
+    range: Range,
+    title: String,
+    tags: Vec<String>,
+}
+
+impl AddSnippetArguments {
+    fn parse(command: &str, arguments: &[Value]) -> Result<Self, InvalidArgument> {
+        let object = ArgumentObject::first(command, arguments, "{uri, range, title}")?;
+        Ok(Self {
+            uri: object.required("uri")?,
+            range: object.required("range")?,
+            title: object.required("title")?,
+            tags: object.optional("tags")?.unwrap_or_default(),
+        })
+    }
+}
+
+/// Arguments of the exportSnippets command
+#[derive(Debug, Clone, PartialEq)]
+struct ExportSnippetsArguments {
+    language: String,
+    format: ExportFormat,
+    path: PathBuf,
+    overwrite: bool,
+}
+
+impl ExportSnippetsArguments {
+    fn parse(command: &str, arguments: &[Value]) -> Result<Self, InvalidArgument> {
+        let object = ArgumentObject::first(command, arguments, "{language, format, path}")?;
+        Ok(Self {
+            language: object.required("language")?,
+            format: object.optional("format")?.unwrap_or_default(),
+            path: object.required("path")?,
+            overwrite: object.optional("overwrite")?.unwrap_or_default(),
+        })
+    }
+}
+
+/// Arguments of the insertSnippetById command
+///
+/// Either a single position or, for multiple cursors, a list of positions
+/// and ranges.
+#[derive(Debug, Clone, PartialEq)]
+struct InsertSnippetByIdArguments {
+    id: i32,
+    folder: Option<String>,
+    uri: Url,
+    targets: Vec<Range>,
+}
+
+impl InsertSnippetByIdArguments {
+    fn parse(command: &str, arguments: &[Value]) -> Result<Self, InvalidArgument> {
+        let object = ArgumentObject::first(command, arguments, "{id, uri, position}")?;
+        let position: Option<Position> = object.optional("position")?;
+        let positions: Vec<InsertTarget> = match object.optional("positions")? {
+            Some(positions) => positions,
+            None => object.optional("ranges")?.unwrap_or_default(),
+        };
+        let targets: Vec<Range> = position
+            .map(|position| Range::new(position, position))
+            .into_iter()
+            .chain(positions.into_iter().map(Range::from))
+            .collect();
+        if targets.is_empty() {
+            return Err(InvalidArgument::new(
+                "position",
+                format!("{} expects a position or a list of positions", command),
+            ));
+        }
+        Ok(Self {
+            id: object.snippet_id()?,
+            folder: object.snippet_folder()?,
+            uri: object.required("uri")?,
+            targets,
+        })
+    }
+}
+
+/// Arguments of the insertRaw command
+#[derive(Debug, Clone, PartialEq)]
+struct InsertRawArguments {
+    id: i32,
+    folder: Option<String>,
+    uri: Url,
+    position: Position,
+}
+
+impl InsertRawArguments {
+    fn parse(command: &str, arguments: &[Value]) -> Result<Self, InvalidArgument> {
+        let object = ArgumentObject::first(command, arguments, "{id, uri, position}")?;
+        Ok(Self {
+            id: object.snippet_id()?,
+            folder: object.snippet_folder()?,
+            uri: object.required("uri")?,
+            position: object.required("position")?,
+        })
+    }
+}
+
+/// One insertSnippetById target, a cursor position or a selection replaced by the snippet
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum InsertTarget {
+    Range(Range),
+    Position(Position),
+}
+
+impl From<InsertTarget> for Range {
+    fn from(target: InsertTarget) -> Self {
+        match target {
+            InsertTarget::Range(range) => range,
+            InsertTarget::Position(position) => Range::new(position, position),
+        }
+    }
+}
 
 /// Service for handling LSP command execution
-pub struct CommandService;
+pub struct CommandService {
+    repository: Arc<dyn SnippetRepository>,
+    document_service: Arc<DocumentService>,
+    /// Tags marking snippets, the first one is added to new snippets
+    snippet_tags: Vec<String>,
+    /// Whether inserted snippets are reported back to bkmr
+    track_usage: bool,
+    /// Languages whose snippets keep `$1` and `$(...)` as literal text
+    escape_all_dollars_for: Vec<String>,
+    /// Separator of relative paths in filepath comments
+    path_separator: PathSeparator,
+    /// Author substituted for `{{ author }}` in inserted snippets
+    author: Option<String>,
+    /// Which universal snippets are translated to the document language
+    translation: TranslationPolicy,
+    /// Whether unsaved buffers get a filepath comment with a synthesized name
+    untitled_filepath_comment: bool,
+    /// Languages of comment syntax, untitled file names and snippet translation
+    language_registry: Arc<LanguageRegistry>,
+}
+
+impl std::fmt::Debug for CommandService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandService")
+            .field("repository", &"<SnippetRepository>")
+            .finish()
+    }
+}
+
+impl CommandService {
+    pub fn new(
+        repository: Arc<dyn SnippetRepository>,
+        document_service: Arc<DocumentService>,
+    ) -> Self {
+        Self {
+            repository,
+            document_service,
+            snippet_tags: default_snippet_tags(),
+            track_usage: false,
+            escape_all_dollars_for: BkmrConfig::default().escape_all_dollars_for,
+            path_separator: PathSeparator::default(),
+            author: None,
+            translation: TranslationPolicy::default(),
+            untitled_filepath_comment: true,
+            language_registry: Arc::new(LanguageRegistry::default()),
+        }
+    }
+
+    pub fn with_snippet_tags(mut self, snippet_tags: Vec<String>) -> Self {
+        self.snippet_tags = snippet_tags;
+        self
+    }
+
+    pub fn with_track_usage(mut self, track_usage: bool) -> Self {
+        self.track_usage = track_usage;
+        self
+    }
+
+    pub fn with_escape_all_dollars_for(mut self, languages: Vec<String>) -> Self {
+        self.escape_all_dollars_for = languages;
+        self
+    }
+
+    pub fn with_native_path_separators(mut self, native: bool) -> Self {
+        self.path_separator = PathSeparator::from_native(native);
+        self
+    }
+
+    pub fn with_author(mut self, author: Option<String>) -> Self {
+        self.author = author;
+        self
+    }
+
+    pub fn with_translation(mut self, translation: TranslationPolicy) -> Self {
+        self.translation = translation;
+        self
+    }
+
+    pub fn with_untitled_filepath_comment(mut self, untitled_filepath_comment: bool) -> Self {
+        self.untitled_filepath_comment = untitled_filepath_comment;
+        self
+    }
+
+    pub fn with_language_registry(mut self, language_registry: Arc<LanguageRegistry>) -> Self {
+        self.language_registry = language_registry;
+        self
+    }
+
+    /// Names of all commands advertised in the execute command capability
+    pub fn command_names(&self) -> Vec<String> {
+        let mut names = vec![
+            INSERT_FILEPATH_COMMENT.to_string(),
+            OPEN_SNIPPET.to_string(),
+            ADD_SNIPPET.to_string(),
+            INSERT_SNIPPET_BY_ID.to_string(),
+            INSERT_RAW.to_string(),
+            SET_TAG_FILTER.to_string(),
+            CLEAR_CACHE.to_string(),
+            RELOAD_CONFIG.to_string(),
+            EXPORT_SNIPPETS.to_string(),
+        ];
+        if self.track_usage {
+            names.push(RECORD_USAGE.to_string());
+        }
+        names
+    }
+
+    /// Parse an execute command request, returning None for unknown commands
+    pub fn parse_command(
+        command: &str,
+        arguments: &[Value],
+    ) -> Result<Option<BkmrCommand>, InvalidArgument> {
+        let parsed = match command {
+            INSERT_FILEPATH_COMMENT => {
+                let arguments = InsertFilepathCommentArguments::parse(command, arguments)?;
+                BkmrCommand::InsertFilepathComment {
+                    uri: arguments.uri,
+                    position: arguments.position,
+                }
+            }
+            OPEN_SNIPPET => {
+                let (id, folder) = Self::snippet_id_argument(command, arguments)?;
+                BkmrCommand::OpenSnippet { id, folder }
+            }
+            ADD_SNIPPET => {
+                let arguments = AddSnippetArguments::parse(command, arguments)?;
+                BkmrCommand::AddSnippet {
+                    uri: arguments.uri,
+                    range: arguments.range,
+                    title: arguments.title,
+                    tags: arguments.tags,
+                }
+            }
+            INSERT_SNIPPET_BY_ID => {
+                let arguments = InsertSnippetByIdArguments::parse(command, arguments)?;
+                BkmrCommand::InsertSnippetById {
+                    id: arguments.id,
+                    folder: arguments.folder,
+                    uri: arguments.uri,
+                    targets: arguments.targets,
+                }
+            }
+            INSERT_RAW => {
+                let arguments = InsertRawArguments::parse(command, arguments)?;
+                BkmrCommand::InsertRaw {
+                    id: arguments.id,
+                    folder: arguments.folder,
+                    uri: arguments.uri,
+                    position: arguments.position,
+                }
+            }
+            RECORD_USAGE => {
+                let (id, folder) = Self::snippet_id_argument(command, arguments)?;
+                BkmrCommand::RecordUsage { id, folder }
+            }
+            SET_TAG_FILTER => {
+                let tag = match arguments.first() {
+                    Some(Value::String(tag)) => Some(tag.clone()),
+                    Some(Value::Null) => None,
+                    _ => {
+                        return Err(InvalidArgument::new(
+                            "tag",
+                            format!(
+                                "{} expects a tag or null argument, optionally followed by a document URI",
+                                command
+                            ),
+                        ));
+                    }
+                };
+                let uri = match arguments.get(1) {
+                    Some(Value::String(uri)) => Some(uri.clone()),
+                    None | Some(Value::Null) => None,
+                    Some(other) => {
+                        return Err(InvalidArgument::new(
+                            "uri",
+                            format!("Unsupported document URI argument: {}", other),
+                        ));
+                    }
+                };
+                BkmrCommand::SetTagFilter { tag, uri }
+            }
+            CLEAR_CACHE => BkmrCommand::ClearCache,
+            RELOAD_CONFIG => BkmrCommand::ReloadConfig,
+            EXPORT_SNIPPETS => {
+                let arguments = ExportSnippetsArguments::parse(command, arguments)?;
+                BkmrCommand::ExportSnippets {
+                    language: arguments.language,
+                    format: arguments.format,
+                    path: arguments.path,
+                    overwrite: arguments.overwrite,
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(parsed))
+    }
+
+    /// Snippet id passed as the first argument of a command, with the folder it came from
+    ///
+    /// The folder is the second argument, or part of the completion item data
+    /// passed as id.
+    fn snippet_id_argument(
+        command: &str,
+        arguments: &[Value],
+    ) -> Result<(i32, Option<String>), InvalidArgument> {
+        let argument = arguments.first().ok_or_else(|| {
+            InvalidArgument::new("id", format!("{} expects a snippet id argument", command))
+        })?;
+        let id = Self::parse_snippet_id(argument)
+            .map_err(|e| InvalidArgument::new("id", e.to_string()))?;
+        let folder = match (argument, arguments.get(1)) {
+            (_, Some(Value::String(folder))) => Some(folder.clone()),
+            (_, Some(other)) if !other.is_null() => {
+                return Err(InvalidArgument::new(
+                    "folder",
+                    format!("Unsupported folder argument: {}", other),
+                ));
+            }
+            (Value::Object(data), _) => ArgumentObject(data.clone()).optional("folder")?,
+            _ => None,
+        };
+        Ok((id, folder))
+    }
+
+    /// Repository of the folder a snippet came from, the service's own without one
+    fn folder_repository(&self, folder: Option<&str>) -> Arc<dyn SnippetRepository> {
+        folder
+            .and_then(|folder| self.repository.folder_repository(folder))
+            .unwrap_or_else(|| self.repository.clone())
+    }
+
+    /// Repository of the folder a snippet came from, else the one the document routes to
+    fn document_repository(&self, folder: Option<&str>, uri: &Url) -> Arc<dyn SnippetRepository> {
+        let route = self.repository.route(uri);
+        self.folder_repository(folder.or(route.as_deref()))
+    }
+
+    /// Check that the positions of a command lie within its document, if that is open
+    ///
+    /// Characters past the end of a line are left to the commands, which
+    /// clamp them; unopened documents are reported by the commands as well.
+    pub async fn validate_positions(&self, command: &BkmrCommand) -> Result<(), InvalidArgument> {
+        let (uri, positions): (&Url, Vec<(String, Position)>) = match command {
+            BkmrCommand::InsertFilepathComment { uri, position } => (
+                uri,
+                position
+                    .iter()
+                    .map(|position| ("position".to_string(), *position))
+                    .collect(),
+            ),
+            BkmrCommand::AddSnippet { uri, range, .. } => (
+                uri,
+                vec![
+                    ("range.start".to_string(), range.start),
+                    ("range.end".to_string(), range.end),
+                ],
+            ),
+            BkmrCommand::InsertSnippetById { uri, targets, .. } => {
+                let field = |index: usize| match targets.len() {
+                    1 => "position".to_string(),
+                    _ => format!("positions[{}]", index),
+                };
+                (
+                    uri,
+                    targets
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(index, target)| {
+                            [(field(index), target.start), (field(index), target.end)]
+                        })
+                        .collect(),
+                )
+            }
+            BkmrCommand::InsertRaw { uri, position, .. } => {
+                (uri, vec![("position".to_string(), *position)])
+            }
+            _ => return Ok(()),
+        };
+        let Some(line_count) = self.document_service.line_count(uri.as_str()).await else {
+            return Ok(());
+        };
+
+        match positions
+            .into_iter()
+            .find(|(_, position)| position.line >= line_count)
+        {
+            Some((field, position)) => Err(InvalidArgument::new(
+                field,
+                format!(
+                    "line {} is past the end of {}, which has {} lines",
+                    position.line, uri, line_count
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Accept an id as number, numeric string or completion item data object
+    fn parse_snippet_id(argument: &Value) -> Result<i32> {
+        match argument {
+            Value::Number(number) => number
+                .as_i64()
+                .and_then(|id| i32::try_from(id).ok())
+                .ok_or_else(|| anyhow!("Snippet id out of range: {}", number)),
+            Value::String(text) => text
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("Snippet id is not numeric: {:?}", text)),
+            Value::Object(object) => object
+                .get("id")
+                .ok_or_else(|| anyhow!("Snippet id missing from argument object"))
+                .and_then(Self::parse_snippet_id),
+            other => Err(anyhow!("Unsupported snippet id argument: {}", other)),
+        }
+    }
+
+    /// Code actions offering the bkmr commands for a document range
+    ///
+    /// Only builds command payloads, bkmr runs when the client executes one.
+    /// Actions are limited to the requested kinds when `only` is given.
+    pub async fn code_actions(
+        &self,
+        uri: &Url,
+        range: Range,
+        only: Option<&[CodeActionKind]>,
+    ) -> Vec<CodeActionOrCommand> {
+        let mut actions = vec![CodeAction {
+            title: "Insert filepath comment".to_string(),
+            kind: Some(CodeActionKind::SOURCE),
+            command: Some(Command {
+                title: "Insert filepath comment".to_string(),
+                command: INSERT_FILEPATH_COMMENT.to_string(),
+                arguments: Some(vec![Value::String(uri.to_string())]),
+            }),
+            ..Default::default()
+        }];
+
+        if range.start != range.end {
+            let selection = self
+                .document_service
+                .get_text_in_range(uri.as_str(), range)
+                .await
+                .unwrap_or_default();
+            let arguments = serde_json::json!({
+                "uri": uri,
+                "range": range,
+                "title": Self::selection_title(&selection),
+            });
+            actions.push(CodeAction {
+                title: "Save selection as bkmr snippet…".to_string(),
+                kind: Some(CodeActionKind::EMPTY),
+                command: Some(Command {
+                    title: "Save selection as bkmr snippet".to_string(),
+                    command: ADD_SNIPPET.to_string(),
+                    arguments: Some(vec![arguments]),
+                }),
+                ..Default::default()
+            });
+        }
+
+        actions
+            .into_iter()
+            .filter(|action| Self::kind_requested(action.kind.as_ref(), only))
+            .map(CodeActionOrCommand::CodeAction)
+            .collect()
+    }
+
+    /// Check an action kind against the kinds a client asked for, all kinds when None
+    fn kind_requested(kind: Option<&CodeActionKind>, only: Option<&[CodeActionKind]>) -> bool {
+        let Some(only) = only else {
+            return true;
+        };
+        let kind = kind.map(CodeActionKind::as_str).unwrap_or("");
+        only.iter().any(|requested| {
+            let requested = requested.as_str();
+            kind == requested || kind.starts_with(&format!("{}.", requested))
+        })
+    }
+
+    /// Propose a title for a snippet from the first non-empty line of the selection
+    fn selection_title(selection: &str) -> String {
+        let line = selection
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or("Untitled snippet");
+        line.chars().take(MAX_SELECTION_TITLE_CHARS).collect()
+    }
+
+    /// Execute the addSnippet command, returning the id of the new snippet
+    ///
+    /// The snippet goes to the database serving the document.
+    #[instrument(skip(self, tags))]
+    pub async fn add_snippet(
+        &self,
+        uri: &Url,
+        range: Range,
+        title: &str,
+        tags: Vec<String>,
+    ) -> Result<i32> {
+        if title.trim().is_empty() {
+            return Err(anyhow!("Snippet title must not be empty"));
+        }
+
+        let content = self
+            .document_service
+            .get_text_in_range(uri.as_str(), range)
+            .await
+            .context("read selected text")?;
+        if content.trim().is_empty() {
+            return Err(anyhow!("Selection is empty, nothing to add"));
+        }
+
+        // Tag with the document language so the snippet completes in the same files
+        let mut snippet_tags = Vec::new();
+        if let Some(language_id) = self.document_service.get_language_id(uri.as_str()).await {
+            snippet_tags.push(language_id);
+        }
+        // Mark it as snippet with the first snippet tag, any of them finds it again
+        if let Some(snippet_tag) = self.snippet_tags.first() {
+            snippet_tags.push(snippet_tag.clone());
+        }
+        for tag in tags {
+            let tag = tag.trim().to_string();
+            if !tag.is_empty() && !snippet_tags.contains(&tag) {
+                snippet_tags.push(tag);
+            }
+        }
+
+        let snippet = NewSnippet {
+            title: title.trim().to_string(),
+            content,
+            tags: snippet_tags,
+        };
+        debug!(
+            "Adding snippet '{}' with tags {:?}",
+            snippet.title, snippet.tags
+        );
+
+        self.document_repository(None, uri)
+            .create_snippet(&snippet)
+            .await
+            .context("create snippet in repository")
+    }
+
+    /// Build the edit inserting a snippet as plain text at each target of a document
+    ///
+    /// Workspace edits have no tabstops, so placeholders are reduced to
+    /// their default values before insertion. Each copy is indented like its
+    /// target line; overlapping targets are rejected before anything is built.
+    #[instrument(skip(self))]
+    pub async fn insert_snippet_by_id(
+        &self,
+        id: i32,
+        folder: Option<&str>,
+        uri: &Url,
+        targets: &[Range],
+        workspace_folders: &[Url],
+    ) -> Result<WorkspaceEdit> {
+        let language_id = self
+            .document_service
+            .get_language_id(uri.as_str())
+            .await
+            .ok_or_else(|| anyhow!("Document is not open: {}", uri))?;
+        let mut ranges = Vec::with_capacity(targets.len());
+        for target in targets {
+            let start = self
+                .document_service
+                .clamp_position(uri.as_str(), target.start)
+                .await
+                .context("resolve insert position")?;
+            let end = self
+                .document_service
+                .clamp_position(uri.as_str(), target.end)
+                .await
+                .context("resolve insert position")?;
+            if end < start {
+                return Err(anyhow!(
+                    "Insert range ends before it starts: {}:{}-{}:{}",
+                    start.line,
+                    start.character,
+                    end.line,
+                    end.character
+                ));
+            }
+            ranges.push(Range::new(start, end));
+        }
+        Self::reject_overlaps(&mut ranges)?;
+
+        let snippet = self
+            .document_repository(folder, uri)
+            .fetch_snippet_by_id(id)
+            .await
+            .context("fetch snippet by id")?
+            .ok_or_else(|| anyhow!("No snippet with id {}", id))?;
+
+        let indent_unit = self.document_service.indent_unit(uri.as_str(), None).await;
+        let variables = ContextVariables::new(uri, &language_id, &self.language_registry)
+            .with_workspace_folders(workspace_folders, self.path_separator)
+            .with_author(self.author.as_deref())
+            .with_indent_unit(indent_unit);
+        let content = LanguageTranslator::translate_snippet(
+            &snippet,
+            &language_id,
+            &variables,
+            &self.translation,
+        )
+        .context("translate snippet content for target language")?;
+        let content = if snippet.is_plain() {
+            content
+        } else {
+            let content = LanguageTranslator::escape_literal_dollars(
+                &content,
+                &language_id,
+                &self.escape_all_dollars_for,
+                &self.language_registry,
+            );
+            strip_placeholders(&content)
+        };
+
+        // Ranges of one edit all refer to the original document, emitting them
+        // bottom-to-top also keeps clients applying them one by one correct
+        let mut edits = Vec::with_capacity(ranges.len());
+        for range in ranges.into_iter().rev() {
+            let new_text = match self
+                .document_service
+                .get_line_indentation(uri.as_str(), range.start.line)
+                .await
+            {
+                Some(indentation) => {
+                    SnippetPresenter::indent_continuation_lines(&content, &indentation)
+                }
+                None => content.clone(),
+            };
+            debug!(
+                "Inserting snippet {} at {}:{}",
+                id, range.start.line, range.start.character
+            );
+            edits.push(TextEdit { range, new_text });
+        }
+        let mut changes = HashMap::new();
+        changes.insert(uri.clone(), edits);
+
+        Ok(WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+            change_annotations: None,
+        })
+    }
+
+    /// Build the edit inserting the uninterpolated template of a snippet at a position
+    ///
+    /// The template goes in exactly as stored: no translation, no dollar
+    /// escaping and placeholders left for bkmr to interpolate later.
+    #[instrument(skip(self))]
+    pub async fn insert_raw(
+        &self,
+        id: i32,
+        folder: Option<&str>,
+        uri: &Url,
+        position: Position,
+    ) -> Result<WorkspaceEdit> {
+        let position = self
+            .document_service
+            .clamp_position(uri.as_str(), position)
+            .await
+            .context("resolve insert position")?;
+        let snippet = self
+            .document_repository(folder, uri)
+            .fetch_raw_snippet_by_id(id)
+            .await
+            .context("fetch raw snippet by id")?
+            .ok_or_else(|| anyhow!("No snippet with id {}", id))?;
+
+        debug!(
+            "Inserting raw snippet {} at {}:{}",
+            id, position.line, position.character
+        );
+        let mut changes = HashMap::new();
+        changes.insert(
+            uri.clone(),
+            vec![TextEdit {
+                range: Range::new(position, position),
+                new_text: snippet.content,
+            }],
+        );
+
+        Ok(WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+            change_annotations: None,
+        })
+    }
+
+    /// Sort insert targets top to bottom, failing when two of them overlap
+    ///
+    /// Two cursors at the same position count as overlapping, the snippet
+    /// would end up there twice.
+    fn reject_overlaps(ranges: &mut [Range]) -> Result<()> {
+        ranges.sort_by_key(|range| (range.start, range.end));
+        for pair in ranges.windows(2) {
+            let (first, second) = (pair[0], pair[1]);
+            if second.start < first.end || second.start == first.start {
+                return Err(anyhow!(
+                    "Insert targets overlap: {}:{}-{}:{} and {}:{}-{}:{}",
+                    first.start.line,
+                    first.start.character,
+                    first.end.line,
+                    first.end.character,
+                    second.start.line,
+                    second.start.character,
+                    second.end.line,
+                    second.end.character
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Write every snippet offered for a language, translated for it, to a file
+    ///
+    /// Existing files are only replaced with `overwrite`; with workspace
+    /// folders the path must lie inside one of them, relative paths start at
+    /// the first.
+    #[instrument(skip(self))]
+    pub async fn export_snippets(
+        &self,
+        language: &str,
+        format: ExportFormat,
+        path: &Path,
+        overwrite: bool,
+        workspace_folders: &[Url],
+    ) -> Result<ExportSummary> {
+        let path = Self::export_path(path, workspace_folders)?;
+        let uri = Url::from_file_path(&path)
+            .map_err(|()| anyhow!("Export path is not a file path: {}", path.display()))?;
+
+        let filter = SnippetFilter::new(Some(language.to_string()), None, EXPORT_MAX_SNIPPETS);
+        let snippets = self
+            .repository
+            .fetch_snippets(&filter)
+            .await
+            .context("fetch snippets to export")?;
+        let variables = ContextVariables::new(&uri, language, &self.language_registry)
+            .with_workspace_folders(workspace_folders, self.path_separator)
+            .with_author(self.author.as_deref());
+        let snippets = snippets
+            .into_iter()
+            .map(|snippet| {
+                let content = LanguageTranslator::translate_snippet(
+                    &snippet,
+                    language,
+                    &variables,
+                    &self.translation,
+                )
+                .with_context(|| format!("translate snippet {}", snippet.id))?;
+                Ok(Snippet { content, ..snippet })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let rendered =
+            render_snippet_export(format, &snippets).context("serialize exported snippets")?;
+
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true);
+        if overwrite {
+            options.create(true).truncate(true);
+        } else {
+            options.create_new(true);
+        }
+        let mut file = options.open(&path).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::AlreadyExists {
+                anyhow!(
+                    "{} already exists, pass overwrite: true to replace it",
+                    path.display()
+                )
+            } else {
+                anyhow::Error::new(e).context(format!("open {}", path.display()))
+            }
+        })?;
+        file.write_all(rendered.as_bytes())
+            .await
+            .with_context(|| format!("write {}", path.display()))?;
+        file.flush()
+            .await
+            .with_context(|| format!("write {}", path.display()))?;
+
+        debug!("Exported {} snippets to {}", snippets.len(), path.display());
+        Ok(ExportSummary {
+            count: snippets.len(),
+            path: path.display().to_string(),
+        })
+    }
+
+    /// Absolute, normalized export path, rejected outside the known workspace folders
+    fn export_path(path: &Path, workspace_folders: &[Url]) -> Result<PathBuf> {
+        let folders: Vec<PathBuf> = workspace_folders
+            .iter()
+            .filter_map(|folder| folder.to_file_path().ok())
+            .map(|folder| normalize_path(&folder))
+            .collect();
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            folders
+                .first()
+                .map(|folder| folder.join(path))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Export path must be absolute without workspace folders: {}",
+                        path.display()
+                    )
+                })?
+        };
+        let path = normalize_path(&path);
+        if !folders.is_empty() && !folders.iter().any(|folder| path.starts_with(folder)) {
+            return Err(anyhow!(
+                "Export path is outside the workspace folders: {}",
+                path.display()
+            ));
+        }
+        Ok(path)
+    }
+
+    /// Execute the openSnippet command, returning the opened snippet id
+    #[instrument(skip(self))]
+    pub async fn open_snippet(&self, id: i32, folder: Option<&str>) -> Result<i32> {
+        self.folder_repository(folder).open_snippet(id).await?;
+        Ok(id)
+    }
+
+    /// Drop the completion and repository caches, returning the number of evicted entries
+    pub fn clear_cache(&self, completion_service: &CompletionService) -> usize {
+        let evicted = completion_service.clear_cache() + self.repository.clear_cache();
+        debug!("Cleared {} cached results", evicted);
+        evicted
+    }
+
+    /// Check bkmr again, reporting failures in the summary instead of as an error
+    ///
+    /// The health check and the version run together.
+    #[instrument(skip(self))]
+    pub async fn health_summary(&self) -> HealthSummary {
+        let (health, version) =
+            tokio::join!(self.repository.health_check(), self.repository.version());
+        let healthy = match health.context("check bkmr health") {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("{:#}", e);
+                false
+            }
+        };
+        let bkmr_version = version.context("read bkmr version").unwrap_or_else(|e| {
+            debug!("{:#}", e);
+            None
+        });
+        HealthSummary {
+            healthy,
+            bkmr_version,
+            bkmr_capabilities: self.repository.capabilities(),
+        }
+    }
+
+    /// Task reporting an inserted snippet to bkmr, for the caller to run in the background
+    ///
+    /// Failures are only logged at debug level, usage tracking must never get
+    /// in the way of editing. Returns None when usage tracking is disabled.
+    pub fn record_usage(
+        &self,
+        id: i32,
+        folder: Option<&str>,
+    ) -> Option<impl Future<Output = ()> + Send + 'static> {
+        if !self.track_usage {
+            debug!("Usage tracking disabled, not recording snippet {}", id);
+            return None;
+        }
+
+        let repository = self.folder_repository(folder);
+        Some(async move {
+            if let Err(e) = repository.record_usage(id).await {
+                debug!("Failed to record usage of snippet {}: {:#}", id, e);
+            }
+        })
+    }
+
+    /// Execute the insertFilepathComment command on the document as currently open
+    ///
+    /// Documents the server has not seen are treated as empty.
+    #[instrument(skip(self, workspace_folders))]
+    pub async fn insert_filepath_comment(
+        &self,
+        file_uri: &str,
+        workspace_folders: &[Url],
+    ) -> Result<FilepathComment> {
+        if !self.untitled_filepath_comment
+            && Url::parse(file_uri).is_ok_and(|url| url.scheme() == UNTITLED_SCHEME)
+        {
+            debug!("Not inserting a filepath comment into unsaved {}", file_uri);
+            return Ok(FilepathComment::NotSaved);
+        }
+        let text = self
+            .document_service
+            .get_document_content(file_uri)
+            .await
+            .unwrap_or_default();
+        let language_id = self.document_service.get_language_id(file_uri).await;
+        Self::filepath_comment(
+            file_uri,
+            &text,
+            language_id.as_deref(),
+            workspace_folders,
+            self.path_separator,
+            self.document_service.position_encoding(),
+            &self.language_registry,
+        )
+    }
+
+    /// Build the filepath comment for a document's text
+    ///
+    /// The comment goes after a byte order mark and below a shebang line or
+    /// XML declaration, which must stay first in the file.
+    pub fn filepath_comment(
+        file_uri: &str,
+        text: &str,
+        language_id: Option<&str>,
+        workspace_folders: &[Url],
+        separator: PathSeparator,
+        encoding: PositionEncoding,
+        registry: &LanguageRegistry,
+    ) -> Result<FilepathComment> {
+        let relative_path = Self::get_relative_path(
+            file_uri,
+            language_id,
+            workspace_folders,
+            separator,
+            registry,
+        )
+        .context("calculate relative path for file")?;
+        if text
+            .lines()
+            .take(FILEPATH_COMMENT_SEARCH_LINES)
+            .any(|line| line.contains(&relative_path))
+        {
+            debug!("Filepath comment already present: {}", relative_path);
+            return Ok(FilepathComment::AlreadyPresent);
+        }
+
+        let comment_text = match registry.comment_delimiters(language_id, file_uri) {
+            (start, Some(end)) => format!("{} {} {}\n", start, relative_path, end),
+            (start, None) => format!("{} {}\n", start, relative_path),
+        };
+        debug!("Inserting filepath comment: {}", comment_text.trim());
+
+        let (position, new_text) = Self::header_insertion(text, comment_text, encoding);
+        let edit = TextEdit {
+            range: Range {
+                start: position,
+                end: position,
+            },
+            new_text,
+        };
+
+        let uri = Url::parse(file_uri).context("parse file URI for workspace edit")?;
+
+        let mut changes = HashMap::new();
+        changes.insert(uri, vec![edit]);
+
+        Ok(FilepathComment::Insert(WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+            change_annotations: None,
+        }))
+    }
+
+    /// Position and text inserting a comment after the lines that must stay first
+    fn header_insertion(
+        text: &str,
+        comment: String,
+        encoding: PositionEncoding,
+    ) -> (Position, String) {
+        let body = text.strip_prefix(BYTE_ORDER_MARK).unwrap_or(text);
+        let first_line = body.lines().next().unwrap_or("");
+        let header_lines = if first_line.starts_with("#!") {
+            1
+        } else if first_line.trim_start().starts_with("<?xml") {
+            body.lines()
+                .position(|line| line.contains("?>"))
+                .map_or(1, |index| index + 1)
+        } else {
+            0
+        };
+
+        if header_lines == 0 {
+            let character = encoding.character(text, text.len() - body.len());
+            return (Position::new(0, character), comment);
+        }
+
+        let lines: Vec<&str> = text.split('\n').collect();
+        if lines.len() > header_lines {
+            (Position::new(header_lines as u32, 0), comment)
+        } else {
+            // The header is the last line and has no line break to insert after
+            let last_line = lines[lines.len() - 1];
+            let position = Position::new(
+                (lines.len() - 1) as u32,
+                encoding.character(last_line, last_line.len()),
+            );
+            (position, format!("\n{}", comment.trim_end_matches('\n')))
+        }
+    }
+
+    /// Get the relative path from the workspace folder or project root
+    ///
+    /// Unsaved buffers get a name synthesized from their URI and language, see
+    /// [`untitled_file_name`].
+    pub(crate) fn get_relative_path(
+        file_uri: &str,
+        language_id: Option<&str>,
+        workspace_folders: &[Url],
+        separator: PathSeparator,
+        registry: &LanguageRegistry,
+    ) -> Result<String> {
+        let url = Url::parse(file_uri).context("parse file URI")?;
+        if let Some(name) = untitled_file_name(&url, language_id, registry) {
+            return Ok(name);
+        }
+        if let Some(relative_path) =
+            Self::workspace_relative_path(&url, workspace_folders, separator)
+        {
+            return Ok(relative_path);
+        }
+
+        let file_path = url
+            .to_file_path()
+            .map_err(|_| anyhow::anyhow!("Convert URL to file path"))
+            .context("convert URL to file path")?;
+
+        // Try to find a project root by looking for common indicators
+        if let Some(root) = Self::project_root(&file_path)
+            && let Ok(rel_path) = file_path.strip_prefix(root)
+        {
+            return Ok(render_relative_path(rel_path, separator));
+        }
+
+        // Fall back to just the filename if no project root found
+        file_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .ok_or_else(|| anyhow::anyhow!("Extract filename from path"))
+            .context("extract filename from file path")
+    }
+
+    /// Nearest directory above a file with a project root indicator like Cargo.toml or .git
+    pub(crate) fn project_root(file_path: &Path) -> Option<&Path> {
+        file_path.ancestors().skip(1).find(|parent| {
+            [
+                "Cargo.toml",
+                "package.json",
+                "pom.xml",
+                "build.gradle",
+                "build.gradle.kts",
+                "Makefile",
+                ".git",
+            ]
+            .iter()
+            .any(|indicator| parent.join(indicator).exists())
+        })
+    }
+
+    /// Path of a file relative to the innermost workspace folder containing it
+    ///
+    /// Returns None when the file lies outside all folders. Drive letters are
+    /// compared case-insensitively, as clients differ in how they spell them.
+    pub fn workspace_relative_path(
+        file_uri: &Url,
+        workspace_folders: &[Url],
+        separator: PathSeparator,
+    ) -> Option<String> {
+        let file = uri_path_components(file_uri)?;
+        workspace_folders
+            .iter()
+            .filter_map(uri_path_components)
+            .filter(|folder| folder.len() < file.len() && file.starts_with(folder))
+            .max_by_key(Vec::len)
+            .map(|folder| join_path_components(&file[folder.len()..], separator))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::Snippet;
+    use crate::domain::{CompletionContext, CompletionQuery};
+    use crate::repositories::{MockSnippetRepository, SnippetIdCache};
+    use crate::services::CompletionService;
+    use serde_json::json;
+
+    #[test]
+    fn given_open_snippet_with_numeric_id_when_parsing_then_returns_command() {
+        // Arrange
+        let arguments = [json!(42)];
+
+        // Act
+        let command = CommandService::parse_command(OPEN_SNIPPET, &arguments);
+
+        // Assert
+        assert_eq!(
+            command.expect("parsed command"),
+            Some(BkmrCommand::OpenSnippet {
+                id: 42,
+                folder: None
+            })
+        );
+    }
+
+    #[test]
+    fn given_open_snippet_with_string_or_data_id_when_parsing_then_returns_command() {
+        // Arrange
+        let string_arguments = [json!("17"), json!("/work/clientA")];
+        let data_arguments =
+            [json!({"id": 23, "uri": "file:///test.rs", "folder": "/work/clientB"})];
+
+        // Act
+        let from_string = CommandService::parse_command(OPEN_SNIPPET, &string_arguments);
+        let from_data = CommandService::parse_command(OPEN_SNIPPET, &data_arguments);
+
+        // Assert
+        assert_eq!(
+            from_string.expect("parsed command"),
+            Some(BkmrCommand::OpenSnippet {
+                id: 17,
+                folder: Some("/work/clientA".to_string())
+            })
+        );
+        assert_eq!(
+            from_data.expect("parsed command"),
+            Some(BkmrCommand::OpenSnippet {
+                id: 23,
+                folder: Some("/work/clientB".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn given_open_snippet_without_id_when_parsing_then_returns_error() {
+        // Arrange
+        let arguments: [Value; 0] = [];
+
+        // Act
+        let result = CommandService::parse_command(OPEN_SNIPPET, &arguments);
+
+        // Assert
+        let error_message = result.unwrap_err().to_string();
+        assert!(error_message.contains("expects a snippet id"));
+    }
+
+    #[test]
+    fn given_open_snippet_with_non_numeric_id_when_parsing_then_returns_error() {
+        // Arrange
+        let arguments = [json!("abc")];
+
+        // Act
+        let result = CommandService::parse_command(OPEN_SNIPPET, &arguments);
+
+        // Assert
+        let error = result.unwrap_err();
+        assert!(format!("{:#}", error).contains("not numeric"));
+    }
+
+    #[test]
+    fn given_command_names_when_parsing_then_routes_to_matching_command() {
+        // Arrange
+        let uri_arguments = [json!("file:///test.rs")];
+
+        // Act
+        let insert = CommandService::parse_command(INSERT_FILEPATH_COMMENT, &uri_arguments);
+        let unknown = CommandService::parse_command("bkmr.unknown", &uri_arguments);
+
+        // Assert
+        assert_eq!(
+            insert.expect("parsed command"),
+            Some(BkmrCommand::InsertFilepathComment {
+                uri: Url::parse("file:///test.rs").expect("parse URI"),
+                position: None,
+            })
+        );
+        assert_eq!(unknown.expect("unknown command"), None);
+        let service = CommandService::new(
+            Arc::new(MockSnippetRepository::new()),
+            Arc::new(DocumentService::new()),
+        );
+        assert!(service.command_names().contains(&OPEN_SNIPPET.to_string()));
+        assert!(!service.command_names().contains(&RECORD_USAGE.to_string()));
+    }
+
+    #[test]
+    fn given_filepath_comment_object_argument_when_parsing_then_returns_uri_and_position() {
+        // Arrange
+        let arguments = [json!({
+            "uri": "file:///tmp/test.rs",
+            "position": {"line": 0, "character": 0}
+        })];
+        let without_position = [json!({"uri": "file:///tmp/test.rs"})];
+
+        // Act
+        let command = CommandService::parse_command(INSERT_FILEPATH_COMMENT, &arguments);
+        let uri_only = CommandService::parse_command(INSERT_FILEPATH_COMMENT, &without_position);
+
+        // Assert
+        let uri = Url::parse("file:///tmp/test.rs").expect("parse URI");
+        assert_eq!(
+            command.expect("parsed command"),
+            Some(BkmrCommand::InsertFilepathComment {
+                uri: uri.clone(),
+                position: Some(Position::new(0, 0)),
+            })
+        );
+        assert_eq!(
+            uri_only.expect("parsed command"),
+            Some(BkmrCommand::InsertFilepathComment {
+                uri,
+                position: None
+            })
+        );
+    }
+
+    #[test]
+    fn given_missing_or_malformed_fields_when_parsing_then_names_the_offending_field() {
+        // Arrange
+        let missing_uri = [json!({"position": {"line": 0, "character": 0}})];
+        let bad_uri = [json!("not a uri")];
+        let negative_line = [json!({
+            "uri": "file:///tmp/test.rs",
+            "position": {"line": -1, "character": 0}
+        })];
+        let bad_id = [json!({
+            "id": "abc",
+            "uri": "file:///tmp/test.rs",
+            "position": {"line": 0, "character": 0}
+        })];
+
+        // Act
+        let missing_uri = CommandService::parse_command(INSERT_FILEPATH_COMMENT, &missing_uri);
+        let bad_uri = CommandService::parse_command(INSERT_FILEPATH_COMMENT, &bad_uri);
+        let negative_line = CommandService::parse_command(INSERT_FILEPATH_COMMENT, &negative_line);
+        let bad_id = CommandService::parse_command(INSERT_RAW, &bad_id);
+        let no_argument = CommandService::parse_command(INSERT_FILEPATH_COMMENT, &[]);
+
+        // Assert
+        assert_eq!(
+            missing_uri.unwrap_err(),
+            InvalidArgument::new("uri", "missing")
+        );
+        assert_eq!(bad_uri.unwrap_err().field, "uri");
+        let negative_line = negative_line.unwrap_err();
+        assert_eq!(negative_line.field, "position");
+        assert!(negative_line.message.contains("-1"));
+        assert_eq!(bad_id.unwrap_err().field, "id");
+        assert_eq!(no_argument.unwrap_err().field, "arguments");
+    }
+
+    #[tokio::test]
+    async fn given_line_past_document_end_when_validating_positions_then_rejects_it() {
+        // Arrange
+        let (service, _, uri) = service_with_document("a = 1\nb = 2\n").await;
+        let unopened = Url::parse("file:///other.py").expect("parse URI");
+        let command = |uri: &Url, line: u32| BkmrCommand::InsertRaw {
+            id: 1,
+            folder: None,
+            uri: uri.clone(),
+            position: Position::new(line, 99),
+        };
+        let cursors = BkmrCommand::InsertSnippetById {
+            id: 1,
+            folder: None,
+            uri: uri.clone(),
+            targets: vec![range((0, 0), (0, 0)), range((2, 0), (3, 0))],
+        };
+
+        // Act
+        let last_line = service.validate_positions(&command(&uri, 2)).await;
+        let past_end = service.validate_positions(&command(&uri, 3)).await;
+        let not_open = service.validate_positions(&command(&unopened, 3)).await;
+        let second_cursor = service.validate_positions(&cursors).await;
+
+        // Assert
+        assert_eq!(last_line, Ok(()));
+        assert_eq!(
+            past_end.unwrap_err(),
+            InvalidArgument::new(
+                "position",
+                "line 3 is past the end of file:///test.py, which has 3 lines"
+            )
+        );
+        assert_eq!(not_open, Ok(()));
+        assert_eq!(second_cursor.unwrap_err().field, "positions[1]");
+    }
+
+    #[test]
+    fn given_tag_or_null_when_parsing_set_tag_filter_then_sets_or_clears_filter() {
+        // Act
+        let global = CommandService::parse_command(SET_TAG_FILTER, &[json!("sql")]);
+        let document =
+            CommandService::parse_command(SET_TAG_FILTER, &[json!(null), json!("file:///a.rs")]);
+        let missing = CommandService::parse_command(SET_TAG_FILTER, &[]);
+
+        // Assert
+        assert_eq!(
+            global.expect("parsed command"),
+            Some(BkmrCommand::SetTagFilter {
+                tag: Some("sql".to_string()),
+                uri: None,
+            })
+        );
+        assert_eq!(
+            document.expect("parsed command"),
+            Some(BkmrCommand::SetTagFilter {
+                tag: None,
+                uri: Some("file:///a.rs".to_string()),
+            })
+        );
+        assert!(format!("{:#}", missing.unwrap_err()).contains("expects a tag or null"));
+    }
+
+    #[tokio::test]
+    async fn given_unhealthy_bkmr_when_summarizing_health_then_reports_it_without_error() {
+        // Arrange
+        let repository = Arc::new(
+            MockSnippetRepository::new().with_health_check_error(anyhow!("bkmr not found")),
+        );
+        let service = CommandService::new(repository, Arc::new(DocumentService::new()));
+        let commands = [CLEAR_CACHE, RELOAD_CONFIG]
+            .map(|command| CommandService::parse_command(command, &[]).expect("parsed command"));
+
+        // Act
+        let summary = service.health_summary().await;
+
+        // Assert
+        assert_eq!(
+            commands,
+            [
+                Some(BkmrCommand::ClearCache),
+                Some(BkmrCommand::ReloadConfig)
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(summary).expect("serialize summary"),
+            json!({ "healthy": false, "bkmrVersion": null })
+        );
+    }
+
+    #[tokio::test]
+    async fn given_failing_bkmr_when_recording_usage_then_error_stays_in_background_task() {
+        // Arrange
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_usage_error("bkmr touch failed"));
+        let service = CommandService::new(repository.clone(), Arc::new(DocumentService::new()))
+            .with_track_usage(true);
+        let command =
+            CommandService::parse_command(RECORD_USAGE, &[json!(7)]).expect("parsed command");
+
+        // Act
+        let task = service
+            .record_usage(7, None)
+            .expect("usage tracking enabled");
+        task.await;
+
+        // Assert
+        assert_eq!(
+            command,
+            Some(BkmrCommand::RecordUsage {
+                id: 7,
+                folder: None
+            })
+        );
+        assert_eq!(repository.used_ids(), vec![7]);
+        assert!(service.command_names().contains(&RECORD_USAGE.to_string()));
+    }
+
+    #[tokio::test]
+    async fn given_usage_tracking_disabled_when_recording_usage_then_skips_bkmr() {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new());
+        let service = CommandService::new(repository.clone(), Arc::new(DocumentService::new()));
+
+        // Act
+        let task = service.record_usage(7, None);
+
+        // Assert
+        assert!(task.is_none());
+        assert!(repository.used_ids().is_empty());
+    }
+
+    #[tokio::test]
+    async fn given_known_snippet_when_opening_then_returns_id() {
+        // Arrange
+        let snippet = Snippet::new(
+            5,
+            "Test".to_string(),
+            "content".to_string(),
+            String::new(),
+            vec!["rust".to_string()],
+        );
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet]));
+        let service = CommandService::new(repository.clone(), Arc::new(DocumentService::new()));
+
+        // Act
+        let result = service.open_snippet(5, None).await;
+
+        // Assert
+        assert_eq!(result.expect("opened snippet"), 5);
+        assert_eq!(repository.opened_ids(), vec![5]);
+    }
+
+    #[tokio::test]
+    async fn given_unknown_snippet_when_opening_then_surfaces_bkmr_message() {
+        // Arrange
+        let service = CommandService::new(
+            Arc::new(MockSnippetRepository::new()),
+            Arc::new(DocumentService::new()),
+        );
+
+        // Act
+        let result = service.open_snippet(99, None).await;
+
+        // Assert
+        let error_message = result.unwrap_err().to_string();
+        assert!(error_message.contains("No bookmark found"));
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range {
+            start: Position {
+                line: start.0,
+                character: start.1,
+            },
+            end: Position {
+                line: end.0,
+                character: end.1,
+            },
+        }
+    }
+
+    async fn service_with_document(
+        content: &str,
+    ) -> (CommandService, Arc<MockSnippetRepository>, Url) {
+        service_with_snippets(content, vec![]).await
+    }
+
+    async fn service_with_snippets(
+        content: &str,
+        snippets: Vec<Snippet>,
+    ) -> (CommandService, Arc<MockSnippetRepository>, Url) {
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+        let document_service = Arc::new(DocumentService::new());
+        document_service
+            .open_document(uri.to_string(), "python".to_string(), content.to_string())
+            .await
+            .expect("open document");
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(snippets));
+        let service = CommandService::new(repository.clone(), document_service);
+        (service, repository, uri)
+    }
+
+    fn inserted_edit(edit: WorkspaceEdit, uri: &Url) -> TextEdit {
+        edit.changes
+            .expect("workspace changes")
+            .remove(uri)
+            .expect("edits for document")
+            .remove(0)
+    }
+
+    /// Empty temporary directory for export files
+    fn export_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bkmr-lsp-export-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("create export dir");
+        dir
+    }
+
+    fn export_snippets() -> Vec<Snippet> {
+        vec![
+            Snippet::new(
+                1,
+                "Header".to_string(),
+                "// Section\nvalue = 1".to_string(),
+                String::new(),
+                vec![
+                    "universal".to_string(),
+                    "rust-style".to_string(),
+                    "_snip_".to_string(),
+                ],
+            ),
+            Snippet::new(
+                2,
+                "Main".to_string(),
+                "if __name__ == \"__main__\":\n    main()".to_string(),
+                String::new(),
+                vec!["python".to_string(), "_snip_".to_string()],
+            ),
+        ]
+    }
+
+    #[test]
+    fn given_export_snippets_arguments_when_parsing_then_defaults_format_and_overwrite() {
+        // Arrange
+        let arguments = [json!({"language": "python", "path": "/tmp/python.json"})];
+
+        // Act
+        let command = CommandService::parse_command(EXPORT_SNIPPETS, &arguments);
+
+        // Assert
+        assert_eq!(
+            command.expect("parsed command"),
+            Some(BkmrCommand::ExportSnippets {
+                language: "python".to_string(),
+                format: ExportFormat::Json,
+                path: PathBuf::from("/tmp/python.json"),
+                overwrite: false,
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_language_when_exporting_vscode_snippets_then_writes_translated_snippets() {
+        // Arrange
+        let (service, _, _) = service_with_snippets("", export_snippets()).await;
+        let path = export_dir("vscode").join("python.code-snippets");
+
+        // Act
+        let summary = service
+            .export_snippets("python", ExportFormat::Vscode, &path, false, &[])
+            .await
+            .expect("export snippets");
+
+        // Assert
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.path, path.display().to_string());
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).expect("read export"))
+                .expect("valid JSON");
+        assert_eq!(written["Header"]["body"], json!(["# Section", "value = 1"]));
+        assert_eq!(
+            written["Main"]["body"],
+            json!(["if __name__ == \"__main__\":", "    main()"])
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_existing_file_when_exporting_then_refuses_unless_overwriting() {
+        // Arrange
+        let (service, _, _) = service_with_snippets("", export_snippets()).await;
+        let path = export_dir("overwrite").join("python.json");
+        std::fs::write(&path, "keep me").expect("write existing file");
+
+        // Act
+        let refused = service
+            .export_snippets("python", ExportFormat::Json, &path, false, &[])
+            .await;
+        let kept = std::fs::read_to_string(&path).expect("read existing file");
+        let replaced = service
+            .export_snippets("python", ExportFormat::Json, &path, true, &[])
+            .await;
+
+        // Assert
+        assert!(
+            format!("{:#}", refused.unwrap_err())
+                .contains("already exists, pass overwrite: true to replace it")
+        );
+        assert_eq!(kept, "keep me");
+        assert_eq!(replaced.expect("overwrite export").count, 2);
+        assert!(
+            std::fs::read_to_string(&path)
+                .expect("read export")
+                .starts_with("[\n  {\n    \"id\": 1,")
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_workspace_folders_when_exporting_outside_them_then_rejects_the_path() {
+        // Arrange
+        let (service, repository, _) = service_with_snippets("", export_snippets()).await;
+        let workspace = export_dir("workspace");
+        let folders = [Url::from_file_path(&workspace).expect("folder URI")];
+
+        // Act
+        let escaping = service
+            .export_snippets(
+                "python",
+                ExportFormat::Json,
+                Path::new("../escaped.json"),
+                false,
+                &folders,
+            )
+            .await;
+        let inside = service
+            .export_snippets(
+                "python",
+                ExportFormat::Json,
+                Path::new("exports/../python.json"),
+                false,
+                &folders,
+            )
+            .await;
+
+        // Assert
+        assert!(
+            format!("{:#}", escaping.unwrap_err())
+                .contains("Export path is outside the workspace folders")
+        );
+        assert_eq!(
+            inside.expect("export inside workspace").path,
+            workspace.join("python.json").display().to_string()
+        );
+        assert_eq!(repository.fetch_count(), 1);
+    }
+
+    #[test]
+    fn given_add_snippet_arguments_when_parsing_then_returns_command() {
+        // Arrange
+        let arguments = [json!({
+            "uri": "file:///test.py",
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": 1, "character": 3}},
+            "title": "Greeting"
+        })];
 
-impl CommandService {
-    /// Execute the insertFilepathComment command
-    #[instrument(skip(file_uri))]
-    pub fn insert_filepath_comment(file_uri: &str) -> Result<WorkspaceEdit> {
-        let relative_path =
-            Self::get_relative_path(file_uri).context("calculate relative path for file")?;
+        // Act
+        let command = CommandService::parse_command(ADD_SNIPPET, &arguments);
 
-        let comment_syntax = LanguageRegistry::get_comment_syntax(file_uri);
+        // Assert
+        assert_eq!(
+            command.expect("parsed command"),
+            Some(BkmrCommand::AddSnippet {
+                uri: Url::parse("file:///test.py").expect("parse URI"),
+                range: range((0, 0), (1, 3)),
+                title: "Greeting".to_string(),
+                tags: vec![],
+            })
+        );
+    }
 
-        let comment_text = match comment_syntax {
-            "<!--" => format!("<!-- {} -->\n", relative_path),
-            "/*" => format!("/* {} */\n", relative_path),
-            _ => format!("{} {}\n", comment_syntax, relative_path),
-        };
+    #[test]
+    fn given_add_snippet_without_title_when_parsing_then_returns_error() {
+        // Arrange
+        let arguments = [json!({
+            "uri": "file:///test.py",
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 1}}
+        })];
 
-        debug!("Inserting filepath comment: {}", comment_text.trim());
+        // Act
+        let result = CommandService::parse_command(ADD_SNIPPET, &arguments);
 
-        // Create a text edit to insert at the beginning of the file
-        let edit = TextEdit {
-            range: Range {
-                start: Position {
-                    line: 0,
-                    character: 0,
-                },
-                end: Position {
-                    line: 0,
-                    character: 0,
-                },
-            },
-            new_text: comment_text,
-        };
+        // Assert
+        assert!(format!("{:#}", result.unwrap_err()).contains("title"));
+    }
 
-        let uri = Url::parse(file_uri).context("parse file URI for workspace edit")?;
+    #[tokio::test]
+    async fn given_multiline_selection_when_adding_snippet_then_stores_selected_text() {
+        // Arrange
+        let content = "def greet():\n    print('hi')\n\ngreet()\n";
+        let (service, repository, uri) = service_with_document(content).await;
 
-        let mut changes = HashMap::new();
-        changes.insert(uri, vec![edit]);
+        // Act
+        let id = service
+            .add_snippet(
+                &uri,
+                range((0, 0), (2, 0)),
+                "Greet",
+                vec!["greeting".to_string()],
+            )
+            .await
+            .expect("added snippet");
 
-        Ok(WorkspaceEdit {
-            changes: Some(changes),
-            document_changes: None,
-            change_annotations: None,
-        })
+        // Assert
+        assert_eq!(id, 1);
+        let created = repository.created_snippets();
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0].title, "Greet");
+        assert_eq!(created[0].content, "def greet():\n    print('hi')\n");
+        assert_eq!(
+            created[0].tags,
+            vec![
+                "python".to_string(),
+                "_snip_".to_string(),
+                "greeting".to_string()
+            ]
+        );
     }
 
-    /// Get the relative path from project root
-    fn get_relative_path(file_uri: &str) -> Result<String> {
-        let url = Url::parse(file_uri).context("parse file URI")?;
+    #[tokio::test]
+    async fn given_selection_starting_and_ending_mid_line_when_adding_snippet_then_trims_to_range()
+    {
+        // Arrange
+        let content = "x = compute(\n    a, b\n) + 1\n";
+        let (service, repository, uri) = service_with_document(content).await;
 
-        let file_path = url
-            .to_file_path()
-            .map_err(|_| anyhow::anyhow!("Convert URL to file path"))
-            .context("convert URL to file path")?;
+        // Act
+        service
+            .add_snippet(
+                &uri,
+                range((0, 4), (2, 1)),
+                "Compute",
+                vec!["_snip_".to_string()],
+            )
+            .await
+            .expect("added snippet");
 
-        // Try to find a project root by looking for common indicators
-        let mut current = file_path.as_path();
-        while let Some(parent) = current.parent() {
-            // Check for common project root indicators
-            if parent.join("Cargo.toml").exists()
-                || parent.join("package.json").exists()
-                || parent.join("pom.xml").exists()
-                || parent.join("build.gradle").exists()
-                || parent.join("build.gradle.kts").exists()
-                || parent.join("Makefile").exists()
-                || parent.join(".git").exists()
-            {
-                // Found project root, return relative path
-                if let Ok(rel_path) = file_path.strip_prefix(parent) {
-                    return Ok(rel_path.to_string_lossy().to_string());
-                }
-                break;
-            }
-            current = parent;
-        }
+        // Assert
+        let created = repository.created_snippets();
+        assert_eq!(created[0].content, "compute(\n    a, b\n)");
+        assert_eq!(
+            created[0].tags,
+            vec!["python".to_string(), "_snip_".to_string()]
+        );
+    }
 
-        // Fall back to just the filename if no project root found
-        file_path
-            .file_name()
-            .map(|n| n.to_string_lossy().to_string())
-            .ok_or_else(|| anyhow::anyhow!("Extract filename from path"))
-            .context("extract filename from file path")
+    #[tokio::test]
+    async fn given_uncached_document_when_adding_snippet_then_returns_error() {
+        // Arrange
+        let (service, repository, _) = service_with_document("content").await;
+        let other_uri = Url::parse("file:///other.py").expect("parse URI");
+
+        // Act
+        let result = service
+            .add_snippet(&other_uri, range((0, 0), (0, 3)), "Other", vec![])
+            .await;
+
+        // Assert
+        assert!(format!("{:#}", result.unwrap_err()).contains("Document not found"));
+        assert!(repository.created_snippets().is_empty());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn given_insert_snippet_by_id_arguments_when_parsing_then_returns_command() {
+        // Arrange
+        let arguments = [json!({
+            "id": "12",
+            "uri": "file:///test.py",
+            "position": {"line": 3, "character": 4}
+        })];
+
+        // Act
+        let command = CommandService::parse_command(INSERT_SNIPPET_BY_ID, &arguments);
+
+        // Assert
+        assert_eq!(
+            command.expect("parsed command"),
+            Some(BkmrCommand::InsertSnippetById {
+                id: 12,
+                folder: None,
+                uri: Url::parse("file:///test.py").expect("parse URI"),
+                targets: vec![range((3, 4), (3, 4))],
+            })
+        );
+    }
 
     #[test]
-    fn given_rust_file_when_inserting_filepath_comment_then_uses_double_slash() {
+    fn given_positions_and_ranges_when_parsing_insert_snippet_by_id_then_returns_all_targets() {
         // Arrange
-        let file_uri = "file:///path/to/test.rs";
+        let arguments = [json!({
+            "id": 12,
+            "uri": "file:///test.py",
+            "positions": [
+                {"line": 1, "character": 0},
+                {"start": {"line": 4, "character": 2}, "end": {"line": 4, "character": 6}}
+            ]
+        })];
+        let without_targets = [json!({"id": 12, "uri": "file:///test.py"})];
 
         // Act
-        let result = CommandService::insert_filepath_comment(file_uri);
+        let command = CommandService::parse_command(INSERT_SNIPPET_BY_ID, &arguments);
+        let missing = CommandService::parse_command(INSERT_SNIPPET_BY_ID, &without_targets);
 
         // Assert
-        assert!(result.is_ok());
-        let workspace_edit = result.expect("valid workspace edit");
+        assert_eq!(
+            command.expect("parsed command"),
+            Some(BkmrCommand::InsertSnippetById {
+                id: 12,
+                folder: None,
+                uri: Url::parse("file:///test.py").expect("parse URI"),
+                targets: vec![range((1, 0), (1, 0)), range((4, 2), (4, 6))],
+            })
+        );
+        assert!(
+            missing
+                .unwrap_err()
+                .to_string()
+                .contains("expects a position or a list of positions")
+        );
+    }
 
-        let changes = workspace_edit.changes.expect("workspace changes");
-        let edits = changes.values().next().expect("text edits");
-        let edit = &edits[0];
+    #[tokio::test]
+    async fn given_id_in_two_folders_when_running_commands_then_uses_the_named_or_document_folder()
+    {
+        // Arrange
+        let folder_snippet = |title: &str| {
+            Snippet::new(
+                5,
+                title.to_string(),
+                title.to_string(),
+                String::new(),
+                vec!["python".to_string(), "_snip_".to_string()],
+            )
+        };
+        let client_a =
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![folder_snippet("a")]));
+        let client_b =
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![folder_snippet("b")]));
+        let repository = Arc::new(crate::repositories::RepositoryRouter::new(
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![folder_snippet("d")])),
+            vec![
+                (
+                    "/work/clientA".to_string(),
+                    std::path::PathBuf::from("/work/clientA"),
+                    client_a.clone(),
+                ),
+                (
+                    "/work/clientB".to_string(),
+                    std::path::PathBuf::from("/work/clientB"),
+                    client_b.clone(),
+                ),
+            ],
+        ));
+        let uri = Url::parse("file:///work/clientB/main.py").expect("parse URI");
+        let document_service = Arc::new(DocumentService::new());
+        document_service
+            .open_document(uri.to_string(), "python".to_string(), "x = 1\n".to_string())
+            .await
+            .expect("open document");
+        let service = CommandService::new(repository, document_service).with_track_usage(true);
+        let target = [range((0, 0), (0, 0))];
+        let command = CommandService::parse_command(
+            INSERT_SNIPPET_BY_ID,
+            &[json!({
+                "id": {"id": 5, "folder": "/work/clientA"},
+                "uri": uri.as_str(),
+                "position": {"line": 0, "character": 0}
+            })],
+        )
+        .expect("parsed command");
 
-        assert!(edit.new_text.starts_with("// "));
-        assert!(edit.new_text.contains("test.rs"));
+        // Act
+        let named = service
+            .insert_snippet_by_id(5, Some("/work/clientA"), &uri, &target, &[])
+            .await
+            .expect("workspace edit");
+        let routed = service
+            .insert_snippet_by_id(5, None, &uri, &target, &[])
+            .await
+            .expect("workspace edit");
+        service
+            .record_usage(5, Some("/work/clientA"))
+            .expect("usage tracking enabled")
+            .await;
+        service
+            .add_snippet(&uri, range((0, 0), (0, 5)), "Assign", vec![])
+            .await
+            .expect("added snippet");
+
+        // Assert
+        assert!(matches!(
+            command,
+            Some(BkmrCommand::InsertSnippetById { folder: Some(folder), .. }) if folder == "/work/clientA"
+        ));
+        assert_eq!(inserted_edit(named, &uri).new_text, "a");
+        assert_eq!(inserted_edit(routed, &uri).new_text, "b");
+        assert_eq!(client_a.used_ids(), vec![5]);
+        assert!(client_b.used_ids().is_empty());
+        assert!(client_a.created_snippets().is_empty());
+        assert_eq!(client_b.created_snippets()[0].content, "x = 1");
     }
 
-    #[test]
-    fn given_html_file_when_inserting_filepath_comment_then_uses_html_comment() {
+    #[tokio::test]
+    async fn given_placeholder_snippet_when_inserting_by_id_then_inserts_defaults_at_position() {
         // Arrange
-        let file_uri = "file:///path/to/test.html";
+        let snippet = Snippet::new(
+            5,
+            "Function".to_string(),
+            "def ${1:name}():\n    ${0:pass}".to_string(),
+            String::new(),
+            vec!["python".to_string(), "_snip_".to_string()],
+        );
+        let (service, _, uri) = service_with_snippets("class A:\n    \n", vec![snippet]).await;
 
         // Act
-        let result = CommandService::insert_filepath_comment(file_uri);
+        let edit = service
+            .insert_snippet_by_id(5, None, &uri, &[range((1, 4), (1, 4))], &[])
+            .await
+            .expect("workspace edit");
 
         // Assert
-        assert!(result.is_ok());
-        let workspace_edit = result.expect("valid workspace edit");
+        let edit = inserted_edit(edit, &uri);
+        assert_eq!(edit.range, range((1, 4), (1, 4)));
+        assert_eq!(edit.new_text, "def name():\n        pass");
+    }
+
+    #[tokio::test]
+    async fn given_completed_snippet_when_inserting_by_id_then_uses_shared_id_cache() {
+        // Arrange
+        let snippet = Snippet::new(
+            6,
+            "Print".to_string(),
+            "print(${1:value})".to_string(),
+            String::new(),
+            vec!["python".to_string(), "_snip_".to_string()],
+        );
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+        let document_service = Arc::new(DocumentService::new());
+        document_service
+            .open_document(uri.to_string(), "python".to_string(), "pri".to_string())
+            .await
+            .expect("open document");
+        let ids = Arc::new(SnippetIdCache::new(10, std::time::Duration::from_secs(30)));
+        let repository = Arc::new(
+            MockSnippetRepository::new()
+                .with_snippets(vec![snippet])
+                .with_id_cache(ids),
+        );
+        let completion_service = CompletionService::new(repository.clone());
+        let service = CommandService::new(repository.clone(), document_service);
+        let context =
+            CompletionContext::new(uri.clone(), Position::new(0, 3), Some("python".to_string()))
+                .with_query(CompletionQuery::new(
+                    "pri".to_string(),
+                    range((0, 0), (0, 3)),
+                ));
+        completion_service
+            .get_completions(&context)
+            .await
+            .expect("completions");
+
+        // Act
+        let edit = service
+            .insert_snippet_by_id(6, None, &uri, &[range((0, 3), (0, 3))], &[])
+            .await
+            .expect("workspace edit");
+
+        // Assert
+        assert_eq!(inserted_edit(edit, &uri).new_text, "print(value)");
+        assert_eq!(repository.fetch_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn given_position_past_line_end_when_inserting_by_id_then_inserts_at_line_end() {
+        // Arrange
+        let snippet = Snippet::new(
+            5,
+            "Print".to_string(),
+            "print($1)".to_string(),
+            String::new(),
+            vec!["python".to_string(), "_snip_".to_string()],
+        );
+        let (service, _, uri) = service_with_snippets("x = 1\n", vec![snippet]).await;
+
+        // Act
+        let edit = service
+            .insert_snippet_by_id(5, None, &uri, &[range((0, 99), (0, 99))], &[])
+            .await
+            .expect("workspace edit");
+
+        // Assert
+        let edit = inserted_edit(edit, &uri);
+        assert_eq!(edit.range, range((0, 5), (0, 5)));
+        assert_eq!(edit.new_text, "print()");
+    }
+
+    #[tokio::test]
+    async fn given_three_cursors_when_inserting_by_id_then_returns_one_edit_each_bottom_to_top() {
+        // Arrange
+        let snippet = Snippet::new(
+            5,
+            "Print".to_string(),
+            "print($1)".to_string(),
+            String::new(),
+            vec!["python".to_string(), "_snip_".to_string()],
+        );
+        let (service, _, uri) = service_with_snippets("a = 1\nb = 2\nc = 3\n", vec![snippet]).await;
+        let targets = [
+            range((1, 5), (1, 5)),
+            range((0, 5), (0, 5)),
+            range((2, 4), (2, 5)),
+        ];
+
+        // Act
+        let edit = service
+            .insert_snippet_by_id(5, None, &uri, &targets, &[])
+            .await
+            .expect("workspace edit");
+
+        // Assert
+        let edits = edit
+            .changes
+            .expect("workspace changes")
+            .remove(&uri)
+            .expect("edits for document");
+        assert_eq!(
+            edits.iter().map(|edit| edit.range).collect::<Vec<_>>(),
+            vec![
+                range((2, 4), (2, 5)),
+                range((1, 5), (1, 5)),
+                range((0, 5), (0, 5))
+            ]
+        );
+        assert!(edits.iter().all(|edit| edit.new_text == "print()"));
+    }
+
+    #[tokio::test]
+    async fn given_overlapping_targets_when_inserting_by_id_then_rejects_them() {
+        // Arrange
+        let snippet = Snippet::new(
+            5,
+            "Print".to_string(),
+            "print($1)".to_string(),
+            String::new(),
+            vec!["python".to_string(), "_snip_".to_string()],
+        );
+        let (service, _, uri) = service_with_snippets("value = compute()\n", vec![snippet]).await;
+
+        // Act
+        let overlapping = service
+            .insert_snippet_by_id(
+                5,
+                None,
+                &uri,
+                &[range((0, 8), (0, 15)), range((0, 0), (0, 10))],
+                &[],
+            )
+            .await;
+        let same_cursor = service
+            .insert_snippet_by_id(
+                5,
+                None,
+                &uri,
+                &[range((0, 3), (0, 3)), range((0, 3), (0, 3))],
+                &[],
+            )
+            .await;
+        let adjacent = service
+            .insert_snippet_by_id(
+                5,
+                None,
+                &uri,
+                &[range((0, 0), (0, 5)), range((0, 5), (0, 8))],
+                &[],
+            )
+            .await;
+
+        // Assert
+        assert_eq!(
+            format!("{:#}", overlapping.unwrap_err()),
+            "Insert targets overlap: 0:0-0:10 and 0:8-0:15"
+        );
+        assert!(format!("{:#}", same_cursor.unwrap_err()).contains("Insert targets overlap"));
+        assert!(adjacent.is_ok());
+    }
+
+    #[tokio::test]
+    async fn given_targets_with_different_indentation_when_inserting_by_id_then_indents_each() {
+        // Arrange
+        let snippet = Snippet::new(
+            5,
+            "If".to_string(),
+            "if ${1:x}:\n    ${0:pass}".to_string(),
+            String::new(),
+            vec!["python".to_string(), "_snip_".to_string()],
+        );
+        let (service, _, uri) = service_with_snippets("\n    \n\t\t\n", vec![snippet]).await;
+        let targets = [
+            range((0, 0), (0, 0)),
+            range((1, 4), (1, 4)),
+            range((2, 2), (2, 2)),
+        ];
+
+        // Act
+        let edit = service
+            .insert_snippet_by_id(5, None, &uri, &targets, &[])
+            .await
+            .expect("workspace edit");
+
+        // Assert
+        let edits = edit
+            .changes
+            .expect("workspace changes")
+            .remove(&uri)
+            .expect("edits for document");
+        assert_eq!(
+            edits
+                .iter()
+                .map(|edit| edit.new_text.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "if x:\n\t\t    pass",
+                "if x:\n        pass",
+                "if x:\n    pass"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn given_unknown_id_or_unopened_document_when_inserting_by_id_then_returns_error() {
+        // Arrange
+        let (service, _, uri) = service_with_document("x = 1\n").await;
+        let other_uri = Url::parse("file:///other.py").expect("parse URI");
+
+        // Act
+        let unknown_id = service
+            .insert_snippet_by_id(99, None, &uri, &[range((0, 0), (0, 0))], &[])
+            .await;
+        let unopened = service
+            .insert_snippet_by_id(99, None, &other_uri, &[range((0, 0), (0, 0))], &[])
+            .await;
+
+        // Assert
+        assert!(format!("{:#}", unknown_id.unwrap_err()).contains("No snippet with id 99"));
+        assert!(format!("{:#}", unopened.unwrap_err()).contains("not open"));
+    }
+
+    #[tokio::test]
+    async fn given_insert_raw_command_when_executing_then_inserts_uninterpolated_template_verbatim()
+    {
+        // Arrange
+        let interpolated = Snippet::new(
+            5,
+            "Greeting".to_string(),
+            "echo alice $1".to_string(),
+            String::new(),
+            vec!["sh".to_string(), "_snip_".to_string()],
+        );
+        let mut raw = interpolated.clone();
+        raw.content = r#"echo {{ env("USER") }} $1"#.to_string();
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+        let document_service = Arc::new(DocumentService::new());
+        document_service
+            .open_document(uri.to_string(), "python".to_string(), "    \n".to_string())
+            .await
+            .expect("open document");
+        let repository = Arc::new(
+            MockSnippetRepository::new()
+                .with_snippets(vec![interpolated])
+                .with_raw_snippets(vec![raw]),
+        );
+        let service = CommandService::new(repository, document_service);
+        let arguments = [json!({
+            "id": {"id": 5, "interpolated": true},
+            "uri": uri.as_str(),
+            "position": {"line": 0, "character": 4}
+        })];
+
+        // Act
+        let command = CommandService::parse_command(INSERT_RAW, &arguments)
+            .expect("parsed command")
+            .expect("known command");
+        let BkmrCommand::InsertRaw {
+            id,
+            folder,
+            uri,
+            position,
+        } = command
+        else {
+            panic!("expected insertRaw, got {:?}", command);
+        };
+        let edit = service
+            .insert_raw(id, folder.as_deref(), &uri, position)
+            .await
+            .expect("workspace edit");
+        let unknown = service.insert_raw(99, None, &uri, position).await;
+
+        // Assert
+        let edit = inserted_edit(edit, &uri);
+        assert_eq!(edit.range, range((0, 4), (0, 4)));
+        assert_eq!(edit.new_text, r#"echo {{ env("USER") }} $1"#);
+        assert!(format!("{:#}", unknown.unwrap_err()).contains("No snippet with id 99"));
+    }
 
-        let changes = workspace_edit.changes.expect("workspace changes");
-        let edits = changes.values().next().expect("text edits");
-        let edit = &edits[0];
+    fn filepath_comment_edit(file_uri: &str, text: &str, language_id: Option<&str>) -> TextEdit {
+        let result = CommandService::filepath_comment(
+            file_uri,
+            text,
+            language_id,
+            &[],
+            PathSeparator::Slash,
+            PositionEncoding::Utf16,
+            &LanguageRegistry::default(),
+        );
+        match result.expect("filepath comment") {
+            FilepathComment::Insert(edit) => {
+                inserted_edit(edit, &Url::parse(file_uri).expect("parse URI"))
+            }
+            FilepathComment::AlreadyPresent | FilepathComment::NotSaved => {
+                panic!("expected a filepath comment to insert")
+            }
+        }
+    }
+
+    #[test]
+    fn given_rust_file_when_inserting_filepath_comment_then_uses_double_slash() {
+        // Act
+        let edit = filepath_comment_edit("file:///path/to/test.rs", "", None);
+
+        // Assert
+        assert!(edit.new_text.starts_with("// "));
+        assert!(edit.new_text.contains("test.rs"));
+    }
+
+    #[test]
+    fn given_html_file_when_inserting_filepath_comment_then_uses_html_comment() {
+        // Act
+        let edit = filepath_comment_edit("file:///path/to/test.html", "", None);
 
+        // Assert
         assert!(edit.new_text.starts_with("<!-- "));
         assert!(edit.new_text.ends_with(" -->\n"));
         assert!(edit.new_text.contains("test.html"));
@@ -138,22 +2480,156 @@ mod tests {
 
     #[test]
     fn given_python_file_when_inserting_filepath_comment_then_uses_hash() {
+        // Act
+        let edit = filepath_comment_edit("file:///path/to/test.py", "", None);
+
+        // Assert
+        assert!(edit.new_text.starts_with("# "));
+        assert!(edit.new_text.contains("test.py"));
+    }
+
+    #[test]
+    fn given_empty_file_when_inserting_filepath_comment_then_inserts_at_start() {
+        // Act
+        let edit = filepath_comment_edit("file:///path/to/empty.rs", "", None);
+
+        // Assert
+        assert_eq!(edit.range, range((0, 0), (0, 0)));
+        assert_eq!(edit.new_text, "// empty.rs\n");
+    }
+
+    #[test]
+    fn given_shebang_script_when_inserting_filepath_comment_then_inserts_below_shebang() {
+        // Act
+        let edit = filepath_comment_edit(
+            "file:///path/to/deploy.sh",
+            "#!/usr/bin/env bash\nset -e\n",
+            Some("shellscript"),
+        );
+        let single_line = filepath_comment_edit(
+            "file:///path/to/deploy.sh",
+            "#!/usr/bin/env bash",
+            Some("shellscript"),
+        );
+
+        // Assert
+        assert_eq!(edit.range, range((1, 0), (1, 0)));
+        assert_eq!(edit.new_text, "# deploy.sh\n");
+        assert_eq!(single_line.range, range((0, 19), (0, 19)));
+        assert_eq!(single_line.new_text, "\n# deploy.sh");
+    }
+
+    #[test]
+    fn given_xml_declaration_when_inserting_filepath_comment_then_inserts_below_declaration() {
+        // Act
+        let edit = filepath_comment_edit(
+            "file:///path/to/pom.xml",
+            "<?xml version=\"1.0\"\n  encoding=\"UTF-8\"?>\n<project/>\n",
+            Some("xml"),
+        );
+
+        // Assert
+        assert_eq!(edit.range, range((2, 0), (2, 0)));
+        assert_eq!(edit.new_text, "<!-- pom.xml -->\n");
+    }
+
+    #[test]
+    fn given_byte_order_mark_when_inserting_filepath_comment_then_inserts_after_it() {
+        // Act
+        let edit = filepath_comment_edit(
+            "file:///path/to/main.py",
+            "\u{feff}import os\n",
+            Some("python"),
+        );
+
+        // Assert
+        assert_eq!(edit.range, range((0, 1), (0, 1)));
+        assert_eq!(edit.new_text, "# main.py\n");
+    }
+
+    #[test]
+    fn given_path_in_first_lines_when_inserting_filepath_comment_then_reports_already_present() {
+        // Act
+        let result = CommandService::filepath_comment(
+            "file:///path/to/deploy.sh",
+            "#!/usr/bin/env bash\n# deploy.sh\nset -e\n",
+            Some("shellscript"),
+            &[],
+            PathSeparator::Slash,
+            PositionEncoding::Utf16,
+            &LanguageRegistry::default(),
+        );
+
+        // Assert
+        assert_eq!(
+            result.expect("filepath comment"),
+            FilepathComment::AlreadyPresent
+        );
+    }
+
+    #[test]
+    fn given_document_language_when_inserting_filepath_comment_then_it_wins_over_extension() {
+        // Act
+        let edit = filepath_comment_edit("file:///path/to/build.txt", "", Some("rust"));
+        let vim = filepath_comment_edit("file:///path/to/init.txt", "", Some("vim"));
+
+        // Assert
+        assert_eq!(edit.new_text, "// build.txt\n");
+        assert_eq!(vim.new_text, "\" init.txt\n");
+    }
+
+    #[tokio::test]
+    async fn given_open_document_when_inserting_filepath_comment_then_uses_its_text() {
         // Arrange
-        let file_uri = "file:///path/to/test.py";
+        let (service, _, uri) = service_with_document("#!/bin/sh\necho hi\n").await;
 
         // Act
-        let result = CommandService::insert_filepath_comment(file_uri);
+        let result = service
+            .insert_filepath_comment(uri.as_str(), &[])
+            .await
+            .expect("filepath comment");
 
         // Assert
-        assert!(result.is_ok());
-        let workspace_edit = result.expect("valid workspace edit");
+        let FilepathComment::Insert(edit) = result else {
+            panic!("expected a filepath comment to insert");
+        };
+        assert_eq!(inserted_edit(edit, &uri).range, range((1, 0), (1, 0)));
+    }
 
-        let changes = workspace_edit.changes.expect("workspace changes");
-        let edits = changes.values().next().expect("text edits");
-        let edit = &edits[0];
+    #[tokio::test]
+    async fn given_untitled_buffer_when_inserting_filepath_comment_then_names_it_by_language() {
+        // Arrange
+        let uri = Url::parse("untitled:Untitled-1").expect("parse URI");
+        let document_service = Arc::new(DocumentService::new());
+        document_service
+            .open_document(
+                uri.to_string(),
+                "python".to_string(),
+                "print(1)\n".to_string(),
+            )
+            .await
+            .expect("open document");
+        let repository = Arc::new(MockSnippetRepository::new());
+        let service = CommandService::new(repository.clone(), document_service.clone());
+        let reporting =
+            CommandService::new(repository, document_service).with_untitled_filepath_comment(false);
 
-        assert!(edit.new_text.starts_with("# "));
-        assert!(edit.new_text.contains("test.py"));
+        // Act
+        let inserted = service
+            .insert_filepath_comment(uri.as_str(), &[])
+            .await
+            .expect("filepath comment");
+        let reported = reporting
+            .insert_filepath_comment(uri.as_str(), &[])
+            .await
+            .expect("filepath comment");
+
+        // Assert
+        let FilepathComment::Insert(edit) = inserted else {
+            panic!("expected a filepath comment to insert");
+        };
+        assert_eq!(inserted_edit(edit, &uri).new_text, "# untitled-1.py\n");
+        assert!(matches!(reported, FilepathComment::NotSaved));
     }
 
     #[test]
@@ -162,7 +2638,15 @@ mod tests {
         let file_uri = "invalid-uri";
 
         // Act
-        let result = CommandService::insert_filepath_comment(file_uri);
+        let result = CommandService::filepath_comment(
+            file_uri,
+            "",
+            None,
+            &[],
+            PathSeparator::Slash,
+            PositionEncoding::Utf16,
+            &LanguageRegistry::default(),
+        );
 
         // Assert
         assert!(result.is_err());
@@ -178,11 +2662,196 @@ mod tests {
         let file_uri = "file:///some/deep/path/test.rs";
 
         // Act
-        let result = CommandService::get_relative_path(file_uri);
+        let result = CommandService::get_relative_path(
+            file_uri,
+            None,
+            &[],
+            PathSeparator::Slash,
+            &LanguageRegistry::default(),
+        );
 
         // Assert
         assert!(result.is_ok());
         let path = result.expect("valid relative path");
         assert_eq!(path, "test.rs"); // Should fall back to filename
     }
+
+    fn folders(uris: &[&str]) -> Vec<Url> {
+        uris.iter()
+            .map(|uri| Url::parse(uri).expect("parse folder URI"))
+            .collect()
+    }
+
+    #[test]
+    fn given_nested_workspace_folders_when_getting_relative_path_then_uses_innermost_folder() {
+        // Arrange
+        let workspace_folders = folders(&["file:///repo", "file:///repo/frontend"]);
+        let file_uri = Url::parse("file:///repo/frontend/src/app.ts").expect("parse URI");
+        let backend_uri = Url::parse("file:///repo/backend/main.rs").expect("parse URI");
+
+        // Act
+        let frontend = CommandService::workspace_relative_path(
+            &file_uri,
+            &workspace_folders,
+            PathSeparator::Slash,
+        );
+        let backend = CommandService::workspace_relative_path(
+            &backend_uri,
+            &workspace_folders,
+            PathSeparator::Slash,
+        );
+
+        // Assert
+        assert_eq!(frontend.as_deref(), Some("src/app.ts"));
+        assert_eq!(backend.as_deref(), Some("backend/main.rs"));
+    }
+
+    #[test]
+    fn given_file_outside_workspace_folders_when_getting_relative_path_then_falls_back() {
+        // Arrange
+        let workspace_folders = folders(&["file:///repo", "file:///repository-two"]);
+        let file_uri = "file:///repository/deep/path/test.rs";
+
+        // Act
+        let relative = CommandService::workspace_relative_path(
+            &Url::parse(file_uri).expect("parse URI"),
+            &workspace_folders,
+            PathSeparator::Slash,
+        );
+        let path = CommandService::get_relative_path(
+            file_uri,
+            None,
+            &workspace_folders,
+            PathSeparator::Slash,
+            &LanguageRegistry::default(),
+        )
+        .expect("valid relative path");
+
+        // Assert
+        assert_eq!(relative, None);
+        assert_eq!(path, "test.rs");
+    }
+
+    #[test]
+    fn given_windows_drive_letters_in_uris_when_getting_relative_path_then_ignores_case() {
+        // Arrange
+        let workspace_folders = folders(&["file:///c%3A/work/repo"]);
+        let file_uri = Url::parse("file:///C:/work/repo/src/main.rs").expect("parse URI");
+
+        // Act
+        let relative = CommandService::workspace_relative_path(
+            &file_uri,
+            &workspace_folders,
+            PathSeparator::Slash,
+        );
+
+        // Assert
+        assert_eq!(relative.as_deref(), Some("src/main.rs"));
+    }
+
+    #[test]
+    fn given_encoded_spaces_and_drive_letter_when_inserting_filepath_comment_then_uses_slashes() {
+        // Arrange
+        let workspace_folders = folders(&["file:///C:/Users/John%20Doe/my%20repo"]);
+        let file_uri = "file:///c%3A/Users/John%20Doe/my%20repo/src/app%20main.rs";
+
+        // Act
+        let slash = CommandService::filepath_comment(
+            file_uri,
+            "",
+            None,
+            &workspace_folders,
+            PathSeparator::Slash,
+            PositionEncoding::Utf16,
+            &LanguageRegistry::default(),
+        );
+        let native = CommandService::workspace_relative_path(
+            &Url::parse(file_uri).expect("parse URI"),
+            &workspace_folders,
+            PathSeparator::Native,
+        );
+
+        // Assert
+        match slash.expect("filepath comment") {
+            FilepathComment::Insert(edit) => assert_eq!(
+                inserted_edit(edit, &Url::parse(file_uri).expect("parse URI")).new_text,
+                "// src/app main.rs\n"
+            ),
+            FilepathComment::AlreadyPresent | FilepathComment::NotSaved => {
+                panic!("expected a filepath comment to insert")
+            }
+        }
+        assert_eq!(
+            native.as_deref(),
+            Some(
+                ["src", "app main.rs"]
+                    .join(std::path::MAIN_SEPARATOR_STR)
+                    .as_str()
+            )
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn given_windows_project_file_when_getting_relative_path_then_uses_forward_slashes() {
+        // Arrange
+        let root = std::env::temp_dir().join("bkmr-lsp windows path");
+        let file = root.join("src").join("main.rs");
+        std::fs::create_dir_all(root.join(".git")).expect("create project root");
+        let file_uri = Url::from_file_path(&file).expect("file URI");
+
+        // Act
+        let slash = CommandService::get_relative_path(
+            file_uri.as_str(),
+            None,
+            &[],
+            PathSeparator::Slash,
+            &LanguageRegistry::default(),
+        );
+        let native = CommandService::get_relative_path(
+            file_uri.as_str(),
+            None,
+            &[],
+            PathSeparator::Native,
+            &LanguageRegistry::default(),
+        );
+
+        // Assert
+        assert_eq!(slash.expect("relative path"), "src/main.rs");
+        assert_eq!(native.expect("relative path"), "src\\main.rs");
+    }
+
+    #[tokio::test]
+    async fn given_requested_kinds_when_building_code_actions_then_filters_by_kind() {
+        // Arrange
+        let (service, _, uri) = service_with_document("let x = 1;").await;
+        let selection = range((0, 0), (0, 10));
+
+        // Act
+        let source_only = service
+            .code_actions(&uri, selection, Some(&[CodeActionKind::SOURCE]))
+            .await;
+        let refactor_only = service
+            .code_actions(&uri, selection, Some(&[CodeActionKind::REFACTOR]))
+            .await;
+        let all = service.code_actions(&uri, selection, None).await;
+
+        // Assert
+        assert_eq!(source_only.len(), 1);
+        assert!(refactor_only.is_empty());
+        assert_eq!(all.len(), 2);
+    }
+
+    #[test]
+    fn given_selection_when_proposing_title_then_uses_first_non_empty_line() {
+        // Act
+        let title = CommandService::selection_title("\n   let value = compute();\nmore");
+        let long = CommandService::selection_title(&"x".repeat(100));
+        let blank = CommandService::selection_title("  \n ");
+
+        // Assert
+        assert_eq!(title, "let value = compute();");
+        assert_eq!(long.chars().count(), MAX_SELECTION_TITLE_CHARS);
+        assert_eq!(blank, "Untitled snippet");
+    }
 }
@@ -0,0 +1,320 @@
+use serde_json::{Map, Value};
+use tower_lsp::lsp_types::{CompletionClientCapabilities, InsertTextMode, MarkupKind};
+
+/// What a client's completion capabilities allow beyond plain completion items
+///
+/// Everything defaults to the assumptions made for clients that advertise
+/// nothing: markdown documentation, single-range text edits and no item
+/// defaults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCompletionSupport {
+    /// Snippet syntax in insert text
+    pub snippets: bool,
+    /// Edits with separate insert and replace ranges (InsertReplaceEdit)
+    pub insert_replace: bool,
+    /// Markdown documentation, false when documentationFormat lacks it
+    pub markdown_documentation: bool,
+    /// Re-indenting inserted lines itself (insertTextMode adjustIndentation)
+    pub adjusts_indentation: bool,
+    /// Item properties filled in lazily via completionItem/resolve
+    pub resolve_properties: Vec<String>,
+    /// Properties the client accepts in CompletionList.itemDefaults
+    pub item_defaults: Vec<String>,
+}
+
+impl Default for ClientCompletionSupport {
+    fn default() -> Self {
+        Self {
+            snippets: false,
+            insert_replace: false,
+            markdown_documentation: true,
+            adjusts_indentation: false,
+            resolve_properties: Vec::new(),
+            item_defaults: Vec::new(),
+        }
+    }
+}
+
+impl ClientCompletionSupport {
+    /// Read the support from textDocument.completion of the client capabilities
+    pub fn from_capabilities(capabilities: Option<&CompletionClientCapabilities>) -> Self {
+        let Some(capabilities) = capabilities else {
+            return Self::default();
+        };
+        let completion_item = capabilities.completion_item.as_ref();
+
+        Self {
+            snippets: completion_item
+                .and_then(|item| item.snippet_support)
+                .unwrap_or(false),
+            insert_replace: completion_item
+                .and_then(|item| item.insert_replace_support)
+                .unwrap_or(false),
+            // Without a stated preference markdown is assumed, as before
+            markdown_documentation: completion_item
+                .and_then(|item| item.documentation_format.as_ref())
+                .is_none_or(|formats| formats.contains(&MarkupKind::Markdown)),
+            adjusts_indentation: completion_item
+                .and_then(|item| item.insert_text_mode_support.as_ref())
+                .is_some_and(|support| {
+                    support
+                        .value_set
+                        .contains(&InsertTextMode::ADJUST_INDENTATION)
+                }),
+            resolve_properties: completion_item
+                .and_then(|item| item.resolve_support.as_ref())
+                .map(|support| support.properties.clone())
+                .unwrap_or_default(),
+            item_defaults: capabilities
+                .completion_list
+                .as_ref()
+                .and_then(|list| list.item_defaults.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Whether the client accepts the given property in CompletionList.itemDefaults
+    pub fn accepts_item_default(&self, property: &str) -> bool {
+        self.item_defaults.iter().any(|p| p == property)
+    }
+}
+
+/// Move values shared by the items of a serialized CompletionList into itemDefaults
+///
+/// lsp-types 0.94 has no CompletionList.itemDefaults, so this works on the
+/// JSON sent to the client. The edit range is hoisted when every item edits
+/// the same range, each item keeping its text as textEditText. The insert
+/// text format is hoisted with the value most items use, items with the
+/// other one keep theirs. Anything but a list with items is left alone.
+pub fn hoist_item_defaults(list: &mut Value, support: &ClientCompletionSupport) {
+    let Some(Value::Array(items)) = list.get_mut("items") else {
+        return;
+    };
+    if items.is_empty() {
+        return;
+    }
+
+    let mut defaults = Map::new();
+    if support.accepts_item_default("editRange")
+        && let Some(edit_range) = hoist_edit_range(items)
+    {
+        defaults.insert("editRange".to_string(), edit_range);
+    }
+    if support.accepts_item_default("insertTextFormat")
+        && let Some(format) = hoist_common_value(items, "insertTextFormat")
+    {
+        defaults.insert("insertTextFormat".to_string(), format);
+    }
+
+    if !defaults.is_empty() {
+        list["itemDefaults"] = Value::Object(defaults);
+    }
+}
+
+/// Range shared by the text edits of all items, replacing each edit by its new text
+fn hoist_edit_range(items: &mut [Value]) -> Option<Value> {
+    let edit_range = |item: &Value| -> Option<Value> {
+        let edit = item.get("textEdit")?;
+        match (edit.get("range"), edit.get("insert"), edit.get("replace")) {
+            (Some(range), _, _) => Some(range.clone()),
+            (None, Some(insert), Some(replace)) => {
+                Some(serde_json::json!({ "insert": insert, "replace": replace }))
+            }
+            _ => None,
+        }
+    };
+
+    let range = edit_range(items.first()?)?;
+    if !items
+        .iter()
+        .all(|item| edit_range(item).as_ref() == Some(&range))
+    {
+        return None;
+    }
+
+    for item in items.iter_mut() {
+        let Some(item) = item.as_object_mut() else {
+            continue;
+        };
+        if let Some(new_text) = item
+            .remove("textEdit")
+            .and_then(|edit| edit.get("newText").cloned())
+        {
+            item.insert("textEditText".to_string(), new_text);
+        }
+    }
+    Some(range)
+}
+
+/// Value of the property most items share, removed from the items holding it
+fn hoist_common_value(items: &mut [Value], property: &str) -> Option<Value> {
+    let values: Vec<&Value> = items.iter().filter_map(|item| item.get(property)).collect();
+    let common = values
+        .iter()
+        .max_by_key(|value| values.iter().filter(|other| other == value).count())
+        .map(|value| (*value).clone())?;
+
+    for item in items.iter_mut() {
+        if item.get(property) == Some(&common)
+            && let Some(item) = item.as_object_mut()
+        {
+            item.remove(property);
+        }
+    }
+    Some(common)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tower_lsp::lsp_types::{
+        CompletionItemCapability, CompletionListCapability, InsertTextModeSupport,
+    };
+
+    fn support_with_defaults(item_defaults: &[&str]) -> ClientCompletionSupport {
+        ClientCompletionSupport {
+            item_defaults: item_defaults.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn edit(new_text: &str) -> Value {
+        json!({
+            "range": {
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": 0, "character": 3 }
+            },
+            "newText": new_text
+        })
+    }
+
+    #[test]
+    fn given_no_capabilities_when_reading_support_then_uses_conservative_defaults() {
+        // Act
+        let support = ClientCompletionSupport::from_capabilities(None);
+        let empty = ClientCompletionSupport::from_capabilities(Some(
+            &CompletionClientCapabilities::default(),
+        ));
+
+        // Assert
+        assert_eq!(support, ClientCompletionSupport::default());
+        assert_eq!(empty, ClientCompletionSupport::default());
+        assert!(support.markdown_documentation);
+        assert!(!support.insert_replace);
+        assert!(support.item_defaults.is_empty());
+    }
+
+    #[test]
+    fn given_full_capabilities_when_reading_support_then_maps_every_detail() {
+        // Arrange
+        let capabilities = CompletionClientCapabilities {
+            completion_item: Some(CompletionItemCapability {
+                snippet_support: Some(true),
+                insert_replace_support: Some(true),
+                documentation_format: Some(vec![MarkupKind::PlainText]),
+                insert_text_mode_support: Some(InsertTextModeSupport {
+                    value_set: vec![InsertTextMode::ADJUST_INDENTATION],
+                }),
+                ..Default::default()
+            }),
+            completion_list: Some(CompletionListCapability {
+                item_defaults: Some(vec![
+                    "editRange".to_string(),
+                    "insertTextFormat".to_string(),
+                ]),
+            }),
+            ..Default::default()
+        };
+
+        // Act
+        let support = ClientCompletionSupport::from_capabilities(Some(&capabilities));
+
+        // Assert
+        assert!(support.snippets);
+        assert!(support.insert_replace);
+        assert!(!support.markdown_documentation);
+        assert!(support.adjusts_indentation);
+        assert!(support.accepts_item_default("editRange"));
+        assert!(support.accepts_item_default("insertTextFormat"));
+        assert!(!support.accepts_item_default("data"));
+    }
+
+    #[test]
+    fn given_shared_edit_range_when_hoisting_then_items_keep_only_their_text() {
+        // Arrange
+        let mut list = json!({
+            "isIncomplete": false,
+            "items": [
+                { "label": "a", "textEdit": edit("alpha"), "insertTextFormat": 2 },
+                { "label": "b", "textEdit": edit("beta"), "insertTextFormat": 2 },
+                { "label": "c", "textEdit": edit("gamma"), "insertTextFormat": 1 }
+            ]
+        });
+
+        // Act
+        hoist_item_defaults(
+            &mut list,
+            &support_with_defaults(&["editRange", "insertTextFormat"]),
+        );
+
+        // Assert
+        assert_eq!(list["itemDefaults"]["editRange"], edit("")["range"]);
+        assert_eq!(list["itemDefaults"]["insertTextFormat"], json!(2));
+        assert_eq!(
+            list["items"][0],
+            json!({ "label": "a", "textEditText": "alpha" })
+        );
+        assert_eq!(list["items"][2]["insertTextFormat"], json!(1));
+    }
+
+    #[test]
+    fn given_insert_replace_edits_when_hoisting_then_default_holds_both_ranges() {
+        // Arrange
+        let insert_replace = |new_text: &str| {
+            json!({
+                "insert": { "start": { "line": 1, "character": 0 }, "end": { "line": 1, "character": 2 } },
+                "replace": { "start": { "line": 1, "character": 0 }, "end": { "line": 1, "character": 5 } },
+                "newText": new_text
+            })
+        };
+        let mut list = json!({
+            "isIncomplete": true,
+            "items": [{ "label": "a", "textEdit": insert_replace("alpha") }]
+        });
+
+        // Act
+        hoist_item_defaults(&mut list, &support_with_defaults(&["editRange"]));
+
+        // Assert
+        assert_eq!(
+            list["itemDefaults"]["editRange"]["replace"]["end"]["character"],
+            json!(5)
+        );
+        assert_eq!(list["items"][0]["textEditText"], json!("alpha"));
+    }
+
+    #[test]
+    fn given_differing_ranges_or_no_support_when_hoisting_then_list_is_unchanged() {
+        // Arrange
+        let mut other_range = edit("beta");
+        other_range["range"]["end"]["character"] = json!(4);
+        let original = json!({
+            "isIncomplete": false,
+            "items": [
+                { "label": "a", "textEdit": edit("alpha") },
+                { "label": "b", "textEdit": other_range }
+            ]
+        });
+        let mut differing = original.clone();
+        let mut unsupported = original.clone();
+
+        // Act
+        hoist_item_defaults(&mut differing, &support_with_defaults(&["editRange"]));
+        hoist_item_defaults(&mut unsupported, &ClientCompletionSupport::default());
+
+        // Assert
+        assert_eq!(differing, original);
+        assert_eq!(unsupported, original);
+    }
+}
@@ -1,20 +1,90 @@
 use anyhow::{Context, Result};
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
 use tower_lsp::lsp_types::{
-    CompletionItem, CompletionItemKind, CompletionTextEdit, Documentation, InsertTextFormat,
-    TextEdit,
+    Command, CompletionItem, CompletionItemKind, CompletionItemLabelDetails, CompletionList,
+    CompletionTextEdit, InsertTextFormat, Position, Range, TextEdit, Url,
 };
-use tracing::{debug, instrument};
+use tracing::{debug, instrument, warn};
 
 use crate::backend::BkmrConfig;
-use crate::domain::{CompletionContext, Snippet, SnippetFilter};
+use crate::domain::{
+    CompletionContext, CompletionItemData, CompletionTier, DEFAULT_ACTION_TAGS,
+    InlineCompletionItem, InlineInsertText, LanguageOverrides, LanguageRegistry, RankingStrategy,
+    Snippet, SnippetFilter, SnippetImports, TagCount, rank_snippets,
+};
+use crate::metrics::{Metrics, Stage};
 use crate::repositories::SnippetRepository;
-use crate::services::LanguageTranslator;
+use crate::services::{
+    ClientCompletionSupport, PresentationContext, RECORD_USAGE, SnippetPresenter,
+};
+use crate::sync;
+
+/// Extra snippets fetched so that dropping duplicates still fills the completion limit
+const DUPLICATE_HEADROOM: usize = 10;
+/// Characters a query needs before an inline completion is offered, shorter ones are noise
+pub const INLINE_MIN_QUERY_CHARS: usize = 3;
+/// Characters a query needs before bookmarks are searched for URL completions
+pub const BOOKMARK_MIN_QUERY_CHARS: usize = 3;
+/// Detail of completion items inserting a bookmark's URL
+const BOOKMARK_DETAIL: &str = "bkmr bookmark";
+/// Detail of completion items inserting a bkmr tag into a frontmatter
+const TAG_DETAIL: &str = "bkmr tag";
+/// Detail of the most used snippets offered when nothing matches the query
+const FALLBACK_DETAIL: &str = "bkmr snippet (no prefix match)";
+/// Number of most used snippets offered when nothing matches the query
+pub const FALLBACK_COMPLETIONS: usize = 10;
+/// Default characters of a snippet description shown next to its completion label
+pub const DEFAULT_DESCRIPTION_MAX_LENGTH: usize = 40;
+/// Default milliseconds between two repository searches for one document
+pub const DEFAULT_MIN_QUERY_INTERVAL_MS: u64 = 75;
+
+/// What an inline completion was computed for, a repeated request gets the same answer
+#[derive(Debug, Clone, PartialEq)]
+struct InlineRequest {
+    position: tower_lsp::lsp_types::Position,
+    query: String,
+    tag_filter: Option<String>,
+}
+
+/// Last repository search for a document, narrowed for requests following it closely
+#[derive(Debug, Clone)]
+struct DocumentFetch {
+    fetched_at: Instant,
+    filter: SnippetFilter,
+    /// Ranked as the repository returned them
+    snippets: Vec<Snippet>,
+    /// Whether the snippets are every match of the filter
+    complete: bool,
+}
 
 /// Service for handling completion logic
 pub struct CompletionService {
     repository: Arc<dyn SnippetRepository>,
     config: BkmrConfig,
+    /// Languages restricting, translating and escaping the offered snippets
+    language_registry: Arc<LanguageRegistry>,
+    /// Snippets returned by the most recent completion request, keyed by id
+    last_snippets: RwLock<HashMap<i32, Snippet>>,
+    /// Completion features of the client, read from its capabilities at initialize
+    client_support: RwLock<ClientCompletionSupport>,
+    /// Last repository result that held every match, reused to narrow longer queries
+    complete_fetch: RwLock<Option<(SnippetFilter, Vec<Snippet>)>>,
+    /// Number of the last completion request, increasing across documents
+    request_generation: AtomicU64,
+    /// Newest completion request per document URI, older ones are abandoned
+    latest_requests: Mutex<HashMap<String, watch::Sender<u64>>>,
+    /// Last repository search per document URI, see `min_query_interval_ms`
+    document_fetches: Mutex<HashMap<String, DocumentFetch>>,
+    /// Last inline completion per document URI, answered again while the user pauses at the same spot
+    last_inline: Mutex<HashMap<String, (InlineRequest, Option<InlineCompletionItem>)>>,
+    /// Workspace folders `{{ filepath }}` is relative to
+    workspace_folders: RwLock<Vec<Url>>,
+    /// Latency of the fetch and item stages, shared with the backend
+    metrics: Arc<Metrics>,
 }
 
 impl std::fmt::Debug for CompletionService {
@@ -32,7 +102,70 @@ impl CompletionService {
     }
 
     pub fn with_config(repository: Arc<dyn SnippetRepository>, config: BkmrConfig) -> Self {
-        Self { repository, config }
+        Self {
+            repository,
+            config,
+            language_registry: Arc::new(LanguageRegistry::default()),
+            last_snippets: RwLock::new(HashMap::new()),
+            client_support: RwLock::new(ClientCompletionSupport::default()),
+            complete_fetch: RwLock::new(None),
+            request_generation: AtomicU64::new(0),
+            latest_requests: Mutex::new(HashMap::new()),
+            document_fetches: Mutex::new(HashMap::new()),
+            last_inline: Mutex::new(HashMap::new()),
+            workspace_folders: RwLock::new(Vec::new()),
+            metrics: Arc::new(Metrics::default()),
+        }
+    }
+
+    /// Resolve languages with the given registry instead of the built-in one
+    pub fn with_language_registry(mut self, language_registry: Arc<LanguageRegistry>) -> Self {
+        self.language_registry = language_registry;
+        self
+    }
+
+    /// Record stage latencies in the shared metrics
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Set the workspace folders snippet file paths are relative to
+    pub fn set_workspace_folders(&self, folders: Vec<Url>) {
+        *sync::write(&self.workspace_folders, "workspace folders") = folders;
+    }
+
+    /// Forget results kept between requests, returning how many were dropped
+    pub fn clear_cache(&self) -> usize {
+        let complete_fetch = sync::write(&self.complete_fetch, "complete fetch").take();
+        let last_inline = std::mem::take(&mut *sync::lock(&self.last_inline, "last inline"));
+        let document_fetches =
+            std::mem::take(&mut *sync::lock(&self.document_fetches, "document fetches"));
+        usize::from(complete_fetch.is_some()) + last_inline.len() + document_fetches.len()
+    }
+
+    /// Drop the inline completion kept for a closed document
+    pub fn close_document(&self, uri: &str) {
+        sync::lock(&self.last_inline, "last inline").remove(uri);
+    }
+
+    /// Set the completion features the client advertised
+    pub fn set_client_support(&self, support: ClientCompletionSupport) {
+        debug!("Client completion support: {:?}", support);
+        *sync::write(&self.client_support, "client support") = support;
+    }
+
+    /// Completion features the client advertised
+    pub fn client_support(&self) -> ClientCompletionSupport {
+        sync::read(&self.client_support, "client support").clone()
+    }
+
+    /// Check whether the client resolves the given item property lazily
+    fn resolves_lazily(&self, property: &str) -> bool {
+        sync::read(&self.client_support, "client support")
+            .resolve_properties
+            .iter()
+            .any(|p| p == property)
     }
 
     /// Generate completion items from context
@@ -41,102 +174,865 @@ impl CompletionService {
         &self,
         context: &CompletionContext,
     ) -> Result<Vec<CompletionItem>> {
+        Ok(self.get_completion_list(context, false).await?.items)
+    }
+
+    /// Generate one page of completion items, incomplete when more results may exist
+    ///
+    /// With `narrow` set, as for re-triggered incomplete completions, a longer
+    /// query is answered from the previous result if that held every match.
+    #[instrument(skip(self))]
+    pub async fn get_completion_list(
+        &self,
+        context: &CompletionContext,
+        narrow: bool,
+    ) -> Result<CompletionList> {
+        self.completion_list(context, narrow, false).await
+    }
+
+    /// Completion list, with `debounce` searching the repository at most once per interval and document
+    async fn completion_list(
+        &self,
+        context: &CompletionContext,
+        narrow: bool,
+        debounce: bool,
+    ) -> Result<CompletionList> {
         let filter = self.build_snippet_filter(context);
+        let bookmark_filter = self.bookmark_filter(context);
+        let document = debounce.then_some(context.uri.as_str());
+
+        let (fetched, bookmarks, fallback) = self
+            .metrics
+            .time(Stage::Fetch, async {
+                let (fetched, bookmarks) = tokio::join!(
+                    self.fetch_completion_snippets(&filter, narrow, document),
+                    self.fetch_bookmarks(bookmark_filter.as_ref())
+                );
+                let fallback = match &fetched {
+                    Ok((snippets, _)) if snippets.is_empty() => self.fetch_fallback(context).await,
+                    _ => Vec::new(),
+                };
+                (fetched, bookmarks, fallback)
+            })
+            .await;
+        let (snippets, complete) = fetched?;
+        let bookmarks_complete = bookmark_filter
+            .as_ref()
+            .is_none_or(|filter| bookmarks.len() < filter.max_results);
+
+        self.metrics.measure(Stage::Items, || {
+            self.build_completion_list(
+                context,
+                snippets,
+                complete && bookmarks_complete,
+                &bookmarks,
+                fallback,
+            )
+        })
+    }
+
+    /// Rank, translate and page the fetched snippets, followed by the bookmarks and fallback snippets
+    fn build_completion_list(
+        &self,
+        context: &CompletionContext,
+        snippets: Vec<Snippet>,
+        complete: bool,
+        bookmarks: &[Snippet],
+        fallback: Vec<Snippet>,
+    ) -> Result<CompletionList> {
+        let mut snippets = self.ranked_snippets(context, snippets);
+        let page_size = self
+            .config
+            .page_size
+            .min(self.max_completions(context.language_id.as_deref()));
+        let is_incomplete = !complete || snippets.len() > page_size;
+        snippets.truncate(page_size);
+
+        // Most used first, shown while the query matches nothing
+        let mut fallback = rank_snippets(
+            self.ranked_snippets(context, fallback),
+            None,
+            RankingStrategy::Usage,
+        );
+        fallback.truncate(FALLBACK_COMPLETIONS);
+        // Typing on has to fetch again, the query filters none of them
+        let is_incomplete = is_incomplete || !fallback.is_empty();
+        let fallback_start = snippets.len();
+        snippets.extend(fallback);
+
+        let labels = Self::completion_labels(&snippets, self.config.label_max_length);
+
+        *sync::write(&self.last_snippets, "last snippets") = snippets
+            .iter()
+            .map(|snippet| (snippet.id, snippet.clone()))
+            .collect();
+
+        // Clients that resolve both documentation and the edit get minimal items,
+        // everyone else gets fully populated items as a fallback
+        let lazy_documentation = self.resolves_lazily("documentation");
+        let lazy_edit = if context.get_replacement_range().is_some() {
+            self.resolves_lazily("textEdit")
+        } else {
+            self.resolves_lazily("insertText")
+        };
+
+        let language_id = context.language_id.as_deref().unwrap_or("unknown");
+        let support = self.client_support();
+        let workspace_folders = sync::read(&self.workspace_folders, "workspace folders");
+        let presentation = PresentationContext::new(
+            &context.uri,
+            language_id,
+            &self.config,
+            &self.language_registry,
+            &support,
+        )
+        .with_completion_context(context)
+        .with_workspace_folders(&workspace_folders)
+        .with_lazy(lazy_documentation, lazy_edit);
+        // Ids of different databases overlap, commands name the one searched
+        let folder = self.repository.route(&context.uri);
+        let mut completion_items = Vec::with_capacity(snippets.len());
+        let mut fallback_items = Vec::new();
+        for (index, snippet) in snippets.iter().enumerate() {
+            let content = SnippetPresenter::content(snippet, &presentation)
+                .context("convert snippets to completion items")?;
+            if let Some(error) = &content.syntax_error
+                && self.config.strict_snippets
+            {
+                warn!(
+                    "Dropping snippet {} '{}' with broken snippet syntax: {}",
+                    snippet.id, snippet.title, error
+                );
+                continue;
+            }
+
+            let mut item = SnippetPresenter::present_content(snippet, content, &presentation);
+            if let Some(label) = labels.get(&snippet.id) {
+                item.label = format!("{}{}", self.config.label_prefix, label);
+            }
+            item.additional_text_edits = self.import_edits(snippet, context);
+            if self.config.track_usage {
+                item.command = Some(Self::record_usage_command(snippet.id, folder.as_deref()));
+            }
+            let tier = if index >= fallback_start {
+                CompletionTier::Fallback
+            } else {
+                CompletionTier::of(snippet, context.get_query_text().unwrap_or(""))
+            };
+            item.sort_text = Some(self.sort_text(tier, completion_items.len()));
+            item.data = Some(
+                serde_json::to_value(
+                    CompletionItemData::new(snippet.id, context)
+                        .with_interpolated(
+                            self.config.enable_interpolation && snippet.source() == "bkmr",
+                        )
+                        .with_tier(tier)
+                        .with_folder(folder.clone()),
+                )
+                .context("serialize completion item data")?,
+            );
+            if index >= fallback_start {
+                item.detail = Some(FALLBACK_DETAIL.to_string());
+                item.filter_text = context.get_query_text().map(str::to_string);
+                fallback_items.push(item);
+            } else {
+                completion_items.push(item);
+            }
+        }
+        // Bookmarks rank after all snippets, fallback snippets after everything
+        for bookmark in bookmarks {
+            let mut item = self.bookmark_to_completion_item(bookmark, context);
+            item.sort_text = Some(self.sort_text(CompletionTier::Fallback, completion_items.len()));
+            completion_items.push(item);
+        }
+        for mut item in fallback_items {
+            item.sort_text = Some(self.sort_text(CompletionTier::Fallback, completion_items.len()));
+            completion_items.push(item);
+        }
+        // Listed the way clients sort them, tier by tier
+        completion_items.sort_by(|a, b| a.sort_text.cmp(&b.sort_text));
+
+        debug!(
+            "Generated {} completion items, incomplete: {}",
+            completion_items.len(),
+            is_incomplete
+        );
+        Ok(CompletionList {
+            is_incomplete,
+            items: completion_items,
+        })
+    }
+
+    /// Tier followed by the zero-padded rank, so clients keep our ordering within each tier
+    fn sort_text(&self, tier: CompletionTier, rank: usize) -> String {
+        format!("{}{:05}", self.config.tier_order.sort_prefix(tier), rank)
+    }
+
+    /// Snippets of the language regardless of the query, for a query matching nothing
+    ///
+    /// Uses the filter of empty-query completions, which the prefetched cache
+    /// answers. Failures only leave the fallback empty.
+    async fn fetch_fallback(&self, context: &CompletionContext) -> Vec<Snippet> {
+        if !self.config.fallback_completions || context.get_query_text().unwrap_or("").is_empty() {
+            return Vec::new();
+        }
+        let filter = SnippetFilter {
+            query_prefix: None,
+            ..self.build_snippet_filter(context)
+        };
+        match self.repository.fetch_snippets(&filter).await {
+            Ok(snippets) => {
+                debug!(
+                    "No snippets match {:?}, falling back to {} snippets",
+                    context.get_query_text(),
+                    snippets.len()
+                );
+                snippets
+            }
+            Err(e) => {
+                warn!("Failed to fetch fallback snippets: {:#}", e);
+                Vec::new()
+            }
+        }
+    }
 
+    /// Snippets for a completion and whether they are every match, narrowed from the last result if possible
+    ///
+    /// With a `document`, a search the document made less than
+    /// `min_query_interval_ms` ago is narrowed instead of searching again.
+    async fn fetch_completion_snippets(
+        &self,
+        filter: &SnippetFilter,
+        narrow: bool,
+        document: Option<&str>,
+    ) -> Result<(Vec<Snippet>, bool)> {
+        if let Some(snippets) = narrow.then(|| self.narrow_complete_fetch(filter)).flatten() {
+            return Ok((snippets, true));
+        }
+        if let Some(fetched) = document.and_then(|uri| self.narrow_document_fetch(uri, filter)) {
+            return Ok(fetched);
+        }
         let snippets = self
             .repository
-            .fetch_snippets(&filter)
+            .fetch_snippets(filter)
             .await
             .context("fetch snippets from repository")?;
+        // Without a total from the repository, a full result suggests it had more matches
+        let complete = self
+            .repository
+            .search_complete(filter)
+            .unwrap_or(snippets.len() < filter.max_results);
+        *sync::write(&self.complete_fetch, "complete fetch") =
+            complete.then(|| (filter.clone(), snippets.clone()));
+        if let Some(uri) = document
+            && self.config.min_query_interval_ms > 0
+        {
+            sync::lock(&self.document_fetches, "document fetches").insert(
+                uri.to_string(),
+                DocumentFetch {
+                    fetched_at: Instant::now(),
+                    filter: filter.clone(),
+                    snippets: snippets.clone(),
+                    complete,
+                },
+            );
+        }
+        Ok((snippets, complete))
+    }
+
+    /// Narrow the document's last search if it is recent and the query extends its query
+    ///
+    /// Keeps the snippets whose title contains the query, in the order the
+    /// repository ranked them. A result that was cut at the limit stays
+    /// incomplete, so the client asks again once the interval is over.
+    fn narrow_document_fetch(
+        &self,
+        uri: &str,
+        filter: &SnippetFilter,
+    ) -> Option<(Vec<Snippet>, bool)> {
+        let interval = Duration::from_millis(self.config.min_query_interval_ms);
+        let document_fetches = sync::lock(&self.document_fetches, "document fetches");
+        let fetch = document_fetches.get(uri)?;
+        if fetch.fetched_at.elapsed() >= interval || !Self::extends_search(&fetch.filter, filter) {
+            return None;
+        }
+        let query = filter.query_prefix.as_deref().unwrap_or("");
+        debug!(
+            "Searched {} {:?} ago, narrowing {} snippets to '{}'",
+            uri,
+            fetch.fetched_at.elapsed(),
+            fetch.snippets.len(),
+            query
+        );
+        Some((
+            self.refilter(&fetch.snippets, query, filter),
+            fetch.complete,
+        ))
+    }
+
+    /// Whether a search with the filter only finds a subset of what the previous one found
+    ///
+    /// A search matching case only narrows to searches matching case as well.
+    fn extends_search(previous: &SnippetFilter, filter: &SnippetFilter) -> bool {
+        let query = filter.query_prefix.as_deref().unwrap_or("");
+        let previous_query = previous.query_prefix.as_deref().unwrap_or("");
+        let narrows = if filter.case_mode.is_case_sensitive(previous_query) {
+            filter.case_mode.is_case_sensitive(query) && query.starts_with(previous_query)
+        } else {
+            query
+                .to_lowercase()
+                .starts_with(&previous_query.to_lowercase())
+        };
+        previous.language_id == filter.language_id
+            && previous.folder == filter.folder
+            && previous.case_mode == filter.case_mode
+            && previous.title_exact == filter.title_exact
+            && previous.required_tags == filter.required_tags
+            && narrows
+    }
+
+    /// Snippets the search of the filter would return for the query, in their original order
+    pub(crate) fn refilter(
+        &self,
+        snippets: &[Snippet],
+        query: &str,
+        filter: &SnippetFilter,
+    ) -> Vec<Snippet> {
+        snippets
+            .iter()
+            .filter(|snippet| self.matches_query(snippet, query, filter))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the snippet matches the query like the repository search does
+    ///
+    /// Titles always count, descriptions only when --alias-search also
+    /// searches them.
+    fn matches_query(&self, snippet: &Snippet, query: &str, filter: &SnippetFilter) -> bool {
+        filter.case_mode.contains(&snippet.title, query)
+            || (self.config.alias_search
+                && !filter.bookmarks
+                && filter.case_mode.contains(&snippet.description, query))
+    }
+
+    /// Bookmarks for URL completions, none when the search fails so snippets are still offered
+    async fn fetch_bookmarks(&self, filter: Option<&SnippetFilter>) -> Vec<Snippet> {
+        let Some(filter) = filter else {
+            return Vec::new();
+        };
+        match self.repository.fetch_snippets(filter).await {
+            Ok(bookmarks) => bookmarks
+                .into_iter()
+                .filter(|bookmark| !bookmark.content.trim().is_empty())
+                .collect(),
+            Err(e) => {
+                warn!("Failed to fetch bookmarks: {:#}", e);
+                Vec::new()
+            }
+        }
+    }
 
-        let completion_items: Vec<CompletionItem> = snippets
+    /// Filter of the bookmark search for URL completions, None unless the language is configured
+    ///
+    /// Queries shorter than [`BOOKMARK_MIN_QUERY_CHARS`] match too many bookmarks to be useful.
+    fn bookmark_filter(&self, context: &CompletionContext) -> Option<SnippetFilter> {
+        let language_id = context.language_id.as_deref()?;
+        if !self
+            .config
+            .url_completion_languages
             .iter()
-            .map(|snippet| {
-                self.snippet_to_completion_item(
-                    snippet,
-                    context.get_query_text().unwrap_or(""),
-                    context.get_replacement_range(),
-                    context.language_id.as_deref().unwrap_or("unknown"),
-                    &context.uri,
+            .any(|language| language.eq_ignore_ascii_case(language_id))
+        {
+            return None;
+        }
+        let query = context
+            .get_query_text()
+            .filter(|query| query.chars().count() >= BOOKMARK_MIN_QUERY_CHARS)?;
+        Some(
+            SnippetFilter::bookmarks(query.to_string(), self.max_completions(Some(language_id)))
+                .with_excluded_tags(self.config.excluded_tags.clone())
+                .with_folder(self.repository.route(&context.uri))
+                .with_case_mode(self.config.case_mode),
+        )
+    }
+
+    /// Build a completion item inserting a bookmark as markdown link
+    ///
+    /// Right after an opening parenthesis, as in `[text](`, the bare URL is inserted.
+    fn bookmark_to_completion_item(
+        &self,
+        bookmark: &Snippet,
+        context: &CompletionContext,
+    ) -> CompletionItem {
+        let url = bookmark.content.trim();
+        let in_parentheses = context
+            .line
+            .as_ref()
+            .is_some_and(|line| line.preceding_char == Some('('));
+        let new_text = if in_parentheses {
+            url.to_string()
+        } else {
+            Self::markdown_link(&bookmark.title, url)
+        };
+
+        let mut item = CompletionItem {
+            label: SnippetPresenter::label(&bookmark.title, &self.config),
+            label_details: Some(CompletionItemLabelDetails {
+                detail: None,
+                description: Some(url.to_string()),
+            }),
+            kind: Some(CompletionItemKind::REFERENCE),
+            detail: Some(BOOKMARK_DETAIL.to_string()),
+            insert_text_format: Some(InsertTextFormat::PLAIN_TEXT),
+            filter_text: Some(SnippetPresenter::case_filter_text(
+                &bookmark.title,
+                self.config.case_mode,
+            )),
+            command: self.config.track_usage.then(|| {
+                Self::record_usage_command(
+                    bookmark.id,
+                    self.repository.route(&context.uri).as_deref(),
+                )
+            }),
+            ..Default::default()
+        };
+        match context.get_replacement_range() {
+            Some(range) => {
+                let range = self
+                    .config
+                    .replace_mode
+                    .edit_range(range, context.get_word_range());
+                item.text_edit = Some(CompletionTextEdit::Edit(TextEdit { range, new_text }))
+            }
+            None => item.insert_text = Some(new_text),
+        }
+        item
+    }
+
+    /// Markdown link `[title](url)`, escaping brackets in the title and parentheses and spaces in the URL
+    pub(crate) fn markdown_link(title: &str, url: &str) -> String {
+        let mut text = String::with_capacity(title.len());
+        for c in title.chars() {
+            if matches!(c, '[' | ']' | '\\') {
+                text.push('\\');
+            }
+            text.push(c);
+        }
+        let destination = url
+            .replace('(', "%28")
+            .replace(')', "%29")
+            .replace(' ', "%20");
+        format!("[{}]({})", text, destination)
+    }
+
+    /// Complete the tag typed in a markdown frontmatter from the repository's tags
+    ///
+    /// Tags starting with the prefix, ignoring case, are offered most used first.
+    pub async fn tag_completions(&self, prefix: &str, range: Range) -> Result<Vec<CompletionItem>> {
+        let prefix = prefix.to_lowercase();
+        let tags = self
+            .repository
+            .list_tags()
+            .await
+            .context("list tags for frontmatter completion")?;
+        Ok(tags
+            .into_iter()
+            .filter(|tag| tag.tag.to_lowercase().starts_with(&prefix))
+            .take(self.max_completions(Some("markdown")))
+            .enumerate()
+            .map(|(i, TagCount { tag, count })| CompletionItem {
+                label: tag.clone(),
+                label_details: Some(CompletionItemLabelDetails {
+                    detail: None,
+                    description: Some(match count {
+                        1 => "1 bookmark".to_string(),
+                        count => format!("{} bookmarks", count),
+                    }),
+                }),
+                kind: Some(CompletionItemKind::KEYWORD),
+                detail: Some(TAG_DETAIL.to_string()),
+                sort_text: Some(format!("{:05}", i)),
+                filter_text: Some(tag.clone()),
+                insert_text_format: Some(InsertTextFormat::PLAIN_TEXT),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range,
+                    new_text: tag,
+                })),
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    /// Get completions unless a newer request for the same document arrives first
+    ///
+    /// A superseded request returns an empty incomplete list. Its fetch is
+    /// dropped, which kills the bkmr process still searching for it.
+    /// Requests following a search of the document within
+    /// `min_query_interval_ms` narrow its result instead of searching again.
+    pub async fn get_latest_completion_list(
+        &self,
+        context: &CompletionContext,
+        narrow: bool,
+    ) -> Result<CompletionList> {
+        let (generation, mut latest) = self.begin_request(context.uri.as_str());
+        tokio::select! {
+            result = self.completion_list(context, narrow, true) => result,
+            Ok(_) = latest.wait_for(|newest| *newest > generation) => {
+                debug!("Completion request {} superseded by a newer one", generation);
+                Ok(CompletionList {
+                    is_incomplete: true,
+                    items: Vec::new(),
+                })
+            }
+        }
+    }
+
+    /// Register a completion request as the newest for its document
+    fn begin_request(&self, uri: &str) -> (u64, watch::Receiver<u64>) {
+        let generation = self.request_generation.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut latest_requests = sync::lock(&self.latest_requests, "latest requests");
+        let latest = latest_requests
+            .entry(uri.to_string())
+            .or_insert_with(|| watch::channel(0).0);
+        latest.send_replace(generation);
+        (generation, latest.subscribe())
+    }
+
+    /// Number of snippets offered by the most recent completion request
+    pub fn last_completion_count(&self) -> usize {
+        sync::read(&self.last_snippets, "last snippets").len()
+    }
+
+    /// Filter the last complete result down to a longer query of the same search
+    fn narrow_complete_fetch(&self, filter: &SnippetFilter) -> Option<Vec<Snippet>> {
+        let complete_fetch = sync::read(&self.complete_fetch, "complete fetch");
+        let (previous, snippets) = complete_fetch.as_ref()?;
+        if !Self::extends_search(previous, filter) {
+            return None;
+        }
+        let query = filter.query_prefix.as_deref().unwrap_or("");
+        let previous_query = previous.query_prefix.as_deref().unwrap_or("");
+
+        debug!(
+            "Narrowing {} snippets from query '{}' to '{}'",
+            snippets.len(),
+            previous_query,
+            query
+        );
+        Some(self.refilter(snippets, query, filter))
+    }
+
+    /// Best snippet for the query as ghost text, None for queries shorter than [`INLINE_MIN_QUERY_CHARS`]
+    ///
+    /// The snippet is picked, translated and escaped like the first item of
+    /// a completion list and replaces the same range. The answer is reused
+    /// while requests repeat for the same document, position and query.
+    #[instrument(skip(self))]
+    pub async fn inline_completion(
+        &self,
+        context: &CompletionContext,
+    ) -> Result<Option<InlineCompletionItem>> {
+        let query = context.get_query_text().unwrap_or("");
+        if query.chars().count() < INLINE_MIN_QUERY_CHARS {
+            debug!("Query '{}' too short for an inline completion", query);
+            return Ok(None);
+        }
+
+        let uri = context.uri.to_string();
+        let request = InlineRequest {
+            position: context.position,
+            query: query.to_string(),
+            tag_filter: context.tag_filter.clone(),
+        };
+        if let Some((previous, item)) = sync::lock(&self.last_inline, "last inline").get(&uri)
+            && *previous == request
+        {
+            debug!("Reusing inline completion for '{}'", query);
+            return Ok(item.clone());
+        }
+
+        let snippets = self
+            .repository
+            .fetch_snippets(&self.build_snippet_filter(context))
+            .await
+            .context("fetch snippets for inline completion")?;
+        let mut item = None;
+        for snippet in self.ranked_snippets(context, snippets) {
+            item = self
+                .snippet_to_inline_item(&snippet, context)
+                .context("convert snippet to inline completion")?;
+            if item.is_some() {
+                break;
+            }
+        }
+
+        sync::lock(&self.last_inline, "last inline").insert(uri, (request, item.clone()));
+        Ok(item)
+    }
+
+    /// Inline completion inserting a snippet, None if strict mode drops its broken syntax
+    fn snippet_to_inline_item(
+        &self,
+        snippet: &Snippet,
+        context: &CompletionContext,
+    ) -> Result<Option<InlineCompletionItem>> {
+        let language_id = context.language_id.as_deref().unwrap_or("unknown");
+        let support = self.client_support();
+        let workspace_folders = sync::read(&self.workspace_folders, "workspace folders");
+        let presentation = PresentationContext::new(
+            &context.uri,
+            language_id,
+            &self.config,
+            &self.language_registry,
+            &support,
+        )
+        .with_completion_context(context)
+        .with_workspace_folders(&workspace_folders);
+        let content = SnippetPresenter::content(snippet, &presentation)?;
+        if content.syntax_error.is_some() && self.config.strict_snippets {
+            return Ok(None);
+        }
+
+        let insert_text = if snippet.is_plain() || content.syntax_error.is_some() {
+            InlineInsertText::Plain(content.insert)
+        } else {
+            InlineInsertText::snippet(content.insert)
+        };
+        Ok(Some(InlineCompletionItem {
+            insert_text,
+            filter_text: context.get_query_text().map(str::to_string),
+            range: context.get_replacement_range(),
+            command: self.config.track_usage.then(|| {
+                Self::record_usage_command(
+                    snippet.id,
+                    self.repository.route(&context.uri).as_deref(),
                 )
+            }),
+        }))
+    }
+
+    /// Deduplicated snippets allowed at the completion position, best match first
+    ///
+    /// Snippets whose restriction tags rule out the document language are
+    /// dropped here, after the search, so a list can come out shorter than
+    /// the limit.
+    fn ranked_snippets(&self, context: &CompletionContext, snippets: Vec<Snippet>) -> Vec<Snippet> {
+        let snippets: Vec<Snippet> = Self::dedupe_snippets(snippets)
+            .into_iter()
+            .filter(|snippet| context.allows_position_tags(&snippet.tags))
+            .filter(|snippet| {
+                snippet.allows_language(context.language_id.as_deref(), &self.language_registry)
             })
-            .collect::<Result<Vec<_>>>()
-            .context("convert snippets to completion items")?;
+            .map(|snippet| self.mark_action_plain(snippet))
+            .collect();
+        let ranking = self
+            .language_overrides(context.language_id.as_deref())
+            .and_then(|overrides| overrides.ranking)
+            .unwrap_or(self.config.ranking);
+        rank_snippets(snippets, context.get_query_text(), ranking)
+    }
+
+    /// Tag an action snippet plain, so its command line is inserted verbatim without tabstops
+    fn mark_action_plain(&self, mut snippet: Snippet) -> Snippet {
+        if !snippet.is_plain() && snippet.action_kind(&self.config.action_tags).is_some() {
+            snippet.tags.push("plain".to_string());
+        }
+        snippet
+    }
+
+    /// Fill in documentation and insert text for an item from a previous completion
+    #[instrument(skip(self, item), fields(label = %item.label))]
+    pub fn resolve_completion_item(&self, item: CompletionItem) -> Result<CompletionItem> {
+        let Some(data) = item
+            .data
+            .clone()
+            .and_then(|data| serde_json::from_value::<CompletionItemData>(data).ok())
+        else {
+            debug!("Completion item carries no bkmr data, returning unchanged");
+            return Ok(item);
+        };
+
+        let snippet = sync::read(&self.last_snippets, "last snippets")
+            .get(&data.id)
+            .cloned();
+        let Some(snippet) = snippet else {
+            debug!(
+                "Snippet {} no longer known, returning item unchanged",
+                data.id
+            );
+            return Ok(item);
+        };
+
+        let support = self.client_support();
+        let workspace_folders = sync::read(&self.workspace_folders, "workspace folders");
+        let context = PresentationContext::new(
+            &data.uri,
+            data.language_id.as_deref().unwrap_or("unknown"),
+            &self.config,
+            &self.language_registry,
+            &support,
+        )
+        .with_item_data(&data)
+        .with_workspace_folders(&workspace_folders);
+        let mut resolved = SnippetPresenter::present(&snippet, &context)
+            .context("build resolved completion item")?;
+        resolved.label = item.label;
+        resolved.label_details = item.label_details;
+        resolved.additional_text_edits = item.additional_text_edits;
+        if item.detail.as_deref() == Some(FALLBACK_DETAIL) {
+            resolved.detail = item.detail;
+            resolved.filter_text = item.filter_text;
+        }
+        resolved.data = item.data;
+        resolved.command = item.command;
+        Ok(resolved)
+    }
+
+    /// Edits adding the imports a snippet declares that the document lacks, None without any
+    fn import_edits(
+        &self,
+        snippet: &Snippet,
+        context: &CompletionContext,
+    ) -> Option<Vec<TextEdit>> {
+        let imports = SnippetImports::parse(&snippet.content, &self.config.import_marker);
+        if imports.imports.is_empty() {
+            return None;
+        }
+        let Some(document) = &context.document_imports else {
+            debug!(
+                "No document text to add the imports of snippet {} to",
+                snippet.id
+            );
+            return None;
+        };
+        Some(document.edits(&imports.imports)).filter(|edits| !edits.is_empty())
+    }
 
-        debug!("Generated {} completion items", completion_items.len());
-        Ok(completion_items)
+    /// Command the client runs after inserting the item, reporting the snippet to bkmr
+    ///
+    /// The folder of a routed search follows the id, so the usage is recorded in its database.
+    fn record_usage_command(id: i32, folder: Option<&str>) -> Command {
+        let mut arguments = vec![serde_json::json!(id)];
+        arguments.extend(folder.map(|folder| serde_json::json!(folder)));
+        Command {
+            title: "Record snippet usage".to_string(),
+            command: RECORD_USAGE.to_string(),
+            arguments: Some(arguments),
+        }
     }
 
     /// Build snippet filter from completion context
     fn build_snippet_filter(&self, context: &CompletionContext) -> SnippetFilter {
-        let query_prefix = context.get_query_text().map(|s| s.to_string());
+        let query_prefix = context
+            .get_query_text()
+            .filter(|text| !text.is_empty())
+            .map(|s| s.to_string());
         SnippetFilter::new(
             context.language_id.clone(),
             query_prefix,
-            50, // TODO: Make configurable
+            self.fetch_limit(context.language_id.as_deref()),
         )
+        .with_universal(self.config.universal_snippets)
+        .with_excluded_tags(self.excluded_tags())
+        .with_required_tags(context.tag_filter.iter().cloned().collect())
+        .with_folder(self.repository.route(&context.uri))
+        .with_case_mode(self.config.case_mode)
     }
 
-    /// Convert snippet to LSP completion item with proper text replacement
-    fn snippet_to_completion_item(
+    /// Filter used by completions with an empty query in a document of a language
+    pub fn language_filter(
         &self,
-        snippet: &Snippet,
-        query: &str,
-        replacement_range: Option<tower_lsp::lsp_types::Range>,
+        uri: &Url,
         language_id: &str,
-        uri: &tower_lsp::lsp_types::Url,
-    ) -> Result<CompletionItem> {
-        // Translate content if this is a universal snippet
-        let translated_content = LanguageTranslator::translate_snippet(snippet, language_id, uri)
-            .context("translate snippet content for target language")?;
+        tag_filter: Option<String>,
+    ) -> SnippetFilter {
+        let mut context = CompletionContext::new(
+            uri.clone(),
+            Position::default(),
+            Some(language_id.to_string()),
+        );
+        context.tag_filter = tag_filter;
+        self.build_snippet_filter(&context)
+    }
 
-        let snippet_content = translated_content;
+    /// Tags of snippets never offered, action snippets' tags unless they are included
+    fn excluded_tags(&self) -> Vec<String> {
+        let mut tags = self.config.excluded_tags.clone();
+        if !self.config.include_action_snippets {
+            tags.extend(DEFAULT_ACTION_TAGS.iter().map(|tag| tag.to_string()));
+            tags.extend(self.config.action_tags.iter().cloned());
+        }
+        tags
+    }
 
-        let label = snippet.title.clone();
+    /// Number of snippets to request from the repository for one completion
+    fn fetch_limit(&self, language_id: Option<&str>) -> usize {
+        self.max_completions(language_id) + DUPLICATE_HEADROOM
+    }
 
-        debug!(
-            "Creating completion item: query='{}', label='{}', content_preview='{}'",
-            query,
-            label,
-            snippet_content.chars().take(20).collect::<String>()
-        );
+    /// Maximum number of completion items in a language, its override or the global one
+    fn max_completions(&self, language_id: Option<&str>) -> usize {
+        self.language_overrides(language_id)
+            .and_then(|overrides| overrides.max_results)
+            .unwrap_or(self.config.max_completions)
+    }
 
-        // Determine if this should be treated as plain text
-        let (item_kind, text_format, detail_text) = if snippet.is_plain() {
-            (CompletionItemKind::TEXT, InsertTextFormat::PLAIN_TEXT, "bkmr plain text")
-        } else {
-            (CompletionItemKind::SNIPPET, InsertTextFormat::SNIPPET, "bkmr snippet")
-        };
+    /// Settings configured for a language with --per-language
+    fn language_overrides(&self, language_id: Option<&str>) -> Option<&LanguageOverrides> {
+        self.config.per_language.get(&language_id?.to_lowercase())
+    }
 
-        let mut completion_item = CompletionItem {
-            label: label.clone(),
-            kind: Some(item_kind),
-            detail: Some(detail_text.to_string()),
-            documentation: Some(Documentation::String(if snippet_content.len() > 500 {
-                format!("{}...", &snippet_content[..500])
-            } else {
-                snippet_content.clone()
-            })),
-            insert_text_format: Some(text_format),
-            filter_text: Some(label.clone()),
-            sort_text: Some(label.clone()),
-            ..Default::default()
-        };
+    /// Drop repeated snippets, e.g. returned by both the language and universal query
+    fn dedupe_snippets(snippets: Vec<Snippet>) -> Vec<Snippet> {
+        let mut seen = HashSet::new();
+        snippets
+            .into_iter()
+            .filter(|snippet| seen.insert(snippet.id))
+            .collect()
+    }
 
-        // Use TextEdit for proper replacement if we have a range
-        if let Some(range) = replacement_range {
-            completion_item.text_edit = Some(CompletionTextEdit::Edit(TextEdit {
-                range,
-                new_text: snippet_content,
-            }));
-            debug!("Set text_edit for range replacement: {:?}", range);
-        } else {
-            // Fallback to insert_text for backward compatibility
-            completion_item.insert_text = Some(snippet_content);
-            debug!("Using fallback insert_text (no range available)");
+    /// Labels for snippets sharing a title, suffixed with their tags to tell them apart
+    ///
+    /// Snippets with a unique title keep it as label and are not included.
+    fn completion_labels(snippets: &[Snippet], max_length: usize) -> HashMap<i32, String> {
+        let mut by_title: HashMap<&str, Vec<&Snippet>> = HashMap::new();
+        for snippet in snippets {
+            by_title.entry(&snippet.title).or_default().push(snippet);
+        }
+
+        let mut labels = HashMap::new();
+        for (title, group) in by_title.into_iter().filter(|(_, group)| group.len() > 1) {
+            let suffixes: Vec<String> = group.iter().map(|s| Self::tag_suffix(s)).collect();
+            for (snippet, suffix) in group.iter().zip(&suffixes) {
+                let ambiguous =
+                    suffix.is_empty() || suffixes.iter().filter(|s| *s == suffix).count() > 1;
+                let suffix = match (ambiguous, suffix.is_empty()) {
+                    (false, _) => suffix.clone(),
+                    (true, true) => format!("#{}", snippet.id),
+                    (true, false) => format!("{}, #{}", suffix, snippet.id),
+                };
+                labels.insert(
+                    snippet.id,
+                    format!(
+                        "{} ({})",
+                        SnippetPresenter::truncate_label(title, max_length),
+                        suffix
+                    ),
+                );
+            }
         }
+        labels
+    }
 
-        Ok(completion_item)
+    /// Describe a snippet by its content tags, or as universal when it has none
+    fn tag_suffix(snippet: &Snippet) -> String {
+        let tags: Vec<&str> = snippet.content_tags().collect();
+        if tags.is_empty() && snippet.is_universal() {
+            "universal".to_string()
+        } else {
+            tags.join(", ")
+        }
     }
 
     /// Health check for the completion service
@@ -151,8 +1047,36 @@ impl CompletionService {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::{
+        BkmrSnippet, CaseMode, ContentLimit, DocumentImports, InsertionKind, ItemKind,
+        LargeSnippetMode, LineContext, ReplaceMode,
+    };
     use crate::repositories::MockSnippetRepository;
-    use tower_lsp::lsp_types::{Position, Range, Url};
+    use crate::services::MAX_FILTER_TEXT_CHARS;
+    use tower_lsp::lsp_types::{Documentation, InsertTextMode, Position, Range, Url};
+
+    impl CompletionService {
+        /// Present one snippet the way completion does, with the context adjusted by `configure`
+        fn present_item(
+            &self,
+            snippet: &Snippet,
+            language_id: &str,
+            uri: &Url,
+            configure: impl for<'a> FnOnce(PresentationContext<'a>) -> PresentationContext<'a>,
+        ) -> Result<CompletionItem> {
+            let support = self.client_support();
+            let workspace_folders = sync::read(&self.workspace_folders, "workspace folders");
+            let context = PresentationContext::new(
+                uri,
+                language_id,
+                &self.config,
+                &self.language_registry,
+                &support,
+            )
+            .with_workspace_folders(&workspace_folders);
+            SnippetPresenter::present(snippet, &configure(context))
+        }
+    }
 
     #[tokio::test]
     async fn given_context_with_query_when_getting_completions_then_returns_filtered_items() {
@@ -192,48 +1116,184 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn given_universal_snippet_when_creating_completion_item_then_translates_content() {
+    async fn given_snippet_with_import_headers_when_completing_then_adds_missing_imports_as_edits()
+    {
         // Arrange
-        let universal_snippet = Snippet::new(
+        let dataclass = Snippet::new(
             1,
-            "Universal Comment".to_string(),
-            "// This is a universal comment".to_string(),
-            "Universal snippet".to_string(),
-            vec!["universal".to_string(), "_snip_".to_string()],
+            "dataclass".to_string(),
+            "#import: from dataclasses import dataclass\n#import: import typing\n@dataclass\nclass $1:\n    pass"
+                .to_string(),
+            "Dataclass".to_string(),
+            vec!["python".to_string(), "_snip_".to_string()],
         );
-
-        let repository = Arc::new(MockSnippetRepository::new());
+        let plain = Snippet::new(
+            2,
+            "dataclass main".to_string(),
+            "if __name__ == \"__main__\":\n    main()".to_string(),
+            "Main guard".to_string(),
+            vec!["python".to_string(), "_snip_".to_string()],
+        );
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![dataclass, plain]));
         let service = CompletionService::new(repository);
-
-        let uri = Url::parse("file:///test.py").expect("parse URI");
+        let context = CompletionContext::new(
+            Url::parse("file:///test.py").expect("parse URI"),
+            Position::new(3, 0),
+            Some("python".to_string()),
+        )
+        .with_document_imports(DocumentImports::new(
+            "#!/usr/bin/env python3\nimport typing\n\n\n",
+        ));
 
         // Act
-        let result =
-            service.snippet_to_completion_item(&universal_snippet, "", None, "python", &uri);
+        let items = service
+            .get_completions(&context)
+            .await
+            .expect("completion items");
 
         // Assert
-        assert!(result.is_ok());
-        let item = result.expect("valid completion item");
-
-        // Should have translated Rust comment to Python comment
-        let insert_text = item.insert_text.expect("insert text");
-        assert!(insert_text.contains("# This is a universal comment"));
+        let dataclass = items
+            .iter()
+            .find(|item| item.label == "dataclass")
+            .expect("dataclass item");
+        assert_eq!(
+            dataclass.insert_text.as_deref(),
+            Some("@dataclass\nclass $1:\n    pass")
+        );
+        assert_eq!(
+            dataclass.additional_text_edits,
+            Some(vec![TextEdit {
+                range: Range::new(Position::new(1, 0), Position::new(1, 0)),
+                new_text: "from dataclasses import dataclass\n".to_string(),
+            }])
+        );
+        let plain = items
+            .iter()
+            .find(|item| item.label == "dataclass main")
+            .expect("main guard item");
+        assert_eq!(plain.additional_text_edits, None);
+        assert_eq!(
+            plain.insert_text.as_deref(),
+            Some("if __name__ == \"__main__\":\n    main()")
+        );
     }
 
     #[tokio::test]
-    async fn given_completion_item_with_range_when_creating_then_uses_text_edit() {
+    async fn given_shared_metrics_when_completing_twice_then_records_each_stage_twice() {
         // Arrange
         let snippet = Snippet::new(
             1,
-            "Test Snippet".to_string(),
-            "test content".to_string(),
-            "Test description".to_string(),
+            "Hello World".to_string(),
+            "println!(\"Hello, World!\");".to_string(),
+            "Hello world example".to_string(),
             vec!["rust".to_string(), "_snip_".to_string()],
         );
-
-        let repository = Arc::new(MockSnippetRepository::new());
-        let service = CompletionService::new(repository);
-
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet]));
+        let metrics = Arc::new(Metrics::new(0));
+        let service = CompletionService::new(repository).with_metrics(metrics.clone());
+        let context = CompletionContext::new(
+            Url::parse("file:///test.rs").expect("parse URI"),
+            Position {
+                line: 0,
+                character: 5,
+            },
+            Some("rust".to_string()),
+        );
+
+        // Act
+        service
+            .get_completions(&context)
+            .await
+            .expect("first completion");
+        service
+            .get_completions(&context)
+            .await
+            .expect("second completion");
+
+        // Assert
+        assert_eq!(metrics.count(Stage::Fetch), 2);
+        assert_eq!(metrics.count(Stage::Items), 2);
+        assert_eq!(metrics.snapshot().bkmr_spawns, 0);
+    }
+
+    #[tokio::test]
+    async fn given_universal_snippet_when_creating_completion_item_then_translates_content() {
+        // Arrange
+        let universal_snippet = Snippet::new(
+            1,
+            "Universal Comment".to_string(),
+            "// This is a universal comment".to_string(),
+            "Universal snippet".to_string(),
+            vec![
+                "universal".to_string(),
+                "rust-style".to_string(),
+                "_snip_".to_string(),
+            ],
+        );
+
+        let repository = Arc::new(MockSnippetRepository::new());
+        let service = CompletionService::new(repository);
+
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+
+        // Act
+        let result = service.present_item(&universal_snippet, "python", &uri, |context| context);
+
+        // Assert
+        assert!(result.is_ok());
+        let item = result.expect("valid completion item");
+
+        // Should have translated Rust comment to Python comment
+        let insert_text = item.insert_text.expect("insert text");
+        assert!(insert_text.contains("# This is a universal comment"));
+    }
+
+    #[tokio::test]
+    async fn given_regular_snippet_with_context_variables_when_creating_item_then_expands_them() {
+        // Arrange
+        let snippet = Snippet::new(
+            1,
+            "Module Header".to_string(),
+            "//! {{ filepath }} by {{ author }}\n$0".to_string(),
+            "Header".to_string(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        );
+        let config = BkmrConfig {
+            author: Some("Jane Doe".to_string()),
+            ..Default::default()
+        };
+        let service =
+            CompletionService::with_config(Arc::new(MockSnippetRepository::new()), config);
+        service.set_workspace_folders(vec![Url::parse("file:///work/app").expect("parse URI")]);
+        let uri = Url::parse("file:///work/app/src/lib.rs").expect("parse URI");
+
+        // Act
+        let item = service
+            .present_item(&snippet, "rust", &uri, |context| context)
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(
+            item.insert_text.as_deref(),
+            Some("//! src/lib.rs by Jane Doe\n$0")
+        );
+    }
+
+    #[tokio::test]
+    async fn given_completion_item_with_range_when_creating_then_uses_text_edit() {
+        // Arrange
+        let snippet = Snippet::new(
+            1,
+            "Test Snippet".to_string(),
+            "test content".to_string(),
+            "Test description".to_string(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        );
+
+        let repository = Arc::new(MockSnippetRepository::new());
+        let service = CompletionService::new(repository);
+
         let uri = Url::parse("file:///test.rs").expect("parse URI");
         let range = Range {
             start: Position {
@@ -247,8 +1307,9 @@ mod tests {
         };
 
         // Act
-        let result =
-            service.snippet_to_completion_item(&snippet, "test", Some(range), "rust", &uri);
+        let result = service.present_item(&snippet, "rust", &uri, |context| {
+            context.with_query("test").with_ranges(Some(range), None)
+        });
 
         // Assert
         assert!(result.is_ok());
@@ -293,7 +1354,7 @@ mod tests {
         let uri = Url::parse("file:///test.rs").expect("parse URI");
 
         // Act
-        let result = service.snippet_to_completion_item(&plain_snippet, "", None, "rust", &uri);
+        let result = service.present_item(&plain_snippet, "rust", &uri, |context| context);
 
         // Assert
         assert!(result.is_ok());
@@ -322,7 +1383,7 @@ mod tests {
         let uri = Url::parse("file:///test.rs").expect("parse URI");
 
         // Act
-        let result = service.snippet_to_completion_item(&regular_snippet, "", None, "rust", &uri);
+        let result = service.present_item(&regular_snippet, "rust", &uri, |context| context);
 
         // Assert
         assert!(result.is_ok());
@@ -333,4 +1394,2640 @@ mod tests {
         assert_eq!(item.detail, Some("bkmr snippet".to_string()));
         assert_eq!(item.label, "Regular Snippet");
     }
+
+    fn context_with_range(uri: &Url, language_id: &str) -> CompletionContext {
+        let range = Range {
+            start: Position {
+                line: 0,
+                character: 0,
+            },
+            end: Position {
+                line: 0,
+                character: 5,
+            },
+        };
+        CompletionContext::new(uri.clone(), range.end, Some(language_id.to_string())).with_query(
+            crate::domain::CompletionQuery::new("Unive".to_string(), range),
+        )
+    }
+
+    fn universal_snippet() -> Snippet {
+        Snippet::new(
+            7,
+            "Universal Comment".to_string(),
+            "// This is a universal comment".to_string(),
+            "Universal snippet".to_string(),
+            vec![
+                "universal".to_string(),
+                "rust-style".to_string(),
+                "_snip_".to_string(),
+            ],
+        )
+    }
+
+    #[tokio::test]
+    async fn given_universal_snippets_disabled_when_completing_then_offers_and_translates_none() {
+        // Arrange
+        let mut python = universal_snippet();
+        python.id = 8;
+        python.title = "Universal helper".to_string();
+        python.tags = vec!["python".to_string(), "_snip_".to_string()];
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![universal_snippet(), python]));
+        let service = CompletionService::with_config(
+            repository,
+            BkmrConfig {
+                universal_snippets: false,
+                ..Default::default()
+            },
+        );
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+
+        // Act
+        let items = service
+            .get_completions(&context_with_range(&uri, "python"))
+            .await
+            .expect("completions");
+        let stray = service
+            .present_item(&universal_snippet(), "python", &uri, |context| context)
+            .expect("valid completion item");
+
+        // Assert
+        let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, vec!["Universal helper"]);
+        assert_eq!(
+            stray.insert_text.as_deref(),
+            Some("// This is a universal comment")
+        );
+    }
+
+    #[tokio::test]
+    async fn given_lazy_resolving_client_when_getting_completions_then_returns_minimal_items() {
+        // Arrange
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![universal_snippet()]));
+        let service = CompletionService::new(repository);
+        service.set_client_support(ClientCompletionSupport {
+            resolve_properties: vec!["documentation".to_string(), "textEdit".to_string()],
+            ..Default::default()
+        });
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+        let context = context_with_range(&uri, "python");
+
+        // Act
+        let items = service
+            .get_completions(&context)
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        assert_eq!(items.len(), 1);
+        let item = &items[0];
+        assert_eq!(item.label, "Universal Comment");
+        assert_eq!(item.kind, Some(CompletionItemKind::SNIPPET));
+        assert!(item.sort_text.is_some());
+        assert!(item.documentation.is_none());
+        assert!(item.text_edit.is_none());
+        assert!(item.insert_text.is_none());
+
+        let data: CompletionItemData =
+            serde_json::from_value(item.data.clone().expect("item data")).expect("parse data");
+        assert_eq!(data.id, 7);
+        assert_eq!(data.language_id.as_deref(), Some("python"));
+        assert_eq!(data.uri, uri);
+        assert_eq!(data.range, context.get_replacement_range());
+    }
+
+    #[tokio::test]
+    async fn given_lazy_item_when_resolving_then_adds_translated_edit_and_documentation() {
+        // Arrange
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![universal_snippet()]));
+        let service = CompletionService::new(repository);
+        service.set_client_support(ClientCompletionSupport {
+            resolve_properties: vec!["documentation".to_string(), "textEdit".to_string()],
+            ..Default::default()
+        });
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+        let context = context_with_range(&uri, "python");
+        let item = service
+            .get_completions(&context)
+            .await
+            .expect("valid completion items")
+            .remove(0);
+
+        // Act
+        let resolved = service
+            .resolve_completion_item(item.clone())
+            .expect("resolved item");
+
+        // Assert
+        assert_eq!(resolved.data, item.data);
+        assert!(resolved.documentation.is_some());
+        match resolved.text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => {
+                assert_eq!(Some(edit.range), context.get_replacement_range());
+                assert_eq!(edit.new_text, "# This is a universal comment");
+            }
+            _ => panic!("Expected text edit"),
+        }
+    }
+
+    #[tokio::test]
+    async fn given_client_without_resolve_support_when_getting_completions_then_items_are_complete()
+    {
+        // Arrange
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![universal_snippet()]));
+        let service = CompletionService::new(repository);
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+        let context = context_with_range(&uri, "python");
+
+        // Act
+        let items = service
+            .get_completions(&context)
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let item = &items[0];
+        assert!(item.documentation.is_some());
+        assert!(item.text_edit.is_some());
+        assert!(item.data.is_some());
+    }
+
+    #[tokio::test]
+    async fn given_client_resolving_documentation_only_when_getting_completions_then_keeps_edit() {
+        // Arrange
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![universal_snippet()]));
+        let service = CompletionService::new(repository);
+        service.set_client_support(ClientCompletionSupport {
+            resolve_properties: vec!["documentation".to_string()],
+            ..Default::default()
+        });
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+        let context = context_with_range(&uri, "python");
+
+        // Act
+        let items = service
+            .get_completions(&context)
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let item = &items[0];
+        assert!(item.documentation.is_none());
+        assert!(item.text_edit.is_some());
+    }
+
+    fn serde_snippets() -> Vec<Snippet> {
+        let mut popular = Snippet::new(
+            1,
+            "Serialize JSON".to_string(),
+            "serde_json::to_string(&value)".to_string(),
+            "Serialize with serde".to_string(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        );
+        popular.access_count = 40;
+        let skeleton = Snippet::new(
+            2,
+            "Serde derive skeleton".to_string(),
+            "#[derive(Serialize, Deserialize)]".to_string(),
+            String::new(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        );
+        vec![popular, skeleton]
+    }
+
+    fn serde_context() -> CompletionContext {
+        let uri = Url::parse("file:///test.rs").expect("parse URI");
+        let range = Range {
+            start: Position {
+                line: 0,
+                character: 0,
+            },
+            end: Position {
+                line: 0,
+                character: 5,
+            },
+        };
+        CompletionContext::new(uri, range.end, Some("rust".to_string())).with_query(
+            crate::domain::CompletionQuery::new("serde".to_string(), range),
+        )
+    }
+
+    /// Context whose query, a typo of "serde", matches none of [`serde_snippets`]
+    fn typo_context() -> CompletionContext {
+        let mut context = serde_context();
+        let range = context.get_replacement_range().expect("query range");
+        context.query = Some(crate::domain::CompletionQuery::new(
+            "srede".to_string(),
+            range,
+        ));
+        context
+    }
+
+    #[tokio::test]
+    async fn given_query_matching_nothing_when_getting_completions_then_offers_most_used_snippets()
+    {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let service = CompletionService::new(repository.clone());
+
+        // Act
+        let list = service
+            .get_completion_list(&typo_context(), false)
+            .await
+            .expect("valid completion list");
+
+        // Assert
+        assert_eq!(repository.fetch_count(), 2);
+        assert!(list.is_incomplete);
+        let labels: Vec<&str> = list.items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, vec!["Serialize JSON", "Serde derive skeleton"]);
+        for item in &list.items {
+            assert_eq!(item.detail.as_deref(), Some(FALLBACK_DETAIL));
+            assert_eq!(item.filter_text.as_deref(), Some("srede"));
+            assert!(
+                item.sort_text
+                    .as_deref()
+                    .is_some_and(|text| text.starts_with('3'))
+            );
+            assert_eq!(
+                item.data.as_ref().expect("item data")["tier"],
+                serde_json::json!("fallback")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn given_matching_query_when_getting_completions_then_fetches_no_fallback() {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let service = CompletionService::new(repository.clone());
+
+        // Act
+        let items = service
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        assert_eq!(repository.fetch_count(), 1);
+        assert!(
+            items
+                .iter()
+                .all(|item| item.detail.as_deref() != Some(FALLBACK_DETAIL))
+        );
+    }
+
+    #[tokio::test]
+    async fn given_failing_fetch_when_getting_completions_then_fetches_no_fallback() {
+        // Arrange
+        let repository = Arc::new(
+            MockSnippetRepository::new()
+                .with_snippets(serde_snippets())
+                .with_fetch_error("bkmr crashed"),
+        );
+        let service = CompletionService::new(repository.clone());
+
+        // Act
+        let result = service.get_completions(&typo_context()).await;
+
+        // Assert
+        assert!(result.is_err());
+        assert_eq!(repository.fetch_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn given_fallback_disabled_when_query_matches_nothing_then_returns_no_items() {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let config = BkmrConfig {
+            fallback_completions: false,
+            ..Default::default()
+        };
+        let service = CompletionService::with_config(repository.clone(), config);
+
+        // Act
+        let items = service
+            .get_completions(&typo_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        assert!(items.is_empty());
+        assert_eq!(repository.fetch_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn given_query_when_getting_inline_completion_then_matches_first_completion_item() {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let service = CompletionService::new(repository);
+        let context = serde_context();
+
+        // Act
+        let inline = service
+            .inline_completion(&context)
+            .await
+            .expect("valid inline completion")
+            .expect("best snippet");
+        let items = service
+            .get_completions(&context)
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let Some(CompletionTextEdit::Edit(edit)) = &items[0].text_edit else {
+            panic!("expected a text edit");
+        };
+        assert_eq!(inline.range, Some(edit.range));
+        assert_eq!(
+            inline.insert_text,
+            InlineInsertText::snippet(edit.new_text.clone())
+        );
+        assert_eq!(inline.filter_text.as_deref(), Some("serde"));
+    }
+
+    #[tokio::test]
+    async fn given_short_query_when_getting_inline_completion_then_returns_none_without_fetching() {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let service = CompletionService::new(repository.clone());
+        let mut context = serde_context();
+        let range = Range::new(Position::new(0, 0), Position::new(0, 2));
+        context.query = Some(crate::domain::CompletionQuery::new("se".to_string(), range));
+
+        // Act
+        let inline = service
+            .inline_completion(&context)
+            .await
+            .expect("valid inline completion");
+
+        // Assert
+        assert_eq!(inline, None);
+        assert_eq!(repository.fetch_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn given_repeated_request_when_getting_inline_completion_then_reuses_last_result() {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let service = CompletionService::new(repository.clone());
+        let context = serde_context();
+        let mut moved = serde_context();
+        moved.position = Position::new(3, 5);
+
+        // Act
+        let first = service.inline_completion(&context).await.expect("first");
+        let repeated = service.inline_completion(&context).await.expect("repeated");
+        let fetches_after_repeat = repository.fetch_count();
+        service.inline_completion(&moved).await.expect("moved");
+
+        // Assert
+        assert_eq!(first, repeated);
+        assert_eq!(fetches_after_repeat, 1);
+        assert_eq!(repository.fetch_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn given_requests_in_two_documents_when_getting_inline_completion_then_keeps_each() {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let service = CompletionService::new(repository.clone());
+        let context = serde_context();
+        let mut other = serde_context();
+        other.uri = Url::parse("file:///other.rs").expect("parse URI");
+
+        // Act
+        service.inline_completion(&context).await.expect("first");
+        service.inline_completion(&other).await.expect("other");
+        service.inline_completion(&context).await.expect("back");
+        let fetches_while_open = repository.fetch_count();
+        service.close_document(context.uri.as_str());
+        service.inline_completion(&context).await.expect("reopened");
+
+        // Assert
+        assert_eq!(fetches_while_open, 2);
+        assert_eq!(repository.fetch_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn given_positional_tags_when_getting_completions_then_filters_by_line_position() {
+        // Arrange
+        let mut snippets = serde_snippets();
+        snippets[1]
+            .tags
+            .push(crate::domain::LINE_START_TAG.to_string());
+        let mut inline = snippets[0].clone();
+        inline.id = 3;
+        inline.title = "Serde inline TODO".to_string();
+        inline.tags.push(crate::domain::INLINE_TAG.to_string());
+        snippets.push(inline);
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(snippets));
+        let service = CompletionService::new(repository);
+        let labels = |items: Vec<CompletionItem>| -> Vec<String> {
+            let mut labels: Vec<String> = items.into_iter().map(|item| item.label).collect();
+            labels.sort();
+            labels
+        };
+
+        // Act
+        let at_indent = service
+            .get_completions(
+                &serde_context().with_line(crate::domain::LineContext::new("    serde", 4)),
+            )
+            .await
+            .expect("valid completion items");
+        let after_code = service
+            .get_completions(
+                &serde_context().with_line(crate::domain::LineContext::new("x = 1; serde", 7)),
+            )
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        assert_eq!(
+            labels(at_indent),
+            vec!["Serde derive skeleton", "Serialize JSON"]
+        );
+        assert_eq!(
+            labels(after_code),
+            vec!["Serde inline TODO", "Serialize JSON"]
+        );
+    }
+
+    #[tokio::test]
+    async fn given_excluded_tag_when_getting_completions_then_matching_snippet_is_left_out() {
+        // Arrange
+        let mut snippets = serde_snippets();
+        snippets[1].tags.push("deprecated".to_string());
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(snippets));
+        let config = BkmrConfig {
+            excluded_tags: vec!["Deprecated".to_string()],
+            ..Default::default()
+        };
+        let service = CompletionService::with_config(repository, config);
+
+        // Act
+        let items = service
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, vec!["Serialize JSON"]);
+    }
+
+    fn restricted_snippets() -> Vec<Snippet> {
+        let snippet = |id: i32, title: &str, tags: &[&str]| {
+            Snippet::new(
+                id,
+                title.to_string(),
+                format!("{} body", title),
+                String::new(),
+                ["universal", "_snip_"]
+                    .iter()
+                    .chain(tags)
+                    .map(|tag| tag.to_string())
+                    .collect(),
+            )
+        };
+        vec![
+            snippet(1, "Context manager", &["only:python", "contextlib"]),
+            snippet(2, "Error wrap", &["only:go,rust"]),
+            snippet(3, "Retry loop", &["not:go"]),
+            snippet(4, "Todo note", &[]),
+        ]
+    }
+
+    #[tokio::test]
+    async fn given_restriction_tags_when_getting_completions_then_filters_by_document_language() {
+        // Arrange
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(restricted_snippets()));
+        let service = CompletionService::new(repository);
+        let uri = Url::parse("file:///tmp/doc").expect("parse URI");
+
+        // Act
+        let mut labels = Vec::new();
+        for language_id in ["python", "go", "rust", "markdown"] {
+            let context = CompletionContext::new(
+                uri.clone(),
+                Position::new(0, 0),
+                Some(language_id.to_string()),
+            );
+            let mut items: Vec<String> = service
+                .get_completions(&context)
+                .await
+                .expect("valid completion items")
+                .into_iter()
+                .map(|item| item.label)
+                .collect();
+            items.sort();
+            labels.push(items);
+        }
+
+        // Assert
+        assert_eq!(
+            labels,
+            vec![
+                vec!["Context manager", "Retry loop", "Todo note"],
+                vec!["Error wrap", "Todo note"],
+                vec!["Error wrap", "Retry loop", "Todo note"],
+                vec!["Retry loop", "Todo note"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn given_restriction_tags_when_getting_completions_then_label_details_leave_them_out() {
+        // Arrange
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(restricted_snippets()));
+        let service = CompletionService::new(repository);
+        let context = CompletionContext::new(
+            Url::parse("file:///tmp/doc.py").expect("parse URI"),
+            Position::new(0, 0),
+            Some("python".to_string()),
+        );
+
+        // Act
+        let items = service
+            .get_completions(&context)
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let details: Vec<(String, Option<String>)> = items
+            .into_iter()
+            .map(|item| {
+                (
+                    item.label,
+                    item.label_details.and_then(|details| details.detail),
+                )
+            })
+            .collect();
+        assert!(details.contains(&(
+            "Context manager".to_string(),
+            Some(" contextlib".to_string())
+        )));
+        assert!(details.contains(&("Retry loop".to_string(), None)));
+    }
+
+    fn shell_snippet() -> Snippet {
+        Snippet::new(
+            3,
+            "Serde bench".to_string(),
+            "cargo bench --bench serde -- $1".to_string(),
+            "Run the serde benchmarks".to_string(),
+            vec![
+                "rust".to_string(),
+                "_snip_".to_string(),
+                "_shell_".to_string(),
+            ],
+        )
+    }
+
+    #[tokio::test]
+    async fn given_shell_snippet_when_getting_completions_then_it_is_left_out_by_default() {
+        // Arrange
+        let mut snippets = serde_snippets();
+        snippets.push(shell_snippet());
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(snippets));
+        let service = CompletionService::new(repository);
+
+        // Act
+        let items = service
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, vec!["Serde derive skeleton", "Serialize JSON"]);
+    }
+
+    #[tokio::test]
+    async fn given_included_shell_snippet_when_getting_completions_then_marks_it_as_plain_event() {
+        // Arrange
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![shell_snippet()]));
+        let config = BkmrConfig {
+            include_action_snippets: true,
+            ..Default::default()
+        };
+        let service = CompletionService::with_config(repository, config);
+
+        // Act
+        let items = service
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        assert_eq!(items.len(), 1);
+        let item = &items[0];
+        assert_eq!(item.kind, Some(CompletionItemKind::EVENT));
+        assert_eq!(item.insert_text_format, Some(InsertTextFormat::PLAIN_TEXT));
+        assert_eq!(
+            item.label_details
+                .as_ref()
+                .and_then(|details| details.detail.as_deref()),
+            Some(" rust [shell]")
+        );
+        match &item.text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => {
+                assert_eq!(edit.new_text, "cargo bench --bench serde -- $1")
+            }
+            other => panic!("expected text edit, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn given_insert_replace_support_when_getting_completions_then_replace_range_covers_word()
+    {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let service = CompletionService::new(repository);
+        service.set_client_support(ClientCompletionSupport {
+            insert_replace: true,
+            ..Default::default()
+        });
+        let mut context = serde_context();
+        let range = context.get_replacement_range().expect("query range");
+        context.query = Some(
+            crate::domain::CompletionQuery::new("serde".to_string(), range).with_word_end(
+                Position {
+                    line: 0,
+                    character: 8,
+                },
+            ),
+        );
+
+        // Act
+        let items = service
+            .get_completions(&context)
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        match &items[0].text_edit {
+            Some(CompletionTextEdit::InsertAndReplace(edit)) => {
+                assert_eq!(edit.insert.end.character, 5);
+                assert_eq!(edit.replace.end.character, 8);
+                assert_eq!(edit.replace.start, edit.insert.start);
+            }
+            other => panic!("Expected insert/replace edit, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn given_client_without_insert_replace_when_completing_mid_word_then_edit_range_follows_mode()
+     {
+        // Arrange
+        let mut context = serde_context();
+        let range = context.get_replacement_range().expect("query range");
+        context.query = Some(
+            crate::domain::CompletionQuery::new("serde".to_string(), range)
+                .with_word_end(Position::new(0, 8)),
+        );
+        let service = |replace_mode| {
+            CompletionService::with_config(
+                Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets())),
+                BkmrConfig {
+                    replace_mode,
+                    ..Default::default()
+                },
+            )
+        };
+
+        // Act
+        let replacing = service(ReplaceMode::Replace)
+            .get_completions(&context)
+            .await
+            .expect("valid completion items");
+        let inserting = service(ReplaceMode::Insert)
+            .get_completions(&context)
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let edit_end = |items: &[CompletionItem]| match &items[0].text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => edit.range.end.character,
+            other => panic!("Expected plain edit, got {:?}", other),
+        };
+        assert_eq!(edit_end(&replacing), 8);
+        assert_eq!(edit_end(&inserting), 5);
+    }
+
+    #[tokio::test]
+    async fn given_lazy_item_completed_mid_word_when_resolving_then_plain_edit_replaces_word() {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let service = CompletionService::new(repository);
+        service.set_client_support(ClientCompletionSupport {
+            resolve_properties: vec!["documentation".to_string(), "textEdit".to_string()],
+            ..Default::default()
+        });
+        let mut context = serde_context();
+        let range = context.get_replacement_range().expect("query range");
+        context.query = Some(
+            crate::domain::CompletionQuery::new("serde".to_string(), range)
+                .with_word_end(Position::new(0, 8)),
+        );
+        let items = service
+            .get_completions(&context)
+            .await
+            .expect("valid completion items");
+
+        // Act
+        let resolved = service
+            .resolve_completion_item(items[0].clone())
+            .expect("resolve item");
+
+        // Assert
+        match resolved.text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => {
+                assert_eq!(
+                    edit.range,
+                    Range::new(Position::new(0, 0), Position::new(0, 8))
+                )
+            }
+            other => panic!("Expected plain edit, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn given_client_without_markdown_when_getting_completions_then_documentation_is_plain_text()
+     {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let service = CompletionService::new(repository);
+        service.set_client_support(ClientCompletionSupport {
+            markdown_documentation: false,
+            ..Default::default()
+        });
+
+        // Act
+        let items = service
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let item = items
+            .iter()
+            .find(|item| item.label == "Serialize JSON")
+            .expect("serialize snippet");
+        match &item.documentation {
+            Some(Documentation::String(text)) => {
+                assert_eq!(
+                    text,
+                    "Serialize with serde\n\nTags: rust, _snip_\n\nserde_json::to_string(&value)"
+                );
+            }
+            other => panic!("Expected plain text documentation, got {:?}", other),
+        }
+    }
+
+    fn broken_serde_snippets() -> Vec<Snippet> {
+        let mut snippets = serde_snippets();
+        snippets[1].content = "#[derive(${1:Serialize)]".to_string();
+        snippets
+    }
+
+    #[tokio::test]
+    async fn given_broken_placeholder_when_getting_completions_then_item_falls_back_to_plain_text()
+    {
+        // Arrange
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(broken_serde_snippets()));
+        let service = CompletionService::new(repository);
+
+        // Act
+        let items = service
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let broken = items
+            .iter()
+            .find(|item| item.label == "Serde derive skeleton")
+            .expect("broken snippet still offered");
+        assert_eq!(
+            broken.insert_text_format,
+            Some(InsertTextFormat::PLAIN_TEXT)
+        );
+        assert_eq!(broken.detail.as_deref(), Some("bkmr plain text (fallback)"));
+        let valid = items
+            .iter()
+            .find(|item| item.label == "Serialize JSON")
+            .expect("valid snippet");
+        assert_eq!(valid.insert_text_format, Some(InsertTextFormat::SNIPPET));
+    }
+
+    #[tokio::test]
+    async fn given_strict_snippets_when_getting_completions_then_broken_snippet_is_dropped() {
+        // Arrange
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(broken_serde_snippets()));
+        let config = BkmrConfig {
+            strict_snippets: true,
+            ..Default::default()
+        };
+        let service = CompletionService::with_config(repository, config);
+
+        // Act
+        let items = service
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, vec!["Serialize JSON"]);
+        assert_eq!(items[0].sort_text.as_deref(), Some("100000"));
+    }
+
+    #[tokio::test]
+    async fn given_relevance_ranking_when_getting_completions_then_best_match_comes_first() {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let service = CompletionService::new(repository);
+
+        // Act
+        let items = service
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, vec!["Serde derive skeleton", "Serialize JSON"]);
+        assert_eq!(items[0].sort_text.as_deref(), Some("100000"));
+        assert_eq!(items[1].sort_text.as_deref(), Some("100001"));
+    }
+
+    #[tokio::test]
+    async fn given_usage_ranking_when_getting_completions_then_most_used_comes_first() {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let config = BkmrConfig {
+            ranking: crate::domain::RankingStrategy::Usage,
+            ..Default::default()
+        };
+        let service = CompletionService::with_config(repository, config);
+
+        // Act
+        let items = service
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        assert_eq!(items[0].label, "Serialize JSON");
+        assert_eq!(items[0].sort_text.as_deref(), Some("100000"));
+    }
+
+    #[tokio::test]
+    async fn given_usage_tracking_when_getting_completions_then_items_record_usage_after_insert() {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets()));
+        let tracking = CompletionService::with_config(
+            repository.clone(),
+            BkmrConfig {
+                track_usage: true,
+                ..Default::default()
+            },
+        );
+        let untracked = CompletionService::new(repository);
+
+        // Act
+        let items = tracking
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+        let resolved = tracking
+            .resolve_completion_item(items[0].clone())
+            .expect("resolved item");
+        let untracked_items = untracked
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let command = items[0].command.as_ref().expect("record usage command");
+        assert_eq!(command.command, RECORD_USAGE);
+        let data: CompletionItemData =
+            serde_json::from_value(items[0].data.clone().expect("item data")).expect("data");
+        assert_eq!(command.arguments, Some(vec![serde_json::json!(data.id)]));
+        assert_eq!(resolved.command, items[0].command);
+        assert!(untracked_items.iter().all(|item| item.command.is_none()));
+    }
+
+    #[tokio::test]
+    async fn given_document_in_configured_folder_when_getting_completions_then_items_name_the_folder()
+     {
+        // Arrange
+        let repository = Arc::new(crate::repositories::RepositoryRouter::new(
+            Arc::new(MockSnippetRepository::new()),
+            vec![(
+                "/work/app".to_string(),
+                std::path::PathBuf::from("/work/app"),
+                Arc::new(MockSnippetRepository::new().with_snippets(serde_snippets())),
+            )],
+        ));
+        let service = CompletionService::with_config(
+            repository,
+            BkmrConfig {
+                track_usage: true,
+                ..Default::default()
+            },
+        );
+        let mut context = serde_context();
+        context.uri = Url::parse("file:///work/app/main.rs").expect("parse URI");
+
+        // Act
+        let items = service
+            .get_completions(&context)
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let data: CompletionItemData =
+            serde_json::from_value(items[0].data.clone().expect("item data")).expect("data");
+        assert_eq!(data.folder.as_deref(), Some("/work/app"));
+        assert_eq!(
+            items[0]
+                .command
+                .as_ref()
+                .and_then(|command| command.arguments.clone()),
+            Some(vec![
+                serde_json::json!(data.id),
+                serde_json::json!("/work/app")
+            ])
+        );
+    }
+
+    #[test]
+    fn given_item_without_data_when_resolving_then_returns_unchanged() {
+        // Arrange
+        let service = CompletionService::new(Arc::new(MockSnippetRepository::new()));
+        let item = CompletionItem {
+            label: "foreign".to_string(),
+            ..Default::default()
+        };
+
+        // Act
+        let resolved = service
+            .resolve_completion_item(item.clone())
+            .expect("resolved item");
+
+        // Assert
+        assert_eq!(resolved, item);
+    }
+
+    fn titled_snippet(title: &str) -> Snippet {
+        Snippet::new(
+            42,
+            title.to_string(),
+            "kubectl rollout restart".to_string(),
+            String::new(),
+            vec!["_snip_".to_string(), "sh".to_string()],
+        )
+    }
+
+    #[test]
+    fn given_title_with_emoji_prefix_when_creating_item_then_filter_text_is_plain_words() {
+        // Arrange
+        let service = CompletionService::new(Arc::new(MockSnippetRepository::new()));
+        let snippet = titled_snippet("🚀 Deploy: full k8s rollout (canary)!");
+        let uri = Url::parse("file:///deploy.sh").expect("parse URI");
+
+        // Act
+        let item = service
+            .present_item(&snippet, "sh", &uri, |context| context)
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(item.label, "🚀 Deploy: full k8s rollout (canary)!");
+        assert_eq!(
+            item.filter_text.as_deref(),
+            Some("Deploy full k8s rollout canary")
+        );
+        assert_eq!(
+            item.sort_text.as_deref(),
+            Some("deploy full k8s rollout canary")
+        );
+        assert_eq!(
+            item.label_details,
+            Some(CompletionItemLabelDetails {
+                detail: Some(" sh".to_string()),
+                description: Some("#42".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn given_aliases_when_creating_item_then_filter_text_includes_them_and_alias_matches_show() {
+        // Arrange
+        let service = CompletionService::new(Arc::new(MockSnippetRepository::new()));
+        let snippet = Snippet::new(
+            42,
+            "Kubernetes deployment with resource limits".to_string(),
+            "apiVersion: apps/v1".to_string(),
+            "alias:KDep,kd".to_string(),
+            vec!["yaml".to_string(), "_snip_".to_string()],
+        );
+        let uri = Url::parse("file:///deploy.yaml").expect("parse URI");
+
+        // Act
+        let by_alias = service
+            .present_item(&snippet, "yaml", &uri, |context| context.with_query("kde"))
+            .expect("valid completion item");
+        let by_title = service
+            .present_item(&snippet, "yaml", &uri, |context| context.with_query("kub"))
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(
+            by_alias.filter_text.as_deref(),
+            Some("Kubernetes deployment with resource limits KDep kd")
+        );
+        assert_eq!(
+            by_alias.sort_text.as_deref(),
+            Some("kubernetes deployment with resource limits")
+        );
+        assert_eq!(
+            by_alias.label_details.and_then(|details| details.detail),
+            Some(" yaml alias:KDep".to_string())
+        );
+        assert_eq!(
+            by_title.label_details.and_then(|details| details.detail),
+            Some(" yaml".to_string())
+        );
+        assert_eq!(SnippetPresenter::alias_filter_text("deploy", &[]), "deploy");
+    }
+
+    #[test]
+    fn given_case_modes_when_creating_item_then_only_insensitive_lowercases_filter_text() {
+        // Arrange
+        let snippet = titled_snippet("Docker compose UP");
+        let uri = Url::parse("file:///deploy.sh").expect("parse URI");
+        let filter_text = |case_mode| {
+            let service = CompletionService::with_config(
+                Arc::new(MockSnippetRepository::new()),
+                BkmrConfig {
+                    case_mode,
+                    ..Default::default()
+                },
+            );
+            service
+                .present_item(&snippet, "sh", &uri, |context| context)
+                .expect("valid completion item")
+                .filter_text
+        };
+
+        // Act & Assert
+        assert_eq!(
+            filter_text(CaseMode::Insensitive).as_deref(),
+            Some("docker compose up")
+        );
+        assert_eq!(
+            filter_text(CaseMode::Smart).as_deref(),
+            Some("Docker compose UP")
+        );
+        assert_eq!(
+            filter_text(CaseMode::Sensitive).as_deref(),
+            Some("Docker compose UP")
+        );
+    }
+
+    #[test]
+    fn given_title_over_100_chars_when_creating_item_then_label_is_cut_but_filter_text_is_whole() {
+        // Arrange
+        let service = CompletionService::new(Arc::new(MockSnippetRepository::new()));
+        let title = format!("Deploy {}", "very long step ".repeat(8));
+        assert!(title.chars().count() > 100);
+        let snippet = titled_snippet(&title);
+        let uri = Url::parse("file:///deploy.sh").expect("parse URI");
+
+        // Act
+        let item = service
+            .present_item(&snippet, "sh", &uri, |context| context)
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(item.label.chars().count(), 60);
+        assert!(item.label.ends_with('…'));
+        assert!(title.starts_with(item.label.trim_end_matches('…')));
+        let filter_text = item.filter_text.expect("filter text");
+        assert!(filter_text.ends_with("very long step"));
+        assert_eq!(filter_text.split(' ').count(), 25);
+    }
+
+    fn described_snippet(description: &str) -> Snippet {
+        Snippet::new(
+            7,
+            "gci".to_string(),
+            "git commit -i".to_string(),
+            description.to_string(),
+            vec!["sh".to_string(), "_snip_".to_string()],
+        )
+    }
+
+    fn description_service(
+        description_max_length: usize,
+        filter_on_description: bool,
+    ) -> CompletionService {
+        CompletionService::with_config(
+            Arc::new(MockSnippetRepository::new()),
+            BkmrConfig {
+                description_max_length,
+                filter_on_description,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn described_item(service: &CompletionService, snippet: &Snippet) -> CompletionItem {
+        let uri = Url::parse("file:///commit.sh").expect("parse URI");
+        service
+            .present_item(snippet, "sh", &uri, |context| context)
+            .expect("valid completion item")
+    }
+
+    #[test]
+    fn given_description_when_creating_item_then_label_details_show_it_cut_before_the_id() {
+        // Arrange
+        let snippet =
+            described_snippet("Git commit interactive with conventional prefix\nalias:commit");
+
+        // Act
+        let short = described_item(&description_service(20, false), &snippet);
+        let whole = described_item(&description_service(100, false), &snippet);
+        let hidden = described_item(&description_service(0, false), &snippet);
+        let empty = described_item(&description_service(20, false), &described_snippet(""));
+
+        // Assert
+        let description =
+            |item: CompletionItem| item.label_details.and_then(|details| details.description);
+        assert_eq!(
+            description(short).as_deref(),
+            Some("Git commit interact… #7")
+        );
+        assert_eq!(
+            description(whole).as_deref(),
+            Some("Git commit interactive with conventional prefix #7")
+        );
+        assert_eq!(description(hidden).as_deref(), Some("#7"));
+        assert_eq!(description(empty).as_deref(), Some("#7"));
+    }
+
+    #[test]
+    fn given_filter_on_description_when_creating_item_then_filter_text_adds_it_but_sort_text_not() {
+        // Arrange
+        let snippet = described_snippet("Git commit: interactive, with *conventional* prefix");
+
+        // Act
+        let without = described_item(&description_service(40, false), &snippet);
+        let with = described_item(&description_service(40, true), &snippet);
+
+        // Assert
+        assert_eq!(without.filter_text.as_deref(), Some("gci"));
+        assert_eq!(
+            with.filter_text.as_deref(),
+            Some("gci Git commit interactive with conventional prefix")
+        );
+        assert_eq!(with.sort_text, without.sort_text);
+    }
+
+    #[test]
+    fn given_long_description_when_filtering_on_it_then_filter_text_is_capped() {
+        // Arrange
+        let snippet = described_snippet(&"conventional commit ".repeat(30));
+
+        // Act
+        let item = described_item(&description_service(40, true), &snippet);
+
+        // Assert
+        let filter_text = item.filter_text.expect("filter text");
+        assert_eq!(filter_text.chars().count(), MAX_FILTER_TEXT_CHARS);
+        assert!(filter_text.starts_with("gci conventional commit"));
+        assert_eq!(
+            SnippetPresenter::cap_filter_text("short".to_string()),
+            "short"
+        );
+    }
+
+    fn three_line_snippet(language: &str, content: &str) -> Snippet {
+        Snippet::new(
+            7,
+            "Block".to_string(),
+            content.to_string(),
+            String::new(),
+            vec![language.to_string(), "_snip_".to_string()],
+        )
+    }
+
+    #[test]
+    fn given_python_line_indented_with_spaces_when_creating_item_then_indents_continuation_lines() {
+        // Arrange
+        let service = CompletionService::new(Arc::new(MockSnippetRepository::new()));
+        let snippet = three_line_snippet("python", "for x in xs:\n    print(x)\nprint('done')");
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+
+        // Act
+        let item = service
+            .present_item(&snippet, "python", &uri, |context| {
+                context.with_indentation(Some("        "), None)
+            })
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(
+            item.insert_text.as_deref(),
+            Some("for x in xs:\n            print(x)\n        print('done')")
+        );
+        assert_eq!(item.insert_text_mode, None);
+    }
+
+    #[test]
+    fn given_go_line_indented_with_tabs_when_creating_item_then_indents_with_tabs() {
+        // Arrange
+        let service = CompletionService::new(Arc::new(MockSnippetRepository::new()));
+        let snippet = three_line_snippet("go", "if err != nil {\n\treturn err\n}");
+        let uri = Url::parse("file:///test.go").expect("parse URI");
+
+        // Act
+        let item = service
+            .present_item(&snippet, "go", &uri, |context| {
+                context.with_indentation(Some("\t\t"), None)
+            })
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(
+            item.insert_text.as_deref(),
+            Some("if err != nil {\n\t\t\treturn err\n\t\t}")
+        );
+    }
+
+    fn markdown_context(line: &str, query: &str) -> CompletionContext {
+        let end = line.chars().count() as u32;
+        let range = Range::new(
+            Position::new(0, end - query.chars().count() as u32),
+            Position::new(0, end),
+        );
+        let uri = Url::parse("file:///notes.md").expect("parse URI");
+        CompletionContext::new(uri, range.end, Some("markdown".to_string()))
+            .with_query(crate::domain::CompletionQuery::new(
+                query.to_string(),
+                range,
+            ))
+            .with_line(LineContext::new(line, line.len() - query.len()))
+    }
+
+    fn bookmark_service() -> CompletionService {
+        let bookmark = Snippet::new(
+            9,
+            "Rust [book]".to_string(),
+            "https://doc.rust-lang.org/book/".to_string(),
+            String::new(),
+            vec!["rust".to_string()],
+        );
+        let snippet = Snippet::new(
+            3,
+            "Rust fence".to_string(),
+            "```rust\n$0\n```".to_string(),
+            String::new(),
+            vec!["universal".to_string(), "_snip_".to_string()],
+        );
+        CompletionService::new(Arc::new(
+            MockSnippetRepository::new().with_snippets(vec![bookmark, snippet]),
+        ))
+    }
+
+    #[test]
+    fn given_titles_and_urls_with_special_characters_when_linking_then_escapes_them() {
+        // Act & Assert
+        assert_eq!(
+            CompletionService::markdown_link("Rust docs", "https://doc.rust-lang.org/"),
+            "[Rust docs](https://doc.rust-lang.org/)"
+        );
+        assert_eq!(
+            CompletionService::markdown_link("The [Rust] Book \\ 2nd", "https://a.org/x"),
+            r"[The \[Rust\] Book \\ 2nd](https://a.org/x)"
+        );
+        assert_eq!(
+            CompletionService::markdown_link(
+                "Wiki",
+                "https://en.wikipedia.org/wiki/Rust_(language) x"
+            ),
+            "[Wiki](https://en.wikipedia.org/wiki/Rust_%28language%29%20x)"
+        );
+    }
+
+    #[test]
+    fn given_language_and_query_length_when_building_bookmark_filter_then_only_configured_languages_search()
+     {
+        // Arrange
+        let service = CompletionService::with_config(
+            Arc::new(MockSnippetRepository::new()),
+            BkmrConfig {
+                url_completion_languages: vec!["Markdown".to_string(), "asciidoc".to_string()],
+                ..Default::default()
+            },
+        );
+        let mut rust = markdown_context("rus", "rus");
+        rust.language_id = Some("rust".to_string());
+
+        // Act
+        let markdown = service.bookmark_filter(&markdown_context("rus", "rus"));
+        let short = service.bookmark_filter(&markdown_context("ru", "ru"));
+        let other_language = service.bookmark_filter(&rust);
+
+        // Assert
+        let markdown = markdown.expect("bookmark filter for markdown");
+        assert!(markdown.bookmarks);
+        assert_eq!(markdown.query_prefix.as_deref(), Some("rus"));
+        assert_eq!(short, None);
+        assert_eq!(other_language, None);
+    }
+
+    #[tokio::test]
+    async fn given_markdown_query_when_getting_completions_then_appends_bookmark_links_after_snippets()
+     {
+        // Arrange
+        let service = bookmark_service();
+
+        // Act
+        let link = service
+            .get_completions(&markdown_context("See rust", "rust"))
+            .await
+            .expect("completions");
+        let bare = service
+            .get_completions(&markdown_context("[the book](rust", "rust"))
+            .await
+            .expect("completions");
+
+        // Assert
+        assert_eq!(
+            link.iter()
+                .map(|item| item.label.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Rust fence", "Rust [book]"]
+        );
+        let bookmark = &link[1];
+        assert_eq!(bookmark.kind, Some(CompletionItemKind::REFERENCE));
+        assert_eq!(bookmark.detail.as_deref(), Some("bkmr bookmark"));
+        assert!(bookmark.sort_text > link[0].sort_text);
+        let new_text = |item: &CompletionItem| match &item.text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => edit.new_text.clone(),
+            other => panic!("expected text edit, got {:?}", other),
+        };
+        assert_eq!(
+            new_text(bookmark),
+            r"[Rust \[book\]](https://doc.rust-lang.org/book/)"
+        );
+        assert_eq!(new_text(&bare[1]), "https://doc.rust-lang.org/book/");
+    }
+
+    fn context_aware_service() -> CompletionService {
+        CompletionService::with_config(
+            Arc::new(MockSnippetRepository::new()),
+            BkmrConfig {
+                context_aware_insertion: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn given_rust_string_context_when_creating_item_then_escapes_quotes_and_backslashes() {
+        // Arrange
+        let service = context_aware_service();
+        let snippet = three_line_snippet("rust", "say \"${1:hi}\"\nC:\\dir \\$HOME");
+        let uri = Url::parse("file:///test.rs").expect("parse URI");
+        let in_string = InsertionKind::String {
+            quote: '"',
+            multiline: false,
+        };
+
+        // Act
+        let item = service
+            .present_item(&snippet, "rust", &uri, |context| {
+                context.with_insertion_kind(in_string.clone())
+            })
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(
+            item.insert_text.as_deref(),
+            Some("say \\\\\"${1:hi}\\\\\"\\\\nC:\\\\\\\\dir \\$HOME")
+        );
+    }
+
+    #[test]
+    fn given_comment_context_when_creating_item_then_continuation_lines_get_the_leader() {
+        // Arrange
+        let service = context_aware_service();
+        let snippet = three_line_snippet("python", "TODO:\n\n- check ${1:input}");
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+        let line_comment = InsertionKind::Comment {
+            leader: "# ".to_string(),
+        };
+        let block_comment = InsertionKind::Comment {
+            leader: " * ".to_string(),
+        };
+
+        // Act
+        let item = service
+            .present_item(&snippet, "python", &uri, |context| {
+                context
+                    .with_indentation(Some("    "), None)
+                    .with_insertion_kind(line_comment.clone())
+            })
+            .expect("valid completion item");
+        let block_item = service
+            .present_item(&snippet, "rust", &uri, |context| {
+                context.with_insertion_kind(block_comment.clone())
+            })
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(
+            item.insert_text.as_deref(),
+            Some("TODO:\n    #\n    # - check ${1:input}")
+        );
+        assert_eq!(
+            block_item.insert_text.as_deref(),
+            Some("TODO:\n *\n * - check ${1:input}")
+        );
+    }
+
+    #[test]
+    fn given_code_context_or_disabled_option_when_creating_item_then_content_is_unchanged() {
+        // Arrange
+        let snippet = three_line_snippet("rust", "println!(\"{}\", x);\nok");
+        let uri = Url::parse("file:///test.rs").expect("parse URI");
+        let in_string = InsertionKind::String {
+            quote: '"',
+            multiline: false,
+        };
+
+        // Act
+        let code = context_aware_service()
+            .present_item(&snippet, "rust", &uri, |context| {
+                context.with_indentation(Some("  "), None)
+            })
+            .expect("valid completion item");
+        let disabled = CompletionService::new(Arc::new(MockSnippetRepository::new()))
+            .present_item(&snippet, "rust", &uri, |context| {
+                context
+                    .with_indentation(Some("  "), None)
+                    .with_insertion_kind(in_string.clone())
+            })
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(
+            code.insert_text.as_deref(),
+            Some("println!(\"{}\", x);\n  ok")
+        );
+        assert_eq!(disabled.insert_text, code.insert_text);
+    }
+
+    #[test]
+    fn given_client_adjusting_indentation_when_creating_item_then_leaves_content_to_client() {
+        // Arrange
+        let service = CompletionService::new(Arc::new(MockSnippetRepository::new()));
+        service.set_client_support(ClientCompletionSupport {
+            adjusts_indentation: true,
+            ..Default::default()
+        });
+        let snippet = three_line_snippet("go", "if err != nil {\n\treturn err\n}");
+        let uri = Url::parse("file:///test.go").expect("parse URI");
+
+        // Act
+        let item = service
+            .present_item(&snippet, "go", &uri, |context| {
+                context.with_indentation(Some("\t\t"), None)
+            })
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(
+            item.insert_text.as_deref(),
+            Some("if err != nil {\n\treturn err\n}")
+        );
+        assert_eq!(
+            item.insert_text_mode,
+            Some(InsertTextMode::ADJUST_INDENTATION)
+        );
+    }
+
+    fn docstring_snippet(id: i32, tags: &[&str]) -> Snippet {
+        Snippet::new(
+            id,
+            "docstring".to_string(),
+            "\"\"\"$0\"\"\"".to_string(),
+            String::new(),
+            tags.iter().map(|tag| tag.to_string()).collect(),
+        )
+    }
+
+    fn python_context() -> CompletionContext {
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+        CompletionContext::new(
+            uri,
+            Position {
+                line: 0,
+                character: 0,
+            },
+            Some("python".to_string()),
+        )
+    }
+
+    #[tokio::test]
+    async fn given_snippet_returned_twice_when_getting_completions_then_lists_it_once() {
+        // Arrange
+        let snippet = docstring_snippet(1, &["python", "universal", "_snip_"]);
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet.clone(), snippet]));
+        let service = CompletionService::new(repository);
+
+        // Act
+        let items = service
+            .get_completions(&python_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].label, "docstring");
+    }
+
+    #[tokio::test]
+    async fn given_snippets_sharing_title_when_getting_completions_then_labels_carry_tags() {
+        // Arrange
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(vec![
+            docstring_snippet(1, &["python", "_snip_"]),
+            docstring_snippet(2, &["universal", "_snip_"]),
+            docstring_snippet(3, &["python", "_snip_"]),
+        ]));
+        let service = CompletionService::new(repository);
+
+        // Act
+        let items = service
+            .get_completions(&python_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let mut labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+        labels.sort();
+        assert_eq!(
+            labels,
+            vec![
+                "docstring (python, #1)",
+                "docstring (python, #3)",
+                "docstring (universal)"
+            ]
+        );
+        assert!(
+            items
+                .iter()
+                .all(|item| item.filter_text.as_deref() == Some("docstring"))
+        );
+    }
+
+    #[tokio::test]
+    async fn given_duplicates_beyond_limit_when_getting_completions_then_still_fills_limit() {
+        // Arrange
+        let mut snippets = Vec::new();
+        for id in 0..5 {
+            let snippet = docstring_snippet(id, &["python", "_snip_"]);
+            snippets.push(snippet.clone());
+            snippets.push(snippet);
+        }
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(snippets));
+        let config = BkmrConfig {
+            max_completions: 3,
+            ..Default::default()
+        };
+        let service = CompletionService::with_config(repository, config);
+
+        // Act
+        let items = service
+            .get_completions(&python_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        assert_eq!(items.len(), 3);
+        let ids: HashSet<i64> = items
+            .iter()
+            .map(|item| {
+                item.data.as_ref().expect("item data")["id"]
+                    .as_i64()
+                    .expect("id")
+            })
+            .collect();
+        assert_eq!(ids.len(), 3);
+    }
+
+    #[test]
+    fn given_awk_snippet_with_cursor_when_creating_item_then_escapes_fields_but_keeps_tabstop() {
+        // Arrange
+        let snippet = Snippet::new(
+            1,
+            "Sum column".to_string(),
+            "{ total += $2 } END { print total{{ cursor }} }".to_string(),
+            String::new(),
+            vec!["awk".to_string(), "_snip_".to_string()],
+        );
+        let uri = Url::parse("file:///sum.awk").expect("parse URI");
+        let service = CompletionService::new(Arc::new(MockSnippetRepository::new()));
+
+        // Act
+        let item = service
+            .present_item(&snippet, "awk", &uri, |context| context)
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(
+            item.insert_text.as_deref(),
+            Some("{ total += \\$2 } END { print total$0 }")
+        );
+    }
+
+    /// Text of an item's documentation, plain or markdown
+    fn documentation_text(item: &CompletionItem) -> &str {
+        match item.documentation.as_ref().expect("documentation") {
+            Documentation::String(value) => value,
+            Documentation::MarkupContent(content) => &content.value,
+        }
+    }
+
+    fn environment_snippet() -> Snippet {
+        Snippet::new(
+            1,
+            "Backup".to_string(),
+            "cp $1 $CARGO_MANIFEST_DIR/${BKMR_LSP_TEST_UNSET_VARIABLE}".to_string(),
+            String::new(),
+            vec!["bash".to_string(), "_snip_".to_string()],
+        )
+    }
+
+    #[test]
+    fn given_escaped_shell_snippet_when_creating_item_then_documentation_shows_it_unescaped() {
+        // Arrange
+        let uri = Url::parse("file:///backup.sh").expect("parse URI");
+        let service = CompletionService::new(Arc::new(MockSnippetRepository::new()));
+
+        // Act
+        let item = service
+            .present_item(&environment_snippet(), "bash", &uri, |context| context)
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(
+            item.insert_text.as_deref(),
+            Some("cp \\$1 $CARGO_MANIFEST_DIR/${BKMR_LSP_TEST_UNSET_VARIABLE}")
+        );
+        assert!(
+            documentation_text(&item)
+                .contains("cp $1 $CARGO_MANIFEST_DIR/${BKMR_LSP_TEST_UNSET_VARIABLE}")
+        );
+    }
+
+    #[test]
+    fn given_resolve_env_in_docs_when_creating_item_then_only_documentation_shows_values() {
+        // Arrange
+        let uri = Url::parse("file:///backup.sh").expect("parse URI");
+        let service = CompletionService::with_config(
+            Arc::new(MockSnippetRepository::new()),
+            BkmrConfig {
+                resolve_env_in_docs: true,
+                ..Default::default()
+            },
+        );
+
+        // Act
+        let item = service
+            .present_item(&environment_snippet(), "bash", &uri, |context| context)
+            .expect("valid completion item");
+
+        // Assert
+        assert!(
+            documentation_text(&item)
+                .contains(&format!("cp $1 {}/(unset)", env!("CARGO_MANIFEST_DIR")))
+        );
+        assert_eq!(
+            item.insert_text.as_deref(),
+            Some("cp \\$1 $CARGO_MANIFEST_DIR/${BKMR_LSP_TEST_UNSET_VARIABLE}")
+        );
+    }
+
+    #[test]
+    fn given_bkmr_placeholders_when_creating_item_then_converts_unless_disabled() {
+        // Arrange
+        let snippet = Snippet::new(
+            1,
+            "Main".to_string(),
+            "fn main() {\n    {{ cursor }}\n}".to_string(),
+            String::new(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        );
+        let uri = Url::parse("file:///test.rs").expect("parse URI");
+        let converting = CompletionService::new(Arc::new(MockSnippetRepository::new()));
+        let verbatim = CompletionService::with_config(
+            Arc::new(MockSnippetRepository::new()),
+            BkmrConfig {
+                convert_placeholders: false,
+                ..Default::default()
+            },
+        );
+
+        // Act
+        let converted = converting
+            .present_item(&snippet, "rust", &uri, |context| context)
+            .expect("valid completion item");
+        let unconverted = verbatim
+            .present_item(&snippet, "rust", &uri, |context| context)
+            .expect("valid completion item");
+
+        // Assert
+        assert_eq!(
+            converted.insert_text.as_deref(),
+            Some("fn main() {\n    $0\n}")
+        );
+        assert_eq!(
+            unconverted.insert_text.as_deref(),
+            Some("fn main() {\n    {{ cursor }}\n}")
+        );
+    }
+
+    fn numbered_snippets(count: i32) -> Vec<Snippet> {
+        (0..count)
+            .map(|id| {
+                Snippet::new(
+                    id,
+                    format!("serde example {}", id),
+                    "content".to_string(),
+                    String::new(),
+                    vec!["rust".to_string(), "_snip_".to_string()],
+                )
+            })
+            .collect()
+    }
+
+    fn rust_query_context(query: &str) -> CompletionContext {
+        let uri = Url::parse("file:///test.rs").expect("parse URI");
+        let range = Range {
+            start: Position {
+                line: 0,
+                character: 0,
+            },
+            end: Position {
+                line: 0,
+                character: query.len() as u32,
+            },
+        };
+        CompletionContext::new(uri, range.end, Some("rust".to_string())).with_query(
+            crate::domain::CompletionQuery::new(query.to_string(), range),
+        )
+    }
+
+    fn paged_service(
+        snippets: Vec<Snippet>,
+        max_completions: usize,
+        page_size: usize,
+    ) -> (Arc<MockSnippetRepository>, CompletionService) {
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(snippets));
+        let config = BkmrConfig {
+            max_completions,
+            page_size,
+            ..Default::default()
+        };
+        let service = CompletionService::with_config(repository.clone(), config);
+        (repository, service)
+    }
+
+    #[test]
+    fn given_language_override_when_building_filter_then_uses_language_limit() {
+        // Arrange
+        let config = BkmrConfig {
+            max_completions: 50,
+            per_language: crate::domain::parse_language_overrides("Rust:limit=20,sh:limit=100")
+                .expect("valid overrides"),
+            ..Default::default()
+        };
+        let service =
+            CompletionService::with_config(Arc::new(MockSnippetRepository::new()), config);
+        let markdown = CompletionContext::new(
+            Url::parse("file:///notes.md").expect("parse URI"),
+            Position::new(0, 0),
+            Some("markdown".to_string()),
+        );
+
+        // Act
+        let rust = service.build_snippet_filter(&rust_query_context("serde"));
+        let markdown = service.build_snippet_filter(&markdown);
+        let shell = service.language_filter(&rust_query_context("").uri, "sh", None);
+
+        // Assert
+        assert_eq!(rust.max_results, 20 + DUPLICATE_HEADROOM);
+        assert_eq!(markdown.max_results, 50 + DUPLICATE_HEADROOM);
+        assert_eq!(shell.max_results, 100 + DUPLICATE_HEADROOM);
+    }
+
+    #[test]
+    fn given_folder_route_and_case_mode_when_building_language_filter_then_matches_empty_query_filter()
+     {
+        // Arrange
+        let repository = Arc::new(crate::repositories::RepositoryRouter::new(
+            Arc::new(MockSnippetRepository::new()),
+            vec![(
+                "/work/app".to_string(),
+                std::path::PathBuf::from("/work/app"),
+                Arc::new(MockSnippetRepository::new()),
+            )],
+        ));
+        let config = BkmrConfig {
+            case_mode: CaseMode::Sensitive,
+            ..Default::default()
+        };
+        let service = CompletionService::with_config(repository, config);
+        let uri = Url::parse("file:///work/app/main.rs").expect("parse URI");
+        let context = CompletionContext::new(uri.clone(), Position::new(0, 0), Some("rust".into()));
+        let tagged = context.clone().with_tag_filter("kubernetes".to_string());
+
+        // Act
+        let prefetched = service.language_filter(&uri, "rust", None);
+        let prefetched_tagged = service.language_filter(&uri, "rust", Some("kubernetes".into()));
+
+        // Assert
+        assert_eq!(prefetched, service.build_snippet_filter(&context));
+        assert_eq!(prefetched_tagged, service.build_snippet_filter(&tagged));
+        assert_eq!(prefetched.folder.as_deref(), Some("/work/app"));
+        assert_eq!(prefetched.case_mode, CaseMode::Sensitive);
+    }
+
+    #[tokio::test]
+    async fn given_language_override_when_getting_completion_list_then_pages_and_ranks_per_language()
+     {
+        // Arrange
+        let mut snippets = numbered_snippets(6);
+        snippets[5].access_count = 10;
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(snippets));
+        let config = BkmrConfig {
+            per_language: crate::domain::parse_language_overrides(
+                "rust:limit=2,rust:ranking=usage",
+            )
+            .expect("valid overrides"),
+            ..Default::default()
+        };
+        let service = CompletionService::with_config(repository, config);
+
+        // Act
+        let list = service
+            .get_completion_list(&rust_query_context("serde"), false)
+            .await
+            .expect("valid completion list");
+
+        // Assert
+        assert_eq!(list.items.len(), 2);
+        assert!(list.is_incomplete);
+        assert_eq!(list.items[0].label, "serde example 5");
+    }
+
+    #[tokio::test]
+    async fn given_results_under_limit_when_getting_completion_list_then_is_complete() {
+        // Arrange
+        let (_, service) = paged_service(numbered_snippets(3), 5, 5);
+
+        // Act
+        let list = service
+            .get_completion_list(&rust_query_context("serde"), false)
+            .await
+            .expect("valid completion list");
+
+        // Assert
+        assert_eq!(list.items.len(), 3);
+        assert!(!list.is_incomplete);
+    }
+
+    #[tokio::test]
+    async fn given_repository_returning_full_limit_when_getting_completion_list_then_is_incomplete()
+    {
+        // Arrange: the fetch limit is max_completions plus the duplicate headroom
+        let (_, service) = paged_service(numbered_snippets(40), 5, 5);
+
+        // Act
+        let list = service
+            .get_completion_list(&rust_query_context("serde"), false)
+            .await
+            .expect("valid completion list");
+
+        // Assert
+        assert_eq!(list.items.len(), 5);
+        assert!(list.is_incomplete);
+    }
+
+    #[tokio::test]
+    async fn given_repository_reporting_more_matches_when_getting_completion_list_then_is_incomplete()
+     {
+        // Arrange
+        let repository = Arc::new(
+            MockSnippetRepository::new()
+                .with_snippets(numbered_snippets(3))
+                .with_search_complete(false),
+        );
+        let service = CompletionService::new(repository);
+
+        // Act
+        let list = service
+            .get_completion_list(&rust_query_context("serde"), false)
+            .await
+            .expect("valid completion list");
+
+        // Assert
+        assert_eq!(list.items.len(), 3);
+        assert!(list.is_incomplete);
+    }
+
+    #[tokio::test]
+    async fn given_more_results_than_page_size_when_getting_completion_list_then_is_incomplete() {
+        // Arrange
+        let (_, service) = paged_service(numbered_snippets(3), 50, 2);
+
+        // Act
+        let list = service
+            .get_completion_list(&rust_query_context("serde"), false)
+            .await
+            .expect("valid completion list");
+
+        // Assert
+        assert_eq!(list.items.len(), 2);
+        assert!(list.is_incomplete);
+    }
+
+    #[tokio::test]
+    async fn given_complete_previous_result_when_narrowing_longer_query_then_filters_without_fetch()
+    {
+        // Arrange
+        let (repository, service) = paged_service(numbered_snippets(12), 50, 5);
+        service
+            .get_completion_list(&rust_query_context("serde"), false)
+            .await
+            .expect("first page");
+
+        // Act
+        let narrowed = service
+            .get_completion_list(&rust_query_context("serde example 1"), true)
+            .await
+            .expect("narrowed page");
+        let shorter = service
+            .get_completion_list(&rust_query_context("ser"), true)
+            .await
+            .expect("shorter query page");
+
+        // Assert: ids 1, 10 and 11 contain "serde example 1"; the shorter query refetches
+        assert_eq!(narrowed.items.len(), 3);
+        assert!(!narrowed.is_incomplete);
+        assert_eq!(shorter.items.len(), 5);
+        assert_eq!(repository.fetch_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn given_full_previous_result_when_narrowing_longer_query_then_refetches() {
+        // Arrange
+        let (repository, service) = paged_service(numbered_snippets(40), 5, 5);
+        service
+            .get_completion_list(&rust_query_context("serde"), false)
+            .await
+            .expect("first page");
+
+        // Act
+        service
+            .get_completion_list(&rust_query_context("serde example"), true)
+            .await
+            .expect("second page");
+
+        // Assert
+        assert_eq!(repository.fetch_count(), 2);
+    }
+
+    /// Whether a process is gone or only waits to be reaped
+    #[cfg(unix)]
+    fn process_ended(pid: &str) -> bool {
+        let output = std::process::Command::new("ps")
+            .args(["-o", "stat=", "-p", pid])
+            .output()
+            .expect("run ps");
+        let state = String::from_utf8_lossy(&output.stdout);
+        state.trim().is_empty() || state.trim_start().starts_with('Z')
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn given_slow_bkmr_when_newer_request_supersedes_then_old_process_is_killed_and_list_empty()
+     {
+        use crate::repositories::{BkmrRepository, RepositoryConfig};
+        use std::os::unix::fs::PermissionsExt;
+
+        // Arrange: the first search hangs, later ones answer right away
+        let dir = std::env::temp_dir().join(format!("bkmr-lsp-supersede-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create temp dir");
+        let pid_file = dir.join("pid");
+        let _ = std::fs::remove_file(&pid_file);
+        let binary = dir.join("bkmr");
+        std::fs::write(
+            &binary,
+            format!(
+                "#!/bin/sh\nif [ -e {pid} ]; then\n  echo '[{{\"id\": 1, \"title\": \"Hello\", \"url\": \"hi\", \"tags\": [\"rust\", \"_snip_\"]}}]'\nelse\n  echo $$ > {pid}.tmp && mv {pid}.tmp {pid}\n  exec sleep 30\nfi\n",
+                pid = pid_file.display()
+            ),
+        )
+        .expect("write fake bkmr");
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755))
+            .expect("make fake bkmr executable");
+        let repository = Arc::new(BkmrRepository::new(RepositoryConfig {
+            binary_path: binary.to_string_lossy().to_string(),
+            ..Default::default()
+        }));
+        let service = Arc::new(CompletionService::new(repository));
+        let context = serde_context();
+
+        let first = tokio::spawn({
+            let service = service.clone();
+            let context = context.clone();
+            async move { service.get_latest_completion_list(&context, false).await }
+        });
+        let mut pid = String::new();
+        for _ in 0..100 {
+            if let Ok(content) = std::fs::read_to_string(&pid_file) {
+                pid = content.trim().to_string();
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(!pid.is_empty(), "first bkmr search did not start");
+
+        // Act
+        let second = service
+            .get_latest_completion_list(&context, false)
+            .await
+            .expect("second completion");
+        let first = first.await.expect("join").expect("first completion");
+
+        // Assert
+        assert!(first.items.is_empty());
+        assert!(first.is_incomplete);
+        assert_eq!(second.items.len(), 1);
+        let mut ended = false;
+        for _ in 0..100 {
+            ended = process_ended(&pid);
+            if ended {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(ended, "superseded bkmr process {} still running", pid);
+    }
+
+    fn typing_snippets() -> Vec<Snippet> {
+        [
+            "Serialize JSON",
+            "Send trait object",
+            "Serde derive",
+            "Sort vector",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, title)| {
+            Snippet::new(
+                i as i32 + 1,
+                title.to_string(),
+                format!("content {}", i),
+                String::new(),
+                vec!["rust".to_string(), "_snip_".to_string()],
+            )
+        })
+        .collect()
+    }
+
+    fn typing_service(
+        min_query_interval_ms: u64,
+    ) -> (CompletionService, Arc<MockSnippetRepository>) {
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(typing_snippets()));
+        let service = CompletionService::with_config(
+            repository.clone(),
+            BkmrConfig {
+                min_query_interval_ms,
+                ..Default::default()
+            },
+        );
+        (service, repository)
+    }
+
+    #[tokio::test]
+    async fn given_burst_of_queries_when_completing_then_searches_repository_at_most_twice() {
+        // Arrange
+        let (service, repository) = typing_service(60_000);
+
+        // Act
+        let mut lists = Vec::new();
+        for query in ["s", "se", "ser"] {
+            lists.push(
+                service
+                    .get_latest_completion_list(&rust_query_context(query), false)
+                    .await
+                    .expect("completion list"),
+            );
+        }
+
+        // Assert: narrowed results rank like a fresh search
+        assert!(repository.fetch_count() <= 2);
+        let (fresh_service, _) = typing_service(0);
+        let fresh = fresh_service
+            .get_latest_completion_list(&rust_query_context("ser"), false)
+            .await
+            .expect("fresh completion list");
+        let labels = |list: &CompletionList| -> Vec<String> {
+            list.items.iter().map(|item| item.label.clone()).collect()
+        };
+        assert_eq!(labels(&lists[2]), labels(&fresh));
+        assert_eq!(labels(&lists[2]).len(), 2);
+        assert_eq!(lists[1].items.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn given_zero_interval_when_completing_burst_then_searches_for_every_query() {
+        // Arrange
+        let (service, repository) = typing_service(0);
+
+        // Act
+        for query in ["s", "se", "ser"] {
+            service
+                .get_latest_completion_list(&rust_query_context(query), false)
+                .await
+                .expect("completion list");
+        }
+
+        // Assert
+        assert_eq!(repository.fetch_count(), 3);
+    }
+
+    #[test]
+    fn given_ranked_snippets_when_refiltering_then_keeps_title_matches_in_order() {
+        // Arrange
+        let mut snippets = typing_snippets();
+        snippets.reverse();
+        snippets[0].description = "serde helpers".to_string();
+        let service = CompletionService::new(Arc::new(MockSnippetRepository::new()));
+        let filter = SnippetFilter::default().with_case_mode(CaseMode::Insensitive);
+
+        // Act
+        let refiltered = service.refilter(&snippets, "SER", &filter);
+
+        // Assert
+        let titles: Vec<&str> = refiltered
+            .iter()
+            .map(|snippet| snippet.title.as_str())
+            .collect();
+        assert_eq!(titles, vec!["Serde derive", "Serialize JSON"]);
+    }
+
+    #[test]
+    fn given_alias_search_when_refiltering_then_keeps_description_matches_too() {
+        // Arrange
+        let mut snippets = typing_snippets();
+        snippets.reverse();
+        snippets[0].description = "serde helpers".to_string();
+        let service = CompletionService::with_config(
+            Arc::new(MockSnippetRepository::new()),
+            BkmrConfig {
+                alias_search: true,
+                ..Default::default()
+            },
+        );
+
+        // Act
+        let refiltered = service.refilter(&snippets, "ser", &SnippetFilter::default());
+
+        // Assert
+        assert_eq!(refiltered.len(), 3);
+        assert_eq!(refiltered[0].description, "serde helpers");
+    }
+
+    #[test]
+    fn given_smart_case_when_refiltering_then_uppercase_query_matches_case() {
+        // Arrange
+        let snippets = typing_snippets();
+        let service = CompletionService::new(Arc::new(MockSnippetRepository::new()));
+        let smart = SnippetFilter::default().with_case_mode(CaseMode::Smart);
+        let sensitive = SnippetFilter::default().with_case_mode(CaseMode::Sensitive);
+
+        // Act
+        let lowercase = service.refilter(&snippets, "ser", &smart);
+        let uppercase = service.refilter(&snippets, "SER", &smart);
+        let sensitive = service.refilter(&snippets, "ser", &sensitive);
+
+        // Assert
+        assert_eq!(lowercase.len(), 2);
+        assert!(uppercase.is_empty());
+        assert!(sensitive.is_empty());
+    }
+
+    #[tokio::test]
+    async fn given_slow_search_when_longer_query_follows_then_old_fetch_is_dropped_and_not_reused()
+    {
+        // Arrange
+        let repository = Arc::new(
+            MockSnippetRepository::new()
+                .with_snippets(typing_snippets())
+                .with_delay(std::time::Duration::from_millis(200)),
+        );
+        let service = Arc::new(CompletionService::with_config(
+            repository.clone(),
+            BkmrConfig {
+                min_query_interval_ms: 60_000,
+                ..Default::default()
+            },
+        ));
+        let first = tokio::spawn({
+            let service = service.clone();
+            async move {
+                service
+                    .get_latest_completion_list(&rust_query_context("s"), false)
+                    .await
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        // Act
+        let second = service
+            .get_latest_completion_list(&rust_query_context("se"), false)
+            .await
+            .expect("second completion");
+        let first = first.await.expect("join").expect("first completion");
+
+        // Assert
+        assert!(first.items.is_empty());
+        assert!(first.is_incomplete);
+        assert_eq!(second.items.len(), 3);
+        assert_eq!(repository.fetch_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn given_partial_tag_when_completing_frontmatter_tags_then_offers_matching_tags_with_counts()
+     {
+        // Arrange
+        let tagged = |id, tags: &[&str]| {
+            Snippet::new(
+                id,
+                format!("snippet {}", id),
+                "content".to_string(),
+                String::new(),
+                tags.iter().map(|tag| tag.to_string()).collect(),
+            )
+        };
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(vec![
+            tagged(1, &["Kubernetes", "shell", "_snip_"]),
+            tagged(2, &["kubectl", "kubernetes", "_snip_"]),
+            tagged(3, &["rust", "_snip_"]),
+        ]));
+        let service = CompletionService::new(repository);
+        let range = Range::new(Position::new(1, 6), Position::new(1, 9));
+
+        // Act
+        let items = service
+            .tag_completions("KUB", range)
+            .await
+            .expect("tag completions");
+
+        // Assert
+        let labels: Vec<(&str, Option<&str>)> = items
+            .iter()
+            .map(|item| {
+                (
+                    item.label.as_str(),
+                    item.label_details
+                        .as_ref()
+                        .and_then(|details| details.description.as_deref()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                ("Kubernetes", Some("2 bookmarks")),
+                ("kubectl", Some("1 bookmark"))
+            ]
+        );
+        assert_eq!(items[0].kind, Some(CompletionItemKind::KEYWORD));
+        assert_eq!(
+            items[1].text_edit,
+            Some(CompletionTextEdit::Edit(TextEdit {
+                range,
+                new_text: "kubectl".to_string(),
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn given_item_kind_override_when_creating_items_then_plain_snippets_stay_text() {
+        // Arrange
+        let config = BkmrConfig {
+            item_kind: Some(ItemKind::Reference),
+            detail_template: Some("bkmr #{id}".to_string()),
+            ..Default::default()
+        };
+        let service =
+            CompletionService::with_config(Arc::new(MockSnippetRepository::new()), config);
+        let uri = Url::parse("file:///test.rs").expect("parse URI");
+        let snippet = |id, tags: &[&str]| {
+            Snippet::new(
+                id,
+                format!("snippet {}", id),
+                "content $1".to_string(),
+                String::new(),
+                tags.iter().map(|tag| tag.to_string()).collect(),
+            )
+        };
+        let item = |snippet: &Snippet| {
+            service
+                .present_item(snippet, "rust", &uri, |context| context)
+                .expect("valid completion item")
+        };
+
+        // Act
+        let regular = item(&snippet(1, &["rust", "_snip_"]));
+        let plain = item(&snippet(2, &["rust", "_snip_", "plain"]));
+
+        // Assert
+        assert_eq!(regular.kind, Some(CompletionItemKind::REFERENCE));
+        assert_eq!(regular.insert_text_format, Some(InsertTextFormat::SNIPPET));
+        assert_eq!(regular.detail.as_deref(), Some("bkmr #1"));
+        assert_eq!(plain.kind, Some(CompletionItemKind::TEXT));
+        assert_eq!(plain.insert_text_format, Some(InsertTextFormat::PLAIN_TEXT));
+        assert_eq!(plain.detail.as_deref(), Some("bkmr #2"));
+    }
+
+    #[tokio::test]
+    async fn given_label_prefix_when_getting_completions_then_prefixes_labels_but_not_filter_text()
+    {
+        // Arrange
+        let config = BkmrConfig {
+            label_prefix: "󰆐 ".to_string(),
+            ..Default::default()
+        };
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(vec![
+            docstring_snippet(1, &["python", "_snip_"]),
+            docstring_snippet(2, &["python", "_snip_", "sql"]),
+            Snippet::new(
+                3,
+                "Dataclass".to_string(),
+                "@dataclass".to_string(),
+                String::new(),
+                vec!["python".to_string(), "_snip_".to_string()],
+            ),
+        ]));
+        let service = CompletionService::with_config(repository, config);
+
+        // Act
+        let items = service
+            .get_completions(&python_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let mut labels: Vec<(&str, Option<&str>)> = items
+            .iter()
+            .map(|item| (item.label.as_str(), item.filter_text.as_deref()))
+            .collect();
+        labels.sort();
+        assert_eq!(
+            labels,
+            vec![
+                ("󰆐 Dataclass", Some("Dataclass")),
+                ("󰆐 docstring (python)", Some("docstring")),
+                ("󰆐 docstring (python, sql)", Some("docstring")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn given_interpolation_setting_when_getting_completions_then_data_marks_interpolated_bkmr_snippets()
+     {
+        // Arrange
+        let snippets = vec![
+            docstring_snippet(1, &["python", "_snip_"]),
+            docstring_snippet(-1, &["python", "_snip_"]),
+        ];
+        let interpolating = CompletionService::with_config(
+            Arc::new(MockSnippetRepository::new().with_snippets(snippets.clone())),
+            BkmrConfig::default(),
+        );
+        let raw = CompletionService::with_config(
+            Arc::new(MockSnippetRepository::new().with_snippets(snippets)),
+            BkmrConfig {
+                enable_interpolation: false,
+                ..Default::default()
+            },
+        );
+        let interpolated_by_id = |items: Vec<CompletionItem>| {
+            let mut flags: Vec<(i32, bool)> = items
+                .into_iter()
+                .map(|item| {
+                    let data: CompletionItemData =
+                        serde_json::from_value(item.data.expect("item data")).expect("parse data");
+                    (data.id, data.interpolated)
+                })
+                .collect();
+            flags.sort();
+            flags
+        };
+
+        // Act
+        let interpolating_items = interpolating
+            .get_completions(&python_context())
+            .await
+            .expect("valid completion items");
+        let raw_items = raw
+            .get_completions(&python_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        assert_eq!(
+            interpolated_by_id(interpolating_items),
+            vec![(-1, false), (1, true)]
+        );
+        assert_eq!(interpolated_by_id(raw_items), vec![(-1, false), (1, false)]);
+    }
+
+    fn tiered_snippets() -> Vec<Snippet> {
+        let rust = |id: i32, title: &str, extra: &[&str], access_count: i32| {
+            let mut tags = vec!["rust".to_string(), "_snip_".to_string()];
+            tags.extend(extra.iter().map(|tag| tag.to_string()));
+            let mut snippet = Snippet::new(
+                id,
+                title.to_string(),
+                "content".to_string(),
+                String::new(),
+                tags,
+            );
+            snippet.access_count = access_count;
+            snippet
+        };
+        vec![
+            rust(1, "Serde", &[], 0),
+            rust(2, "Serde derive", &[], 0),
+            rust(3, "Serde universal", &["universal"], 50),
+        ]
+    }
+
+    #[tokio::test]
+    async fn given_exact_language_and_universal_matches_when_getting_completions_then_tiers_never_interleave()
+     {
+        // Arrange: usage ranks the universal snippet first within prefix matches
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(tiered_snippets()));
+        let service = CompletionService::new(repository);
+
+        // Act
+        let items = service
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let tiers: Vec<(&str, &str, CompletionTier)> = items
+            .iter()
+            .map(|item| {
+                let data: CompletionItemData =
+                    serde_json::from_value(item.data.clone().expect("item data"))
+                        .expect("parse data");
+                (
+                    item.label.as_str(),
+                    item.sort_text.as_deref().expect("sort text"),
+                    data.tier,
+                )
+            })
+            .collect();
+        assert_eq!(
+            tiers,
+            vec![
+                ("Serde", "000001", CompletionTier::Exact),
+                ("Serde derive", "100002", CompletionTier::Language),
+                ("Serde universal", "200000", CompletionTier::Universal),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn given_custom_tier_order_when_getting_completions_then_universal_snippets_come_first() {
+        // Arrange
+        let config = BkmrConfig {
+            tier_order: "universal,exact".parse().expect("tier order"),
+            ..Default::default()
+        };
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(tiered_snippets()));
+        let service = CompletionService::with_config(repository, config);
+
+        // Act
+        let items = service
+            .get_completions(&serde_context())
+            .await
+            .expect("valid completion items");
+
+        // Assert
+        let sort_texts: Vec<(&str, &str)> = items
+            .iter()
+            .map(|item| {
+                (
+                    item.label.as_str(),
+                    item.sort_text.as_deref().expect("sort text"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            sort_texts,
+            vec![
+                ("Serde universal", "000000"),
+                ("Serde", "100001"),
+                ("Serde derive", "200002"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn given_oversized_snippet_when_completing_then_its_content_is_never_translated() {
+        // Arrange: a small snippet and one of 500 bytes, over the 100 byte limit
+        let bkmr_snippets = vec![
+            BkmrSnippet {
+                id: 1,
+                title: "small".to_string(),
+                url: "ok".to_string(),
+                description: String::new(),
+                tags: vec!["universal".to_string(), "_snip_".to_string()],
+                access_count: 0,
+            },
+            BkmrSnippet {
+                id: 2,
+                title: "fixture".to_string(),
+                url: "x".repeat(500),
+                description: String::new(),
+                tags: vec!["universal".to_string(), "_snip_".to_string()],
+                access_count: 0,
+            },
+        ];
+        let service = |mode: LargeSnippetMode| {
+            let limit = ContentLimit {
+                max_bytes: 100,
+                mode,
+            };
+            CompletionService::new(Arc::new(
+                MockSnippetRepository::new().with_snippets(limit.apply(bkmr_snippets.clone())),
+            ))
+        };
+        let uri = Url::parse("file:///fixture.rs").expect("parse URI");
+        let context = CompletionContext::new(uri, Position::new(0, 0), Some("rust".to_string()));
+
+        // Act
+        let skipped = service(LargeSnippetMode::Skip)
+            .get_completion_list(&context, false)
+            .await
+            .expect("completions in skip mode");
+        let stubbed = service(LargeSnippetMode::Stub)
+            .get_completion_list(&context, false)
+            .await
+            .expect("completions in stub mode");
+
+        // Assert
+        let labels = |list: &CompletionList| {
+            list.items
+                .iter()
+                .map(|item| item.label.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(labels(&skipped), vec!["small"]);
+        assert_eq!(labels(&stubbed), vec!["fixture", "small"]);
+        let stub = &stubbed.items[0];
+        assert_eq!(stub.insert_text_format, Some(InsertTextFormat::PLAIN_TEXT));
+        assert_eq!(
+            stub.insert_text.as_deref(),
+            Some(
+                "[bkmr snippet 2: content too large (500 B), use bkmr.insertSnippetById to insert]"
+            )
+        );
+    }
 }
@@ -1,29 +1,277 @@
 use anyhow::{Context, Result};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tower_lsp::lsp_types::{Position, Range, Url};
-use tracing::{debug, instrument};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use tokio::sync::{Mutex, RwLock};
+use tower_lsp::lsp_types::{Position, Range, TextDocumentContentChangeEvent, Url};
+use tracing::{debug, instrument, warn};
 
-use crate::domain::{CompletionContext, CompletionQuery};
+use crate::domain::{
+    CompletionContext, CompletionQuery, DocumentImports, FENCED_LANGUAGES, FenceScan,
+    FrontmatterTagQuery, IndentUnit, InsertionKind, LanguageRegistry, LineContext,
+    PositionEncoding, uri_file_name,
+};
+use crate::sync;
+
+/// Characters besides alphanumerics, '_' and '-' that belong to a completion query
+pub const DEFAULT_WORD_CHARS: [char; 2] = ['.', '/'];
+
+/// Lines above the completion line scanned for an open block comment or string
+const INSERTION_LOOKBACK_LINES: u32 = 20;
+
+/// Default total size of document texts kept in memory
+pub const DEFAULT_DOC_CACHE_BYTES: usize = 50 * 1024 * 1024;
+
+/// Text of an open document and when it was last used
+#[derive(Debug)]
+struct CachedDocument {
+    text: String,
+    /// Tick of the last change or completion, higher is more recent
+    last_used: AtomicU64,
+}
+
+/// Document texts bounded in total size, least recently used ones are evicted first
+///
+/// Evicted documents stay open for the client; their URIs are remembered so
+/// that completions and changes for them are expected rather than errors.
+#[derive(Debug, Default)]
+struct DocumentStore {
+    documents: HashMap<String, CachedDocument>,
+    /// Open documents whose text was dropped to stay under the size cap
+    evicted: HashSet<String>,
+    /// Total bytes of the cached texts
+    bytes: usize,
+    /// Source of `last_used` ticks
+    clock: AtomicU64,
+}
+
+impl DocumentStore {
+    fn get(&self, uri: &str) -> Option<&String> {
+        self.documents.get(uri).map(|document| &document.text)
+    }
+
+    fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    fn is_evicted(&self, uri: &str) -> bool {
+        self.evicted.contains(uri)
+    }
+
+    /// Mark a document as just used, making it the last one to be evicted
+    fn touch(&self, uri: &str) {
+        if let Some(document) = self.documents.get(uri) {
+            let tick = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+            document.last_used.store(tick, Ordering::Relaxed);
+        }
+    }
+
+    /// Store a document's text, evicting others if the total exceeds `max_bytes`
+    fn insert(&mut self, uri: String, text: String, max_bytes: usize) {
+        self.evicted.remove(&uri);
+        self.bytes += text.len();
+        let previous = self.documents.insert(
+            uri.clone(),
+            CachedDocument {
+                text,
+                last_used: AtomicU64::new(0),
+            },
+        );
+        if let Some(previous) = previous {
+            self.bytes -= previous.text.len();
+        }
+        self.touch(&uri);
+        self.evict(&uri, max_bytes);
+    }
+
+    /// Change a document's text in place, None if it is not cached
+    fn update<R>(
+        &mut self,
+        uri: &str,
+        max_bytes: usize,
+        change: impl FnOnce(&mut String) -> R,
+    ) -> Option<R> {
+        let document = self.documents.get_mut(uri)?;
+        let before = document.text.len();
+        let result = change(&mut document.text);
+        self.bytes = self.bytes - before + document.text.len();
+        self.touch(uri);
+        self.evict(uri, max_bytes);
+        Some(result)
+    }
+
+    /// Forget a closed or discarded document
+    fn remove(&mut self, uri: &str) {
+        self.evicted.remove(uri);
+        if let Some(document) = self.documents.remove(uri) {
+            self.bytes -= document.text.len();
+        }
+    }
+
+    /// Drop least recently used documents until the total fits, never the one to keep
+    ///
+    /// A single document larger than the cap stays cached on its own.
+    fn evict(&mut self, keep: &str, max_bytes: usize) {
+        while max_bytes > 0 && self.bytes > max_bytes {
+            let Some(uri) = self
+                .documents
+                .iter()
+                .filter(|(uri, _)| uri.as_str() != keep)
+                .min_by_key(|(_, document)| document.last_used.load(Ordering::Relaxed))
+                .map(|(uri, _)| uri.clone())
+            else {
+                break;
+            };
+            let document = self
+                .documents
+                .remove(&uri)
+                .expect("evicted document exists");
+            self.bytes -= document.text.len();
+            debug!(
+                "Evicted {} ({} bytes) from the document cache, {} bytes cached",
+                uri,
+                document.text.len(),
+                self.bytes
+            );
+            self.evicted.insert(uri);
+        }
+    }
+}
+
+/// Version bookkeeping for a cached document
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DocumentVersion {
+    /// Latest version received via didOpen or didChange
+    pub version: i32,
+    /// Version the client last reported as saved
+    pub saved: Option<i32>,
+}
+
+/// Indentation detected in a document, with the version and length of the text inspected
+type IndentDetection = (i32, usize, Option<IndentUnit>);
+
+/// Text, version and language of a document read together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentSnapshot {
+    pub text: String,
+    /// Latest version received via didOpen or didChange, None if never versioned
+    pub version: Option<i32>,
+    pub language_id: Option<String>,
+}
 
 /// Service for managing document state and extracting completion queries
 #[derive(Debug)]
 pub struct DocumentService {
-    /// Cache of document contents
-    document_cache: Arc<RwLock<HashMap<String, String>>>,
+    /// Cache of document contents, bounded by `cache_bytes`
+    document_cache: Arc<RwLock<DocumentStore>>,
     /// Cache of document language IDs
     language_cache: Arc<RwLock<HashMap<String, String>>>,
+    /// Versions of documents opened or changed with a version number
+    version_cache: Arc<RwLock<HashMap<String, DocumentVersion>>>,
+    /// Encoding of `Position.character` agreed with the client
+    position_encoding: std::sync::RwLock<PositionEncoding>,
+    /// Prefix character that starts a snippet query, e.g. ':'
+    trigger_char: std::sync::RwLock<Option<char>>,
+    /// Extra characters of completion queries, e.g. '.' for "docker.compose"
+    word_chars: std::sync::RwLock<Vec<char>>,
+    /// Largest total size of cached document texts in bytes, 0 for no limit
+    cache_bytes: AtomicUsize,
+    /// Documents completed without being cached, warned about once until opened
+    missing_warned: Mutex<HashSet<String>>,
+    /// Fenced code blocks of markdown documents, with the version and length scanned
+    fence_scans: std::sync::Mutex<HashMap<String, (i32, usize, FenceScan)>>,
+    /// Indentation detected in documents
+    indent_units: std::sync::Mutex<HashMap<String, IndentDetection>>,
+    /// Languages of comment syntax, file extensions and fence info strings
+    language_registry: std::sync::RwLock<Arc<LanguageRegistry>>,
+}
+
+/// Size of the document cache, reported by the bkmr/status request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentCacheStats {
+    /// Total bytes of the cached document texts
+    pub bytes: usize,
+    /// Open documents whose text was evicted
+    pub evicted: usize,
 }
 
 impl DocumentService {
     pub fn new() -> Self {
         Self {
-            document_cache: Arc::new(RwLock::new(HashMap::new())),
+            document_cache: Arc::new(RwLock::new(DocumentStore::default())),
             language_cache: Arc::new(RwLock::new(HashMap::new())),
+            version_cache: Arc::new(RwLock::new(HashMap::new())),
+            position_encoding: std::sync::RwLock::new(PositionEncoding::default()),
+            trigger_char: std::sync::RwLock::new(None),
+            word_chars: std::sync::RwLock::new(DEFAULT_WORD_CHARS.to_vec()),
+            cache_bytes: AtomicUsize::new(DEFAULT_DOC_CACHE_BYTES),
+            missing_warned: Mutex::new(HashSet::new()),
+            fence_scans: std::sync::Mutex::new(HashMap::new()),
+            indent_units: std::sync::Mutex::new(HashMap::new()),
+            language_registry: std::sync::RwLock::new(Arc::new(LanguageRegistry::default())),
+        }
+    }
+
+    /// Change the document cache cap, applied from the next opened or changed document
+    pub fn set_cache_bytes(&self, max_bytes: usize) {
+        self.cache_bytes.store(max_bytes, Ordering::Relaxed);
+    }
+
+    /// Size of the document cache
+    pub async fn cache_stats(&self) -> DocumentCacheStats {
+        let cache = self.document_cache.read().await;
+        DocumentCacheStats {
+            bytes: cache.bytes,
+            evicted: cache.evicted.len(),
         }
     }
 
+    /// Recognize queries prefixed with the given trigger character
+    pub fn with_trigger_char(self, trigger_char: Option<char>) -> Self {
+        self.set_trigger_char(trigger_char);
+        self
+    }
+
+    /// Change the trigger character, e.g. after reading initialization options
+    pub fn set_trigger_char(&self, trigger_char: Option<char>) {
+        *sync::write(&self.trigger_char, "trigger char") = trigger_char;
+    }
+
+    /// Treat these characters as part of completion queries, on top of alphanumerics, '_' and '-'
+    pub fn with_word_chars(self, word_chars: Vec<char>) -> Self {
+        self.set_word_chars(word_chars);
+        self
+    }
+
+    /// Change the extra query characters, e.g. after reading initialization options
+    pub fn set_word_chars(&self, word_chars: Vec<char>) {
+        *sync::write(&self.word_chars, "word chars") = word_chars;
+    }
+
+    /// Change the language registry, e.g. after the configured languages changed
+    ///
+    /// Fence scans resolved their languages with the previous one and are dropped.
+    pub fn set_language_registry(&self, language_registry: Arc<LanguageRegistry>) {
+        *sync::write(&self.language_registry, "language registry") = language_registry;
+        sync::lock(&self.fence_scans, "fence scans").clear();
+    }
+
+    fn language_registry(&self) -> Arc<LanguageRegistry> {
+        sync::read(&self.language_registry, "language registry").clone()
+    }
+
+    /// Set the position encoding negotiated with the client
+    pub fn set_position_encoding(&self, encoding: PositionEncoding) {
+        debug!("Using position encoding: {:?}", encoding);
+        *sync::write(&self.position_encoding, "position encoding") = encoding;
+    }
+
+    /// Get the position encoding negotiated with the client
+    pub fn position_encoding(&self) -> PositionEncoding {
+        *sync::read(&self.position_encoding, "position encoding")
+    }
+
     /// Register a new document
     #[instrument(skip(self, content))]
     pub async fn open_document(
@@ -32,30 +280,516 @@ impl DocumentService {
         language_id: String,
         content: String,
     ) -> Result<()> {
+        self.insert_document(uri, language_id, content, None).await;
+        Ok(())
+    }
+
+    /// Register a new document at the version sent with didOpen
+    #[instrument(skip(self, content))]
+    pub async fn open_versioned_document(
+        &self,
+        uri: String,
+        language_id: String,
+        content: String,
+        version: i32,
+    ) -> Result<()> {
+        self.insert_document(uri, language_id, content, Some(version))
+            .await;
+        Ok(())
+    }
+
+    /// Store text, language and version while holding the document cache lock
+    ///
+    /// Snapshots take the same lock first, so they never see the text of one
+    /// open with the language or version of another.
+    async fn insert_document(
+        &self,
+        uri: String,
+        language_id: String,
+        content: String,
+        version: Option<i32>,
+    ) {
         debug!("Opening document: {} (language: {})", uri, language_id);
 
+        let mut cache = self.document_cache.write().await;
+        cache.insert(
+            uri.clone(),
+            content,
+            self.cache_bytes.load(Ordering::Relaxed),
+        );
+        self.language_cache
+            .write()
+            .await
+            .insert(uri.clone(), language_id);
+        if let Some(version) = version {
+            self.version_cache.write().await.insert(
+                uri.clone(),
+                DocumentVersion {
+                    version,
+                    saved: None,
+                },
+            );
+        }
+        drop(cache);
+
+        self.missing_warned.lock().await.remove(&uri);
+    }
+
+    /// Apply content changes to a cached document
+    ///
+    /// Changes without a range replace the whole document, ranged changes are
+    /// spliced into the cached content in the order they were received.
+    #[instrument(skip(self, changes))]
+    pub async fn update_document(
+        &self,
+        uri: String,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Result<()> {
+        debug!("Updating document: {} ({} changes)", uri, changes.len());
+
+        let encoding = self.position_encoding();
+        let mut cache = self.document_cache.write().await;
+        if cache.is_evicted(&uri) {
+            debug!("Ignoring changes to evicted {} until it is saved", uri);
+            return Ok(());
+        }
+        cache
+            .update(&uri, self.cache_bytes.load(Ordering::Relaxed), |content| {
+                for change in changes {
+                    Self::apply_change(content, change, encoding);
+                }
+            })
+            .ok_or_else(|| anyhow::anyhow!("Document not found in cache"))
+            .context("retrieve document for update")
+    }
+
+    /// Apply the changes of a didChange unless its version is not newer than the cached one
+    ///
+    /// Clients reordering notifications, e.g. on a rapid undo and redo, would
+    /// otherwise have an older change clobber newer text. The check and the
+    /// edit happen under one lock, so concurrent changes apply in version
+    /// order. Returns whether the changes were applied.
+    #[instrument(skip(self, changes))]
+    pub async fn change_document(
+        &self,
+        uri: String,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Result<bool> {
+        debug!(
+            "Changing document: {} to version {} ({} changes)",
+            uri,
+            version,
+            changes.len()
+        );
+
+        let encoding = self.position_encoding();
+        let mut cache = self.document_cache.write().await;
+        let mut versions = self.version_cache.write().await;
+        if let Some(cached) = versions.get(&uri)
+            && version <= cached.version
         {
-            let mut cache = self.document_cache.write().await;
-            cache.insert(uri.clone(), content);
+            warn!(
+                "Ignoring change to {}: version {} is not newer than cached version {}",
+                uri, version, cached.version
+            );
+            return Ok(false);
+        }
+
+        let saved = versions.get(&uri).and_then(|cached| cached.saved);
+        if cache.is_evicted(&uri) {
+            debug!("Ignoring changes to evicted {} until it is saved", uri);
+            versions.insert(uri, DocumentVersion { version, saved });
+            return Ok(false);
         }
+        cache
+            .update(&uri, self.cache_bytes.load(Ordering::Relaxed), |content| {
+                for change in changes {
+                    Self::apply_change(content, change, encoding);
+                }
+            })
+            .ok_or_else(|| anyhow::anyhow!("Document not found in cache"))
+            .context("retrieve document for change")?;
+        versions.insert(uri, DocumentVersion { version, saved });
+        Ok(true)
+    }
+
+    /// Text, version and language of a cached document, None if it is not cached
+    ///
+    /// Read under the document cache lock that opens and changes hold, so the
+    /// three always belong to the same state of the document.
+    pub async fn get_document_snapshot(&self, uri: &str) -> Option<DocumentSnapshot> {
+        let cache = self.document_cache.read().await;
+        let text = cache.get(uri)?.clone();
+        let language_id = self.language_cache.read().await.get(uri).cloned();
+        let version = self
+            .version_cache
+            .read()
+            .await
+            .get(uri)
+            .map(|cached| cached.version);
+        Some(DocumentSnapshot {
+            text,
+            version,
+            language_id,
+        })
+    }
+
+    /// Get the version bookkeeping of a document
+    pub async fn document_version(&self, uri: &str) -> Option<DocumentVersion> {
+        self.version_cache.read().await.get(uri).copied()
+    }
 
+    /// Handle a save, replacing the cached content when the client sent the text
+    #[instrument(skip(self, text))]
+    pub async fn save_document(&self, uri: &str, text: Option<String>) -> Result<()> {
         {
-            let mut lang_cache = self.language_cache.write().await;
-            lang_cache.insert(uri, language_id);
+            let mut cache = self.document_cache.write().await;
+            let max_bytes = self.cache_bytes.load(Ordering::Relaxed);
+            match text {
+                // The saved text brings an evicted document back
+                Some(text) if cache.is_evicted(uri) => {
+                    debug!("Restoring evicted {} from saved text", uri);
+                    cache.insert(uri.to_string(), text, max_bytes);
+                }
+                Some(text) => {
+                    debug!("Resyncing {} from saved text", uri);
+                    cache
+                        .update(uri, max_bytes, |content| *content = text)
+                        .ok_or_else(|| anyhow::anyhow!("Document not found in cache"))
+                        .context("retrieve document for save")?;
+                }
+                None if cache.get(uri).is_none() && !cache.is_evicted(uri) => {
+                    return Err(anyhow::anyhow!("Document not found in cache"))
+                        .context("retrieve document for save");
+                }
+                None => {}
+            }
         }
 
+        if let Some(version) = self.version_cache.write().await.get_mut(uri) {
+            version.saved = Some(version.version);
+        }
         Ok(())
     }
 
-    /// Update document content
-    #[instrument(skip(self, content))]
-    pub async fn update_document(&self, uri: String, content: String) -> Result<()> {
-        debug!("Updating document: {}", uri);
+    /// Get the cached content of a document
+    pub async fn get_document_content(&self, uri: &str) -> Option<String> {
+        let cache = self.document_cache.read().await;
+        cache.get(uri).cloned()
+    }
 
-        let mut cache = self.document_cache.write().await;
-        cache.insert(uri, content);
+    /// Number of lines of a cached document, a trailing newline starts an empty last line
+    pub async fn line_count(&self, uri: &str) -> Option<u32> {
+        let cache = self.document_cache.read().await;
+        cache
+            .get(uri)
+            .map(|content| content.split('\n').count() as u32)
+    }
 
-        Ok(())
+    /// Get the leading whitespace of a line in a cached document
+    pub async fn get_line_indentation(&self, uri: &str, line: u32) -> Option<String> {
+        let cache = self.document_cache.read().await;
+        let line = Self::document_line(cache.get(uri)?, line)?;
+        let indentation_len = line.len() - line.trim_start_matches([' ', '\t']).len();
+        Some(line[..indentation_len].to_string())
+    }
+
+    /// Describe the line of a cached document around a query starting at the position
+    ///
+    /// A line past the end of the text is empty, columns past the line end
+    /// clamp to it.
+    pub async fn get_line_context(&self, uri: &str, query_start: Position) -> Option<LineContext> {
+        let cache = self.document_cache.read().await;
+        let line = Self::document_line(cache.get(uri)?, query_start.line).unwrap_or("");
+        let offset = self
+            .position_encoding()
+            .byte_offset(line, query_start.character)
+            .unwrap_or(line.len());
+        Some(LineContext::new(line, offset))
+    }
+
+    /// Classify the text before a position in a cached document as code, string or comment
+    pub async fn get_insertion_kind(
+        &self,
+        uri: &str,
+        position: Position,
+        language_id: &str,
+    ) -> Option<InsertionKind> {
+        let cache = self.document_cache.read().await;
+        let first_line = position.line.saturating_sub(INSERTION_LOOKBACK_LINES);
+        let mut lines: Vec<&str> = cache
+            .get(uri)?
+            .split('\n')
+            .skip(first_line as usize)
+            .take((position.line - first_line) as usize + 1)
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .collect();
+        let line = lines.last_mut()?;
+        let offset = self
+            .position_encoding()
+            .byte_offset(line, position.character)
+            .unwrap_or(line.len());
+        *line = &line[..offset];
+        Some(Self::insertion_kind(
+            &lines.join("\n"),
+            language_id,
+            &self.language_registry(),
+        ))
+    }
+
+    /// Classify the end of a text by the language's comment and quote syntax
+    ///
+    /// A lightweight scan without a parser: backslashes escape the next
+    /// character in strings, and strings that cannot span lines end with them.
+    pub fn insertion_kind(
+        text: &str,
+        language_id: &str,
+        registry: &LanguageRegistry,
+    ) -> InsertionKind {
+        enum Scan {
+            Code,
+            String(char),
+            LineComment,
+            BlockComment,
+        }
+
+        let info = registry.get_language_info(language_id);
+        let syntax = registry.string_syntax(language_id);
+        let mut state = Scan::Code;
+        let mut chars = text.char_indices();
+        while let Some((i, c)) = chars.next() {
+            let rest = &text[i..];
+            let mut skip = |token: &str| {
+                for _ in 1..token.chars().count() {
+                    chars.next();
+                }
+            };
+            match state {
+                Scan::Code => {
+                    if let Some((start, _)) = &info.block_comment
+                        && rest.starts_with(start.as_str())
+                    {
+                        skip(start);
+                        state = Scan::BlockComment;
+                    } else if let Some(line_comment) = &info.line_comment
+                        && rest.starts_with(line_comment.as_str())
+                    {
+                        state = Scan::LineComment;
+                    } else if syntax.quotes.contains(&c) {
+                        state = Scan::String(c);
+                    }
+                }
+                Scan::String(quote) => {
+                    if c == '\\' {
+                        chars.next();
+                    } else if c == quote || (c == '\n' && !syntax.multiline_quotes.contains(&quote))
+                    {
+                        state = Scan::Code;
+                    }
+                }
+                Scan::LineComment => {
+                    if c == '\n' {
+                        state = Scan::Code;
+                    }
+                }
+                Scan::BlockComment => {
+                    if let Some((_, end)) = &info.block_comment
+                        && rest.starts_with(end.as_str())
+                    {
+                        skip(end);
+                        state = Scan::Code;
+                    }
+                }
+            }
+        }
+
+        match state {
+            Scan::Code => InsertionKind::Code,
+            Scan::String(quote) => InsertionKind::String {
+                quote,
+                multiline: syntax.multiline_quotes.contains(&quote),
+            },
+            Scan::LineComment => InsertionKind::Comment {
+                leader: format!("{} ", info.line_comment.unwrap_or_default()),
+            },
+            Scan::BlockComment => {
+                // Continue "/*" comments with " * ", or "* " on a line already starting with it
+                let line = text.rsplit('\n').next().unwrap_or(text).trim_start();
+                let leader = match &info.block_comment {
+                    Some((start, _)) if start == "/*" && line.starts_with('*') => "* ",
+                    Some((start, _)) if start == "/*" => " * ",
+                    _ => "",
+                };
+                InsertionKind::Comment {
+                    leader: leader.to_string(),
+                }
+            }
+        }
+    }
+
+    /// Get the word under the cursor and its range in a cached document
+    pub async fn get_word_at_position(
+        &self,
+        uri: &str,
+        position: Position,
+    ) -> Option<(String, Range)> {
+        let cache = self.document_cache.read().await;
+        let line = Self::document_line(cache.get(uri)?, position.line)?;
+
+        let encoding = self.position_encoding();
+        let cursor = encoding.byte_offset(line, position.character)?;
+        let (start, end) = Self::word_bounds(line, cursor)?;
+
+        let range = Range {
+            start: Position::new(position.line, encoding.character(line, start)),
+            end: Position::new(position.line, encoding.character(line, end)),
+        };
+        Some((line[start..end].to_string(), range))
+    }
+
+    /// Get the tag typed on the `tags:` line of a markdown document's frontmatter and its range
+    pub async fn get_frontmatter_tag_query(
+        &self,
+        uri: &str,
+        position: Position,
+    ) -> Option<(String, Range)> {
+        if self.get_language_id(uri).await.as_deref() != Some("markdown") {
+            return None;
+        }
+        let cache = self.document_cache.read().await;
+        let text = cache.get(uri)?;
+        let line = Self::document_line(text, position.line)?;
+
+        let encoding = self.position_encoding();
+        let cursor = encoding.byte_offset(line, position.character)?;
+        let query = FrontmatterTagQuery::at(text, position.line, cursor)?;
+
+        let range = Range {
+            start: Position::new(position.line, encoding.character(line, query.start)),
+            end: Position::new(position.line, encoding.character(line, cursor)),
+        };
+        Some((query.prefix, range))
+    }
+
+    /// Byte range of the word touching the cursor, which may sit on either end of it
+    pub fn word_bounds(line: &str, cursor: usize) -> Option<(usize, usize)> {
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+        let start = line[..cursor]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_word_char(*c))
+            .last()
+            .map_or(cursor, |(i, _)| i);
+        let end = line[cursor..]
+            .char_indices()
+            .find(|(_, c)| !is_word_char(*c))
+            .map_or(line.len(), |(i, _)| cursor + i);
+
+        (start < end).then_some((start, end))
+    }
+
+    /// A line of a document without its line ending
+    ///
+    /// Lines are split at '\n' with a trailing '\r' removed, so CRLF documents
+    /// measure like LF ones and the empty line after a final newline exists.
+    fn document_line(content: &str, line: u32) -> Option<&str> {
+        content
+            .split('\n')
+            .nth(line as usize)
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+    }
+
+    /// Clamp a position into a cached document
+    ///
+    /// Columns past the line end move to the line end, lines past the last
+    /// line move to the end of the document.
+    pub async fn clamp_position(&self, uri: &str, position: Position) -> Result<Position> {
+        let cache = self.document_cache.read().await;
+        let content = cache
+            .get(uri)
+            .ok_or_else(|| anyhow::anyhow!("Document not found in cache: {}", uri))
+            .context("retrieve document for position")?;
+
+        let encoding = self.position_encoding();
+        let lines: Vec<&str> = content
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .collect();
+        let last_line = lines.len() - 1;
+        if position.line as usize > last_line {
+            let line = lines[last_line];
+            return Ok(Position::new(
+                last_line as u32,
+                encoding.character(line, line.len()),
+            ));
+        }
+
+        let line = lines[position.line as usize];
+        let byte_offset = encoding
+            .byte_offset(line, position.character)
+            .unwrap_or(line.len());
+        Ok(Position::new(
+            position.line,
+            encoding.character(line, byte_offset),
+        ))
+    }
+
+    /// Get the text of a cached document within a range
+    pub async fn get_text_in_range(&self, uri: &str, range: Range) -> Result<String> {
+        let cache = self.document_cache.read().await;
+        let content = cache
+            .get(uri)
+            .ok_or_else(|| anyhow::anyhow!("Document not found in cache: {}", uri))
+            .context("retrieve document for range")?;
+
+        let encoding = self.position_encoding();
+        let start = Self::position_to_offset(content, range.start, encoding);
+        let end = Self::position_to_offset(content, range.end, encoding).max(start);
+        Ok(content[start..end].to_string())
+    }
+
+    /// Apply a single change event to document content
+    fn apply_change(
+        content: &mut String,
+        change: TextDocumentContentChangeEvent,
+        encoding: PositionEncoding,
+    ) {
+        match change.range {
+            Some(range) => {
+                let start = Self::position_to_offset(content, range.start, encoding);
+                let end = Self::position_to_offset(content, range.end, encoding).max(start);
+                content.replace_range(start..end, &change.text);
+            }
+            None => *content = change.text,
+        }
+    }
+
+    /// Convert an LSP position in the given encoding into a byte offset
+    ///
+    /// Positions past the end of a line clamp to the line end, positions past
+    /// the last line clamp to the end of the document.
+    fn position_to_offset(content: &str, position: Position, encoding: PositionEncoding) -> usize {
+        let mut line_start = 0;
+        for _ in 0..position.line {
+            match content[line_start..].find('\n') {
+                Some(newline) => line_start += newline + 1,
+                None => return content.len(),
+            }
+        }
+
+        let line_end = content[line_start..]
+            .find('\n')
+            .map(|newline| line_start + newline)
+            .unwrap_or(content.len());
+        let line = content[line_start..line_end]
+            .strip_suffix('\r')
+            .unwrap_or(&content[line_start..line_end]);
+
+        line_start
+            + encoding
+                .byte_offset(line, position.character)
+                .unwrap_or(line.len())
     }
 
     /// Close a document and remove from cache
@@ -73,6 +807,10 @@ impl DocumentService {
             lang_cache.remove(&uri);
         }
 
+        self.version_cache.write().await.remove(&uri);
+        sync::lock(&self.fence_scans, "fence scans").remove(&uri);
+        sync::lock(&self.indent_units, "indent units").remove(&uri);
+
         Ok(())
     }
 
@@ -82,6 +820,18 @@ impl DocumentService {
         cache.get(uri).cloned()
     }
 
+    /// Language ID of every cached document, keyed by URI
+    pub async fn document_languages(&self) -> HashMap<String, Option<String>> {
+        let documents = self.document_cache.read().await;
+        let languages = self.language_cache.read().await;
+        documents
+            .documents
+            .keys()
+            .chain(&documents.evicted)
+            .map(|uri| (uri.clone(), languages.get(uri).cloned()))
+            .collect()
+    }
+
     /// Extract completion context from document position
     #[instrument(skip(self))]
     pub async fn extract_completion_context(
@@ -92,14 +842,179 @@ impl DocumentService {
         let language_id = self.get_language_id(uri.as_ref()).await;
         let mut context = CompletionContext::new(uri.clone(), position, language_id);
 
-        if let Some(query) = self.extract_snippet_query(uri, position).await? {
+        {
+            let cache = self.document_cache.read().await;
+            if cache.is_evicted(uri.as_str()) {
+                debug!(
+                    "{} was evicted from the document cache, using empty query",
+                    uri
+                );
+                return Ok(context);
+            }
+            cache.touch(uri.as_str());
+            match cache.get(uri.as_str()) {
+                Some(text) => {
+                    context = context.with_document_imports(DocumentImports::new(text));
+                    if let Some(cached) = self.version_cache.read().await.get(uri.as_str()) {
+                        context = context.with_version(cached.version);
+                    }
+                    if let Some(document_language) = context.language_id.clone()
+                        && FENCED_LANGUAGES
+                            .iter()
+                            .any(|fenced| fenced.eq_ignore_ascii_case(&document_language))
+                        && let Some(language_id) =
+                            self.fence_language(uri.as_str(), text, context.version, position.line)
+                    {
+                        debug!(
+                            "Completing in a {} code block of {} document {}",
+                            language_id, document_language, uri
+                        );
+                        context.language_id = Some(language_id);
+                    }
+                    if let Some(indent_unit) =
+                        self.detected_indent_unit(uri.as_str(), text, context.version)
+                    {
+                        context = context.with_indent_unit(indent_unit);
+                    }
+                }
+                None => {
+                    // Without the text only the language can be guessed, from the extension
+                    if context.language_id.is_none() {
+                        context.language_id = self.uri_language(uri);
+                    }
+                    self.warn_missing(uri.as_str(), context.language_id.as_deref(), cache.len())
+                        .await;
+                    return Ok(context);
+                }
+            }
+        }
+
+        let query = self.extract_snippet_query(uri, position).await?;
+        let query_start = query.as_ref().map_or(position, |query| query.range.start);
+        if let Some(line) = self.get_line_context(uri.as_ref(), query_start).await {
+            context = context.with_line(line);
+        }
+        if let Some(language_id) = context.language_id.clone()
+            && let Some(kind) = self
+                .get_insertion_kind(uri.as_ref(), query_start, &language_id)
+                .await
+        {
+            context = context.with_insertion_kind(kind);
+        }
+        if let Some(query) = query {
             context = context.with_query(query);
         }
+        if let Some(indentation) = self
+            .get_line_indentation(uri.as_ref(), position.line)
+            .await
+            .filter(|indentation| !indentation.is_empty())
+        {
+            context = context.with_indentation(indentation);
+        }
 
         Ok(context)
     }
 
+    /// Language of the fenced code block a line of the document is in
+    ///
+    /// Scans are kept per document version, documents without version are
+    /// scanned again each time.
+    fn fence_language(
+        &self,
+        uri: &str,
+        text: &str,
+        version: Option<i32>,
+        line: u32,
+    ) -> Option<String> {
+        let registry = self.language_registry();
+        let Some(version) = version else {
+            return FenceScan::default()
+                .language_at(text, line, &registry)
+                .map(str::to_string);
+        };
+        let mut scans = sync::lock(&self.fence_scans, "fence scans");
+        let (scanned_version, scanned_length, scan) = scans
+            .entry(uri.to_string())
+            .or_insert_with(|| (version, text.len(), FenceScan::default()));
+        if *scanned_version != version || *scanned_length != text.len() {
+            *scanned_version = version;
+            *scanned_length = text.len();
+            *scan = FenceScan::default();
+        }
+        scan.language_at(text, line, &registry).map(str::to_string)
+    }
+
+    /// Unit a document indents by, an explicit one taking precedence over detection
+    ///
+    /// The explicit unit comes from the editor, e.g. the formatting options of
+    /// a request. Without it the unit is detected from the cached text. None
+    /// when neither is known, snippets then indent like the language does.
+    pub async fn indent_unit(&self, uri: &str, explicit: Option<IndentUnit>) -> Option<IndentUnit> {
+        if explicit.is_some() {
+            return explicit;
+        }
+        let cache = self.document_cache.read().await;
+        let text = cache.get(uri)?;
+        let version = self
+            .version_cache
+            .read()
+            .await
+            .get(uri)
+            .map(|cached| cached.version);
+        self.detected_indent_unit(uri, text, version)
+    }
+
+    /// Indentation detected in a document's text, see [`IndentUnit::detect`]
+    ///
+    /// Detections are kept per document version, documents without version
+    /// are inspected again each time.
+    fn detected_indent_unit(
+        &self,
+        uri: &str,
+        text: &str,
+        version: Option<i32>,
+    ) -> Option<IndentUnit> {
+        let Some(version) = version else {
+            return IndentUnit::detect(text);
+        };
+        let mut detections = sync::lock(&self.indent_units, "indent units");
+        if let Some((detected_version, detected_length, indent_unit)) = detections.get(uri)
+            && *detected_version == version
+            && *detected_length == text.len()
+        {
+            return *indent_unit;
+        }
+        let indent_unit = IndentUnit::detect(text);
+        debug!("Detected indentation {:?} in {}", indent_unit, uri);
+        detections.insert(uri.to_string(), (version, text.len(), indent_unit));
+        indent_unit
+    }
+
+    /// Language of a document by its file extension, None for unknown extensions
+    fn uri_language(&self, uri: &Url) -> Option<String> {
+        uri_file_name(uri).and_then(|name| self.language_registry().language_for_file(&name))
+    }
+
+    /// Warn the first time a document is completed that was never opened
+    ///
+    /// Without didOpen every completion in it has an empty query and inserts
+    /// at the cursor, in the language guessed from the URI.
+    async fn warn_missing(&self, uri: &str, language_id: Option<&str>, cached: usize) {
+        if self.missing_warned.lock().await.insert(uri.to_string()) {
+            warn!(
+                "Completion for {} which is not among the {} cached documents, \
+                 was it opened with didOpen? Completing {} snippets for an empty query",
+                uri,
+                cached,
+                language_id.unwrap_or("all")
+            );
+        }
+    }
+
     /// Extract word backwards from cursor position and return both query and range
+    ///
+    /// A cursor past the line end counts as being at the line end. Lines past
+    /// the document, and lines without a word before the cursor, have no query.
     #[instrument(skip(self))]
     async fn extract_snippet_query(
         &self,
@@ -108,21 +1023,17 @@ impl DocumentService {
     ) -> Result<Option<CompletionQuery>> {
         let cache = self.document_cache.read().await;
         let content = cache
-            .get(&uri.to_string())
+            .get(uri.as_str())
             .ok_or_else(|| anyhow::anyhow!("Document not found in cache"))
             .context("retrieve document from cache")?;
 
-        let lines: Vec<&str> = content.lines().collect();
-        if position.line as usize >= lines.len() {
+        let Some(line) = Self::document_line(content, position.line) else {
             return Ok(None);
-        }
-
-        let line = lines[position.line as usize];
-        let char_pos = position.character as usize;
-
-        if char_pos > line.len() {
-            return Ok(None);
-        }
+        };
+        let encoding = self.position_encoding();
+        let char_pos = encoding
+            .byte_offset(line, position.character)
+            .unwrap_or(line.len());
 
         let before_cursor = &line[..char_pos];
         debug!(
@@ -131,40 +1042,88 @@ impl DocumentService {
         );
 
         // Extract word backwards from cursor - find where the word starts
+        let word_chars = sync::read(&self.word_chars, "word chars").clone();
+        let is_extra = |c: char| word_chars.contains(&c);
         let word_start = before_cursor
             .char_indices()
             .rev()
-            .take_while(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == '-')
+            .take_while(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == '-' || is_extra(*c))
             .last()
             .map(|(i, _)| i)
             .unwrap_or(char_pos);
 
+        // The word may continue after the cursor, clients can replace that too
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-' || is_extra(c);
+        let word_end = line[char_pos..]
+            .char_indices()
+            .find(|(_, c)| !is_word_char(*c))
+            .map_or(line.len(), |(i, _)| char_pos + i);
+        let word_end = Position {
+            line: position.line,
+            character: encoding.character(line, word_end),
+        };
+
         debug!("Word boundaries: start={}, end={}", word_start, char_pos);
 
-        if word_start < char_pos {
-            let word = &before_cursor[word_start..];
-            if !word.is_empty() && word.chars().any(|c| c.is_alphanumeric()) {
-                debug!("Extracted word: '{}' from position {}", word, char_pos);
-
-                // Create range for the word to be replaced
-                let range = Range {
-                    start: Position {
-                        line: position.line,
-                        character: word_start as u32,
-                    },
-                    end: Position {
-                        line: position.line,
-                        character: char_pos as u32,
-                    },
-                };
+        // Separators around the word, as in "docker.", are replaced but not searched for
+        let word = before_cursor[word_start..].trim_matches(is_extra);
 
-                return Ok(Some(CompletionQuery::new(word.to_string(), range)));
-            }
+        // A trigger prefix is replaced together with the word but not searched for
+        if let Some(trigger_start) = self.trigger_start(before_cursor, word_start) {
+            debug!("Extracted triggered word: '{}'", word);
+
+            let range = Range {
+                start: Position {
+                    line: position.line,
+                    character: encoding.character(line, trigger_start),
+                },
+                end: Position {
+                    line: position.line,
+                    character: encoding.character(line, char_pos),
+                },
+            };
+            return Ok(Some(
+                CompletionQuery::new(word.to_string(), range).with_word_end(word_end),
+            ));
+        }
+
+        if word.chars().any(|c| c.is_alphanumeric()) {
+            debug!("Extracted word: '{}' from position {}", word, char_pos);
+
+            // Create range for the word to be replaced, in the client's encoding
+            let range = Range {
+                start: Position {
+                    line: position.line,
+                    character: encoding.character(line, word_start),
+                },
+                end: Position {
+                    line: position.line,
+                    character: encoding.character(line, char_pos),
+                },
+            };
+
+            return Ok(Some(
+                CompletionQuery::new(word.to_string(), range).with_word_end(word_end),
+            ));
         }
 
         debug!("No valid word found at position {}", char_pos);
         Ok(None)
     }
+
+    /// Byte offset of the trigger character directly before the word, if any
+    ///
+    /// The trigger only counts at line start or after whitespace, so that
+    /// e.g. "std::" does not start a query when ':' is the trigger.
+    fn trigger_start(&self, before_cursor: &str, word_start: usize) -> Option<usize> {
+        let trigger = (*sync::read(&self.trigger_char, "trigger char"))?;
+        let prefix = before_cursor[..word_start].strip_suffix(trigger)?;
+        if prefix.chars().next_back().is_none_or(char::is_whitespace) {
+            Some(prefix.len())
+        } else {
+            None
+        }
+    }
 }
 
 impl Default for DocumentService {
@@ -225,24 +1184,732 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn given_document_without_word_when_extracting_query_then_returns_none() {
+    async fn given_tab_indented_line_when_extracting_context_then_captures_indentation() {
         // Arrange
         let service = DocumentService::new();
-        let uri_str = "file:///test.rs".to_string();
-        let uri = Url::parse(&uri_str).expect("parse URI");
-        let content = "   ".to_string(); // Only whitespace
-        let position = Position {
-            line: 0,
-            character: 2,
-        };
-
+        let uri = Url::parse("file:///test.go").expect("parse URI");
         service
-            .open_document(uri_str, "rust".to_string(), content)
+            .open_document(
+                uri.to_string(),
+                "go".to_string(),
+                "func main() {\n\t\terr\n}".to_string(),
+            )
             .await
             .expect("open document");
 
         // Act
-        let result = service.extract_completion_context(&uri, position).await;
+        let context = service
+            .extract_completion_context(&uri, Position::new(1, 5))
+            .await
+            .expect("valid completion context");
+
+        // Assert
+        assert_eq!(context.indentation.as_deref(), Some("\t\t"));
+        assert_eq!(context.get_query_text(), Some("err"));
+    }
+
+    #[tokio::test]
+    async fn given_query_at_indent_or_after_code_when_extracting_context_then_describes_line() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = Url::parse("file:///test.rs").expect("parse URI");
+        let content = "struct A;\n    derive\nlet x = 1; todo\n";
+        service
+            .open_document(uri.to_string(), "rust".to_string(), content.to_string())
+            .await
+            .expect("open document");
+
+        // Act
+        let at_indent = service
+            .extract_completion_context(&uri, Position::new(1, 10))
+            .await
+            .expect("valid completion context");
+        let after_code = service
+            .extract_completion_context(&uri, Position::new(2, 15))
+            .await
+            .expect("valid completion context");
+        let empty_line = service
+            .extract_completion_context(&uri, Position::new(3, 0))
+            .await
+            .expect("valid completion context");
+
+        // Assert
+        let at_indent = at_indent.line.expect("line context");
+        assert_eq!(at_indent.text, "    derive");
+        assert!(at_indent.at_line_start);
+        assert_eq!(at_indent.preceding_char, Some(' '));
+        let after_code = after_code.line.expect("line context");
+        assert!(!after_code.at_line_start);
+        assert_eq!(after_code.preceding_char, Some(' '));
+        let empty_line = empty_line.line.expect("line context");
+        assert!(empty_line.at_line_start);
+        assert_eq!(empty_line.preceding_char, None);
+    }
+
+    #[test]
+    fn given_documents_used_in_turn_when_exceeding_cap_then_evicts_least_recently_used() {
+        // Arrange
+        let mut store = DocumentStore::default();
+        store.insert("a".to_string(), "a".repeat(10), 25);
+        store.insert("b".to_string(), "b".repeat(10), 25);
+        store.touch("a");
+
+        // Act
+        store.insert("c".to_string(), "c".repeat(10), 25);
+
+        // Assert
+        assert!(store.get("a").is_some());
+        assert!(store.get("b").is_none());
+        assert!(store.is_evicted("b"));
+        assert!(store.get("c").is_some());
+        assert_eq!(store.bytes, 20);
+    }
+
+    #[test]
+    fn given_growing_document_when_updating_then_evicts_others_but_keeps_it_even_over_cap() {
+        // Arrange
+        let mut store = DocumentStore::default();
+        store.insert("old".to_string(), "x".repeat(10), 30);
+        store.insert("edited".to_string(), "y".repeat(10), 30);
+
+        // Act
+        let grown = store.update("edited", 30, |text| text.push_str(&"y".repeat(15)));
+        let overflowing = store.update("edited", 30, |text| text.push_str(&"y".repeat(20)));
+
+        // Assert
+        assert_eq!(grown, Some(()));
+        assert_eq!(overflowing, Some(()));
+        assert!(store.is_evicted("old"));
+        assert_eq!(store.get("edited").map(String::len), Some(45));
+        assert_eq!(store.bytes, 45);
+    }
+
+    #[tokio::test]
+    async fn given_evicted_document_when_completing_then_uses_empty_query_until_saved() {
+        // Arrange
+        let service = DocumentService::new();
+        service.set_cache_bytes(16);
+        let uri = Url::parse("file:///old.rs").expect("parse URI");
+        for (open_uri, text) in [
+            (uri.as_str(), "fn a() { hello"),
+            ("file:///new.rs", "fn b() {}"),
+        ] {
+            service
+                .open_document(open_uri.to_string(), "rust".to_string(), text.to_string())
+                .await
+                .expect("open document");
+        }
+
+        // Act
+        let evicted = service
+            .extract_completion_context(&uri, Position::new(0, 14))
+            .await
+            .expect("context of evicted document");
+        let changed = service.update_document(uri.to_string(), vec![]).await;
+        let stats = service.cache_stats().await;
+        service
+            .save_document(uri.as_str(), Some("hello".to_string()))
+            .await
+            .expect("save document");
+        let restored = service
+            .extract_completion_context(&uri, Position::new(0, 5))
+            .await
+            .expect("context of restored document");
+
+        // Assert
+        assert!(!evicted.has_query());
+        assert_eq!(evicted.language_id.as_deref(), Some("rust"));
+        assert!(changed.is_ok());
+        assert_eq!(
+            stats,
+            DocumentCacheStats {
+                bytes: 9,
+                evicted: 1,
+            }
+        );
+        assert_eq!(restored.get_query_text(), Some("hello"));
+        assert_eq!(service.cache_stats().await.evicted, 0);
+    }
+
+    #[tokio::test]
+    async fn given_query_inside_rust_string_when_extracting_context_then_insertion_kind_is_string()
+    {
+        // Act
+        let in_string = context_at("let s = \"say \\\"hi\\\" hel", Position::new(0, 23)).await;
+        let after_string = context_at("let s = \"done\"; hel", Position::new(0, 19)).await;
+
+        // Assert
+        assert_eq!(
+            in_string.insertion_kind,
+            InsertionKind::String {
+                quote: '"',
+                multiline: true,
+            }
+        );
+        assert_eq!(after_string.insertion_kind, InsertionKind::Code);
+        assert_eq!(after_string.get_query_text(), Some("hel"));
+    }
+
+    #[test]
+    fn given_python_hash_comment_when_classifying_then_leader_is_the_hash() {
+        let registry = LanguageRegistry::default();
+        // Act
+        let comment = DocumentService::insertion_kind("x = 1  # see ", "python", &registry);
+        let hash_in_string =
+            DocumentService::insertion_kind("x = '# not a comment' + ", "python", &registry);
+        let unclosed_string =
+            DocumentService::insertion_kind("x = 'abc\nprint(", "python", &registry);
+
+        // Assert
+        assert_eq!(
+            comment,
+            InsertionKind::Comment {
+                leader: "# ".to_string()
+            }
+        );
+        assert_eq!(hash_in_string, InsertionKind::Code);
+        assert_eq!(unclosed_string, InsertionKind::Code);
+    }
+
+    #[test]
+    fn given_block_comment_over_several_lines_when_classifying_then_continues_it_with_star() {
+        let registry = LanguageRegistry::default();
+        // Act
+        let opening_line =
+            DocumentService::insertion_kind("fn a() {}\n    /* Note: ", "rust", &registry);
+        let inner_line = DocumentService::insertion_kind("/**\n * Usage:\n * ", "rust", &registry);
+        let closed = DocumentService::insertion_kind("/* a\n b */ let x = ", "rust", &registry);
+
+        // Assert
+        assert_eq!(
+            opening_line,
+            InsertionKind::Comment {
+                leader: " * ".to_string()
+            }
+        );
+        assert_eq!(
+            inner_line,
+            InsertionKind::Comment {
+                leader: "* ".to_string()
+            }
+        );
+        assert_eq!(closed, InsertionKind::Code);
+    }
+
+    #[test]
+    fn given_cursor_at_start_middle_or_end_of_word_when_finding_word_bounds_then_returns_whole_word()
+     {
+        // Arrange
+        let line = "call retry-loop now";
+        let word = (5, 15);
+
+        // Act & Assert
+        assert_eq!(DocumentService::word_bounds(line, 5), Some(word));
+        assert_eq!(DocumentService::word_bounds(line, 10), Some(word));
+        assert_eq!(DocumentService::word_bounds(line, 15), Some(word));
+    }
+
+    #[test]
+    fn given_cursor_between_spaces_when_finding_word_bounds_then_returns_none() {
+        // Arrange
+        let line = "a  b";
+
+        // Act
+        let bounds = DocumentService::word_bounds(line, 2);
+
+        // Assert
+        assert_eq!(bounds, None);
+    }
+
+    #[tokio::test]
+    async fn given_multibyte_line_when_getting_word_at_position_then_returns_utf16_range() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = "file:///test.md".to_string();
+        service
+            .open_document(
+                uri.clone(),
+                "markdown".to_string(),
+                "日本 héllo x".to_string(),
+            )
+            .await
+            .expect("open document");
+
+        // Act
+        let found = service
+            .get_word_at_position(&uri, Position::new(0, 4))
+            .await;
+
+        // Assert
+        assert_eq!(
+            found,
+            Some((
+                "héllo".to_string(),
+                Range::new(Position::new(0, 3), Position::new(0, 8))
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn given_frontmatter_tags_line_when_getting_tag_query_then_only_markdown_matches() {
+        // Arrange
+        let service = DocumentService::new();
+        let text = "---\ntags: größe, sn\n---\n";
+        for (uri, language) in [("file:///a.md", "markdown"), ("file:///a.txt", "plaintext")] {
+            service
+                .open_document(uri.to_string(), language.to_string(), text.to_string())
+                .await
+                .expect("open document");
+        }
+
+        // Act
+        let markdown = service
+            .get_frontmatter_tag_query("file:///a.md", Position::new(1, 15))
+            .await;
+        let plaintext = service
+            .get_frontmatter_tag_query("file:///a.txt", Position::new(1, 15))
+            .await;
+
+        // Assert
+        assert_eq!(
+            markdown,
+            Some((
+                "sn".to_string(),
+                Range::new(Position::new(1, 13), Position::new(1, 15))
+            ))
+        );
+        assert_eq!(plaintext, None);
+    }
+
+    #[tokio::test]
+    async fn given_panic_while_holding_setting_locks_when_completing_then_still_works() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = Url::parse("file:///test.rs").expect("parse URI");
+        service
+            .open_document(
+                uri.to_string(),
+                "rust".to_string(),
+                "docker.comp".to_string(),
+            )
+            .await
+            .expect("open document");
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _chars = service.word_chars.write().expect("unpoisoned lock");
+            let _encoding = service.position_encoding.write().expect("unpoisoned lock");
+            panic!("panic while holding the locks");
+        }));
+        assert!(result.is_err());
+        assert!(service.word_chars.is_poisoned());
+
+        // Act
+        service.set_position_encoding(PositionEncoding::Utf8);
+        let context = service
+            .extract_completion_context(&uri, Position::new(0, 11))
+            .await
+            .expect("completion context");
+
+        // Assert
+        assert_eq!(context.get_query_text(), Some("docker.comp"));
+        assert_eq!(service.position_encoding(), PositionEncoding::Utf8);
+        assert!(!service.word_chars.is_poisoned());
+    }
+
+    #[tokio::test]
+    async fn given_unopened_document_when_completing_then_warns_once_until_opened() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = Url::parse("file:///unopened.rs").expect("parse URI");
+
+        // Act
+        let missing = service
+            .extract_completion_context(&uri, Position::new(0, 0))
+            .await;
+        let warned = service.missing_warned.lock().await.contains(uri.as_str());
+        service
+            .open_document(uri.to_string(), "rust".to_string(), String::new())
+            .await
+            .expect("open document");
+
+        // Assert
+        assert!(
+            missing
+                .expect("context of unopened document")
+                .query
+                .is_none()
+        );
+        assert!(warned);
+        assert!(service.missing_warned.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn given_unopened_documents_when_completing_then_language_comes_from_extension() {
+        // Arrange
+        let service = DocumentService::new();
+        let context = |uri: &str| {
+            let uri = Url::parse(uri).expect("parse URI");
+            let service = &service;
+            async move {
+                service
+                    .extract_completion_context(&uri, Position::new(3, 7))
+                    .await
+                    .expect("context of unopened document")
+            }
+        };
+
+        // Act
+        let python = context("file:///work/never/opened.py").await;
+        let unknown = context("file:///work/notes.xyz").await;
+        let no_path = context("bkmr:").await;
+
+        // Assert
+        assert_eq!(python.language_id.as_deref(), Some("python"));
+        assert_eq!(python.get_replacement_range(), None);
+        assert_eq!(unknown.language_id, None);
+        assert_eq!(no_path.language_id, None);
+        assert_eq!(no_path.query, None);
+        assert_eq!(service.missing_warned.lock().await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn given_positions_outside_document_when_clamping_then_moves_inside() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = "file:///test.rs".to_string();
+        service
+            .open_document(uri.clone(), "rust".to_string(), "héllo\r\n🚀!".to_string())
+            .await
+            .expect("open document");
+
+        // Act
+        let inside = service.clamp_position(&uri, Position::new(0, 2)).await;
+        let past_line_end = service.clamp_position(&uri, Position::new(0, 40)).await;
+        let inside_surrogate = service.clamp_position(&uri, Position::new(1, 1)).await;
+        let past_last_line = service.clamp_position(&uri, Position::new(9, 0)).await;
+
+        // Assert
+        assert_eq!(inside.expect("inside"), Position::new(0, 2));
+        assert_eq!(past_line_end.expect("past line end"), Position::new(0, 5));
+        assert_eq!(
+            inside_surrogate.expect("inside surrogate"),
+            Position::new(1, 0)
+        );
+        assert_eq!(past_last_line.expect("past last line"), Position::new(1, 3));
+    }
+
+    fn full_change(text: &str) -> Vec<TextDocumentContentChangeEvent> {
+        vec![TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: text.to_string(),
+        }]
+    }
+
+    #[tokio::test]
+    async fn given_out_of_order_change_when_changing_then_keeps_newer_text() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = "file:///test.rs".to_string();
+        service
+            .open_versioned_document(uri.clone(), "rust".to_string(), "v1".to_string(), 1)
+            .await
+            .expect("open document");
+        service
+            .change_document(uri.clone(), 3, full_change("v3"))
+            .await
+            .expect("change to version 3");
+
+        // Act
+        let stale = service
+            .change_document(uri.clone(), 2, full_change("v2"))
+            .await
+            .expect("change to version 2");
+
+        // Assert
+        assert!(!stale);
+        assert_eq!(
+            service.get_document_content(&uri).await.as_deref(),
+            Some("v3")
+        );
+        assert_eq!(
+            service
+                .document_version(&uri)
+                .await
+                .map(|cached| cached.version),
+            Some(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn given_change_with_cached_version_when_changing_then_rejects_it() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = "file:///test.rs".to_string();
+        service
+            .open_versioned_document(uri.clone(), "rust".to_string(), "open".to_string(), 4)
+            .await
+            .expect("open document");
+
+        // Act
+        let equal = service
+            .change_document(uri.clone(), 4, full_change("replayed"))
+            .await
+            .expect("change to version 4");
+        let newer = service
+            .change_document(uri.clone(), 5, full_change("newer"))
+            .await
+            .expect("change to version 5");
+
+        // Assert
+        assert!(!equal);
+        assert!(newer);
+        let snapshot = service.get_document_snapshot(&uri).await;
+        assert_eq!(
+            snapshot,
+            Some(DocumentSnapshot {
+                text: "newer".to_string(),
+                version: Some(5),
+                language_id: Some("rust".to_string()),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn given_versioned_document_when_extracting_context_then_includes_version() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = Url::parse("file:///test.rs").expect("parse URI");
+        service
+            .open_versioned_document(uri.to_string(), "rust".to_string(), ":fn".to_string(), 7)
+            .await
+            .expect("open document");
+
+        // Act
+        let context = service
+            .extract_completion_context(&uri, Position::new(0, 3))
+            .await
+            .expect("extract context");
+
+        // Assert
+        assert_eq!(context.version, Some(7));
+    }
+
+    #[tokio::test]
+    async fn given_indented_document_when_extracting_context_then_detects_indent_per_version() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = Url::parse("file:///app.js").expect("parse URI");
+        let spaces = "function f() {\n    if (x) {\n        g();\n    }\n}\n";
+        let tabs = "function f() {\n\tif (x) {\n\t\tg();\n\t}\n}\n";
+        service
+            .open_versioned_document(
+                uri.to_string(),
+                "javascript".to_string(),
+                spaces.to_string(),
+                1,
+            )
+            .await
+            .expect("open document");
+
+        // Act
+        let opened = service
+            .extract_completion_context(&uri, Position::new(4, 1))
+            .await
+            .expect("extract context");
+        service
+            .change_document(uri.to_string(), 2, full_change(tabs))
+            .await
+            .expect("change document");
+        let changed = service
+            .extract_completion_context(&uri, Position::new(4, 1))
+            .await
+            .expect("extract context");
+        let explicit = service
+            .indent_unit(uri.as_str(), Some(IndentUnit::Spaces(2)))
+            .await;
+
+        // Assert
+        assert_eq!(opened.indent_unit, Some(IndentUnit::Spaces(4)));
+        assert_eq!(changed.indent_unit, Some(IndentUnit::Tabs));
+        assert_eq!(explicit, Some(IndentUnit::Spaces(2)));
+        assert_eq!(
+            service
+                .indent_units
+                .lock()
+                .expect("indent units lock")
+                .get(uri.as_str()),
+            Some(&(2, tabs.len(), Some(IndentUnit::Tabs)))
+        );
+    }
+
+    #[tokio::test]
+    async fn given_cursor_in_markdown_fence_when_extracting_context_then_uses_fence_language() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = Url::parse("file:///notes.md").expect("parse URI");
+        service
+            .open_versioned_document(
+                uri.to_string(),
+                "markdown".to_string(),
+                "# Setup\n```py\nimp\n```\nimp\n~~~\nimp\n".to_string(),
+                1,
+            )
+            .await
+            .expect("open document");
+        let language_at = |line: u32| {
+            let service = &service;
+            let uri = &uri;
+            async move {
+                service
+                    .extract_completion_context(uri, Position::new(line, 3))
+                    .await
+                    .expect("extract context")
+                    .language_id
+            }
+        };
+
+        // Act
+        let inside = language_at(2).await;
+        let outside = language_at(4).await;
+        let without_info = language_at(6).await;
+
+        // Assert
+        assert_eq!(inside.as_deref(), Some("python"));
+        assert_eq!(outside.as_deref(), Some("markdown"));
+        assert_eq!(without_info.as_deref(), Some("markdown"));
+    }
+
+    #[tokio::test]
+    async fn given_fence_in_non_markdown_document_when_extracting_context_then_keeps_language() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = Url::parse("file:///doc.rs").expect("parse URI");
+        service
+            .open_document(
+                uri.to_string(),
+                "rust".to_string(),
+                "/// ```python\n/// imp\n".to_string(),
+            )
+            .await
+            .expect("open document");
+
+        // Act
+        let context = service
+            .extract_completion_context(&uri, Position::new(1, 7))
+            .await
+            .expect("extract context");
+
+        // Assert
+        assert_eq!(context.language_id.as_deref(), Some("rust"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn given_concurrent_changes_when_taking_snapshots_then_text_matches_version() {
+        // Arrange
+        let service = Arc::new(DocumentService::new());
+        let uri = "file:///test.rs".to_string();
+        service
+            .open_versioned_document(uri.clone(), "rust".to_string(), "0".to_string(), 0)
+            .await
+            .expect("open document");
+        let writer = {
+            let service = service.clone();
+            let uri = uri.clone();
+            tokio::spawn(async move {
+                for version in 1..=200 {
+                    service
+                        .change_document(uri.clone(), version, full_change(&version.to_string()))
+                        .await
+                        .expect("change document");
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let service = service.clone();
+                let uri = uri.clone();
+                tokio::spawn(async move {
+                    let mut last = 0;
+                    for _ in 0..200 {
+                        let snapshot = service.get_document_snapshot(&uri).await.expect("snapshot");
+                        let version = snapshot.version.expect("version");
+                        assert_eq!(snapshot.text, version.to_string());
+                        assert!(version >= last);
+                        last = version;
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+
+        // Act
+        writer.await.expect("writer task");
+        for reader in readers {
+            reader.await.expect("reader task");
+        }
+
+        // Assert
+        let snapshot = service.get_document_snapshot(&uri).await.expect("snapshot");
+        assert_eq!(snapshot.text, "200");
+        assert_eq!(snapshot.version, Some(200));
+    }
+
+    #[tokio::test]
+    async fn given_save_with_and_without_text_when_saving_then_updates_content_and_saved_version() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = "file:///test.rs".to_string();
+        service
+            .open_versioned_document(uri.clone(), "rust".to_string(), "drifted".to_string(), 7)
+            .await
+            .expect("open document");
+
+        // Act
+        service
+            .save_document(&uri, None)
+            .await
+            .expect("save without text");
+        let kept = service.get_document_content(&uri).await;
+        service
+            .save_document(&uri, Some("saved text".to_string()))
+            .await
+            .expect("save with text");
+
+        // Assert
+        assert_eq!(kept.as_deref(), Some("drifted"));
+        assert_eq!(
+            service.get_document_content(&uri).await.as_deref(),
+            Some("saved text")
+        );
+        assert_eq!(
+            service.document_version(&uri).await,
+            Some(DocumentVersion {
+                version: 7,
+                saved: Some(7)
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn given_document_without_word_when_extracting_query_then_returns_none() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri_str = "file:///test.rs".to_string();
+        let uri = Url::parse(&uri_str).expect("parse URI");
+        let content = "   ".to_string(); // Only whitespace
+        let position = Position {
+            line: 0,
+            character: 2,
+        };
+
+        service
+            .open_document(uri_str, "rust".to_string(), content)
+            .await
+            .expect("open document");
+
+        // Act
+        let result = service.extract_completion_context(&uri, position).await;
 
         // Assert
         assert!(result.is_ok());
@@ -250,6 +1917,67 @@ mod tests {
         assert!(!context.has_query());
     }
 
+    async fn context_at(content: &str, position: Position) -> CompletionContext {
+        let service = DocumentService::new();
+        let uri = Url::parse("file:///test.rs").expect("parse URI");
+        service
+            .open_document(uri.to_string(), "rust".to_string(), content.to_string())
+            .await
+            .expect("open document");
+        service
+            .extract_completion_context(&uri, position)
+            .await
+            .expect("valid completion context")
+    }
+
+    #[tokio::test]
+    async fn given_crlf_document_when_extracting_query_then_ignores_carriage_returns() {
+        // Act
+        let context = context_at("fn main() {\r\n    hello\r\n}", Position::new(1, 9)).await;
+
+        // Assert
+        let query = context.query.expect("query");
+        assert_eq!(query.text, "hello");
+        assert_eq!(
+            query.range,
+            Range::new(Position::new(1, 4), Position::new(1, 9))
+        );
+        assert_eq!(query.word_end, Position::new(1, 9));
+        assert_eq!(context.indentation.as_deref(), Some("    "));
+    }
+
+    #[tokio::test]
+    async fn given_cursor_at_or_past_end_of_file_when_extracting_query_then_uses_last_word() {
+        // Arrange
+        let content = "let x = 1;\r\nprintln";
+
+        // Act
+        let at_end = context_at(content, Position::new(1, 7)).await;
+        let past_end = context_at(content, Position::new(1, 8)).await;
+
+        // Assert
+        let expected = Range::new(Position::new(1, 0), Position::new(1, 7));
+        assert_eq!(at_end.get_query_text(), Some("println"));
+        assert_eq!(at_end.get_replacement_range(), Some(expected));
+        assert_eq!(past_end.get_query_text(), Some("println"));
+        assert_eq!(past_end.get_replacement_range(), Some(expected));
+    }
+
+    #[tokio::test]
+    async fn given_cursor_after_final_newline_or_empty_buffer_when_extracting_then_query_is_empty()
+    {
+        // Act
+        let after_newline = context_at("hello\r\n", Position::new(1, 0)).await;
+        let empty = context_at("", Position::new(0, 0)).await;
+
+        // Assert
+        for context in [after_newline, empty] {
+            assert!(!context.has_query());
+            assert_eq!(context.get_replacement_range(), None);
+            assert!(context.line.expect("line context").at_line_start);
+        }
+    }
+
     #[tokio::test]
     async fn given_document_when_closing_then_removes_from_cache() {
         // Arrange
@@ -269,4 +1997,471 @@ mod tests {
         assert!(result.is_ok());
         assert!(service.get_language_id(&uri).await.is_none());
     }
+
+    fn ranged_change(
+        start: (u32, u32),
+        end: (u32, u32),
+        text: &str,
+    ) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range {
+                start: Position {
+                    line: start.0,
+                    character: start.1,
+                },
+                end: Position {
+                    line: end.0,
+                    character: end.1,
+                },
+            }),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn given_incremental_changes_when_updating_then_extracts_query_from_final_text() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri_str = "file:///test.rs".to_string();
+        let uri = Url::parse(&uri_str).expect("parse URI");
+        service
+            .open_document(
+                uri_str.clone(),
+                "rust".to_string(),
+                "fn main() {\n}".to_string(),
+            )
+            .await
+            .expect("open document");
+
+        // Act: insert a line, type a word, then rename part of it
+        service
+            .update_document(
+                uri_str.clone(),
+                vec![ranged_change((0, 11), (0, 11), "\n    ")],
+            )
+            .await
+            .expect("insert newline");
+        service
+            .update_document(
+                uri_str.clone(),
+                vec![ranged_change((1, 4), (1, 4), "hello")],
+            )
+            .await
+            .expect("insert word");
+        service
+            .update_document(uri_str.clone(), vec![ranged_change((1, 5), (1, 9), "ELP")])
+            .await
+            .expect("replace part of word");
+
+        // Assert
+        let content = service.get_document_content(&uri_str).await;
+        assert_eq!(content.as_deref(), Some("fn main() {\n    hELP\n}"));
+        let context = service
+            .extract_completion_context(
+                &uri,
+                Position {
+                    line: 1,
+                    character: 8,
+                },
+            )
+            .await
+            .expect("valid completion context");
+        assert_eq!(context.get_query_text(), Some("hELP"));
+    }
+
+    #[tokio::test]
+    async fn given_multiline_replacement_when_updating_then_splices_across_lines() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = "file:///test.rs".to_string();
+        service
+            .open_document(
+                uri.clone(),
+                "rust".to_string(),
+                "one\ntwo\nthree".to_string(),
+            )
+            .await
+            .expect("open document");
+
+        // Act
+        service
+            .update_document(uri.clone(), vec![ranged_change((0, 1), (2, 2), "X\nY")])
+            .await
+            .expect("apply multi-line change");
+
+        // Assert
+        let content = service.get_document_content(&uri).await;
+        assert_eq!(content.as_deref(), Some("oX\nYree"));
+    }
+
+    #[tokio::test]
+    async fn given_edit_at_document_end_when_updating_then_appends_text() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri_str = "file:///test.rs".to_string();
+        let uri = Url::parse(&uri_str).expect("parse URI");
+        service
+            .open_document(
+                uri_str.clone(),
+                "rust".to_string(),
+                "let x = 1;\n".to_string(),
+            )
+            .await
+            .expect("open document");
+
+        // Act: the position after the trailing newline is the document end
+        service
+            .update_document(
+                uri_str.clone(),
+                vec![
+                    ranged_change((1, 0), (1, 0), "da"),
+                    ranged_change((1, 2), (1, 2), "te"),
+                ],
+            )
+            .await
+            .expect("append text");
+
+        // Assert
+        let content = service.get_document_content(&uri_str).await;
+        assert_eq!(content.as_deref(), Some("let x = 1;\ndate"));
+        let context = service
+            .extract_completion_context(
+                &uri,
+                Position {
+                    line: 1,
+                    character: 4,
+                },
+            )
+            .await
+            .expect("valid completion context");
+        assert_eq!(context.get_query_text(), Some("date"));
+    }
+
+    #[tokio::test]
+    async fn given_utf16_positions_when_updating_then_converts_to_byte_offsets() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = "file:///test.md".to_string();
+        service
+            .open_document(uri.clone(), "markdown".to_string(), "🚀 é x".to_string())
+            .await
+            .expect("open document");
+
+        // Act: the rocket is two UTF-16 code units, so 'x' starts at character 5
+        service
+            .update_document(uri.clone(), vec![ranged_change((0, 5), (0, 6), "y")])
+            .await
+            .expect("apply change after multi-byte characters");
+
+        // Assert
+        let content = service.get_document_content(&uri).await;
+        assert_eq!(content.as_deref(), Some("🚀 é y"));
+    }
+
+    async fn query_at(
+        encoding: PositionEncoding,
+        content: &str,
+        character: u32,
+    ) -> Option<CompletionQuery> {
+        query_with_service(DocumentService::new(), encoding, content, character).await
+    }
+
+    async fn triggered_query_at(content: &str, character: u32) -> Option<CompletionQuery> {
+        let service = DocumentService::new().with_trigger_char(Some(':'));
+        query_with_service(service, PositionEncoding::Utf16, content, character).await
+    }
+
+    async fn query_with_service(
+        service: DocumentService,
+        encoding: PositionEncoding,
+        content: &str,
+        character: u32,
+    ) -> Option<CompletionQuery> {
+        service.set_position_encoding(encoding);
+        let uri = Url::parse("file:///test.txt").expect("parse URI");
+        service
+            .open_document(uri.to_string(), "text".to_string(), content.to_string())
+            .await
+            .expect("open document");
+
+        service
+            .extract_completion_context(&uri, Position { line: 0, character })
+            .await
+            .expect("completion context")
+            .query
+    }
+
+    fn line_range(start: u32, end: u32) -> Range {
+        Range {
+            start: Position {
+                line: 0,
+                character: start,
+            },
+            end: Position {
+                line: 0,
+                character: end,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn given_multibyte_prefix_when_extracting_query_then_uses_utf16_columns() {
+        // Arrange: cursor after "wor" in "日本語 héllo🚀 wor|d"
+        let content = "日本語 héllo🚀 word";
+
+        // Act
+        let query = query_at(PositionEncoding::Utf16, content, 15).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "wor");
+        assert_eq!(query.range, line_range(12, 15));
+    }
+
+    #[tokio::test]
+    async fn given_multibyte_prefix_when_extracting_query_then_uses_utf8_columns() {
+        // Arrange: same cursor as above, expressed in UTF-8 bytes
+        let content = "日本語 héllo🚀 word";
+
+        // Act
+        let query = query_at(PositionEncoding::Utf8, content, 24).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "wor");
+        assert_eq!(query.range, line_range(21, 24));
+    }
+
+    #[tokio::test]
+    async fn given_multibyte_word_when_extracting_query_then_range_covers_whole_word() {
+        // Arrange: cursor after "héllo"
+        let content = "日本語 héllo🚀 word";
+
+        // Act
+        let utf16 = query_at(PositionEncoding::Utf16, content, 9).await;
+        let utf8 = query_at(PositionEncoding::Utf8, content, 16).await;
+
+        // Assert
+        let utf16 = utf16.expect("UTF-16 query");
+        let utf8 = utf8.expect("UTF-8 query");
+        assert_eq!(utf16.text, "héllo");
+        assert_eq!(utf16.range, line_range(4, 9));
+        assert_eq!(utf8.text, "héllo");
+        assert_eq!(utf8.range, line_range(10, 16));
+    }
+
+    #[tokio::test]
+    async fn given_trigger_at_line_start_when_extracting_query_then_range_includes_trigger() {
+        // Act
+        let query = triggered_query_at(":hel", 4).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "hel");
+        assert_eq!(query.range, line_range(0, 4));
+    }
+
+    #[tokio::test]
+    async fn given_trigger_mid_line_when_extracting_query_then_range_includes_trigger() {
+        // Act
+        let query = triggered_query_at("let x = :hel", 12).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "hel");
+        assert_eq!(query.range, line_range(8, 12));
+    }
+
+    #[tokio::test]
+    async fn given_bare_trigger_when_extracting_query_then_returns_empty_query_over_trigger() {
+        // Act
+        let query = triggered_query_at("x :", 3).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "");
+        assert_eq!(query.range, line_range(2, 3));
+    }
+
+    #[tokio::test]
+    async fn given_trigger_inside_word_when_extracting_query_then_ignores_trigger() {
+        // Act: "std::vec" must not be treated as a triggered query
+        let query = triggered_query_at("std::vec", 8).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "vec");
+        assert_eq!(query.range, line_range(5, 8));
+    }
+
+    #[tokio::test]
+    async fn given_no_trigger_configured_when_extracting_query_then_keeps_plain_word() {
+        // Act
+        let query = query_at(PositionEncoding::Utf16, ":hel", 4).await;
+        let bare = query_at(PositionEncoding::Utf16, ":", 1).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "hel");
+        assert_eq!(query.range, line_range(1, 4));
+        assert!(bare.is_none());
+    }
+
+    #[tokio::test]
+    async fn given_dotted_token_when_extracting_query_then_range_covers_whole_token() {
+        // Act
+        let query = query_at(PositionEncoding::Utf16, "run docker.compose", 18).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "docker.compose");
+        assert_eq!(query.range, line_range(4, 18));
+    }
+
+    #[tokio::test]
+    async fn given_path_like_token_at_line_start_when_extracting_query_then_keeps_slashes() {
+        // Act
+        let query = query_at(PositionEncoding::Utf16, "aws/s3", 6).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "aws/s3");
+        assert_eq!(query.range, line_range(0, 6));
+    }
+
+    #[tokio::test]
+    async fn given_cursor_inside_word_when_extracting_query_then_word_end_lies_past_cursor() {
+        // Act
+        let query = query_at(PositionEncoding::Utf16, "let hello = 1", 7).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "hel");
+        assert_eq!(query.range, line_range(4, 7));
+        assert_eq!(query.word_end.character, 9);
+    }
+
+    #[tokio::test]
+    async fn given_cursor_at_word_end_when_extracting_query_then_word_ends_at_cursor() {
+        // Act
+        let query = query_at(PositionEncoding::Utf16, "let hello = 1", 9).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.range, line_range(4, 9));
+        assert_eq!(query.word_end, query.range.end);
+    }
+
+    #[tokio::test]
+    async fn given_punctuation_after_cursor_when_extracting_query_then_word_ends_before_it() {
+        // Act
+        let mid_word = query_at(PositionEncoding::Utf16, "confimap(x)", 5).await;
+        let before_paren = query_at(PositionEncoding::Utf16, "confi(x)", 5).await;
+
+        // Assert
+        assert_eq!(mid_word.expect("query").word_end.character, 8);
+        assert_eq!(before_paren.expect("query").word_end.character, 5);
+    }
+
+    #[tokio::test]
+    async fn given_extra_word_chars_when_extracting_query_then_scans_both_ways_over_them() {
+        // Arrange
+        let service = DocumentService::new().with_word_chars(vec!['.']);
+
+        // Act
+        let query =
+            query_with_service(service, PositionEncoding::Utf16, "docker.comp.yml rest", 9).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "docker.co");
+        assert_eq!(query.range, line_range(0, 9));
+        assert_eq!(query.word_end.character, 15);
+    }
+
+    #[tokio::test]
+    async fn given_only_separators_when_extracting_query_then_returns_none() {
+        // Act
+        let query = query_at(PositionEncoding::Utf16, "x ./.", 5).await;
+
+        // Assert
+        assert!(query.is_none());
+    }
+
+    #[tokio::test]
+    async fn given_trailing_separator_when_extracting_query_then_replaces_but_does_not_search_it() {
+        // Act
+        let query = query_at(PositionEncoding::Utf16, "docker.", 7).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "docker");
+        assert_eq!(query.range, line_range(0, 7));
+    }
+
+    #[tokio::test]
+    async fn given_no_extra_word_chars_when_extracting_query_then_stops_at_separator() {
+        // Arrange
+        let service = DocumentService::new().with_word_chars(Vec::new());
+
+        // Act
+        let query =
+            query_with_service(service, PositionEncoding::Utf16, "docker.compose", 14).await;
+
+        // Assert
+        let query = query.expect("query");
+        assert_eq!(query.text, "compose");
+        assert_eq!(query.range, line_range(7, 14));
+    }
+
+    #[tokio::test]
+    async fn given_utf8_encoding_when_updating_then_interprets_byte_columns() {
+        // Arrange
+        let service = DocumentService::new();
+        service.set_position_encoding(PositionEncoding::Utf8);
+        let uri = "file:///test.md".to_string();
+        service
+            .open_document(uri.clone(), "markdown".to_string(), "🚀 é x".to_string())
+            .await
+            .expect("open document");
+
+        // Act: the rocket is four bytes and 'é' two, so 'x' starts at byte 8
+        service
+            .update_document(uri.clone(), vec![ranged_change((0, 8), (0, 9), "y")])
+            .await
+            .expect("apply change after multi-byte characters");
+
+        // Assert
+        let content = service.get_document_content(&uri).await;
+        assert_eq!(content.as_deref(), Some("🚀 é y"));
+    }
+
+    #[tokio::test]
+    async fn given_full_change_when_updating_then_replaces_content() {
+        // Arrange
+        let service = DocumentService::new();
+        let uri = "file:///test.rs".to_string();
+        service
+            .open_document(uri.clone(), "rust".to_string(), "old".to_string())
+            .await
+            .expect("open document");
+
+        // Act
+        service
+            .update_document(
+                uri.clone(),
+                vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: "new content".to_string(),
+                }],
+            )
+            .await
+            .expect("apply full change");
+
+        // Assert
+        let content = service.get_document_content(&uri).await;
+        assert_eq!(content.as_deref(), Some("new content"));
+    }
 }
@@ -0,0 +1,179 @@
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use tower_lsp::lsp_types::{Hover, HoverContents, MarkupContent, MarkupKind, Range, Url};
+use tracing::{debug, instrument};
+
+use crate::domain::{LanguageRegistry, SnippetFilter, TranslationPolicy};
+use crate::repositories::SnippetRepository;
+use crate::services::{ContextVariables, LanguageTranslator, SnippetPresenter};
+
+/// Words shorter than this are not looked up, to avoid running bkmr on every hover
+const MIN_HOVER_WORD_CHARS: usize = 3;
+/// Maximum number of snippets shown when several share a title
+const MAX_HOVER_SNIPPETS: usize = 3;
+
+/// Service previewing snippets whose title is under the cursor
+pub struct HoverService {
+    repository: Arc<dyn SnippetRepository>,
+    /// Which universal snippets are previewed translated
+    translation: TranslationPolicy,
+    /// Languages the previews are translated with
+    language_registry: Arc<LanguageRegistry>,
+}
+
+impl std::fmt::Debug for HoverService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HoverService")
+            .field("repository", &"<SnippetRepository>")
+            .finish()
+    }
+}
+
+impl HoverService {
+    pub fn new(repository: Arc<dyn SnippetRepository>) -> Self {
+        Self {
+            repository,
+            translation: TranslationPolicy::default(),
+            language_registry: Arc::new(LanguageRegistry::default()),
+        }
+    }
+
+    pub fn with_translation(mut self, translation: TranslationPolicy) -> Self {
+        self.translation = translation;
+        self
+    }
+
+    pub fn with_language_registry(mut self, language_registry: Arc<LanguageRegistry>) -> Self {
+        self.language_registry = language_registry;
+        self
+    }
+
+    /// Build a hover for the snippets titled exactly like the word, if any
+    #[instrument(skip(self))]
+    pub async fn hover_for_word(
+        &self,
+        word: &str,
+        range: Range,
+        language_id: Option<&str>,
+        uri: &Url,
+    ) -> Result<Option<Hover>> {
+        if word.chars().count() < MIN_HOVER_WORD_CHARS {
+            debug!("Word '{}' too short for hover lookup", word);
+            return Ok(None);
+        }
+
+        let filter = SnippetFilter::new(language_id.map(str::to_string), None, MAX_HOVER_SNIPPETS)
+            .with_title_exact(word.to_string())
+            .with_universal(self.translation.universal)
+            .with_folder(self.repository.route(uri));
+        let snippets = self
+            .repository
+            .fetch_snippets(&filter)
+            .await
+            .context("fetch snippets for hover")?;
+        if snippets.is_empty() {
+            return Ok(None);
+        }
+
+        let language_id = language_id.unwrap_or("unknown");
+        let sections = snippets
+            .iter()
+            .take(MAX_HOVER_SNIPPETS)
+            .map(|snippet| {
+                let variables = ContextVariables::new(uri, language_id, &self.language_registry);
+                let content = LanguageTranslator::translate_snippet(
+                    snippet,
+                    language_id,
+                    &variables,
+                    &self.translation,
+                )
+                .context("translate snippet content for hover")?;
+                Ok(SnippetPresenter::snippet_markdown(
+                    snippet,
+                    &content,
+                    language_id,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: sections.join("\n\n---\n\n"),
+            }),
+            range: Some(range),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::Snippet;
+    use crate::repositories::MockSnippetRepository;
+    use tower_lsp::lsp_types::Position;
+
+    fn snippet(id: i32, title: &str, content: &str) -> Snippet {
+        Snippet::new(
+            id,
+            title.to_string(),
+            content.to_string(),
+            String::new(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        )
+    }
+
+    fn hover_text(hover: Hover) -> String {
+        match hover.contents {
+            HoverContents::Markup(markup) => markup.value,
+            other => panic!("expected markup, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn given_several_snippets_with_title_when_hovering_then_shows_top_three() {
+        // Arrange
+        let snippets = (1..=4)
+            .map(|id| snippet(id, "retry", &format!("retry_{}()", id)))
+            .chain([snippet(5, "retry loop", "loop {}")])
+            .collect();
+        let service = HoverService::new(Arc::new(
+            MockSnippetRepository::new().with_snippets(snippets),
+        ));
+        let uri = Url::parse("file:///test.rs").expect("parse URI");
+        let range = Range::new(Position::new(0, 0), Position::new(0, 5));
+
+        // Act
+        let hover = service
+            .hover_for_word("Retry", range, Some("rust"), &uri)
+            .await
+            .expect("hover")
+            .expect("some hover");
+
+        // Assert
+        let text = hover_text(hover);
+        assert_eq!(text.matches("\n---\n").count(), 2);
+        assert!(text.contains("```rust\nretry_1()\n```"));
+        assert!(!text.contains("retry_4()"));
+        assert!(!text.contains("loop {}"));
+    }
+
+    #[tokio::test]
+    async fn given_short_word_when_hovering_then_does_not_query_repository() {
+        // Arrange
+        let repository =
+            Arc::new(MockSnippetRepository::new().with_snippets(vec![snippet(1, "ab", "content")]));
+        let service = HoverService::new(repository.clone());
+        let uri = Url::parse("file:///test.rs").expect("parse URI");
+
+        // Act
+        let hover = service
+            .hover_for_word("ab", Range::default(), Some("rust"), &uri)
+            .await
+            .expect("hover");
+
+        // Assert
+        assert!(hover.is_none());
+        assert_eq!(repository.fetch_count(), 0);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use tower_lsp::lsp_types::Url;
+use tracing::{debug, info, warn};
+
+use crate::domain::{IgnoreRules, PathSeparator, render_relative_path, uri_file_name};
+use crate::services::CommandService;
+use crate::sync;
+
+/// File at a workspace or project root listing documents without completions
+pub const IGNORE_FILE_NAME: &str = ".bkmrlspignore";
+
+/// Decides which documents get no snippet completions, by language or ignore file
+///
+/// Ignore files are read once per root, with tokio::fs, and again when the
+/// client reports a change to them.
+#[derive(Debug, Default)]
+pub struct IgnoreService {
+    /// Language ids completions are disabled for
+    disabled_languages: RwLock<Vec<String>>,
+    /// Rules of the ignore file of each root directory, empty if it has none
+    rules: RwLock<HashMap<PathBuf, IgnoreRules>>,
+}
+
+impl IgnoreService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the language ids completions are disabled for
+    pub fn set_disabled_languages(&self, languages: Vec<String>) {
+        *sync::write(&self.disabled_languages, "disabled languages") = languages;
+    }
+
+    /// Read the ignore files of the workspace folders
+    pub async fn load(&self, workspace_folders: &[Url]) {
+        for root in workspace_folders
+            .iter()
+            .filter_map(|folder| folder.to_file_path().ok())
+        {
+            self.read_rules(&root).await;
+        }
+    }
+
+    /// Read an ignore file again after the client reported a change, None if it is no ignore file
+    pub async fn reload(&self, file_uri: &Url) -> Option<()> {
+        if uri_file_name(file_uri).as_deref() != Some(IGNORE_FILE_NAME) {
+            return None;
+        }
+        let path = file_uri.to_file_path().ok()?;
+        self.read_rules(path.parent()?).await;
+        Some(())
+    }
+
+    /// Whether completions are disabled for a document
+    ///
+    /// Paths are relative to the innermost workspace folder containing the
+    /// document, or to its project root when it lies outside all folders.
+    pub async fn is_ignored(
+        &self,
+        uri: &Url,
+        language_id: Option<&str>,
+        workspace_folders: &[Url],
+    ) -> bool {
+        if let Some(language_id) = language_id
+            && sync::read(&self.disabled_languages, "disabled languages")
+                .iter()
+                .any(|disabled| disabled.eq_ignore_ascii_case(language_id))
+        {
+            debug!("Completions disabled for language {}", language_id);
+            return true;
+        }
+
+        let Some((root, relative_path)) = Self::root_and_relative_path(uri, workspace_folders)
+        else {
+            return false;
+        };
+        let cached = sync::read(&self.rules, "ignore rules")
+            .get(&root)
+            .map(|rules| rules.is_ignored(&relative_path));
+        let ignored = match cached {
+            Some(ignored) => ignored,
+            None => self.read_rules(&root).await.is_ignored(&relative_path),
+        };
+        if ignored {
+            debug!(
+                "Completions disabled by {} for {}",
+                IGNORE_FILE_NAME, relative_path
+            );
+        }
+        ignored
+    }
+
+    /// Root directory of a file and its path relative to it, separated by '/'
+    fn root_and_relative_path(uri: &Url, workspace_folders: &[Url]) -> Option<(PathBuf, String)> {
+        let innermost = workspace_folders
+            .iter()
+            .filter_map(|folder| {
+                let relative_path = CommandService::workspace_relative_path(
+                    uri,
+                    std::slice::from_ref(folder),
+                    PathSeparator::Slash,
+                )?;
+                Some((folder.to_file_path().ok()?, relative_path))
+            })
+            .min_by_key(|(_, relative_path)| relative_path.matches('/').count());
+        if innermost.is_some() {
+            return innermost;
+        }
+
+        let file_path = uri.to_file_path().ok()?;
+        let root = CommandService::project_root(&file_path)?;
+        let relative_path =
+            render_relative_path(file_path.strip_prefix(root).ok()?, PathSeparator::Slash);
+        Some((root.to_path_buf(), relative_path))
+    }
+
+    /// Read and remember the ignore file of a root, empty rules if it has none
+    async fn read_rules(&self, root: &Path) -> IgnoreRules {
+        let path = root.join(IGNORE_FILE_NAME);
+        let rules = match tokio::fs::read_to_string(&path).await {
+            Ok(text) => {
+                let rules = IgnoreRules::parse(&text);
+                info!("Read {}", path.display());
+                rules
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => IgnoreRules::default(),
+            Err(e) => {
+                warn!("Failed to read {}: {}", path.display(), e);
+                IgnoreRules::default()
+            }
+        };
+        sync::write(&self.rules, "ignore rules").insert(root.to_path_buf(), rules.clone());
+        rules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace_with_ignore_file(patterns: &str) -> (PathBuf, Url) {
+        let root = std::env::temp_dir().join(format!(
+            "bkmr-lsp-ignore-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&root).expect("create workspace");
+        std::fs::write(root.join(IGNORE_FILE_NAME), patterns).expect("write ignore file");
+        let folder = Url::from_directory_path(&root).expect("workspace URI");
+        (root, folder)
+    }
+
+    fn file_uri(root: &Path, relative_path: &str) -> Url {
+        Url::from_file_path(root.join(relative_path)).expect("file URI")
+    }
+
+    #[tokio::test]
+    async fn given_disabled_language_when_checking_then_ignores_document_anywhere() {
+        // Arrange
+        let service = IgnoreService::new();
+        service.set_disabled_languages(vec!["gitcommit".to_string()]);
+        let uri = Url::parse("file:///repo/.git/COMMIT_EDITMSG").expect("parse URI");
+
+        // Act & Assert
+        assert!(service.is_ignored(&uri, Some("GitCommit"), &[]).await);
+        assert!(!service.is_ignored(&uri, Some("markdown"), &[]).await);
+        assert!(!service.is_ignored(&uri, None, &[]).await);
+    }
+
+    #[tokio::test]
+    async fn given_ignore_file_when_checking_nested_paths_then_matches_workspace_relative_path() {
+        // Arrange
+        let (root, folder) = workspace_with_ignore_file("vendor/\n*.gen.ts\n");
+        let service = IgnoreService::new();
+        service.load(std::slice::from_ref(&folder)).await;
+
+        // Act & Assert
+        assert!(
+            service
+                .is_ignored(
+                    &file_uri(&root, "vendor/lib/a.rs"),
+                    Some("rust"),
+                    std::slice::from_ref(&folder)
+                )
+                .await
+        );
+        assert!(
+            service
+                .is_ignored(
+                    &file_uri(&root, "web/api/client.gen.ts"),
+                    None,
+                    std::slice::from_ref(&folder)
+                )
+                .await
+        );
+        assert!(
+            !service
+                .is_ignored(&file_uri(&root, "src/vendor.rs"), Some("rust"), &[folder])
+                .await
+        );
+        std::fs::remove_dir_all(root).expect("remove workspace");
+    }
+
+    #[tokio::test]
+    async fn given_changed_ignore_file_when_reloading_then_uses_new_patterns() {
+        // Arrange
+        let (root, folder) = workspace_with_ignore_file("vendor/\n");
+        let service = IgnoreService::new();
+        service.load(std::slice::from_ref(&folder)).await;
+        let document = file_uri(&root, "generated/schema.rs");
+        let was_ignored = service
+            .is_ignored(&document, None, std::slice::from_ref(&folder))
+            .await;
+        std::fs::write(root.join(IGNORE_FILE_NAME), "generated/\n").expect("rewrite ignore file");
+
+        // Act
+        let reloaded = service.reload(&file_uri(&root, IGNORE_FILE_NAME)).await;
+        let not_reloaded = service.reload(&file_uri(&root, "README.md")).await;
+
+        // Assert
+        assert!(!was_ignored);
+        assert_eq!(reloaded, Some(()));
+        assert_eq!(not_reloaded, None);
+        assert!(service.is_ignored(&document, None, &[folder]).await);
+        std::fs::remove_dir_all(root).expect("remove workspace");
+    }
+}
@@ -1,19 +1,138 @@
 use anyhow::{Context, Result};
 use lazy_static::lazy_static;
-use regex::{Regex, RegexBuilder};
+use regex::Regex;
+use time::OffsetDateTime;
+use time::format_description::parse_strftime_borrowed;
 use tower_lsp::lsp_types::Url;
-use tracing::{debug, instrument};
+use tracing::{debug, instrument, warn};
 
-use crate::domain::{LanguageInfo, LanguageRegistry, Snippet};
+use crate::domain::{
+    IndentUnit, LanguageInfo, LanguageRegistry, PathSeparator, Snippet, TranslationPolicy,
+    untitled_file_name, uri_file_name,
+};
+use crate::logging::preview;
+use crate::services::CommandService;
 
 // Pre-compiled regex patterns for performance
 lazy_static! {
-    static ref LINE_COMMENT_START: Regex =
-        Regex::new(r"^(\s*)//\s*(.*)$").expect("compile line comment start regex");
-    static ref LINE_COMMENT_END: Regex =
-        Regex::new(r"^(.+?)(\s+)//\s*(.*)$").expect("compile line comment end regex");
     static ref RUST_INDENT: Regex =
         Regex::new(r"^( {4})+").expect("compile rust indentation regex");
+    static ref BKMR_PLACEHOLDER: Regex =
+        Regex::new(r"\{\{\s*(cursor|selection|tabstop(\d+))\s*\}\}")
+            .expect("compile bkmr placeholder regex");
+    static ref CONTEXT_VARIABLE: Regex =
+        Regex::new(r"\{\{\s*(filename|filepath|dirname|date|language|author)(?::([^}]*?))?\s*\}\}")
+            .expect("compile context variable regex");
+    static ref ENVIRONMENT_VARIABLE: Regex =
+        Regex::new(r"(\\)?\$(?:([A-Za-z_][A-Za-z0-9_]*)|\{([A-Za-z_][A-Za-z0-9_]*)\})")
+            .expect("compile environment variable regex");
+    static ref TABSTOP_INDEX: Regex =
+        Regex::new(r"(\\)?\$\{?(\d+)").expect("compile tabstop index regex");
+    static ref VARIANT_MARKER: Regex =
+        Regex::new(r"%%(choice|input):").expect("compile variant marker regex");
+}
+
+/// Shown in documentation for environment variables that are not set
+const UNSET_VARIABLE: &str = "(unset)";
+
+/// Format of `{{ date }}` without an explicit one
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Editor context substituted for `{{ filename }}`, `{{ date }}` and friends
+///
+/// Paths are only computed when a snippet uses them.
+#[derive(Debug, Clone)]
+pub struct ContextVariables<'a> {
+    pub uri: &'a Url,
+    pub language_id: &'a str,
+    pub workspace_folders: &'a [Url],
+    pub separator: PathSeparator,
+    pub author: Option<&'a str>,
+    /// Time `{{ date }}` renders, in UTC
+    pub now: OffsetDateTime,
+    /// Indentation of the document, used by universal snippets instead of the language's
+    pub indent_unit: Option<IndentUnit>,
+    /// Languages naming untitled files and translating universal snippets
+    pub language_registry: &'a LanguageRegistry,
+}
+
+impl<'a> ContextVariables<'a> {
+    pub fn new(
+        uri: &'a Url,
+        language_id: &'a str,
+        language_registry: &'a LanguageRegistry,
+    ) -> Self {
+        Self {
+            uri,
+            language_id,
+            language_registry,
+            workspace_folders: &[],
+            separator: PathSeparator::default(),
+            author: None,
+            now: OffsetDateTime::now_utc(),
+            indent_unit: None,
+        }
+    }
+
+    pub fn with_workspace_folders(
+        mut self,
+        workspace_folders: &'a [Url],
+        separator: PathSeparator,
+    ) -> Self {
+        self.workspace_folders = workspace_folders;
+        self.separator = separator;
+        self
+    }
+
+    pub fn with_author(mut self, author: Option<&'a str>) -> Self {
+        self.author = author;
+        self
+    }
+
+    pub fn with_indent_unit(mut self, indent_unit: Option<IndentUnit>) -> Self {
+        self.indent_unit = indent_unit;
+        self
+    }
+
+    /// Value of a variable, None leaves it untouched
+    fn value(&self, name: &str, format: Option<&str>) -> Option<String> {
+        match name {
+            "filename" => Some(
+                untitled_file_name(self.uri, Some(self.language_id), self.language_registry)
+                    .or_else(|| uri_file_name(self.uri))
+                    .unwrap_or_else(|| "untitled".to_string()),
+            ),
+            "filepath" => self.filepath(),
+            "dirname" => self.filepath().map(|filepath| {
+                match filepath.rfind(['/', std::path::MAIN_SEPARATOR]) {
+                    Some(end) => filepath[..end].to_string(),
+                    None => ".".to_string(),
+                }
+            }),
+            "date" => {
+                let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+                parse_strftime_borrowed(format)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|items| self.now.format(&items).map_err(anyhow::Error::from))
+                    .map_err(|e| debug!("Leaving date with format '{}': {}", format, e))
+                    .ok()
+            }
+            "language" => Some(self.language_id.to_string()),
+            "author" => self.author.map(str::to_string),
+            _ => None,
+        }
+    }
+
+    fn filepath(&self) -> Option<String> {
+        CommandService::get_relative_path(
+            self.uri.as_str(),
+            Some(self.language_id),
+            self.workspace_folders,
+            self.separator,
+            self.language_registry,
+        )
+        .ok()
+    }
 }
 
 /// Service for translating Rust syntax patterns to target languages
@@ -21,118 +140,423 @@ pub struct LanguageTranslator;
 
 impl LanguageTranslator {
     /// Translate Rust syntax patterns in universal snippets to target language
-    #[instrument(skip(snippet))]
-    pub fn translate_snippet(snippet: &Snippet, language_id: &str, uri: &Url) -> Result<String> {
-        let content = if snippet.is_universal() {
+    ///
+    /// Only snippets the policy selects are translated, see
+    /// [`Snippet::needs_translation`]. Context variables are expanded in every
+    /// snippet, escaped for snippet syntax unless the snippet is inserted as
+    /// plain text.
+    #[instrument(skip(snippet, variables))]
+    pub fn translate_snippet(
+        snippet: &Snippet,
+        language_id: &str,
+        variables: &ContextVariables,
+        policy: &TranslationPolicy,
+    ) -> Result<String> {
+        let registry = variables.language_registry;
+        let content = if snippet.needs_translation(language_id, policy, registry) {
             debug!("Processing universal snippet: {}", snippet.title);
-            debug!("Original content: {:?}", snippet.get_content());
+            debug!("Original content: {}", preview(snippet.get_content()));
 
-            Self::translate_rust_patterns(snippet.get_content(), language_id, uri)
-                .context("translate Rust patterns to target language")?
+            Self::translate_rust_patterns_with_indent(
+                snippet.get_content(),
+                language_id,
+                variables.indent_unit,
+                registry,
+            )
+            .context("translate Rust patterns to target language")?
         } else {
-            // Regular snippet - return content as-is
+            // Regular snippet, or universal one not written in Rust style - return content as-is
             snippet.get_content().to_string()
         };
+        let content = if snippet.is_plain() {
+            content
+        } else {
+            Self::convert_variant_markers(&content)
+        };
+        let content = Self::expand_context_variables(&content, variables, !snippet.is_plain());
 
-        debug!("Final translated content: {:?}", content);
+        debug!("Final translated content: {}", preview(&content));
         Ok(content)
     }
 
     /// Translate Rust syntax patterns in content to target language
-    #[instrument(skip(content))]
-    pub fn translate_rust_patterns(content: &str, language_id: &str, uri: &Url) -> Result<String> {
-        let target_lang = LanguageRegistry::get_language_info(language_id);
+    pub fn translate_rust_patterns(
+        content: &str,
+        language_id: &str,
+        registry: &LanguageRegistry,
+    ) -> Result<String> {
+        Self::translate_rust_patterns_with_indent(content, language_id, None, registry)
+    }
+
+    /// Translate Rust syntax patterns, indenting by `indent_unit` rather than the language's indentation
+    #[instrument(skip(content, registry))]
+    pub fn translate_rust_patterns_with_indent(
+        content: &str,
+        language_id: &str,
+        indent_unit: Option<IndentUnit>,
+        registry: &LanguageRegistry,
+    ) -> Result<String> {
+        let mut target_lang = registry.get_language_info(language_id);
+        if let Some(indent_unit) = indent_unit {
+            target_lang.indent_char = indent_unit.text();
+        }
 
         debug!("Translating Rust patterns for language: {}", language_id);
-        debug!("Input content: {:?}", content);
+        debug!("Input content: {}", preview(content));
         debug!("Content length: {} bytes", content.len());
 
         // Use line-by-line processing to preserve newlines
-        let mut processed_content =
-            Self::translate_rust_patterns_line_by_line(content, &target_lang)
-                .context("process content line by line")?;
-
-        // Replace Rust block comments (/* */) with target language block comments
-        if let Some((target_start, target_end)) = &target_lang.block_comment {
-            let block_comment_regex = RegexBuilder::new(r"/\*(.*?)\*/")
-                .dot_matches_new_line(true)
-                .build()
-                .context("compile block comment regex")?;
-
-            processed_content = block_comment_regex
-                .replace_all(&processed_content, |caps: &regex::Captures| {
-                    format!("{}{}{}", target_start, &caps[1], target_end)
-                })
-                .to_string();
-        }
-
-        // Add file name replacement for simple relative path
-        if processed_content.contains("{{ filename }}") {
-            let filename = uri.path().split('/').next_back().unwrap_or("untitled");
-            processed_content = processed_content.replace("{{ filename }}", filename);
-        }
+        let processed_content = Self::translate_rust_patterns_line_by_line(content, &target_lang)
+            .context("process content line by line")?;
 
         debug!("Rust pattern translation complete");
-        debug!("Final content: {:?}", processed_content);
+        debug!("Final content: {}", preview(&processed_content));
         debug!("Final content length: {} bytes", processed_content.len());
 
         Ok(processed_content)
     }
 
+    /// Replace editor context variables left after bkmr's interpolation
+    ///
+    /// Supports `{{ filename }}`, `{{ filepath }}` relative to the project,
+    /// `{{ dirname }}`, `{{ date }}` or `{{ date:%d.%m.%Y }}` with a strftime
+    /// format, `{{ language }}` and `{{ author }}`. Unknown variables, and
+    /// known ones without a value, are left untouched. With `escape` the
+    /// values are escaped for snippet syntax.
+    pub fn expand_context_variables(
+        content: &str,
+        variables: &ContextVariables,
+        escape: bool,
+    ) -> String {
+        if !content.contains("{{") {
+            return content.to_string();
+        }
+        CONTEXT_VARIABLE
+            .replace_all(content, |caps: &regex::Captures| {
+                match variables.value(&caps[1], caps.get(2).map(|format| format.as_str())) {
+                    Some(value) if escape => Self::escape_snippet_text(&value),
+                    Some(value) => value,
+                    None => caps[0].to_string(),
+                }
+            })
+            .to_string()
+    }
+
+    /// Escape the characters snippet syntax gives a meaning to
+    fn escape_snippet_text(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if matches!(c, '\\' | '$' | '}') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    /// Convert editor placeholders left in bkmr templates to LSP snippet syntax
+    ///
+    /// `{{ cursor }}` becomes `$0`, `{{ tabstopN }}` becomes `$N` and
+    /// `{{ selection }}` becomes `${n:$TM_SELECTED_TEXT}`, numbered after the
+    /// highest tabstop in the content. A `{{ cursor }}` is dropped when the
+    /// content already has a `$0`, which a snippet may hold only once. Other
+    /// `{{ ... }}` blocks are left untouched.
+    pub fn convert_bkmr_placeholders(content: &str) -> String {
+        let selection = Self::max_tabstop_index(content) + 1;
+        let has_final_tabstop = TABSTOP_INDEX
+            .captures_iter(content)
+            .any(|caps| caps.get(1).is_none() && &caps[2] == "0");
+        BKMR_PLACEHOLDER
+            .replace_all(content, |caps: &regex::Captures| match &caps[1] {
+                "cursor" if has_final_tabstop => String::new(),
+                "cursor" => "$0".to_string(),
+                "selection" => format!("${{{}:$TM_SELECTED_TEXT}}", selection),
+                _ => format!("${}", &caps[2]),
+            })
+            .to_string()
+    }
+
+    /// Turn `%%choice:a,b,c%%` into `${n|a,b,c|}` and `%%input:default%%` into `${n:default}`
+    ///
+    /// One snippet can then stand for near-duplicates differing in a word.
+    /// Tabstops are numbered after the highest one already in the content,
+    /// `{{ tabstopN }}` included, so they never collide.
+    /// Markers with an empty choice list or without closing `%%` are kept as
+    /// written.
+    pub fn convert_variant_markers(content: &str) -> String {
+        if !content.contains("%%") {
+            return content.to_string();
+        }
+
+        let mut index = Self::max_tabstop_index(content);
+        let mut converted = String::with_capacity(content.len());
+        let mut rest = content;
+        while let Some(marker) = VARIANT_MARKER.captures(rest) {
+            let whole = marker.get(0).expect("whole marker match");
+            converted.push_str(&rest[..whole.start()]);
+            let body = &rest[whole.end()..];
+            let line_end = body.find('\n').unwrap_or(body.len());
+            let Some(end) = body[..line_end].find("%%") else {
+                warn!(
+                    "Keeping unterminated %%{}: marker, closing %% is missing",
+                    &marker[1]
+                );
+                converted.push_str(whole.as_str());
+                rest = body;
+                continue;
+            };
+            let value = &body[..end];
+            let placeholder = match &marker[1] {
+                "choice" => {
+                    let options: Vec<&str> = value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|option| !option.is_empty())
+                        .collect();
+                    if options.is_empty() {
+                        warn!("Keeping %%choice:{}%% marker without choices", value);
+                        None
+                    } else {
+                        index += 1;
+                        let options: Vec<String> = options
+                            .into_iter()
+                            .map(Self::escape_choice_option)
+                            .collect();
+                        Some(format!("${{{}|{}|}}", index, options.join(",")))
+                    }
+                }
+                _ => {
+                    index += 1;
+                    Some(match value {
+                        "" => format!("${{{}}}", index),
+                        value => format!("${{{}:{}}}", index, Self::escape_snippet_text(value)),
+                    })
+                }
+            };
+            match placeholder {
+                Some(placeholder) => converted.push_str(&placeholder),
+                None => converted.push_str(&rest[whole.start()..whole.end() + end + 2]),
+            }
+            rest = &body[end + 2..];
+        }
+        converted.push_str(rest);
+        converted
+    }
+
+    /// Highest tabstop index used in the content, 0 if there is none
+    fn max_tabstop_index(content: &str) -> u32 {
+        let tabstops = TABSTOP_INDEX
+            .captures_iter(content)
+            .filter(|caps| caps.get(1).is_none())
+            .filter_map(|caps| caps[2].parse().ok());
+        let placeholders = BKMR_PLACEHOLDER
+            .captures_iter(content)
+            .filter_map(|caps| caps.get(2))
+            .filter_map(|index| index.as_str().parse().ok());
+        tabstops.chain(placeholders).max().unwrap_or(0)
+    }
+
+    /// Escape the characters with a meaning inside a `${n|...|}` choice
+    fn escape_choice_option(option: &str) -> String {
+        let mut escaped = String::with_capacity(option.len());
+        for c in option.chars() {
+            if matches!(c, '\\' | '$' | '}' | '|' | ',') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    /// Replace `$NAME` and `${NAME}` with the looked up value, for documentation only
+    ///
+    /// Tabstops like `$1` or `${1:x}`, snippet variables like `$TM_FILENAME`
+    /// or `${NAME:default}` and escaped `\$` are kept; unset variables show
+    /// as "(unset)".
+    pub fn resolve_environment_variables(
+        content: &str,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> String {
+        ENVIRONMENT_VARIABLE
+            .replace_all(content, |caps: &regex::Captures| {
+                let name = caps
+                    .get(2)
+                    .or_else(|| caps.get(3))
+                    .map_or("", |m| m.as_str());
+                if caps.get(1).is_some() || name.starts_with("TM_") {
+                    caps[0].to_string()
+                } else {
+                    lookup(name).unwrap_or_else(|| UNSET_VARIABLE.to_string())
+                }
+            })
+            .to_string()
+    }
+
+    /// Escape dollars that are the language's own syntax in the given languages
+    ///
+    /// In make, awk, perl or bash `$1` is a field or argument and `$(CC)` a
+    /// substitution, not a tabstop, so their `$` becomes `\$`. Placeholders
+    /// written as `${1:name}` stay intact. Other languages are returned as is.
+    pub fn escape_literal_dollars(
+        content: &str,
+        language_id: &str,
+        languages: &[String],
+        registry: &LanguageRegistry,
+    ) -> String {
+        if !registry.escapes_all_dollars(language_id, languages) {
+            return content.to_string();
+        }
+
+        let mut escaped = String::with_capacity(content.len());
+        let mut chars = content.chars().peekable();
+        let mut previous = None;
+        while let Some(c) = chars.next() {
+            let literal = c == '$'
+                && previous != Some('\\')
+                && chars
+                    .peek()
+                    .is_some_and(|next| next.is_ascii_digit() || *next == '(');
+            if literal {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+            previous = Some(c);
+        }
+        escaped
+    }
+
     /// Process content line by line to preserve newlines properly
     fn translate_rust_patterns_line_by_line(
         content: &str,
         target_lang: &LanguageInfo,
     ) -> Result<String> {
-        let lines: Vec<&str> = content.split('\n').collect();
         let mut processed_lines = Vec::new();
+        let mut in_block_comment = false;
+        let mut offset = 0;
 
-        for line in lines {
-            let mut processed_line = line.to_string();
+        for line in content.split('\n') {
+            offset = (offset + line.len() + 1).min(content.len());
+            let mut processed_line = Self::translate_comments(
+                line,
+                &content[offset..],
+                target_lang,
+                &mut in_block_comment,
+            );
 
-            // Process line comments (//)
-            if let Some(target_comment) = &target_lang.line_comment {
-                // Start of line comments
-                if let Some(captures) = LINE_COMMENT_START.captures(line) {
-                    processed_line = format!("{}{} {}", &captures[1], target_comment, &captures[2]);
-                }
-                // End of line comments (after code)
-                else if let Some(captures) = LINE_COMMENT_END.captures(line) {
-                    processed_line = format!(
-                        "{}{}{} {}",
-                        &captures[1], &captures[2], target_comment, &captures[3]
-                    );
-                }
-            } else if let Some((block_start, block_end)) = &target_lang.block_comment {
-                // For languages without line comments, use block comments
-                if let Some(captures) = LINE_COMMENT_START.captures(line) {
-                    processed_line = format!(
-                        "{}{} {} {}",
-                        &captures[1], block_start, &captures[2], block_end
-                    );
-                } else if let Some(captures) = LINE_COMMENT_END.captures(line) {
-                    processed_line = format!(
-                        "{}{}{} {} {}",
-                        &captures[1], &captures[2], block_start, &captures[3], block_end
-                    );
+            // Process indentation
+            if target_lang.indent_char != "    "
+                && let Some(captures) = RUST_INDENT.captures(&processed_line)
+            {
+                let rust_indent_count = captures[0].len() / 4;
+                let new_indent = target_lang.indent_char.repeat(rust_indent_count);
+                processed_line = processed_line.replacen(&captures[0], &new_indent, 1);
+            }
+
+            processed_lines.push(processed_line);
+        }
+
+        Ok(processed_lines.join("\n"))
+    }
+
+    /// Translate the `//` and `/* */` comments of a line outside string and char literals
+    ///
+    /// Line comments need whitespace or the line start before them, block
+    /// comments a closing `*/` on this or a `following` line. Block comments
+    /// spanning lines are tracked in `in_block_comment`.
+    fn translate_comments(
+        line: &str,
+        following: &str,
+        target_lang: &LanguageInfo,
+        in_block_comment: &mut bool,
+    ) -> String {
+        let block_comment = target_lang.block_comment.as_ref();
+        let mut processed = String::with_capacity(line.len());
+        let mut rest = line;
+
+        while let Some(c) = rest.chars().next() {
+            if *in_block_comment {
+                match rest.strip_prefix("*/") {
+                    Some(after) => {
+                        *in_block_comment = false;
+                        processed.push_str(block_comment.map_or("*/", |(_, end)| end));
+                        rest = after;
+                    }
+                    None => {
+                        processed.push(c);
+                        rest = &rest[c.len_utf8()..];
+                    }
                 }
+                continue;
             }
 
-            // Process indentation
-            if target_lang.indent_char != "    " {
-                if let Some(captures) = RUST_INDENT.captures(&processed_line) {
-                    let rust_indent_count = captures[0].len() / 4;
-                    let new_indent = target_lang.indent_char.repeat(rust_indent_count);
-                    processed_line = processed_line.replacen(&captures[0], &new_indent, 1);
+            if let Some(len) = Self::literal_len(rest) {
+                processed.push_str(&rest[..len]);
+                rest = &rest[len..];
+                continue;
+            }
+
+            if let Some(text) = rest.strip_prefix("//") {
+                let code = processed.trim_end();
+                let after_whitespace = code.is_empty() || code.len() < processed.len();
+                let text = text.trim_start();
+                match (&target_lang.line_comment, block_comment) {
+                    (Some(line_comment), _) if after_whitespace => {
+                        processed.push_str(&format!("{} {}", line_comment, text));
+                    }
+                    (None, Some((start, end))) if after_whitespace => {
+                        processed.push_str(&format!("{} {} {}", start, text, end));
+                    }
+                    _ => processed.push_str(rest),
                 }
+                return processed;
             }
 
-            processed_lines.push(processed_line);
+            if let Some(after) = rest.strip_prefix("/*")
+                && (after.contains("*/") || following.contains("*/"))
+            {
+                *in_block_comment = true;
+                processed.push_str(block_comment.map_or("/*", |(start, _)| start));
+                rest = after;
+                continue;
+            }
+
+            processed.push(c);
+            rest = &rest[c.len_utf8()..];
         }
 
-        Ok(processed_lines.join("\n"))
+        processed
     }
 
+    /// Byte length of the string or char literal `text` starts with, None if it starts with none
+    ///
+    /// Literals end at their closing quote or the end of the line. A `'` only
+    /// starts char literals like `'x'` or `'\n'`, lifetimes and apostrophes are code.
+    fn literal_len(text: &str) -> Option<usize> {
+        let mut chars = text.char_indices();
+        let quote = match chars.next()? {
+            (_, '"') => '"',
+            (_, '\'') => {
+                let mut ahead = text[1..].chars();
+                match (ahead.next(), ahead.next()) {
+                    (Some('\\'), _) | (Some(_), Some('\'')) => '\'',
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+
+        let mut escaped = false;
+        for (i, c) in chars {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == quote {
+                return Some(i + 1);
+            }
+        }
+        Some(text.len())
+    }
 }
 
 #[cfg(test)]
@@ -147,12 +571,21 @@ mod tests {
             "Test Universal Snippet".to_string(),
             "// This is a test".to_string(),
             "Test description".to_string(),
-            vec!["universal".to_string(), "_snip_".to_string()],
+            vec![
+                "universal".to_string(),
+                "rust-style".to_string(),
+                "_snip_".to_string(),
+            ],
         );
         let uri = Url::parse("file:///test.py").expect("parse URI");
 
         // Act
-        let result = LanguageTranslator::translate_snippet(&snippet, "python", &uri);
+        let result = LanguageTranslator::translate_snippet(
+            &snippet,
+            "python",
+            &ContextVariables::new(&uri, "python", &LanguageRegistry::default()),
+            &TranslationPolicy::default(),
+        );
 
         // Assert
         assert!(result.is_ok());
@@ -160,6 +593,40 @@ mod tests {
         assert!(translated.contains("# This is a test"));
     }
 
+    #[test]
+    fn given_untagged_universal_snippet_when_translating_then_keeps_comments_unless_legacy() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let snippet = Snippet::new(
+            1,
+            "Test Universal Snippet".to_string(),
+            "// This is a test".to_string(),
+            "Test description".to_string(),
+            vec!["universal".to_string(), "_snip_".to_string()],
+        );
+        let uri = Url::parse("file:///test.py").expect("parse URI");
+        let variables = ContextVariables::new(&uri, "python", &registry);
+        let legacy = TranslationPolicy {
+            legacy: true,
+            ..TranslationPolicy::default()
+        };
+
+        // Act
+        let tagged_only = LanguageTranslator::translate_snippet(
+            &snippet,
+            "python",
+            &variables,
+            &TranslationPolicy::default(),
+        )
+        .expect("valid translation result");
+        let legacy = LanguageTranslator::translate_snippet(&snippet, "python", &variables, &legacy)
+            .expect("valid translation result");
+
+        // Assert
+        assert_eq!(tagged_only, "// This is a test");
+        assert_eq!(legacy, "# This is a test");
+    }
+
     #[test]
     fn given_regular_snippet_when_translating_then_returns_as_is() {
         // Arrange
@@ -173,7 +640,12 @@ mod tests {
         let uri = Url::parse("file:///test.py").expect("parse URI");
 
         // Act
-        let result = LanguageTranslator::translate_snippet(&snippet, "python", &uri);
+        let result = LanguageTranslator::translate_snippet(
+            &snippet,
+            "python",
+            &ContextVariables::new(&uri, "python", &LanguageRegistry::default()),
+            &TranslationPolicy::default(),
+        );
 
         // Assert
         assert!(result.is_ok());
@@ -184,13 +656,16 @@ mod tests {
     #[test]
     fn given_rust_line_comments_when_translating_to_python_then_converts_correctly() {
         // Arrange
-        let uri = Url::parse("file:///test.py").expect("parse URI");
         let rust_content = r#"// This is a line comment
     // Indented comment
 let x = 5; // End of line comment"#;
 
         // Act
-        let result = LanguageTranslator::translate_rust_patterns(rust_content, "python", &uri);
+        let result = LanguageTranslator::translate_rust_patterns(
+            rust_content,
+            "python",
+            &LanguageRegistry::default(),
+        );
 
         // Assert
         assert!(result.is_ok());
@@ -203,7 +678,6 @@ let x = 5; // End of line comment"#;
     #[test]
     fn given_rust_block_comments_when_translating_to_python_then_converts_correctly() {
         // Arrange
-        let uri = Url::parse("file:///test.py").expect("parse URI");
         let rust_content = r#"/* This is a block comment */
 /*
 Multi-line
@@ -211,7 +685,11 @@ block comment
 */"#;
 
         // Act
-        let result = LanguageTranslator::translate_rust_patterns(rust_content, "python", &uri);
+        let result = LanguageTranslator::translate_rust_patterns(
+            rust_content,
+            "python",
+            &LanguageRegistry::default(),
+        );
 
         // Assert
         assert!(result.is_ok());
@@ -220,10 +698,76 @@ block comment
         assert!(python_result.contains("\"\"\"\nMulti-line\nblock comment\n\"\"\""));
     }
 
+    #[test]
+    fn given_comment_markers_in_literals_when_translating_to_python_then_keeps_them() {
+        // Arrange
+        let rust_content = r#"let url = "https://example.com/a//b"; // home page
+let glob = "src/**/*.rs";
+let open = "/*"; let slash = '/'; let quote = '\''; /* real */
+fn name<'a>(s: &'a str) -> &'a str { s } // lifetime"#;
+
+        // Act
+        let result = LanguageTranslator::translate_rust_patterns(
+            rust_content,
+            "python",
+            &LanguageRegistry::default(),
+        )
+        .expect("Python translation result");
+
+        // Assert
+        assert_eq!(
+            result,
+            r#"let url = "https://example.com/a//b"; # home page
+let glob = "src/**/*.rs";
+let open = "/*"; let slash = '/'; let quote = '\''; """ real """
+fn name<'a>(s: &'a str) -> &'a str { s } # lifetime"#
+        );
+    }
+
+    #[test]
+    fn given_comment_markers_in_literals_when_translating_to_html_then_keeps_them() {
+        // Arrange
+        let rust_content = "<a href=\"https://example.com\">home</a> // link\n\
+                            /* see http://example.com\n   and *.rs files */\n\
+                            let s = \"/* not a comment */\";";
+
+        // Act
+        let result = LanguageTranslator::translate_rust_patterns(
+            rust_content,
+            "html",
+            &LanguageRegistry::default(),
+        )
+        .expect("HTML translation result");
+
+        // Assert
+        assert_eq!(
+            result,
+            "<a href=\"https://example.com\">home</a> <!-- link -->\n\
+             <!-- see http://example.com\n   and *.rs files -->\n\
+             let s = \"/* not a comment */\";"
+        );
+    }
+
+    #[test]
+    fn given_comment_markers_without_whitespace_or_closing_when_translating_then_keeps_them() {
+        // Arrange
+        let rust_content = "see https://example.com\nlet x = 1;// tight\n/* unclosed";
+
+        // Act
+        let result = LanguageTranslator::translate_rust_patterns(
+            rust_content,
+            "python",
+            &LanguageRegistry::default(),
+        )
+        .expect("Python translation result");
+
+        // Assert
+        assert_eq!(result, rust_content);
+    }
+
     #[test]
     fn given_rust_indentation_when_translating_to_go_then_converts_to_tabs() {
         // Arrange
-        let uri = Url::parse("file:///test.go").expect("parse URI");
         let rust_content = r#"fn example() {
     let x = 5;
         let y = 10;
@@ -231,7 +775,11 @@ block comment
 }"#;
 
         // Act
-        let result = LanguageTranslator::translate_rust_patterns(rust_content, "go", &uri);
+        let result = LanguageTranslator::translate_rust_patterns(
+            rust_content,
+            "go",
+            &LanguageRegistry::default(),
+        );
 
         // Assert
         assert!(result.is_ok());
@@ -243,18 +791,474 @@ block comment
     }
 
     #[test]
-    fn given_filename_template_when_translating_then_replaces_correctly() {
+    fn given_document_indent_unit_when_translating_then_overrides_language_indentation() {
+        // Arrange: the registry indents JavaScript by 2 spaces
+        let snippet = Snippet::new(
+            1,
+            "Function".to_string(),
+            "function f() {\n    if (x) {\n        return 1;\n    }\n}".to_string(),
+            String::new(),
+            vec![
+                "universal".to_string(),
+                "rust-style".to_string(),
+                "_snip_".to_string(),
+            ],
+        );
+        let uri = Url::parse("file:///app.js").expect("parse URI");
+        let policy = TranslationPolicy::default();
+
+        // Act
+        let registry = LanguageTranslator::translate_snippet(
+            &snippet,
+            "javascript",
+            &ContextVariables::new(&uri, "javascript", &LanguageRegistry::default()),
+            &policy,
+        )
+        .expect("translate with registry indentation");
+        let tabs = LanguageTranslator::translate_snippet(
+            &snippet,
+            "javascript",
+            &ContextVariables::new(&uri, "javascript", &LanguageRegistry::default())
+                .with_indent_unit(Some(IndentUnit::Tabs)),
+            &policy,
+        )
+        .expect("translate with tabs");
+        let four = LanguageTranslator::translate_rust_patterns_with_indent(
+            &snippet.content,
+            "javascript",
+            Some(IndentUnit::Spaces(4)),
+            &LanguageRegistry::default(),
+        )
+        .expect("translate with 4 spaces");
+
+        // Assert
+        assert_eq!(
+            registry,
+            "function f() {\n  if (x) {\n    return 1;\n  }\n}"
+        );
+        assert_eq!(tabs, "function f() {\n\tif (x) {\n\t\treturn 1;\n\t}\n}");
+        assert_eq!(four, snippet.content);
+    }
+
+    fn variables<'a>(uri: &'a Url, registry: &'a LanguageRegistry) -> ContextVariables<'a> {
+        ContextVariables {
+            now: time::Date::from_calendar_date(2026, time::Month::March, 7)
+                .and_then(|date| date.with_hms(9, 5, 0))
+                .expect("valid test date")
+                .assume_utc(),
+            ..ContextVariables::new(uri, "rust", registry)
+        }
+    }
+
+    #[test]
+    fn given_filename_variable_when_expanding_then_uses_decoded_file_name() {
         // Arrange
-        let uri = Url::parse("file:///path/to/example.rs").expect("parse URI");
-        let content = "// File: {{ filename }}";
+        let registry = LanguageRegistry::default();
+        let uri = Url::parse("file:///C:/Users/John%20Doe/my%20script.py").expect("parse URI");
 
         // Act
-        let result = LanguageTranslator::translate_rust_patterns(content, "rust", &uri);
+        let expanded = LanguageTranslator::expand_context_variables(
+            "# File: {{ filename }}",
+            &variables(&uri, &registry),
+            false,
+        );
 
         // Assert
-        assert!(result.is_ok());
-        let translated = result.expect("valid translation result");
-        assert!(translated.contains("// File: example.rs"));
+        assert_eq!(expanded, "# File: my script.py");
+    }
+
+    #[test]
+    fn given_untitled_python_buffer_when_expanding_filename_then_synthesizes_name() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let uri = Url::parse("untitled:Untitled-1").expect("parse URI");
+        let variables = ContextVariables::new(&uri, "python", &registry);
+
+        // Act
+        let expanded = LanguageTranslator::expand_context_variables(
+            "# {{ filename }} at {{ filepath }}",
+            &variables,
+            false,
+        );
+
+        // Assert
+        assert_eq!(expanded, "# untitled-1.py at untitled-1.py");
+    }
+
+    #[test]
+    fn given_path_variables_when_expanding_then_paths_are_workspace_relative() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let uri = Url::parse("file:///work/app/src/net/client.rs").expect("parse URI");
+        let folders = [Url::parse("file:///work/app").expect("parse URI")];
+        let root_uri = Url::parse("file:///work/app/main.rs").expect("parse URI");
+        let content = "{{ filepath }} in {{dirname}}";
+
+        // Act
+        let nested = LanguageTranslator::expand_context_variables(
+            content,
+            &variables(&uri, &registry).with_workspace_folders(&folders, PathSeparator::Slash),
+            false,
+        );
+        let at_root = LanguageTranslator::expand_context_variables(
+            content,
+            &variables(&root_uri, &registry).with_workspace_folders(&folders, PathSeparator::Slash),
+            false,
+        );
+
+        // Assert
+        assert_eq!(nested, "src/net/client.rs in src/net");
+        assert_eq!(at_root, "main.rs in .");
     }
 
+    #[test]
+    fn given_date_variables_when_expanding_then_applies_strftime_formats() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let uri = Url::parse("file:///notes.md").expect("parse URI");
+
+        // Act
+        let expanded = LanguageTranslator::expand_context_variables(
+            "{{ date }} | {{ date:%d.%m.%Y %H:%M }} | {{ date:%Q }}",
+            &variables(&uri, &registry),
+            false,
+        );
+
+        // Assert
+        assert_eq!(expanded, "2026-03-07 | 07.03.2026 09:05 | {{ date:%Q }}");
+    }
+
+    #[test]
+    fn given_language_and_author_variables_when_expanding_then_uses_context() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let uri = Url::parse("file:///lib.rs").expect("parse URI");
+
+        // Act
+        let with_author = LanguageTranslator::expand_context_variables(
+            "// {{ language }} by {{ author }}",
+            &variables(&uri, &registry).with_author(Some("Jane Doe")),
+            false,
+        );
+        let without_author = LanguageTranslator::expand_context_variables(
+            "// {{ author }}",
+            &variables(&uri, &registry),
+            false,
+        );
+
+        // Assert
+        assert_eq!(with_author, "// rust by Jane Doe");
+        assert_eq!(without_author, "// {{ author }}");
+    }
+
+    #[test]
+    fn given_unknown_variables_when_expanding_then_leaves_them_untouched() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let uri = Url::parse("file:///lib.rs").expect("parse URI");
+        let content = "{{ cursor }} {{ current_date }} {{ filenames }} {{ env('HOME') }}";
+
+        // Act
+        let expanded = LanguageTranslator::expand_context_variables(
+            content,
+            &variables(&uri, &registry),
+            false,
+        );
+
+        // Assert
+        assert_eq!(expanded, content);
+    }
+
+    #[test]
+    fn given_snippet_mixing_variables_when_translating_then_expands_and_escapes_values() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let snippet = Snippet::new(
+            1,
+            "Header".to_string(),
+            "// {{ filename }} ({{ language }}), {{ date:%Y }} by {{ author }}\n$0".to_string(),
+            "File header".to_string(),
+            vec![
+                "universal".to_string(),
+                "rust-style".to_string(),
+                "_snip_".to_string(),
+            ],
+        );
+        let uri = Url::parse("file:///src/price$%7Bdollar%7D.py").expect("parse URI");
+        let variables = ContextVariables {
+            language_id: "python",
+            ..variables(&uri, &registry).with_author(Some("Jane Doe"))
+        };
+
+        // Act
+        let translated = LanguageTranslator::translate_snippet(
+            &snippet,
+            "python",
+            &variables,
+            &TranslationPolicy::default(),
+        )
+        .expect("valid translation result");
+
+        // Assert
+        assert_eq!(
+            translated,
+            "# price\\${dollar\\}.py (python), 2026 by Jane Doe\n$0"
+        );
+    }
+
+    #[test]
+    fn given_cursor_placeholder_when_converting_then_becomes_final_tabstop() {
+        // Act
+        let result =
+            LanguageTranslator::convert_bkmr_placeholders("fn main() {\n    {{ cursor }}\n}");
+
+        // Assert
+        assert_eq!(result, "fn main() {\n    $0\n}");
+    }
+
+    #[test]
+    fn given_tabstop_placeholders_when_converting_then_become_numbered_tabstops() {
+        // Act
+        let result =
+            LanguageTranslator::convert_bkmr_placeholders("{{ tabstop1 }} = {{tabstop2}};");
+
+        // Assert
+        assert_eq!(result, "$1 = $2;");
+    }
+
+    #[test]
+    fn given_selection_placeholder_when_converting_then_uses_selected_text_variable() {
+        // Act
+        let result = LanguageTranslator::convert_bkmr_placeholders("<b>{{ selection }}</b>");
+
+        // Assert
+        assert_eq!(result, "<b>${1:$TM_SELECTED_TEXT}</b>");
+    }
+
+    #[test]
+    fn given_selection_after_tabstops_when_converting_then_numbers_selection_after_highest() {
+        // Act
+        let result = LanguageTranslator::convert_bkmr_placeholders(
+            "${1:name} {{ tabstop2 }} ${3|a,b|} {{ selection }} {{selection}}",
+        );
+
+        // Assert
+        assert_eq!(
+            result,
+            "${1:name} $2 ${3|a,b|} ${4:$TM_SELECTED_TEXT} ${4:$TM_SELECTED_TEXT}"
+        );
+    }
+
+    #[test]
+    fn given_cursor_and_final_tabstop_when_converting_then_drops_cursor() {
+        // Act
+        let with_final = LanguageTranslator::convert_bkmr_placeholders("{{ cursor }}\nreturn $0");
+        let escaped_final = LanguageTranslator::convert_bkmr_placeholders("echo \\$0 {{ cursor }}");
+
+        // Assert
+        assert_eq!(with_final, "\nreturn $0");
+        assert_eq!(escaped_final, "echo \\$0 $0");
+    }
+
+    #[test]
+    fn given_unknown_or_nested_blocks_when_converting_then_converts_only_known_placeholders() {
+        // Act
+        let unknown =
+            LanguageTranslator::convert_bkmr_placeholders("{{ clipboard }} {{ env.HOME }}");
+        let nested = LanguageTranslator::convert_bkmr_placeholders("{{ outer {{ cursor }} }}");
+        let braced = LanguageTranslator::convert_bkmr_placeholders("{{{ cursor }}}");
+
+        // Assert
+        assert_eq!(unknown, "{{ clipboard }} {{ env.HOME }}");
+        assert_eq!(nested, "{{ outer $0 }}");
+        assert_eq!(braced, "{$0}");
+    }
+
+    #[test]
+    fn given_interpolated_content_when_converting_then_returns_unchanged() {
+        // Arrange
+        let content = "// Generated on 2024-01-31 by alice\nlet x = ${1:value};";
+
+        // Act
+        let result = LanguageTranslator::convert_bkmr_placeholders(content);
+
+        // Assert
+        assert_eq!(result, content);
+    }
+
+    #[test]
+    fn given_choice_marker_when_converting_then_becomes_first_choice_placeholder() {
+        // Act
+        let result = LanguageTranslator::convert_variant_markers(
+            "log.%%choice:info, warn,error%%(\"%%input:message%%\");$0",
+        );
+
+        // Assert
+        assert_eq!(result, "log.${1|info,warn,error|}(\"${2:message}\");$0");
+    }
+
+    #[test]
+    fn given_existing_tabstops_when_converting_markers_then_continues_after_highest() {
+        // Act
+        let after_tabstop =
+            LanguageTranslator::convert_variant_markers("$1 ${2:x} %%choice:a,b%% %%input:%%");
+        let after_placeholder = LanguageTranslator::convert_variant_markers(
+            "%%input:name%% = ${5|y,n|}; {{ tabstop7 }} %%choice:c%%",
+        );
+        let escaped_dollar =
+            LanguageTranslator::convert_variant_markers("\\$9 costs %%input:amount%%");
+
+        // Assert
+        assert_eq!(after_tabstop, "$1 ${2:x} ${3|a,b|} ${4}");
+        assert_eq!(
+            after_placeholder,
+            "${8:name} = ${5|y,n|}; {{ tabstop7 }} ${9|c|}"
+        );
+        assert_eq!(escaped_dollar, "\\$9 costs ${1:amount}");
+    }
+
+    #[test]
+    fn given_option_with_snippet_syntax_when_converting_then_escapes_it() {
+        // Act
+        let result =
+            LanguageTranslator::convert_variant_markers("%%choice:$HOME,a|b%% %%input:${x}%%");
+
+        // Assert
+        assert_eq!(result, "${1|\\$HOME,a\\|b|} ${2:\\${x\\}}");
+    }
+
+    #[test]
+    fn given_malformed_markers_when_converting_then_keeps_them() {
+        // Arrange
+        let content = "%%choice:%% %%choice: , %% %%choice:a,b\n%%input:open";
+
+        // Act
+        let result = LanguageTranslator::convert_variant_markers(content);
+
+        // Assert
+        assert_eq!(result, content);
+    }
+
+    #[test]
+    fn given_choice_marker_in_bash_snippet_when_escaping_then_placeholder_stays_intact() {
+        // Arrange
+        let converted = LanguageTranslator::convert_variant_markers("echo \"$1\" >&%%choice:1,2%%");
+
+        // Act
+        let result = escaped(&converted, "bash");
+
+        // Assert
+        assert_eq!(result, "echo \"\\$1\" >&${2|1,2|}");
+    }
+
+    #[test]
+    fn given_snippet_with_markers_when_translating_then_converts_unless_plain() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let uri = Url::parse("file:///project/log.rs").expect("parse URI");
+        let variables = ContextVariables::new(&uri, "rust", &registry);
+        let snippet = Snippet::new(
+            1,
+            "log".to_string(),
+            "log::%%choice:info,warn%%!(\"{{ filename }}\")".to_string(),
+            String::new(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        );
+        let mut plain = snippet.clone();
+        plain.tags.push("plain".to_string());
+
+        // Act
+        let translated = LanguageTranslator::translate_snippet(
+            &snippet,
+            "rust",
+            &variables,
+            &TranslationPolicy::default(),
+        )
+        .expect("translate snippet");
+        let untouched = LanguageTranslator::translate_snippet(
+            &plain,
+            "rust",
+            &variables,
+            &TranslationPolicy::default(),
+        )
+        .expect("translate plain snippet");
+
+        // Assert
+        assert_eq!(translated, "log::${1|info,warn|}!(\"log.rs\")");
+        assert_eq!(untouched, "log::%%choice:info,warn%%!(\"log.rs\")");
+    }
+
+    #[test]
+    fn given_environment_variables_when_resolving_then_substitutes_set_and_marks_unset_ones() {
+        // Arrange
+        let content =
+            "cd $HOME/${PROJECT} && echo $MISSING ${1:name} $0 $TM_FILENAME ${USER:bob} \\$PATH";
+        let lookup = |name: &str| match name {
+            "HOME" => Some("/Users/me".to_string()),
+            "PROJECT" => Some("bkmr".to_string()),
+            _ => None,
+        };
+
+        // Act
+        let resolved = LanguageTranslator::resolve_environment_variables(content, lookup);
+
+        // Assert
+        assert_eq!(
+            resolved,
+            "cd /Users/me/bkmr && echo (unset) ${1:name} $0 $TM_FILENAME ${USER:bob} \\$PATH"
+        );
+    }
+
+    fn escaped(content: &str, language_id: &str) -> String {
+        let defaults: Vec<String> = crate::domain::DEFAULT_ESCAPE_ALL_DOLLARS_FOR
+            .iter()
+            .map(|language| language.to_string())
+            .collect();
+        LanguageTranslator::escape_literal_dollars(
+            content,
+            language_id,
+            &defaults,
+            &LanguageRegistry::default(),
+        )
+    }
+
+    #[test]
+    fn given_makefile_snippet_when_escaping_then_substitutions_stay_literal() {
+        // Arrange
+        let content = "build:\n\t$(CC) $(CFLAGS) -o $@ $^";
+
+        // Act
+        let result = escaped(content, "makefile");
+
+        // Assert
+        assert_eq!(result, "build:\n\t\\$(CC) \\$(CFLAGS) -o $@ $^");
+    }
+
+    #[test]
+    fn given_awk_snippet_when_escaping_then_field_references_stay_literal() {
+        // Act
+        let result = escaped("{ print $1, $NF; total += $10 }", "awk");
+
+        // Assert
+        assert_eq!(result, "{ print \\$1, $NF; total += \\$10 }");
+    }
+
+    #[test]
+    fn given_bash_snippet_when_escaping_then_keeps_placeholders_and_escapes_arguments() {
+        // Arrange
+        let content = r#"cut -d, -f1 "$1" > ${1:output}.csv; echo $(date) \$2"#;
+
+        // Act
+        let bash = escaped(content, "bash");
+        let shellscript = escaped(content, "shellscript");
+        let rust = escaped(content, "rust");
+
+        // Assert
+        assert_eq!(
+            bash,
+            r#"cut -d, -f1 "\$1" > ${1:output}.csv; echo \$(date) \$2"#
+        );
+        assert_eq!(shellscript, bash);
+        assert_eq!(rust, content);
+    }
 }
@@ -0,0 +1,347 @@
+use anyhow::{Context, Result, anyhow};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tower_lsp::lsp_types::{DocumentLink, Position, Range, Url};
+use tracing::{debug, instrument};
+
+use crate::domain::PositionEncoding;
+use crate::repositories::SnippetRepository;
+use crate::services::DocumentService;
+use crate::sync;
+
+/// Documents larger than this are not scanned for bkmr markers
+pub const MAX_LINK_SCAN_BYTES: usize = 512 * 1024;
+
+lazy_static! {
+    /// `bkmr:<id>` referencing a bookmark or snippet, e.g. in a comment
+    static ref BKMR_MARKER: Regex = Regex::new(r"\bbkmr:(\d+)\b").expect("bkmr marker regex");
+}
+
+/// A `bkmr:<id>` marker found in a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BkmrMarker {
+    pub id: i32,
+    pub range: Range,
+}
+
+/// Links of one document, valid for the version they were scanned at
+#[derive(Debug, Clone)]
+struct LinkScan {
+    version: i32,
+    links: Vec<DocumentLink>,
+}
+
+/// Service turning `bkmr:<id>` markers in open documents into document links
+///
+/// Links are returned without target, which documentLink/resolve fills in
+/// with the URL of the bkmr entry. Scans are cached per document version.
+pub struct LinkService {
+    repository: Arc<dyn SnippetRepository>,
+    document_service: Arc<DocumentService>,
+    max_bytes: usize,
+    scans: Mutex<HashMap<String, LinkScan>>,
+}
+
+impl std::fmt::Debug for LinkService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LinkService")
+            .field("repository", &"<SnippetRepository>")
+            .field("max_bytes", &self.max_bytes)
+            .finish()
+    }
+}
+
+impl LinkService {
+    pub fn new(
+        repository: Arc<dyn SnippetRepository>,
+        document_service: Arc<DocumentService>,
+    ) -> Self {
+        Self {
+            repository,
+            document_service,
+            max_bytes: MAX_LINK_SCAN_BYTES,
+            scans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Markers in the text with ranges in the given position encoding
+    pub fn scan_markers(text: &str, encoding: PositionEncoding) -> Vec<BkmrMarker> {
+        text.lines()
+            .enumerate()
+            .flat_map(|(line_number, line)| {
+                BKMR_MARKER.captures_iter(line).filter_map(move |captures| {
+                    let marker = captures.get(0)?;
+                    let id = captures[1].parse().ok()?;
+                    let line_number = line_number as u32;
+                    Some(BkmrMarker {
+                        id,
+                        range: Range::new(
+                            Position::new(line_number, encoding.character(line, marker.start())),
+                            Position::new(line_number, encoding.character(line, marker.end())),
+                        ),
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// Unresolved links for the markers in an open document
+    ///
+    /// Documents that are not open or over the size limit have no links.
+    #[instrument(skip(self))]
+    pub async fn document_links(&self, uri: &Url) -> Vec<DocumentLink> {
+        let version = self
+            .document_service
+            .document_version(uri.as_str())
+            .await
+            .map(|version| version.version);
+        if let Some(version) = version
+            && let Some(scan) = sync::lock(&self.scans, "link scans").get(uri.as_str())
+            && scan.version == version
+        {
+            debug!("Reusing links of {} version {}", uri, version);
+            return scan.links.clone();
+        }
+
+        let Some(text) = self
+            .document_service
+            .get_document_content(uri.as_str())
+            .await
+        else {
+            return Vec::new();
+        };
+        if text.len() > self.max_bytes {
+            debug!(
+                "Not scanning {} for bkmr links, {} bytes is over the limit",
+                uri,
+                text.len()
+            );
+            return Vec::new();
+        }
+
+        let encoding = self.document_service.position_encoding();
+        // Ids of different databases overlap, resolving needs the one of the document
+        let folder = self.repository.route(uri);
+        let links: Vec<DocumentLink> = Self::scan_markers(&text, encoding)
+            .into_iter()
+            .map(|marker| {
+                let mut data = serde_json::json!({ "id": marker.id });
+                if let Some(folder) = &folder {
+                    data["folder"] = serde_json::json!(folder);
+                }
+                DocumentLink {
+                    range: marker.range,
+                    target: None,
+                    tooltip: Some(format!("Open bkmr entry {}", marker.id)),
+                    data: Some(data),
+                }
+            })
+            .collect();
+        if let Some(version) = version {
+            sync::lock(&self.scans, "link scans").insert(
+                uri.to_string(),
+                LinkScan {
+                    version,
+                    links: links.clone(),
+                },
+            );
+        }
+        links
+    }
+
+    /// Fill in the target of a link with the URL of its bkmr entry
+    ///
+    /// Entries whose content is not a URL, like most snippets, keep no target
+    /// and get their title as tooltip.
+    #[instrument(skip(self))]
+    pub async fn resolve(&self, mut link: DocumentLink) -> Result<DocumentLink> {
+        let id = link
+            .data
+            .as_ref()
+            .and_then(|data| data.get("id"))
+            .and_then(|id| id.as_i64())
+            .and_then(|id| i32::try_from(id).ok())
+            .ok_or_else(|| anyhow!("document link data has no bkmr id"))?;
+        let repository = link
+            .data
+            .as_ref()
+            .and_then(|data| data.get("folder"))
+            .and_then(|folder| folder.as_str())
+            .and_then(|folder| self.repository.folder_repository(folder))
+            .unwrap_or_else(|| self.repository.clone());
+        let entry = repository
+            .fetch_snippet_by_id(id)
+            .await
+            .context("fetch linked bkmr entry")?;
+
+        match entry {
+            Some(entry) => match Url::parse(entry.content.trim()) {
+                Ok(url) if !url.cannot_be_a_base() => {
+                    link.tooltip = Some(entry.title);
+                    link.target = Some(url);
+                }
+                _ => link.tooltip = Some(format!("{} (bkmr {}, not a URL)", entry.title, id)),
+            },
+            None => link.tooltip = Some(format!("No bkmr entry {}", id)),
+        }
+        Ok(link)
+    }
+
+    /// Drop the cached scan of a closed document
+    pub fn forget(&self, uri: &str) {
+        sync::lock(&self.scans, "link scans").remove(uri);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::Snippet;
+    use crate::repositories::MockSnippetRepository;
+    use tower_lsp::lsp_types::TextDocumentContentChangeEvent;
+
+    async fn service_with_document(
+        text: &str,
+        entries: Vec<Snippet>,
+    ) -> (LinkService, Arc<DocumentService>, Url) {
+        let uri = Url::parse("file:///notes.rs").expect("parse URI");
+        let document_service = Arc::new(DocumentService::new());
+        document_service
+            .open_versioned_document(uri.to_string(), "rust".to_string(), text.to_string(), 1)
+            .await
+            .expect("open document");
+        let repository = Arc::new(MockSnippetRepository::new().with_snippets(entries));
+        let service = LinkService::new(repository, document_service.clone());
+        (service, document_service, uri)
+    }
+
+    #[test]
+    fn given_several_markers_on_a_line_when_scanning_then_finds_each() {
+        // Arrange
+        let text = "// see bkmr:12 and bkmr:7\nlet x = 1; // xbkmr:3 bkmr:abc\n/* bkmr:42 */";
+
+        // Act
+        let markers = LinkService::scan_markers(text, PositionEncoding::Utf16);
+
+        // Assert
+        let found: Vec<(i32, Range)> = markers
+            .iter()
+            .map(|marker| (marker.id, marker.range))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (12, Range::new(Position::new(0, 7), Position::new(0, 14))),
+                (7, Range::new(Position::new(0, 19), Position::new(0, 25))),
+                (42, Range::new(Position::new(2, 3), Position::new(2, 10))),
+            ]
+        );
+    }
+
+    #[test]
+    fn given_wide_characters_before_marker_when_scanning_then_counts_in_encoding() {
+        // Act
+        let utf16 = LinkService::scan_markers("// 🔖 bkmr:5", PositionEncoding::Utf16);
+        let utf8 = LinkService::scan_markers("// 🔖 bkmr:5", PositionEncoding::Utf8);
+
+        // Assert
+        assert_eq!(utf16[0].range.start, Position::new(0, 6));
+        assert_eq!(utf8[0].range.start, Position::new(0, 8));
+    }
+
+    #[tokio::test]
+    async fn given_bookmark_link_when_resolving_then_fills_in_its_url() {
+        // Arrange
+        let bookmark = Snippet::new(
+            12,
+            "Rust book".to_string(),
+            "https://doc.rust-lang.org/book/".to_string(),
+            String::new(),
+            vec!["rust".to_string()],
+        );
+        let snippet = Snippet::new(
+            7,
+            "Hello".to_string(),
+            "println!(\"hi\");".to_string(),
+            String::new(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        );
+        let (service, _, uri) =
+            service_with_document("// bkmr:12 bkmr:7 bkmr:99", vec![bookmark, snippet]).await;
+        let links = service.document_links(&uri).await;
+
+        // Act
+        let mut resolved = Vec::new();
+        for link in links {
+            resolved.push(service.resolve(link).await.expect("resolve link"));
+        }
+
+        // Assert
+        assert_eq!(
+            resolved[0].target.as_ref().map(Url::as_str),
+            Some("https://doc.rust-lang.org/book/")
+        );
+        assert_eq!(resolved[0].tooltip.as_deref(), Some("Rust book"));
+        assert_eq!(resolved[1].target, None);
+        assert_eq!(resolved[2].tooltip.as_deref(), Some("No bkmr entry 99"));
+    }
+
+    #[tokio::test]
+    async fn given_changed_document_when_getting_links_then_rescans_new_version() {
+        // Arrange
+        let (service, document_service, uri) = service_with_document("// bkmr:1", Vec::new()).await;
+        let first = service.document_links(&uri).await;
+        document_service
+            .update_document(
+                uri.to_string(),
+                vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: "// bkmr:1 bkmr:2".to_string(),
+                }],
+            )
+            .await
+            .expect("update document");
+
+        // Act
+        let same_version = service.document_links(&uri).await;
+        document_service
+            .change_document(
+                uri.to_string(),
+                2,
+                vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: "// bkmr:1 bkmr:2".to_string(),
+                }],
+            )
+            .await
+            .expect("change document");
+        let new_version = service.document_links(&uri).await;
+
+        // Assert
+        assert_eq!(first.len(), 1);
+        assert_eq!(same_version.len(), 1);
+        assert_eq!(new_version.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn given_document_over_size_limit_when_getting_links_then_returns_none() {
+        // Arrange
+        let (service, _, uri) = service_with_document("// bkmr:1 and more", Vec::new()).await;
+        let service = service.with_max_bytes(8);
+
+        // Act
+        let links = service.document_links(&uri).await;
+
+        // Assert
+        assert!(links.is_empty());
+    }
+}
@@ -1,9 +1,27 @@
 pub mod command_service;
+pub mod completion_capabilities;
 pub mod completion_service;
 pub mod document_service;
+pub mod hover_service;
+pub mod ignore_service;
 pub mod language_translator;
+pub mod link_service;
+pub mod prefetch_service;
+pub mod progress_service;
+pub mod query_service;
+pub mod snippet_presenter;
+pub mod symbol_service;
 
 pub use command_service::*;
+pub use completion_capabilities::*;
 pub use completion_service::*;
 pub use document_service::*;
+pub use hover_service::*;
+pub use ignore_service::*;
 pub use language_translator::*;
+pub use link_service::*;
+pub use prefetch_service::*;
+pub use progress_service::*;
+pub use query_service::*;
+pub use snippet_presenter::*;
+pub use symbol_service::*;
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::domain::SnippetFilter;
+use crate::repositories::CachingSnippetRepository;
+use crate::sync;
+
+/// Minimum time between two prefetches of the same filter
+const PREFETCH_DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// Service warming the snippet cache in the background, once per filter at a time
+pub struct PrefetchService {
+    cache: Arc<CachingSnippetRepository>,
+    debounce: Duration,
+    /// Start time and task of the latest prefetch per filter
+    tasks: Mutex<HashMap<SnippetFilter, (Instant, JoinHandle<()>)>>,
+}
+
+impl std::fmt::Debug for PrefetchService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrefetchService")
+            .field("debounce", &self.debounce)
+            .finish()
+    }
+}
+
+impl PrefetchService {
+    pub fn new(cache: Arc<CachingSnippetRepository>) -> Self {
+        Self {
+            cache,
+            debounce: PREFETCH_DEBOUNCE,
+            tasks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Override the minimum time between prefetches of one filter
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Refresh the cached results for a filter in a background task
+    ///
+    /// Returns false when a prefetch for the filter is still running or
+    /// started less than the debounce interval ago.
+    pub fn prefetch(&self, filter: SnippetFilter) -> bool {
+        let mut tasks = sync::lock(&self.tasks, "prefetch tasks");
+        if let Some((started_at, task)) = tasks.get(&filter)
+            && (!task.is_finished() || started_at.elapsed() < self.debounce)
+        {
+            debug!("Skipping prefetch for {:?}: debounced", filter.language_id);
+            return false;
+        }
+
+        debug!(
+            "Prefetching snippets for {:?} (folder: {:?})",
+            filter.language_id, filter.folder
+        );
+        let cache = Arc::clone(&self.cache);
+        let key = filter.clone();
+        let task = tokio::spawn(async move {
+            if let Err(e) = cache.refresh(&filter).await {
+                warn!("Prefetch for {:?} failed: {:#}", filter.language_id, e);
+            }
+        });
+        tasks.insert(key, (Instant::now(), task));
+        true
+    }
+
+    /// Cancel all running prefetches
+    pub fn shutdown(&self) {
+        for (filter, (_, task)) in sync::lock(&self.tasks, "prefetch tasks").drain() {
+            if !task.is_finished() {
+                debug!("Cancelling prefetch for {:?}", filter.language_id);
+                task.abort();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::Snippet;
+    use crate::repositories::{CacheConfig, MockSnippetRepository, SnippetRepository};
+
+    fn rust_snippet() -> Snippet {
+        Snippet::new(
+            1,
+            "Hello World".to_string(),
+            "println!(\"Hello, World!\");".to_string(),
+            String::new(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        )
+    }
+
+    fn filter(language_id: &str) -> SnippetFilter {
+        SnippetFilter::new(Some(language_id.to_string()), None, 50)
+    }
+
+    fn prefetch_setup(
+        inner: MockSnippetRepository,
+    ) -> (
+        Arc<MockSnippetRepository>,
+        Arc<CachingSnippetRepository>,
+        PrefetchService,
+    ) {
+        let inner = Arc::new(inner);
+        let cache = Arc::new(CachingSnippetRepository::new(
+            inner.clone(),
+            CacheConfig::default(),
+        ));
+        let service = PrefetchService::new(cache.clone());
+        (inner, cache, service)
+    }
+
+    #[tokio::test]
+    async fn given_many_opens_of_one_language_when_prefetching_then_fetches_once() {
+        // Arrange
+        let (inner, _, service) = prefetch_setup(
+            MockSnippetRepository::new()
+                .with_snippets(vec![rust_snippet()])
+                .with_delay(Duration::from_millis(20)),
+        );
+
+        // Act
+        let spawned = (0..20).filter(|_| service.prefetch(filter("rust"))).count();
+        service.prefetch(filter("python"));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Assert
+        assert_eq!(spawned, 1);
+        assert_eq!(inner.fetch_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn given_prefetched_language_when_fetching_then_serves_from_cache() {
+        // Arrange
+        let (inner, cache, service) =
+            prefetch_setup(MockSnippetRepository::new().with_snippets(vec![rust_snippet()]));
+
+        // Act
+        service.prefetch(filter("rust"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let snippets = cache.fetch_snippets(&filter("rust")).await.expect("fetch");
+
+        // Assert
+        assert_eq!(snippets.len(), 1);
+        assert_eq!(inner.fetch_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn given_elapsed_debounce_when_prefetching_again_then_refreshes_in_background() {
+        // Arrange
+        let (inner, _, service) =
+            prefetch_setup(MockSnippetRepository::new().with_snippets(vec![rust_snippet()]));
+        let service = service.with_debounce(Duration::ZERO);
+
+        // Act
+        service.prefetch(filter("rust"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let refreshed = service.prefetch(filter("rust"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Assert
+        assert!(refreshed);
+        assert_eq!(inner.fetch_count(), 2);
+    }
+}
@@ -0,0 +1,168 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tower_lsp::Client;
+use tower_lsp::lsp_types::notification::Progress;
+use tower_lsp::lsp_types::request::WorkDoneProgressCreate;
+use tower_lsp::lsp_types::{
+    NumberOrString, ProgressParams, ProgressParamsValue, ProgressToken, WorkDoneProgress,
+    WorkDoneProgressBegin, WorkDoneProgressCreateParams, WorkDoneProgressEnd,
+    WorkDoneProgressReport,
+};
+use tracing::{debug, warn};
+
+/// Time work may take before progress is shown
+const PROGRESS_DELAY: Duration = Duration::from_millis(300);
+/// Interval of progress reports while the work goes on
+const PROGRESS_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Work-done progress for slow operations like bkmr searches
+///
+/// Progress only appears once the work takes longer than a short delay, on
+/// the token the client supplied with the request or on one created via
+/// window/workDoneProgress/create. Nothing is sent to clients without the
+/// window.workDoneProgress capability.
+#[derive(Debug, Clone)]
+pub struct ProgressService {
+    client: Client,
+    enabled: Arc<AtomicBool>,
+    next_token: Arc<AtomicU64>,
+    delay: Duration,
+}
+
+impl ProgressService {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            enabled: Arc::new(AtomicBool::new(false)),
+            next_token: Arc::new(AtomicU64::new(1)),
+            delay: PROGRESS_DELAY,
+        }
+    }
+
+    /// Enable progress after the client advertised window.workDoneProgress
+    pub fn set_enabled(&self, enabled: bool) {
+        debug!("Client supports work done progress: {}", enabled);
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Run the work, showing progress with the title if it is slow
+    ///
+    /// The progress ends when the work finishes, whether it succeeded or not.
+    pub async fn run<F: Future>(
+        &self,
+        title: &str,
+        token: Option<ProgressToken>,
+        work: F,
+    ) -> F::Output {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return work.await;
+        }
+
+        tokio::pin!(work);
+        tokio::select! {
+            output = &mut work => return output,
+            _ = tokio::time::sleep(self.delay) => {}
+        }
+
+        let started_at = Instant::now();
+        let create = self.token_or_create(token);
+        tokio::pin!(create);
+        let mut reports = tokio::time::interval(PROGRESS_REPORT_INTERVAL);
+        reports.tick().await;
+        let mut token: Option<Option<ProgressToken>> = None;
+
+        let output = loop {
+            tokio::select! {
+                output = &mut work => break output,
+                created = &mut create, if token.is_none() => {
+                    if let Some(created) = &created {
+                        self.begin(created, title).await;
+                    }
+                    token = Some(created);
+                }
+                _ = reports.tick(), if matches!(token, Some(Some(_))) => {
+                    if let Some(Some(token)) = &token {
+                        let message = format!("{}s", started_at.elapsed().as_secs());
+                        self.report(token, message).await;
+                    }
+                }
+            }
+        };
+
+        // Without a begun progress, e.g. while create is unanswered, there is nothing to end
+        if let Some(Some(token)) = token {
+            self.end(token).await;
+        }
+        output
+    }
+
+    /// The client's token or a newly created one, None if the client refuses to create it
+    async fn token_or_create(&self, token: Option<ProgressToken>) -> Option<ProgressToken> {
+        if token.is_some() {
+            return token;
+        }
+
+        let token = NumberOrString::String(format!(
+            "bkmr-lsp/progress/{}",
+            self.next_token.fetch_add(1, Ordering::Relaxed)
+        ));
+        let params = WorkDoneProgressCreateParams {
+            token: token.clone(),
+        };
+        match self
+            .client
+            .send_request::<WorkDoneProgressCreate>(params)
+            .await
+        {
+            Ok(()) => Some(token),
+            Err(e) => {
+                warn!("Client refused to create progress token: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn begin(&self, token: &ProgressToken, title: &str) {
+        self.notify(
+            token,
+            WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: title.to_string(),
+                cancellable: Some(false),
+                message: None,
+                percentage: None,
+            }),
+        )
+        .await;
+    }
+
+    async fn report(&self, token: &ProgressToken, message: String) {
+        self.notify(
+            token,
+            WorkDoneProgress::Report(WorkDoneProgressReport {
+                cancellable: Some(false),
+                message: Some(message),
+                percentage: None,
+            }),
+        )
+        .await;
+    }
+
+    async fn end(&self, token: ProgressToken) {
+        self.notify(
+            &token,
+            WorkDoneProgress::End(WorkDoneProgressEnd { message: None }),
+        )
+        .await;
+    }
+
+    async fn notify(&self, token: &ProgressToken, progress: WorkDoneProgress) {
+        self.client
+            .send_notification::<Progress>(ProgressParams {
+                token: token.clone(),
+                value: ProgressParamsValue::WorkDone(progress),
+            })
+            .await;
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::sync::Arc;
+use tower_lsp::lsp_types::{CompletionItem, CompletionTextEdit, Position, Url};
+use tracing::instrument;
+
+use crate::backend::BkmrConfig;
+use crate::domain::{LanguageRegistry, PositionEncoding};
+use crate::repositories::SnippetRepository;
+use crate::services::{CompletionService, DocumentService};
+
+/// Document completions of `bkmr-lsp query` are computed in when no URI is given
+pub const DEFAULT_QUERY_URI: &str = "untitled:bkmr-lsp-query";
+
+/// A completion item as printed by `bkmr-lsp query`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryItem {
+    pub label: String,
+    pub detail: Option<String>,
+    /// Text the editor would insert, in snippet syntax unless the snippet is plain
+    pub insert_text: String,
+}
+
+impl QueryItem {
+    fn from_completion_item(item: CompletionItem) -> Self {
+        let insert_text = match item.text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => edit.new_text,
+            Some(CompletionTextEdit::InsertAndReplace(edit)) => edit.new_text,
+            None => item.insert_text.unwrap_or_else(|| item.label.clone()),
+        };
+        Self {
+            label: item.label,
+            detail: item.detail,
+            insert_text,
+        }
+    }
+}
+
+/// Runs a single completion through the server's pipeline, without an LSP client
+///
+/// The prefix is typed into a scratch document, so query extraction, the
+/// snippet filter, translation and escaping are the ones the server uses.
+pub struct QueryService {
+    completion_service: CompletionService,
+    document_service: DocumentService,
+}
+
+impl std::fmt::Debug for QueryService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueryService")
+            .field("completion_service", &self.completion_service)
+            .finish()
+    }
+}
+
+impl QueryService {
+    pub fn new(
+        repository: Arc<dyn SnippetRepository>,
+        config: BkmrConfig,
+        language_registry: Arc<LanguageRegistry>,
+    ) -> Self {
+        let document_service = DocumentService::new()
+            .with_trigger_char(config.trigger_char)
+            .with_word_chars(config.word_chars.clone());
+        document_service.set_language_registry(language_registry.clone());
+        Self {
+            completion_service: CompletionService::with_config(repository, config)
+                .with_language_registry(language_registry),
+            document_service,
+        }
+    }
+
+    /// Completion items for the prefix typed at the end of a document in the language
+    #[instrument(skip(self))]
+    pub async fn query(
+        &self,
+        language_id: &str,
+        prefix: &str,
+        uri: Option<Url>,
+    ) -> Result<Vec<QueryItem>> {
+        let uri = match uri {
+            Some(uri) => uri,
+            None => Url::parse(DEFAULT_QUERY_URI).context("parse default query URI")?,
+        };
+        self.document_service
+            .open_document(uri.to_string(), language_id.to_string(), prefix.to_string())
+            .await
+            .context("open query document")?;
+
+        let last_line = prefix.rsplit('\n').next().unwrap_or("");
+        let position = Position::new(
+            prefix.matches('\n').count() as u32,
+            PositionEncoding::Utf16.character(last_line, last_line.len()),
+        );
+        let context = self
+            .document_service
+            .extract_completion_context(&uri, position)
+            .await
+            .context("extract completion context")?;
+
+        let list = self
+            .completion_service
+            .get_completion_list(&context, false)
+            .await
+            .context("get completions")?;
+        Ok(list
+            .items
+            .into_iter()
+            .map(QueryItem::from_completion_item)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::Snippet;
+    use crate::repositories::MockSnippetRepository;
+
+    fn query_service(repository: MockSnippetRepository) -> QueryService {
+        QueryService::new(
+            Arc::new(repository),
+            BkmrConfig::default(),
+            Arc::new(LanguageRegistry::default()),
+        )
+    }
+
+    #[tokio::test]
+    async fn given_prefix_when_querying_then_returns_translated_and_escaped_items() {
+        // Arrange
+        let snippets = vec![
+            Snippet::new(
+                1,
+                "make clean".to_string(),
+                "clean:\n\trm -rf $(BUILD)".to_string(),
+                "Clean target".to_string(),
+                vec!["make".to_string(), "_snip_".to_string()],
+            ),
+            Snippet::new(
+                2,
+                "make todo".to_string(),
+                "// TODO: ${1:task}".to_string(),
+                "Universal todo".to_string(),
+                vec![
+                    "universal".to_string(),
+                    "rust-style".to_string(),
+                    "_snip_".to_string(),
+                ],
+            ),
+        ];
+        let service = query_service(MockSnippetRepository::new().with_snippets(snippets));
+
+        // Act
+        let items = service
+            .query("make", "make", None)
+            .await
+            .expect("query items");
+
+        // Assert
+        assert_eq!(
+            items,
+            vec![
+                QueryItem {
+                    label: "make clean".to_string(),
+                    detail: Some("bkmr snippet".to_string()),
+                    insert_text: "clean:\n\trm -rf \\$(BUILD)".to_string(),
+                },
+                QueryItem {
+                    label: "make todo".to_string(),
+                    detail: Some("bkmr snippet".to_string()),
+                    insert_text: "# TODO: ${1:task}".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn given_failing_repository_when_querying_then_returns_error() {
+        // Arrange
+        let service =
+            query_service(MockSnippetRepository::new().with_fetch_error("database locked"));
+
+        // Act
+        let result = service.query("rust", "hello", None).await;
+
+        // Assert
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,1105 @@
+use anyhow::{Context, Result};
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionItemLabelDetails, CompletionTextEdit,
+    Documentation, InsertReplaceEdit, InsertTextFormat, InsertTextMode, MarkupContent, MarkupKind,
+    Range, TextEdit, Url,
+};
+use tracing::debug;
+
+use crate::backend::BkmrConfig;
+use crate::domain::{
+    ALIAS_PREFIX, CaseMode, CompletionContext, CompletionItemData, IndentUnit, InsertionKind,
+    LanguageRegistry, PathSeparator, Snippet, SnippetImports, SnippetSyntaxError,
+    validate_snippet_syntax,
+};
+use crate::services::{ClientCompletionSupport, ContextVariables, LanguageTranslator};
+
+/// Maximum number of characters of snippet content shown in the documentation preview
+const PREVIEW_MAX_CHARS: usize = 500;
+/// Detail of snippets inserted as plain text because their snippet syntax is broken
+const PLAIN_TEXT_FALLBACK_DETAIL: &str = "bkmr plain text (fallback)";
+/// Longest filter text, titles with long aliases or descriptions are cut
+pub(crate) const MAX_FILTER_TEXT_CHARS: usize = 256;
+
+/// Content a completion inserts and the variant its documentation shows
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnippetText {
+    /// Escaped, converted and indented for insertion
+    pub insert: String,
+    /// Translated only, with environment variables resolved if configured
+    pub display: String,
+    /// Snippet grammar error of the inserted content, which is then inserted as plain text
+    pub syntax_error: Option<SnippetSyntaxError>,
+}
+
+/// Everything a snippet's completion item depends on besides the snippet
+#[derive(Debug, Clone)]
+pub struct PresentationContext<'a> {
+    pub uri: &'a Url,
+    pub language_id: &'a str,
+    /// Query typed before the cursor, empty without one
+    pub query: &'a str,
+    /// Range the item replaces, None inserts at the cursor with insertText
+    pub replacement_range: Option<Range>,
+    /// Query start up to the end of the word, for clients replacing rather than inserting
+    pub word_range: Option<Range>,
+    /// Leading whitespace of the line the completion is inserted on
+    pub indentation: Option<&'a str>,
+    /// Unit the document indents by, None to use the language's
+    pub indent_unit: Option<IndentUnit>,
+    pub insertion_kind: InsertionKind,
+    /// Workspace folders `{{ filepath }}` is relative to
+    pub workspace_folders: &'a [Url],
+    pub config: &'a BkmrConfig,
+    /// Languages translating and escaping the snippet
+    pub language_registry: &'a LanguageRegistry,
+    pub client: &'a ClientCompletionSupport,
+    /// Leave documentation to completionItem/resolve
+    pub lazy_documentation: bool,
+    /// Leave the inserted text to completionItem/resolve
+    pub lazy_edit: bool,
+}
+
+impl<'a> PresentationContext<'a> {
+    pub fn new(
+        uri: &'a Url,
+        language_id: &'a str,
+        config: &'a BkmrConfig,
+        language_registry: &'a LanguageRegistry,
+        client: &'a ClientCompletionSupport,
+    ) -> Self {
+        Self {
+            uri,
+            language_id,
+            query: "",
+            replacement_range: None,
+            word_range: None,
+            indentation: None,
+            indent_unit: None,
+            insertion_kind: InsertionKind::Code,
+            workspace_folders: &[],
+            config,
+            language_registry,
+            client,
+            lazy_documentation: false,
+            lazy_edit: false,
+        }
+    }
+
+    /// Query, ranges, indentation and insertion kind of a completion request
+    pub fn with_completion_context(mut self, context: &'a CompletionContext) -> Self {
+        self.query = context.get_query_text().unwrap_or("");
+        self.replacement_range = context.get_replacement_range();
+        self.word_range = context.get_word_range();
+        self.indentation = context.indentation.as_deref();
+        self.indent_unit = context.indent_unit;
+        self.insertion_kind = context.insertion_kind.clone();
+        self
+    }
+
+    /// Ranges, indentation and insertion kind recorded on an item for its resolve
+    pub fn with_item_data(mut self, data: &'a CompletionItemData) -> Self {
+        self.replacement_range = data.range;
+        self.word_range = data.word_range;
+        self.indentation = data.indentation.as_deref();
+        self.indent_unit = data.indent_unit;
+        self.insertion_kind = data.insertion_kind.clone();
+        self
+    }
+
+    pub fn with_query(mut self, query: &'a str) -> Self {
+        self.query = query;
+        self
+    }
+
+    pub fn with_ranges(
+        mut self,
+        replacement_range: Option<Range>,
+        word_range: Option<Range>,
+    ) -> Self {
+        self.replacement_range = replacement_range;
+        self.word_range = word_range;
+        self
+    }
+
+    pub fn with_indentation(
+        mut self,
+        indentation: Option<&'a str>,
+        indent_unit: Option<IndentUnit>,
+    ) -> Self {
+        self.indentation = indentation;
+        self.indent_unit = indent_unit;
+        self
+    }
+
+    pub fn with_insertion_kind(mut self, insertion_kind: InsertionKind) -> Self {
+        self.insertion_kind = insertion_kind;
+        self
+    }
+
+    pub fn with_workspace_folders(mut self, workspace_folders: &'a [Url]) -> Self {
+        self.workspace_folders = workspace_folders;
+        self
+    }
+
+    pub fn with_lazy(mut self, documentation: bool, edit: bool) -> Self {
+        self.lazy_documentation = documentation;
+        self.lazy_edit = edit;
+        self
+    }
+}
+
+/// Turns snippets into completion items
+///
+/// Everything here depends only on the snippet and the presentation
+/// context: translation, escaping, plain text or snippet format, labels,
+/// filter texts, documentation and the edit. Which snippets are offered, and
+/// the list-level fields like sort text, data and commands, are left to
+/// [`crate::services::CompletionService`].
+pub struct SnippetPresenter;
+
+impl SnippetPresenter {
+    /// Completion item of a snippet
+    ///
+    /// Snippets whose content breaks the snippet grammar fall back to plain text.
+    pub fn present(snippet: &Snippet, context: &PresentationContext) -> Result<CompletionItem> {
+        let content = Self::content(snippet, context)?;
+        Ok(Self::present_content(snippet, content, context))
+    }
+
+    /// Completion item inserting content computed with [`Self::content`]
+    ///
+    /// Clients resolving both documentation and the edit get a minimal item,
+    /// everyone else a fully populated one.
+    pub fn present_content(
+        snippet: &Snippet,
+        content: SnippetText,
+        context: &PresentationContext,
+    ) -> CompletionItem {
+        let broken = content.syntax_error.is_some();
+        let mut item = if context.lazy_documentation && context.lazy_edit {
+            Self::lazy_item(snippet, context)
+        } else {
+            Self::full_item(snippet, content, context)
+        };
+        if broken {
+            Self::fall_back_to_plain_text(&mut item);
+        }
+        if context.lazy_documentation {
+            item.documentation = None;
+        }
+        item
+    }
+
+    /// Content inserted for a snippet: translated, escaped, converted and indented
+    ///
+    /// The documentation shows the translated content before escaping, so
+    /// both come from one translation.
+    pub fn content(snippet: &Snippet, context: &PresentationContext) -> Result<SnippetText> {
+        let config = context.config;
+        let language_id = context.language_id;
+
+        // Import headers are left to additional edits, see import_edits
+        let imports = SnippetImports::parse(&snippet.content, &config.import_marker);
+        let stripped;
+        let snippet = if imports.imports.is_empty() {
+            snippet
+        } else {
+            stripped = Snippet {
+                content: imports.body.to_string(),
+                ..snippet.clone()
+            };
+            &stripped
+        };
+
+        // Translate content if this is a universal snippet
+        let variables = ContextVariables::new(context.uri, language_id, context.language_registry)
+            .with_workspace_folders(
+                context.workspace_folders,
+                PathSeparator::from_native(config.native_path_separators),
+            )
+            .with_author(config.author.as_deref())
+            .with_indent_unit(context.indent_unit);
+        let translated_content = LanguageTranslator::translate_snippet(
+            snippet,
+            language_id,
+            &variables,
+            &config.translation_policy(),
+        )
+        .context("translate snippet content for target language")?;
+        let display = if config.resolve_env_in_docs {
+            LanguageTranslator::resolve_environment_variables(&translated_content, |name| {
+                std::env::var(name).ok()
+            })
+        } else {
+            translated_content.clone()
+        };
+
+        // Keep `$1` and `$(...)` literal where they are the language's own syntax
+        let translated_content = if snippet.is_plain() {
+            translated_content
+        } else {
+            LanguageTranslator::escape_literal_dollars(
+                &translated_content,
+                language_id,
+                &config.escape_all_dollars_for,
+                context.language_registry,
+            )
+        };
+
+        // Editor placeholders bkmr left in the template become snippet tabstops
+        let translated_content = if config.convert_placeholders && !snippet.is_plain() {
+            LanguageTranslator::convert_bkmr_placeholders(&translated_content)
+        } else {
+            translated_content
+        };
+
+        // Let the client indent continuation lines if it can, otherwise do it here
+        let indentation = context
+            .indentation
+            .filter(|_| !context.client.adjusts_indentation);
+        let insertion_kind = if config.context_aware_insertion {
+            &context.insertion_kind
+        } else {
+            &InsertionKind::Code
+        };
+        let insert = match insertion_kind {
+            InsertionKind::Code => match indentation {
+                Some(indentation) => {
+                    Self::indent_continuation_lines(&translated_content, indentation)
+                }
+                None => translated_content,
+            },
+            // Continuation lines are part of the string, indenting them would change it
+            InsertionKind::String { quote, multiline } => Self::escape_for_string(
+                &translated_content,
+                *quote,
+                *multiline,
+                !snippet.is_plain(),
+            ),
+            InsertionKind::Comment { leader } => {
+                Self::continue_comment(&translated_content, indentation.unwrap_or(""), leader)
+            }
+        };
+        let syntax_error = Self::syntax_error(snippet, &insert);
+        Ok(SnippetText {
+            insert,
+            display,
+            syntax_error,
+        })
+    }
+
+    /// Escape backslashes, the quote and, unless the string may span lines, newlines
+    ///
+    /// In snippet syntax every inserted backslash is written twice, and the
+    /// snippet escapes `\$` and `\}` are kept.
+    pub(crate) fn escape_for_string(
+        content: &str,
+        quote: char,
+        multiline: bool,
+        snippet: bool,
+    ) -> String {
+        let backslash = if snippet { "\\\\" } else { "\\" };
+        let mut escaped = String::with_capacity(content.len());
+        let mut chars = content.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if snippet && matches!(chars.peek(), Some('$' | '}')) => {
+                    escaped.push(c);
+                    escaped.extend(chars.next());
+                }
+                '\\' => {
+                    // An escaped backslash in snippet syntax is still one backslash
+                    if snippet && chars.peek() == Some(&'\\') {
+                        chars.next();
+                    }
+                    escaped.push_str(backslash);
+                    escaped.push_str(backslash);
+                }
+                '\r' if !multiline && chars.peek() == Some(&'\n') => {}
+                '\n' if !multiline => {
+                    escaped.push_str(backslash);
+                    escaped.push('n');
+                }
+                c if c == quote => {
+                    escaped.push_str(backslash);
+                    escaped.push(c);
+                }
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    /// Start every line after the first with the indentation and the comment leader
+    pub(crate) fn continue_comment(content: &str, indentation: &str, leader: &str) -> String {
+        content
+            .split('\n')
+            .enumerate()
+            .map(|(i, line)| match i {
+                0 => line.to_string(),
+                _ if line.trim().is_empty() => format!("{}{}", indentation, leader.trim_end()),
+                _ => format!("{}{}{}", indentation, leader, line),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Prefix every line after the first with the insertion line's indentation
+    ///
+    /// The indentation is copied verbatim from the document, so tab-indented
+    /// files get tabs; universal snippets already use the language's indent
+    /// unit for their own nesting.
+    pub(crate) fn indent_continuation_lines(content: &str, indentation: &str) -> String {
+        content
+            .split('\n')
+            .enumerate()
+            .map(|(i, line)| {
+                if i == 0 || line.trim().is_empty() {
+                    line.to_string()
+                } else {
+                    format!("{}{}", indentation, line)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Snippet grammar error in the content of a snippet inserted as snippet
+    fn syntax_error(snippet: &Snippet, content: &str) -> Option<SnippetSyntaxError> {
+        if snippet.is_plain() {
+            return None;
+        }
+        let error = validate_snippet_syntax(content).err()?;
+        debug!(
+            "Snippet {} '{}' has broken snippet syntax: {}",
+            snippet.id, snippet.title, error
+        );
+        Some(error)
+    }
+
+    /// Insert an item as plain text, marking it so broken snippets can be spotted
+    fn fall_back_to_plain_text(item: &mut CompletionItem) {
+        item.kind = Some(CompletionItemKind::TEXT);
+        item.insert_text_format = Some(InsertTextFormat::PLAIN_TEXT);
+        item.detail = Some(PLAIN_TEXT_FALLBACK_DETAIL.to_string());
+    }
+
+    /// Build the completion item inserting the given content
+    fn full_item(
+        snippet: &Snippet,
+        content: SnippetText,
+        context: &PresentationContext,
+    ) -> CompletionItem {
+        let SnippetText {
+            insert: snippet_content,
+            display,
+            ..
+        } = content;
+        debug!(
+            "Creating completion item: query='{}', title='{}', content_preview='{}'",
+            context.query,
+            snippet.title,
+            snippet_content.chars().take(20).collect::<String>()
+        );
+
+        let support = context.client;
+        let documentation = if support.markdown_documentation {
+            Self::snippet_documentation(snippet, &display, context.language_id)
+        } else {
+            Documentation::String(Self::snippet_plain_text(snippet, &display))
+        };
+        let mut completion_item = CompletionItem {
+            documentation: Some(documentation),
+            insert_text_mode: support
+                .adjusts_indentation
+                .then_some(InsertTextMode::ADJUST_INDENTATION),
+            ..Self::lazy_item(snippet, context)
+        };
+
+        // Use TextEdit for proper replacement if we have a range
+        if let Some(range) = context.replacement_range {
+            let word_range = context.word_range;
+            completion_item.text_edit = Some(if support.insert_replace {
+                // Inserting keeps the rest of the word after the cursor, replacing drops it
+                CompletionTextEdit::InsertAndReplace(InsertReplaceEdit {
+                    new_text: snippet_content,
+                    insert: range,
+                    replace: word_range.unwrap_or(range),
+                })
+            } else {
+                CompletionTextEdit::Edit(TextEdit {
+                    range: context.config.replace_mode.edit_range(range, word_range),
+                    new_text: snippet_content,
+                })
+            });
+            debug!("Set text_edit for range replacement: {:?}", range);
+        } else {
+            // Fallback to insert_text for backward compatibility
+            completion_item.insert_text = Some(snippet_content);
+            debug!("Using fallback insert_text (no range available)");
+        }
+
+        completion_item
+    }
+
+    /// Build a completion item without content, to be completed on resolve
+    fn lazy_item(snippet: &Snippet, context: &PresentationContext) -> CompletionItem {
+        let config = context.config;
+        let action = snippet.action_kind(&config.action_tags);
+        let (mut item_kind, text_format, detail_text) =
+            Self::item_presentation(snippet, action.is_some());
+        // Plain text and actions keep their kind, the override is for real snippets
+        if text_format == InsertTextFormat::SNIPPET
+            && let Some(kind) = config.item_kind
+        {
+            item_kind = kind.completion_item_kind();
+        }
+        let detail = match &config.detail_template {
+            Some(template) => Self::render_detail(template, snippet),
+            None => detail_text.to_string(),
+        };
+        let sort_text = Self::filter_text(&snippet.title);
+        let aliases = snippet.aliases(config.alias_pattern.as_ref());
+        let alias = Self::matched_alias(&sort_text, &aliases, context.query);
+
+        CompletionItem {
+            label: Self::label(&snippet.title, config),
+            label_details: Some(Self::label_details(
+                snippet,
+                alias,
+                action.as_deref(),
+                config.description_max_length,
+            )),
+            kind: Some(item_kind),
+            detail: Some(detail),
+            insert_text_format: Some(text_format),
+            filter_text: Some(Self::snippet_filter_text(snippet, &aliases, config)),
+            sort_text: Some(sort_text),
+            ..Default::default()
+        }
+    }
+
+    /// Completion label of a title, cut to the maximum length behind the label prefix
+    pub(crate) fn label(title: &str, config: &BkmrConfig) -> String {
+        format!(
+            "{}{}",
+            config.label_prefix,
+            Self::truncate_label(title, config.label_max_length)
+        )
+    }
+
+    /// Fill in the {tags}, {id}, {access_count} and {source} placeholders of a detail template
+    ///
+    /// Anything else in braces, and braces without a match, stay as written.
+    pub(crate) fn render_detail(template: &str, snippet: &Snippet) -> String {
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            rendered.push_str(&rest[..open]);
+            rest = &rest[open..];
+            let value = rest.find('}').and_then(|close| {
+                let value = match &rest[1..close] {
+                    "tags" => snippet.content_tags().collect::<Vec<_>>().join(", "),
+                    "id" => snippet.id.to_string(),
+                    "access_count" => snippet.access_count.to_string(),
+                    "source" => snippet.source().to_string(),
+                    _ => return None,
+                };
+                Some((value, close))
+            });
+            match value {
+                Some((value, close)) => {
+                    rendered.push_str(&value);
+                    rest = &rest[close + 1..];
+                }
+                None => {
+                    rendered.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+
+    /// Normalized title followed by the aliases, so client-side filtering matches both
+    pub(crate) fn alias_filter_text(filter_text: &str, aliases: &[String]) -> String {
+        std::iter::once(filter_text.to_string())
+            .chain(aliases.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Filter text of a snippet item: the normalized title, its aliases and optionally its description
+    ///
+    /// Cut to [`MAX_FILTER_TEXT_CHARS`], the sort text stays the title alone.
+    fn snippet_filter_text(snippet: &Snippet, aliases: &[String], config: &BkmrConfig) -> String {
+        let mut filter_text = Self::alias_filter_text(&Self::filter_words(&snippet.title), aliases);
+        let description = snippet.description_text();
+        if config.filter_on_description && !description.is_empty() {
+            filter_text.push(' ');
+            filter_text.push_str(&Self::filter_words(&description));
+        }
+        if config.case_mode == CaseMode::Insensitive {
+            filter_text = filter_text.to_lowercase();
+        }
+        Self::cap_filter_text(filter_text)
+    }
+
+    /// Filter text of a title, lowercased only when case is always ignored
+    pub(crate) fn case_filter_text(title: &str, case_mode: CaseMode) -> String {
+        match case_mode {
+            CaseMode::Insensitive => Self::filter_text(title),
+            CaseMode::Smart | CaseMode::Sensitive => Self::filter_words(title),
+        }
+    }
+
+    /// Cut a filter text to [`MAX_FILTER_TEXT_CHARS`] characters
+    pub(crate) fn cap_filter_text(filter_text: String) -> String {
+        match filter_text.char_indices().nth(MAX_FILTER_TEXT_CHARS) {
+            Some((end, _)) => filter_text[..end].trim_end().to_string(),
+            None => filter_text,
+        }
+    }
+
+    /// Alias the query is a prefix of, when no word of the normalized title is
+    fn matched_alias<'a>(filter_text: &str, aliases: &'a [String], query: &str) -> Option<&'a str> {
+        let query = query.to_lowercase();
+        if query.is_empty() || filter_text.split(' ').any(|word| word.starts_with(&query)) {
+            return None;
+        }
+        aliases
+            .iter()
+            .find(|alias| alias.to_lowercase().starts_with(&query))
+            .map(String::as_str)
+    }
+
+    /// Text clients match typed characters against
+    ///
+    /// Lowercased, without leading emoji or symbols, and with punctuation
+    /// collapsed to single spaces: "🚀 Deploy: k8s" becomes "deploy k8s".
+    pub(crate) fn filter_text(title: &str) -> String {
+        Self::filter_words(title).to_lowercase()
+    }
+
+    /// Words of a title as in [`Self::filter_text`], keeping their case
+    pub(crate) fn filter_words(title: &str) -> String {
+        let words: String = title
+            .trim_start_matches(|c: char| !c.is_alphanumeric())
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '_' {
+                    c
+                } else {
+                    ' '
+                }
+            })
+            .collect();
+        let normalized = words.split_whitespace().collect::<Vec<_>>().join(" ");
+        if normalized.is_empty() {
+            title.to_string()
+        } else {
+            normalized
+        }
+    }
+
+    /// Cut a title to at most `max_length` characters ending in an ellipsis, 0 keeps it whole
+    pub(crate) fn truncate_label(title: &str, max_length: usize) -> String {
+        if max_length == 0 || title.chars().count() <= max_length {
+            return title.to_string();
+        }
+        let kept: String = title.chars().take(max_length - 1).collect();
+        format!("{}…", kept.trim_end())
+    }
+
+    /// First content tag, the matched alias and the action next to the label
+    ///
+    /// The description, cut to `description_max_length` characters, and the
+    /// snippet id go on the right.
+    fn label_details(
+        snippet: &Snippet,
+        alias: Option<&str>,
+        action: Option<&str>,
+        description_max_length: usize,
+    ) -> CompletionItemLabelDetails {
+        let parts: Vec<String> = [
+            snippet.content_tags().next().map(str::to_string),
+            alias.map(|alias| format!("{}{}", ALIAS_PREFIX, alias)),
+            action.map(|action| format!("[{}]", action)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let description = snippet.description_text();
+        CompletionItemLabelDetails {
+            detail: (!parts.is_empty()).then(|| format!(" {}", parts.join(" "))),
+            description: Some(if description_max_length == 0 || description.is_empty() {
+                format!("#{}", snippet.id)
+            } else {
+                format!(
+                    "{} #{}",
+                    Self::truncate_label(&description, description_max_length),
+                    snippet.id
+                )
+            }),
+        }
+    }
+
+    /// Determine kind, insert format and detail text for a snippet
+    fn item_presentation(
+        snippet: &Snippet,
+        action: bool,
+    ) -> (CompletionItemKind, InsertTextFormat, &'static str) {
+        if action {
+            (
+                CompletionItemKind::EVENT,
+                InsertTextFormat::PLAIN_TEXT,
+                "bkmr action",
+            )
+        } else if snippet.is_plain() {
+            (
+                CompletionItemKind::TEXT,
+                InsertTextFormat::PLAIN_TEXT,
+                "bkmr plain text",
+            )
+        } else {
+            (
+                CompletionItemKind::SNIPPET,
+                InsertTextFormat::SNIPPET,
+                "bkmr snippet",
+            )
+        }
+    }
+
+    /// Wrap the markdown preview of a snippet as completion documentation
+    pub(crate) fn snippet_documentation(
+        snippet: &Snippet,
+        content: &str,
+        language_id: &str,
+    ) -> Documentation {
+        Documentation::MarkupContent(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: Self::snippet_markdown(snippet, content, language_id),
+        })
+    }
+
+    /// Render a plain text preview with description, tags and the content
+    pub(crate) fn snippet_plain_text(snippet: &Snippet, content: &str) -> String {
+        let mut value = String::new();
+        if !snippet.description.is_empty() {
+            value.push_str(&snippet.description);
+            value.push_str("\n\n");
+        }
+        if !snippet.tags.is_empty() {
+            value.push_str(&format!("Tags: {}\n\n", snippet.tags.join(", ")));
+        }
+        value.extend(content.chars().take(PREVIEW_MAX_CHARS));
+        if content.chars().count() > PREVIEW_MAX_CHARS {
+            value.push_str("\n\n… (truncated)");
+        }
+        value
+    }
+
+    /// Render a markdown preview with description, tags and the content in a fenced code block
+    pub(crate) fn snippet_markdown(snippet: &Snippet, content: &str, language_id: &str) -> String {
+        let fence_language = match language_id {
+            "" | "unknown" => snippet.language_tag().unwrap_or(""),
+            language_id => language_id,
+        };
+
+        let truncated = content.chars().count() > PREVIEW_MAX_CHARS;
+        let body: String = content.chars().take(PREVIEW_MAX_CHARS).collect();
+
+        // The fence must be longer than any backtick run inside the body
+        let longest_backtick_run = body.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+        let fence = "`".repeat((longest_backtick_run + 1).max(3));
+
+        let mut value = String::new();
+        if !snippet.description.is_empty() {
+            value.push_str(&snippet.description);
+            value.push_str("\n\n");
+        }
+        if !snippet.tags.is_empty() {
+            let tags: Vec<String> = snippet
+                .tags
+                .iter()
+                .map(|tag| format!("`{}`", tag))
+                .collect();
+            value.push_str(&format!("Tags: {}\n\n", tags.join(", ")));
+        }
+        value.push_str(&format!("{}{}\n{}", fence, fence_language, body));
+        if !body.ends_with('\n') {
+            value.push('\n');
+        }
+        value.push_str(&fence);
+        if truncated {
+            value.push_str("\n\n… (truncated)");
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tower_lsp::lsp_types::Position;
+
+    /// Set to rewrite the golden files from the current output instead of comparing
+    const UPDATE_GOLDEN: &str = "BKMR_LSP_UPDATE_GOLDEN";
+
+    fn snippet(id: i32, title: &str, content: &str, description: &str, tags: &[&str]) -> Snippet {
+        Snippet::new(
+            id,
+            title.to_string(),
+            content.to_string(),
+            description.to_string(),
+            tags.iter().map(|tag| tag.to_string()).collect(),
+        )
+    }
+
+    /// Snippets covering translation, plain text, tabstops, variables and truncated previews
+    fn golden_snippets() -> Vec<(&'static str, Snippet)> {
+        let big = (1..=40)
+            .map(|n| format!("<p>line {}: lorem ipsum dolor sit amet</p>", n))
+            .collect::<Vec<_>>()
+            .join("\n");
+        vec![
+            (
+                "universal",
+                snippet(
+                    1,
+                    "Debug print",
+                    "// Print a value\nfn debug() {\n    println!(\"{:?}\", value);\n}",
+                    "Print a value for debugging",
+                    &["universal", "rust-style", "_snip_"],
+                ),
+            ),
+            (
+                "plain",
+                snippet(
+                    2,
+                    "Shell loop",
+                    "for f in *.txt; do\n    echo $f ${1}\ndone",
+                    "Loop over text files",
+                    &["plain", "sh", "_snip_"],
+                ),
+            ),
+            (
+                "placeholders",
+                snippet(
+                    3,
+                    "Function",
+                    "def ${1:name}(${2:args}):\n    {{ cursor }}\n    return $0",
+                    "Function with tabstops",
+                    &["python", "_snip_"],
+                ),
+            ),
+            (
+                "env_vars",
+                snippet(
+                    4,
+                    "Home path",
+                    "cd $HOME && ls ${PROJECT_DIR}/{{ filename }}",
+                    "",
+                    &["sh", "_snip_"],
+                ),
+            ),
+            (
+                "oversized",
+                snippet(
+                    5,
+                    "Big template",
+                    &big,
+                    "A snippet too long to preview whole",
+                    &["html", "_snip_"],
+                ),
+            ),
+        ]
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    /// Compare each golden snippet's item with `tests/golden/presenter/<fixture>_<language>.json`
+    fn assert_golden_items(context: &PresentationContext) {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/presenter");
+        let update = std::env::var_os(UPDATE_GOLDEN).is_some();
+        for (fixture, snippet) in golden_snippets() {
+            let item = SnippetPresenter::present(&snippet, context).expect("present snippet");
+            let actual = serde_json::to_string_pretty(&item).expect("serialize item") + "\n";
+            let path = dir.join(format!("{}_{}.json", fixture, context.language_id));
+            if update {
+                std::fs::write(&path, &actual).expect("write golden file");
+                continue;
+            }
+            let expected = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("read {}: {}", path.display(), e));
+            assert_eq!(
+                actual,
+                expected,
+                "{} differs, set {}=1 to update it",
+                path.display(),
+                UPDATE_GOLDEN
+            );
+        }
+    }
+
+    #[test]
+    fn given_python_document_with_ranges_when_presenting_then_matches_golden_items() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let uri = Url::parse("file:///project/app/main.py").expect("parse URI");
+        let config = BkmrConfig::default();
+        let client = ClientCompletionSupport::default();
+        let context = PresentationContext::new(&uri, "python", &config, &registry, &client)
+            .with_query("de")
+            .with_ranges(Some(range((3, 4), (3, 6))), Some(range((3, 4), (3, 8))))
+            .with_indentation(Some("    "), Some(IndentUnit::Spaces(4)));
+
+        // Act & Assert
+        assert_golden_items(&context);
+    }
+
+    #[test]
+    fn given_go_document_with_insert_replace_client_when_presenting_then_matches_golden_items() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let uri = Url::parse("file:///project/cmd/main.go").expect("parse URI");
+        let config = BkmrConfig::default();
+        let client = ClientCompletionSupport {
+            snippets: true,
+            insert_replace: true,
+            ..Default::default()
+        };
+        let context = PresentationContext::new(&uri, "go", &config, &registry, &client)
+            .with_query("fu")
+            .with_ranges(Some(range((10, 1), (10, 3))), Some(range((10, 1), (10, 5))))
+            .with_indentation(Some("\t"), Some(IndentUnit::Tabs));
+
+        // Act & Assert
+        assert_golden_items(&context);
+    }
+
+    #[test]
+    fn given_html_document_with_plain_text_client_when_presenting_then_matches_golden_items() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let uri = Url::parse("file:///project/site/index.html").expect("parse URI");
+        let config = BkmrConfig::default();
+        let client = ClientCompletionSupport {
+            snippets: true,
+            markdown_documentation: false,
+            adjusts_indentation: true,
+            ..Default::default()
+        };
+        let context = PresentationContext::new(&uri, "html", &config, &registry, &client);
+
+        // Act & Assert
+        assert_golden_items(&context);
+    }
+
+    #[test]
+    fn given_lazy_context_when_presenting_then_leaves_documentation_and_edit_out() {
+        // Arrange
+        let registry = LanguageRegistry::default();
+        let uri = Url::parse("file:///project/app/main.py").expect("parse URI");
+        let config = BkmrConfig::default();
+        let client = ClientCompletionSupport::default();
+        let context = PresentationContext::new(&uri, "python", &config, &registry, &client)
+            .with_ranges(Some(range((0, 0), (0, 2))), None)
+            .with_lazy(true, true);
+        let (_, snippet) = golden_snippets().remove(2);
+
+        // Act
+        let item = SnippetPresenter::present(&snippet, &context).expect("present snippet");
+
+        // Assert
+        assert_eq!(item.label, "Function");
+        assert_eq!(item.documentation, None);
+        assert_eq!(item.text_edit, None);
+        assert_eq!(item.insert_text, None);
+    }
+
+    fn markdown_value(documentation: Documentation) -> String {
+        match documentation {
+            Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }) => value,
+            other => panic!("Expected markdown documentation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn given_snippet_when_rendering_documentation_then_shows_description_tags_and_fence() {
+        // Arrange
+        let snippet = Snippet::new(
+            1,
+            "Hello".to_string(),
+            "println!(\"hi\");".to_string(),
+            "Print a greeting".to_string(),
+            vec!["rust".to_string(), "_snip_".to_string()],
+        );
+
+        // Act
+        let value = markdown_value(SnippetPresenter::snippet_documentation(
+            &snippet,
+            snippet.get_content(),
+            "rust",
+        ));
+
+        // Assert
+        assert_eq!(
+            value,
+            "Print a greeting\n\nTags: `rust`, `_snip_`\n\n```rust\nprintln!(\"hi\");\n```"
+        );
+    }
+
+    #[test]
+    fn given_content_with_backticks_when_rendering_documentation_then_uses_longer_fence() {
+        // Arrange
+        let content = "Example:\n```sh\necho hi\n```";
+        let snippet = Snippet::new(
+            1,
+            "Markdown".to_string(),
+            content.to_string(),
+            String::new(),
+            vec!["markdown".to_string(), "_snip_".to_string()],
+        );
+
+        // Act
+        let value = markdown_value(SnippetPresenter::snippet_documentation(
+            &snippet, content, "markdown",
+        ));
+
+        // Assert
+        assert!(value.contains("````markdown\nExample:\n```sh\necho hi\n```\n````"));
+        assert!(value.ends_with("````"));
+    }
+
+    #[test]
+    fn given_long_content_when_rendering_documentation_then_truncates_code_body_only() {
+        // Arrange
+        let description = "d".repeat(600);
+        let content = "é".repeat(600);
+        let snippet = Snippet::new(
+            1,
+            "Long".to_string(),
+            content.clone(),
+            description.clone(),
+            vec!["text".to_string()],
+        );
+
+        // Act
+        let value = markdown_value(SnippetPresenter::snippet_documentation(
+            &snippet, &content, "text",
+        ));
+
+        // Assert
+        assert!(value.starts_with(&description));
+        assert!(value.contains(&format!("```text\n{}\n```", "é".repeat(PREVIEW_MAX_CHARS))));
+        assert!(!value.contains(&"é".repeat(PREVIEW_MAX_CHARS + 1)));
+        assert!(value.ends_with("\n\n… (truncated)"));
+    }
+
+    #[test]
+    fn given_unknown_document_language_when_rendering_documentation_then_uses_first_content_tag() {
+        // Arrange
+        let snippet = Snippet::new(
+            1,
+            "Script".to_string(),
+            "echo hi".to_string(),
+            String::new(),
+            vec![
+                "_snip_".to_string(),
+                "universal".to_string(),
+                "sh".to_string(),
+                "deploy".to_string(),
+            ],
+        );
+
+        // Act
+        let unknown = markdown_value(SnippetPresenter::snippet_documentation(
+            &snippet, "echo hi", "unknown",
+        ));
+        let known = markdown_value(SnippetPresenter::snippet_documentation(
+            &snippet, "echo hi", "bash",
+        ));
+
+        // Assert
+        assert!(unknown.contains("```sh\necho hi\n```"));
+        assert!(known.contains("```bash\necho hi\n```"));
+    }
+
+    #[test]
+    fn given_all_caps_title_when_normalizing_then_filter_text_is_lowercase() {
+        // Act & Assert
+        assert_eq!(
+            SnippetPresenter::filter_text("TODO: FIX_ME NOW"),
+            "todo fix_me now"
+        );
+        assert_eq!(SnippetPresenter::filter_text("🔥🔥"), "🔥🔥");
+    }
+
+    #[test]
+    fn given_zero_max_length_when_truncating_label_then_keeps_whole_title() {
+        // Act & Assert
+        assert_eq!(
+            SnippetPresenter::truncate_label(&"x".repeat(120), 0).len(),
+            120
+        );
+        assert_eq!(SnippetPresenter::truncate_label("short", 60), "short");
+        assert_eq!(SnippetPresenter::truncate_label("ab cd", 4), "ab…");
+    }
+
+    #[test]
+    fn given_all_placeholders_when_rendering_detail_then_fills_them_in() {
+        // Arrange
+        let mut snippet = Snippet::new(
+            42,
+            "Deploy".to_string(),
+            "kubectl apply".to_string(),
+            String::new(),
+            vec!["sh".to_string(), "k8s".to_string(), "_snip_".to_string()],
+        );
+        snippet.access_count = 7;
+        let file_snippet =
+            Snippet::new(-3, "File".to_string(), String::new(), String::new(), vec![]);
+
+        // Act
+        let rendered = SnippetPresenter::render_detail(
+            "{source} #{id} [{tags}] used {access_count}x",
+            &snippet,
+        );
+        let file = SnippetPresenter::render_detail("{source}", &file_snippet);
+
+        // Assert
+        assert_eq!(rendered, "bkmr #42 [sh, k8s] used 7x");
+        assert_eq!(file, "file");
+    }
+
+    #[test]
+    fn given_unknown_or_unbalanced_placeholders_when_rendering_detail_then_keeps_them_literal() {
+        // Arrange
+        let snippet = Snippet::new(5, "T".to_string(), String::new(), String::new(), vec![]);
+
+        // Act & Assert
+        assert_eq!(
+            SnippetPresenter::render_detail("{title} {id}", &snippet),
+            "{title} 5"
+        );
+        assert_eq!(SnippetPresenter::render_detail("{{id}}", &snippet), "{5}");
+        assert_eq!(
+            SnippetPresenter::render_detail("id} {id", &snippet),
+            "id} {id"
+        );
+        assert_eq!(SnippetPresenter::render_detail("{}{", &snippet), "{}{");
+        assert_eq!(
+            SnippetPresenter::render_detail("{ü}é{id}", &snippet),
+            "{ü}é5"
+        );
+    }
+}
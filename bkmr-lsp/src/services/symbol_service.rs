@@ -0,0 +1,152 @@
+use anyhow::{Context, Result, anyhow};
+use std::sync::Arc;
+use tower_lsp::lsp_types::{Location, Position, Range, SymbolInformation, SymbolKind, Url};
+use tracing::{debug, instrument};
+
+use crate::domain::{RankingStrategy, Snippet, SnippetFilter, rank_snippets};
+use crate::repositories::SnippetRepository;
+
+/// URI scheme locating snippets, which are not files
+pub const SNIPPET_URI_SCHEME: &str = "bkmr";
+/// Snippets fetched for an empty query, to pick the most used ones from
+const EMPTY_QUERY_FETCH_LIMIT: usize = 500;
+
+/// Service listing snippets in the editor's workspace symbol picker
+pub struct SymbolService {
+    repository: Arc<dyn SnippetRepository>,
+    max_results: usize,
+}
+
+impl std::fmt::Debug for SymbolService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SymbolService")
+            .field("repository", &"<SnippetRepository>")
+            .field("max_results", &self.max_results)
+            .finish()
+    }
+}
+
+impl SymbolService {
+    pub fn new(repository: Arc<dyn SnippetRepository>, max_results: usize) -> Self {
+        Self {
+            repository,
+            max_results,
+        }
+    }
+
+    /// Search snippets of all languages by title, the most used ones for an empty query
+    #[instrument(skip(self))]
+    pub async fn workspace_symbols(&self, query: &str) -> Result<Vec<SymbolInformation>> {
+        let query = query.trim();
+        let query_prefix = (!query.is_empty()).then(|| query.to_string());
+        let fetch_limit = if query.is_empty() {
+            self.max_results.max(EMPTY_QUERY_FETCH_LIMIT)
+        } else {
+            self.max_results
+        };
+        let filter = SnippetFilter::new(None, query_prefix, fetch_limit);
+
+        let snippets = self
+            .repository
+            .fetch_snippets(&filter)
+            .await
+            .context("fetch snippets for workspace symbols")?;
+        let strategy = if query.is_empty() {
+            RankingStrategy::Usage
+        } else {
+            RankingStrategy::Relevance
+        };
+        let mut snippets = rank_snippets(snippets, Some(query), strategy);
+        snippets.truncate(self.max_results);
+
+        debug!("Returning {} snippets as workspace symbols", snippets.len());
+        snippets.iter().map(Self::snippet_symbol).collect()
+    }
+
+    /// Location of a snippet, e.g. `bkmr://snippet/42`
+    pub fn snippet_uri(id: i32) -> Result<Url> {
+        Url::parse(&format!("{}://snippet/{}", SNIPPET_URI_SCHEME, id))
+            .map_err(|e| anyhow!("build snippet URI for {}: {}", id, e))
+    }
+
+    fn snippet_symbol(snippet: &Snippet) -> Result<SymbolInformation> {
+        #[allow(deprecated)] // `deprecated` has no default and must be spelled out
+        Ok(SymbolInformation {
+            name: snippet.title.clone(),
+            kind: SymbolKind::STRING,
+            tags: None,
+            deprecated: None,
+            location: Location {
+                uri: Self::snippet_uri(snippet.id)?,
+                range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+            },
+            container_name: (!snippet.tags.is_empty()).then(|| snippet.tags.join(", ")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::MockSnippetRepository;
+
+    fn snippet(id: i32, title: &str, language: &str, access_count: i32) -> Snippet {
+        let mut snippet = Snippet::new(
+            id,
+            title.to_string(),
+            "content".to_string(),
+            String::new(),
+            vec![language.to_string(), "_snip_".to_string()],
+        );
+        snippet.access_count = access_count;
+        snippet
+    }
+
+    fn service(max_results: usize) -> SymbolService {
+        let repository = MockSnippetRepository::new().with_snippets(vec![
+            snippet(1, "Serde derive", "rust", 1),
+            snippet(2, "Python main", "python", 20),
+            snippet(3, "Serialize JSON", "rust", 5),
+        ]);
+        SymbolService::new(Arc::new(repository), max_results)
+    }
+
+    #[tokio::test]
+    async fn given_query_when_searching_symbols_then_returns_snippets_of_all_languages() {
+        // Act
+        let symbols = service(10)
+            .workspace_symbols("ser")
+            .await
+            .expect("workspace symbols");
+
+        // Assert
+        let names: Vec<&str> = symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["Serialize JSON", "Serde derive"]);
+        assert_eq!(symbols[1].kind, SymbolKind::STRING);
+        assert_eq!(symbols[1].location.uri.as_str(), "bkmr://snippet/1");
+        assert_eq!(symbols[1].container_name.as_deref(), Some("rust, _snip_"));
+    }
+
+    #[tokio::test]
+    async fn given_empty_query_when_searching_symbols_then_most_used_come_first_up_to_cap() {
+        // Act
+        let symbols = service(2)
+            .workspace_symbols("  ")
+            .await
+            .expect("workspace symbols");
+
+        // Assert
+        let names: Vec<&str> = symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["Python main", "Serialize JSON"]);
+    }
+
+    #[test]
+    fn given_snippet_id_when_building_uri_then_uses_bkmr_scheme() {
+        // Act
+        let uri = SymbolService::snippet_uri(42).expect("snippet URI");
+
+        // Assert
+        assert_eq!(uri.scheme(), SNIPPET_URI_SCHEME);
+        assert_eq!(uri.as_str(), "bkmr://snippet/42");
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tracing::error;
+
+/// Lock for reading, recovering the value if a thread panicked while writing it
+///
+/// The guarded values are replaced whole, never left half-written, so the
+/// poison is cleared and the last value kept instead of failing every access.
+pub fn read<'a, T>(lock: &'a RwLock<T>, name: &str) -> RwLockReadGuard<'a, T> {
+    lock.read().unwrap_or_else(|poisoned| {
+        error!("Recovered {} lock poisoned by a panic", name);
+        lock.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Lock for writing, recovering the value if a thread panicked while writing it
+pub fn write<'a, T>(lock: &'a RwLock<T>, name: &str) -> RwLockWriteGuard<'a, T> {
+    lock.write().unwrap_or_else(|poisoned| {
+        error!("Recovered {} lock poisoned by a panic", name);
+        lock.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Lock a mutex, recovering the value if a thread panicked while holding it
+pub fn lock<'a, T>(mutex: &'a Mutex<T>, name: &str) -> MutexGuard<'a, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        error!("Recovered {} lock poisoned by a panic", name);
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poison(lock: &RwLock<i32>) {
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = lock.write().expect("unpoisoned lock");
+            panic!("panic while holding the lock");
+        }));
+        assert!(result.is_err());
+        assert!(lock.is_poisoned());
+    }
+
+    #[test]
+    fn given_poisoned_lock_when_reading_then_returns_value_and_clears_poison() {
+        // Arrange
+        let lock = RwLock::new(7);
+        poison(&lock);
+
+        // Act
+        let value = *read(&lock, "test");
+
+        // Assert
+        assert_eq!(value, 7);
+        assert!(!lock.is_poisoned());
+    }
+
+    #[test]
+    fn given_poisoned_lock_when_writing_then_stores_new_value() {
+        // Arrange
+        let lock = RwLock::new(7);
+        poison(&lock);
+
+        // Act
+        *write(&lock, "test") = 8;
+
+        // Assert
+        assert_eq!(*read(&lock, "test"), 8);
+        assert!(lock.read().is_ok());
+    }
+
+    #[test]
+    fn given_poisoned_mutex_when_locking_then_returns_value_and_clears_poison() {
+        // Arrange
+        let mutex = Mutex::new(7);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = mutex.lock().expect("unpoisoned mutex");
+            panic!("panic while holding the mutex");
+        }));
+        assert!(result.is_err());
+
+        // Act
+        let value = *lock(&mutex, "test");
+
+        // Assert
+        assert_eq!(value, 7);
+        assert!(!mutex.is_poisoned());
+    }
+}
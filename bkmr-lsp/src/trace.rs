@@ -0,0 +1,149 @@
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tower_lsp::lsp_types::{LogTraceParams, TraceValue};
+use tracing::debug;
+
+use crate::repositories::CacheStats;
+use crate::sync;
+
+/// Summary of one completion request, reported as `$/logTrace`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompletionTrace {
+    pub query: String,
+    pub language_id: Option<String>,
+    pub items: usize,
+    pub elapsed: Duration,
+    /// FTS query of the last bkmr search, only reported at verbose
+    pub fts_query: Option<String>,
+    /// Cache hits and misses during the request, only reported at verbose
+    pub cache: Option<CacheStats>,
+}
+
+/// Reports what the server does as `$/logTrace` at the level set with `$/setTrace`
+///
+/// Traces are sent to a channel the backend forwards to the client, so
+/// whatever reports them does not need the client. Nothing is sent while the
+/// level is off, which it is until the client asks for more.
+#[derive(Debug)]
+pub struct TraceReporter {
+    level: RwLock<TraceValue>,
+    sender: UnboundedSender<LogTraceParams>,
+}
+
+impl TraceReporter {
+    /// A silent reporter and the receiving end of its traces
+    pub fn new() -> (Self, UnboundedReceiver<LogTraceParams>) {
+        let (sender, receiver) = unbounded_channel();
+        let reporter = Self {
+            level: RwLock::new(TraceValue::Off),
+            sender,
+        };
+        (reporter, receiver)
+    }
+
+    pub fn level(&self) -> TraceValue {
+        *sync::read(&self.level, "trace level")
+    }
+
+    pub fn set_level(&self, level: TraceValue) {
+        debug!("Trace level set to {:?}", level);
+        *sync::write(&self.level, "trace level") = level;
+    }
+
+    /// Whether traces are reported at all
+    pub fn enabled(&self) -> bool {
+        self.level() != TraceValue::Off
+    }
+
+    /// Whether traces carry their verbose details
+    pub fn verbose(&self) -> bool {
+        self.level() == TraceValue::Verbose
+    }
+
+    /// Report a message, the verbose details only at verbose level
+    pub fn report(&self, message: String, verbose: impl FnOnce() -> String) {
+        let verbose = match self.level() {
+            TraceValue::Off => return,
+            TraceValue::Messages => None,
+            TraceValue::Verbose => Some(verbose()),
+        };
+        // The receiver is gone once the server stops, the trace is of no use then
+        let _ = self.sender.send(LogTraceParams { message, verbose });
+    }
+
+    pub fn completion(&self, trace: &CompletionTrace) {
+        let message = format!(
+            "textDocument/completion {:?} ({}): {} items in {} ms",
+            trace.query,
+            trace.language_id.as_deref().unwrap_or("no language"),
+            trace.items,
+            trace.elapsed.as_millis()
+        );
+        self.report(message, || {
+            let cache = trace.cache.map_or("off".to_string(), |cache| {
+                format!("{} hits, {} misses", cache.hits, cache.misses)
+            });
+            format!(
+                "fts query: {}\ncache: {}",
+                trace.fts_query.as_deref().unwrap_or("none"),
+                cache
+            )
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completion_trace() -> CompletionTrace {
+        CompletionTrace {
+            query: "doc".to_string(),
+            language_id: Some("rust".to_string()),
+            items: 3,
+            elapsed: Duration::from_millis(12),
+            fts_query: Some("tags:rust".to_string()),
+            cache: Some(CacheStats { hits: 1, misses: 0 }),
+        }
+    }
+
+    #[test]
+    fn given_default_level_when_reporting_then_sends_nothing() {
+        // Arrange
+        let (reporter, mut receiver) = TraceReporter::new();
+
+        // Act
+        reporter.completion(&completion_trace());
+
+        // Assert
+        assert_eq!(reporter.level(), TraceValue::Off);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn given_messages_and_verbose_levels_when_reporting_then_only_verbose_has_details() {
+        // Arrange
+        let (reporter, mut receiver) = TraceReporter::new();
+
+        // Act
+        reporter.set_level(TraceValue::Messages);
+        reporter.completion(&completion_trace());
+        reporter.set_level(TraceValue::Verbose);
+        reporter.completion(&completion_trace());
+
+        // Assert
+        let messages = receiver.try_recv().expect("messages trace");
+        assert_eq!(
+            messages.message,
+            "textDocument/completion \"doc\" (rust): 3 items in 12 ms"
+        );
+        assert_eq!(messages.verbose, None);
+        let verbose = receiver.try_recv().expect("verbose trace");
+        assert_eq!(verbose.message, messages.message);
+        assert_eq!(
+            verbose.verbose.as_deref(),
+            Some("fts query: tags:rust\ncache: 1 hits, 0 misses")
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use anyhow::{Context, Result};
+use std::str::FromStr;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+use crate::backend::{BkmrConfig, BkmrLspBackend, lsp_service, serve};
+
+/// Default of `--listen`
+pub const DEFAULT_TRANSPORT: &str = "stdio";
+
+/// How the server talks to its client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Transport {
+    /// LSP messages on stdin and stdout, the server is started by the editor
+    #[default]
+    Stdio,
+    /// A TCP listener serving one client connection at a time
+    Tcp {
+        /// `host:port` to listen on
+        address: String,
+        /// Accept the next connection when a client disconnects instead of exiting
+        keep_listening: bool,
+    },
+}
+
+impl Transport {
+    /// Await a new client over TCP after a disconnect, no effect on stdio
+    pub fn with_keep_listening(self, keep_listening: bool) -> Self {
+        match self {
+            Self::Tcp { address, .. } => Self::Tcp {
+                address,
+                keep_listening,
+            },
+            Self::Stdio => Self::Stdio,
+        }
+    }
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    /// Parse `stdio` or `tcp://HOST:PORT`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("stdio") {
+            return Ok(Self::Stdio);
+        }
+        let Some((scheme, address)) = s.split_once("://") else {
+            return Err(format!(
+                "invalid transport '{}', expected stdio or tcp://HOST:PORT",
+                s
+            ));
+        };
+        if !scheme.eq_ignore_ascii_case("tcp") {
+            return Err(format!(
+                "unsupported transport scheme '{}', expected stdio or tcp://HOST:PORT",
+                scheme
+            ));
+        }
+        let address = address.trim_end_matches('/');
+        match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(Self::Tcp {
+                    address: address.to_string(),
+                    keep_listening: false,
+                })
+            }
+            _ => Err(format!(
+                "invalid TCP address '{}', expected HOST:PORT like 127.0.0.1:9257",
+                address
+            )),
+        }
+    }
+}
+
+/// Serve the LSP over the transport until the client exits, returning the exit code
+///
+/// Each connection gets a backend of its own built from the configuration.
+pub async fn run_server(transport: Transport, config: BkmrConfig) -> Result<i32> {
+    match transport {
+        Transport::Stdio => {
+            info!("Starting LSP server on stdin/stdout");
+            Ok(serve_connection(tokio::io::stdin(), tokio::io::stdout(), config).await)
+        }
+        Transport::Tcp {
+            address,
+            keep_listening,
+        } => {
+            let listener = TcpListener::bind(&address)
+                .await
+                .with_context(|| format!("listen on tcp://{} (is the port taken?)", address))?;
+            info!("Listening for an LSP client on tcp://{}", address);
+            serve_tcp(listener, keep_listening, config).await
+        }
+    }
+}
+
+/// Serve the clients connecting to the listener one after another
+///
+/// Returns the exit code of the first connection, or keeps accepting new
+/// connections after each disconnect if `keep_listening` is set.
+pub async fn serve_tcp(
+    listener: TcpListener,
+    keep_listening: bool,
+    config: BkmrConfig,
+) -> Result<i32> {
+    loop {
+        let (stream, peer) = listener
+            .accept()
+            .await
+            .context("accept LSP client connection")?;
+        info!("LSP client connected from {}", peer);
+        let (read, write) = stream.into_split();
+        let exit_code = serve_connection(read, write, config.clone()).await;
+        if !keep_listening {
+            return Ok(exit_code);
+        }
+        warn!(
+            "LSP client {} disconnected (exit code {}), awaiting the next one",
+            peer, exit_code
+        );
+    }
+}
+
+/// Serve one client over the given streams with a new backend
+pub async fn serve_connection<I, O>(read: I, write: O, config: BkmrConfig) -> i32
+where
+    I: tokio::io::AsyncRead + Unpin,
+    O: tokio::io::AsyncWrite,
+{
+    let (service, socket) = lsp_service(move |client| {
+        tracing::debug!("Creating new LSP backend instance");
+        BkmrLspBackend::with_config(client, config)
+    });
+    serve(read, write, service, socket).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_listen_uris_when_parsing_then_accepts_stdio_and_tcp() {
+        // Act
+        let stdio = "stdio".parse::<Transport>();
+        let tcp = "tcp://127.0.0.1:9257".parse::<Transport>();
+        let hostname = "TCP://localhost:9257/".parse::<Transport>();
+
+        // Assert
+        assert_eq!(stdio, Ok(Transport::Stdio));
+        assert_eq!(
+            tcp,
+            Ok(Transport::Tcp {
+                address: "127.0.0.1:9257".to_string(),
+                keep_listening: false,
+            })
+        );
+        assert_eq!(
+            hostname.map(|transport| transport.with_keep_listening(true)),
+            Ok(Transport::Tcp {
+                address: "localhost:9257".to_string(),
+                keep_listening: true,
+            })
+        );
+    }
+
+    #[test]
+    fn given_invalid_listen_uris_when_parsing_then_names_the_problem() {
+        // Act
+        let websocket = "ws://127.0.0.1:9257".parse::<Transport>();
+        let no_port = "tcp://127.0.0.1".parse::<Transport>();
+        let bad_port = "tcp://127.0.0.1:99999".parse::<Transport>();
+        let no_scheme = "127.0.0.1:9257".parse::<Transport>();
+
+        // Assert
+        assert!(
+            websocket
+                .expect_err("websocket")
+                .contains("unsupported transport scheme 'ws'")
+        );
+        assert!(
+            no_port
+                .expect_err("no port")
+                .contains("invalid TCP address")
+        );
+        assert!(
+            bad_port
+                .expect_err("bad port")
+                .contains("invalid TCP address")
+        );
+        assert!(
+            no_scheme
+                .expect_err("no scheme")
+                .contains("invalid transport")
+        );
+    }
+
+    #[tokio::test]
+    async fn given_taken_port_when_listening_then_fails_with_address() {
+        // Arrange
+        let taken = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let address = taken.local_addr().expect("local address").to_string();
+        let transport = Transport::Tcp {
+            address: address.clone(),
+            keep_listening: false,
+        };
+
+        // Act
+        let result = run_server(transport, BkmrConfig::default()).await;
+
+        // Assert
+        let message = format!("{:#}", result.expect_err("port taken"));
+        assert!(message.contains(&format!("listen on tcp://{}", address)));
+    }
+}
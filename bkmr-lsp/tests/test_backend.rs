@@ -10,6 +10,53 @@ async fn test_config_defaults() {
     assert_eq!(config.max_completions, 50);
 }
 
+#[test_log::test(tokio::test)]
+async fn test_config_repository_plumbing() {
+    let config = BkmrConfig {
+        bkmr_binary: "/opt/bkmr/bin/bkmr".to_string(),
+        db_path: Some("/home/user/work.db".to_string()),
+        max_completions: 25,
+        ..Default::default()
+    };
+
+    let repo_config = config.repository_config();
+    assert_eq!(repo_config.binary_path, "/opt/bkmr/bin/bkmr");
+    assert_eq!(repo_config.db_path.as_deref(), Some("/home/user/work.db"));
+    assert_eq!(repo_config.max_results, 25);
+    assert!(BkmrConfig::default().repository_config().db_path.is_none());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_config_resolved_author_keeps_configured_author() {
+    let config = BkmrConfig {
+        author: Some("Jane Doe".to_string()),
+        ..Default::default()
+    };
+
+    let resolved = config.with_resolved_author().await;
+
+    assert_eq!(resolved.author.as_deref(), Some("Jane Doe"));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_config_validate_binary() {
+    let relative = BkmrConfig::default();
+    assert!(relative.validate_binary().is_ok());
+
+    let missing = BkmrConfig {
+        bkmr_binary: "/nonexistent/bkmr".to_string(),
+        ..Default::default()
+    };
+    let error = missing.validate_binary().unwrap_err().to_string();
+    assert!(error.contains("/nonexistent/bkmr"));
+
+    let directory = BkmrConfig {
+        bkmr_binary: std::env::temp_dir().to_string_lossy().to_string(),
+        ..Default::default()
+    };
+    assert!(directory.validate_binary().is_err());
+}
+
 #[test_log::test(tokio::test)]
 async fn test_snippet_deserialization() {
     let json = r#"{
@@ -80,3 +127,129 @@ fn test_snippet_tags() {
     assert!(snippet.tags.contains(&"_snip_".to_string()));
     assert!(snippet.tags.contains(&"universal".to_string()));
 }
+
+fn initialization_options(options: serde_json::Value) -> bkmr_lsp::InitializationOptions {
+    let params: tower_lsp::lsp_types::InitializeParams =
+        serde_json::from_value(serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": options,
+        }))
+        .expect("valid initialize params");
+    bkmr_lsp::InitializationOptions::from_value(
+        params
+            .initialization_options
+            .as_ref()
+            .expect("initialization options"),
+    )
+}
+
+#[test_log::test(tokio::test)]
+async fn test_config_initialization_options_override_command_line() {
+    let options = initialization_options(serde_json::json!({
+        "bkmrBinary": "/opt/bkmr",
+        "maxCompletions": 10,
+        "enableInterpolation": true,
+        "triggerChar": ":",
+        "ranking": "usage",
+        "languageMap": {"markdown": ["md", "notes"], "vue": "javascript"},
+    }));
+
+    let config = options.merge_into(BkmrConfig {
+        cache_ttl_seconds: 5,
+        ..Default::default()
+    });
+
+    assert_eq!(config.bkmr_binary, "/opt/bkmr");
+    assert_eq!(config.max_completions, 10);
+    assert!(config.enable_interpolation);
+    assert_eq!(config.trigger_char, Some(':'));
+    assert_eq!(config.ranking, bkmr_lsp::domain::RankingStrategy::Usage);
+    assert_eq!(config.cache_ttl_seconds, 5);
+    assert_eq!(
+        config.language_map,
+        "markdown=md,markdown=notes,vue=javascript"
+            .parse()
+            .expect("valid language map")
+    );
+}
+
+#[test_log::test(tokio::test)]
+async fn test_config_initialization_options_type_mismatch_keeps_default_for_field() {
+    let options = initialization_options(serde_json::json!({
+        "maxCompletions": "ten",
+        "cacheTtlSeconds": 5,
+        "triggerChar": "too long",
+        "ranking": "random",
+    }));
+
+    let config = options.merge_into(BkmrConfig::default());
+
+    assert_eq!(config.max_completions, 50);
+    assert_eq!(config.cache_ttl_seconds, 5);
+    assert_eq!(config.trigger_char, None);
+    assert_eq!(config.ranking, bkmr_lsp::domain::RankingStrategy::default());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_config_initialization_options_unknown_keys_are_collected() {
+    let options = initialization_options(serde_json::json!({
+        "escapeVariables": false,
+        "prefetch": false,
+    }));
+
+    assert_eq!(options.unknown_keys, vec!["escapeVariables".to_string()]);
+    assert_eq!(options.prefetch, Some(false));
+}
+
+#[test_log::test(tokio::test)]
+async fn test_config_initialization_options_inline_completions_reach_config() {
+    let options = initialization_options(serde_json::json!({
+        "inlineCompletions": true,
+    }));
+
+    let config = options.clone().merge_into(BkmrConfig::default());
+
+    assert!(options.unknown_keys.is_empty());
+    assert_eq!(options.inline_completions, Some(true));
+    assert!(config.inline_completions);
+}
+
+#[test_log::test(tokio::test)]
+async fn test_config_initialization_options_non_object_is_ignored() {
+    let options = bkmr_lsp::InitializationOptions::from_value(&serde_json::json!("bkmr"));
+
+    assert_eq!(options, bkmr_lsp::InitializationOptions::default());
+}
+
+#[test_log::test(tokio::test)]
+async fn test_config_folder_repositories_from_options_win_over_folder_files() {
+    let options = initialization_options(serde_json::json!({
+        "folderRepositories": [
+            {"folder": "file:///work/clientA", "dbPath": "/dbs/a.db"},
+        ],
+    }));
+    let file = |folder: &str, db_path: &str| bkmr_lsp::domain::FolderRepository {
+        folder: folder.to_string(),
+        db_path: Some(db_path.to_string()),
+        bkmr_binary: None,
+        from_file: true,
+    };
+
+    let config = options
+        .merge_into(BkmrConfig::default())
+        .with_folder_files(vec![file("/work/clientA", "/work/clientA/other.db")])
+        .with_folder_files(vec![file("/work/clientB", "/work/clientB/bkmr.db")]);
+
+    let databases: Vec<Option<&str>> = config
+        .folder_repositories
+        .iter()
+        .map(|folder| folder.db_path.as_deref())
+        .collect();
+    assert_eq!(
+        databases,
+        vec![Some("/dbs/a.db"), Some("/work/clientB/bkmr.db")]
+    );
+    let client_b = config.for_folder(&config.folder_repositories[1]);
+    assert_eq!(client_b.db_path.as_deref(), Some("/work/clientB/bkmr.db"));
+    assert_eq!(client_b.bkmr_binary, config.bkmr_binary);
+}
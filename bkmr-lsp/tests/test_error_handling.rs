@@ -1,11 +1,13 @@
 // Error handling and edge case testing
 
-use bkmr_lsp::BkmrConfig;
+use bkmr_lsp::{BkmrConfig, ErrorMode};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::timeout;
+use tower_lsp::jsonrpc;
 
 mod test_utils;
-use test_utils::{SnippetBuilder, TestContext};
+use test_utils::{SnippetBuilder, StaticSnippetRepository, TestContext};
 
 #[test_log::test(tokio::test)]
 async fn test_config_edge_cases() {
@@ -14,6 +16,7 @@ async fn test_config_edge_cases() {
         bkmr_binary: "".to_string(),
         max_completions: 0,
         enable_interpolation: false,
+        ..Default::default()
     };
 
     assert_eq!(config.bkmr_binary, "");
@@ -162,3 +165,110 @@ async fn test_lsp_error_handling() {
         }
     }
 }
+
+/// Server whose bkmr searches all fail, reporting failures in the given mode
+async fn failing_server(errors: ErrorMode) -> anyhow::Result<TestContext> {
+    let repository = StaticSnippetRepository::new(vec![])
+        .with_fetch_error("bkmr command failed: database is locked");
+    let mut context = TestContext::with_repository(
+        BkmrConfig {
+            errors,
+            prefetch: false,
+            ..Default::default()
+        },
+        Arc::new(repository),
+    );
+    context.initialize().await?;
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"hello","uri":"file:///tmp/main.rs","version":0}}}"#,
+    ]).await?;
+    Ok(context)
+}
+
+fn completion_request(id: i64) -> jsonrpc::Request {
+    jsonrpc::Request::build("textDocument/completion")
+        .id(id)
+        .params(serde_json::json!({
+            "textDocument": { "uri": "file:///tmp/main.rs" },
+            "position": { "line": 0, "character": 5 },
+            "context": { "triggerKind": 1 }
+        }))
+        .finish()
+}
+
+fn unknown_command_request(id: i64) -> jsonrpc::Request {
+    jsonrpc::Request::build("workspace/executeCommand")
+        .id(id)
+        .params(serde_json::json!({ "command": "bkmr.doesNotExist", "arguments": [] }))
+        .finish()
+}
+
+/// Response to the request with the given id and the types of log messages sent before it
+async fn response_and_log_types(
+    context: &mut TestContext,
+    request: jsonrpc::Request,
+) -> anyhow::Result<(serde_json::Value, Vec<serde_json::Value>)> {
+    let id = request.id().cloned().expect("request id");
+    context.send(&request).await?;
+    let id = match id {
+        jsonrpc::Id::Number(id) => id,
+        other => panic!("expected numeric id, got {:?}", other),
+    };
+    let messages = context.messages_until_response(id).await?;
+    let response = messages
+        .iter()
+        .find(|message| message.get("method").is_none() && message["id"] == id)
+        .cloned()
+        .expect("response");
+    let log_types = messages
+        .iter()
+        .filter(|message| message["method"] == "window/logMessage")
+        .map(|message| message["params"]["type"].clone())
+        .collect();
+    Ok((response, log_types))
+}
+
+#[test_log::test(tokio::test)]
+async fn test_respond_mode_returns_request_failed_errors() -> anyhow::Result<()> {
+    let mut context = failing_server(ErrorMode::Respond).await?;
+
+    let (completion, log_types) =
+        response_and_log_types(&mut context, completion_request(2)).await?;
+    assert_eq!(completion["error"]["code"], -32803);
+    assert!(
+        completion["error"]["message"]
+            .as_str()
+            .is_some_and(|message| message.contains("database is locked"))
+    );
+    assert!(completion.get("result").is_none());
+    assert!(log_types.is_empty());
+
+    let (command, _) = response_and_log_types(&mut context, unknown_command_request(3)).await?;
+    assert_eq!(command["error"]["code"], -32803);
+    assert_eq!(
+        command["error"]["message"],
+        "Unknown command: bkmr.doesNotExist"
+    );
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_silent_and_log_modes_return_empty_results() -> anyhow::Result<()> {
+    for (errors, log_type) in [(ErrorMode::Silent, 1), (ErrorMode::Log, 2)] {
+        let mut context = failing_server(errors).await?;
+
+        let (completion, log_types) =
+            response_and_log_types(&mut context, completion_request(2)).await?;
+        assert_eq!(completion["result"], serde_json::json!([]));
+        assert!(completion.get("error").is_none());
+        assert_eq!(log_types, vec![serde_json::json!(log_type)]);
+
+        let (command, log_types) =
+            response_and_log_types(&mut context, unknown_command_request(3)).await?;
+        assert_eq!(command["result"], serde_json::Value::Null);
+        assert_eq!(log_types, vec![serde_json::json!(log_type)]);
+    }
+
+    Ok(())
+}
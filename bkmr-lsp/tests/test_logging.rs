@@ -0,0 +1,39 @@
+use bkmr_lsp::logging::{LogConfig, LogFile, init_logging};
+
+/// Installs the global subscriber, so this binary holds a single test
+#[test]
+fn test_init_logging_writes_to_rotated_file() {
+    let dir = std::env::temp_dir().join(format!("bkmr-lsp-logging-{}", std::process::id()));
+    let path = dir.join("bkmr-lsp.log");
+    let config = LogConfig {
+        file: Some(path.clone()),
+        keep: 2,
+        level: Some("info".to_string()),
+        ..LogConfig::default()
+    };
+
+    let guard = init_logging(&config).expect("init logging");
+    tracing::info!("logging test line");
+    drop(guard);
+
+    let log_file = LogFile::from_path(&path, config.keep).expect("log file");
+    let written: Vec<_> = std::fs::read_dir(&dir)
+        .expect("read log dir")
+        .map(|entry| entry.expect("dir entry").path())
+        .filter(|path| {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            name.starts_with("bkmr-lsp.") && name.ends_with(".log")
+        })
+        .collect();
+    assert_eq!(written.len(), 1);
+    let name = written[0]
+        .file_name()
+        .and_then(|n| n.to_str())
+        .expect("file name");
+    let date = &name["bkmr-lsp.".len()..name.len() - ".log".len()];
+    assert_eq!(log_file.file_name(date), name);
+    let content = std::fs::read_to_string(&written[0]).expect("read log file");
+    assert!(content.contains("logging test line"));
+
+    std::fs::remove_dir_all(&dir).expect("remove log dir");
+}
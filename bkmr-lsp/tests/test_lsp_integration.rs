@@ -4,7 +4,9 @@ use std::str::FromStr;
 use tower_lsp::{jsonrpc, lsp_types::*};
 
 mod test_utils;
-use test_utils::TestContext;
+use bkmr_lsp::BkmrConfig;
+use std::sync::Arc;
+use test_utils::{SnippetBuilder, StaticSnippetRepository, TestContext};
 
 #[test_log::test(tokio::test)]
 async fn test_lsp_initialize() -> anyhow::Result<()> {
@@ -31,7 +33,28 @@ async fn test_lsp_initialize() -> anyhow::Result<()> {
     assert!(response.capabilities.completion_provider.is_some());
     assert_eq!(
         response.capabilities.text_document_sync,
-        Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL))
+        Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::INCREMENTAL),
+                save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                    include_text: Some(true)
+                })),
+                ..Default::default()
+            }
+        ))
+    );
+
+    // Workspace folders are tracked as base for relative file paths
+    let workspace_folders = response
+        .capabilities
+        .workspace
+        .and_then(|workspace| workspace.workspace_folders)
+        .expect("workspace folder capability");
+    assert_eq!(workspace_folders.supported, Some(true));
+    assert_eq!(
+        workspace_folders.change_notifications,
+        Some(OneOf::Left(true))
     );
 
     // Verify execute command provider for bkmr commands
@@ -46,6 +69,36 @@ async fn test_lsp_initialize() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test_log::test(tokio::test)]
+async fn test_lsp_initialize_applies_initialization_options() -> anyhow::Result<()> {
+    let mut context = TestContext::new();
+
+    let request = jsonrpc::Request::build("initialize")
+        .id(1)
+        .params(serde_json::json!({
+            "capabilities": {},
+            "initializationOptions": {
+                "triggerChar": ":",
+                "maxCompletions": "not a number",
+                "unknownSetting": true
+            }
+        }))
+        .finish();
+
+    let response = context.request::<InitializeResult>(&request).await?;
+
+    let completion_provider = response
+        .capabilities
+        .completion_provider
+        .expect("completion provider");
+    assert_eq!(
+        completion_provider.trigger_characters,
+        Some(vec![":".to_string()])
+    );
+
+    Ok(())
+}
+
 #[test_log::test(tokio::test)]
 async fn test_lsp_document_lifecycle() -> anyhow::Result<()> {
     let mut context = TestContext::new();
@@ -175,20 +228,31 @@ async fn test_lsp_execute_command() -> anyhow::Result<()> {
         }))
         .finish();
 
-    let result = context
-        .request::<Option<serde_json::Value>>(&execute_command_request)
-        .await;
+    context.send(&execute_command_request).await?;
+    let messages = context.messages_until_response(3).await?;
 
-    // The command should execute without error
-    match result {
-        Ok(_) => {
-            tracing::info!("Execute command successful");
-        }
-        Err(e) => {
-            tracing::warn!("Execute command failed: {}", e);
-            // May fail if document not actually open in server
-        }
-    }
+    // The object form is accepted, the server goes on to ask the client to apply the edit
+    let response = response_with_id(&messages, 3);
+    assert!(response.get("error").is_none(), "{}", response);
+    assert!(
+        messages
+            .iter()
+            .any(|message| message["method"] == "workspace/applyEdit")
+    );
+
+    // Malformed arguments are rejected with InvalidParams naming the field
+    let missing_uri_request = jsonrpc::Request::build("workspace/executeCommand")
+        .id(4)
+        .params(serde_json::json!({
+            "command": "bkmr.insertFilepathComment",
+            "arguments": [{ "position": { "line": 0, "character": 0 } }]
+        }))
+        .finish();
+    context.send(&missing_uri_request).await?;
+    let messages = context.messages_until_response(4).await?;
+    let error = &response_with_id(&messages, 4)["error"];
+    assert_eq!(error["code"], -32602);
+    assert_eq!(error["data"]["field"], "uri");
 
     Ok(())
 }
@@ -218,24 +282,49 @@ async fn test_lsp_configuration_change() -> anyhow::Result<()> {
     Ok(())
 }
 
-// Note: Shutdown test commented out as it causes hanging due to server termination
-// #[test_log::test(tokio::test)]
-// async fn test_lsp_server_shutdown() -> anyhow::Result<()> {
-//     let mut context = TestContext::new();
-//     context.initialize().await?;
-//
-//     // Test shutdown sequence
-//     let shutdown_request = jsonrpc::Request::build("shutdown")
-//         .id(999)
-//         .finish();
-//
-//     let response = context.request::<serde_json::Value>(&shutdown_request).await?;
-//
-//     // Shutdown should return null
-//     assert_eq!(response, serde_json::Value::Null);
-//
-//     Ok(())
-// }
+#[test_log::test(tokio::test)]
+async fn given_shutdown_when_exit_arrives_then_server_stops_with_code_zero() -> anyhow::Result<()> {
+    // Arrange
+    let mut context = TestContext::with_snippets(vec![]);
+    context.initialize().await?;
+
+    // Act
+    let shutdown = jsonrpc::Request::build("shutdown").id(999).finish();
+    let response = context.request::<serde_json::Value>(&shutdown).await?;
+    let after_shutdown = jsonrpc::Request::build("bkmr/status").id(1000).finish();
+    let rejected = context.request::<serde_json::Value>(&after_shutdown).await;
+    context
+        .send(&jsonrpc::Request::build("exit").finish())
+        .await?;
+    let exit_code =
+        tokio::time::timeout(std::time::Duration::from_secs(5), context._server).await??;
+
+    // Assert
+    assert_eq!(response, serde_json::Value::Null);
+    let error = rejected.expect_err("requests after shutdown are rejected");
+    assert!(error.to_string().contains("InvalidRequest"), "{error}");
+    assert_eq!(exit_code, 0);
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn given_no_shutdown_when_exit_arrives_then_server_stops_with_code_one() -> anyhow::Result<()>
+{
+    // Arrange
+    let mut context = TestContext::with_snippets(vec![]);
+    context.initialize().await?;
+
+    // Act
+    context
+        .send(&jsonrpc::Request::build("exit").finish())
+        .await?;
+    let exit_code =
+        tokio::time::timeout(std::time::Duration::from_secs(5), context._server).await??;
+
+    // Assert
+    assert_eq!(exit_code, 1);
+    Ok(())
+}
 
 #[test_log::test(tokio::test)]
 async fn test_lsp_multiple_documents() -> anyhow::Result<()> {
@@ -277,3 +366,1486 @@ async fn test_lsp_multiple_documents() -> anyhow::Result<()> {
     // If we get here without panics/errors, multi-document handling works
     Ok(())
 }
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_completion_resolve_round_trip() -> anyhow::Result<()> {
+    let snippet = SnippetBuilder::new()
+        .with_id(7)
+        .with_title("hello")
+        .with_content("println!(\"Hello\");")
+        .with_language("rust")
+        .with_snippet_tag()
+        .build();
+    let mut context = TestContext::with_snippets(vec![snippet]);
+
+    let initialize_request = jsonrpc::Request::build("initialize")
+        .id(1)
+        .params(serde_json::json!({
+            "capabilities": {
+                "textDocument": {
+                    "completion": {
+                        "completionItem": {
+                            "snippetSupport": true,
+                            "resolveSupport": {
+                                "properties": ["documentation", "textEdit", "insertText"]
+                            }
+                        }
+                    }
+                }
+            }
+        }))
+        .finish();
+    let response = context
+        .request::<InitializeResult>(&initialize_request)
+        .await?;
+    assert_eq!(
+        response
+            .capabilities
+            .completion_provider
+            .and_then(|provider| provider.resolve_provider),
+        Some(true)
+    );
+
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"hel","uri":"file:///tmp/main.rs","version":0}}}"#
+    ]).await?;
+
+    let completion_request = jsonrpc::Request::build("textDocument/completion")
+        .id(2)
+        .params(serde_json::json!({
+            "position": { "line": 0, "character": 3 },
+            "textDocument": { "uri": "file:///tmp/main.rs" },
+            "context": { "triggerKind": 1 }
+        }))
+        .finish();
+    let items = match context
+        .request::<Option<CompletionResponse>>(&completion_request)
+        .await?
+    {
+        Some(CompletionResponse::List(list)) => list.items,
+        Some(CompletionResponse::Array(items)) => items,
+        None => vec![],
+    };
+
+    assert_eq!(items.len(), 1);
+    let item = items[0].clone();
+    assert_eq!(item.label, "hello");
+    assert!(item.data.is_some());
+    assert!(item.documentation.is_none());
+    assert!(item.text_edit.is_none());
+
+    let resolve_request = jsonrpc::Request::build("completionItem/resolve")
+        .id(3)
+        .params(serde_json::to_value(&item)?)
+        .finish();
+    let resolved = context.request::<CompletionItem>(&resolve_request).await?;
+
+    assert_eq!(resolved.label, "hello");
+    assert!(resolved.documentation.is_some());
+    match resolved.text_edit {
+        Some(CompletionTextEdit::Edit(edit)) => {
+            assert_eq!(edit.new_text, "println!(\"Hello\");");
+        }
+        other => panic!("expected text edit, got {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_hover_shows_snippet_for_title_under_cursor() -> anyhow::Result<()> {
+    let repository = Arc::new(StaticSnippetRepository::new(vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("retry")
+            .with_content("for attempt in 0..3 {}")
+            .with_description("Retry loop")
+            .with_language("rust")
+            .with_snippet_tag()
+            .build(),
+        SnippetBuilder::new()
+            .with_id(2)
+            .with_title("ok")
+            .with_content("Ok(())")
+            .with_language("rust")
+            .with_snippet_tag()
+            .build(),
+    ]));
+    let mut context = TestContext::with_repository(
+        BkmrConfig {
+            prefetch: false,
+            ..Default::default()
+        },
+        repository.clone(),
+    );
+
+    let initialize_request = jsonrpc::Request::build("initialize")
+        .id(1)
+        .params(serde_json::json!({ "capabilities": {} }))
+        .finish();
+    let response = context
+        .request::<InitializeResult>(&initialize_request)
+        .await?;
+    assert_eq!(
+        response.capabilities.hover_provider,
+        Some(HoverProviderCapability::Simple(true))
+    );
+
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"let x = retry;\nok","uri":"file:///tmp/main.rs","version":0}}}"#
+    ]).await?;
+
+    let hover_request = |id: i64, line: u32, character: u32| {
+        jsonrpc::Request::build("textDocument/hover")
+            .id(id)
+            .params(serde_json::json!({
+                "position": { "line": line, "character": character },
+                "textDocument": { "uri": "file:///tmp/main.rs" }
+            }))
+            .finish()
+    };
+
+    let hover = context
+        .request::<Option<Hover>>(&hover_request(2, 0, 10))
+        .await?
+        .expect("hover for retry");
+    match hover.contents {
+        HoverContents::Markup(markup) => {
+            assert!(markup.value.starts_with("Retry loop"));
+            assert!(
+                markup
+                    .value
+                    .contains("```rust\nfor attempt in 0..3 {}\n```")
+            );
+        }
+        other => panic!("expected markup, got {:?}", other),
+    }
+    assert_eq!(
+        hover.range,
+        Some(Range::new(Position::new(0, 8), Position::new(0, 13)))
+    );
+    assert_eq!(repository.fetch_count(), 1);
+
+    // Words shorter than three characters never reach the repository
+    let short = context
+        .request::<Option<Hover>>(&hover_request(3, 1, 1))
+        .await?;
+    assert!(short.is_none());
+    assert_eq!(repository.fetch_count(), 1);
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_save_with_text_resyncs_completion_context() -> anyhow::Result<()> {
+    let snippets = ["hello", "world"]
+        .iter()
+        .enumerate()
+        .map(|(id, title)| {
+            SnippetBuilder::new()
+                .with_id(id as i32 + 1)
+                .with_title(title)
+                .with_content(title)
+                .with_language("rust")
+                .with_snippet_tag()
+                .build()
+        })
+        .collect();
+    let mut context = TestContext::with_snippets(snippets);
+    context.initialize().await?;
+
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"he","uri":"file:///tmp/main.rs","version":1}}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///tmp/main.rs","version":2},"contentChanges":[{"range":{"start":{"line":0,"character":2},"end":{"line":0,"character":2}},"text":"l"}]}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didSave","params":{"textDocument":{"uri":"file:///tmp/main.rs"},"text":"wor"}}"#,
+    ]).await?;
+
+    let completion_request = jsonrpc::Request::build("textDocument/completion")
+        .id(2)
+        .params(serde_json::json!({
+            "position": { "line": 0, "character": 3 },
+            "textDocument": { "uri": "file:///tmp/main.rs" },
+            "context": { "triggerKind": 1 }
+        }))
+        .finish();
+    let items = match context
+        .request::<Option<CompletionResponse>>(&completion_request)
+        .await?
+    {
+        Some(CompletionResponse::List(list)) => list.items,
+        Some(CompletionResponse::Array(items)) => items,
+        None => vec![],
+    };
+
+    let labels: Vec<&str> = items.iter().map(|item| item.label.as_str()).collect();
+    assert_eq!(labels, vec!["world"]);
+    match &items[0].text_edit {
+        Some(CompletionTextEdit::Edit(edit)) => {
+            assert_eq!(
+                edit.range,
+                Range::new(Position::new(0, 0), Position::new(0, 3))
+            );
+        }
+        other => panic!("expected text edit, got {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_completion_in_unopened_document_uses_extension_language() -> anyhow::Result<()> {
+    let snippets = [(1, "pyprint", "python"), (2, "rsprint", "rust")]
+        .iter()
+        .map(|(id, title, language)| {
+            SnippetBuilder::new()
+                .with_id(*id)
+                .with_title(title)
+                .with_content(title)
+                .with_language(language)
+                .with_snippet_tag()
+                .build()
+        })
+        .collect();
+    let mut context = TestContext::with_snippets(snippets);
+    context.initialize().await?;
+
+    let completion = |id: i64, uri: &str| {
+        jsonrpc::Request::build("textDocument/completion")
+            .id(id)
+            .params(serde_json::json!({
+                "position": { "line": 4, "character": 2 },
+                "textDocument": { "uri": uri },
+                "context": { "triggerKind": 1 }
+            }))
+            .finish()
+    };
+    let items = |response: Option<CompletionResponse>| match response {
+        Some(CompletionResponse::List(list)) => list.items,
+        Some(CompletionResponse::Array(items)) => items,
+        None => vec![],
+    };
+    let python = items(
+        context
+            .request::<Option<CompletionResponse>>(&completion(2, "file:///tmp/restarted.py"))
+            .await?,
+    );
+    let unknown = items(
+        context
+            .request::<Option<CompletionResponse>>(&completion(3, "file:///tmp/notes.xyz"))
+            .await?,
+    );
+
+    let labels = |items: &[CompletionItem]| -> Vec<String> {
+        items.iter().map(|item| item.label.clone()).collect()
+    };
+    assert_eq!(labels(&python), vec!["pyprint"]);
+    assert_eq!(python[0].insert_text.as_deref(), Some("pyprint"));
+    assert_eq!(python[0].text_edit, None);
+    let mut unknown_labels = labels(&unknown);
+    unknown_labels.sort();
+    assert_eq!(unknown_labels, vec!["pyprint", "rsprint"]);
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_code_actions_offer_bkmr_commands() -> anyhow::Result<()> {
+    let mut context = TestContext::new();
+    context.initialize().await?;
+
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"\n  fn main() {}\n","uri":"file:///tmp/main.rs","version":1}}}"#,
+    ]).await?;
+
+    let code_actions = |id: i64, range: Range| {
+        jsonrpc::Request::build("textDocument/codeAction")
+            .id(id)
+            .params(serde_json::json!({
+                "textDocument": { "uri": "file:///tmp/main.rs" },
+                "range": range,
+                "context": { "diagnostics": [] }
+            }))
+            .finish()
+    };
+    let commands = |response: Option<CodeActionResponse>| -> Vec<CodeAction> {
+        response
+            .unwrap_or_default()
+            .into_iter()
+            .map(|action| match action {
+                CodeActionOrCommand::CodeAction(action) => action,
+                other => panic!("expected code action, got {:?}", other),
+            })
+            .collect()
+    };
+
+    // Without a selection only the filepath comment is offered
+    let cursor = Range::new(Position::new(1, 2), Position::new(1, 2));
+    let actions = commands(
+        context
+            .request::<Option<CodeActionResponse>>(&code_actions(2, cursor))
+            .await?,
+    );
+    assert_eq!(actions.len(), 1);
+    assert_eq!(actions[0].title, "Insert filepath comment");
+    assert_eq!(actions[0].kind, Some(CodeActionKind::SOURCE));
+    let command = actions[0].command.as_ref().expect("command");
+    assert_eq!(command.command, "bkmr.insertFilepathComment");
+    assert_eq!(
+        command.arguments,
+        Some(vec![serde_json::json!("file:///tmp/main.rs")])
+    );
+
+    // A selection adds saving it as snippet, with uri, range and title filled in
+    let selection = Range::new(Position::new(0, 0), Position::new(2, 0));
+    let actions = commands(
+        context
+            .request::<Option<CodeActionResponse>>(&code_actions(3, selection))
+            .await?,
+    );
+    let titles: Vec<&str> = actions.iter().map(|action| action.title.as_str()).collect();
+    assert_eq!(
+        titles,
+        vec!["Insert filepath comment", "Save selection as bkmr snippet…"]
+    );
+    let command = actions[1].command.as_ref().expect("command");
+    assert_eq!(command.command, "bkmr.addSnippet");
+    assert_eq!(
+        command.arguments,
+        Some(vec![serde_json::json!({
+            "uri": "file:///tmp/main.rs",
+            "range": selection,
+            "title": "fn main() {}"
+        })])
+    );
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_workspace_symbols_list_snippets_with_bkmr_uris() -> anyhow::Result<()> {
+    let snippets = (1..=4)
+        .map(|id| {
+            SnippetBuilder::new()
+                .with_id(id)
+                .with_title(&format!("deploy step {}", id))
+                .with_language(if id % 2 == 0 { "sh" } else { "python" })
+                .with_snippet_tag()
+                .build()
+        })
+        .collect();
+    let config = BkmrConfig {
+        max_completions: 3,
+        ..Default::default()
+    };
+    let mut context =
+        TestContext::with_repository(config, Arc::new(StaticSnippetRepository::new(snippets)));
+    context.initialize().await?;
+
+    let request = jsonrpc::Request::build("workspace/symbol")
+        .id(2)
+        .params(serde_json::json!({ "query": "deploy" }))
+        .finish();
+    let symbols = context
+        .request::<Option<Vec<SymbolInformation>>>(&request)
+        .await?
+        .unwrap_or_default();
+
+    assert_eq!(symbols.len(), 3);
+    assert!(symbols.iter().all(|symbol| {
+        symbol.location.uri.scheme() == "bkmr"
+            && symbol.location.uri.as_str()
+                == format!("bkmr://snippet/{}", &symbol.name["deploy step ".len()..])
+    }));
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_status_reports_documents_completions_and_config() -> anyhow::Result<()> {
+    let snippets = vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("hello world")
+            .with_language("rust")
+            .with_snippet_tag()
+            .build(),
+    ];
+    let mut context = TestContext::with_snippets(snippets);
+    context.initialize().await?;
+
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"hel","uri":"file:///tmp/main.rs","version":0}}}"#
+    ]).await?;
+    let completion_request = jsonrpc::Request::build("textDocument/completion")
+        .id(2)
+        .params(serde_json::json!({
+            "position": { "character": 3, "line": 0 },
+            "textDocument": { "uri": "file:///tmp/main.rs" },
+            "context": { "triggerKind": 1 }
+        }))
+        .finish();
+    context
+        .request::<Option<CompletionResponse>>(&completion_request)
+        .await?;
+
+    let status_request = jsonrpc::Request::build("bkmr/status").id(3).finish();
+    let status = context
+        .request::<serde_json::Value>(&status_request)
+        .await?;
+
+    assert_eq!(status["serverVersion"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(status["bkmr"]["binary"], "bkmr");
+    assert_eq!(status["documents"]["count"], 1);
+    assert_eq!(
+        status["documents"]["languages"]["file:///tmp/main.rs"],
+        "rust"
+    );
+    assert_eq!(status["lastCompletionCount"], 1);
+    assert_eq!(
+        status["config"]["maxCompletions"],
+        BkmrConfig::default().max_completions
+    );
+
+    Ok(())
+}
+
+/// Slow completion over a server whose client shows work-done progress if `progress` is set
+async fn slow_completion_messages(
+    progress: bool,
+    work_done_token: Option<&str>,
+) -> anyhow::Result<Vec<serde_json::Value>> {
+    let snippets = vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("hello world")
+            .with_language("rust")
+            .with_snippet_tag()
+            .build(),
+    ];
+    let repository =
+        StaticSnippetRepository::new(snippets).with_delay(std::time::Duration::from_millis(600));
+    let mut context = TestContext::with_repository(BkmrConfig::default(), Arc::new(repository));
+    context
+        .initialize_with_capabilities(serde_json::json!({
+            "window": { "workDoneProgress": progress }
+        }))
+        .await?;
+
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"hel","uri":"file:///tmp/main.rs","version":0}}}"#
+    ]).await?;
+    let mut params = serde_json::json!({
+        "position": { "character": 3, "line": 0 },
+        "textDocument": { "uri": "file:///tmp/main.rs" },
+        "context": { "triggerKind": 1 }
+    });
+    if let Some(token) = work_done_token {
+        params["workDoneToken"] = serde_json::json!(token);
+    }
+    let completion_request = jsonrpc::Request::build("textDocument/completion")
+        .id(2)
+        .params(params)
+        .finish();
+    context.send(&completion_request).await?;
+    let mut messages = context.messages_until_response(2).await?;
+
+    // Notifications and responses travel separately, the progress end may come after the response
+    let is_end = |message: &serde_json::Value| message["params"]["value"]["kind"] == "end";
+    if progress && !messages.iter().any(is_end) {
+        messages.extend(context.messages_until(is_end).await?);
+    }
+    Ok(messages)
+}
+
+fn response_with_id(messages: &[serde_json::Value], id: i64) -> &serde_json::Value {
+    messages
+        .iter()
+        .find(|message| message.get("method").is_none() && message["id"] == id)
+        .expect("response with id")
+}
+
+fn messages_with_method<'a>(
+    messages: &'a [serde_json::Value],
+    method: &str,
+) -> Vec<&'a serde_json::Value> {
+    messages
+        .iter()
+        .filter(|message| message["method"] == method)
+        .collect()
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_slow_completion_reports_progress_on_created_token() -> anyhow::Result<()> {
+    let messages = slow_completion_messages(true, None).await?;
+
+    let created = messages_with_method(&messages, "window/workDoneProgress/create");
+    assert_eq!(created.len(), 1);
+    let token = &created[0]["params"]["token"];
+
+    let progress = messages_with_method(&messages, "$/progress");
+    let kinds: Vec<&serde_json::Value> = progress
+        .iter()
+        .map(|message| &message["params"]["value"]["kind"])
+        .collect();
+    assert_eq!(kinds, vec!["begin", "end"]);
+    assert!(
+        progress
+            .iter()
+            .all(|message| &message["params"]["token"] == token)
+    );
+    assert_eq!(progress[0]["params"]["value"]["title"], "Querying bkmr…");
+
+    let response = messages
+        .iter()
+        .find(|message| message["id"] == 2)
+        .expect("completion response");
+    assert_eq!(response["result"]["items"][0]["label"], "hello world");
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_slow_completion_reports_progress_on_client_token() -> anyhow::Result<()> {
+    let messages = slow_completion_messages(true, Some("completion-1")).await?;
+
+    assert!(messages_with_method(&messages, "window/workDoneProgress/create").is_empty());
+    let progress = messages_with_method(&messages, "$/progress");
+    assert_eq!(progress.len(), 2);
+    assert!(
+        progress
+            .iter()
+            .all(|message| message["params"]["token"] == "completion-1")
+    );
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_slow_completion_without_progress_capability_sends_no_progress()
+-> anyhow::Result<()> {
+    let messages = slow_completion_messages(false, Some("completion-1")).await?;
+
+    assert!(messages_with_method(&messages, "window/workDoneProgress/create").is_empty());
+    assert!(messages_with_method(&messages, "$/progress").is_empty());
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_completion_uses_insert_replace_edits_and_item_defaults() -> anyhow::Result<()> {
+    let snippets = vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("hello world")
+            .with_content("println!(\"Hello, $1\");")
+            .with_language("rust")
+            .with_snippet_tag()
+            .build(),
+        SnippetBuilder::new()
+            .with_id(2)
+            .with_title("hello plain")
+            .with_content("Hello!")
+            .with_tags(vec!["rust", "_snip_", "plain"])
+            .build(),
+    ];
+    let mut context = TestContext::with_snippets(snippets);
+    context
+        .initialize_with_capabilities(serde_json::json!({
+            "textDocument": {
+                "completion": {
+                    "completionItem": {
+                        "snippetSupport": true,
+                        "insertReplaceSupport": true,
+                        "documentationFormat": ["plaintext"]
+                    },
+                    "completionList": {
+                        "itemDefaults": ["editRange", "insertTextFormat"]
+                    }
+                }
+            }
+        }))
+        .await?;
+
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"hello","uri":"file:///tmp/main.rs","version":0}}}"#
+    ]).await?;
+    let completion_request = jsonrpc::Request::build("textDocument/completion")
+        .id(2)
+        .params(serde_json::json!({
+            "position": { "line": 0, "character": 3 },
+            "textDocument": { "uri": "file:///tmp/main.rs" },
+            "context": { "triggerKind": 1 }
+        }))
+        .finish();
+    let list = context
+        .request::<serde_json::Value>(&completion_request)
+        .await?;
+
+    let edit_range = &list["itemDefaults"]["editRange"];
+    assert_eq!(edit_range["insert"]["end"]["character"], 3);
+    assert_eq!(edit_range["replace"]["end"]["character"], 5);
+    assert_eq!(list["itemDefaults"]["insertTextFormat"], 2);
+
+    let items = list["items"].as_array().expect("completion items");
+    assert_eq!(items.len(), 2);
+    let snippet = items
+        .iter()
+        .find(|item| item["label"] == "hello world")
+        .expect("snippet item");
+    assert!(snippet.get("textEdit").is_none());
+    assert!(snippet.get("insertTextFormat").is_none());
+    assert_eq!(snippet["textEditText"], "println!(\"Hello, $1\");");
+    assert!(snippet["documentation"].is_string());
+    let plain = items
+        .iter()
+        .find(|item| item["label"] == "hello plain")
+        .expect("plain item");
+    assert_eq!(plain["insertTextFormat"], 1);
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_tag_filter_round_trip_scopes_completions_and_lists_tags() -> anyhow::Result<()> {
+    let snippets = vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("hello query")
+            .with_tags(vec!["rust", "_snip_", "sql"])
+            .build(),
+        SnippetBuilder::new()
+            .with_id(2)
+            .with_title("hello world")
+            .with_tags(vec!["rust", "_snip_"])
+            .build(),
+    ];
+    let mut context = TestContext::with_snippets(snippets);
+    context.initialize().await?;
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"hel","uri":"file:///tmp/main.rs","version":0}}}"#
+    ]).await?;
+
+    let list_tags_request = jsonrpc::Request::build("bkmr/listTags").id(2).finish();
+    let tags = context
+        .request::<serde_json::Value>(&list_tags_request)
+        .await?;
+    assert_eq!(
+        tags,
+        serde_json::json!([{ "tag": "rust", "count": 2 }, { "tag": "sql", "count": 1 }])
+    );
+
+    let mut next_id = 3;
+    let mut request = |method: &str, params: serde_json::Value| {
+        next_id += 1;
+        jsonrpc::Request::build(method.to_string())
+            .id(next_id)
+            .params(params)
+            .finish()
+    };
+    let completion_params = serde_json::json!({
+        "position": { "character": 3, "line": 0 },
+        "textDocument": { "uri": "file:///tmp/main.rs" },
+        "context": { "triggerKind": 1 }
+    });
+
+    let set = context
+        .request::<serde_json::Value>(&request(
+            "workspace/executeCommand",
+            serde_json::json!({ "command": "bkmr.setTagFilter", "arguments": ["sql"] }),
+        ))
+        .await?;
+    assert_eq!(set, serde_json::json!({ "tagFilter": "sql" }));
+    let filtered = context
+        .request::<CompletionList>(&request(
+            "textDocument/completion",
+            completion_params.clone(),
+        ))
+        .await?;
+    let labels: Vec<&str> = filtered
+        .items
+        .iter()
+        .map(|item| item.label.as_str())
+        .collect();
+    assert_eq!(labels, vec!["hello query"]);
+    let status = context
+        .request::<serde_json::Value>(&jsonrpc::Request::build("bkmr/status").id(99).finish())
+        .await?;
+    assert_eq!(status["tagFilters"]["global"], "sql");
+
+    let cleared = context
+        .request::<serde_json::Value>(&request(
+            "workspace/executeCommand",
+            serde_json::json!({ "command": "bkmr.setTagFilter", "arguments": [null] }),
+        ))
+        .await?;
+    assert_eq!(cleared, serde_json::json!({ "tagFilter": null }));
+    let unfiltered = context
+        .request::<CompletionList>(&request("textDocument/completion", completion_params))
+        .await?;
+    assert_eq!(unfiltered.items.len(), 2);
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_frontmatter_tags_line_completes_tags_instead_of_snippets() -> anyhow::Result<()> {
+    let snippets = vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("rust tips")
+            .with_tags(vec!["markdown", "rust", "_snip_"])
+            .build(),
+        SnippetBuilder::new()
+            .with_id(2)
+            .with_title("runbook")
+            .with_tags(vec!["markdown", "_snip_"])
+            .build(),
+    ];
+    let mut context = TestContext::with_snippets(snippets);
+    context.initialize().await?;
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"markdown","text":"---\ntags: ru\n---\nru","uri":"file:///tmp/notes.md","version":0}}}"#
+    ]).await?;
+    let completion = |id: i64, line: u32, character: u32| {
+        jsonrpc::Request::build("textDocument/completion")
+            .id(id)
+            .params(serde_json::json!({
+                "position": { "character": character, "line": line },
+                "textDocument": { "uri": "file:///tmp/notes.md" },
+                "context": { "triggerKind": 1 }
+            }))
+            .finish()
+    };
+
+    let tags = context
+        .request::<CompletionList>(&completion(2, 1, 8))
+        .await?;
+    let labels: Vec<(&str, Option<CompletionItemKind>)> = tags
+        .items
+        .iter()
+        .map(|item| (item.label.as_str(), item.kind))
+        .collect();
+    assert_eq!(labels, vec![("rust", Some(CompletionItemKind::KEYWORD))]);
+
+    let snippets = context
+        .request::<CompletionList>(&completion(3, 3, 2))
+        .await?;
+    let mut labels: Vec<&str> = snippets
+        .items
+        .iter()
+        .map(|item| item.label.as_str())
+        .collect();
+    labels.sort();
+    assert_eq!(labels, vec!["runbook", "rust tips"]);
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_inline_completion_previews_best_snippet_when_enabled() -> anyhow::Result<()> {
+    let snippets = vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("hello world")
+            .with_content("println!(\"hello ${1:world}\");")
+            .with_tags(vec!["rust", "_snip_"])
+            .build(),
+    ];
+    let config = BkmrConfig {
+        inline_completions: true,
+        ..Default::default()
+    };
+    let mut context =
+        TestContext::with_repository(config, Arc::new(StaticSnippetRepository::new(snippets)));
+
+    let initialize = jsonrpc::Request::build("initialize")
+        .id(1)
+        .params(serde_json::json!({ "capabilities": {} }))
+        .finish();
+    let result = context.request::<serde_json::Value>(&initialize).await?;
+    assert_eq!(
+        result["capabilities"]["inlineCompletionProvider"],
+        serde_json::json!({})
+    );
+    assert!(result["capabilities"].get("experimental").is_none());
+
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"    hello","uri":"file:///tmp/main.rs","version":0}}}"#
+    ]).await?;
+    let inline_request = |id: i64, character: u32| {
+        jsonrpc::Request::build("textDocument/inlineCompletion")
+            .id(id)
+            .params(serde_json::json!({
+                "textDocument": { "uri": "file:///tmp/main.rs" },
+                "position": { "line": 0, "character": character },
+                "context": { "triggerKind": 2 }
+            }))
+            .finish()
+    };
+
+    let list = context
+        .request::<serde_json::Value>(&inline_request(2, 9))
+        .await?;
+    assert_eq!(
+        list["items"][0]["insertText"],
+        serde_json::json!({ "kind": "snippet", "value": "println!(\"hello ${1:world}\");" })
+    );
+    assert_eq!(
+        list["items"][0]["range"],
+        serde_json::json!({
+            "start": { "line": 0, "character": 4 },
+            "end": { "line": 0, "character": 9 }
+        })
+    );
+
+    let short = context
+        .request::<serde_json::Value>(&inline_request(3, 6))
+        .await?;
+    assert_eq!(short, serde_json::json!({ "items": [] }));
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_completion_fires_past_line_end_of_crlf_and_in_empty_documents()
+-> anyhow::Result<()> {
+    let snippets = vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("hello world")
+            .with_content("println!(\"hello\");")
+            .with_tags(vec!["rust", "_snip_"])
+            .build(),
+        SnippetBuilder::new()
+            .with_id(2)
+            .with_title("main function")
+            .with_content("fn main() {\n    $0\n}")
+            .with_tags(vec!["rust", "_snip_"])
+            .build(),
+    ];
+    let mut context = TestContext::with_repository(
+        BkmrConfig::default(),
+        Arc::new(StaticSnippetRepository::new(snippets)),
+    );
+    context.initialize().await?;
+
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"fn main() {}\r\nhello","uri":"file:///tmp/crlf.rs","version":0}}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"","uri":"file:///tmp/empty.rs","version":0}}}"#,
+    ]).await?;
+    let completion_request = |id: i64, uri: &str, line: u32, character: u32| {
+        jsonrpc::Request::build("textDocument/completion")
+            .id(id)
+            .params(serde_json::json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": line, "character": character },
+                "context": { "triggerKind": 1 }
+            }))
+            .finish()
+    };
+    let labels = |response: Option<CompletionResponse>| match response {
+        Some(CompletionResponse::List(list)) => {
+            list.items.into_iter().map(|item| item.label).collect()
+        }
+        Some(CompletionResponse::Array(items)) => {
+            items.into_iter().map(|item| item.label).collect()
+        }
+        None => Vec::<String>::new(),
+    };
+
+    let past_line_end = context
+        .request::<Option<CompletionResponse>>(&completion_request(2, "file:///tmp/crlf.rs", 1, 6))
+        .await?;
+    assert_eq!(labels(past_line_end), vec!["hello world"]);
+
+    let empty = context
+        .request::<Option<CompletionResponse>>(&completion_request(3, "file:///tmp/empty.rs", 0, 0))
+        .await?;
+    assert_eq!(labels(empty), vec!["hello world", "main function"]);
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_completion_skips_repository_for_ignored_documents() -> anyhow::Result<()> {
+    let workspace = std::env::temp_dir().join(format!("bkmr-lsp-ignore-{}", std::process::id()));
+    std::fs::create_dir_all(&workspace)?;
+    std::fs::write(workspace.join(".bkmrlspignore"), "vendor/\n")?;
+    let folder = Url::from_directory_path(&workspace).expect("workspace URI");
+    let file = |relative_path: &str| {
+        Url::from_file_path(workspace.join(relative_path))
+            .expect("file URI")
+            .to_string()
+    };
+
+    let repository = Arc::new(StaticSnippetRepository::new(vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("hello world")
+            .with_content("println!(\"hello\");")
+            .with_tags(vec!["rust", "_snip_"])
+            .build(),
+    ]));
+    let mut context = TestContext::with_repository(
+        BkmrConfig {
+            prefetch: false,
+            ..Default::default()
+        },
+        repository.clone(),
+    );
+    let initialize_request = jsonrpc::Request::build("initialize")
+        .id(1)
+        .params(serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": folder, "name": "workspace" }],
+            "initializationOptions": { "disableFor": ["gitcommit"] }
+        }))
+        .finish();
+    context
+        .request::<InitializeResult>(&initialize_request)
+        .await?;
+
+    let open = |uri: &str, language_id: &str| {
+        jsonrpc::Request::build("textDocument/didOpen")
+            .params(serde_json::json!({
+                "textDocument": { "uri": uri, "languageId": language_id, "text": "hello", "version": 0 }
+            }))
+            .finish()
+    };
+    context
+        .send(&open(&file("vendor/serde/lib.rs"), "rust"))
+        .await?;
+    context
+        .send(&open(&file("COMMIT_EDITMSG"), "gitcommit"))
+        .await?;
+    context.send(&open(&file("src/main.rs"), "rust")).await?;
+    let completion_request = |id: i64, uri: &str| {
+        jsonrpc::Request::build("textDocument/completion")
+            .id(id)
+            .params(serde_json::json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": 0, "character": 5 },
+                "context": { "triggerKind": 1 }
+            }))
+            .finish()
+    };
+
+    let vendored = context
+        .request::<Option<CompletionResponse>>(&completion_request(2, &file("vendor/serde/lib.rs")))
+        .await?;
+    assert_eq!(vendored, Some(CompletionResponse::Array(vec![])));
+    let commit_message = context
+        .request::<Option<CompletionResponse>>(&completion_request(3, &file("COMMIT_EDITMSG")))
+        .await?;
+    assert_eq!(commit_message, Some(CompletionResponse::Array(vec![])));
+    assert_eq!(repository.fetch_count(), 0);
+
+    let source = context
+        .request::<Option<CompletionResponse>>(&completion_request(4, &file("src/main.rs")))
+        .await?;
+    assert!(matches!(source, Some(CompletionResponse::List(list)) if list.items.len() == 1));
+    assert_eq!(repository.fetch_count(), 1);
+
+    std::fs::remove_dir_all(workspace)?;
+    Ok(())
+}
+
+/// Methods of the server's requests and notifications up to the status response
+async fn registrations_after_initialized(
+    dynamic_registration: bool,
+) -> anyhow::Result<Vec<serde_json::Value>> {
+    let mut context = TestContext::with_repository(
+        BkmrConfig {
+            db_path: Some("/data/bkmr.db".to_string()),
+            snippet_dirs: vec!["/home/me/snippets".to_string()],
+            prefetch: false,
+            ..Default::default()
+        },
+        Arc::new(StaticSnippetRepository::new(vec![])),
+    );
+    context
+        .initialize_with_capabilities(serde_json::json!({
+            "workspace": {
+                "didChangeWatchedFiles": { "dynamicRegistration": dynamic_registration }
+            }
+        }))
+        .await?;
+    context
+        .send_all(&[r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#])
+        .await?;
+    let messages = context
+        .messages_until(|message| message["params"]["message"] == "bkmr-lsp server ready")
+        .await?;
+    Ok(messages
+        .into_iter()
+        .filter(|message| message["method"] == "client/registerCapability")
+        .flat_map(|message| {
+            message["params"]["registrations"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default()
+        })
+        .collect())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_initialized_watches_database_and_snippet_dirs_when_client_supports_it()
+-> anyhow::Result<()> {
+    let registrations = registrations_after_initialized(true).await?;
+    let sources = registrations
+        .iter()
+        .find(|registration| registration["id"] == "bkmr-lsp-snippet-sources")
+        .expect("snippet source registration");
+    assert_eq!(sources["method"], "workspace/didChangeWatchedFiles");
+    assert_eq!(
+        sources["registerOptions"]["watchers"],
+        serde_json::json!([
+            { "globPattern": "/data/bkmr.db*" },
+            { "globPattern": "/home/me/snippets/**" }
+        ])
+    );
+
+    let unsupported = registrations_after_initialized(false).await?;
+    assert!(unsupported.is_empty());
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_database_change_clears_cached_completions() -> anyhow::Result<()> {
+    let repository = Arc::new(StaticSnippetRepository::new(vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("hello world")
+            .with_tags(vec!["rust", "_snip_"])
+            .build(),
+    ]));
+    let mut context = TestContext::with_repository(
+        BkmrConfig {
+            db_path: Some("/data/bkmr.db".to_string()),
+            prefetch: false,
+            ..Default::default()
+        },
+        repository.clone(),
+    );
+    context.initialize().await?;
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"hel","uri":"file:///tmp/main.rs","version":0}}}"#
+    ]).await?;
+    let completion_request = |id: i64| {
+        jsonrpc::Request::build("textDocument/completion")
+            .id(id)
+            .params(serde_json::json!({
+                "position": { "character": 3, "line": 0 },
+                "textDocument": { "uri": "file:///tmp/main.rs" },
+                "context": { "triggerKind": 1 }
+            }))
+            .finish()
+    };
+    let changed = |path: &str| {
+        jsonrpc::Request::build("workspace/didChangeWatchedFiles")
+            .params(serde_json::json!({
+                "changes": [{ "uri": Url::from_file_path(path).expect("file URI"), "type": 2 }]
+            }))
+            .finish()
+    };
+
+    context
+        .request::<CompletionList>(&completion_request(2))
+        .await?;
+    context.send(&changed("/data/other.db")).await?;
+    context
+        .request::<CompletionList>(&completion_request(3))
+        .await?;
+    assert_eq!(repository.fetch_count(), 1);
+
+    context.send(&changed("/data/bkmr.db-wal")).await?;
+    context
+        .request::<CompletionList>(&completion_request(4))
+        .await?;
+    assert_eq!(repository.fetch_count(), 2);
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_clear_cache_command_makes_next_completion_fetch_again() -> anyhow::Result<()> {
+    let repository = Arc::new(StaticSnippetRepository::new(vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("hello world")
+            .with_tags(vec!["rust", "_snip_"])
+            .build(),
+    ]));
+    let mut context = TestContext::with_repository(
+        BkmrConfig {
+            prefetch: false,
+            ..Default::default()
+        },
+        repository.clone(),
+    );
+    let initialize_request = jsonrpc::Request::build("initialize")
+        .id(1)
+        .params(serde_json::json!({ "capabilities": {} }))
+        .finish();
+    let response = context
+        .request::<InitializeResult>(&initialize_request)
+        .await?;
+    let commands = response
+        .capabilities
+        .execute_command_provider
+        .expect("execute command provider")
+        .commands;
+    assert!(commands.contains(&"bkmr.clearCache".to_string()));
+    assert!(commands.contains(&"bkmr.reloadConfig".to_string()));
+
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"hel","uri":"file:///tmp/main.rs","version":0}}}"#
+    ]).await?;
+    let completion_request = |id: i64| {
+        jsonrpc::Request::build("textDocument/completion")
+            .id(id)
+            .params(serde_json::json!({
+                "position": { "character": 3, "line": 0 },
+                "textDocument": { "uri": "file:///tmp/main.rs" },
+                "context": { "triggerKind": 1 }
+            }))
+            .finish()
+    };
+
+    context
+        .request::<CompletionList>(&completion_request(2))
+        .await?;
+    context
+        .request::<CompletionList>(&completion_request(3))
+        .await?;
+    assert_eq!(repository.fetch_count(), 1);
+
+    let clear_request = jsonrpc::Request::build("workspace/executeCommand")
+        .id(4)
+        .params(serde_json::json!({ "command": "bkmr.clearCache", "arguments": [] }))
+        .finish();
+    let cleared = context.request::<serde_json::Value>(&clear_request).await?;
+    assert!(
+        cleared["evicted"]
+            .as_u64()
+            .is_some_and(|evicted| evicted >= 1)
+    );
+
+    let completions = context
+        .request::<CompletionList>(&completion_request(5))
+        .await?;
+    assert_eq!(completions.items.len(), 1);
+    assert_eq!(repository.fetch_count(), 2);
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_empty_query_completion_refreshes_cache_only_after_a_hit() -> anyhow::Result<()> {
+    let repository = Arc::new(StaticSnippetRepository::new(vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("hello world")
+            .with_tags(vec!["rust", "_snip_"])
+            .build(),
+    ]));
+    let mut context = TestContext::with_repository(BkmrConfig::default(), repository.clone());
+    context.initialize().await?;
+    // Not opened, so no didOpen prefetch warms the cache
+    let completion_request = |id: i64| {
+        jsonrpc::Request::build("textDocument/completion")
+            .id(id)
+            .params(serde_json::json!({
+                "position": { "character": 0, "line": 0 },
+                "textDocument": { "uri": "file:///tmp/main.rs" },
+                "context": { "triggerKind": 1 }
+            }))
+            .finish()
+    };
+    let settle = || tokio::time::sleep(std::time::Duration::from_millis(100));
+
+    context
+        .request::<CompletionList>(&completion_request(2))
+        .await?;
+    settle().await;
+    assert_eq!(repository.fetch_count(), 1);
+
+    context
+        .request::<CompletionList>(&completion_request(3))
+        .await?;
+    settle().await;
+    assert_eq!(repository.fetch_count(), 2);
+
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_reload_config_command_rereads_ignore_files_and_reports_health()
+-> anyhow::Result<()> {
+    let workspace = std::env::temp_dir().join(format!("bkmr-lsp-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&workspace)?;
+    std::fs::write(workspace.join(".bkmrlspignore"), "vendor/\n")?;
+    let folder = Url::from_directory_path(&workspace).expect("workspace URI");
+    let file = Url::from_file_path(workspace.join("generated/schema.rs"))
+        .expect("file URI")
+        .to_string();
+
+    let repository = Arc::new(StaticSnippetRepository::new(
+        ["hello world", "hello query"]
+            .iter()
+            .enumerate()
+            .map(|(id, title)| {
+                SnippetBuilder::new()
+                    .with_id(id as i32 + 1)
+                    .with_title(title)
+                    .with_tags(vec!["rust", "_snip_"])
+                    .build()
+            })
+            .collect(),
+    ));
+    let mut context = TestContext::with_repository(
+        BkmrConfig {
+            prefetch: false,
+            ..Default::default()
+        },
+        repository.clone(),
+    );
+    let initialize_request = jsonrpc::Request::build("initialize")
+        .id(1)
+        .params(serde_json::json!({
+            "capabilities": {},
+            "workspaceFolders": [{ "uri": folder, "name": "workspace" }],
+            "initializationOptions": { "maxCompletions": 1 }
+        }))
+        .finish();
+    context
+        .request::<InitializeResult>(&initialize_request)
+        .await?;
+    context
+        .send(
+            &jsonrpc::Request::build("textDocument/didOpen")
+                .params(serde_json::json!({
+                    "textDocument": { "uri": file, "languageId": "rust", "text": "hello", "version": 0 }
+                }))
+                .finish(),
+        )
+        .await?;
+    let completion_request = |id: i64| {
+        jsonrpc::Request::build("textDocument/completion")
+            .id(id)
+            .params(serde_json::json!({
+                "textDocument": { "uri": file },
+                "position": { "line": 0, "character": 5 },
+                "context": { "triggerKind": 1 }
+            }))
+            .finish()
+    };
+
+    let before = context
+        .request::<Option<CompletionResponse>>(&completion_request(2))
+        .await?;
+    assert!(matches!(before, Some(CompletionResponse::List(list)) if list.items.len() == 1));
+
+    // Changed without the client reporting it
+    std::fs::write(workspace.join(".bkmrlspignore"), "generated/\n")?;
+    let reload_request = jsonrpc::Request::build("workspace/executeCommand")
+        .id(3)
+        .params(serde_json::json!({ "command": "bkmr.reloadConfig" }))
+        .finish();
+    let summary = context
+        .request::<serde_json::Value>(&reload_request)
+        .await?;
+    assert_eq!(
+        summary,
+        serde_json::json!({ "healthy": true, "bkmrVersion": null })
+    );
+
+    let status = context
+        .request::<serde_json::Value>(&jsonrpc::Request::build("bkmr/status").id(4).finish())
+        .await?;
+    assert_eq!(status["config"]["maxCompletions"], 1);
+    let after = context
+        .request::<Option<CompletionResponse>>(&completion_request(5))
+        .await?;
+    assert_eq!(after, Some(CompletionResponse::Array(vec![])));
+    assert_eq!(repository.fetch_count(), 1);
+
+    std::fs::remove_dir_all(workspace)?;
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_workspace_folder_with_settings_file_rebuilds_folder_repositories()
+-> anyhow::Result<()> {
+    let workspace =
+        std::env::temp_dir().join(format!("bkmr-lsp-folder-change-{}", std::process::id()));
+    std::fs::create_dir_all(&workspace)?;
+    std::fs::write(
+        workspace.join(".bkmr-lsp.toml"),
+        "db_path = \"client.db\"\n",
+    )?;
+    let folder = Url::from_directory_path(&workspace).expect("workspace URI");
+
+    let mut context = TestContext::with_repository(
+        BkmrConfig {
+            prefetch: false,
+            ..Default::default()
+        },
+        Arc::new(StaticSnippetRepository::new(Vec::new())),
+    );
+    context.initialize().await?;
+    let change_folders = |added: serde_json::Value, removed: serde_json::Value| {
+        jsonrpc::Request::build("workspace/didChangeWorkspaceFolders")
+            .params(serde_json::json!({
+                "event": { "added": added, "removed": removed }
+            }))
+            .finish()
+    };
+
+    context
+        .send(&change_folders(
+            serde_json::json!([{ "uri": folder, "name": "client" }]),
+            serde_json::json!([]),
+        ))
+        .await?;
+    let added = folder_databases(&mut context, 1, 2).await?;
+    context
+        .send(&change_folders(
+            serde_json::json!([]),
+            serde_json::json!([{ "uri": folder, "name": "client" }]),
+        ))
+        .await?;
+    let removed = folder_databases(&mut context, 0, 200).await?;
+
+    assert_eq!(
+        added,
+        vec![serde_json::json!(
+            workspace.join("client.db").display().to_string()
+        )]
+    );
+    assert!(removed.is_empty());
+
+    std::fs::remove_dir_all(workspace)?;
+    Ok(())
+}
+
+/// Databases of the folder repositories in bkmr/status, once there are `count` of them
+///
+/// Workspace folder changes are notifications, the server may still be applying one.
+async fn folder_databases(
+    context: &mut TestContext,
+    count: usize,
+    first_id: i64,
+) -> anyhow::Result<Vec<serde_json::Value>> {
+    for id in first_id..first_id + 100 {
+        let status = context
+            .request::<serde_json::Value>(&jsonrpc::Request::build("bkmr/status").id(id).finish())
+            .await?;
+        let databases: Vec<serde_json::Value> = status["config"]["folderRepositories"]
+            .as_array()
+            .map(|folders| {
+                folders
+                    .iter()
+                    .map(|folder| folder["dbPath"].clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if databases.len() == count {
+            return Ok(databases);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    anyhow::bail!("bkmr/status never listed {} folder repositories", count)
+}
+
+/// Send $/setTrace, the status response confirms the server handled it
+async fn set_trace(context: &mut TestContext, value: &str, id: i64) -> anyhow::Result<()> {
+    context
+        .send_all(&[&format!(
+            r#"{{"jsonrpc":"2.0","method":"$/setTrace","params":{{"value":"{}"}}}}"#,
+            value
+        )])
+        .await?;
+    context
+        .request::<serde_json::Value>(&jsonrpc::Request::build("bkmr/status").id(id).finish())
+        .await?;
+    Ok(())
+}
+
+#[test_log::test(tokio::test)]
+async fn test_lsp_set_trace_reports_completions_as_log_trace_until_off() -> anyhow::Result<()> {
+    let snippets = vec![
+        SnippetBuilder::new()
+            .with_id(1)
+            .with_title("hello")
+            .with_content("println!(\"Hello\");")
+            .with_language("rust")
+            .with_snippet_tag()
+            .build(),
+    ];
+    let mut context = TestContext::with_snippets(snippets);
+    context.initialize().await?;
+    context
+        .send_all(&[
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"languageId":"rust","text":"hel","uri":"file:///tmp/main.rs","version":0}}}"#,
+        ])
+        .await?;
+    context
+        .messages_until(|message| message["params"]["message"] == "bkmr-lsp server ready")
+        .await?;
+    let completion = |id: i64, character: u32| {
+        jsonrpc::Request::build("textDocument/completion")
+            .id(id)
+            .params(serde_json::json!({
+                "position": { "character": character, "line": 0 },
+                "textDocument": { "uri": "file:///tmp/main.rs" },
+                "context": { "triggerKind": 1 }
+            }))
+            .finish()
+    };
+    let is_trace = |message: &serde_json::Value| message["method"] == "$/logTrace";
+
+    set_trace(&mut context, "verbose", 2).await?;
+    context.send(&completion(3, 3)).await?;
+    let messages = context.messages_until(is_trace).await?;
+    let trace = messages
+        .iter()
+        .find(|message| is_trace(message))
+        .expect("log trace");
+    assert!(
+        trace["params"]["message"]
+            .as_str()
+            .is_some_and(|message| message
+                .starts_with("textDocument/completion \"hel\" (rust): 1 items in ")),
+        "unexpected trace {}",
+        trace
+    );
+    assert!(trace["params"]["verbose"].is_string());
+
+    set_trace(&mut context, "off", 4).await?;
+    context.send(&completion(5, 3)).await?;
+    context.messages_until_response(5).await?;
+
+    // Traces are forwarded in order, one of the completion at off would come first
+    context.send_all(&[
+        r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///tmp/main.rs","version":1},"contentChanges":[{"text":"hell"}]}}"#,
+    ]).await?;
+    set_trace(&mut context, "messages", 6).await?;
+    context.send(&completion(7, 4)).await?;
+    let messages = context.messages_until(is_trace).await?;
+    let trace = messages
+        .iter()
+        .find(|message| is_trace(message))
+        .expect("log trace");
+    assert!(
+        trace["params"]["message"]
+            .as_str()
+            .is_some_and(|message| message.starts_with("textDocument/completion \"hell\"")),
+        "unexpected trace {}",
+        trace
+    );
+    assert!(trace["params"].get("verbose").is_none());
+
+    Ok(())
+}
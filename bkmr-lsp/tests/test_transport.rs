@@ -0,0 +1,81 @@
+use bkmr_lsp::BkmrConfig;
+use bkmr_lsp::transport::serve_tcp;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Write one LSP message with its Content-Length header
+async fn send(stream: &mut (impl AsyncWriteExt + Unpin), message: serde_json::Value) {
+    let body = message.to_string();
+    let frame = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    stream
+        .write_all(frame.as_bytes())
+        .await
+        .expect("write message");
+}
+
+/// Read one LSP message, skipping its headers
+async fn recv(reader: &mut BufReader<impl AsyncReadExt + Unpin>) -> serde_json::Value {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await.expect("read header");
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().expect("content length"));
+        }
+    }
+    let mut body = vec![0; length.expect("Content-Length header")];
+    reader.read_exact(&mut body).await.expect("read body");
+    serde_json::from_slice(&body).expect("JSON message")
+}
+
+#[tokio::test]
+async fn test_initialize_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let address = listener.local_addr().expect("local address");
+    let server = tokio::spawn(serve_tcp(listener, false, BkmrConfig::default()));
+
+    let stream = TcpStream::connect(address).await.expect("connect");
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    send(
+        &mut write,
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": {"capabilities": {}}
+        }),
+    )
+    .await;
+
+    // Notifications like log messages may arrive before the response
+    let response = loop {
+        let message = tokio::time::timeout(std::time::Duration::from_secs(10), recv(&mut reader))
+            .await
+            .expect("message before timeout");
+        if message["id"] == 1 {
+            break message;
+        }
+    };
+    let result: tower_lsp::lsp_types::InitializeResult =
+        serde_json::from_value(response["result"].clone()).expect("initialize result");
+    assert!(result.capabilities.completion_provider.is_some());
+    assert!(result.capabilities.execute_command_provider.is_some());
+
+    for message in [
+        serde_json::json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"}),
+        serde_json::json!({"jsonrpc": "2.0", "method": "exit"}),
+    ] {
+        send(&mut write, message).await;
+    }
+    let exit_code = tokio::time::timeout(std::time::Duration::from_secs(10), server)
+        .await
+        .expect("server stops after exit")
+        .expect("server task")
+        .expect("serve TCP client");
+    assert_eq!(exit_code, 0);
+}
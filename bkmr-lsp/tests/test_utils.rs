@@ -1,8 +1,12 @@
 // Test utilities for LSP server testing
 
-use bkmr_lsp::BkmrSnippet;
+use bkmr_lsp::domain::{NewSnippet, Snippet, SnippetFilter};
+use bkmr_lsp::repositories::SnippetRepository;
+use bkmr_lsp::{BkmrConfig, BkmrLspBackend, BkmrSnippet};
 use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
@@ -41,16 +45,16 @@ fn parse_lsp_messages(response: &str) -> Vec<String> {
             if let Some(header_end) = header_start.find("\r\n\r\n") {
                 // Extract Content-Length value
                 let length_str = &header_start[16..]; // Skip "Content-Length: "
-                if let Some(length_end) = length_str.find("\r\n") {
-                    if let Ok(content_length) = length_str[..length_end].parse::<usize>() {
-                        let message_start = content_length_start + header_end + 4; // Skip past \r\n\r\n
-                        if message_start + content_length <= remaining.len() {
-                            let message = remaining[message_start..message_start + content_length]
-                                .to_string();
-                            messages.push(message);
-                            remaining = &remaining[message_start + content_length..];
-                            continue;
-                        }
+                if let Some(length_end) = length_str.find("\r\n")
+                    && let Ok(content_length) = length_str[..length_end].parse::<usize>()
+                {
+                    let message_start = content_length_start + header_end + 4; // Skip past \r\n\r\n
+                    if message_start + content_length <= remaining.len() {
+                        let message =
+                            remaining[message_start..message_start + content_length].to_string();
+                        messages.push(message);
+                        remaining = &remaining[message_start + content_length..];
+                        continue;
                     }
                 }
             }
@@ -58,12 +62,11 @@ fn parse_lsp_messages(response: &str) -> Vec<String> {
 
         // If we can't parse a proper LSP message, try to extract JSON from the end
         // This is a fallback for malformed responses
-        if let Some(last_line) = remaining.split('\n').last() {
-            if !last_line.trim().is_empty()
-                && (last_line.contains("jsonrpc") || last_line.starts_with('{'))
-            {
-                messages.push(last_line.to_string());
-            }
+        if let Some(last_line) = remaining.split('\n').next_back()
+            && !last_line.trim().is_empty()
+            && (last_line.contains("jsonrpc") || last_line.starts_with('{'))
+        {
+            messages.push(last_line.to_string());
         }
         break;
     }
@@ -71,6 +74,97 @@ fn parse_lsp_messages(response: &str) -> Vec<String> {
     messages
 }
 
+/// In-memory snippet repository so integration tests don't depend on bkmr
+pub struct StaticSnippetRepository {
+    snippets: Vec<Snippet>,
+    fetch_count: AtomicUsize,
+    delay: Option<std::time::Duration>,
+    fetch_error: Option<String>,
+}
+
+impl StaticSnippetRepository {
+    pub fn new(snippets: Vec<BkmrSnippet>) -> Self {
+        Self {
+            snippets: snippets.into_iter().map(Snippet::from).collect(),
+            fetch_count: AtomicUsize::new(0),
+            delay: None,
+            fetch_error: None,
+        }
+    }
+
+    /// Make every fetch take at least this long, like bkmr on a cold cache
+    #[allow(dead_code)]
+    pub fn with_delay(mut self, delay: std::time::Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Make every fetch fail with this message, like a broken bkmr
+    #[allow(dead_code)]
+    pub fn with_fetch_error(mut self, message: &str) -> Self {
+        self.fetch_error = Some(message.to_string());
+        self
+    }
+
+    /// Number of fetch_snippets calls received so far
+    #[allow(dead_code)]
+    pub fn fetch_count(&self) -> usize {
+        self.fetch_count.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait::async_trait]
+impl SnippetRepository for StaticSnippetRepository {
+    async fn fetch_snippets(&self, filter: &SnippetFilter) -> anyhow::Result<Vec<Snippet>> {
+        self.fetch_count.fetch_add(1, Ordering::SeqCst);
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
+        if let Some(message) = &self.fetch_error {
+            return Err(anyhow::anyhow!("{}", message));
+        }
+        let mut snippets = self.snippets.clone();
+        if let Some(ref language) = filter.language_id {
+            snippets.retain(|s| s.has_language(language) || s.is_universal());
+        }
+        if let Some(ref prefix) = filter.query_prefix {
+            let prefix = prefix.to_lowercase();
+            snippets.retain(|s| s.title.to_lowercase().contains(&prefix));
+        }
+        snippets.retain(|s| filter.matches_title(&s.title));
+        snippets.retain(|s| filter.allows_tags(&s.tags));
+        snippets.truncate(filter.max_results);
+        Ok(snippets)
+    }
+
+    async fn query_snippet_by_id(&self, id: i32) -> anyhow::Result<Option<Snippet>> {
+        Ok(self.snippets.iter().find(|s| s.id == id).cloned())
+    }
+
+    async fn create_snippet(&self, _snippet: &NewSnippet) -> anyhow::Result<i32> {
+        Ok(self.snippets.iter().map(|s| s.id).max().unwrap_or(0) + 1)
+    }
+
+    async fn open_snippet(&self, id: i32) -> anyhow::Result<()> {
+        if self.snippets.iter().any(|s| s.id == id) {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "bkmr open {} failed: No bookmark found",
+                id
+            ))
+        }
+    }
+
+    async fn record_usage(&self, _id: i32) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn health_check(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
 impl AsyncRead for AsyncIn {
     fn poll_read(
         self: Pin<&mut Self>,
@@ -117,7 +211,7 @@ pub struct TestContext {
     pub request_tx: UnboundedSender<String>,
     #[allow(dead_code)]
     pub response_rx: UnboundedReceiver<String>,
-    pub _server: tokio::task::JoinHandle<()>,
+    pub _server: tokio::task::JoinHandle<i32>,
     pub _client: tokio::task::JoinHandle<()>,
 }
 
@@ -130,21 +224,47 @@ impl Default for TestContext {
 impl TestContext {
     /// Create a new test context with real LSP server spawning
     pub fn new() -> Self {
+        Self::spawn(|async_in, async_out| {
+            tokio::spawn(async move { bkmr_lsp::start_server(async_in, async_out).await })
+        })
+    }
+
+    /// Create a test context whose server serves the given snippets instead of bkmr
+    #[allow(dead_code)]
+    pub fn with_snippets(snippets: Vec<BkmrSnippet>) -> Self {
+        Self::with_repository(
+            BkmrConfig::default(),
+            Arc::new(StaticSnippetRepository::new(snippets)),
+        )
+    }
+
+    /// Create a test context whose server uses the given config and repository
+    #[allow(dead_code)]
+    pub fn with_repository(config: BkmrConfig, repository: Arc<dyn SnippetRepository>) -> Self {
+        Self::spawn(move |async_in, async_out| {
+            tokio::spawn(async move {
+                let (service, socket) = bkmr_lsp::lsp_service(move |client| {
+                    BkmrLspBackend::with_repository(client, config, repository)
+                });
+                bkmr_lsp::serve(async_in, async_out, service, socket).await
+            })
+        })
+    }
+
+    fn spawn(start: impl FnOnce(AsyncIn, AsyncOut) -> tokio::task::JoinHandle<i32>) -> Self {
         use tokio::sync::mpsc;
 
         let (request_tx, rx) = mpsc::unbounded_channel::<String>();
         let (tx, mut client_response_rx) = mpsc::unbounded_channel::<String>();
         let (client_tx, response_rx) = mpsc::unbounded_channel::<String>();
 
-        let async_in = AsyncIn(rx);
-        let async_out = AsyncOut(tx);
-
-        let server = tokio::spawn(async move { bkmr_lsp::start_server(async_in, async_out).await });
+        let server = start(AsyncIn(rx), AsyncOut(tx));
 
         let client = tokio::spawn(async move {
             loop {
+                // The server stopped, e.g. after the exit notification
                 let Some(response) = client_response_rx.recv().await else {
-                    continue;
+                    break;
                 };
                 if client_tx.send(response).is_err() {
                     tracing::error!("Failed to pass client response");
@@ -249,11 +369,21 @@ impl TestContext {
     /// Send initialize request and wait for response
     #[allow(dead_code)]
     pub async fn initialize(&mut self) -> anyhow::Result<()> {
+        self.initialize_with_capabilities(serde_json::json!({}))
+            .await
+    }
+
+    /// Send initialize request advertising the given client capabilities
+    #[allow(dead_code)]
+    pub async fn initialize_with_capabilities(
+        &mut self,
+        capabilities: serde_json::Value,
+    ) -> anyhow::Result<()> {
         use tower_lsp::lsp_types;
 
         let request = jsonrpc::Request::build("initialize")
             .id(1)
-            .params(serde_json::json!({"capabilities":{}}))
+            .params(serde_json::json!({ "capabilities": capabilities }))
             .finish();
 
         let _ = self
@@ -264,6 +394,57 @@ impl TestContext {
     }
 }
 
+impl TestContext {
+    /// Collect all messages up to the response with the given id
+    #[allow(dead_code)]
+    pub async fn messages_until_response(
+        &mut self,
+        id: i64,
+    ) -> anyhow::Result<Vec<serde_json::Value>> {
+        self.messages_until(|message| message.get("method").is_none() && message["id"] == id)
+            .await
+    }
+
+    /// Collect all messages up to and including the first one matching the predicate
+    ///
+    /// Messages written together with the matching one are included as well.
+    /// Requests from the server, e.g. window/workDoneProgress/create, are
+    /// answered with a null result.
+    #[allow(dead_code)]
+    pub async fn messages_until(
+        &mut self,
+        last: impl Fn(&serde_json::Value) -> bool,
+    ) -> anyhow::Result<Vec<serde_json::Value>> {
+        let mut collected = Vec::new();
+        let mut done = false;
+        loop {
+            let response = self
+                .response_rx
+                .recv()
+                .await
+                .ok_or_else(|| anyhow::anyhow!("empty response"))?;
+
+            for message in parse_lsp_messages(&response) {
+                let message: serde_json::Value = serde_json::from_str(&message)?;
+                if message.get("method").is_some() && message.get("id").is_some() {
+                    let reply = serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": message["id"],
+                        "result": null
+                    });
+                    self.request_tx
+                        .send(encode_message(None, &reply.to_string()))?;
+                }
+                done |= last(&message);
+                collected.push(message);
+            }
+            if done {
+                return Ok(collected);
+            }
+        }
+    }
+}
+
 /// Test data builder for creating mock bkmr snippets
 #[derive(Default)]
 pub struct SnippetBuilder {